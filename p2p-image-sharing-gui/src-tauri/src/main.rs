@@ -5,66 +5,276 @@
 
 use anyhow::{bail, Result};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fs;
-use std::io::{Read, Write};
-use std::net::TcpStream;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
-use std::time::{Duration, SystemTime};
-use tauri::State;
+use std::future::Future;
+use std::time::{Duration, Instant, SystemTime};
+use tauri::{Emitter, Manager, State, WindowEvent, Wry};
+use tauri::menu::{Menu, MenuItem, PredefinedMenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+use tauri::ipc::Channel;
 use std::sync::Arc;
-use tokio::sync::{RwLock, Mutex as TokioMutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::{RwLock, Mutex as TokioMutex, Semaphore};
 use tokio::sync::mpsc;
 
 // Import from your main project
 use cloud_p2p_project::directory_service::{
-    DirectoryMessage, ImageInfo, UserStatus,
-    send_directory_message,
+    AvailabilityWindow, Delegation, DirectoryMessage, ExistingGrant, ExpiredDelivery, ImageInfo, ImageVisibility, PendingPermissionUpdate, PendingRequest, RequestFilter, RequestStatus, ResponseOutlook, TradeProposal,
+    ServerInfo, UserEntry, UserProfile, UserStatus, avatar_thumbnail,
+    fetch_pending_permission_updates, negotiated_heartbeat_interval, parse_share_link, qualified_image_id, send_directory_message, set_namespace, share_link, unqualify_image_id, user_key,
+    USER_KEYS_FILE,
 };
 use cloud_p2p_project::p2p_protocol::{
-    ImageMetadata, PeerImageStore, P2PMessage, send_p2p_message,
-    list_peer_images, request_image_from_peer, request_thumbnail_from_peer, start_p2p_server,
+    self, ImageMetadata, PeerImageStore, P2PMessage, ReceivedImageVerification, send_p2p_message,
+    search_peer_images, request_scaled_image_from_peer, request_thumbnail_from_peer, serve_p2p_via_relay, start_p2p_server,
+    received_record_path, save_received_image, sha256_hex, verify_received_image,
+    local_capabilities, CAP_CHECKSUMS, CAP_THUMBNAILS,
+    access_denial_for, preview_quota_change, QuotaPreview, load_access_denial_stats, record_access_denials, send_access_denial,
+    AccessDenial, ACCESS_DENIAL_STATS_FILE, CAP_ACCESS_REPORTS,
+    content_sha256, probe_peer_image, CAP_IMAGE_PROBE, image_annotations,
+    make_room_for_received, received_storage_usage, StorageUsage,
+    embedded_image_id, migrate_carrier, new_image_id, shared_image_id,
+    send_remote_wipe, send_wipe_ack, wipe_received_image, CAP_REMOTE_WIPE, AccessRevoked,
+    VISIBILITY_FILE, ServerLoadStats, deliver_image, CAP_DELIVERY_BUNDLES,
+    reconcile_received_grant, GrantReconciliation, CAP_GRANT_VERIFY,
+    subscribe_catalog, CatalogEvent, CatalogSubscription, CAP_CATALOG_UPDATES, write_atomic,
 };
-use cloud_p2p_project::{lsb, CombinedPayload, ImagePermissions, get_local_ip};
+use cloud_p2p_project::audit_log::{self, audit, AuditAction, AuditRecord, AuditVerification, AUDIT_LOG_FILE};
+use cloud_p2p_project::bandwidth::{self, BandwidthStats, BANDWIDTH_STATS_FILE};
+use cloud_p2p_project::reputation::{self, LOW_REPUTATION_SCORE, REPUTATION_FILE};
+use cloud_p2p_project::job_queue::{JobKind, JobQueue, JobStatus, JobSummary, JOB_QUEUE_FILE};
+use cloud_p2p_project::diagnostics::{self, DiagnosticReport};
+use cloud_p2p_project::bootstrap::{DirectoryBootstrap, KnownDirectory, RefreshReport};
+use cloud_p2p_project::protocol_trace::{self, TraceEntry};
+use cloud_p2p_project::lsb::CarrierAnalysis;
+use cloud_p2p_project::photo_import::scan_library;
+use cloud_p2p_project::fsscan;
+use cloud_p2p_project::humantime::time_ago;
+use cloud_p2p_project::relay;
+use cloud_p2p_project::received_index::{IndexedImage, ReceivedIndex};
+use cloud_p2p_project::encryption_pool::{self, encrypt_batch, EncryptionJob, EncryptionProgress, EncryptionStats, ENCRYPTION_STATS_FILE};
+use cloud_p2p_project::integrity::{self, IntegrityReport};
+use cloud_p2p_project::viewing::{self, ViewOutcome};
+use cloud_p2p_project::grant_package::{export_grant_package, import_grant_package, GrantPackageInfo, GRANT_PACKAGE_EXTENSION};
+use cloud_p2p_project::pairing::{parse_pairing, redeem_pairing, PairingOffer, RedeemedPairing};
+use cloud_p2p_project::{lsb, watermark, CombinedPayload, EncryptionMetadata, ImageAnnotations, ImagePermissions, ViewPolicy, PAYLOAD_VERSION, get_local_ip, provenance_chain};
 use image::imageops;
 
+/// Build a `UserMessage` from a stable code and an English template whose `{name}`
+/// placeholders are filled from the named parameters, e.g.
+/// `msg!("found_peers", "Found {count} peers", count = peers.len())`
+macro_rules! msg {
+    ($code:expr, $text:literal $(,)?) => {
+        UserMessage { code: $code.to_string(), params: BTreeMap::new(), message: $text.to_string() }
+    };
+    ($code:expr, $text:literal $(, $name:ident = $value:expr)+ $(,)?) => {{
+        let params: BTreeMap<String, String> = [$((stringify!($name).to_string(), $value.to_string())),+].into_iter().collect();
+        UserMessage { code: $code.to_string(), message: format!($text $(, $name = params[stringify!($name)])+), params }
+    }};
+}
+
 // ============================================================================
 // APP STATE
 // ============================================================================
 
+/// Environment variable that re-enables the legacy path-based `view_image` command
+const LEGACY_FILE_VIEWER_ENV: &str = "P2P_LEGACY_FILE_VIEWER";
+
+/// Environment variable that starts the GUI locked in viewer-only mode (for shared machines)
+const VIEWER_ONLY_ENV: &str = "P2P_VIEWER_ONLY";
+
+/// Everything the GUI keeps between commands
+///
+/// What belongs to one go-online lives in `session` (plain values) and `tasks` (the background
+/// tasks started for it); the rest outlives going offline. Lock order: `tasks` before `session`
+/// before any other field. `session` and the std mutexes are only held to copy or change a value,
+/// never across an await; `tasks` is the only lock held across one.
 pub struct AppState {
-    pub username: Mutex<Option<String>>,
-    pub p2p_port: Mutex<Option<u16>>,
-    pub is_online: Mutex<bool>,
+    pub session: std::sync::RwLock<Session>,  // Who we are and whether we're online, read and changed as one
+    pub tasks: TokioMutex<SessionTasks>,  // Background tasks started by going online
     pub directory_servers: Mutex<Vec<String>>,
-    pub images_directory: Mutex<Option<PathBuf>>,
+    pub directory_bootstrap: Mutex<DirectoryBootstrap>,  // Known directory servers with health history, persisted to the bootstrap file
     pub local_images: Mutex<Vec<LocalImage>>,
     pub received_images: Mutex<Vec<ReceivedImage>>,
     pub image_store: Arc<RwLock<PeerImageStore>>,
-    pub p2p_address: Mutex<Option<String>>,
-    pub heartbeat_failures: Mutex<u32>,  // Track consecutive heartbeat failures
-    pub heartbeat_shutdown: TokioMutex<Option<mpsc::Sender<()>>>,  // Channel to stop heartbeat task (using Tokio's async Mutex)
+    pub legacy_file_viewer: Mutex<bool>,  // Compatibility flag: allow view_image to write viewable_image.png
+    pub legacy_views: Mutex<HashMap<(String, String), PathBuf>>,  // Decoded files the legacy viewer wrote, by (owner, image ID)
+    pub share_roots: Mutex<Vec<ShareRoot>>,  // Extra share folders scanned alongside the images directory
+    pub peer_cache: Mutex<HashMap<String, CachedPeer>>,  // Recently resolved online peers, keyed by username
+    pub viewer_only: Mutex<bool>,  // Refuse owner-side commands (encrypt, grant, delete, ...)
+    pub viewer_only_locked: bool,  // Set by P2P_VIEWER_ONLY: viewer-only can't be switched off from the UI
+    pub jobs: Mutex<JobQueue>,  // Deliveries, directory updates and reports retried in the background
+    pub app_handle: Mutex<Option<tauri::AppHandle>>,  // Set at startup so state changes can be pushed as events
+    pub peer_statuses: Mutex<HashMap<String, String>>,  // Last status announced for each peer
+    pub request_statuses: Mutex<HashMap<String, String>>,  // Last status announced for each request
+    pub integrity_report: Mutex<Option<IntegrityReport>>,  // What the carrier check found when we last went online
+    pub background_mode: Mutex<bool>,  // Closing the window while online hides it to the tray and keeps serving
+    pub catalog_watches: Mutex<HashMap<String, mpsc::Sender<()>>>,  // Live catalog subscriptions by peer; dropping a sender ends one
 }
 
 impl Default for AppState {
     fn default() -> Self {
+        let bootstrap = DirectoryBootstrap::load_or_default(&DirectoryBootstrap::default_path());
         Self {
-            username: Mutex::new(None),
-            p2p_port: Mutex::new(None),
-            is_online: Mutex::new(false),
-            directory_servers: Mutex::new(vec![
-                "10.7.57.239:9000".to_string(),
-                "10.7.57.240:9000".to_string(),
-                "10.7.57.99:9000".to_string(),
-            ]),
-            images_directory: Mutex::new(None),
+            session: std::sync::RwLock::new(Session::default()),
+            tasks: TokioMutex::new(SessionTasks::default()),
+            directory_servers: Mutex::new(bootstrap.addresses()),
+            directory_bootstrap: Mutex::new(bootstrap),
             local_images: Mutex::new(Vec::new()),
             received_images: Mutex::new(Vec::new()),
             image_store: Arc::new(RwLock::new(PeerImageStore::new())),
-            p2p_address: Mutex::new(None),
-            heartbeat_failures: Mutex::new(0),
-            heartbeat_shutdown: TokioMutex::new(None),
+            legacy_file_viewer: Mutex::new(std::env::var(LEGACY_FILE_VIEWER_ENV).is_ok_and(|v| v == "1")),
+            legacy_views: Mutex::new(HashMap::new()),
+            share_roots: Mutex::new(Vec::new()),
+            peer_cache: Mutex::new(HashMap::new()),
+            viewer_only: Mutex::new(std::env::var(VIEWER_ONLY_ENV).is_ok_and(|v| v == "1")),
+            viewer_only_locked: std::env::var(VIEWER_ONLY_ENV).is_ok_and(|v| v == "1"),
+            jobs: Mutex::new(JobQueue::default()),
+            app_handle: Mutex::new(None),
+            peer_statuses: Mutex::new(HashMap::new()),
+            request_statuses: Mutex::new(HashMap::new()),
+            integrity_report: Mutex::new(None),
+            background_mode: Mutex::new(true),
+            catalog_watches: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl AppState {
+    /// A consistent copy of the session, taken under a single read lock
+    pub fn session(&self) -> Result<Session, String> {
+        Ok(self.session.read().map_err(|e| e.to_string())?.clone())
+    }
+
+    /// Change the session under a single write lock, so no command sees it half updated
+    pub fn update_session<T>(&self, update: impl FnOnce(&mut Session) -> T) -> Result<T, String> {
+        Ok(update(&mut *self.session.write().map_err(|e| e.to_string())?))
+    }
+
+    /// The user we went online as
+    pub fn current_user(&self) -> Result<String, String> {
+        Ok(self.session()?.username.ok_or("Not logged in")?)
+    }
+
+    /// The key we registered with, which the directory wants before we answer requests
+    pub fn registration_key(&self) -> Result<String, String> {
+        Ok(self.session()?.key.ok_or("Not logged in")?)
+    }
+}
+
+/// Where the session is on its way online
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionPhase {
+    #[default]
+    Offline,
+    /// Registering with the directory, going online or reconnecting after heartbeats failed
+    Connecting,
+    Online,
+}
+
+/// Everything that changes when we go online or offline, kept together so it's never seen half set
+///
+/// Commands take a snapshot with `AppState::session` and change it only through the
+/// transitions below, each made under one write lock.
+#[derive(Debug, Clone, Default)]
+pub struct Session {
+    pub phase: SessionPhase,
+    pub username: Option<String>,
+    pub p2p_port: Option<u16>,
+    pub p2p_address: Option<String>,
+    pub images_directory: Option<PathBuf>,
+    pub heartbeat_failures: u32,  // Consecutive heartbeat failures
+    pub last_heartbeat: Option<SystemTime>,  // When a directory last acknowledged a heartbeat
+    pub reconnect_attempts: Option<u32>,  // Registration attempts made while reconnecting (None when not reconnecting)
+    pub key: Option<String>,  // Key we registered with
+    pub availability: Option<AvailabilityWindow>,  // Usual online hours we registered with, for re-registering
+    pub max_grant_views: Option<u32>,  // Cap on views granted per request for our shared images (kept offline)
+    pub catalog_since: Option<SystemTime>,  // Directory time of the last catalog change poll
+    pub due_reminders: usize,  // Overdue requests the directory counted at our last heartbeat
+}
+
+impl Session {
+    pub fn is_online(&self) -> bool {
+        self.phase == SessionPhase::Online
+    }
+
+    /// Offline → Connecting as the user registers; returns the phase to go back to if it fails
+    ///
+    /// Refused while online or while another go-online is registering. Going online by hand
+    /// while reconnecting is fine, and takes over from the reconnect.
+    fn begin_connecting(&mut self) -> Result<SessionPhase, String> {
+        match self.phase {
+            SessionPhase::Online => Err(format!("Already online as {}", self.username.as_deref().unwrap_or("?"))),
+            SessionPhase::Connecting if self.reconnect_attempts.is_none() => Err("Already going online".to_string()),
+            previous => {
+                self.phase = SessionPhase::Connecting;
+                Ok(previous)
+            }
+        }
+    }
+
+    /// Connecting → Online once a directory accepted our registration
+    fn finish_connecting(&mut self, username: String, port: u16, p2p_address: String, images_directory: PathBuf, key: String) {
+        *self = Session {
+            phase: SessionPhase::Online,
+            username: Some(username),
+            p2p_port: Some(port),
+            p2p_address: Some(p2p_address),
+            images_directory: Some(images_directory),
+            last_heartbeat: self.last_heartbeat,
+            key: Some(key),
+            ..Session::default()
+        };
+    }
+
+    /// Online → Connecting after too many heartbeats failed, keeping who we are for the reconnect
+    fn lose_connection(&mut self) {
+        self.phase = SessionPhase::Connecting;
+        self.heartbeat_failures = 0;
+        self.reconnect_attempts = Some(0);
+    }
+
+    /// Connecting → Online when a reconnect's registration went through
+    fn reconnected(&mut self) {
+        self.phase = SessionPhase::Online;
+        self.heartbeat_failures = 0;
+        self.last_heartbeat = Some(SystemTime::now());
+    }
+
+    /// Back to Offline; the images directory, grant cap and P2P address stay for offline
+    /// browsing and the still-running P2P server
+    fn end(&mut self) {
+        *self = Session {
+            images_directory: self.images_directory.take(),
+            max_grant_views: self.max_grant_views,
+            p2p_address: self.p2p_address.take(),
+            ..Session::default()
+        };
+    }
+}
+
+/// Background tasks started by going online, each stopped through the handle kept here
+#[derive(Default)]
+pub struct SessionTasks {
+    pub heartbeat: Option<mpsc::Sender<()>>,  // Stops the heartbeat task
+    pub reconnect: Option<mpsc::Sender<()>>,  // Stops the auto-reconnect task
+    pub relay: Option<mpsc::Sender<()>>,  // Stops serving through a relay (anonymous mode)
+    pub job_worker: Option<mpsc::Sender<()>>,  // Stops the job worker
+    pub catalog_scan: Option<Arc<AtomicBool>>,  // Set to cancel the background scan started by going online
+}
+
+impl SessionTasks {
+    /// Stop every task; the heartbeat goes first so it can't re-register us while we unregister
+    async fn stop_all(&mut self) {
+        for sender in [self.heartbeat.take(), self.job_worker.take(), self.reconnect.take(), self.relay.take()].into_iter().flatten() {
+            let _ = sender.send(()).await;
+        }
+        if let Some(cancel_scan) = self.catalog_scan.take() {
+            cancel_scan.store(true, Ordering::Relaxed);
         }
     }
 }
@@ -80,6 +290,15 @@ pub struct LocalImage {
     pub file_name: String,
     pub file_size_kb: u64,
     pub is_encrypted: bool,
+    pub origin: String,  // Name of the share root the image was found in
+    #[serde(default)]
+    pub visibility: ImageVisibility,  // Who can find a shared image (public for originals)
+    #[serde(default)]
+    pub share_link: Option<String>,  // `owner/image_id#token` while link-only
+    #[serde(default)]
+    pub escrow_preview: bool,  // A blurred preview is left with the directory for browsing while we're offline
+    #[serde(default)]
+    pub max_grant_views: Option<u32>,  // Cap on views granted per request for a shared image
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -90,12 +309,70 @@ pub struct ReceivedImage {
     pub file_name: String,
     pub views_remaining: u32,
     pub received_at: String,
+    pub sha256: Option<String>,  // Checksum recorded when the image was received
+    pub size_bytes: u64,
+    /// e.g. `alice → bob (re-shared)`, when it reached us through someone other than the owner
+    #[serde(default)]
+    pub provenance: Option<String>,
+}
+
+/// How the received images list is ordered
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceivedSort {
+    #[default]
+    Name,
+    Owner,
+    Date,
+    Views,
+}
+
+/// One page of the received images list
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedPage {
+    pub images: Vec<ReceivedImage>,
+    pub total: usize,  // Received images across all pages
+    pub offset: usize,
+    pub limit: Option<usize>,
+}
+
+/// What a command tells the user: a stable code and parameters the frontend can translate,
+/// plus the English rendering it falls back to
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UserMessage {
+    pub code: String,
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+    pub message: String,
+}
+
+impl UserMessage {
+    /// Text with no code of its own (library errors, reports), shown as is in every language
+    pub fn untranslated(text: impl Into<String>) -> Self {
+        let text = text.into();
+        let params = BTreeMap::from([("text".to_string(), text.clone())]);
+        UserMessage { code: "untranslated".to_string(), params, message: text }
+    }
+}
+
+impl From<String> for UserMessage {
+    fn from(text: String) -> Self {
+        UserMessage::untranslated(text)
+    }
 }
 
+impl std::fmt::Display for UserMessage {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// `message` is flattened so `response.message` stays the English text, with `code` and `params` beside it
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
-    pub message: String,
+    #[serde(flatten)]
+    pub message: UserMessage,
     pub data: Option<T>,
 }
 
@@ -105,6 +382,44 @@ pub struct PeerInfo {
     pub p2p_address: String,
     pub status: String,
     pub shared_images: Vec<ImageInfoJson>,
+    pub capabilities: Vec<String>,
+    pub latency_ms: Option<u64>,  // Round trip of our last successful P2P call to this peer
+    pub availability: Option<AvailabilityWindow>,  // Usual online hours in UTC; the UI shows them in local time
+    pub outlook: ResponseOutlook,  // Whether a request is likely to be answered soon
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,  // PNG data URL
+    pub reputation: Option<u8>,  // Local 0-100 score from past deliveries, latency and revocations
+    pub low_reputation: bool,
+    pub anonymous: bool,  // Hidden peer, reachable only through a relay
+    pub anonymous_images: Vec<String>,  // Image IDs anonymous users may request
+}
+
+/// When a peer was last seen and is typically online
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceInfo {
+    pub online: bool,
+    pub summary: String,  // e.g. "last seen 2 hours ago, typically online evenings"
+    pub hourly_online: Vec<f64>,  // Share of each UTC hour spent online, for the sparkline
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProfileJson {
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub avatar: Option<String>,  // PNG data URL
+}
+
+impl From<&UserProfile> for ProfileJson {
+    fn from(profile: &UserProfile) -> Self {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        Self {
+            display_name: profile.display_name.clone(),
+            bio: profile.bio.clone(),
+            avatar: profile.avatar_thumbnail.as_ref()
+                .map(|png| format!("data:image/png;base64,{}", STANDARD.encode(png))),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -112,6 +427,137 @@ pub struct ImageInfoJson {
     pub image_id: String,
     pub image_name: String,
     pub thumbnail_path: Option<String>,
+    pub max_grant_views: Option<u32>,
+    pub caption: Option<String>,
+    pub preview: Option<String>,  // Blurred PNG data URL the owner left with the directory
+    pub file_size_kb: Option<u64>,  // Only known from an escrowed preview
+}
+
+impl From<&ImageInfo> for ImageInfoJson {
+    fn from(image: &ImageInfo) -> Self {
+        use base64::{Engine as _, engine::general_purpose::STANDARD};
+        Self {
+            image_id: image.image_id.clone(),
+            image_name: image.image_name.clone(),
+            thumbnail_path: image.thumbnail_path.clone(),
+            max_grant_views: image.max_grant_views,
+            caption: image.caption.clone(),
+            preview: image.preview.as_ref()
+                .map(|preview| format!("data:image/png;base64,{}", STANDARD.encode(&preview.thumbnail))),
+            file_size_kb: image.preview.as_ref().map(|preview| preview.file_size_kb),
+        }
+    }
+}
+
+impl From<&ImageMetadata> for ImageInfoJson {
+    fn from(image: &ImageMetadata) -> Self {
+        Self {
+            image_id: image.image_id.clone(),
+            image_name: image.image_name.clone(),
+            thumbnail_path: None,
+            max_grant_views: image.max_grant_views,
+            caption: image.description.clone(),
+            preview: None,
+            file_size_kb: Some(image.file_size_kb),
+        }
+    }
+}
+
+/// A peer sharing an image found by `find_image`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageHolderInfo {
+    pub owner: String,
+    pub status: String,
+    /// `owner/image_id`, unambiguous when requesting
+    pub qualified_id: String,
+    pub image: ImageInfoJson,
+}
+
+/// A shared image matching a catalog search, best match first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogMatchInfo {
+    pub owner: String,
+    pub status: String,
+    pub qualified_id: String,
+    pub image: ImageInfoJson,
+    /// How well the image matched, 0-1
+    pub score: f32,
+    /// Views we already hold on the image
+    pub your_grant: Option<ExistingGrantInfo>,
+}
+
+/// Views we were last granted on a peer's image, as the directory recorded it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExistingGrantInfo {
+    pub owner: String,
+    pub image_id: String,
+    pub views: u32,
+    pub granted_ago: String,
+}
+
+impl From<ExistingGrant> for ExistingGrantInfo {
+    fn from(grant: ExistingGrant) -> Self {
+        Self {
+            owner: grant.owner,
+            image_id: grant.image_id,
+            views: grant.views,
+            granted_ago: time_ago(grant.granted_at),
+        }
+    }
+}
+
+/// How `respond_to_request` answered a request
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind")]
+pub enum RespondOutcome {
+    /// Answered as asked
+    Responded,
+    /// The image was removed since the request was made, so the request was rejected
+    /// with a note to the requester and the image dropped from our shared catalog
+    ImageNoLongerShared { image_id: String, from_user: String },
+}
+
+/// What happened to one request answered by `respond_to_requests_bulk`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BulkResponseResult {
+    pub request_id: String,
+    pub from_user: String,
+    pub image_id: String,
+    pub success: bool,
+    pub message: String,
+}
+
+/// What happened to one item of a batch command
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchItemResult {
+    /// The file path, or `peer/image_id` for a request
+    pub item: String,
+    pub success: bool,
+    pub message: String,
+    /// The encrypted copy's path, or the new request's ID
+    pub data: Option<String>,
+}
+
+/// One image asked for by `request_images`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchImageRequest {
+    pub peer_username: String,
+    pub image_id: String,
+    pub views: u32,
+    #[serde(default)]
+    pub max_dimension: Option<u32>,
+}
+
+/// A successfully viewed image along with the owner's annotations
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewedImage {
+    pub image: String,  // Data URL (or file path for the legacy viewer)
+    pub annotations: ImageAnnotations,
+    /// What the owner lets us do with the view; owners may always save and print
+    pub policy: ViewPolicy,
+    /// e.g. `alice → bob (re-shared)`, when it reached us through someone other than the owner
+    #[serde(default)]
+    pub provenance: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -121,8 +567,12 @@ pub struct RequestInfo {
     pub to_user: String,
     pub image_id: String,
     pub requested_views: u32,
+    /// Longest side the requester wants, if they asked for a smaller copy
+    pub requested_max_dimension: Option<u32>,
     pub timestamp: String,
     pub status: String,
+    /// We can answer it as `to_user`'s delegate
+    pub delegated: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -133,6 +583,78 @@ pub struct NotificationInfo {
     pub requested_views: u32,
     pub status: String,
     pub timestamp: String,
+    /// Delegate who answered for the owner
+    pub approved_by: Option<String>,
+    /// The owner's side's explanation for its answer
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DenialStatsInfo {
+    pub total: u32,
+    pub by_viewer: HashMap<String, u32>,
+    pub last_denied: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogChangeInfo {
+    pub owner: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub timestamp: String,
+}
+
+/// A trade from the current user's point of view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewerMode {
+    pub enabled: bool,
+    /// Forced on by the environment, so the UI can't turn it off
+    pub locked: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeInfo {
+    pub trade_id: String,
+    pub peer: String,
+    pub give_image_id: String,
+    pub give_views: u32,
+    pub give_deposited: bool,
+    pub get_image_id: String,
+    pub get_views: u32,
+    pub get_deposited: bool,
+    pub status: String,
+    /// Proposed to us (so we're the one who accepts or rejects)
+    pub incoming: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogFeed {
+    pub changes: Vec<CatalogChangeInfo>,
+    pub following: Vec<String>,
+}
+
+/// Outcome of queueing a photo library for encryption
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryImport {
+    pub found: usize,
+    pub queued: usize,
+    /// Photos whose encrypted copy already exists
+    pub already_imported: usize,
+}
+
+/// Status events streamed to the frontend by `request_image_quick`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum QuickRequestEvent {
+    Submitted { request_id: String, views: u32 },
+    Accepted { request_id: String },
+    Rejected { request_id: String },
+    Delivered { request_id: String, file_path: String },
+    TimedOut { request_id: String },
+    Failed {
+        #[serde(flatten)]
+        message: UserMessage,
+    },
 }
 
 // ============================================================================
@@ -167,6 +689,23 @@ async fn send_directory_message_async(addr: &str, message: DirectoryMessage) ->
     send_directory_message(addr, message).await
 }
 
+/// `multicast_directory_message`, noting in the bootstrap list which servers answered
+async fn multicast_tracked(state: &AppState, servers: &[String], message: DirectoryMessage) -> Result<DirectoryMessage> {
+    for server in servers {
+        let started = Instant::now();
+        let outcome = send_directory_message_async(server, message.clone()).await;
+        let latency_ms = outcome.is_ok().then(|| started.elapsed().as_millis() as u64);
+        if let Ok(mut bootstrap) = state.directory_bootstrap.lock() {
+            bootstrap.record_contact(server, latency_ms);
+        }
+        match outcome {
+            Ok(response) => return Ok(response),
+            Err(e) => eprintln!("Server {} failed: {}", server, e),
+        }
+    }
+    bail!("All directory servers failed to respond")
+}
+
 async fn multicast_directory_message(servers: &[String], message: DirectoryMessage) -> Result<DirectoryMessage> {
     for server in servers {
         match send_directory_message_async(server, message.clone()).await {
@@ -180,231 +719,773 @@ async fn multicast_directory_message(servers: &[String], message: DirectoryMessa
     bail!("All directory servers failed to respond")
 }
 
+/// Fetch (and clear) our pending permission updates from the first server that answers, images streamed
+async fn fetch_pending_updates(servers: &[String], username: &str) -> Result<Vec<PendingPermissionUpdate>> {
+    for server in servers {
+        match fetch_pending_permission_updates(server, username).await {
+            Ok(updates) => return Ok(updates),
+            Err(e) => {
+                eprintln!("Server {} failed: {}", server, e);
+                continue;
+            }
+        }
+    }
+    bail!("All directory servers failed to respond")
+}
+
 // ============================================================================
-// TAURI COMMANDS
+// SHARE ROOTS
 // ============================================================================
 
-#[tauri::command]
-async fn set_directory_servers(
-    state: State<'_, AppState>,
-    servers: Vec<String>,
-) -> Result<ApiResponse<()>, String> {
-    let mut dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?;
-    *dir_servers = servers.clone();
-    
-    Ok(ApiResponse {
-        success: true,
-        message: format!("Set {} directory servers", servers.len()),
-        data: None,
-    })
+/// Origin tag for images found in the directory entered when going online
+const PRIMARY_ROOT_NAME: &str = "Main";
+
+/// An extra folder whose images are shared alongside the main images directory.
+/// Uses the same layout: originals at the top level, shareable copies in `encrypted/`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShareRoot {
+    pub name: String,
+    pub path: String,
 }
 
-#[tauri::command]
-async fn get_directory_servers(
-    state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<String>>, String> {
-    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?;
-    
-    Ok(ApiResponse {
-        success: true,
-        message: "Directory servers retrieved".to_string(),
-        data: Some(dir_servers.clone()),
-    })
+/// Every root to scan as (origin, path): the images directory first, then the share roots
+fn image_roots(images_path: &Path, share_roots: &[ShareRoot]) -> Vec<(String, PathBuf)> {
+    let mut roots = vec![(PRIMARY_ROOT_NAME.to_string(), images_path.to_path_buf())];
+    for root in share_roots {
+        let path = PathBuf::from(&root.path);
+        if roots.iter().all(|(_, existing)| existing != &path) {
+            roots.push((root.name.clone(), path));
+        }
+    }
+    roots
 }
 
-#[tauri::command]
-async fn go_online(
-    state: State<'_, AppState>,
-    username: String,
-    port: u16,
-    images_dir: String,
-) -> Result<ApiResponse<Vec<LocalImage>>, String> {
-    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    
-    if dir_servers.is_empty() {
-        return Ok(ApiResponse {
-            success: false,
-            message: "No directory servers configured".to_string(),
-            data: None,
-        });
+/// Image roots for the current session, or None when we haven't gone online yet
+fn session_image_roots(state: &AppState) -> Result<Option<Vec<(String, PathBuf)>>, String> {
+    let images_directory = state.session()?.images_directory;
+    let share_roots = state.share_roots.lock().map_err(|e| e.to_string())?.clone();
+    Ok(images_directory.map(|images_path| image_roots(&images_path, &share_roots)))
+}
+
+/// Find a shareable image by id across the `encrypted/` folders of every root
+fn find_encrypted_image(roots: &[(String, PathBuf)], image_id: &str) -> Option<PathBuf> {
+    roots.iter()
+        .map(|(_, root)| root.join("encrypted").join(image_id))
+        .find(|path| path.exists())
+}
+
+/// Our encrypted copy of an image: shared images by ID, else by file name in whichever share root holds it
+async fn find_owned_image(state: &AppState, image_id: &str) -> Result<Option<PathBuf>, String> {
+    let roots = session_image_roots(state)?
+        .ok_or("Images directory not configured")?;
+    let shared_path = state.image_store.read().await.get_image_path(image_id).cloned();
+    Ok(shared_path.or_else(|| find_encrypted_image(&roots, image_id)))
+}
+
+/// Scan every root: shares the images in their `encrypted/` folders (adding them to the
+/// image store) and lists the originals at their top level for local display
+async fn scan_share_roots(
+    state: &AppState,
+    roots: &[(String, PathBuf)],
+    owner: &str,
+    max_grant_views: Option<u32>,
+) -> (Vec<ImageInfo>, Vec<LocalImage>) {
+    let mut shared_images = Vec::new();
+    for carrier in fsscan::scan_shared_carriers(roots) {
+        let caption = embedded_caption(&carrier.file.path);
+        shared_images.push(add_shared_carrier(state, carrier.image_id, carrier.file, caption, owner, max_grant_views).await);
     }
-    
-    // Setup directory structure
-    let images_path = PathBuf::from(&images_dir);
-    let encrypted_dir = images_path.join("encrypted");
-    let received_dir = images_path.join("received");
 
-    // Create subdirectories if they don't exist
-    let _ = fs::create_dir_all(&encrypted_dir);
-    let _ = fs::create_dir_all(&received_dir);
+    let local_images = list_local_images(roots, fsscan::is_encrypted_carrier);
+    (shared_images, local_images)
+}
 
-    let mut shared_images: Vec<ImageInfo> = Vec::new();
-    let mut local_images_list: Vec<LocalImage> = Vec::new();
-
-    // Get access to the image store
-    let image_store = state.image_store.clone();
-
-    // Scan ONLY the encrypted folder for images to share with peers
-    if encrypted_dir.exists() && encrypted_dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(&encrypted_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            let file_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-                            let image_id = file_name.clone();
-                            let file_size = fs::metadata(&path)
-                                .map(|m| m.len() / 1024)
-                                .unwrap_or(0);
-
-                            // These are encrypted images - share them with peers (NO thumbnail)
-                            shared_images.push(ImageInfo {
-                                image_id: image_id.clone(),
-                                image_name: file_name.clone(),
-                                thumbnail_path: None, // No thumbnail for encrypted images
-                            });
-
-                            // Add to image store
-                            let metadata = ImageMetadata {
-                                image_id: image_id.clone(),
-                                image_name: file_name.clone(),
-                                owner: username.clone(),
-                                description: Some(format!("Encrypted image from {}", username)),
-                                file_size_kb: file_size,
-                            };
-
-                            image_store.write().await.add_image(
-                                image_id,
-                                path.clone(),
-                                metadata,
-                            );
-                        }
-                    }
-                }
-            }
-        }
+/// Add a carrier to the image store, returning its entry in our shared catalog
+async fn add_shared_carrier(
+    state: &AppState,
+    image_id: String,
+    file: fsscan::ImageFile,
+    caption: Option<String>,
+    owner: &str,
+    max_grant_views: Option<u32>,
+) -> ImageInfo {
+    let metadata = ImageMetadata {
+        image_id: image_id.clone(),
+        image_name: file.file_name.clone(),
+        owner: owner.to_string(),
+        description: caption.clone().or_else(|| Some(format!("Encrypted image from {}", owner))),
+        file_size_kb: file.size_kb(),
+        max_grant_views,
+    };
+    let mut store = state.image_store.write().await;
+    store.add_image(image_id.clone(), file.path, metadata);
+    let visibility = store.get_visibility(&image_id);
+    // Encrypted images are shared with peers without a thumbnail, unless one is left for browsing offline
+    ImageInfo {
+        preview: store.escrowed_preview(&image_id),
+        image_id,
+        image_name: file.file_name,
+        thumbnail_path: None,
+        max_grant_views,
+        caption,
+        visibility: visibility.visibility,
+        link_token: visibility.link_token,
     }
+}
 
-    // Scan the main directory for ALL images (for local display only, not shared)
-    if images_path.exists() && images_path.is_dir() {
-        if let Ok(entries) = fs::read_dir(&images_path) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            let file_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-                            let image_id = file_name.clone();
-                            let file_size = fs::metadata(&path)
-                                .map(|m| m.len() / 1024)
-                                .unwrap_or(0);
-
-                            // Check if encrypted
-                            let is_encrypted = if let Ok(data) = fs::read(&path) {
-                                if let Ok(img) = image::load_from_memory(&data) {
-                                    lsb::decode(&img).ok().flatten().is_some()
-                                } else {
-                                    false
-                                }
-                            } else {
-                                false
-                            };
-
-                            local_images_list.push(LocalImage {
-                                image_id: image_id.clone(),
-                                file_path: path.to_string_lossy().to_string(),
-                                file_name: file_name.clone(),
-                                file_size_kb: file_size,
-                                is_encrypted,
-                            });
-                        }
+/// The images at the top level of each root, with `is_encrypted` deciding which hide a payload
+fn list_local_images(roots: &[(String, PathBuf)], is_encrypted: impl Fn(&Path) -> bool) -> Vec<LocalImage> {
+    roots.iter()
+        .flat_map(|(origin, root)| {
+            fsscan::scan_images(root).into_iter().map(move |file| (origin, file))
+        })
+        .map(|(origin, file)| LocalImage {
+            image_id: file.file_name.clone(),
+            file_path: file.path.to_string_lossy().to_string(),
+            is_encrypted: is_encrypted(&file.path),
+            file_size_kb: file.size_kb(),
+            file_name: file.file_name,
+            origin: origin.clone(),
+            visibility: ImageVisibility::Public,
+            share_link: None,
+            escrow_preview: false,
+            max_grant_views: None,
+        })
+        .collect()
+}
+
+/// What reading one file found during the background scan
+enum ScannedFile {
+    /// A shared carrier; `rank` is its root's position, so the first root's copy of an ID wins
+    Carrier { rank: usize, image_id: String, file: fsscan::ImageFile, caption: Option<String> },
+    /// An image at the top level of a root, and whether it hides a payload
+    Local { file_path: String, is_encrypted: bool },
+}
+
+/// Check the carriers and read the catalog of `roots` in parallel, after we've registered
+///
+/// We register with whatever the scan has found so far (nothing, the first time) and the
+/// catalog is sent to the directory again after every batch, so peers see images as they're
+/// found rather than after the whole folder is read. Local images start out as unencrypted
+/// and are moved to the encrypted list as their payloads turn up. Stops early once `cancel`
+/// is set, e.g. by going offline.
+async fn run_catalog_scan(
+    app: tauri::AppHandle,
+    roots: Vec<(String, PathBuf)>,
+    owner: String,
+    max_grant_views: Option<u32>,
+    cancel: Arc<AtomicBool>,
+) {
+    let state = app.state::<AppState>();
+
+    // Move damaged carriers aside before scanning, so they're reported rather than silently skipped
+    let carrier_dirs: Vec<PathBuf> = roots.iter()
+        .flat_map(|(_, root)| [root.join("encrypted"), root.join("received")])
+        .collect();
+    let Ok(report) = tokio::task::spawn_blocking(move || integrity::scan_folders(&carrier_dirs)).await else {
+        return;
+    };
+    eprintln!("🩺 Integrity scan: {}", report.summary());
+    if let Ok(mut integrity_report) = state.integrity_report.lock() {
+        *integrity_report = Some(report);
+    }
+
+    let mut files = Vec::new();
+    for (rank, (_, root)) in roots.iter().enumerate() {
+        files.extend(fsscan::scan_images(&root.join("encrypted")).into_iter().map(|file| (Some(rank), file)));
+        files.extend(fsscan::scan_images(root).into_iter().map(|file| (None, file)));
+    }
+    let total = files.len();
+    let mut batches = fsscan::scan_parallel(files, fsscan::scan_workers(), cancel.clone(), |(rank, file)| match rank {
+        Some(rank) => ScannedFile::Carrier {
+            rank,
+            image_id: shared_image_id(&file.path),
+            caption: embedded_caption(&file.path),
+            file,
+        },
+        None => ScannedFile::Local {
+            is_encrypted: fsscan::is_encrypted_carrier(&file.path),
+            file_path: file.path.to_string_lossy().into_owned(),
+        },
+    });
+
+    let mut catalog: BTreeMap<String, (usize, ImageInfo)> = BTreeMap::new();
+    let mut scanned = 0;
+    while let Some(batch) = batches.recv().await {
+        if cancel.load(Ordering::Relaxed) {
+            eprintln!("Catalog scan cancelled after {}/{} files", scanned, total);
+            return;
+        }
+        scanned += batch.len();
+        let shared_before = catalog.len();
+        for file in batch {
+            match file {
+                ScannedFile::Carrier { rank, image_id, file, caption } => {
+                    if catalog.get(&image_id).is_some_and(|(kept, _)| *kept < rank) {
+                        eprintln!("Skipping '{}': already shared from another root", image_id);
+                        continue;
                     }
+                    let info = add_shared_carrier(&state, image_id.clone(), file, caption, &owner, max_grant_views).await;
+                    catalog.insert(image_id, (rank, info));
                 }
+                ScannedFile::Local { file_path, is_encrypted: true } => mark_local_encrypted(&state, &file_path),
+                ScannedFile::Local { .. } => {}
+            }
+        }
+        if catalog.len() != shared_before {
+            let shared_images = catalog.values().map(|(_, info)| info.clone()).collect();
+            if let Err(e) = enqueue_job(&state, JobKind::UpdateSharedImages { shared_images }) {
+                eprintln!("Failed to queue the catalog update: {}", e);
             }
         }
     }
 
-    // NOTE: We only show images from the main directory the user entered
-    // Encrypted images (in the /encrypted subfolder) are NOT shown in local images
-    // They are only used for sharing with peers
-    
-    // Get local IP address dynamically
-    let local_ip = match get_local_ip() {
-        Ok(ip) => {
-            eprintln!("Detected local IP: {}", ip);
-            ip
-        }
-        Err(e) => {
-            eprintln!("Failed to detect local IP: {}, falling back to 0.0.0.0", e);
-            return Ok(ApiResponse {
-                success: false,
-                message: format!("Failed to detect local IP address: {}. Please check your network connection.", e),
+    eprintln!("📂 Scanned {} files: sharing {} images", total, catalog.len());
+    emit_state_event(&state, StateEvent::CatalogScanned { files: total, shared_images: catalog.len() });
+}
+
+/// Move a local image found to hide a payload onto the encrypted list
+fn mark_local_encrypted(state: &AppState, file_path: &str) {
+    let Ok(mut local_images) = state.local_images.lock() else {
+        return;
+    };
+    let Some(image) = local_images.iter_mut().find(|img| img.file_path == file_path && !img.is_encrypted) else {
+        return;
+    };
+    image.is_encrypted = true;
+    let image = image.clone();
+    drop(local_images);
+    emit_state_event(state, StateEvent::ImageRemoved { image_id: image.image_id.clone(), file_path: image.file_path.clone() });
+    emit_state_event(state, StateEvent::ImageAdded(local_entry(image)));
+}
+
+/// Every image in the received folder, with its owner and our remaining views when it carries permissions
+///
+/// Read from the received index, which only decodes new or changed files unless `force_rescan` is set.
+fn scan_received_images(
+    received_dir: &Path,
+    username: Option<&str>,
+    force_rescan: bool,
+    sort: ReceivedSort,
+    descending: bool,
+) -> Vec<ReceivedImage> {
+    let index = ReceivedIndex::refresh(received_dir, force_rescan);
+    let views = |entry: &IndexedImage| username.map(|user| entry.views_remaining(user)).unwrap_or(0);
+    let mut entries: Vec<&IndexedImage> = index.images()
+        // Skip the viewable_image.png temp file
+        .filter(|entry| entry.file_name != "viewable_image.png")
+        .collect();
+    match sort {
+        ReceivedSort::Name => {}  // The index is kept in name order
+        ReceivedSort::Owner => entries.sort_by(|a, b| a.from_owner.cmp(&b.from_owner)),
+        ReceivedSort::Date => entries.sort_by_key(|entry| entry.received_at),
+        ReceivedSort::Views => entries.sort_by_key(|entry| views(*entry)),
+    }
+    if descending {
+        entries.reverse();
+    }
+
+    entries.into_iter()
+        .map(|entry| ReceivedImage {
+            image_id: entry.file_name.clone(),
+            from_owner: entry.from_owner.clone().unwrap_or_else(|| "Unknown".to_string()),
+            file_path: received_dir.join(&entry.file_name).to_string_lossy().to_string(),
+            file_name: entry.file_name.clone(),
+            views_remaining: views(entry),
+            received_at: entry.received_at.map(time_ago).unwrap_or_else(|| "Unknown".to_string()),
+            sha256: entry.sha256.clone(),
+            size_bytes: entry.size_bytes,
+            provenance: entry.from_owner.as_deref()
+                .filter(|_| !entry.provenance.is_empty())
+                .map(|owner| provenance_chain(owner, &entry.provenance)),
+        })
+        .collect()
+}
+
+/// The root a file lives under, matching the deepest root first
+fn root_containing<'a>(roots: &'a [(String, PathBuf)], path: &Path) -> Option<&'a (String, PathBuf)> {
+    roots.iter()
+        .filter(|(_, root)| path.starts_with(root))
+        .max_by_key(|(_, root)| root.components().count())
+}
+
+// ============================================================================
+// PEER ADDRESS CACHE
+// ============================================================================
+
+/// How long a cached directory entry is trusted before we query the directory again
+const PEER_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// An online peer's directory entry, plus how quickly it answered us last time
+#[derive(Debug, Clone)]
+pub struct CachedPeer {
+    pub entry: UserEntry,
+    pub fetched_at: Instant,
+    pub latency: Option<Duration>,
+}
+
+/// Remember an online peer's entry, keeping its latency if the address hasn't changed
+fn cache_peer(state: &AppState, entry: &UserEntry) -> Result<(), String> {
+    let mut cache = state.peer_cache.lock().map_err(|e| e.to_string())?;
+    if entry.status != UserStatus::Online {
+        cache.remove(&entry.username);
+        return Ok(());
+    }
+    let latency = cache.get(&entry.username)
+        .filter(|cached| cached.entry.p2p_address == entry.p2p_address)
+        .and_then(|cached| cached.latency);
+    cache.insert(entry.username.clone(), CachedPeer {
+        entry: entry.clone(),
+        fetched_at: Instant::now(),
+        latency,
+    });
+    Ok(())
+}
+
+/// Query the directory for a peer, refreshing its cache entry
+async fn requery_peer(state: &AppState, peer_username: &str) -> Result<Option<UserEntry>, String> {
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    let query_msg = DirectoryMessage::QueryUser {
+        username: peer_username.to_string(),
+        requesting_user: None,
+    };
+
+    match multicast_directory_message(&dir_servers, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(peer), .. }) => {
+            cache_peer(state, &peer)?;
+            Ok(Some(peer))
+        }
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            state.peer_cache.lock().map_err(|e| e.to_string())?.remove(peer_username);
+            Ok(None)
+        }
+        Ok(_) => Err("Unexpected response".to_string()),
+        Err(e) => Err(format!("Failed to query peer: {}", e)),
+    }
+}
+
+/// Look up a peer, serving from the cache while the entry is fresh
+async fn resolve_peer(state: &AppState, peer_username: &str) -> Result<Option<UserEntry>, String> {
+    let cached = state.peer_cache.lock().map_err(|e| e.to_string())?
+        .get(peer_username)
+        .filter(|cached| cached.fetched_at.elapsed() < PEER_CACHE_TTL)
+        .map(|cached| cached.entry.clone());
+
+    match cached {
+        Some(peer) => Ok(Some(peer)),
+        None => requery_peer(state, peer_username).await,
+    }
+}
+
+/// Run `send` against a peer resolved through the cache, timing the round trip.
+/// If it fails, the cached entry is dropped and `send` is retried once against a fresh lookup.
+/// Returns Ok(None) when the directory doesn't know the peer.
+async fn resolve_and_send<T, F, Fut>(state: &AppState, peer_username: &str, send: F) -> Result<Option<T>, String>
+where
+    F: Fn(UserEntry) -> Fut,
+    Fut: Future<Output = Result<T>>,
+{
+    let Some(peer) = resolve_peer(state, peer_username).await? else {
+        return Ok(None);
+    };
+
+    let started = Instant::now();
+    let result = match send(peer.clone()).await {
+        Ok(value) => value,
+        Err(e) => {
+            eprintln!("Call to {} at {} failed ({}), re-resolving", peer_username, peer.p2p_address, e);
+            state.peer_cache.lock().map_err(|e| e.to_string())?.remove(peer_username);
+
+            let Some(peer) = requery_peer(state, peer_username).await? else {
+                return Ok(None);
+            };
+            let started = Instant::now();
+            let value = send(peer).await.map_err(|e| e.to_string())?;
+            record_peer_latency(state, peer_username, started.elapsed())?;
+            return Ok(Some(value));
+        }
+    };
+    record_peer_latency(state, peer_username, started.elapsed())?;
+    Ok(Some(result))
+}
+
+fn record_peer_latency(state: &AppState, peer_username: &str, latency: Duration) -> Result<(), String> {
+    if let Some(cached) = state.peer_cache.lock().map_err(|e| e.to_string())?.get_mut(peer_username) {
+        cached.latency = Some(latency);
+    }
+    reputation::record_latency(peer_username, latency);
+    Ok(())
+}
+
+// ============================================================================
+// TAURI COMMANDS
+// ============================================================================
+
+#[tauri::command]
+async fn set_directory_servers(
+    state: State<'_, AppState>,
+    servers: Vec<String>,
+) -> Result<ApiResponse<()>, String> {
+    let addresses = {
+        let mut bootstrap = state.directory_bootstrap.lock().map_err(|e| e.to_string())?;
+        bootstrap.set_addresses(servers.iter().map(String::as_str));
+        if let Err(e) = bootstrap.save(&DirectoryBootstrap::default_path()) {
+            eprintln!("Failed to save directory servers: {}", e);
+        }
+        bootstrap.addresses()
+    };
+    *state.directory_servers.lock().map_err(|e| e.to_string())? = addresses.clone();
+    state.image_store.write().await.set_directory_servers(addresses);
+    
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("directory_servers_set", "Set {count} directory servers", count = servers.len()),
+        data: None,
+    })
+}
+
+#[tauri::command]
+async fn get_directory_servers(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<String>>, String> {
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?;
+    
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("directory_servers_retrieved", "Directory servers retrieved"),
+        data: Some(dir_servers.clone()),
+    })
+}
+
+/// Directory servers after a refresh, best first
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryListInfo {
+    pub servers: Vec<KnownDirectory>,
+    pub healthy: usize,
+    pub learned: Vec<String>,
+}
+
+/// Health-check the directory servers, learn replicas they advertise, and persist the new order
+async fn refresh_directories(state: &AppState) -> Result<(RefreshReport, Vec<KnownDirectory>), String> {
+    let mut bootstrap = state.directory_bootstrap.lock().map_err(|e| e.to_string())?.clone();
+    let checked = bootstrap.addresses();
+    let report = bootstrap.refresh().await;
+
+    {
+        let mut current = state.directory_bootstrap.lock().map_err(|e| e.to_string())?;
+        if current.addresses() != checked {
+            return Err("Directory servers were changed during the refresh; try again".to_string());
+        }
+        *current = bootstrap.clone();
+    }
+    if let Err(e) = bootstrap.save(&DirectoryBootstrap::default_path()) {
+        eprintln!("Failed to save directory servers: {}", e);
+    }
+    let servers = bootstrap.addresses();
+    *state.directory_servers.lock().map_err(|e| e.to_string())? = servers.clone();
+    state.image_store.write().await.set_directory_servers(servers.clone());
+    emit_state_event(state, StateEvent::DirectoryServersChanged { servers });
+
+    Ok((report, bootstrap.servers))
+}
+
+#[tauri::command]
+async fn refresh_directory_list(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<DirectoryListInfo>, String> {
+    let (report, servers) = refresh_directories(&state).await?;
+    let message = if report.learned.is_empty() {
+        msg!("directory_servers_healthy", "{healthy}/{total} directory servers healthy", healthy = report.healthy, total = report.total)
+    } else {
+        msg!(
+            "directory_servers_healthy_learned",
+            "{healthy}/{total} directory servers healthy, learned {learned}",
+            healthy = report.healthy,
+            total = report.total,
+            learned = report.learned.join(", "),
+        )
+    };
+
+    Ok(ApiResponse {
+        success: report.healthy > 0,
+        message,
+        data: Some(DirectoryListInfo { servers, healthy: report.healthy, learned: report.learned }),
+    })
+}
+
+/// One configured directory server and what it reported about itself
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryServerInfo {
+    pub address: String,
+    /// Unset if the server didn't answer
+    pub info: Option<ServerInfo>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+/// Ask every configured directory server which replica it is, for the dashboard
+#[tauri::command]
+async fn get_directory_server_info(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<DirectoryServerInfo>>, String> {
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let mut servers = Vec::new();
+    for address in dir_servers {
+        let (info, error) = match send_directory_message_async(&address, DirectoryMessage::GetServerInfo {}).await {
+            Ok(DirectoryMessage::GetServerInfoResponse { info }) => (Some(info), None),
+            Ok(_) => (None, Some("Server doesn't report its info (older version)".to_string())),
+            Err(e) => (None, Some(e.to_string())),
+        };
+        servers.push(DirectoryServerInfo { address, info, error });
+    }
+
+    let answered = servers.iter().filter(|server| server.info.is_some()).count();
+    Ok(ApiResponse {
+        success: answered > 0,
+        message: msg!("directory_servers_answered", "{answered}/{total} directory servers answered", answered = answered, total = servers.len()),
+        data: Some(servers),
+    })
+}
+
+#[tauri::command]
+async fn set_share_roots(
+    state: State<'_, AppState>,
+    roots: Vec<ShareRoot>,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change share folders")? {
+        return Ok(refusal);
+    }
+    if let Some(root) = roots.iter().find(|r| r.name.trim().is_empty() || r.name == PRIMARY_ROOT_NAME) {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("invalid_share_root_name", "Invalid share root name '{name}'", name = root.name),
+            data: None,
+        });
+    }
+    let mut names = HashSet::new();
+    if let Some(root) = roots.iter().find(|r| !names.insert(r.name.clone())) {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("duplicate_share_root_name", "Duplicate share root name '{name}'", name = root.name),
+            data: None,
+        });
+    }
+
+    for root in &roots {
+        let _ = fs::create_dir_all(PathBuf::from(&root.path).join("encrypted"));
+    }
+
+    *state.share_roots.lock().map_err(|e| e.to_string())? = roots.clone();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("share_roots_set", "Set {count} share roots", count = roots.len()),
+        data: None,
+    })
+}
+
+#[tauri::command]
+async fn get_share_roots(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<ShareRoot>>, String> {
+    let roots = state.share_roots.lock().map_err(|e| e.to_string())?.clone();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("share_roots_configured", "{count} share roots configured", count = roots.len()),
+        data: Some(roots),
+    })
+}
+
+#[tauri::command]
+async fn go_online(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    username: String,
+    port: u16,
+    images_dir: String,
+    max_grant_views: Option<u32>,
+    availability: Option<AvailabilityWindow>,
+    anonymous: Option<bool>,
+    namespace: Option<String>,
+) -> Result<ApiResponse<Vec<LocalImage>>, String> {
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    
+    if dir_servers.is_empty() {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("no_directory_servers_configured", "No directory servers configured"),
+            data: None,
+        });
+    }
+
+    if let Some(Err(e)) = availability.map(|w| AvailabilityWindow::new(w.start_minute, w.end_minute)) {
+        return Ok(ApiResponse { success: false, message: msg!("invalid_availability", "Invalid availability: {error}", error = e), data: None });
+    }
+
+    // Every directory message from here on, heartbeats included, goes to this namespace
+    if let Err(e) = set_namespace(namespace.filter(|namespace| !namespace.is_empty())) {
+        return Ok(ApiResponse { success: false, message: msg!("invalid_namespace", "Invalid namespace: {error}", error = e), data: None });
+    }
+    
+    // Setup directory structure
+    let images_path = PathBuf::from(&images_dir);
+    let encrypted_dir = images_path.join("encrypted");
+    let received_dir = images_path.join("received");
+
+    // Create subdirectories if they don't exist
+    let _ = fs::create_dir_all(&encrypted_dir);
+    let _ = fs::create_dir_all(&received_dir);
+
+    let share_roots = state.share_roots.lock().map_err(|e| e.to_string())?.clone();
+    let roots = image_roots(&images_path, &share_roots);
+
+    // Reading every image takes minutes in large folders, so only list them here; the
+    // background scan started once we're registered fills in the catalog
+    state.image_store.write().await.set_visibility_path(images_path.join(VISIBILITY_FILE));
+    let local_images_list = list_local_images(&roots, |_| false);
+
+    // NOTE: We only show images from the top level of each root
+    // Encrypted images (in the /encrypted subfolders) are NOT shown in local images
+    // They are only used for sharing with peers
+    
+    // Get local IP address dynamically
+    let local_ip = match get_local_ip() {
+        Ok(ip) => {
+            eprintln!("Detected local IP: {}", ip);
+            ip
+        }
+        Err(e) => {
+            eprintln!("Failed to detect local IP: {}, falling back to 0.0.0.0", e);
+            return Ok(ApiResponse {
+                success: false,
+                message: msg!("local_ip_not_detected", "Failed to detect local IP address: {error}. Please check your network connection.", error = e),
                 data: None,
             });
         }
     };
-    let p2p_address = format!("{}:{}", local_ip, port);
+    // Anonymous peers register their relay and handle instead of an address, and open no port
+    let relay_addr = anonymous.unwrap_or(false).then(|| dir_servers[0].clone());
+    let relay_secret = match &relay_addr {
+        Some(_) => match relay::relay_secret(&images_path.join(relay::RELAY_SECRETS_FILE), &username) {
+            Ok(secret) => Some(secret),
+            Err(e) => return Ok(ApiResponse { success: false, message: msg!("failed_to_set_up_anonymous_mode", "Failed to set up anonymous mode: {error}", error = e), data: None }),
+        },
+        None => None,
+    };
+    let key = match user_key(&images_path.join(USER_KEYS_FILE), &username) {
+        Ok(key) => key,
+        Err(e) => return Ok(ApiResponse { success: false, message: msg!("failed_to_set_up_registration_key", "Failed to set up the registration key: {error}", error = e), data: None }),
+    };
+    let p2p_address = match &relay_addr {
+        Some(relay_addr) => relay::relay_p2p_address(relay_addr, &username),
+        None => format!("{}:{}", local_ip, port),
+    };
     
     // Register with directory service
+    let previous_phase = match state.update_session(Session::begin_connecting)? {
+        Ok(previous) => previous,
+        Err(message) => return Ok(ApiResponse { success: false, message: message.into(), data: None }),
+    };
     let register_msg = DirectoryMessage::Register {
         username: username.clone(),
         p2p_address: p2p_address.clone(),
-        shared_images,
+        shared_images: Vec::new(),
+        capabilities: local_capabilities(),
+        availability,
+        key: Some(key.clone()),
     };
     
-    match multicast_directory_message(&dir_servers, register_msg).await {
-        Ok(DirectoryMessage::RegisterResponse { success, message }) => {
+    let registered = multicast_directory_message(&dir_servers, register_msg).await;
+    if !matches!(registered, Ok(DirectoryMessage::RegisterResponse { success: true, .. })) {
+        state.update_session(|session| session.phase = previous_phase)?;
+    }
+    match registered {
+        Ok(DirectoryMessage::RegisterResponse { success, message, heartbeat_interval_secs, .. }) => {
             if success {
                 // Update state
-                *state.username.lock().map_err(|e| e.to_string())? = Some(username.clone());
-                *state.p2p_port.lock().map_err(|e| e.to_string())? = Some(port);
-                *state.is_online.lock().map_err(|e| e.to_string())? = true;
-                *state.images_directory.lock().map_err(|e| e.to_string())? = Some(images_path.clone());
-                *state.local_images.lock().map_err(|e| e.to_string())? = local_images_list.clone();
-                *state.p2p_address.lock().map_err(|e| e.to_string())? = Some(p2p_address.clone());
+                state.update_session(|session| {
+                    session.finish_connecting(username.clone(), port, p2p_address.clone(), images_path.clone(), key.clone());
+                    session.max_grant_views = max_grant_views;
+                    session.availability = availability;
+                })?;
+                store_local_images(&state, local_images_list.clone())?;
+                if let Some(reconnect) = state.tasks.lock().await.reconnect.take() {
+                    let _ = reconnect.send(()).await;
+                }
                 
                 // Set received images directory in the image store to the received/ subfolder
                 {
                     let mut store = state.image_store.write().await;
                     store.set_received_images_dir(received_dir.clone());
+                    store.set_denial_stats_path(images_path.join(ACCESS_DENIAL_STATS_FILE));
+                    store.set_audit_log_path(images_path.join(AUDIT_LOG_FILE));
+                    store.set_directory_servers(dir_servers.clone());
                 }
+                bandwidth::set_bandwidth_stats_path(images_path.join(BANDWIDTH_STATS_FILE));
+                encryption_pool::set_encryption_stats_path(images_path.join(ENCRYPTION_STATS_FILE));
+                reputation::set_reputation_path(images_path.join(REPUTATION_FILE));
+                state.jobs.lock().map_err(|e| e.to_string())?.set_path(images_path.join(JOB_QUEUE_FILE));
                 
-                // Start P2P server in background
+                // Start P2P server in background, or serve through the relay when anonymous
                 let store_clone = state.image_store.clone();
                 let user_clone = username.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = start_p2p_server(port, user_clone, store_clone).await {
-                        eprintln!("P2P server error: {}", e);
+                relay::set_outbound_relay(relay_addr.clone());
+                if let Some(previous) = state.tasks.lock().await.relay.take() {
+                    let _ = previous.send(()).await;
+                }
+                match relay_addr.zip(relay_secret) {
+                    Some((relay_addr, secret)) => {
+                        let (relay_tx, mut relay_rx) = mpsc::channel::<()>(1);
+                        state.tasks.lock().await.relay = Some(relay_tx);
+                        tokio::spawn(async move {
+                            tokio::select! {
+                                result = serve_p2p_via_relay(relay_addr, user_clone, secret, store_clone) => {
+                                    if let Err(e) = result {
+                                        eprintln!("Relay error: {}", e);
+                                    }
+                                }
+                                _ = relay_rx.recv() => eprintln!("Stopped serving through the relay"),
+                            }
+                        });
                     }
-                });
+                    None => {
+                        tokio::spawn(async move {
+                            if let Err(e) = start_p2p_server(port, user_clone, store_clone).await {
+                                eprintln!("P2P server error: {}", e);
+                            }
+                        });
+                    }
+                }
                 
                 // Start heartbeat task with shutdown channel
+                let heartbeat_app = app.clone();
                 let heartbeat_username = username.clone();
                 let heartbeat_servers = dir_servers.clone();
+                let heartbeat_interval = negotiated_heartbeat_interval(heartbeat_interval_secs);
                 let (shutdown_tx, mut shutdown_rx) = mpsc::channel::<()>(1);
 
                 // Store the shutdown sender in state so we can cancel the heartbeat task
-                *state.heartbeat_shutdown.lock().await = Some(shutdown_tx);
+                state.tasks.lock().await.heartbeat = Some(shutdown_tx);
 
                 tokio::spawn(async move {
                     loop {
                         tokio::select! {
-                            _ = tokio::time::sleep(Duration::from_secs(10)) => {
+                            _ = tokio::time::sleep(heartbeat_interval) => {
                                 let heartbeat_msg = DirectoryMessage::Heartbeat {
                                     username: heartbeat_username.clone(),
+                                    capabilities: Some(local_capabilities()),
                                 };
 
-                                if let Err(e) = multicast_directory_message(&heartbeat_servers, heartbeat_msg).await {
-                                    eprintln!("Heartbeat failed: {}", e);
+                                let state = heartbeat_app.state::<AppState>();
+                                match multicast_tracked(&state, &heartbeat_servers, heartbeat_msg).await {
+                                    Ok(DirectoryMessage::HeartbeatResponse { success: true, due_reminders }) => {
+                                        note_heartbeat(&state);
+                                        note_due_reminders(&state, due_reminders);
+                                    }
+                                    Ok(_) => eprintln!("Heartbeat was not acknowledged"),
+                                    Err(e) => eprintln!("Heartbeat failed: {}", e),
                                 }
                             }
                             _ = shutdown_rx.recv() => {
@@ -414,28 +1495,49 @@ async fn go_online(
                         }
                     }
                 });
+
+                // Start the background job worker
+                let (job_shutdown_tx, job_shutdown_rx) = mpsc::channel::<()>(1);
+                let cancel_scan = Arc::new(AtomicBool::new(false));
+                {
+                    let mut tasks = state.tasks.lock().await;
+                    if let Some(previous) = tasks.job_worker.replace(job_shutdown_tx) {
+                        let _ = previous.send(()).await;
+                    }
+                    if let Some(previous) = tasks.catalog_scan.replace(cancel_scan.clone()) {
+                        previous.store(true, Ordering::Relaxed);
+                    }
+                }
+                tokio::spawn(run_catalog_scan(app.clone(), roots, username.clone(), max_grant_views, cancel_scan));
+                tokio::spawn(verify_received_grants(app.clone(), received_dir));
+                tokio::spawn(run_job_worker(app, job_shutdown_rx));
+                spawn_tray_refresh(&state);
                 
                 Ok(ApiResponse {
                     success: true,
-                    message: format!("Connected as {} on port {}", username, port),
+                    message: if anonymous.unwrap_or(false) {
+                        msg!("connected_anonymously", "Connected anonymously as {username} through a relay", username = username)
+                    } else {
+                        msg!("connected", "Connected as {username} on port {port}", username = username, port = port)
+                    },
                     data: Some(local_images_list),
                 })
             } else {
                 Ok(ApiResponse {
                     success: false,
-                    message,
+                    message: message.into(),
                     data: None,
                 })
             }
         }
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response from directory service".to_string(),
+            message: msg!("unexpected_response_from_directory_service", "Unexpected response from directory service"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to connect: {}", e),
+            message: msg!("failed_to_connect", "Failed to connect: {error}", error = e),
             data: None,
         }),
     }
@@ -445,16 +1547,12 @@ async fn go_online(
 async fn go_offline(
     state: State<'_, AppState>,
 ) -> Result<ApiResponse<()>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone();
+    let username = state.session()?.username;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
 
-    // CRITICAL FIX: Stop the heartbeat task FIRST before unregistering
-    // This prevents the heartbeat from re-registering the user after we unregister
-    if let Some(sender) = state.heartbeat_shutdown.lock().await.take() {
-        // Send shutdown signal - this will stop the heartbeat loop
-        let _ = sender.send(()).await;
-        eprintln!("Sent shutdown signal to heartbeat task");
-    }
+    // Stop the heartbeat (and everything else) BEFORE unregistering, so it can't register us again
+    state.tasks.lock().await.stop_all().await;
+    state.catalog_watches.lock().map_err(|e| e.to_string())?.clear();
 
     if let Some(user) = username {
         let unregister_msg = DirectoryMessage::Unregister {
@@ -464,33 +1562,93 @@ async fn go_offline(
         let _ = multicast_directory_message(&dir_servers, unregister_msg).await;
     }
 
-    *state.is_online.lock().map_err(|e| e.to_string())? = false;
-    *state.username.lock().map_err(|e| e.to_string())? = None;
-    *state.p2p_port.lock().map_err(|e| e.to_string())? = None;
+    state.update_session(Session::end)?;
+    state.peer_cache.lock().map_err(|e| e.to_string())?.clear();
+    *state.jobs.lock().map_err(|e| e.to_string())? = JobQueue::default();
+    spawn_tray_refresh(&state);
 
     Ok(ApiResponse {
         success: true,
-        message: "Went offline successfully".to_string(),
+        message: msg!("went_offline", "Went offline successfully"),
         data: None,
     })
 }
 
+/// Connection health for the status widget
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionStatus {
+    pub is_online: bool,
+    /// Offline, connecting (going online or reconnecting) or online
+    pub phase: SessionPhase,
+    pub username: Option<String>,
+    pub port: Option<u16>,
+    /// Unix time a directory last acknowledged our heartbeat
+    pub last_heartbeat: Option<u64>,
+    pub heartbeat_failures: u32,
+    /// Directory replicas that answered the last time we contacted them
+    pub responsive_directories: Vec<String>,
+    /// Address our P2P server is bound to
+    pub p2p_bind_address: Option<String>,
+    /// Address peers reach it at, as registered with the directory
+    pub p2p_address: Option<String>,
+    /// Serving through a relay under a pseudonymous handle, with no port open
+    pub anonymous: bool,
+    /// Image transfers being sent or served right now
+    pub active_transfers: usize,
+    /// Connections our P2P server is serving, queueing and has turned away
+    pub server_load: ServerLoadStats,
+    /// Registration attempts so far, while reconnecting after heartbeats failed
+    pub reconnect_attempts: Option<u32>,
+}
+
+/// Record a heartbeat a directory acknowledged
+fn note_heartbeat(state: &AppState) {
+    let _ = state.update_session(|session| {
+        session.heartbeat_failures = 0;
+        session.last_heartbeat = Some(SystemTime::now());
+    });
+}
+
+/// Remember the heartbeat's overdue request count, announcing it when more requests come due
+fn note_due_reminders(state: &AppState, count: usize) {
+    let Ok(previous) = state.update_session(|session| std::mem::replace(&mut session.due_reminders, count)) else {
+        return;
+    };
+    if count > previous {
+        emit_state_event(state, StateEvent::RemindersDue { count });
+        spawn_tray_refresh(state);
+    }
+}
+
 #[tauri::command]
 async fn get_connection_status(
     state: State<'_, AppState>,
-) -> Result<ApiResponse<serde_json::Value>, String> {
-    let is_online = *state.is_online.lock().map_err(|e| e.to_string())?;
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone();
-    let port = state.p2p_port.lock().map_err(|e| e.to_string())?.clone();
+) -> Result<ApiResponse<ConnectionStatus>, String> {
+    let session = state.session()?;
+    let is_online = session.is_online();
+    let anonymous = session.p2p_address.as_deref().and_then(relay::parse_relay_address).is_some();
+    let status = ConnectionStatus {
+        is_online,
+        phase: session.phase,
+        username: session.username,
+        port: session.p2p_port,
+        last_heartbeat: session.last_heartbeat
+            .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+            .map(|since| since.as_secs()),
+        heartbeat_failures: session.heartbeat_failures,
+        responsive_directories: state.directory_bootstrap.lock().map_err(|e| e.to_string())?.responsive(),
+        p2p_bind_address: session.p2p_port.filter(|_| is_online && !anonymous).map(p2p_protocol::p2p_bind_address),
+        p2p_address: session.p2p_address,
+        anonymous,
+        active_transfers: p2p_protocol::active_transfers(),
+        server_load: p2p_protocol::p2p_server_stats(),
+        reconnect_attempts: session.reconnect_attempts,
+    };
     
     Ok(ApiResponse {
         success: true,
-        message: "Status retrieved".to_string(),
-        data: Some(serde_json::json!({
-            "is_online": is_online,
-            "username": username,
-            "port": port,
-        })),
+        message: msg!("status_retrieved", "Status retrieved"),
+        data: Some(status),
     })
 }
 
@@ -498,8 +1656,7 @@ async fn get_connection_status(
 async fn discover_peers(
     state: State<'_, AppState>,
 ) -> Result<ApiResponse<Vec<PeerInfo>>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
 
     // Use QueryAllPeers to get both online and offline users
@@ -509,1485 +1666,5077 @@ async fn discover_peers(
 
     match multicast_directory_message(&dir_servers, query_msg).await {
         Ok(DirectoryMessage::QueryAllPeersResponse { peers }) => {
-            let peer_infos: Vec<PeerInfo> = peers.iter().map(|p| PeerInfo {
-                username: p.username.clone(),
-                p2p_address: p.p2p_address.clone(),
-                status: format!("{:?}", p.status),
-                shared_images: p.shared_images.iter().map(|img| ImageInfoJson {
-                    image_id: img.image_id.clone(),
-                    image_name: img.image_name.clone(),
-                    thumbnail_path: img.thumbnail_path.clone(),
-                }).collect(),
+            for peer in &peers {
+                cache_peer(&state, peer)?;
+                note_peer_status(&state, &peer.username, format!("{:?}", peer.status));
+            }
+            let latencies: HashMap<String, u64> = state.peer_cache.lock().map_err(|e| e.to_string())?
+                .iter()
+                .filter_map(|(name, cached)| cached.latency.map(|l| (name.clone(), l.as_millis() as u64)))
+                .collect();
+
+            let peer_infos: Vec<PeerInfo> = peers.iter().map(|p| {
+                let profile = ProfileJson::from(&p.profile);
+                let score = reputation::reputation(&p.username).map(|rep| rep.score);
+                PeerInfo {
+                    username: p.username.clone(),
+                    p2p_address: p.p2p_address.clone(),
+                    status: format!("{:?}", p.status),
+                    shared_images: p.shared_images.iter().map(ImageInfoJson::from).collect(),
+                    capabilities: p.capabilities.clone(),
+                    latency_ms: latencies.get(&p.username).copied(),
+                    availability: p.availability,
+                    outlook: p.response_outlook(SystemTime::now()),
+                    display_name: profile.display_name,
+                    bio: profile.bio,
+                    avatar: profile.avatar,
+                    reputation: score,
+                    low_reputation: score.is_some_and(|score| score < LOW_REPUTATION_SCORE),
+                    anonymous: p.is_anonymous(),
+                    anonymous_images: p.anonymous_images.clone(),
+                }
             }).collect();
 
             Ok(ApiResponse {
                 success: true,
-                message: format!("Found {} peers", peer_infos.len()),
+                message: msg!("found_peers", "Found {count} peers", count = peer_infos.len()),
                 data: Some(peer_infos),
             })
         }
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to discover peers: {}", e),
+            message: msg!("failed_to_discover_peers", "Failed to discover peers: {error}", error = e),
             data: None,
         }),
     }
 }
 
+/// Find which peers share an image, by ID (optionally `owner/image_id`) or content hash prefix
 #[tauri::command]
-async fn request_image(
+async fn find_image(
+    image_id_or_hash: String,
     state: State<'_, AppState>,
-    peer_username: String,
-    image_id: String,
-    views: u32,
-) -> Result<ApiResponse<String>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+) -> Result<ApiResponse<Vec<ImageHolderInfo>>, String> {
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    
-    let leave_request_msg = DirectoryMessage::LeaveRequest {
-        from_user: username,
-        to_user: peer_username.clone(),
-        image_id: image_id.clone(),
-        requested_views: views,
-    };
-    
-    match multicast_directory_message(&dir_servers, leave_request_msg).await {
-        Ok(DirectoryMessage::LeaveRequestResponse { success, request_id, message }) => {
+    let requesting_user = state.session()?.username;
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::QueryImageHolders { image_id_or_hash, requesting_user }).await {
+        Ok(DirectoryMessage::QueryImageHoldersResponse { holders }) => {
+            let holders: Vec<ImageHolderInfo> = holders.into_iter().map(|h| ImageHolderInfo {
+                qualified_id: qualified_image_id(&h.owner, &h.image.image_id),
+                owner: h.owner,
+                status: format!("{:?}", h.status),
+                image: ImageInfoJson::from(&h.image),
+            }).collect();
             Ok(ApiResponse {
-                success,
-                message,
-                data: if success { Some(request_id) } else { None },
+                success: true,
+                message: msg!("found_matching_images", "Found {count} matching images", count = holders.len()),
+                data: Some(holders),
             })
         }
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to request image: {}", e),
+            message: msg!("failed_to_search_directory", "Failed to search the directory: {error}", error = e),
             data: None,
         }),
     }
 }
 
+/// Search every shared catalog by image name and caption, tolerating typos and partial words
 #[tauri::command]
-async fn get_pending_requests(
+async fn search_catalog(
+    query: String,
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<RequestInfo>>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+) -> Result<ApiResponse<Vec<CatalogMatchInfo>>, String> {
+    let requesting_user = state.session()?.username;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    
-    let msg = DirectoryMessage::GetPendingRequests {
-        username,
-    };
-    
-    match multicast_directory_message(&dir_servers, msg).await {
-        Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) => {
-            let request_infos: Vec<RequestInfo> = requests.iter().map(|r| {
-                let timestamp_str = r.timestamp.duration_since(SystemTime::UNIX_EPOCH)
-                    .map(|d| {
-                        let secs = d.as_secs();
-                        let now_secs = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .map(|n| n.as_secs())
-                            .unwrap_or(0);
-                        let diff = now_secs.saturating_sub(secs);
-                        let mins = diff / 60;
-                        let hours = mins / 60;
-                        if hours > 0 {
-                            format!("{} hours ago", hours)
-                        } else if mins > 0 {
-                            format!("{} mins ago", mins)
-                        } else {
-                            "Just now".to_string()
-                        }
-                    })
-                    .unwrap_or_else(|_| "Unknown".to_string());
-                
-                RequestInfo {
-                    request_id: r.request_id.clone(),
-                    from_user: r.from_user.clone(),
-                    to_user: r.to_user.clone(),
-                    image_id: r.image_id.clone(),
-                    requested_views: r.requested_views,
-                    timestamp: timestamp_str,
-                    status: format!("{:?}", r.status),
-                }
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::SearchCatalog { query, limit: None, requesting_user }).await {
+        Ok(DirectoryMessage::SearchCatalogResponse { results }) => {
+            let results: Vec<CatalogMatchInfo> = results.into_iter().map(|m| CatalogMatchInfo {
+                qualified_id: qualified_image_id(&m.owner, &m.image.image_id),
+                owner: m.owner,
+                status: format!("{:?}", m.status),
+                image: ImageInfoJson::from(&m.image),
+                score: m.score,
+                your_grant: m.your_grant.map(ExistingGrantInfo::from),
             }).collect();
-            
             Ok(ApiResponse {
                 success: true,
-                message: format!("Found {} pending requests", request_infos.len()),
-                data: Some(request_infos),
+                message: msg!("found_matching_images", "Found {count} matching images", count = results.len()),
+                data: Some(results),
             })
         }
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to get requests: {}", e),
+            message: msg!("failed_to_search_directory", "Failed to search the directory: {error}", error = e),
             data: None,
         }),
     }
 }
 
+/// Get the profile we've published to the directory
 #[tauri::command]
-async fn respond_to_request(
+async fn get_profile(
     state: State<'_, AppState>,
-    request_id: String,
-    accept: bool,
-) -> Result<ApiResponse<()>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+) -> Result<ApiResponse<ProfileJson>, String> {
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    let p2p_address = state.p2p_address.lock().map_err(|e| e.to_string())?.clone();
-    
-    let msg = DirectoryMessage::RespondToRequest {
-        request_id: request_id.clone(),
-        owner: username.clone(),
-        accept,
-    };
-    
-    match multicast_directory_message(&dir_servers, msg).await {
-        Ok(DirectoryMessage::RespondToRequestResponse { success, message, request }) => {
-            if success && accept {
-                // If accepted, grant permissions and deliver image
-                if let Some(req) = request {
-                    if let Some(own_addr) = p2p_address {
-                        // Fetch the image from our P2P server with the REQUESTING user's name
-                        // so the quota gets embedded for them, not the owner
-                        match request_image_from_peer(&own_addr, &req.from_user, &req.image_id, req.requested_views).await {
-                            Ok(encrypted_image) => {
-                                // Try to deliver to the requester
-                                let query_msg = DirectoryMessage::QueryUser {
-                                    username: req.from_user.clone(),
-                                };
-                                
-                                if let Ok(DirectoryMessage::QueryUserResponse { user: Some(target) }) = 
-                                    multicast_directory_message(&dir_servers, query_msg).await {
-                                    if target.status == UserStatus::Online {
-                                        let deliver_msg = P2PMessage::DeliverImage {
-                                            from_owner: username.clone(),
-                                            image_id: req.image_id.clone(),
-                                            requested_views: req.requested_views,
-                                            encrypted_image: encrypted_image.clone(),
-                                        };
-                                        
-                                        let _ = send_p2p_message(&target.p2p_address, deliver_msg).await;
-                                    } else {
-                                        // Store for later delivery
-                                        let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
-                                            from_owner: username.clone(),
-                                            target_user: req.from_user.clone(),
-                                            image_id: req.image_id.clone(),
-                                            new_quota: req.requested_views,
-                                            embedded_image: Some(encrypted_image),
-                                        };
-                                        let _ = multicast_directory_message(&dir_servers, pending_msg).await;
-                                    }
-                                }
-                            }
-                            Err(e) => {
-                                eprintln!("Failed to fetch image for delivery: {}", e);
-                            }
-                        }
-                    }
-                }
-            }
-            
-            Ok(ApiResponse {
-                success,
-                message,
-                data: None,
-            })
-        }
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::QueryUser { username, requesting_user: None }).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => Ok(ApiResponse {
+            success: true,
+            message: msg!("profile_loaded", "Profile loaded"),
+            data: Some(ProfileJson::from(&user.profile)),
+        }),
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("not_registered_with_directory", "Not registered with the directory"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to respond: {}", e),
+            message: msg!("failed_to_load_profile", "Failed to load profile: {error}", error = e),
             data: None,
         }),
     }
 }
 
+/// Users we've let answer requests for our images
 #[tauri::command]
-async fn get_notifications(
+async fn get_delegates(
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<NotificationInfo>>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+) -> Result<ApiResponse<Vec<Delegation>>, String> {
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    
-    let msg = DirectoryMessage::GetNotifications {
-        username,
-    };
-    
-    match multicast_directory_message(&dir_servers, msg).await {
-        Ok(DirectoryMessage::GetNotificationsResponse { notifications }) => {
-            let notif_infos: Vec<NotificationInfo> = notifications.iter().map(|n| {
-                let timestamp_str = n.timestamp.duration_since(SystemTime::UNIX_EPOCH)
-                    .map(|d| {
-                        let secs = d.as_secs();
-                        let now_secs = SystemTime::now()
-                            .duration_since(SystemTime::UNIX_EPOCH)
-                            .map(|ns| ns.as_secs())
-                            .unwrap_or(0);
-                        let diff = now_secs.saturating_sub(secs);
-                        let mins = diff / 60;
-                        let hours = mins / 60;
-                        if hours > 0 {
-                            format!("{} hours ago", hours)
-                        } else if mins > 0 {
-                            format!("{} mins ago", mins)
-                        } else {
-                            "Just now".to_string()
-                        }
-                    })
-                    .unwrap_or_else(|_| "Unknown".to_string());
-                
-                NotificationInfo {
-                    request_id: n.request_id.clone(),
-                    to_user: n.to_user.clone(),
-                    image_id: n.image_id.clone(),
-                    requested_views: n.requested_views,
-                    status: format!("{:?}", n.status),
-                    timestamp: timestamp_str,
-                }
-            }).collect();
-            
-            Ok(ApiResponse {
-                success: true,
-                message: format!("Found {} notifications", notif_infos.len()),
-                data: Some(notif_infos),
-            })
-        }
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::QueryUser { username, requesting_user: None }).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => Ok(ApiResponse {
+            success: true,
+            message: msg!("delegate_count", "{count} delegate(s)", count = user.delegates.len()),
+            data: Some(user.delegates),
+        }),
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("not_registered_with_directory", "Not registered with the directory"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to get notifications: {}", e),
+            message: msg!("failed_to_load_delegates", "Failed to load delegates: {error}", error = e),
             data: None,
         }),
     }
 }
 
+/// Let `delegate` answer requests for our images (all of them if `image_ids` is empty), or stop them
 #[tauri::command]
-async fn update_permissions(
+async fn set_delegate(
+    delegate: String,
+    image_ids: Vec<String>,
+    enabled: bool,
     state: State<'_, AppState>,
-    target_user: String,
-    image_id: String,
-    new_quota: u32,
 ) -> Result<ApiResponse<()>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Images directory not configured")?;
-    
-    // Find the encrypted image file
-    let encrypted_dir = images_directory.join("encrypted");
-    let image_path = encrypted_dir.join(&image_id);
-    
-    if !image_path.exists() {
-        return Ok(ApiResponse {
+
+    let msg = DirectoryMessage::SetDelegate { owner: username, delegate, image_ids, enabled, key: Some(state.registration_key()?) };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success, message }) => Ok(ApiResponse { success, message: message.into(), data: None }),
+        Ok(_) => Ok(ApiResponse {
             success: false,
-            message: format!("Encrypted image '{}' not found in {}", image_id, encrypted_dir.display()),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
-        });
-    }
-    
-    // Read and update the image permissions locally
-    let img_data = fs::read(&image_path).map_err(|e| format!("Failed to read image: {}", e))?;
-    let carrier_img = image::load_from_memory(&img_data).map_err(|e| format!("Failed to load image: {}", e))?;
-    
-    let payload = lsb::decode(&carrier_img)
-        .map_err(|e| format!("Failed to decode: {}", e))?
-        .ok_or("No hidden metadata found in image")?;
-    
-    let mut combined_data: CombinedPayload = bincode::deserialize(&payload)
-        .map_err(|e| format!("Failed to deserialize: {}", e))?;
-    
-    // Verify ownership
-    if combined_data.permissions.owner != username {
-        return Ok(ApiResponse {
+        }),
+        Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("You are not the owner of this image. Owner is: {}", combined_data.permissions.owner),
+            message: msg!("failed_to_update_delegate", "Failed to update delegate: {error}", error = e),
             data: None,
-        });
-    }
-    
-    // Update the quota for target user
-    combined_data.permissions.quotas.insert(target_user.clone(), new_quota);
-    
-    // Re-encode and save the updated image
-    let updated_payload = bincode::serialize(&combined_data)
-        .map_err(|e| format!("Failed to serialize: {}", e))?;
-    let updated_carrier = lsb::encode(&carrier_img, &updated_payload)
-        .map_err(|e| format!("Failed to encode: {}", e))?;
-    updated_carrier.save(&image_path)
-        .map_err(|e| format!("Failed to save: {}", e))?;
-    
-    eprintln!("✓ Updated local image permissions: {} now has {} views for {}", target_user, new_quota, image_id);
-    
-    // Now create a copy of the image with the target user's quota embedded for delivery
-    // Read the freshly saved image to get the updated version
-    let updated_img_data = fs::read(&image_path).map_err(|e| format!("Failed to read updated image: {}", e))?;
-    
-    // Check if target user is online and deliver/store the update
-    let query_msg = DirectoryMessage::QueryUser {
-        username: target_user.clone(),
-    };
-    
-    match multicast_directory_message(&dir_servers, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(target) }) => {
-            if target.status == UserStatus::Online {
-                eprintln!("📤 Target user {} is online, delivering updated image...", target_user);
-                // Deliver directly via P2P
-                let deliver_msg = P2PMessage::DeliverImage {
-                    from_owner: username.clone(),
-                    image_id: image_id.clone(),
-                    requested_views: new_quota,
-                    encrypted_image: updated_img_data.clone(),
-                };
-                match send_p2p_message(&target.p2p_address, deliver_msg).await {
-                    Ok(P2PMessage::DeliverImageResponse { success: true, message }) => {
-                        eprintln!("✓ Image delivered: {}", message);
-                    }
-                    Ok(P2PMessage::DeliverImageResponse { success: false, message }) => {
-                        eprintln!("⚠ Delivery failed: {}, storing for later", message);
-                        // Fall back to storing
-                        let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
-                            from_owner: username.clone(),
-                            target_user: target_user.clone(),
-                            image_id: image_id.clone(),
-                            new_quota,
-                            embedded_image: Some(updated_img_data.clone()),
-                        };
-                        let _ = multicast_directory_message(&dir_servers, pending_msg).await;
-                    }
-                    Err(e) => {
-                        eprintln!("⚠ Delivery error: {}, storing for later", e);
-                        let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
-                            from_owner: username.clone(),
-                            target_user: target_user.clone(),
-                            image_id: image_id.clone(),
-                            new_quota,
-                            embedded_image: Some(updated_img_data.clone()),
-                        };
-                        let _ = multicast_directory_message(&dir_servers, pending_msg).await;
-                    }
-                    _ => {}
-                }
-            } else {
-                eprintln!("📥 Target user {} is offline, storing update for later delivery...", target_user);
-                let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
-                    from_owner: username.clone(),
-                    target_user: target_user.clone(),
-                    image_id: image_id.clone(),
-                    new_quota,
-                    embedded_image: Some(updated_img_data),
-                };
-                let _ = multicast_directory_message(&dir_servers, pending_msg).await;
-            }
-        }
-        _ => {
-            eprintln!("📥 Target user {} not found, storing update for later delivery...", target_user);
-            let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
-                from_owner: username.clone(),
-                target_user: target_user.clone(),
-                image_id: image_id.clone(),
-                new_quota,
-                embedded_image: Some(updated_img_data),
-            };
-            let _ = multicast_directory_message(&dir_servers, pending_msg).await;
-        }
+        }),
     }
-    
-    let action = if new_quota == 0 { "revoked" } else { "updated" };
-    Ok(ApiResponse {
-        success: true,
-        message: format!("Permissions {} for {}. They now have {} views.", action, target_user, new_quota),
-        data: None,
-    })
 }
 
+/// Our images anonymous users may request
 #[tauri::command]
-async fn get_local_images(
+async fn get_anonymous_access(
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<LocalImage>>, String> {
-    let images = state.local_images.lock().map_err(|e| e.to_string())?.clone();
+) -> Result<ApiResponse<Vec<String>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
 
-    Ok(ApiResponse {
-        success: true,
-        message: format!("Found {} local images", images.len()),
-        data: Some(images),
-    })
+    match multicast_directory_message(&dir_servers, DirectoryMessage::QueryUser { username, requesting_user: None }).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => Ok(ApiResponse {
+            success: true,
+            message: msg!("anonymous_image_count", "{count} image(s) open to anonymous requests", count = user.anonymous_images.len()),
+            data: Some(user.anonymous_images),
+        }),
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("not_registered_with_directory", "Not registered with the directory"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_load_anonymous_access", "Failed to load anonymous access: {error}", error = e),
+            data: None,
+        }),
+    }
 }
 
+/// Let anonymous users request `image_ids`, or stop them
 #[tauri::command]
-async fn get_encrypted_images(
+async fn set_anonymous_access(
+    image_ids: Vec<String>,
+    allowed: bool,
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<LocalImage>>, String> {
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone();
-
-    let mut encrypted_list: Vec<LocalImage> = Vec::new();
-
-    // Get the encrypted directory from the user's images directory
-    let encrypted_dir = match images_directory {
-        Some(images_path) => images_path.join("encrypted"),
-        None => {
-            return Ok(ApiResponse {
-                success: true,
-                message: "Not connected - no images directory configured".to_string(),
-                data: Some(encrypted_list),
-            });
-        }
-    };
+) -> Result<ApiResponse<()>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
 
-    eprintln!("Scanning encrypted directory: {:?}", encrypted_dir);
-
-    if encrypted_dir.exists() && encrypted_dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(&encrypted_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            let file_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-                            let image_id = file_name.clone();
-                            let file_size = fs::metadata(&path)
-                                .map(|m| m.len() / 1024)
-                                .unwrap_or(0);
-
-                            encrypted_list.push(LocalImage {
-                                image_id: image_id.clone(),
-                                file_path: path.to_string_lossy().to_string(),
-                                file_name: file_name.clone(),
-                                file_size_kb: file_size,
-                                is_encrypted: true,
-                            });
-                        }
-                    }
-                }
-            }
-        }
+    let msg = DirectoryMessage::SetAnonymousAccess { owner: username, image_ids, allowed, key: Some(state.registration_key()?) };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success, message }) => Ok(ApiResponse { success, message: message.into(), data: None }),
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_update_anonymous_access", "Failed to update anonymous access: {error}", error = e),
+            data: None,
+        }),
     }
-
-    eprintln!("Total encrypted images found: {}", encrypted_list.len());
-
-    Ok(ApiResponse {
-        success: true,
-        message: format!("Found {} encrypted images", encrypted_list.len()),
-        data: Some(encrypted_list),
-    })
 }
 
+/// Views we already hold on a peer's images, so a repeat request can be flagged before it's sent
 #[tauri::command]
-async fn get_received_images(
+async fn get_existing_grants(
+    peer_username: String,
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<ReceivedImage>>, String> {
-    // Scan the received images directory for ALL images
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone();
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone();
-
-    let mut received_list: Vec<ReceivedImage> = Vec::new();
+) -> Result<ApiResponse<Vec<ExistingGrantInfo>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
 
-    // Get the received directory from the user's images directory (entered in the GUI)
-    let received_dir = match images_directory {
-        Some(images_path) => images_path.join("received"),
-        None => {
-            // Fallback: user not connected yet, return empty list
-            return Ok(ApiResponse {
+    let query = DirectoryMessage::QueryUser { username: peer_username, requesting_user: Some(username) };
+    match multicast_directory_message(&dir_servers, query).await {
+        Ok(DirectoryMessage::QueryUserResponse { grants, .. }) => {
+            let grants: Vec<ExistingGrantInfo> = grants.into_iter().map(ExistingGrantInfo::from).collect();
+            Ok(ApiResponse {
                 success: true,
-                message: "Not connected - no images directory configured".to_string(),
-                data: Some(received_list),
-            });
-        }
-    };
-    
-    eprintln!("Scanning directory: {:?}", received_dir);
-    eprintln!("Directory exists: {}", received_dir.exists());
-    
-    if received_dir.exists() && received_dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(&received_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                eprintln!("Found file: {:?}", path);
-                
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            let file_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-
-                            // Skip the viewable_image.png temp file
-                            if file_name == "viewable_image.png" {
-                                continue;
-                            }
-
-                            // Try to extract owner and views from encrypted data, use defaults if not available
-                            let mut from_owner = "Unknown".to_string();
-                            let mut views_remaining: u32 = 0;
-
-                            // Try to read encrypted metadata if available
-                            if let Ok(data) = fs::read(&path) {
-                                if let Ok(img) = image::load_from_memory(&data) {
-                                    if let Ok(Some(payload_bytes)) = lsb::decode(&img) {
-                                        if let Ok(combined_data) = bincode::deserialize::<CombinedPayload>(&payload_bytes) {
-                                            let permissions = combined_data.permissions;
-                                            from_owner = permissions.owner.clone();
-                                            if let Some(user) = &username {
-                                                views_remaining = permissions.quotas.get(user).copied().unwrap_or(0);
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-
-                            // Get timestamp from file metadata
-                            let received_at = match fs::metadata(&path).and_then(|m| m.modified()) {
-                                Ok(modified_time) => {
-                                    match modified_time.duration_since(SystemTime::UNIX_EPOCH) {
-                                        Ok(d) => {
-                                            let secs = d.as_secs();
-                                            let now_secs = SystemTime::now()
-                                                .duration_since(SystemTime::UNIX_EPOCH)
-                                                .map(|n| n.as_secs())
-                                                .unwrap_or(0);
-                                            let diff = now_secs.saturating_sub(secs);
-                                            let mins = diff / 60;
-                                            let hours = mins / 60;
-                                            let days = hours / 24;
-                                            if days > 0 {
-                                                format!("{} days ago", days)
-                                            } else if hours > 0 {
-                                                format!("{} hours ago", hours)
-                                            } else if mins > 0 {
-                                                format!("{} mins ago", mins)
-                                            } else {
-                                                "Just now".to_string()
-                                            }
-                                        }
-                                        Err(_) => "Unknown".to_string()
-                                    }
-                                }
-                                Err(_) => "Unknown".to_string()
-                            };
-
-                            eprintln!("Adding image: {} from {}", file_name, from_owner);
-                            
-                            received_list.push(ReceivedImage {
-                                image_id: file_name.clone(),
-                                from_owner,
-                                file_path: path.to_string_lossy().to_string(),
-                                file_name,
-                                views_remaining,
-                                received_at,
-                            });
-                        }
-                    }
-                }
-            }
+                message: msg!("existing_grant_count", "{count} existing grant(s)", count = grants.len()),
+                data: Some(grants),
+            })
         }
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_load_existing_grants", "Failed to load existing grants: {error}", error = e),
+            data: None,
+        }),
     }
-
-    eprintln!("Total received images found: {}", received_list.len());
-
-    // Update state
-    *state.received_images.lock().map_err(|e| e.to_string())? = received_list.clone();
-
-    Ok(ApiResponse {
-        success: true,
-        message: format!("Found {} received images", received_list.len()),
-        data: Some(received_list),
-    })
 }
 
+/// How recently a peer was online and when they usually are, from the directory's presence history
 #[tauri::command]
-async fn refresh_images(
+async fn get_presence_history(
+    username: String,
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<LocalImage>>, String> {
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone();
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone();
+) -> Result<ApiResponse<PresenceInfo>, String> {
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    let is_online = *state.is_online.lock().map_err(|e| e.to_string())?;
 
-    let images_path = match images_directory {
-        Some(path) => path,
-        None => {
-            return Ok(ApiResponse {
-                success: false,
-                message: "No images directory configured. Please go online first.".to_string(),
-                data: None,
-            });
+    match multicast_directory_message(&dir_servers, DirectoryMessage::GetPresenceHistory { username }).await {
+        Ok(DirectoryMessage::GetPresenceHistoryResponse { history: Some(history) }) => {
+            let now = SystemTime::now();
+            let summary = history.summary(now);
+            Ok(ApiResponse {
+                success: true,
+                message: UserMessage::untranslated(summary.clone()),
+                data: Some(PresenceInfo {
+                    online: history.online,
+                    summary,
+                    hourly_online: history.hourly_online(now).to_vec(),
+                }),
+            })
         }
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("user_not_found", "User not found"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_load_presence_history", "Failed to load presence history: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// Publish our display name, bio and avatar (`avatar_bytes` is any image; None keeps the current one)
+#[tauri::command]
+async fn update_profile(
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar_bytes: Option<Vec<u8>>,
+    clear_avatar: bool,
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<ProfileJson>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let current = match multicast_directory_message(&dir_servers, DirectoryMessage::QueryUser { username: username.clone(), requesting_user: None }).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => user.profile,
+        _ => UserProfile::default(),
+    };
+    let avatar_thumbnail = match avatar_bytes {
+        Some(bytes) => Some(avatar_thumbnail(&bytes).map_err(|e| format!("Invalid avatar image: {}", e))?),
+        None if clear_avatar => None,
+        None => current.avatar_thumbnail,
     };
+    let profile = UserProfile { display_name, bio, avatar_thumbnail };
 
-    let user = username.clone().unwrap_or_else(|| "unknown".to_string());
-    let image_store = state.image_store.clone();
+    let msg = DirectoryMessage::UpdateProfile { username, profile: profile.clone() };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success, message }) => Ok(ApiResponse {
+            success,
+            message: message.into(),
+            data: success.then(|| ProfileJson::from(&profile)),
+        }),
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_update_profile", "Failed to update profile: {error}", error = e),
+            data: None,
+        }),
+    }
+}
 
-    let encrypted_dir = images_path.join("encrypted");
-    let received_dir = images_path.join("received");
+#[tauri::command]
+async fn request_image(
+    state: State<'_, AppState>,
+    peer_username: String,
+    image_id: String,
+    views: u32,
+    max_dimension: Option<u32>,
+) -> Result<ApiResponse<String>, String> {
+    leave_image_request(&state, peer_username, image_id, views, max_dimension).await
+}
 
-    // Ensure subdirectories exist
-    let _ = fs::create_dir_all(&encrypted_dir);
-    let _ = fs::create_dir_all(&received_dir);
+/// Request an image someone gave us a share link (`owner/image_id#token`) for
+#[tauri::command]
+async fn request_by_share_link(
+    state: State<'_, AppState>,
+    link: String,
+    views: u32,
+) -> Result<ApiResponse<String>, String> {
+    let (owner, _, _) = parse_share_link(link.trim()).map_err(|e| e.to_string())?;
+    leave_image_request(&state, owner, link.trim().to_string(), views, None).await
+}
 
-    let mut local_images_list: Vec<LocalImage> = Vec::new();
-    let mut shared_images: Vec<ImageInfo> = Vec::new();
-
-    // Scan ONLY the encrypted folder for images to share with peers
-    if encrypted_dir.exists() && encrypted_dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(&encrypted_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            let file_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-                            let image_id = file_name.clone();
-                            let file_size = fs::metadata(&path)
-                                .map(|m| m.len() / 1024)
-                                .unwrap_or(0);
-
-                            // Add encrypted image to shared list (NO thumbnail)
-                            shared_images.push(ImageInfo {
-                                image_id: image_id.clone(),
-                                image_name: file_name.clone(),
-                                thumbnail_path: None,
-                            });
-
-                            // Add to image store
-                            let metadata = ImageMetadata {
-                                image_id: image_id.clone(),
-                                image_name: file_name.clone(),
-                                owner: user.clone(),
-                                description: Some(format!("Encrypted image from {}", user)),
-                                file_size_kb: file_size,
-                            };
-
-                            image_store.write().await.add_image(
-                                image_id,
-                                path.clone(),
-                                metadata,
-                            );
-                        }
-                    }
-                }
-            }
-        }
-    }
+/// Leave a request for a peer's image at the directory, answering with its request ID
+///
+/// `image_id` may be a share link, whose token lets the request through for a link-only image.
+async fn leave_image_request(
+    state: &AppState,
+    peer_username: String,
+    image_id: String,
+    views: u32,
+    max_dimension: Option<u32>,
+) -> Result<ApiResponse<String>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
 
-    // Scan main directory for original images (for local display only)
-    if images_path.exists() && images_path.is_dir() {
-        if let Ok(entries) = fs::read_dir(&images_path) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            let file_name = path.file_name()
-                                .and_then(|n| n.to_str())
-                                .unwrap_or("unknown")
-                                .to_string();
-                            let image_id = file_name.clone();
-                            let file_size = fs::metadata(&path)
-                                .map(|m| m.len() / 1024)
-                                .unwrap_or(0);
-
-                            local_images_list.push(LocalImage {
-                                image_id: image_id.clone(),
-                                file_path: path.to_string_lossy().to_string(),
-                                file_name: file_name.clone(),
-                                file_size_kb: file_size,
-                                is_encrypted: false,
-                            });
-                        }
-                    }
-                }
-            }
+    let (image_id, share_token) = if image_id.contains('#') {
+        let (owner, image_id, token) = parse_share_link(&image_id).map_err(|e| e.to_string())?;
+        if owner != peer_username {
+            return Err(format!("That share link is for {}'s image, not {}'s", owner, peer_username));
+        }
+        (image_id, Some(token))
+    } else {
+        (image_id, None)
+    };
+    
+    // Only ask for a permission update if we already hold the same image
+    let delta_only = match resolve_peer(state, &peer_username).await {
+        Ok(Some(owner)) => holds_identical_copy(state, &username, &owner, &image_id).await,
+        _ => false,
+    };
+    
+    let leave_request_msg = DirectoryMessage::LeaveRequest {
+        from_user: username,
+        to_user: peer_username.clone(),
+        image_id: image_id.clone(),
+        requested_views: views,
+        delta_only,
+        requested_max_dimension: max_dimension,
+        share_token,
+    };
+    
+    match multicast_directory_message(&dir_servers, leave_request_msg).await {
+        Ok(DirectoryMessage::LeaveRequestResponse { success, request_id, message }) => {
+            Ok(ApiResponse {
+                success,
+                message: message.into(),
+                data: if success { Some(request_id) } else { None },
+            })
         }
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_request_image", "Failed to request image: {error}", error = e),
+            data: None,
+        }),
     }
+}
 
-    // NOTE: We only show images from the main directory the user entered
-    // Encrypted images (in the /encrypted subfolder) are NOT shown in local images
+/// Whether our received copy of an owner's image embeds the same bytes they share
+async fn holds_identical_copy(state: &AppState, username: &str, owner: &UserEntry, image_id: &str) -> bool {
+    if owner.status != UserStatus::Online || !owner.supports(CAP_IMAGE_PROBE) {
+        return false;
+    }
 
-    // Update the local images in state
-    *state.local_images.lock().map_err(|e| e.to_string())? = local_images_list.clone();
+    let local_copy = match state.image_store.read().await.get_received_images_dir() {
+        Some(dir) => p2p_protocol::find_received_copies(dir, &owner.username, image_id).into_iter().next(),
+        None => return false,
+    };
+    let Some(local_copy) = local_copy else {
+        return false;
+    };
+    let Some(content_hash) = fs::read(&local_copy).ok().and_then(|data| content_sha256(&data)) else {
+        return false;
+    };
 
-    // ALSO refresh received images
-    let mut received_list: Vec<ReceivedImage> = Vec::new();
-    if received_dir.exists() && received_dir.is_dir() {
-        if let Ok(entries) = fs::read_dir(&received_dir) {
-            for entry in entries.flatten() {
-                let path = entry.path();
-                if path.is_file() {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_str().unwrap_or("").to_lowercase();
-                        if ext_str == "png" || ext_str == "jpg" || ext_str == "jpeg" {
-                            // Try to read the image and check if it's encrypted
-                            if let Ok(data) = fs::read(&path) {
-                                if let Ok(img) = image::load_from_memory(&data) {
-                                    if let Ok(Some(payload_bytes)) = lsb::decode(&img) {
-                                        // This is an encrypted image, decode the metadata
-                                        if let Ok(combined_data) = bincode::deserialize::<CombinedPayload>(&payload_bytes) {
-                                            let permissions = combined_data.permissions;
-                                            let file_name = path.file_name()
-                                                .and_then(|n| n.to_str())
-                                                .unwrap_or("unknown")
-                                                .to_string();
-
-                                            // DEBUG: Log the permissions for troubleshooting
-                                            println!("[DEBUG] Received image: {}", file_name);
-                                            println!("[DEBUG] Owner: {}", permissions.owner);
-                                            println!("[DEBUG] Quotas: {:?}", permissions.quotas);
-                                            println!("[DEBUG] Current user: {:?}", username);
-
-                                            // Get views remaining for current user
-                                            let views_remaining = if let Some(current_user) = &username {
-                                                let views = permissions.quotas.get(current_user).copied().unwrap_or(0);
-                                                println!("[DEBUG] Views for '{}': {}", current_user, views);
-                                                views
-                                            } else {
-                                                0
-                                            };
-
-                                            // Try to extract timestamp from file metadata
-                                            let received_at = match fs::metadata(&path)
-                                                .and_then(|m| m.modified())
-                                            {
-                                                Ok(modified_time) => {
-                                                    match modified_time.duration_since(SystemTime::UNIX_EPOCH) {
-                                                        Ok(d) => {
-                                                            let secs = d.as_secs();
-                                                            let now_secs = SystemTime::now()
-                                                                .duration_since(SystemTime::UNIX_EPOCH)
-                                                                .map(|n| n.as_secs())
-                                                                .unwrap_or(0);
-                                                            let diff = now_secs.saturating_sub(secs);
-                                                            let mins = diff / 60;
-                                                            let hours = mins / 60;
-                                                            let days = hours / 24;
-                                                            if days > 0 {
-                                                                format!("{} days ago", days)
-                                                            } else if hours > 0 {
-                                                                format!("{} hours ago", hours)
-                                                            } else if mins > 0 {
-                                                                format!("{} mins ago", mins)
-                                                            } else {
-                                                                "Just now".to_string()
-                                                            }
-                                                        }
-                                                        Err(_) => "Unknown".to_string()
-                                                    }
-                                                }
-                                                Err(_) => "Unknown".to_string()
-                                            };
-
-                                            received_list.push(ReceivedImage {
-                                                image_id: file_name.clone(),
-                                                from_owner: permissions.owner,
-                                                file_path: path.to_string_lossy().to_string(),
-                                                file_name,
-                                                views_remaining,
-                                                received_at,
-                                            });
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    }
-                }
-            }
+    match probe_peer_image(&owner.p2p_address, username, image_id, &content_hash).await {
+        Ok(identical) => identical,
+        Err(e) => {
+            eprintln!("Could not compare images with {}: {}", owner.username, e);
+            false
         }
     }
+}
 
-    // Update received images in state
-    *state.received_images.lock().map_err(|e| e.to_string())? = received_list.clone();
-
-    // IMPORTANT: Update the directory service with the new shared images list
-    // This ensures other peers see the updated list when they query
-    if is_online && username.is_some() {
-        let update_msg = DirectoryMessage::UpdateSharedImages {
-            username: user.clone(),
-            shared_images,
+/// Update the quota on a requester's existing copy, returning false if they still need the full image
+async fn send_permission_delta(state: &AppState, owner: &str, req: &PendingRequest) -> bool {
+    let update = |target: UserEntry| {
+        let update_msg = P2PMessage::RemoteUpdatePermissions {
+            from_owner: owner.to_string(),
+            image_id: req.image_id.clone(),
+            for_user: req.from_user.clone(),
+            new_quota: req.requested_views,
         };
-
-        // Try to update the directory service
-        match multicast_directory_message(&dir_servers, update_msg).await {
-            Ok(DirectoryMessage::UpdateResponse { success, message }) => {
-                eprintln!("Directory service update: {} - {}", success, message);
+        async move {
+            if target.status != UserStatus::Online {
+                bail!("{} is offline", target.username);
             }
-            Ok(_) => {
-                eprintln!("Unexpected response from directory service");
+            let response = send_p2p_message(&target.p2p_address, update_msg).await?;
+            if let P2PMessage::RemoteUpdatePermissionsResponse { success, .. } = &response {
+                reputation::record_delivery(&target.username, *success);
             }
-            Err(e) => {
-                eprintln!("Failed to update directory service: {}", e);
+            match response {
+                P2PMessage::RemoteUpdatePermissionsResponse { success: true, .. } => Ok(()),
+                P2PMessage::RemoteUpdatePermissionsResponse { message, .. } | P2PMessage::StorageFull { message, .. } => bail!(message),
+                _ => bail!("unexpected response"),
             }
         }
+    };
+
+    match resolve_and_send(state, &req.from_user, update).await {
+        Ok(Some(())) => true,
+        Ok(None) => false,
+        Err(e) => {
+            eprintln!("Permission-only update to {} failed ({}), sending the full image", req.from_user, e);
+            false
+        }
     }
+}
 
-    Ok(ApiResponse {
-        success: true,
-        message: format!("Refreshed: Found {} local images and {} received images", local_images_list.len(), received_list.len()),
-        data: Some(local_images_list),
+/// How often and how long `request_image_quick` polls for an answer
+const QUICK_REQUEST_POLL_INTERVAL: Duration = Duration::from_secs(3);
+const QUICK_REQUEST_POLL_ATTEMPTS: u32 = 100;
+
+/// Report a quick request failure on the channel and as the command result
+fn quick_request_failed(on_event: &Channel<QuickRequestEvent>, message: UserMessage) -> ApiResponse<String> {
+    let _ = on_event.send(QuickRequestEvent::Failed { message: message.clone() });
+    ApiResponse {
+        success: false,
+        message,
+        data: None,
+    }
+}
+
+/// Record a `DeliverImage` attempt in the owner's audit log
+async fn audit_delivery(state: &AppState, recipient: &str, image_id: &str, views: u32, success: bool, message: String) {
+    let audit_log = state.image_store.read().await.get_audit_log_path().cloned();
+    audit(audit_log.as_deref(), AuditRecord {
+        action: AuditAction::Delivery,
+        recipient: recipient.to_string(),
+        image_id: image_id.to_string(),
+        views: Some(views),
+        success,
+        message,
+        approved_by: None,
+    });
+}
+
+/// Find a file delivered from `owner` for `image_id` since `since`
+fn find_delivered_image(dir: &std::path::Path, owner: &str, image_id: &str, since: SystemTime) -> Option<PathBuf> {
+    p2p_protocol::find_received_copies(dir, owner, image_id).into_iter().find(|path| {
+        fs::metadata(path).and_then(|metadata| metadata.modified()).is_ok_and(|modified| modified >= since)
     })
 }
 
+/// Look up the peer, send a request, and optionally wait for it to be answered and delivered
 #[tauri::command]
-async fn encrypt_image(
+async fn request_image_quick(
     state: State<'_, AppState>,
-    image_path: String,
+    peer_username: String,
+    image_id: String,
+    views: u32,
+    auto_poll: bool,
+    on_event: Channel<QuickRequestEvent>,
 ) -> Result<ApiResponse<String>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
-    
-    // Read the image file
-    let img_data = fs::read(&image_path).map_err(|e| e.to_string())?;
-    
-    // Create permissions metadata
-    let permissions = ImagePermissions {
-        owner: username.clone(),
-        quotas: HashMap::new(),
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    // Make sure the peer still shares the image, and respect its view cap
+    let peer = match resolve_peer(&state, &peer_username).await {
+        Ok(Some(peer)) => peer,
+        Ok(None) => {
+            return Ok(quick_request_failed(&on_event, msg!("peer_not_found", "Peer {peer_username} not found", peer_username = peer_username)));
+        }
+        Err(e) => return Ok(quick_request_failed(&on_event, msg!("failed_to_look_up_peer", "Failed to look up peer: {error}", error = e))),
     };
-    let meta_bytes = bincode::serialize(&permissions).map_err(|e| e.to_string())?;
-    
-    // Load servers.conf from the main project directory
-    let servers_content = fs::read_to_string("/home/michael12@auc.egy/Documents/Distributed_project/servers.conf")
-        .unwrap_or_else(|_| "10.7.57.239:8080\n10.7.57.240:8081\n10.7.57.99:8082".to_string());
-    
-    let servers: Vec<String> = servers_content
-        .lines()
-        .filter(|s| !s.trim().is_empty() && !s.starts_with('#'))
-        .map(|s| s.trim().to_string())
-        .collect();
-    
-    // Get the images directory and encrypted subfolder
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone();
-    let encrypted_dir = images_directory
-        .ok_or("Not online. Please go online first.")?
-        .join("encrypted");
+    let Some(image) = peer.shared_images.iter().find(|img| img.image_id == image_id) else {
+        return Ok(quick_request_failed(
+            &on_event,
+            msg!("peer_not_sharing_image", "{peer_username} is not sharing '{image_id}'", peer_username = peer_username, image_id = image_id),
+        ));
+    };
+    let views = image.max_grant_views.map_or(views, |cap| views.min(cap));
+    let delta_only = holds_identical_copy(&state, &username, &peer, &image_id).await;
 
-    // Ensure encrypted directory exists
-    fs::create_dir_all(&encrypted_dir).map_err(|e| e.to_string())?;
+    let leave_request_msg = DirectoryMessage::LeaveRequest {
+        from_user: username.clone(),
+        to_user: peer_username.clone(),
+        image_id: image_id.clone(),
+        requested_views: views,
+        delta_only,
+        requested_max_dimension: None,
+        share_token: None,
+    };
+    let request_id = match multicast_directory_message(&dir_servers, leave_request_msg).await {
+        Ok(DirectoryMessage::LeaveRequestResponse { success: true, request_id, .. }) => request_id,
+        Ok(DirectoryMessage::LeaveRequestResponse { message, .. }) => {
+            return Ok(quick_request_failed(&on_event, message.into()));
+        }
+        Ok(_) => return Ok(quick_request_failed(&on_event, msg!("unexpected_response", "Unexpected response"))),
+        Err(e) => return Ok(quick_request_failed(&on_event, msg!("failed_to_request_image", "Failed to request image: {error}", error = e))),
+    };
+    let submitted_at = SystemTime::now();
+    let _ = on_event.send(QuickRequestEvent::Submitted { request_id: request_id.clone(), views });
 
-    // Try each server
-    for server in &servers {
-        match send_encryption_request(server, &meta_bytes, &img_data) {
-            Ok(encrypted_data) => {
-                // Save encrypted image to the encrypted/ folder
-                let original_path = PathBuf::from(&image_path);
-                let file_name = original_path.file_name().unwrap_or_default().to_string_lossy();
-                let output_path = encrypted_dir.join(format!("encrypted_{}", file_name));
+    if !auto_poll {
+        return Ok(ApiResponse {
+            success: true,
+            message: msg!("views_requested", "Requested {views} views from {peer_username}", views = views, peer_username = peer_username),
+            data: Some(request_id),
+        });
+    }
 
-                fs::write(&output_path, &encrypted_data).map_err(|e| e.to_string())?;
-                
-                let file_name = output_path.file_name().unwrap_or_default().to_string_lossy().to_string();
-                let image_id = file_name.clone();
-                let file_size_kb = encrypted_data.len() as u64 / 1024;
-                
-                // Update local images list (scope the lock to drop it before await)
-                {
-                    let mut local_images = state.local_images.lock().map_err(|e| e.to_string())?;
-                    local_images.push(LocalImage {
-                        image_id: image_id.clone(),
-                        file_path: output_path.to_string_lossy().to_string(),
-                        file_name: file_name.clone(),
-                        file_size_kb,
-                        is_encrypted: true,
+    let received_dir = state.image_store.read().await
+        .get_received_images_dir()
+        .cloned()
+        .unwrap_or_else(|| PathBuf::from("."));
+    let mut accepted = false;
+
+    for _ in 0..QUICK_REQUEST_POLL_ATTEMPTS {
+        tokio::time::sleep(QUICK_REQUEST_POLL_INTERVAL).await;
+
+        if !accepted {
+            let msg = DirectoryMessage::GetNotifications {
+                username: username.clone(),
+            };
+            let status = match multicast_directory_message(&dir_servers, msg).await {
+                Ok(DirectoryMessage::GetNotificationsResponse { notifications }) => notifications
+                    .into_iter()
+                    .find(|n| n.request_id == request_id)
+                    .map(|n| n.status),
+                _ => None,
+            };
+
+            match status {
+                Some(RequestStatus::Accepted) => {
+                    accepted = true;
+                    let _ = on_event.send(QuickRequestEvent::Accepted { request_id: request_id.clone() });
+                }
+                Some(RequestStatus::Rejected) => {
+                    let _ = on_event.send(QuickRequestEvent::Rejected { request_id: request_id.clone() });
+                    return Ok(ApiResponse {
+                        success: false,
+                        message: msg!("request_rejected", "{peer_username} rejected the request", peer_username = peer_username),
+                        data: Some(request_id),
                     });
-                } // Lock dropped here
-                
-                // IMPORTANT: Also add to the P2P image store so it's immediately shareable!
-                let metadata = ImageMetadata {
-                    image_id: image_id.clone(),
-                    image_name: file_name.clone(),
-                    owner: username.clone(),
-                    description: Some(format!("Encrypted image from {}", username)),
-                    file_size_kb,
-                };
-                
-                state.image_store.write().await.add_image(
-                    image_id.clone(),
-                    output_path.clone(),
-                    metadata,
-                );
-                
-                eprintln!("✓ Added '{}' to P2P image store - now shareable with peers!", image_id);
-                
-                return Ok(ApiResponse {
-                    success: true,
-                    message: "Image encrypted and added to shareable images".to_string(),
-                    data: Some(output_path.to_string_lossy().to_string()),
-                });
-            }
-            Err(e) => {
-                eprintln!("Server {} failed: {}", server, e);
-                continue;
+                }
+                _ => continue,
             }
         }
+
+        if let Some(path) = find_delivered_image(&received_dir, &peer_username, &image_id, submitted_at) {
+            let file_path = path.to_string_lossy().to_string();
+            let _ = on_event.send(QuickRequestEvent::Delivered {
+                request_id: request_id.clone(),
+                file_path: file_path.clone(),
+            });
+            return Ok(ApiResponse {
+                success: true,
+                message: msg!("image_received", "Received '{image_id}' from {peer_username} at {file_path}", image_id = image_id, peer_username = peer_username, file_path = file_path),
+                data: Some(request_id),
+            });
+        }
     }
-    
+
+    let _ = on_event.send(QuickRequestEvent::TimedOut { request_id: request_id.clone() });
     Ok(ApiResponse {
         success: false,
-        message: "All encryption servers failed".to_string(),
-        data: None,
+        message: msg!("answer_timed_out", "Timed out waiting for {peer_username} to answer", peer_username = peer_username),
+        data: Some(request_id),
     })
 }
 
-fn send_encryption_request(addr: &str, meta_bytes: &[u8], img_buf: &[u8]) -> Result<Vec<u8>> {
-    let mut stream = TcpStream::connect_timeout(
-        &addr.parse()?,
-        Duration::from_secs(10),
-    )?;
-    
-    stream.set_read_timeout(Some(Duration::from_secs(120)))?;
-    stream.set_write_timeout(Some(Duration::from_secs(120)))?;
-    
-    // Send metadata size and data
-    let meta_size = meta_bytes.len() as u64;
-    stream.write_all(&meta_size.to_be_bytes())?;
-    stream.write_all(meta_bytes)?;
-    
-    // Send image size and data
-    let img_size = img_buf.len() as u64;
-    stream.write_all(&img_size.to_be_bytes())?;
-    stream.write_all(img_buf)?;
-    stream.flush()?;
-    
-    // Read response
-    let mut size_bytes = [0u8; 8];
-    stream.read_exact(&mut size_bytes)?;
-    let response_size = u64::from_be_bytes(size_bytes);
-    
-    let mut response_buf = vec![0u8; response_size as usize];
-    stream.read_exact(&mut response_buf)?;
-    
-    // Check for error responses
-    if let Ok(msg) = std::str::from_utf8(&response_buf) {
-        if msg.starts_with("NOT_LEADER") || msg.starts_with("NO_LEADER") {
-            bail!("{}", msg);
-        }
-    }
-    
-    Ok(response_buf)
+/// A requester the directory refused for leaving us too many requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottledSenderInfo {
+    pub sender: String,
+    pub rejected: u32,
+    pub last_rejected: String,
 }
 
+/// Requesters recently throttled for flooding us with requests
 #[tauri::command]
-async fn view_image(
+async fn get_request_throttles(
     state: State<'_, AppState>,
-    image_path: String,
-) -> Result<ApiResponse<String>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
-    
-    // Read and decode the image
-    let img_data = fs::read(&image_path).map_err(|e| e.to_string())?;
-    let carrier_img = image::load_from_memory(&img_data).map_err(|e| e.to_string())?;
-    
-    let payload = lsb::decode(&carrier_img)
-        .map_err(|e| e.to_string())?
-        .ok_or("No hidden metadata found")?;
-    
-    let combined_data: CombinedPayload = bincode::deserialize(&payload)
-        .map_err(|e| e.to_string())?;
-    
-    let mut permissions = combined_data.permissions;
-    let client_image_bytes = combined_data.unified_image;
-    
-    let is_owner = username == permissions.owner;
-    
-    let has_access = if is_owner {
-        true
-    } else {
-        match permissions.quotas.get_mut(&username) {
-            Some(views_left) if *views_left > 0 => {
-                *views_left -= 1;
-                true
-            }
-            _ => false,
-        }
-    };
-    
-    if has_access {
-        // Save viewable image
-        let view_path = PathBuf::from(&image_path)
-            .parent()
-            .map(|p| p.join("viewable_image.png"))
-            .unwrap_or_else(|| PathBuf::from("viewable_image.png"));
-        
-        fs::write(&view_path, &client_image_bytes).map_err(|e| e.to_string())?;
-        
-        // Update metadata if not owner
-        if !is_owner {
-            let updated_combined = CombinedPayload {
-                permissions,
-                unified_image: client_image_bytes,
-            };
-            let updated_payload = bincode::serialize(&updated_combined).map_err(|e| e.to_string())?;
-            let updated_carrier = lsb::encode(&carrier_img, &updated_payload).map_err(|e| e.to_string())?;
-            updated_carrier.save(&image_path).map_err(|e| e.to_string())?;
+) -> Result<ApiResponse<Vec<ThrottledSenderInfo>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::GetRequestThrottles { username }).await {
+        Ok(DirectoryMessage::GetRequestThrottlesResponse { throttled }) => {
+            let infos: Vec<ThrottledSenderInfo> = throttled
+                .into_iter()
+                .map(|note| {
+                    let last_rejected = time_ago(note.last_rejected);
+                    ThrottledSenderInfo { sender: note.sender, rejected: note.rejected, last_rejected }
+                })
+                .collect();
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("throttled_requester_count", "{count} throttled requesters", count = infos.len()),
+                data: Some(infos),
+            })
         }
-        
-        Ok(ApiResponse {
-            success: true,
-            message: "Image decoded successfully".to_string(),
-            data: Some(view_path.to_string_lossy().to_string()),
-        })
-    } else {
-        Ok(ApiResponse {
-            success: false,
-            message: "Access denied - no remaining views or not authorized".to_string(),
-            data: None,
-        })
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response_from_directory_service", "Unexpected response from directory service"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("could_not_check_throttled_requesters", "Could not check throttled requesters: {error}", error = e), data: None }),
     }
 }
 
+/// A pending request that has waited on us past the directory's reminder threshold
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReminderInfo {
+    pub request_id: String,
+    pub from_user: String,
+    pub image_id: String,
+    pub requested_views: u32,
+    pub waiting_since: String,
+}
+
+/// Our overdue requests, oldest first, leaving out snoozed and dismissed ones
 #[tauri::command]
-async fn send_heartbeat(
-    state: State<'_, AppState>,
-) -> Result<ApiResponse<serde_json::Value>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone();
+async fn get_reminders(state: State<'_, AppState>) -> Result<ApiResponse<Vec<ReminderInfo>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::GetReminders { owner: username }).await {
+        Ok(DirectoryMessage::GetRemindersResponse { reminders }) => {
+            state.update_session(|session| session.due_reminders = reminders.len())?;
+            let infos: Vec<ReminderInfo> = reminders
+                .into_iter()
+                .map(|reminder| ReminderInfo {
+                    waiting_since: time_ago(reminder.requested_at),
+                    request_id: reminder.request_id,
+                    from_user: reminder.from_user,
+                    image_id: reminder.image_id,
+                    requested_views: reminder.requested_views,
+                })
+                .collect();
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("overdue_request_count", "{count} overdue requests", count = infos.len()),
+                data: Some(infos),
+            })
+        }
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response_from_directory_service", "Unexpected response from directory service"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("could_not_check_reminders", "Could not check reminders: {error}", error = e), data: None }),
+    }
+}
+
+/// Stop reminding us about a request for `hours`, or for good if unset
+#[tauri::command]
+async fn snooze_reminder(
+    state: State<'_, AppState>,
+    request_id: String,
+    hours: Option<u64>,
+) -> Result<ApiResponse<()>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::SnoozeReminder {
+        owner: username,
+        request_id,
+        snooze_secs: hours.map(|hours| hours * 3600),
+    };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success, message }) => {
+            if success {
+                let _ = state.update_session(|session| session.due_reminders = session.due_reminders.saturating_sub(1));
+            }
+            Ok(ApiResponse { success, message: message.into(), data: None })
+        }
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response_from_directory_service", "Unexpected response from directory service"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("failed_to_snooze_reminder", "Failed to snooze reminder: {error}", error = e), data: None }),
+    }
+}
+
+#[tauri::command]
+async fn get_pending_requests(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<RequestInfo>>, String> {
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    let is_online = *state.is_online.lock().map_err(|e| e.to_string())?;
     
-    if !is_online || username.is_none() {
-        return Ok(ApiResponse {
+    let msg = DirectoryMessage::GetPendingRequests {
+        username: username.clone(),
+    };
+    
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::GetPendingRequestsResponse { mut requests }) => {
+            // Requests we can answer for owners who made us their delegate
+            let delegated = DirectoryMessage::GetDelegatedRequests { delegate: username.clone() };
+            if let Ok(DirectoryMessage::GetPendingRequestsResponse { requests: theirs }) =
+                multicast_directory_message(&dir_servers, delegated).await
+            {
+                requests.extend(theirs);
+            }
+            let request_infos: Vec<RequestInfo> = requests.iter().map(|r| {
+                let timestamp_str = time_ago(r.timestamp);
+                
+                note_request_status(&state, &r.request_id, format!("{:?}", r.status));
+                RequestInfo {
+                    request_id: r.request_id.clone(),
+                    from_user: r.from_user.clone(),
+                    to_user: r.to_user.clone(),
+                    image_id: r.image_id.clone(),
+                    requested_views: r.requested_views,
+                    requested_max_dimension: r.requested_max_dimension,
+                    timestamp: timestamp_str,
+                    status: format!("{:?}", r.status),
+                    delegated: r.to_user != username,
+                }
+            }).collect();
+            
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("found_pending_requests", "Found {count} pending requests", count = request_infos.len()),
+                data: Some(request_infos),
+            })
+        }
+        Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Not online".to_string(),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
-        });
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_get_requests", "Failed to get requests: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+#[tauri::command]
+async fn respond_to_request(
+    state: State<'_, AppState>,
+    request_id: String,
+    accept: bool,
+) -> Result<ApiResponse<RespondOutcome>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "respond to requests")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    if accept {
+        if let Some(stale) = unshared_request_image(&state, &dir_servers, &username, &request_id).await? {
+            return reject_unshared_request(&state, &dir_servers, &username, stale).await;
+        }
     }
     
-    let heartbeat_msg = DirectoryMessage::Heartbeat {
-        username: username.unwrap(),
+    let msg = DirectoryMessage::RespondToRequest {
+        request_id: request_id.clone(),
+        owner: username.clone(),
+        accept,
+        granted_views: None,
+        note: None,
+        key: Some(state.registration_key()?),
     };
     
-    const MAX_FAILURES: u32 = 3; // Disconnect after 3 consecutive failures
-    
-    match multicast_directory_message(&dir_servers, heartbeat_msg).await {
-        Ok(DirectoryMessage::HeartbeatResponse { success }) => {
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::RespondToRequestResponse { success, message, request }) => {
             if success {
-                // Reset failure counter on success
-                *state.heartbeat_failures.lock().map_err(|e| e.to_string())? = 0;
+                let status = if accept { RequestStatus::Accepted } else { RequestStatus::Rejected };
+                note_request_status(&state, &request_id, format!("{:?}", status));
+            }
+            if success && accept {
+                // If accepted, grant permissions and deliver image
+                if let Some(req) = request {
+                    // As a delegate the owner's peer grants it, so send the full image from there
+                    let owner = (req.to_user != username).then(|| req.to_user.clone());
+                    if owner.is_none() && req.delta_only && send_permission_delta(&state, &username, &req).await {
+                        eprintln!("♻ {} already had '{}', sent a permission update only", req.from_user, req.image_id);
+                        if let Err(e) = mark_request_delivered(&state, &dir_servers, &username, &req.request_id).await {
+                            eprintln!("Failed to mark request {} delivered: {}", req.request_id, e);
+                        }
+                    } else {
+                        enqueue_job(&state, JobKind::DeliverGrant {
+                            target_user: req.from_user.clone(),
+                            image_id: req.image_id.clone(),
+                            views: req.views_to_grant(),
+                            max_dimension: req.requested_max_dimension,
+                            owner,
+                            request_id: Some(req.request_id),
+                            grant_token: req.grant_token,
+                        })?;
+                    }
+                }
             }
+            
             Ok(ApiResponse {
                 success,
-                message: if success { "Heartbeat sent" } else { "Heartbeat failed" }.to_string(),
-                data: Some(serde_json::json!({
-                    "connected": true,
-                    "failures": 0
-                })),
+                message: message.into(),
+                data: success.then_some(RespondOutcome::Responded),
             })
         }
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_respond", "Failed to respond: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// A pending request to us for an image we no longer have, found before accepting it
+///
+/// Delegated requests and requests the directory no longer lists are left for the
+/// normal response path to deal with.
+async fn unshared_request_image(
+    state: &AppState,
+    dir_servers: &[String],
+    username: &str,
+    request_id: &str,
+) -> Result<Option<PendingRequest>, String> {
+    let msg = DirectoryMessage::GetPendingRequests { username: username.to_string() };
+    let Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) = multicast_directory_message(dir_servers, msg).await else {
+        return Ok(None);
+    };
+    let Some(request) = requests.into_iter().find(|r| r.request_id == request_id && r.to_user == username) else {
+        return Ok(None);
+    };
+    let shared = find_owned_image(state, &request.image_id).await?.is_some_and(|path| path.exists());
+    Ok((!shared).then_some(request))
+}
+
+/// Reject a request for an image we no longer share, telling the requester why, and
+/// stop advertising the image
+async fn reject_unshared_request(
+    state: &AppState,
+    dir_servers: &[String],
+    username: &str,
+    request: PendingRequest,
+) -> Result<ApiResponse<RespondOutcome>, String> {
+    eprintln!("'{}' requested by {} is no longer shared - rejecting", request.image_id, request.from_user);
+    let msg = DirectoryMessage::RespondToRequest {
+        request_id: request.request_id.clone(),
+        owner: username.to_string(),
+        accept: false,
+        granted_views: None,
+        note: Some(format!("{} no longer shares '{}'", username, request.image_id)),
+        key: Some(state.registration_key()?),
+    };
+    let rejected = matches!(
+        multicast_directory_message(dir_servers, msg).await,
+        Ok(DirectoryMessage::RespondToRequestResponse { success: true, .. })
+    );
+    if rejected {
+        note_request_status(state, &request.request_id, format!("{:?}", RequestStatus::Rejected));
+    }
+
+    // A stale store entry would keep the image in every catalog we publish
+    state.image_store.write().await.remove_image(&request.image_id);
+    if state.session()?.is_online() {
+        enqueue_job(state, JobKind::UpdateSharedImages { shared_images: shared_catalog(state).await })?;
+    }
+
+    let message = if rejected {
+        msg!(
+            "image_unshared_request_rejected",
+            "'{image_id}' is no longer shared - rejected {from_user}'s request and told them why",
+            image_id = request.image_id,
+            from_user = request.from_user,
+        )
+    } else {
+        msg!(
+            "image_unshared_reject_failed",
+            "'{image_id}' is no longer shared, and rejecting {from_user}'s request failed",
+            image_id = request.image_id,
+            from_user = request.from_user,
+        )
+    };
+    Ok(ApiResponse {
+        success: false,
+        message,
+        data: Some(RespondOutcome::ImageNoLongerShared {
+            image_id: request.image_id,
+            from_user: request.from_user,
+        }),
+    })
+}
+
+/// Requests whose grants are sent at once when responding in bulk
+const BULK_RESPOND_CONCURRENCY: usize = 4;
+
+/// Accept or reject every pending request matching `filter`, then send the grants
+#[tauri::command]
+async fn respond_to_requests_bulk(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    filter: RequestFilter,
+    accept: bool,
+) -> Result<ApiResponse<Vec<BulkResponseResult>>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "respond to requests")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::RespondToRequestsBulk {
+        owner: username.clone(),
+        filter,
+        accept,
+        key: Some(state.registration_key()?),
+    };
+    let requests = match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::RespondToRequestsBulkResponse { requests }) => requests,
         Ok(_) => {
-            let mut failures = state.heartbeat_failures.lock().map_err(|e| e.to_string())?;
-            *failures += 1;
-            let should_disconnect = *failures >= MAX_FAILURES;
-            
-            if should_disconnect {
-                // Auto-disconnect
-                *state.is_online.lock().map_err(|e| e.to_string())? = false;
-                *state.heartbeat_failures.lock().map_err(|e| e.to_string())? = 0;
-            }
-            
-            Ok(ApiResponse {
+            return Ok(ApiResponse {
                 success: false,
-                message: format!("Unexpected response (failures: {})", *failures),
-                data: Some(serde_json::json!({
-                    "connected": !should_disconnect,
-                    "failures": *failures,
-                    "disconnected": should_disconnect
-                })),
-            })
+                message: msg!("unexpected_response", "Unexpected response"),
+                data: None,
+            });
         }
         Err(e) => {
-            let mut failures = state.heartbeat_failures.lock().map_err(|e| e.to_string())?;
-            *failures += 1;
-            let current_failures = *failures;
-            let should_disconnect = current_failures >= MAX_FAILURES;
-            drop(failures); // Release lock before acquiring another
-            
-            if should_disconnect {
-                // Auto-disconnect - all servers are down
-                *state.is_online.lock().map_err(|e| e.to_string())? = false;
-                *state.heartbeat_failures.lock().map_err(|e| e.to_string())? = 0;
-                eprintln!("All directory servers unreachable. Auto-disconnecting.");
-            }
-            
-            Ok(ApiResponse {
+            return Ok(ApiResponse {
                 success: false,
-                message: format!("Heartbeat failed: {} (failures: {}/{})", e, current_failures, MAX_FAILURES),
-                data: Some(serde_json::json!({
-                    "connected": !should_disconnect,
-                    "failures": current_failures,
-                    "disconnected": should_disconnect,
-                    "reason": "All directory servers unreachable"
-                })),
-            })
+                message: msg!("failed_to_respond", "Failed to respond: {error}", error = e),
+                data: None,
+            });
         }
+    };
+
+    // Permission-only updates talk to the requester directly, so cap how many run at once
+    let limit = Arc::new(Semaphore::new(BULK_RESPOND_CONCURRENCY));
+    let mut grants = tokio::task::JoinSet::new();
+    for req in requests {
+        let (app, limit, owner, dir_servers) = (app.clone(), limit.clone(), username.clone(), dir_servers.clone());
+        grants.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            let state = app.state::<AppState>();
+            note_request_status(&state, &req.request_id, format!("{:?}", req.status));
+
+            let outcome = if !accept {
+                Ok("Rejected".to_string())
+            } else if req.delta_only && send_permission_delta(&state, &owner, &req).await {
+                if let Err(e) = mark_request_delivered(&state, &dir_servers, &owner, &req.request_id).await {
+                    eprintln!("Failed to mark request {} delivered: {}", req.request_id, e);
+                }
+                Ok("Updated the quota on their existing copy".to_string())
+            } else {
+                enqueue_job(&state, JobKind::DeliverGrant {
+                    target_user: req.from_user.clone(),
+                    image_id: req.image_id.clone(),
+                    views: req.views_to_grant(),
+                    max_dimension: req.requested_max_dimension,
+                    owner: None,
+                    request_id: Some(req.request_id.clone()),
+                    grant_token: req.grant_token.clone(),
+                })
+                .map(|()| "Accepted, delivery queued".to_string())
+            };
+            BulkResponseResult {
+                request_id: req.request_id,
+                from_user: req.from_user,
+                image_id: req.image_id,
+                success: outcome.is_ok(),
+                message: outcome.unwrap_or_else(|e| e),
+            }
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(joined) = grants.join_next().await {
+        results.push(joined.map_err(|e| e.to_string())?);
     }
+    results.sort_by(|a, b| (&a.from_user, &a.image_id).cmp(&(&b.from_user, &b.image_id)));
+
+    let failed = results.iter().filter(|r| !r.success).count();
+    let message = match (accept, failed) {
+        (true, 0) => msg!("requests_accepted", "Accepted {count} requests", count = results.len()),
+        (false, 0) => msg!("requests_rejected", "Rejected {count} requests", count = results.len()),
+        (true, _) => msg!(
+            "requests_accepted_some_failed",
+            "Accepted {count} requests ({failed} could not be granted)",
+            count = results.len(),
+            failed = failed,
+        ),
+        (false, _) => msg!(
+            "requests_rejected_some_failed",
+            "Rejected {count} requests ({failed} could not be granted)",
+            count = results.len(),
+            failed = failed,
+        ),
+    };
+    Ok(ApiResponse {
+        success: failed == 0,
+        message,
+        data: Some(results),
+    })
 }
 
 #[tauri::command]
-async fn list_peer_images_cmd(
+async fn get_notifications(
     state: State<'_, AppState>,
-    peer_username: String,
-) -> Result<ApiResponse<Vec<ImageMetadata>>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+) -> Result<ApiResponse<Vec<NotificationInfo>>, String> {
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
     
-    // Query directory to get peer's P2P address
-    let query_msg = DirectoryMessage::QueryUser {
-        username: peer_username.clone(),
+    let msg = DirectoryMessage::GetNotifications {
+        username,
     };
     
-    match multicast_directory_message(&dir_servers, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(peer) }) => {
-            match list_peer_images(&peer.p2p_address, &username).await {
-                Ok(images) => {
-                    Ok(ApiResponse {
-                        success: true,
-                        message: format!("Found {} images", images.len()),
-                        data: Some(images),
-                    })
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::GetNotificationsResponse { notifications }) => {
+            let notif_infos: Vec<NotificationInfo> = notifications.iter().map(|n| {
+                let timestamp_str = time_ago(n.timestamp);
+                
+                note_request_status(&state, &n.request_id, format!("{:?}", n.status));
+                NotificationInfo {
+                    request_id: n.request_id.clone(),
+                    to_user: n.to_user.clone(),
+                    image_id: n.image_id.clone(),
+                    requested_views: n.requested_views,
+                    status: format!("{:?}", n.status),
+                    timestamp: timestamp_str,
+                    approved_by: n.approved_by.clone(),
+                    note: n.response_note.clone(),
                 }
-                Err(e) => Ok(ApiResponse {
-                    success: false,
-                    message: format!("Failed to list images: {}", e),
-                    data: None,
-                }),
-            }
-        }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
+            }).collect();
+            
             Ok(ApiResponse {
-                success: false,
-                message: format!("Peer {} not found or offline", peer_username),
-                data: None,
+                success: true,
+                message: msg!("found_notifications", "Found {count} notifications", count = notif_infos.len()),
+                data: Some(notif_infos),
             })
         }
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to query peer: {}", e),
+            message: msg!("failed_to_get_notifications", "Failed to get notifications: {error}", error = e),
             data: None,
         }),
     }
 }
 
 #[tauri::command]
-async fn get_image_thumbnail(
+async fn cancel_request(
     state: State<'_, AppState>,
-    peer_username: String,
-    image_id: String,
-) -> Result<ApiResponse<String>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
+    request_id: String,
+) -> Result<ApiResponse<()>, String> {
+    let username = state.current_user()?;
     let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
     
-    // Query directory to get peer's P2P address
-    let query_msg = DirectoryMessage::QueryUser {
-        username: peer_username.clone(),
+    let msg = DirectoryMessage::CancelRequest {
+        request_id: request_id.clone(),
+        from_user: username,
+        key: Some(state.registration_key()?),
     };
     
-    match multicast_directory_message(&dir_servers, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(peer) }) => {
-            if peer.status != UserStatus::Online {
-                return Ok(ApiResponse {
-                    success: false,
-                    message: format!("Peer {} is not online", peer_username),
-                    data: None,
-                });
-            }
-            
-            // Request thumbnail from peer
-            match request_thumbnail_from_peer(&peer.p2p_address, &username, &image_id).await {
-                Ok(thumbnail_bytes) => {
-                    // Convert to base64 for easy transfer to frontend
-                    use base64::{Engine as _, engine::general_purpose::STANDARD};
-                    let base64_thumbnail = STANDARD.encode(&thumbnail_bytes);
-                    let data_url = format!("data:image/png;base64,{}", base64_thumbnail);
-                    
-                    Ok(ApiResponse {
-                        success: true,
-                        message: "Thumbnail retrieved".to_string(),
-                        data: Some(data_url),
-                    })
-                }
-                Err(e) => Ok(ApiResponse {
-                    success: false,
-                    message: format!("Failed to get thumbnail: {}", e),
-                    data: None,
-                }),
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::CancelRequestResponse { success, message }) => {
+            if success {
+                note_request_status(&state, &request_id, "Cancelled".to_string());
             }
-        }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
             Ok(ApiResponse {
-                success: false,
-                message: format!("Peer {} not found", peer_username),
+                success,
+                message: message.into(),
                 data: None,
             })
         }
         Ok(_) => Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("unexpected_response", "Unexpected response"),
             data: None,
         }),
         Err(e) => Ok(ApiResponse {
             success: false,
-            message: format!("Failed to query peer: {}", e),
+            message: msg!("failed_to_cancel_request", "Failed to cancel request: {error}", error = e),
             data: None,
         }),
     }
 }
 
-// ============================================================================
-// PENDING PERMISSION UPDATES
-// ============================================================================
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct PermissionUpdateInfo {
-    pub from_owner: String,
-    pub image_id: String,
-    pub new_quota: u32,
-    pub message: String,
-}
-
+/// Set a user's quota on one of our images and deliver the change
+///
+/// With `preview` set, only returns the quotas the change would leave; nothing is written or sent.
 #[tauri::command]
-async fn check_pending_permission_updates(
+async fn update_permissions(
     state: State<'_, AppState>,
-) -> Result<ApiResponse<Vec<PermissionUpdateInfo>>, String> {
-    let username = state.username.lock().map_err(|e| e.to_string())?.clone()
-        .ok_or("Not logged in")?;
-    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone();
+    target_user: String,
+    image_id: String,
+    new_quota: u32,
+    preview: bool,
+) -> Result<ApiResponse<QuotaPreview>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change permissions")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
     
-    let received_dir = match images_directory {
-        Some(path) => path.join("received"),
-        None => return Ok(ApiResponse {
-            success: false,
-            message: "Images directory not configured".to_string(),
-            data: None,
-        }),
+    // Find the encrypted image file: shared images by ID, else by file name in whichever share root holds it
+    let image_path = match find_owned_image(&state, &image_id).await? {
+        Some(path) => path,
+        None => {
+            return Ok(ApiResponse {
+                success: false,
+                message: msg!("encrypted_image_not_found", "Encrypted image '{image_id}' not found in any share root", image_id = image_id),
+                data: None,
+            });
+        }
     };
     
-    // Ensure received directory exists
-    let _ = fs::create_dir_all(&received_dir);
+    // Read and update the image permissions locally, holding the carrier until the change is written
+    let carrier_lock = viewing::lock_carrier(&image_path).await;
+    let img_data = fs::read(&image_path).map_err(|e| format!("Failed to read image: {}", e))?;
+    let carrier_img = image::load_from_memory(&img_data).map_err(|e| format!("Failed to load image: {}", e))?;
     
-    let pending_msg = DirectoryMessage::GetPendingPermissionUpdates {
-        username: username.clone(),
-    };
+    let payload = lsb::decode(&carrier_img)
+        .map_err(|e| format!("Failed to decode: {}", e))?
+        .ok_or("No hidden metadata found in image")?;
     
-    match multicast_directory_message(&dir_servers, pending_msg).await {
-        Ok(DirectoryMessage::GetPendingPermissionUpdatesResponse { updates }) => {
-            let mut processed_updates: Vec<PermissionUpdateInfo> = Vec::new();
-            
-            for update in updates {
-                let mut info = PermissionUpdateInfo {
-                    from_owner: update.from_owner.clone(),
-                    image_id: update.image_id.clone(),
-                    new_quota: update.new_quota,
-                    message: String::new(),
-                };
-                
-                // If there's an embedded image, save it
-                if let Some(embedded_image) = update.embedded_image {
-                    let save_name = format!("from_{}_{}", update.from_owner, update.image_id);
-                    let save_path = received_dir.join(&save_name);
-                    
-                    match fs::write(&save_path, &embedded_image) {
-                        Ok(_) => {
-                            if update.new_quota == 0 {
-                                info.message = format!(
-                                    "{} has REVOKED your access to image '{}'",
-                                    update.from_owner, update.image_id
-                                );
-                            } else {
-                                info.message = format!(
-                                    "{} has updated your permissions for image '{}' to {} views",
-                                    update.from_owner, update.image_id, update.new_quota
-                                );
-                            }
-                        }
-                        Err(e) => {
-                            info.message = format!("Failed to save image: {}", e);
-                        }
-                    }
-                } else {
-                    info.message = format!(
-                        "{} updated permissions for image '{}' to {} views (no image delivered)",
-                        update.from_owner, update.image_id, update.new_quota
-                    );
-                }
-                
-                processed_updates.push(info);
-            }
-            
-            Ok(ApiResponse {
-                success: true,
-                message: format!("Processed {} pending updates", processed_updates.len()),
-                data: Some(processed_updates),
-            })
-        }
-        Err(e) => Ok(ApiResponse {
+    let mut combined_data = CombinedPayload::from_bytes(&payload)
+        .map_err(|e| format!("Failed to deserialize: {}", e))?;
+    
+    // Verify ownership
+    if combined_data.permissions.owner != username {
+        return Ok(ApiResponse {
             success: false,
-            message: format!("Failed to check updates: {}", e),
+            message: msg!("not_image_owner", "You are not the owner of this image. Owner is: {owner}", owner = combined_data.permissions.owner),
             data: None,
-        }),
-        _ => Ok(ApiResponse {
+        });
+    }
+    
+    let quota_preview = preview_quota_change(&img_data, &target_user, new_quota)
+        .map_err(|e| format!("Failed to preview: {}", e))?;
+    if preview {
+        let message = if quota_preview.changes.is_empty() {
+            msg!("quota_unchanged", "No change: {target_user} already has {views} views", target_user = target_user, views = new_quota)
+        } else {
+            msg!("quota_preview", "{target_user} would have {views} views", target_user = target_user, views = new_quota)
+        };
+        return Ok(ApiResponse {
+            success: true,
+            message,
+            data: Some(quota_preview),
+        });
+    }
+    
+    // Update the quota for target user
+    combined_data.permissions.set_quota(&target_user, new_quota);
+    
+    // Re-encode and save the updated image
+    let updated_payload = combined_data.to_bytes()
+        .map_err(|e| format!("Failed to serialize: {}", e))?;
+    let updated_carrier = lsb::encode(&carrier_img, &updated_payload)
+        .map_err(|e| format!("Failed to encode: {}", e))?;
+    let mut png = Vec::new();
+    updated_carrier.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .map_err(|e| format!("Failed to encode: {}", e))?;
+    write_atomic(&image_path, &png)
+        .map_err(|e| format!("Failed to save: {:#}", e))?;
+    drop(carrier_lock);
+    
+    eprintln!("✓ Updated local image permissions: {} now has {} views for {}", target_user, new_quota, image_id);
+    
+    // Deliver the updated copy in the background (stored at the directory if they're offline)
+    enqueue_job(&state, JobKind::DeliverPermissionUpdate {
+        target_user: target_user.clone(),
+        image_id,
+        new_quota,
+    })?;
+    
+    Ok(ApiResponse {
+        success: true,
+        message: if new_quota == 0 {
+            msg!("permissions_revoked", "Permissions revoked for {target_user}. They now have 0 views.", target_user = target_user)
+        } else {
+            msg!(
+                "permissions_updated",
+                "Permissions updated for {target_user}. They now have {views} views.",
+                target_user = target_user,
+                views = new_quota,
+            )
+        },
+        data: Some(quota_preview),
+    })
+}
+
+/// Let a user keep a copy of one of our images without viewing it, or stop them
+///
+/// Adding a holder delivers them the carrier; removing one delivers the updated copy.
+#[tauri::command]
+async fn set_image_holder(
+    state: State<'_, AppState>,
+    image_id: String,
+    holder: String,
+    enabled: bool,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change holders")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let Some(image_path) = find_owned_image(&state, &image_id).await? else {
+        return Ok(ApiResponse {
             success: false,
-            message: "Unexpected response".to_string(),
+            message: msg!("encrypted_image_not_found", "Encrypted image '{image_id}' not found in any share root", image_id = image_id),
             data: None,
-        }),
+        });
+    };
+
+    let changed = match p2p_protocol::set_image_holder(&image_path, &username, &holder, enabled) {
+        Ok(changed) => changed,
+        Err(e) => {
+            return Ok(ApiResponse { success: false, message: e.to_string().into(), data: None });
+        }
+    };
+    if !changed {
+        let message = if enabled {
+            msg!("already_holder", "{holder} is already a holder of {image_id}", holder = holder, image_id = image_id)
+        } else {
+            msg!("not_holder", "{holder} was not a holder of {image_id}", holder = holder, image_id = image_id)
+        };
+        return Ok(ApiResponse { success: true, message, data: None });
     }
+
+    let job = if enabled {
+        JobKind::DeliverGrant { target_user: holder.clone(), image_id: image_id.clone(), views: 0, max_dimension: None, owner: None, request_id: None, grant_token: None }
+    } else {
+        JobKind::DeliverPermissionUpdate { target_user: holder.clone(), image_id: image_id.clone(), new_quota: 0 }
+    };
+    enqueue_job(&state, job)?;
+
+    let message = if enabled {
+        msg!("holder_added", "{holder} will hold {image_id} without being able to view it", holder = holder, image_id = image_id)
+    } else {
+        msg!("holder_removed", "{holder} is no longer a holder of {image_id}", holder = holder, image_id = image_id)
+    };
+    Ok(ApiResponse { success: true, message, data: None })
 }
 
+/// What viewers may currently do with one of our images
 #[tauri::command]
-async fn delete_image(
+async fn get_view_policy(
     state: State<'_, AppState>,
-    file_path: String,
-) -> Result<ApiResponse<()>, String> {
-    let path = PathBuf::from(&file_path);
-    
-    // Verify the file exists
-    if !path.exists() {
+    image_id: String,
+) -> Result<ApiResponse<ViewPolicy>, String> {
+    let Some(image_path) = find_owned_image(&state, &image_id).await? else {
         return Ok(ApiResponse {
             success: false,
-            message: format!("File not found: {}", file_path),
+            message: msg!("encrypted_image_not_found", "Encrypted image '{image_id}' not found in any share root", image_id = image_id),
             data: None,
         });
+    };
+    let carrier = fs::read(&image_path).map_err(|e| e.to_string())?;
+    match p2p_protocol::embedded_view_policy(&carrier) {
+        Some(policy) => Ok(ApiResponse { success: true, message: UserMessage::default(), data: Some(policy) }),
+        None => Ok(ApiResponse { success: false, message: msg!("no_hidden_metadata_found", "No hidden metadata found"), data: None }),
     }
-    
-    // Get images directory to make sure we're only deleting files within allowed directories
-    let images_directory = state.images_directory.lock().map_err(|e| e.to_string())?.clone();
-    
-    let allowed = match &images_directory {
-        Some(base_dir) => {
-            // Allow deletion from: main dir, encrypted/, or received/
-            let encrypted_dir = base_dir.join("encrypted");
-            let received_dir = base_dir.join("received");
-            
-            path.starts_with(base_dir) || 
-            path.starts_with(&encrypted_dir) || 
-            path.starts_with(&received_dir)
-        }
-        None => false,
+}
+
+/// One quota change from an image's embedded history
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionHistoryEntry {
+    pub at: Option<u64>,  // Unix seconds; the UI shows it in local time
+    pub ago: String,
+    pub changed_by: String,
+    pub user: String,
+    pub before: Option<u32>,
+    pub after: u32,
+    pub revocation: bool,
+}
+
+/// The quota changes recorded inside one of our images or a received one, newest first
+#[tauri::command]
+async fn get_permission_history(
+    state: State<'_, AppState>,
+    image_id: String,
+) -> Result<ApiResponse<Vec<PermissionHistoryEntry>>, String> {
+    let received_path = state.received_images.lock().map_err(|e| e.to_string())?
+        .iter()
+        .find(|img| img.image_id == image_id || img.file_path == image_id)
+        .map(|img| PathBuf::from(&img.file_path));
+    let image_path = match received_path {
+        Some(path) => Some(path),
+        None => find_owned_image(&state, &image_id).await?,
     };
-    
-    if !allowed {
+    let Some(image_path) = image_path else {
         return Ok(ApiResponse {
             success: false,
-            message: "Cannot delete files outside of your images directory".to_string(),
+            message: msg!("image_not_found", "Image '{image_id}' not found", image_id = image_id),
             data: None,
         });
+    };
+
+    let carrier = fs::read(&image_path).map_err(|e| e.to_string())?;
+    let Some(permissions) = p2p_protocol::embedded_permissions(&carrier) else {
+        return Ok(ApiResponse { success: false, message: msg!("no_hidden_metadata_found", "No hidden metadata found"), data: None });
+    };
+    let history: Vec<PermissionHistoryEntry> = permissions.history.into_iter()
+        .rev()
+        .map(|change| PermissionHistoryEntry {
+            at: change.at.duration_since(SystemTime::UNIX_EPOCH).ok().map(|since| since.as_secs()),
+            ago: time_ago(change.at),
+            revocation: change.is_revocation(),
+            changed_by: change.changed_by,
+            user: change.user,
+            before: change.before,
+            after: change.after,
+        })
+        .collect();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("history_change_count", "{count} changes recorded", count = history.len()),
+        data: Some(history),
+    })
+}
+
+/// Change whether viewers may save or print one of our images, and whether it's watermarked
+///
+/// Copies already delivered keep their old policy until the image is delivered to them again.
+#[tauri::command]
+async fn set_view_policy(
+    state: State<'_, AppState>,
+    image_id: String,
+    policy: ViewPolicy,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change viewer rights")? {
+        return Ok(refusal);
     }
-    
-    // Delete the file
-    match fs::remove_file(&path) {
-        Ok(_) => {
-            let file_name = path.file_name()
-                .and_then(|n| n.to_str())
-                .unwrap_or("unknown");
-            
-            eprintln!("✓ Deleted image: {}", file_path);
-            
-            // Also remove from local_images state if it exists there
-            if let Ok(mut local_images) = state.local_images.lock() {
-                local_images.retain(|img| img.file_path != file_path);
+    let username = state.current_user()?;
+    let Some(image_path) = find_owned_image(&state, &image_id).await? else {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("encrypted_image_not_found", "Encrypted image '{image_id}' not found in any share root", image_id = image_id),
+            data: None,
+        });
+    };
+
+    match p2p_protocol::set_view_policy(&image_path, &username, policy) {
+        Ok(false) => Ok(ApiResponse {
+            success: true,
+            message: msg!("viewer_rights_unchanged", "{image_id} already has these viewer rights", image_id = image_id),
+            data: None,
+        }),
+        Ok(true) => Ok(ApiResponse {
+            success: true,
+            message: msg!("viewer_rights_updated", "Viewer rights for {image_id} apply to copies you grant from now on", image_id = image_id),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse { success: false, message: e.to_string().into(), data: None }),
+    }
+}
+
+/// Ask a recipient's client to securely delete its copy, queueing the wipe if they can't be reached
+#[tauri::command]
+async fn remote_wipe(
+    state: State<'_, AppState>,
+    target_user: String,
+    image_id: String,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "wipe recipients' copies")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    let image_id = unqualify_image_id(&username, &image_id).map_err(|e| e.to_string())?.to_string();
+
+    let wipe = |target: UserEntry| {
+        let (owner, image_id) = (username.clone(), image_id.clone());
+        async move {
+            if target.status != UserStatus::Online {
+                bail!("{} is offline", target.username);
             }
-            
-            // Remove from image_store if it's an encrypted image
-            let image_store = state.image_store.clone();
-            let image_id = file_name.to_string();
-            {
-                let mut store = image_store.write().await;
-                store.remove_image(&image_id);
+            if !target.supports(CAP_REMOTE_WIPE) {
+                bail!("{}'s client doesn't support remote wipe yet", target.username);
             }
-            
-            Ok(ApiResponse {
+            let result = send_remote_wipe(&target.p2p_address, &owner, &image_id).await;
+            reputation::record_revocation(&target.username, result.is_ok());
+            result
+        }
+    };
+
+    let reason = match resolve_and_send(&state, &target_user, wipe).await {
+        Ok(Some(message)) => {
+            let audit_log = state.image_store.read().await.get_audit_log_path().cloned();
+            audit(audit_log.as_deref(), AuditRecord {
+                action: AuditAction::Wipe,
+                recipient: target_user.clone(),
+                image_id: image_id.clone(),
+                views: None,
+                success: true,
+                message: message.clone(),
+                approved_by: None,
+            });
+            return Ok(ApiResponse {
                 success: true,
-                message: format!("Image '{}' deleted successfully", file_name),
+                message: msg!("wipe_confirmed", "{target_user} confirmed: {message}", target_user = target_user, message = message),
                 data: None,
-            })
+            });
         }
-        Err(e) => {
-            eprintln!("✗ Failed to delete image: {}", e);
-            Ok(ApiResponse {
-                success: false,
-                message: format!("Failed to delete image: {}", e),
-                data: None,
-            })
+        Ok(None) => format!("{} not found", target_user),
+        Err(e) => e,
+    };
+
+    // Queue it so their client wipes the copy at next login
+    let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
+        from_owner: username,
+        target_user: target_user.clone(),
+        image_id,
+        new_quota: 0,
+        embedded_image: None,
+        wipe: true,
+        ttl_secs: None,
+    };
+    match multicast_directory_message(&dir_servers, pending_msg).await {
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, .. }) => Ok(ApiResponse {
+            success: true,
+            message: msg!("wipe_queued", "Could not wipe now ({reason}) - queued for when {target_user} next logs in", reason = reason, target_user = target_user),
+            data: None,
+        }),
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { message, .. }) => Ok(ApiResponse {
+            success: false,
+            message: msg!("wipe_queue_failed", "Could not wipe ({reason}) or queue the wipe: {message}", reason = reason, message = message),
+            data: None,
+        }),
+        Ok(_) => Err("Unexpected response from directory service".to_string()),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("wipe_directory_unreachable", "Could not wipe ({reason}) or reach the directory: {error}", reason = reason, error = e),
+            data: None,
+        }),
+    }
+}
+
+/// Tell an owner we carried out the wipe they queued while we were offline
+async fn ack_queued_wipe(state: &AppState, owner: &str, image_id: &str, success: bool, message: &str) {
+    let username = match state.session() {
+        Ok(session) => session.username.unwrap_or_default(),
+        Err(_) => return,
+    };
+    let ack = |peer: UserEntry| {
+        let username = username.clone();
+        async move {
+            if peer.status != UserStatus::Online {
+                bail!("{} is offline", peer.username);
+            }
+            send_wipe_ack(&peer.p2p_address, &username, image_id, success, message).await
         }
+    };
+    if let Err(e) = resolve_and_send(state, owner, ack).await {
+        eprintln!("Could not acknowledge wipe of '{}' to {}: {}", image_id, owner, e);
     }
 }
 
-// ============================================================================
-// MAIN
-// ============================================================================
+/// Grant views of an image into a package file for a recipient with no network path to us
+///
+/// The package is written to `output_dir` (e.g. a USB stick) and signed with `passphrase`,
+/// which the recipient needs to import it.
+#[tauri::command]
+async fn export_grant(
+    state: State<'_, AppState>,
+    image_id: String,
+    recipient: String,
+    views: u32,
+    passphrase: String,
+    output_dir: String,
+) -> Result<ApiResponse<String>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "export grants")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let image_id = unqualify_image_id(&username, &image_id).map_err(|e| e.to_string())?.to_string();
+    let Some(carrier_path) = find_owned_image(&state, &image_id).await? else {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("image_not_found", "Image '{image_id}' not found", image_id = image_id),
+            data: None,
+        });
+    };
 
-fn main() {
-    tauri::Builder::default()
-        .manage(AppState::default())
+    let exported = {
+        // The grant is written into our copy, which may be being viewed
+        let _carrier_lock = viewing::lock_carrier(&carrier_path).await;
+        export_grant_package(&carrier_path, &username, &recipient, views, &passphrase)
+    };
+    let (info, package) = match exported {
+        Ok(exported) => exported,
+        Err(e) => return Ok(ApiResponse {
+            success: false,
+            message: msg!("grant_export_failed", "Could not export the grant: {error}", error = e),
+            data: None,
+        }),
+    };
+    let image = info.image_id.trim_end_matches(".png");
+    let output = Path::new(&output_dir).join(format!("{}_{}.{}", recipient, image, GRANT_PACKAGE_EXTENSION));
+    fs::write(&output, &package).map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+
+    let audit_log = state.image_store.read().await.get_audit_log_path().cloned();
+    audit(audit_log.as_deref(), AuditRecord {
+        action: AuditAction::Grant,
+        recipient: recipient.clone(),
+        image_id: info.image_id.clone(),
+        views: Some(views),
+        success: true,
+        message: format!("Exported as grant package {}", output.display()),
+        approved_by: None,
+    });
+
+    let output = output.to_string_lossy().into_owned();
+    Ok(ApiResponse {
+        success: true,
+        message: msg!(
+            "grant_exported",
+            "Granted {recipient} {views} views - package saved to {path}",
+            recipient = recipient,
+            views = views,
+            path = output,
+        ),
+        data: Some(output),
+    })
+}
+
+/// Import a grant package from removable media into `received/`, as if it had been delivered
+#[tauri::command]
+async fn import_grant(
+    state: State<'_, AppState>,
+    package_path: String,
+    passphrase: String,
+) -> Result<ApiResponse<GrantPackageInfo>, String> {
+    let Session { username, images_directory, .. } = state.session()?;
+    let username = username.ok_or("Not logged in")?;
+    let received_dir = images_directory.ok_or("Images directory not configured")?.join("received");
+    fs::create_dir_all(&received_dir).map_err(|e| e.to_string())?;
+    {
+        let mut store = state.image_store.write().await;
+        if store.get_received_images_dir().is_none() {
+            store.set_received_images_dir(received_dir);
+        }
+    }
+
+    let bytes = fs::read(&package_path).map_err(|e| format!("Failed to read {}: {}", package_path, e))?;
+    match import_grant_package(&bytes, &passphrase, &username, &state.image_store).await {
+        Ok((info, message)) => Ok(ApiResponse {
+            success: true,
+            message: msg!(
+                "grant_imported",
+                "{owner} granted you {views} views of '{image_id}': {message}",
+                owner = info.from_owner,
+                views = info.views,
+                image_id = info.image_id,
+                message = message,
+            ),
+            data: Some(info),
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("grant_import_failed", "Could not import the grant package: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// A pairing code as shown to the owner, with the link and QR code a requester can scan
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingCode {
+    #[serde(flatten)]
+    pub offer: PairingOffer,
+    pub link: String,
+    /// The link as an SVG QR code
+    pub qr_svg: String,
+}
+
+/// Offer `views` of a shared image to whoever redeems the returned code, for a device on the same desk
+///
+/// The requester gets the views and the image straight from our P2P server, without
+/// the directory's request and approval cycle; the code works once and expires quickly.
+#[tauri::command]
+async fn create_pairing_code(
+    state: State<'_, AppState>,
+    image_id: String,
+    views: u32,
+) -> Result<ApiResponse<PairingCode>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "pair devices")? {
+        return Ok(refusal);
+    }
+    let session = state.session()?;
+    let username = session.username.ok_or("Not logged in")?;
+    let Some(p2p_address) = session.p2p_address else {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("pairing_needs_online", "Go online to pair - the code is redeemed with your P2P server"),
+            data: None,
+        });
+    };
+    if views == 0 {
+        return Err("A pairing code must offer at least one view".to_string());
+    }
+    let image_id = unqualify_image_id(&username, &image_id).map_err(|e| e.to_string())?.to_string();
+
+    let offer = {
+        let mut store = state.image_store.write().await;
+        if store.get_image_path(&image_id).is_none() {
+            return Ok(ApiResponse {
+                success: false,
+                message: msg!("image_not_found", "Image '{image_id}' not found", image_id = image_id),
+                data: None,
+            });
+        }
+        store.pairing_offers().offer(&image_id, views)
+    };
+    let link = offer.link(&p2p_address);
+    let qr_svg = qrcode::QrCode::new(link.as_bytes())
+        .map_err(|e| e.to_string())?
+        .render::<qrcode::render::svg::Color>()
+        .min_dimensions(200, 200)
+        .build();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!(
+            "pairing_code_created",
+            "Pairing code {code} offers {views} views of '{image_id}'",
+            code = offer.code,
+            views = views,
+            image_id = image_id,
+        ),
+        data: Some(PairingCode { offer, link, qr_svg }),
+    })
+}
+
+/// Withdraw a pairing code that hasn't been redeemed yet
+#[tauri::command]
+async fn cancel_pairing_code(
+    state: State<'_, AppState>,
+    code: String,
+) -> Result<ApiResponse<()>, String> {
+    let withdrawn = state.image_store.write().await.pairing_offers().withdraw(&code);
+    Ok(ApiResponse {
+        success: withdrawn,
+        message: if withdrawn {
+            msg!("pairing_code_cancelled", "Pairing code {code} withdrawn", code = code)
+        } else {
+            msg!("pairing_code_gone", "Pairing code {code} was already used or has expired", code = code)
+        },
+        data: None,
+    })
+}
+
+/// Redeem a pairing code (or the link from its QR code) shown on the owner's device
+#[tauri::command]
+async fn redeem_pairing_code(
+    state: State<'_, AppState>,
+    pairing: String,
+) -> Result<ApiResponse<RedeemedPairing>, String> {
+    let Session { username, images_directory, .. } = state.session()?;
+    let username = username.ok_or("Not logged in")?;
+    let received_dir = images_directory.ok_or("Images directory not configured")?.join("received");
+    fs::create_dir_all(&received_dir).map_err(|e| e.to_string())?;
+    {
+        let mut store = state.image_store.write().await;
+        if store.get_received_images_dir().is_none() {
+            store.set_received_images_dir(received_dir);
+        }
+    }
+
+    let redeemed = match parse_pairing(&pairing) {
+        Ok((peer_addr, code)) => redeem_pairing(&peer_addr, &code, &username, &state.image_store).await,
+        Err(e) => Err(e),
+    };
+    match redeemed {
+        Ok(redeemed) => Ok(ApiResponse {
+            success: true,
+            message: msg!(
+                "pairing_redeemed",
+                "{owner} granted you {views} views of '{image_id}'",
+                owner = redeemed.from_owner,
+                views = redeemed.views,
+                image_id = redeemed.image_id,
+            ),
+            data: Some(redeemed),
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("pairing_redeem_failed", "Could not redeem the pairing code: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+#[tauri::command]
+async fn get_local_images(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<LocalImage>>, String> {
+    let images = state.local_images.lock().map_err(|e| e.to_string())?.clone();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("found_local_images", "Found {count} local images", count = images.len()),
+        data: Some(images),
+    })
+}
+
+#[tauri::command]
+async fn get_encrypted_images(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<LocalImage>>, String> {
+    let mut encrypted_list: Vec<LocalImage> = Vec::new();
+
+    // Union of the encrypted folders of every share root
+    let roots = match session_image_roots(&state)? {
+        Some(roots) => roots,
+        None => {
+            return Ok(ApiResponse {
+                success: true,
+                message: msg!("no_images_directory_offline", "Not connected - no images directory configured"),
+                data: Some(encrypted_list),
+            });
+        }
+    };
+    let username = state.session()?.username;
+    let store = state.image_store.read().await;
+    encrypted_list.extend(fsscan::scan_shared_carriers(&roots).into_iter().map(|carrier| {
+        let setting = store.get_visibility(&carrier.image_id);
+        LocalImage {
+            share_link: username.as_deref()
+                .zip(setting.link_token.as_deref())
+                .map(|(owner, token)| share_link(owner, &carrier.image_id, token)),
+            visibility: setting.visibility,
+            escrow_preview: setting.escrow_preview,
+            max_grant_views: store.get_max_grant_views(&carrier.image_id),
+            image_id: carrier.image_id,
+            file_path: carrier.file.path.to_string_lossy().to_string(),
+            file_name: carrier.file.file_name,
+            file_size_kb: carrier.file.size_kb(),
+            is_encrypted: true,
+            origin: carrier.origin,
+        }
+    }));
+    drop(store);
+
+    eprintln!("Total encrypted images found: {}", encrypted_list.len());
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("found_encrypted_images", "Found {count} encrypted images", count = encrypted_list.len()),
+        data: Some(encrypted_list),
+    })
+}
+
+/// Change who can find and request one of our shared images, answering with its share link when link-only
+#[tauri::command]
+async fn set_image_visibility(
+    state: State<'_, AppState>,
+    image_id: String,
+    visibility: ImageVisibility,
+) -> Result<ApiResponse<Option<String>>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change image visibility")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let setting = match state.image_store.write().await.set_visibility(&image_id, visibility) {
+        Ok(setting) => setting,
+        Err(e) => {
+            return Ok(ApiResponse {
+                success: false,
+                message: msg!("failed_to_change_visibility", "Failed to change visibility of '{image_id}': {error}", image_id = image_id, error = e),
+                data: None,
+            });
+        }
+    };
+
+    // The directory filters catalogs and requests by what we last published
+    if state.session()?.is_online() {
+        enqueue_job(&state, JobKind::UpdateSharedImages { shared_images: shared_catalog(&state).await })?;
+    }
+    let link = setting.link_token.as_deref().map(|token| share_link(&username, &image_id, token));
+    Ok(ApiResponse {
+        success: true,
+        message: match &link {
+            Some(link) => msg!("image_link_only", "'{image_id}' is now link-only: {link}", image_id = image_id, link = link),
+            None => msg!("image_visibility_set", "'{image_id}' is now {visibility}", image_id = image_id, visibility = setting.visibility),
+        },
+        data: Some(link),
+    })
+}
+
+/// Start or stop leaving a blurred preview of a shared image with the directory
+///
+/// Peers can then browse the image while we're offline; their requests wait at the
+/// directory until we're back.
+#[tauri::command]
+async fn set_escrow_preview(
+    state: State<'_, AppState>,
+    image_id: String,
+    enabled: bool,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change image previews")? {
+        return Ok(refusal);
+    }
+    let escrowed = {
+        let mut store = state.image_store.write().await;
+        match store.set_escrow_preview(&image_id, enabled) {
+            Err(e) => Err(e.to_string()),
+            // Don't leave it opted in to a preview that can't be made
+            Ok(_) if enabled && store.escrowed_preview(&image_id).is_none() => {
+                let _ = store.set_escrow_preview(&image_id, false);
+                Err("Could not make a preview of it".to_string())
+            }
+            Ok(_) => Ok(()),
+        }
+    };
+    if let Err(e) = escrowed {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_change_preview", "Failed to change the preview of '{image_id}': {error}", image_id = image_id, error = e),
+            data: None,
+        });
+    }
+
+    if state.session()?.is_online() {
+        enqueue_job(&state, JobKind::UpdateSharedImages { shared_images: shared_catalog(&state).await })?;
+    }
+    Ok(ApiResponse {
+        success: true,
+        message: if enabled {
+            msg!("preview_escrowed", "'{image_id}' can now be browsed while you're offline", image_id = image_id)
+        } else {
+            msg!("preview_withdrawn", "'{image_id}' no longer leaves a preview with the directory", image_id = image_id)
+        },
+        data: Some(()),
+    })
+}
+
+/// Cap the views granted per request for one shared image, or with none go back to the session's cap
+#[tauri::command]
+async fn set_grant_cap(
+    state: State<'_, AppState>,
+    image_id: String,
+    max_views: Option<u32>,
+) -> Result<ApiResponse<Option<u32>>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "change grant caps")? {
+        return Ok(refusal);
+    }
+    if max_views == Some(0) {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("grant_cap_zero", "The cap must allow at least one view"),
+            data: None,
+        });
+    }
+    let cap = match state.image_store.write().await.set_max_grant_views(&image_id, max_views) {
+        Ok(cap) => cap,
+        Err(e) => {
+            return Ok(ApiResponse {
+                success: false,
+                message: msg!("failed_to_change_grant_cap", "Failed to change the grant cap of '{image_id}': {error}", image_id = image_id, error = e),
+                data: None,
+            });
+        }
+    };
+
+    // The directory refuses requests over the cap we last published
+    if state.session()?.is_online() {
+        enqueue_job(&state, JobKind::UpdateSharedImages { shared_images: shared_catalog(&state).await })?;
+    }
+    Ok(ApiResponse {
+        success: true,
+        message: match cap {
+            Some(cap) => msg!("grant_cap_set", "Requests for '{image_id}' will be granted at most {cap} views", image_id = image_id, cap = cap),
+            None => msg!("grant_cap_cleared", "Requests for '{image_id}' are no longer capped", image_id = image_id),
+        },
+        data: Some(cap),
+    })
+}
+
+#[tauri::command]
+async fn get_received_images(
+    state: State<'_, AppState>,
+    sort_by: Option<ReceivedSort>,
+    descending: Option<bool>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+    force_rescan: Option<bool>,
+) -> Result<ApiResponse<ReceivedPage>, String> {
+    // Scan the received images directory for ALL images
+    let Session { username, images_directory, .. } = state.session()?;
+
+    let offset = offset.unwrap_or(0);
+
+    // Get the received directory from the user's images directory (entered in the GUI)
+    let received_dir = match images_directory {
+        Some(images_path) => images_path.join("received"),
+        None => {
+            // Fallback: user not connected yet, return empty list
+            return Ok(ApiResponse {
+                success: true,
+                message: msg!("no_images_directory_offline", "Not connected - no images directory configured"),
+                data: Some(ReceivedPage { images: Vec::new(), total: 0, offset, limit }),
+            });
+        }
+    };
+
+    let received_list = scan_received_images(
+        &received_dir,
+        username.as_deref(),
+        force_rescan.unwrap_or(false),
+        sort_by.unwrap_or_default(),
+        descending.unwrap_or(false),
+    );
+
+    eprintln!("Total received images found: {}", received_list.len());
+
+    // Update state with every image, not just this page
+    store_received_images(&state, received_list.clone())?;
+
+    let total = received_list.len();
+    let images = received_list.into_iter()
+        .skip(offset)
+        .take(limit.unwrap_or(usize::MAX))
+        .collect();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("found_received_images", "Found {count} received images", count = total),
+        data: Some(ReceivedPage { images, total, offset, limit }),
+    })
+}
+
+#[tauri::command]
+async fn refresh_images(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<LocalImage>>, String> {
+    let session = state.session()?;
+    let is_online = session.is_online();
+    let Session { username, images_directory, max_grant_views, .. } = session;
+
+    let images_path = match images_directory {
+        Some(path) => path,
+        None => {
+            return Ok(ApiResponse {
+                success: false,
+                message: msg!("no_images_directory_go_online", "No images directory configured. Please go online first."),
+                data: None,
+            });
+        }
+    };
+
+    let user = username.clone().unwrap_or_else(|| "unknown".to_string());
+
+    let encrypted_dir = images_path.join("encrypted");
+    let received_dir = images_path.join("received");
+
+    // Ensure subdirectories exist
+    let _ = fs::create_dir_all(&encrypted_dir);
+    let _ = fs::create_dir_all(&received_dir);
+
+    let share_roots = state.share_roots.lock().map_err(|e| e.to_string())?.clone();
+    let roots = image_roots(&images_path, &share_roots);
+
+    let (shared_images, local_images_list) = scan_share_roots(&state, &roots, &user, max_grant_views).await;
+
+    // NOTE: We only show images from the top level of each root
+    // Encrypted images (in the /encrypted subfolders) are NOT shown in local images
+
+    // Update the local images in state
+    store_local_images(&state, local_images_list.clone())?;
+
+    // ALSO refresh received images
+    let received_list = scan_received_images(&received_dir, username.as_deref(), false, ReceivedSort::default(), false);
+
+    // Update received images in state
+    store_received_images(&state, received_list.clone())?;
+
+    // IMPORTANT: Update the directory service with the new shared images list
+    // This ensures other peers see the updated list when they query
+    if is_online && username.is_some() {
+        enqueue_job(&state, JobKind::UpdateSharedImages { shared_images })?;
+    }
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("images_refreshed", "Refreshed: Found {local_count} local images and {received_count} received images", local_count = local_images_list.len(), received_count = received_list.len()),
+        data: Some(local_images_list),
+    })
+}
+
+/// Encryption servers from servers.conf in the main project directory
+fn load_encryption_servers() -> Vec<String> {
+    let servers_content = fs::read_to_string("/home/michael12@auc.egy/Documents/Distributed_project/servers.conf")
+        .unwrap_or_else(|_| "10.7.57.239:8080\n10.7.57.240:8081\n10.7.57.99:8082".to_string());
+
+    servers_content
+        .lines()
+        .filter(|s| !s.trim().is_empty() && !s.starts_with('#'))
+        .map(|s| s.trim().to_string())
+        .collect()
+}
+
+/// Carrier the encryption servers embed images into (loaded from `src/unified_image.png`)
+const DEFAULT_CARRIER: &[u8] = include_bytes!("../../../src/unified_image.png");
+
+/// Check that an image will fit its carrier before committing to encryption
+///
+/// Uses the servers' default carrier unless `carrier_path` points at another one.
+#[tauri::command]
+async fn analyze_carrier(
+    state: State<'_, AppState>,
+    image_path: String,
+    annotations: Option<ImageAnnotations>,
+    carrier_path: Option<String>,
+) -> Result<ApiResponse<CarrierAnalysis>, String> {
+    let username = state.session()?.username.unwrap_or_default();
+    let img_data = fs::read(&image_path).map_err(|e| e.to_string())?;
+
+    let metadata = EncryptionMetadata {
+        permissions: ImagePermissions::new(username, HashMap::new()),
+        annotations: annotations.unwrap_or_default(),
+        image_id: Some(new_image_id(&img_data)),
+        expires_at: None,
+        flags: 0,
+    };
+    let payload_bytes = match lsb::payload_size(&img_data, metadata) {
+        Ok(size) => size,
+        Err(e) => return Ok(ApiResponse { success: false, message: msg!("cant_read_image", "Can't read image: {error}", error = e), data: None }),
+    };
+
+    let carrier = match &carrier_path {
+        Some(path) => fs::read(path).map_err(|e| e.to_string())?,
+        None => DEFAULT_CARRIER.to_vec(),
+    };
+    match lsb::analyze_carrier(&carrier, payload_bytes) {
+        Ok(analysis) => Ok(ApiResponse {
+            success: true,
+            message: if analysis.fits {
+                msg!(
+                    "payload_fits",
+                    "Fits: {payload_kb} of {capacity_kb} KB",
+                    payload_kb = format!("{:.1}", payload_bytes as f64 / 1024.0),
+                    capacity_kb = format!("{:.1}", analysis.capacity_bytes as f64 / 1024.0),
+                )
+            } else {
+                msg!("payload_too_big", "Image won't fit the carrier")
+            },
+            data: Some(analysis),
+        }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("cant_read_carrier", "Can't read carrier: {error}", error = e), data: None }),
+    }
+}
+
+#[tauri::command]
+async fn encrypt_image(
+    state: State<'_, AppState>,
+    image_path: String,
+    annotations: Option<ImageAnnotations>,
+    policy: Option<ViewPolicy>,
+    optimize: Option<bool>,
+) -> Result<ApiResponse<String>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "encrypt images")? {
+        return Ok(refusal);
+    }
+    let original_path = PathBuf::from(&image_path);
+    let file_name = original_path.file_name().unwrap_or_default().to_string_lossy();
+    let output_name = format!("encrypted_{}", file_name);
+
+    let encrypted = encrypt_into_share_root(
+        &state,
+        &original_path,
+        annotations.unwrap_or_default(),
+        policy.unwrap_or_default(),
+        &output_name,
+        optimize.unwrap_or(false),
+    ).await?;
+    match encrypted {
+        Some(copy) => {
+            let message = match copy.optimized {
+                Some(summary) => msg!("image_encrypted_optimized", "Image encrypted and added to shareable images (carrier {summary})", summary = summary),
+                None => msg!("image_encrypted", "Image encrypted and added to shareable images"),
+            };
+            Ok(ApiResponse {
+                success: true,
+                message,
+                data: Some(copy.path.to_string_lossy().to_string()),
+            })
+        }
+        None => Ok(ApiResponse {
+            success: false,
+            message: msg!("all_encryption_servers_failed", "All encryption servers failed"),
+            data: None,
+        }),
+    }
+}
+
+/// Queue every photo in a library for encryption, captioned from its EXIF caption, date and keywords
+///
+/// With `albums`, photos are grouped into albums named after their folders.
+#[tauri::command]
+async fn import_library(
+    state: State<'_, AppState>,
+    library_path: String,
+    albums: bool,
+) -> Result<ApiResponse<LibraryImport>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "import photos")? {
+        return Ok(refusal);
+    }
+    let roots = session_image_roots(&state)?
+        .ok_or("Not online. Please go online first.")?;
+    let photos = scan_library(Path::new(&library_path), albums).map_err(|e| e.to_string())?;
+
+    let mut import = LibraryImport { found: photos.len(), queued: 0, already_imported: 0 };
+    for photo in photos {
+        let (_, root) = root_containing(&roots, &photo.path).unwrap_or(&roots[0]);
+        let output_name = photo.output_name();
+        if root.join("encrypted").join(&output_name).exists() {
+            import.already_imported += 1;
+            continue;
+        }
+        enqueue_job(&state, JobKind::EncryptImage {
+            annotations: photo.annotations(),
+            image_path: photo.path,
+            output_name,
+        })?;
+        import.queued += 1;
+    }
+
+    let message = if import.found == 0 {
+        msg!("no_photos_found", "No photos found in {library_path}", library_path = library_path)
+    } else {
+        msg!(
+            "library_import_queued",
+            "Queued {queued} of {found} photos for encryption ({already_imported} already imported)",
+            queued = import.queued,
+            found = import.found,
+            already_imported = import.already_imported,
+        )
+    };
+    Ok(ApiResponse { success: true, message, data: Some(import) })
+}
+
+/// Where an image's encrypted copy was saved
+struct EncryptedCopy {
+    path: PathBuf,
+    /// How much recompressing the carrier saved, if it was optimized
+    optimized: Option<String>,
+}
+
+/// Encrypt an image into the encrypted folder of its share root (or the first root) and share it
+///
+/// With `optimize`, the carrier is losslessly recompressed before it's saved.
+/// Returns None if every encryption server failed.
+async fn encrypt_into_share_root(
+    state: &AppState,
+    image_path: &Path,
+    annotations: ImageAnnotations,
+    policy: ViewPolicy,
+    output_name: &str,
+    optimize: bool,
+) -> Result<Option<EncryptedCopy>, String> {
+    session_image_roots(state)?.ok_or("Not online. Please go online first.")?;
+    let (job, description) = encryption_job(state, image_path, annotations, policy)?;
+    let servers = load_encryption_servers();
+
+    // Try each server
+    for server in &servers {
+        match encryption_pool::send_encryption_request(server, &job.meta_bytes, &job.image).await {
+            Ok(encrypted_data) => {
+                let copy = save_encrypted_copy(state, image_path, encrypted_data, output_name, optimize, description).await?;
+                return Ok(Some(copy));
+            }
+            Err(e) => {
+                eprintln!("Server {} failed: {}", server, e);
+                continue;
+            }
+        }
+    }
+    
+    Ok(None)
+}
+
+/// Read an image and build what the encryption servers need to protect it for the logged-in user,
+/// along with the description shared catalogs will show for it
+fn encryption_job(
+    state: &AppState,
+    image_path: &Path,
+    annotations: ImageAnnotations,
+    policy: ViewPolicy,
+) -> Result<(EncryptionJob, Option<String>), String> {
+    let username = state.current_user()?;
+    
+    // Read the image file
+    let img_data = fs::read(image_path).map_err(|e| e.to_string())?;
+    
+    // Shared catalogs describe an image by its caption, as they do after a rescan
+    let description = annotations.caption.clone().or_else(|| Some(format!("Encrypted image from {}", username)));
+
+    // Create permissions metadata
+    let permissions = ImagePermissions::new(username, HashMap::new());
+    let metadata = EncryptionMetadata {
+        permissions,
+        annotations,
+        image_id: Some(new_image_id(&img_data)),
+        expires_at: None,
+        flags: policy.apply_to_flags(0),
+    };
+    let meta_bytes = bincode::serialize(&metadata).map_err(|e| e.to_string())?;
+    Ok((EncryptionJob { meta_bytes, image: img_data }, description))
+}
+
+/// Save a carrier the servers returned into the encrypted folder of the original's share root
+/// (or the first root), and add it to the P2P image store so it's shareable
+async fn save_encrypted_copy(
+    state: &AppState,
+    image_path: &Path,
+    mut encrypted_data: Vec<u8>,
+    output_name: &str,
+    optimize: bool,
+    description: Option<String>,
+) -> Result<EncryptedCopy, String> {
+    let Session { username, max_grant_views, .. } = state.session()?;
+    let username = username.ok_or("Not logged in")?;
+
+    // Encrypted copies go to the encrypted subfolder of the root the original came from
+    let roots = session_image_roots(state)?
+        .ok_or("Not online. Please go online first.")?;
+    let (origin, root) = root_containing(&roots, image_path)
+        .unwrap_or(&roots[0])
+        .clone();
+    let encrypted_dir = root.join("encrypted");
+
+    // Ensure encrypted directory exists
+    fs::create_dir_all(&encrypted_dir).map_err(|e| e.to_string())?;
+
+    let optimized = if optimize {
+        let optimized = lsb::optimize_carrier(&encrypted_data).map_err(|e| e.to_string())?;
+        let summary = if optimized.saved_bytes() > 0 {
+            optimized.summary()
+        } else {
+            "already as small as it gets".to_string()
+        };
+        encrypted_data = optimized.bytes;
+        Some(summary)
+    } else {
+        None
+    };
+
+    // Save encrypted image to the encrypted/ folder
+    let output_path = encrypted_dir.join(output_name);
+
+    fs::write(&output_path, &encrypted_data).map_err(|e| e.to_string())?;
+    
+    let file_name = output_path.file_name().unwrap_or_default().to_string_lossy().to_string();
+    // Servers that predate image IDs don't embed one, so fall back to the file name
+    let image_id = embedded_image_id(&encrypted_data).unwrap_or_else(|| file_name.clone());
+    let file_size_kb = encrypted_data.len() as u64 / 1024;
+    
+    // Update local images list (scope the lock to drop it before await)
+    let encrypted_image = LocalImage {
+        image_id: image_id.clone(),
+        file_path: output_path.to_string_lossy().to_string(),
+        file_name: file_name.clone(),
+        file_size_kb,
+        is_encrypted: true,
+        origin,
+        visibility: ImageVisibility::Public,
+        share_link: None,
+        escrow_preview: false,
+        max_grant_views,
+    };
+    {
+        let mut local_images = state.local_images.lock().map_err(|e| e.to_string())?;
+        local_images.push(encrypted_image.clone());
+    } // Lock dropped here
+    emit_state_event(state, StateEvent::ImageAdded(ImageEntry::Encrypted(encrypted_image)));
+    
+    // IMPORTANT: Also add to the P2P image store so it's immediately shareable!
+    let metadata = ImageMetadata {
+        image_id: image_id.clone(),
+        image_name: file_name,
+        owner: username,
+        description,
+        file_size_kb,
+        max_grant_views,
+    };
+    
+    state.image_store.write().await.add_image(
+        image_id.clone(),
+        output_path.clone(),
+        metadata,
+    );
+    
+    eprintln!("✓ Added '{}' to P2P image store - now shareable with peers!", image_id);
+    Ok(EncryptedCopy { path: output_path, optimized })
+}
+
+/// Decode a protected image for viewing, consuming one view for non-owners.
+/// Returns the plaintext image bytes (watermarked if the owner requires it), annotations,
+/// the policy that applies to this viewer and the provenance chain if the image was passed on,
+/// or None if access is denied.
+async fn consume_view(username: &str, image_path: &str) -> Result<Option<(Vec<u8>, ImageAnnotations, ViewPolicy, Option<String>)>, String> {
+    // Serialized per file, so simultaneous views can't both spend the same view
+    let outcome = viewing::consume_view(Path::new(image_path), username).await.map_err(|e| e.to_string())?;
+    let ViewOutcome::Granted { payload, is_owner, .. } = outcome else {
+        return Ok(None);
+    };
+    let chain = (!payload.provenance.is_empty()).then(|| payload.provenance_chain());
+
+    if is_owner {
+        return Ok(Some((payload.unified_image, payload.annotations, ViewPolicy::default(), chain)));
+    }
+    let policy = ViewPolicy::from_flags(payload.flags);
+    let view_bytes = if policy.watermark {
+        watermark::watermark_png(&payload.unified_image, &watermark::viewer_stamp(username))
+            .map_err(|e| e.to_string())?
+    } else {
+        payload.unified_image
+    };
+    Ok(Some((view_bytes, payload.annotations, policy, chain)))
+}
+
+/// Upgrade images encrypted by older versions to the current payload format, in place
+///
+/// Migrates `image_path` if given, else every encrypted and received image in the session's roots.
+#[tauri::command]
+async fn migrate_image(
+    state: State<'_, AppState>,
+    image_path: Option<String>,
+) -> Result<ApiResponse<()>, String> {
+    let files: Vec<PathBuf> = match image_path {
+        Some(path) => vec![PathBuf::from(path)],
+        None => {
+            let roots = session_image_roots(&state)?.ok_or("Images directory not configured")?;
+            roots.iter()
+                .flat_map(|(_, root)| [root.join("encrypted"), root.join("received")])
+                .filter_map(|dir| fs::read_dir(dir).ok())
+                .flatten()
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|file| file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+                .collect()
+        }
+    };
+
+    let (mut migrated, mut failed) = (0, Vec::new());
+    for file in &files {
+        match migrate_carrier(file) {
+            Ok(Some(version)) => {
+                eprintln!("⬆ Migrated {} from payload v{} to v{}", file.display(), version, PAYLOAD_VERSION);
+                migrated += 1;
+            }
+            Ok(None) => {}
+            Err(e) => failed.push(format!("{}: {}", file.display(), e)),
+        }
+    }
+
+    Ok(ApiResponse {
+        success: failed.is_empty(),
+        message: if failed.is_empty() {
+            msg!("images_migrated", "Migrated {migrated} of {count} images to the current format", migrated = migrated, count = files.len())
+        } else {
+            msg!(
+                "images_migrated_some_failed",
+                "Migrated {migrated} images, {failed} could not be migrated ({errors})",
+                migrated = migrated,
+                failed = failed.len(),
+                errors = failed.join("; "),
+            )
+        },
+        data: None,
+    })
+}
+
+/// Caption embedded in one of our encrypted images, for the shared catalog
+fn embedded_caption(path: &Path) -> Option<String> {
+    let data = fs::read(path).ok()?;
+    image_annotations(&data)?.caption
+}
+
+/// Tell the owner a view was denied, queueing the report in the directory if they're offline
+async fn report_access_denial(dir_servers: &[String], denial: AccessDenial) -> Result<String, String> {
+    let owner_query = DirectoryMessage::QueryUser {
+        username: denial.owner.clone(),
+        requesting_user: None,
+    };
+
+    if let Ok(DirectoryMessage::QueryUserResponse { user: Some(owner), .. }) =
+        multicast_directory_message(dir_servers, owner_query).await {
+        if owner.status == UserStatus::Online && owner.supports(CAP_ACCESS_REPORTS) {
+            match send_access_denial(&owner.p2p_address, denial.clone()).await {
+                Ok(()) => return Ok(format!("Reported to {}", denial.owner)),
+                Err(e) => eprintln!("Could not report denial to {} directly: {}", denial.owner, e),
+            }
+        }
+    }
+
+    let owner = denial.owner.clone();
+    let store_msg = DirectoryMessage::StoreAccessDenial { denial };
+    match multicast_directory_message(dir_servers, store_msg).await {
+        Ok(DirectoryMessage::StoreAccessDenialResponse { success: true, .. }) => {
+            Ok(format!("Queued at the directory until {} is online", owner))
+        }
+        Ok(DirectoryMessage::StoreAccessDenialResponse { message, .. }) => Err(message),
+        Ok(_) => Err("Unexpected response from directory service".to_string()),
+        Err(e) => Err(format!("Could not queue denial report: {}", e)),
+    }
+}
+
+/// Owner, views left and expiry of an image, read without using up a view, so the viewer
+/// can be asked before opening it
+#[tauri::command]
+async fn peek_image_permissions(
+    state: State<'_, AppState>,
+    image_path: String,
+) -> Result<ApiResponse<viewing::ViewPreview>, String> {
+    let username = state.current_user()?;
+    match viewing::peek_view(Path::new(&image_path), &username) {
+        Ok(preview) => Ok(ApiResponse {
+            success: true,
+            message: match preview.views_remaining {
+                Some(views) => msg!("views_left_before_opening", "You have {views} views left", views = views),
+                None => msg!("viewing_own_image", "You own this image"),
+            },
+            data: Some(preview),
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_read_permissions", "Failed to read permissions: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// Legacy viewer: writes the decoded image to viewable_image.png and returns its path.
+/// Disabled unless the legacy file viewer compatibility flag is set.
+#[tauri::command]
+async fn view_image(
+    state: State<'_, AppState>,
+    image_path: String,
+) -> Result<ApiResponse<ViewedImage>, String> {
+    let username = state.current_user()?;
+    
+    if !*state.legacy_file_viewer.lock().map_err(|e| e.to_string())? {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("file_viewer_disabled", "File-based viewer is disabled (set {env}=1 to enable). Use view_image_bytes instead.", env = LEGACY_FILE_VIEWER_ENV),
+            data: None,
+        });
+    }
+    
+    // The legacy viewer leaves the image on disk, so it counts as saving it
+    let carrier = fs::read(&image_path).map_err(|e| e.to_string())?;
+    let is_owner = p2p_protocol::embedded_permissions(&carrier).is_some_and(|p| p.owner == username);
+    if !is_owner && !p2p_protocol::embedded_view_policy(&carrier).unwrap_or_default().allow_save {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("saving_not_allowed", "The owner doesn't allow saving this image. Use the in-app viewer instead."),
+            data: None,
+        });
+    }
+
+    let denial = access_denial_for(std::path::Path::new(&image_path), &username);
+    
+    match consume_view(&username, &image_path).await? {
+        Some((client_image_bytes, annotations, policy, provenance)) => {
+            // Save viewable image
+            let view_path = PathBuf::from(&image_path)
+                .parent()
+                .map(|p| p.join("viewable_image.png"))
+                .unwrap_or_else(|| PathBuf::from("viewable_image.png"));
+            
+            fs::write(&view_path, &client_image_bytes).map_err(|e| e.to_string())?;
+            if let (Some(permissions), Some(image_id)) = (p2p_protocol::embedded_permissions(&carrier), embedded_image_id(&carrier)) {
+                state.legacy_views.lock().map_err(|e| e.to_string())?.insert((permissions.owner, image_id), view_path.clone());
+            }
+            
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("image_decoded", "Image decoded successfully"),
+                data: Some(ViewedImage {
+                    image: view_path.to_string_lossy().to_string(),
+                    annotations,
+                    policy,
+                    provenance,
+                }),
+            })
+        }
+        None => {
+            if let Some(denial) = denial {
+                enqueue_job(&state, JobKind::ReportAccessDenial { denial })?;
+            }
+            Ok(ApiResponse {
+                success: false,
+                message: msg!("access_denied", "Access denied - no remaining views or not authorized"),
+                data: None,
+            })
+        }
+    }
+}
+
+/// In-memory viewer: consumes a view and returns the decoded image as a base64 data URL.
+/// The plaintext image is never written to disk.
+#[tauri::command]
+async fn view_image_bytes(
+    state: State<'_, AppState>,
+    image_path: String,
+) -> Result<ApiResponse<ViewedImage>, String> {
+    let username = state.current_user()?;
+    
+    // Work out a denial before viewing, since a successful view can use up the last view
+    let denial = access_denial_for(std::path::Path::new(&image_path), &username);
+    
+    match consume_view(&username, &image_path).await? {
+        Some((client_image_bytes, annotations, policy, provenance)) => {
+            use base64::{Engine as _, engine::general_purpose::STANDARD};
+            let data_url = format!("data:image/png;base64,{}", STANDARD.encode(&client_image_bytes));
+            
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("image_decoded", "Image decoded successfully"),
+                data: Some(ViewedImage { image: data_url, annotations, policy, provenance }),
+            })
+        }
+        None => {
+            if let Some(denial) = denial {
+                enqueue_job(&state, JobKind::ReportAccessDenial { denial })?;
+            }
+            Ok(ApiResponse {
+                success: false,
+                message: msg!("access_denied", "Access denied - no remaining views or not authorized"),
+                data: None,
+            })
+        }
+    }
+}
+
+#[tauri::command]
+async fn send_heartbeat(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<serde_json::Value>, String> {
+    let session = state.session()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    
+    let (Some(username), true) = (session.username, session.phase == SessionPhase::Online) else {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("not_online", "Not online"),
+            data: None,
+        });
+    };
+    
+    let heartbeat_msg = DirectoryMessage::Heartbeat {
+        username,
+        capabilities: Some(local_capabilities()),
+    };
+    
+    const MAX_FAILURES: u32 = 3; // Disconnect after 3 consecutive failures
+    
+    match multicast_tracked(&state, &dir_servers, heartbeat_msg).await {
+        Ok(DirectoryMessage::HeartbeatResponse { success, due_reminders }) => {
+            if success {
+                // Resets the failure counter too
+                note_heartbeat(&state);
+                note_due_reminders(&state, due_reminders);
+            }
+            Ok(ApiResponse {
+                success,
+                message: if success { msg!("heartbeat_sent", "Heartbeat sent") } else { msg!("heartbeat_rejected", "Heartbeat failed") },
+                data: Some(serde_json::json!({
+                    "connected": true,
+                    "failures": 0,
+                    "due_reminders": due_reminders
+                })),
+            })
+        }
+        Ok(_) => {
+            let (current_failures, should_disconnect) = state.update_session(|session| count_heartbeat_failure(session, MAX_FAILURES))?;
+            
+            if should_disconnect {
+                // Auto-disconnect, then keep trying to register again in the background
+                start_reconnecting(&state).await;
+            }
+            
+            Ok(ApiResponse {
+                success: false,
+                message: msg!("heartbeat_unexpected_response", "Unexpected response (failures: {failures})", failures = current_failures),
+                data: Some(serde_json::json!({
+                    "connected": !should_disconnect,
+                    "failures": current_failures,
+                    "disconnected": should_disconnect,
+                    "reconnecting": should_disconnect
+                })),
+            })
+        }
+        Err(e) => {
+            let (current_failures, should_disconnect) = state.update_session(|session| count_heartbeat_failure(session, MAX_FAILURES))?;
+            
+            if should_disconnect {
+                // Auto-disconnect - all servers are down
+                eprintln!("All directory servers unreachable. Auto-disconnecting, will keep trying to reconnect.");
+                start_reconnecting(&state).await;
+            }
+            
+            Ok(ApiResponse {
+                success: false,
+                message: msg!("heartbeat_failed", "Heartbeat failed: {error} (failures: {failures}/{max_failures})", error = e, failures = current_failures, max_failures = MAX_FAILURES),
+                data: Some(serde_json::json!({
+                    "connected": !should_disconnect,
+                    "failures": current_failures,
+                    "disconnected": should_disconnect,
+                    "reconnecting": should_disconnect,
+                    "reason": "All directory servers unreachable"
+                })),
+            })
+        }
+    }
+}
+
+/// Count a failed heartbeat, dropping to Connecting once `max_failures` fail in a row
+///
+/// Returns the failures so far and whether this one disconnected us.
+fn count_heartbeat_failure(session: &mut Session, max_failures: u32) -> (u32, bool) {
+    session.heartbeat_failures += 1;
+    let failures = session.heartbeat_failures;
+    let disconnect = failures >= max_failures && session.is_online();
+    if disconnect {
+        session.lose_connection();
+    }
+    (failures, disconnect)
+}
+
+/// First pause before re-registering after heartbeats fail; it doubles after each failed attempt
+const RECONNECT_INITIAL_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest pause between re-registration attempts
+const RECONNECT_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Start re-registering in the background, unless that's already happening
+async fn start_reconnecting(state: &AppState) {
+    let Some(app) = state.app_handle.lock().ok().and_then(|handle| handle.clone()) else {
+        return;
+    };
+    let mut tasks = state.tasks.lock().await;
+    if tasks.reconnect.as_ref().is_some_and(|sender| !sender.is_closed()) {
+        return;
+    }
+    let (shutdown_tx, shutdown_rx) = mpsc::channel::<()>(1);
+    tasks.reconnect = Some(shutdown_tx);
+    tokio::spawn(run_reconnect(app, shutdown_rx));
+}
+
+/// Re-register with growing pauses until a directory takes us back, then resync and announce it
+///
+/// Stops early when the user goes offline or back online themselves.
+async fn run_reconnect(app: tauri::AppHandle, mut shutdown_rx: mpsc::Receiver<()>) {
+    let started = Instant::now();
+    let mut delay = RECONNECT_INITIAL_DELAY;
+    let mut attempts = 0;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown_rx.recv() => break,
+        }
+
+        attempts += 1;
+        let state = app.state::<AppState>();
+        let _ = state.update_session(|session| session.reconnect_attempts = Some(attempts));
+        match reregister(&state).await {
+            Ok(()) => {
+                eprintln!("✓ Reconnected to the directory after {} attempts", attempts);
+                let (shared_images, pending_updates) = resync_after_reconnect(&app).await;
+                emit_state_event(&state, StateEvent::Reconnected {
+                    attempts,
+                    offline_secs: started.elapsed().as_secs(),
+                    shared_images,
+                    pending_updates,
+                });
+                break;
+            }
+            Err(e) => {
+                eprintln!("Reconnect attempt {} failed: {}", attempts, e);
+                delay = (delay * 2).min(RECONNECT_MAX_DELAY);
+            }
+        }
+    }
+    let _ = app.state::<AppState>().update_session(|session| session.reconnect_attempts = None);
+}
+
+/// Register again at the same address with the catalog we last scanned
+async fn reregister(state: &AppState) -> Result<(), String> {
+    let session = state.session()?;
+    let username = session.username.ok_or("Not logged in")?;
+    let p2p_address = session.p2p_address.ok_or("P2P server is not running")?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let register_msg = DirectoryMessage::Register {
+        username,
+        p2p_address,
+        shared_images: shared_catalog(state).await,
+        capabilities: local_capabilities(),
+        availability: session.availability,
+        key: session.key,
+    };
+    match multicast_directory_message(&dir_servers, register_msg).await {
+        Ok(DirectoryMessage::RegisterResponse { success: true, .. }) => {}
+        Ok(DirectoryMessage::RegisterResponse { message, .. }) => return Err(message),
+        Ok(_) => return Err("Unexpected response from directory service".to_string()),
+        Err(e) => return Err(e.to_string()),
+    }
+
+    // Going offline or online by hand in the meantime wins over the reconnect
+    state.update_session(|session| {
+        if session.phase == SessionPhase::Connecting {
+            session.reconnected();
+        }
+    })?;
+    Ok(())
+}
+
+/// The images we share, as last scanned into the image store
+async fn shared_catalog(state: &AppState) -> Vec<ImageInfo> {
+    let store = state.image_store.read().await;
+    store.get_all_metadata()
+        .into_iter()
+        .map(|metadata| {
+            let visibility = store.get_visibility(&metadata.image_id);
+            ImageInfo {
+                caption: store.get_image_path(&metadata.image_id).and_then(|path| embedded_caption(path)),
+                preview: store.escrowed_preview(&metadata.image_id),
+                image_id: metadata.image_id,
+                image_name: metadata.image_name,
+                thumbnail_path: None,
+                max_grant_views: metadata.max_grant_views,
+                visibility: visibility.visibility,
+                link_token: visibility.link_token,
+            }
+        })
+        .collect()
+}
+
+/// Catch up on what changed while we were cut off
+///
+/// Rescanning queues `UpdateSharedImages` with anything encrypted or deleted
+/// meanwhile, then pending permission updates are applied. Returns how many
+/// images we share and a line per applied update.
+async fn resync_after_reconnect(app: &tauri::AppHandle) -> (usize, Vec<String>) {
+    match refresh_images(app.state()).await {
+        Ok(response) if !response.success => eprintln!("Rescan after reconnecting failed: {}", response.message),
+        Err(e) => eprintln!("Rescan after reconnecting failed: {}", e),
+        Ok(_) => {}
+    }
+    let shared_images = app.state::<AppState>().image_store.read().await.get_all_metadata().len();
+
+    let pending_updates = match check_pending_permission_updates(app.state()).await {
+        Ok(ApiResponse { data: Some(updates), .. }) => updates.into_iter().map(|update| update.message).collect(),
+        Ok(response) => {
+            eprintln!("Pending updates after reconnecting: {}", response.message);
+            Vec::new()
+        }
+        Err(e) => {
+            eprintln!("Pending updates after reconnecting: {}", e);
+            Vec::new()
+        }
+    };
+    (shared_images, pending_updates)
+}
+
+#[tauri::command]
+async fn list_peer_images_cmd(
+    state: State<'_, AppState>,
+    peer_username: String,
+    query: Option<String>,
+) -> Result<ApiResponse<Vec<ImageMetadata>>, String> {
+    let username = state.current_user()?;
+    // Resolve the peer's P2P address (cached) and ask for its images (matching `query`, if given)
+    let list = |peer: UserEntry| {
+        let username = username.clone();
+        let query = query.clone();
+        async move { search_peer_images(&peer.p2p_address, &username, query.as_deref()).await }
+    };
+    
+    match resolve_and_send(&state, &peer_username, list).await {
+        Ok(Some(images)) => Ok(ApiResponse {
+            success: true,
+            message: msg!("found_images", "Found {count} images", count = images.len()),
+            data: Some(images),
+        }),
+        Ok(None) => Ok(ApiResponse {
+            success: false,
+            message: msg!("peer_not_found_or_offline", "Peer {peer_username} not found or offline", peer_username = peer_username),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_list_images", "Failed to list images: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// Start or stop following a peer's catalog live, instead of re-listing it
+///
+/// Starting returns the peer's images as they are now; changes then arrive as
+/// `peer_image_shared` and `peer_image_unshared` state events until it's stopped.
+#[tauri::command]
+async fn watch_peer_catalog(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    peer_username: String,
+    watch: bool,
+) -> Result<ApiResponse<Vec<ImageInfoJson>>, String> {
+    // Replacing (or removing) the sender ends any subscription already open to this peer
+    state.catalog_watches.lock().map_err(|e| e.to_string())?.remove(&peer_username);
+    if !watch {
+        return Ok(ApiResponse {
+            success: true,
+            message: msg!("catalog_unwatched", "Stopped following {peer_username}'s images", peer_username = peer_username),
+            data: None,
+        });
+    }
+
+    let username = state.current_user()?;
+    let subscribe = |peer: UserEntry| {
+        let username = username.clone();
+        async move {
+            if peer.status != UserStatus::Online {
+                bail!("{} is offline", peer.username);
+            }
+            if !peer.supports(CAP_CATALOG_UPDATES) {
+                bail!("{}'s client doesn't push catalog updates yet", peer.username);
+            }
+            subscribe_catalog(&peer.p2p_address, &username).await
+        }
+    };
+    let (images, subscription) = match resolve_and_send(&state, &peer_username, subscribe).await {
+        Ok(Some(subscribed)) => subscribed,
+        Ok(None) => return Ok(ApiResponse {
+            success: false,
+            message: msg!("peer_not_found_or_offline", "Peer {peer_username} not found or offline", peer_username = peer_username),
+            data: None,
+        }),
+        Err(e) => return Ok(ApiResponse {
+            success: false,
+            message: msg!("catalog_watch_failed", "Could not follow {peer_username}'s images: {error}", peer_username = peer_username, error = e),
+            data: None,
+        }),
+    };
+
+    let (shutdown_tx, shutdown_rx) = mpsc::channel(1);
+    state.catalog_watches.lock().map_err(|e| e.to_string())?.insert(peer_username.clone(), shutdown_tx);
+    tokio::spawn(run_catalog_watch(app, peer_username.clone(), subscription, shutdown_rx));
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("catalog_watched", "Following {peer_username}'s {count} images", peer_username = peer_username, count = images.len()),
+        data: Some(images.iter().map(ImageInfoJson::from).collect()),
+    })
+}
+
+/// Pass a peer's catalog changes on to the frontend until the peer or the frontend ends the watch
+async fn run_catalog_watch(
+    app: tauri::AppHandle,
+    peer_username: String,
+    mut subscription: CatalogSubscription,
+    mut shutdown_rx: mpsc::Receiver<()>,
+) {
+    let state = app.state::<AppState>();
+    loop {
+        let event = tokio::select! {
+            _ = shutdown_rx.recv() => return,
+            event = subscription.next_event() => event,
+        };
+        let event = match event {
+            Ok(Some(CatalogEvent::Added { image } | CatalogEvent::Updated { image })) => StateEvent::PeerImageShared {
+                username: peer_username.clone(),
+                image: ImageInfoJson::from(&image),
+            },
+            Ok(Some(CatalogEvent::Removed { image_id })) => StateEvent::PeerImageUnshared {
+                username: peer_username.clone(),
+                image_id,
+            },
+            Ok(Some(CatalogEvent::KeepAlive)) => continue,
+            Ok(None) => break,
+            Err(e) => {
+                eprintln!("Lost {}'s catalog updates: {:#}", peer_username, e);
+                break;
+            }
+        };
+        emit_state_event(&state, event);
+    }
+    // Forget the watch, unless a newer one already replaced it (dropping our sender)
+    if let Ok(mut watches) = state.catalog_watches.lock() {
+        if matches!(shutdown_rx.try_recv(), Err(mpsc::error::TryRecvError::Empty)) {
+            watches.remove(&peer_username);
+        }
+    }
+}
+
+#[tauri::command]
+async fn get_image_thumbnail(
+    state: State<'_, AppState>,
+    peer_username: String,
+    image_id: String,
+) -> Result<ApiResponse<String>, String> {
+    let username = state.current_user()?;
+    // Resolve the peer's P2P address (cached) and request the thumbnail
+    let fetch = |peer: UserEntry| {
+        let username = username.clone();
+        let image_id = image_id.clone();
+        async move {
+            if peer.status != UserStatus::Online {
+                bail!("Peer {} is not online", peer.username);
+            }
+            if !peer.supports(CAP_THUMBNAILS) {
+                bail!("Peer {} does not serve thumbnails", peer.username);
+            }
+            request_thumbnail_from_peer(&peer.p2p_address, &username, &image_id).await
+        }
+    };
+    
+    match resolve_and_send(&state, &peer_username, fetch).await {
+        Ok(Some(thumbnail_bytes)) => {
+            // Convert to base64 for easy transfer to frontend
+            use base64::{Engine as _, engine::general_purpose::STANDARD};
+            let base64_thumbnail = STANDARD.encode(&thumbnail_bytes);
+            let data_url = format!("data:image/png;base64,{}", base64_thumbnail);
+            
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("thumbnail_retrieved", "Thumbnail retrieved"),
+                data: Some(data_url),
+            })
+        }
+        Ok(None) => Ok(ApiResponse {
+            success: false,
+            message: msg!("peer_not_found", "Peer {peer_username} not found", peer_username = peer_username),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_get_thumbnail", "Failed to get thumbnail: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+// ============================================================================
+// PENDING PERMISSION UPDATES
+// ============================================================================
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PermissionUpdateInfo {
+    pub from_owner: String,
+    pub image_id: String,
+    pub new_quota: u32,
+    pub message: String,
+}
+
+/// Our queued updates the directory dropped because the recipient never came back for them
+#[tauri::command]
+async fn check_expired_deliveries(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<ExpiredDelivery>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    match multicast_directory_message(&dir_servers, DirectoryMessage::GetExpiredDeliveries { username }).await {
+        Ok(DirectoryMessage::GetExpiredDeliveriesResponse { expired }) => Ok(ApiResponse {
+            success: true,
+            message: msg!("expired_delivery_count", "{count} expired deliveries", count = expired.len()),
+            data: Some(expired),
+        }),
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response_from_directory_service", "Unexpected response from directory service"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("could_not_check_expired_deliveries", "Could not check expired deliveries: {error}", error = e), data: None }),
+    }
+}
+
+/// Denied view attempts on our images, including reports queued while we were offline
+#[tauri::command]
+async fn get_access_denial_stats(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<HashMap<String, DenialStatsInfo>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    let stats_path = state.image_store.read().await.get_denial_stats_path().cloned()
+        .ok_or("Not online")?;
+
+    let msg = DirectoryMessage::GetAccessDenials { username };
+    if let Ok(DirectoryMessage::GetAccessDenialsResponse { denials }) =
+        multicast_directory_message(&dir_servers, msg).await {
+        if !denials.is_empty() {
+            record_access_denials(&stats_path, &denials).map_err(|e| e.to_string())?;
+        }
+    }
+
+    let stats: HashMap<String, DenialStatsInfo> = load_access_denial_stats(&stats_path)
+        .into_iter()
+        .map(|(image_id, s)| {
+            let last_denied = s.last_denied_at.map(time_ago).unwrap_or_else(|| "Unknown".to_string());
+            (image_id, DenialStatsInfo {
+                total: s.total,
+                by_viewer: s.by_viewer,
+                last_denied,
+            })
+        })
+        .collect();
+
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("denial_stats_loaded", "Denial stats for {count} image(s)", count = stats.len()),
+        data: Some(stats),
+    })
+}
+
+#[tauri::command]
+async fn follow_peer(
+    state: State<'_, AppState>,
+    peer_username: String,
+    follow: bool,
+) -> Result<ApiResponse<()>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::FollowPeer {
+        username,
+        peer: peer_username,
+        follow,
+    };
+
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::FollowPeerResponse { success, message }) => Ok(ApiResponse {
+            success,
+            message: message.into(),
+            data: None,
+        }),
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_update_follow", "Failed to update follow: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// Fetch catalog changes from followed peers since the last poll
+#[tauri::command]
+async fn get_catalog_changes(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<CatalogFeed>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    let since = state.session()?.catalog_since;
+
+    let msg = DirectoryMessage::GetCatalogChanges { username, since };
+
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::GetCatalogChangesResponse { changes, following, server_time }) => {
+            state.update_session(|session| session.catalog_since = Some(server_time))?;
+
+            // First poll only establishes the baseline, so old changes aren't replayed
+            let changes = if since.is_none() { Vec::new() } else { changes };
+            let changes: Vec<CatalogChangeInfo> = changes.into_iter().map(|c| {
+                let timestamp = time_ago(c.timestamp);
+
+                CatalogChangeInfo {
+                    owner: c.owner,
+                    added: c.added,
+                    removed: c.removed,
+                    timestamp,
+                }
+            }).collect();
+
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("catalog_change_count", "{count} catalog change(s)", count = changes.len()),
+                data: Some(CatalogFeed { changes, following }),
+            })
+        }
+        Ok(_) => Ok(ApiResponse {
+            success: false,
+            message: msg!("unexpected_response", "Unexpected response"),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_get_catalog_changes", "Failed to get catalog changes: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+#[tauri::command]
+async fn check_pending_permission_updates(
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<Vec<PermissionUpdateInfo>>, String> {
+    let Session { username, images_directory, .. } = state.session()?;
+    let username = username.ok_or("Not logged in")?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    
+    let received_dir = match images_directory {
+        Some(path) => path.join("received"),
+        None => return Ok(ApiResponse {
+            success: false,
+            message: msg!("images_directory_not_configured", "Images directory not configured"),
+            data: None,
+        }),
+    };
+    
+    // Ensure received directory exists
+    let _ = fs::create_dir_all(&received_dir);
+    
+    match fetch_pending_updates(&dir_servers, &username).await {
+        Ok(updates) => {
+            let mut processed_updates: Vec<PermissionUpdateInfo> = Vec::new();
+            
+            for update in updates {
+                let mut info = PermissionUpdateInfo {
+                    from_owner: update.from_owner.clone(),
+                    image_id: update.image_id.clone(),
+                    new_quota: update.new_quota,
+                    message: String::new(),
+                };
+
+                if update.wipe {
+                    let (success, message) = match wipe_received_image(Some(&received_dir), &update.from_owner, &update.image_id) {
+                        Ok(wiped) => (true, format!("Deleted {} local copies of '{}'", wiped, update.image_id)),
+                        Err(e) => (false, format!("Failed to delete local copy: {}", e)),
+                    };
+                    ack_queued_wipe(&state, &update.from_owner, &update.image_id, success, &message).await;
+                    info.message = format!("{} had image '{}' wiped: {}", update.from_owner, update.image_id, message);
+                    processed_updates.push(info);
+                    continue;
+                }
+                
+                // If there's an embedded image, save it
+                if let Some(embedded_image) = update.embedded_image {
+                    let save_path = p2p_protocol::received_image_path(Some(&received_dir), &update.from_owner, &update.image_id);
+
+                    // Hand it back to the directory rather than half-write it to a full disk
+                    if let Err(shortfall) = p2p_protocol::reserve_disk_space(&received_dir, embedded_image.len() as u64) {
+                        let requeue_msg = DirectoryMessage::StorePendingPermissionUpdate {
+                            from_owner: update.from_owner.clone(),
+                            target_user: username.clone(),
+                            image_id: update.image_id.clone(),
+                            new_quota: update.new_quota,
+                            embedded_image: Some(embedded_image),
+                            wipe: false,
+                            // Keep the owner's original deadline
+                            ttl_secs: update.expires_at
+                                .and_then(|expires_at| expires_at.duration_since(SystemTime::now()).ok())
+                                .map(|left| left.as_secs()),
+                        };
+                        let requeued = matches!(
+                            multicast_directory_message(&dir_servers, requeue_msg).await,
+                            Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, .. })
+                        );
+                        info.message = if requeued {
+                            format!("{} - update from {} for '{}' left queued until there's room", shortfall, update.from_owner, update.image_id)
+                        } else {
+                            format!("{} - update from {} for '{}' could not be queued again", shortfall, update.from_owner, update.image_id)
+                        };
+                        processed_updates.push(info);
+                        continue;
+                    }
+                    
+                    match save_received_image(&save_path, &update.from_owner, &update.image_id, &embedded_image) {
+                        Ok(_) => {
+                            if update.new_quota == 0 {
+                                info.message = format!(
+                                    "{} has REVOKED your access to image '{}'",
+                                    update.from_owner, update.image_id
+                                );
+                            } else {
+                                info.message = format!(
+                                    "{} has updated your permissions for image '{}' to {} views",
+                                    update.from_owner, update.image_id, update.new_quota
+                                );
+                            }
+                        }
+                        Err(e) => {
+                            info.message = format!("Failed to save image: {}", e);
+                        }
+                    }
+                } else {
+                    info.message = format!(
+                        "{} updated permissions for image '{}' to {} views (no image delivered)",
+                        update.from_owner, update.image_id, update.new_quota
+                    );
+                }
+                
+                processed_updates.push(info);
+            }
+            
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("processed_pending_updates", "Processed {count} pending updates", count = processed_updates.len()),
+                data: Some(processed_updates),
+            })
+        }
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_check_updates", "Failed to check updates: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+#[tauri::command]
+async fn verify_received_image_cmd(
+    file_path: String,
+) -> Result<ApiResponse<ReceivedImageVerification>, String> {
+    match verify_received_image(&PathBuf::from(&file_path)) {
+        Ok(verification) => {
+            let message = if verification.file_unchanged {
+                msg!("received_image_unchanged", "Image matches the bytes originally received")
+            } else if verification.content_matches {
+                msg!("received_image_content_verified", "Image content verified (permissions changed since delivery)")
+            } else {
+                msg!("received_image_mismatch", "Image does NOT match what was received")
+            };
+            Ok(ApiResponse {
+                success: verification.content_matches,
+                message,
+                data: Some(verification),
+            })
+        }
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_verify_image", "Failed to verify image: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+#[tauri::command]
+async fn delete_image(
+    state: State<'_, AppState>,
+    file_path: String,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "delete images")? {
+        return Ok(refusal);
+    }
+    let roots = session_image_roots(&state)?.unwrap_or_default();
+    delete_image_file(&state, &roots, file_path).await
+}
+
+/// Why a file can't be deleted, if it can't
+fn deletion_refusal(roots: &[(String, PathBuf)], path: &Path) -> Option<String> {
+    if !path.exists() {
+        return Some(format!("File not found: {}", path.display()));
+    }
+    // Each root covers its own encrypted/ subfolder, and the main one also covers received/
+    if root_containing(roots, path).is_none() {
+        return Some("Cannot delete files outside of your images directory".to_string());
+    }
+    None
+}
+
+/// Delete an image inside one of our share roots and forget it
+async fn delete_image_file(
+    state: &AppState,
+    roots: &[(String, PathBuf)],
+    file_path: String,
+) -> Result<ApiResponse<()>, String> {
+    let path = PathBuf::from(&file_path);
+    if let Some(message) = deletion_refusal(roots, &path) {
+        return Ok(ApiResponse {
+            success: false,
+            message: message.into(),
+            data: None,
+        });
+    }
+    
+    // Delete the file
+    match fs::remove_file(&path) {
+        Ok(_) => {
+            let file_name = path.file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or("unknown");
+            
+            eprintln!("✓ Deleted image: {}", file_path);
+            
+            // Remove the integrity record for received images
+            let _ = fs::remove_file(received_record_path(&path));
+            
+            // Also remove from local_images state if it exists there
+            if let Ok(mut local_images) = state.local_images.lock() {
+                local_images.retain(|img| img.file_path != file_path);
+            }
+            if let Ok(mut received_images) = state.received_images.lock() {
+                received_images.retain(|img| img.file_path != file_path);
+            }
+            
+            // Remove from image_store if it's an encrypted image
+            let image_store = state.image_store.clone();
+            let image_id = file_name.to_string();
+            emit_state_event(state, StateEvent::ImageRemoved {
+                image_id: image_id.clone(),
+                file_path: file_path.clone(),
+            });
+            {
+                let mut store = image_store.write().await;
+                store.remove_image(&image_id);
+            }
+            
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("image_deleted", "Image '{file_name}' deleted successfully", file_name = file_name),
+                data: None,
+            })
+        }
+        Err(e) => {
+            eprintln!("✗ Failed to delete image: {}", e);
+            Ok(ApiResponse {
+                success: false,
+                message: msg!("failed_to_delete_image", "Failed to delete image: {error}", error = e),
+                data: None,
+            })
+        }
+    }
+}
+
+// ============================================================================
+// BATCH OPERATIONS
+// ============================================================================
+
+/// Items of a batch command worked on at once
+const BATCH_CONCURRENCY: usize = 4;
+
+/// Tries at the encryption servers for each image of a batch before it counts as failed
+const BATCH_ENCRYPT_ATTEMPTS: u32 = 3;
+
+/// Run `task` on every item, at most `BATCH_CONCURRENCY` at a time, with results in input order
+async fn run_batch<T, F, Fut>(app: &tauri::AppHandle, items: Vec<T>, task: F) -> Result<Vec<BatchItemResult>, String>
+where
+    T: Send + 'static,
+    F: Fn(tauri::AppHandle, T) -> Fut,
+    Fut: Future<Output = BatchItemResult> + Send + 'static,
+{
+    let limit = Arc::new(Semaphore::new(BATCH_CONCURRENCY));
+    let mut tasks = tokio::task::JoinSet::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let limit = limit.clone();
+        let run = task(app.clone(), item);
+        tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            (index, run.await)
+        });
+    }
+
+    let mut results = Vec::new();
+    while let Some(joined) = tasks.join_next().await {
+        results.push(joined.map_err(|e| e.to_string())?);
+    }
+    results.sort_by_key(|(index, _)| *index);
+    Ok(results.into_iter().map(|(_, result)| result).collect())
+}
+
+/// Check every item of a batch before any of it runs
+///
+/// Returns a refusal listing each problem if anything is wrong (or repeated), so
+/// a batch either runs in full or not at all.
+fn validate_batch<T>(
+    items: &[T],
+    label: impl Fn(&T) -> String,
+    problem: impl Fn(&T) -> Option<String>,
+) -> Option<ApiResponse<Vec<BatchItemResult>>> {
+    if items.is_empty() {
+        return Some(ApiResponse {
+            success: false,
+            message: msg!("nothing_selected", "Nothing selected"),
+            data: None,
+        });
+    }
+
+    let mut seen = HashSet::new();
+    let checked: Vec<(String, Option<String>)> = items.iter()
+        .map(|item| {
+            let label = label(item);
+            if seen.insert(label.clone()) {
+                let problem = problem(item);
+                (label, problem)
+            } else {
+                (label, Some("Listed more than once".to_string()))
+            }
+        })
+        .collect();
+    let invalid = checked.iter().filter(|(_, problem)| problem.is_some()).count();
+    if invalid == 0 {
+        return None;
+    }
+    let results = checked.into_iter()
+        .map(|(item, problem)| BatchItemResult {
+            item,
+            success: false,
+            message: problem.unwrap_or_else(|| "Not attempted".to_string()),
+            data: None,
+        })
+        .collect();
+    Some(ApiResponse {
+        success: false,
+        message: msg!("batch_items_invalid", "{invalid} of {count} selected items can't be processed, nothing was done", invalid = invalid, count = items.len()),
+        data: Some(results),
+    })
+}
+
+/// Summarize a finished batch, e.g. "Deleted 4 of 5 images (1 failed)"
+///
+/// The code is `batch_<action>` or `batch_<action>_some_failed`, so each action can be translated on its own.
+fn batch_response(action: &str, results: Vec<BatchItemResult>) -> ApiResponse<Vec<BatchItemResult>> {
+    let failed = results.iter().filter(|r| !r.success).count();
+    let code = format!("batch_{}", action.to_lowercase());
+    let message = match failed {
+        0 => msg!(code, "{action} {count} images", action = action, count = results.len()),
+        _ => msg!(
+            format!("{}_some_failed", code),
+            "{action} {done} of {count} images ({failed} failed)",
+            action = action,
+            done = results.len() - failed,
+            count = results.len(),
+            failed = failed,
+        ),
+    };
+    ApiResponse {
+        success: failed == 0,
+        message,
+        data: Some(results),
+    }
+}
+
+/// Delete several images at once, answering with what happened to each
+#[tauri::command]
+async fn delete_images(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    file_paths: Vec<String>,
+) -> Result<ApiResponse<Vec<BatchItemResult>>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "delete images")? {
+        return Ok(refusal);
+    }
+    let roots = Arc::new(session_image_roots(&state)?.unwrap_or_default());
+    if let Some(refusal) = validate_batch(&file_paths, String::clone, |path| deletion_refusal(&roots, Path::new(path))) {
+        return Ok(refusal);
+    }
+
+    let results = run_batch(&app, file_paths, |app, file_path| {
+        let roots = roots.clone();
+        async move {
+            let state = app.state::<AppState>();
+            let outcome = delete_image_file(&state, &roots, file_path.clone()).await;
+            let (success, message) = match outcome {
+                Ok(response) => (response.success, response.message.to_string()),
+                Err(e) => (false, e),
+            };
+            BatchItemResult { item: file_path, success, message, data: None }
+        }
+    }).await?;
+    Ok(batch_response("Deleted", results))
+}
+
+/// Encrypt several images at once into the share root, answering with what happened to each
+///
+/// The images are spread over every healthy encryption server, reporting progress as
+/// `encryption_progress` state events.
+#[tauri::command]
+async fn encrypt_images(
+    state: State<'_, AppState>,
+    image_paths: Vec<String>,
+    policy: Option<ViewPolicy>,
+    optimize: Option<bool>,
+) -> Result<ApiResponse<Vec<BatchItemResult>>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "encrypt images")? {
+        return Ok(refusal);
+    }
+    if session_image_roots(&state)?.is_none() {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("not_online_go_online", "Not online. Please go online first."),
+            data: None,
+        });
+    }
+    let output_name = |image_path: &String| {
+        format!("encrypted_{}", Path::new(image_path).file_name().unwrap_or_default().to_string_lossy())
+    };
+    // Images with the same file name would be encrypted over each other
+    let mut output_names = HashMap::new();
+    for image_path in &image_paths {
+        *output_names.entry(output_name(image_path)).or_insert(0) += 1;
+    }
+    let problem = |image_path: &String| {
+        if !Path::new(image_path).is_file() {
+            Some(format!("File not found: {}", image_path))
+        } else if output_names[&output_name(image_path)] > 1 {
+            Some("Another selected image has the same file name".to_string())
+        } else {
+            None
+        }
+    };
+    if let Some(refusal) = validate_batch(&image_paths, String::clone, problem) {
+        return Ok(refusal);
+    }
+
+    let (policy, optimize) = (policy.unwrap_or_default(), optimize.unwrap_or(false));
+    let mut jobs = Vec::new();
+    let mut descriptions = Vec::new();
+    for image_path in &image_paths {
+        let (job, description) = encryption_job(&state, Path::new(image_path), ImageAnnotations::default(), policy)?;
+        jobs.push(job);
+        descriptions.push(description);
+    }
+
+    // One job per healthy server at a time, each retried on its own
+    let servers = load_encryption_servers();
+    let encrypted = encrypt_batch(&servers, jobs, BATCH_ENCRYPT_ATTEMPTS, |progress| {
+        emit_state_event(&state, StateEvent::EncryptionProgress(progress.clone()));
+    }).await;
+
+    let mut results = Vec::new();
+    for ((image_path, description), encrypted) in image_paths.into_iter().zip(descriptions).zip(encrypted) {
+        let saved = match encrypted {
+            Ok(carrier) => save_encrypted_copy(
+                &state,
+                Path::new(&image_path),
+                carrier,
+                &output_name(&image_path),
+                optimize,
+                description,
+            ).await,
+            Err(e) => Err(format!("{:#}", e)),
+        };
+        let (success, message, data) = match saved {
+            Ok(copy) => {
+                let message = match copy.optimized {
+                    Some(summary) => format!("Encrypted (carrier {})", summary),
+                    None => "Encrypted".to_string(),
+                };
+                (true, message, Some(copy.path.to_string_lossy().to_string()))
+            }
+            Err(e) => (false, e, None),
+        };
+        results.push(BatchItemResult { item: image_path, success, message, data });
+    }
+    Ok(batch_response("Encrypted", results))
+}
+
+/// Request several peers' images at once, answering with each request's ID
+#[tauri::command]
+async fn request_images(
+    app: tauri::AppHandle,
+    state: State<'_, AppState>,
+    requests: Vec<BatchImageRequest>,
+) -> Result<ApiResponse<Vec<BatchItemResult>>, String> {
+    let username = state.current_user()?;
+    let label = |req: &BatchImageRequest| format!("{}/{}", req.peer_username, req.image_id);
+    let problem = |req: &BatchImageRequest| {
+        if req.peer_username.trim().is_empty() || req.image_id.trim().is_empty() {
+            Some("Missing the peer or image ID".to_string())
+        } else if req.peer_username == username {
+            Some("That's your own image".to_string())
+        } else if req.views == 0 {
+            Some("Ask for at least one view".to_string())
+        } else {
+            None
+        }
+    };
+    if let Some(refusal) = validate_batch(&requests, label, problem) {
+        return Ok(refusal);
+    }
+
+    let results = run_batch(&app, requests, |app, req| async move {
+        let state = app.state::<AppState>();
+        let item = label(&req);
+        let outcome = leave_image_request(&state, req.peer_username, req.image_id, req.views, req.max_dimension).await;
+        let (success, message, data) = match outcome {
+            Ok(response) => (response.success, response.message.to_string(), response.data),
+            Err(e) => (false, e, None),
+        };
+        BatchItemResult { item, success, message, data }
+    }).await?;
+    Ok(batch_response("Requested", results))
+}
+
+// ============================================================================
+// CONNECTION DOCTOR
+// ============================================================================
+
+/// Check networking, port reachability, directory replicas, and encryption servers
+///
+/// Uses the given port, falling back to the one we're online with.
+#[tauri::command]
+async fn run_diagnostics(
+    port: Option<u16>,
+    state: State<'_, AppState>,
+) -> Result<ApiResponse<DiagnosticReport>, String> {
+    let port = match port {
+        Some(port) => port,
+        None => state.session()?.p2p_port
+            .ok_or("Enter a P2P port to check (or go online first)")?,
+    };
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    let encryption_servers = load_encryption_servers();
+
+    let report = diagnostics::run_diagnostics(port, &dir_servers, &encryption_servers).await;
+    let failed = report.checks().filter(|c| !c.ok).count();
+
+    Ok(ApiResponse {
+        success: true,
+        message: if failed == 0 {
+            msg!("all_checks_passed", "All checks passed")
+        } else {
+            msg!("checks_failed", "{failed} check(s) failed", failed = failed)
+        },
+        data: Some(report),
+    })
+}
+
+// ============================================================================
+// AUDIT LOG
+// ============================================================================
+
+/// File the audit log is exported to, next to the log itself
+const AUDIT_EXPORT_FILE: &str = "audit_export.json";
+
+/// Verify the outbound transfer audit log and export it next to the log
+#[tauri::command]
+async fn export_audit_log(state: State<'_, AppState>) -> Result<ApiResponse<AuditVerification>, String> {
+    let log_path = state.image_store.read().await.get_audit_log_path().cloned()
+        .ok_or("Not online. Please go online first.")?;
+    let output = log_path.with_file_name(AUDIT_EXPORT_FILE);
+
+    match audit_log::export_audit_log(&log_path, &output) {
+        Ok(verification) => Ok(ApiResponse {
+            success: verification.intact,
+            message: if verification.intact {
+                msg!("audit_log_exported", "Exported {entries} entries to {path}", entries = verification.entries, path = output.display())
+            } else {
+                msg!(
+                    "audit_log_tampered",
+                    "Audit log tampered with at line {line}: {problem}",
+                    line = verification.first_bad_line.unwrap_or_default(),
+                    problem = verification.problem.clone().unwrap_or_default(),
+                )
+            },
+            data: Some(verification),
+        }),
+        Err(e) => Ok(ApiResponse { success: false, message: e.to_string().into(), data: None }),
+    }
+}
+
+// ============================================================================
+// VIEWER-ONLY MODE
+// ============================================================================
+
+/// Refusal for owner-side commands while the session is viewer-only (None when allowed)
+fn viewer_only_refusal<T>(state: &AppState, action: &str) -> Result<Option<ApiResponse<T>>, String> {
+    if !*state.viewer_only.lock().map_err(|e| e.to_string())? {
+        return Ok(None);
+    }
+    Ok(Some(ApiResponse {
+        success: false,
+        message: msg!("viewer_only_refused", "Viewer-only mode is on: can't {action} in this session", action = action),
+        data: None,
+    }))
+}
+
+/// Switch viewer-only mode on or off
+#[tauri::command]
+async fn set_viewer_only(
+    state: State<'_, AppState>,
+    enabled: bool,
+) -> Result<ApiResponse<ViewerMode>, String> {
+    let mode = ViewerMode { enabled: true, locked: state.viewer_only_locked };
+    if !enabled && state.viewer_only_locked {
+        return Ok(ApiResponse {
+            success: false,
+            message: msg!("viewer_only_enforced", "Viewer-only mode is enforced by {env}=1", env = VIEWER_ONLY_ENV),
+            data: Some(mode),
+        });
+    }
+
+    *state.viewer_only.lock().map_err(|e| e.to_string())? = enabled;
+    Ok(ApiResponse {
+        success: true,
+        message: if enabled { msg!("viewer_only_on", "Viewer-only mode on") } else { msg!("viewer_only_off", "Viewer-only mode off") },
+        data: Some(ViewerMode { enabled, ..mode }),
+    })
+}
+
+#[tauri::command]
+async fn get_viewer_only(state: State<'_, AppState>) -> Result<ApiResponse<ViewerMode>, String> {
+    let enabled = *state.viewer_only.lock().map_err(|e| e.to_string())?;
+    Ok(ApiResponse {
+        success: true,
+        message: UserMessage::default(),
+        data: Some(ViewerMode { enabled, locked: state.viewer_only_locked }),
+    })
+}
+
+// ============================================================================
+// SYSTEM TRAY
+// ============================================================================
+
+const TRAY_ID: &str = "main";
+const TRAY_SHOW: &str = "show";
+const TRAY_PENDING: &str = "pending";
+const TRAY_GO_OFFLINE: &str = "go_offline";
+const TRAY_QUIT: &str = "quit";
+
+/// How often the tray's pending request count is refreshed
+const TRAY_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+/// What the tray shows while the window may be closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrayStatus {
+    pub is_online: bool,
+    pub username: Option<String>,
+    /// Requests waiting for our answer, ours and delegated (unset when offline or no directory answered)
+    pub pending_requests: Option<usize>,
+    /// Pending requests past the directory's reminder threshold, as of the last heartbeat
+    pub due_reminders: usize,
+    pub background_mode: bool,
+}
+
+/// Tray menu items whose labels follow the app's state
+struct TrayItems {
+    pending: MenuItem<Wry>,
+    go_offline: MenuItem<Wry>,
+}
+
+/// Requests still waiting for our answer, including those we answer as a delegate
+async fn pending_request_count(state: &AppState) -> Result<usize, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let mut count = 0;
+    for msg in [
+        DirectoryMessage::GetPendingRequests { username: username.clone() },
+        DirectoryMessage::GetDelegatedRequests { delegate: username.clone() },
+    ] {
+        match multicast_directory_message(&dir_servers, msg).await {
+            Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) => {
+                count += requests.iter().filter(|r| r.status == RequestStatus::Pending).count();
+            }
+            Ok(_) => return Err("Unexpected response".to_string()),
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Ok(count)
+}
+
+async fn tray_status(state: &AppState) -> Result<TrayStatus, String> {
+    let session = state.session()?;
+    let (is_online, username, due_reminders) = (session.is_online(), session.username, session.due_reminders);
+    let background_mode = *state.background_mode.lock().map_err(|e| e.to_string())?;
+    let pending_requests = if is_online { pending_request_count(state).await.ok() } else { None };
+    Ok(TrayStatus { is_online, username, pending_requests, due_reminders, background_mode })
+}
+
+/// Bring the tray menu and tooltip in line with `status`
+fn update_tray(app: &tauri::AppHandle, status: &TrayStatus) {
+    let pending = match (status.is_online, status.pending_requests) {
+        (false, _) => "Offline".to_string(),
+        (true, None) => "Pending requests: unknown".to_string(),
+        (true, Some(count)) if status.due_reminders > 0 => {
+            format!("Pending requests: {} ({} overdue)", count, status.due_reminders)
+        }
+        (true, Some(count)) => format!("Pending requests: {}", count),
+    };
+    if let Some(items) = app.try_state::<TrayItems>() {
+        let _ = items.pending.set_text(&pending);
+        let _ = items.go_offline.set_enabled(status.is_online);
+    }
+    if let Some(tray) = app.tray_by_id(TRAY_ID) {
+        let tooltip = match &status.username {
+            Some(username) if status.is_online => format!("P2P Image Sharing: {} ({})", username, pending.to_lowercase()),
+            _ => "P2P Image Sharing: offline".to_string(),
+        };
+        let _ = tray.set_tooltip(Some(tooltip));
+    }
+}
+
+async fn refresh_tray(app: tauri::AppHandle) {
+    let state = app.state::<AppState>();
+    match tray_status(&state).await {
+        Ok(status) => update_tray(&app, &status),
+        Err(e) => eprintln!("Failed to refresh tray: {}", e),
+    }
+}
+
+/// Refresh the tray without holding up the caller (its pending count asks the directory)
+fn spawn_tray_refresh(state: &AppState) {
+    let Ok(handle) = state.app_handle.lock() else {
+        return;
+    };
+    if let Some(handle) = handle.clone() {
+        tauri::async_runtime::spawn(refresh_tray(handle));
+    }
+}
+
+fn show_main_window(app: &tauri::AppHandle) {
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.show();
+        let _ = window.unminimize();
+        let _ = window.set_focus();
+    }
+}
+
+/// Add the tray icon and keep its pending request count current
+fn setup_tray(app: &tauri::App) -> tauri::Result<()> {
+    let show = MenuItem::with_id(app, TRAY_SHOW, "Show window", true, None::<&str>)?;
+    let pending = MenuItem::with_id(app, TRAY_PENDING, "Offline", false, None::<&str>)?;
+    let go_offline = MenuItem::with_id(app, TRAY_GO_OFFLINE, "Go offline", false, None::<&str>)?;
+    let quit = MenuItem::with_id(app, TRAY_QUIT, "Quit", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[
+        &show,
+        &PredefinedMenuItem::separator(app)?,
+        &pending,
+        &go_offline,
+        &PredefinedMenuItem::separator(app)?,
+        &quit,
+    ])?;
+    app.manage(TrayItems { pending, go_offline });
+
+    let mut tray = TrayIconBuilder::with_id(TRAY_ID)
+        .menu(&menu)
+        .tooltip("P2P Image Sharing: offline")
+        .on_menu_event(|app, event| match event.id().as_ref() {
+            TRAY_SHOW => show_main_window(app),
+            TRAY_GO_OFFLINE => {
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    let state = app.state::<AppState>();
+                    match go_offline(app.state::<AppState>()).await {
+                        Ok(_) => emit_state_event(&state, StateEvent::WentOffline),
+                        Err(e) => eprintln!("Failed to go offline from the tray: {}", e),
+                    }
+                });
+            }
+            TRAY_QUIT => {
+                let app = app.clone();
+                tauri::async_runtime::spawn(async move {
+                    // Unregister first so peers don't keep trying a server that's gone
+                    if app.state::<AppState>().session().is_ok_and(|session| session.is_online()) {
+                        let _ = go_offline(app.state::<AppState>()).await;
+                    }
+                    app.exit(0);
+                });
+            }
+            _ => {}
+        })
+        .on_tray_icon_event(|tray, event| {
+            if let TrayIconEvent::Click { button: MouseButton::Left, button_state: MouseButtonState::Up, .. } = event {
+                show_main_window(tray.app_handle());
+            }
+        });
+    if let Some(icon) = app.default_window_icon() {
+        tray = tray.icon(icon.clone());
+    }
+    tray.build(app)?;
+
+    let handle = app.handle().clone();
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(TRAY_REFRESH_INTERVAL).await;
+            if handle.state::<AppState>().session().is_ok_and(|session| session.is_online()) {
+                refresh_tray(handle.clone()).await;
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Keep serving with the window closed: closing it while online hides it to the tray instead
+#[tauri::command]
+async fn set_background_mode(
+    state: State<'_, AppState>,
+    enabled: bool,
+) -> Result<ApiResponse<TrayStatus>, String> {
+    *state.background_mode.lock().map_err(|e| e.to_string())? = enabled;
+    let status = tray_status(&state).await?;
+    Ok(ApiResponse {
+        success: true,
+        message: if enabled {
+            msg!("background_mode_on", "Closing the window now keeps sharing from the tray")
+        } else {
+            msg!("background_mode_off", "Closing the window now quits")
+        },
+        data: Some(status),
+    })
+}
+
+/// Online state, pending request count and background mode, as the tray shows them
+#[tauri::command]
+async fn get_tray_status(state: State<'_, AppState>) -> Result<ApiResponse<TrayStatus>, String> {
+    let status = tray_status(&state).await?;
+    spawn_tray_refresh(&state);
+    Ok(ApiResponse {
+        success: true,
+        message: UserMessage::default(),
+        data: Some(status),
+    })
+}
+
+// ============================================================================
+// IMAGE TRADES
+// ============================================================================
+
+/// Escrow our side of a trade, fetching it from our own P2P server with the other party's views embedded
+async fn deposit_trade_side(state: &AppState, username: &str, trade: &TradeProposal) -> Result<String, String> {
+    let own_addr = state.session()?.p2p_address
+        .ok_or("Go online to deposit trade images")?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    let (mine, theirs) = trade.sides_for(username).ok_or("Not part of this trade")?;
+
+    // The accepted trade's ID vouches for the views we embed for them
+    let encrypted_image = request_scaled_image_from_peer(&own_addr, &theirs.owner, &mine.image_id, mine.views, None, None, Some(&trade.trade_id)).await
+        .map_err(|e| format!("Failed to prepare {}: {}", mine.image_id, e))?;
+
+    let msg = DirectoryMessage::DepositTradeImage {
+        trade_id: trade.trade_id.clone(),
+        username: username.to_string(),
+        encrypted_image,
+        key: Some(state.registration_key()?),
+    };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::DepositTradeImageResponse { success: true, message, .. }) => Ok(message),
+        Ok(DirectoryMessage::DepositTradeImageResponse { message, .. }) => Err(message),
+        Ok(_) => Err("Unexpected response".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Look up one of our trades by ID
+async fn find_trade(dir_servers: &[String], username: &str, trade_id: &str) -> Result<TradeProposal, String> {
+    let msg = DirectoryMessage::GetTrades { username: username.to_string() };
+    match multicast_directory_message(dir_servers, msg).await {
+        Ok(DirectoryMessage::GetTradesResponse { trades }) => trades.into_iter()
+            .find(|t| t.trade_id == trade_id)
+            .ok_or_else(|| "Trade not found".to_string()),
+        Ok(_) => Err("Unexpected response".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Offer views on one of our images for views on one of a peer's, escrowing ours straight away
+#[tauri::command]
+async fn propose_trade(
+    state: State<'_, AppState>,
+    peer_username: String,
+    offered_image_id: String,
+    offered_views: u32,
+    requested_image_id: String,
+    requested_views: u32,
+) -> Result<ApiResponse<String>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "trade images")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::ProposeTrade {
+        from_user: username.clone(),
+        to_user: peer_username,
+        offered_image_id,
+        offered_views,
+        requested_image_id,
+        requested_views,
+    };
+    let trade_id = match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::ProposeTradeResponse { success: true, trade_id, .. }) => trade_id,
+        Ok(DirectoryMessage::ProposeTradeResponse { message, .. }) => {
+            return Ok(ApiResponse { success: false, message: message.into(), data: None });
+        }
+        Ok(_) => return Ok(ApiResponse { success: false, message: msg!("unexpected_response", "Unexpected response"), data: None }),
+        Err(e) => return Ok(ApiResponse { success: false, message: msg!("failed_to_propose_trade", "Failed to propose trade: {error}", error = e), data: None }),
+    };
+
+    let deposited = match find_trade(&dir_servers, &username, &trade_id).await {
+        Ok(trade) => deposit_trade_side(&state, &username, &trade).await,
+        Err(e) => Err(e),
+    };
+    Ok(ApiResponse {
+        success: true,
+        message: match deposited {
+            Ok(message) => msg!("trade_proposed", "Trade proposed. {detail}", detail = message),
+            Err(e) => msg!("trade_proposed_not_deposited", "Trade proposed, but your image isn't deposited yet: {error}", error = e),
+        },
+        data: Some(trade_id),
+    })
+}
+
+#[tauri::command]
+async fn get_trades(state: State<'_, AppState>) -> Result<ApiResponse<Vec<TradeInfo>>, String> {
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::GetTrades { username: username.clone() };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::GetTradesResponse { trades }) => {
+            let infos: Vec<TradeInfo> = trades.iter()
+                .filter_map(|trade| {
+                    let (mine, theirs) = trade.sides_for(&username)?;
+                    Some(TradeInfo {
+                        trade_id: trade.trade_id.clone(),
+                        peer: theirs.owner.clone(),
+                        give_image_id: mine.image_id.clone(),
+                        give_views: mine.views,
+                        give_deposited: mine.deposit_sha256.is_some(),
+                        get_image_id: theirs.image_id.clone(),
+                        get_views: theirs.views,
+                        get_deposited: theirs.deposit_sha256.is_some(),
+                        status: format!("{:?}", trade.status),
+                        incoming: trade.counterparty.owner == username,
+                    })
+                })
+                .collect();
+            Ok(ApiResponse {
+                success: true,
+                message: msg!("found_trades", "Found {count} trades", count = infos.len()),
+                data: Some(infos),
+            })
+        }
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response", "Unexpected response"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("failed_to_get_trades", "Failed to get trades: {error}", error = e), data: None }),
+    }
+}
+
+/// Accept (and deposit our side of) or reject a trade proposed to us
+#[tauri::command]
+async fn respond_to_trade(
+    state: State<'_, AppState>,
+    trade_id: String,
+    accept: bool,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "trade images")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::RespondToTrade { trade_id, username: username.clone(), accept, key: Some(state.registration_key()?) };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::RespondToTradeResponse { success: true, message, trade }) => {
+            let message = match trade.filter(|_| accept) {
+                Some(trade) => match deposit_trade_side(&state, &username, &trade).await {
+                    Ok(deposit_message) => deposit_message.into(),
+                    Err(e) => msg!("trade_deposit_failed", "{detail} Deposit failed: {error}", detail = message, error = e),
+                },
+                None => message.into(),
+            };
+            Ok(ApiResponse { success: true, message, data: None })
+        }
+        Ok(DirectoryMessage::RespondToTradeResponse { message, .. }) => {
+            Ok(ApiResponse { success: false, message: message.into(), data: None })
+        }
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response", "Unexpected response"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("failed_to_respond", "Failed to respond: {error}", error = e), data: None }),
+    }
+}
+
+/// Deposit (or re-deposit) our side of a trade
+#[tauri::command]
+async fn deposit_trade(
+    state: State<'_, AppState>,
+    trade_id: String,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "trade images")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let result = match find_trade(&dir_servers, &username, &trade_id).await {
+        Ok(trade) => deposit_trade_side(&state, &username, &trade).await,
+        Err(e) => Err(e),
+    };
+    Ok(match result {
+        Ok(message) => ApiResponse { success: true, message: message.into(), data: None },
+        Err(message) => ApiResponse { success: false, message: message.into(), data: None },
+    })
+}
+
+#[tauri::command]
+async fn cancel_trade(
+    state: State<'_, AppState>,
+    trade_id: String,
+) -> Result<ApiResponse<()>, String> {
+    if let Some(refusal) = viewer_only_refusal(&state, "trade images")? {
+        return Ok(refusal);
+    }
+    let username = state.current_user()?;
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let msg = DirectoryMessage::CancelTrade { trade_id, username, key: Some(state.registration_key()?) };
+    match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::CancelTradeResponse { success, message }) => {
+            Ok(ApiResponse { success, message: message.into(), data: None })
+        }
+        Ok(_) => Ok(ApiResponse { success: false, message: msg!("unexpected_response", "Unexpected response"), data: None }),
+        Err(e) => Ok(ApiResponse { success: false, message: msg!("failed_to_cancel_trade", "Failed to cancel trade: {error}", error = e), data: None }),
+    }
+}
+
+// ============================================================================
+// RECEIVED STORAGE
+// ============================================================================
+
+/// How much space received images use, and what could be evicted automatically
+#[tauri::command]
+async fn get_storage_usage(state: State<'_, AppState>) -> Result<ApiResponse<StorageUsage>, String> {
+    let username = state.current_user()?;
+    let store = state.image_store.read().await;
+    let received_dir = store.get_received_images_dir().ok_or("Go online to locate received images")?;
+
+    let usage = received_storage_usage(received_dir, &username, store.get_received_quota_bytes());
+    Ok(ApiResponse {
+        success: true,
+        message: match usage.quota_bytes {
+            Some(quota) => msg!("storage_used", "{used_kb} of {quota_kb} KB used", used_kb = usage.used_bytes / 1024, quota_kb = quota / 1024),
+            None => msg!("storage_used_no_quota", "{used_kb} KB used (no quota)", used_kb = usage.used_bytes / 1024),
+        },
+        data: Some(usage),
+    })
+}
+
+/// Set (or clear) the received images quota, evicting straight away if we're already over it
+#[tauri::command]
+async fn set_storage_quota(
+    state: State<'_, AppState>,
+    max_mb: Option<u64>,
+) -> Result<ApiResponse<Vec<String>>, String> {
+    let username = state.session()?.username;
+    let quota = max_mb.map(|mb| mb * 1024 * 1024);
+
+    let mut store = state.image_store.write().await;
+    store.set_received_quota_bytes(quota);
+
+    let (Some(quota), Some(username), Some(received_dir)) = (quota, username, store.get_received_images_dir()) else {
+        return Ok(ApiResponse {
+            success: true,
+            message: match max_mb {
+                Some(mb) => msg!("received_quota_set", "Received storage quota set to {quota_mb} MB", quota_mb = mb),
+                None => msg!("received_quota_removed", "Received storage quota removed"),
+            },
+            data: Some(Vec::new()),
+        });
+    };
+
+    match make_room_for_received(received_dir, &username, quota, 0, Path::new("")) {
+        Ok(evicted) => Ok(ApiResponse {
+            success: true,
+            message: msg!("received_quota_set_evicted", "Received storage quota set to {quota_mb} MB ({evicted} images evicted)", quota_mb = quota / 1024 / 1024, evicted = evicted.len()),
+            data: Some(evicted.iter().map(|p| p.to_string_lossy().to_string()).collect()),
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("received_quota_eviction_failed", "Quota set to {quota_mb} MB: {error}", quota_mb = quota / 1024 / 1024, error = e),
+            data: Some(Vec::new()),
+        }),
+    }
+}
+
+/// How images delivered to us are named on disk
+#[tauri::command]
+async fn get_received_name_policy(state: State<'_, AppState>) -> Result<ApiResponse<p2p_protocol::ReceivedNamePolicy>, String> {
+    let store = state.image_store.read().await;
+    let received_dir = store.get_received_images_dir().ok_or("Go online to locate received images")?;
+    let policy = p2p_protocol::load_name_policy(received_dir);
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("received_name_policy", "Received images are named: {policy}", policy = policy),
+        data: Some(policy),
+    })
+}
+
+/// Change how new deliveries are named; images already held keep their names
+#[tauri::command]
+async fn set_received_name_policy(
+    state: State<'_, AppState>,
+    policy: p2p_protocol::ReceivedNamePolicy,
+) -> Result<ApiResponse<()>, String> {
+    let store = state.image_store.read().await;
+    let received_dir = store.get_received_images_dir().ok_or("Go online to locate received images")?;
+    match p2p_protocol::save_name_policy(received_dir, policy) {
+        Ok(()) => Ok(ApiResponse {
+            success: true,
+            message: msg!("received_name_policy_set", "New deliveries will be named: {policy}", policy = policy),
+            data: None,
+        }),
+        Err(e) => Ok(ApiResponse {
+            success: false,
+            message: msg!("failed_to_set_name_policy", "Failed to save the naming setting: {error}", error = e),
+            data: None,
+        }),
+    }
+}
+
+/// What the carrier check found when we last went online: damaged files quarantined or restored
+#[tauri::command]
+async fn get_integrity_report(state: State<'_, AppState>) -> Result<ApiResponse<IntegrityReport>, String> {
+    let report = state.integrity_report.lock().map_err(|e| e.to_string())?.clone()
+        .ok_or("Go online to check your images")?;
+    Ok(ApiResponse {
+        success: true,
+        message: UserMessage::untranslated(report.summary()),
+        data: Some(report),
+    })
+}
+
+/// Daily P2P traffic totals (per peer) and the current cap, for charting
+#[tauri::command]
+async fn get_bandwidth_stats() -> Result<ApiResponse<BandwidthStats>, String> {
+    let stats = bandwidth::bandwidth_stats();
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("bandwidth_used_today", "{used_mb} MB used today", used_mb = format!("{:.1}", stats.today_bytes as f64 / 1_048_576.0)),
+        data: Some(stats),
+    })
+}
+
+/// Timings of recent encryption jobs by phase and server, for sizing the encryption cluster
+#[tauri::command]
+async fn get_encryption_stats() -> Result<ApiResponse<EncryptionStats>, String> {
+    let stats = encryption_pool::encryption_stats();
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("encryption_stats", "{summary}", summary = stats.summary()),
+        data: Some(stats),
+    })
+}
+
+/// Connections our P2P server is serving and queueing now, and how many it has turned away
+#[tauri::command]
+async fn get_p2p_server_stats() -> Result<ApiResponse<ServerLoadStats>, String> {
+    let stats = p2p_protocol::p2p_server_stats();
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("server_load", "{active}/{max} connections active, {queued} queued, {rejected} turned away", active = stats.active, max = stats.max_concurrent, queued = stats.queued, rejected = stats.rejected_busy),
+        data: Some(stats),
+    })
+}
+
+/// Set (or clear) the MB of P2P traffic per UTC day after which image transfers are deferred
+#[tauri::command]
+async fn set_bandwidth_cap(max_mb: Option<u64>) -> Result<ApiResponse<()>, String> {
+    bandwidth::set_daily_cap(max_mb.map(|mb| mb * 1024 * 1024));
+    Ok(ApiResponse {
+        success: true,
+        message: match max_mb {
+            Some(mb) => msg!("bandwidth_cap_set", "Daily bandwidth cap set to {cap_mb} MB", cap_mb = mb),
+            None => msg!("bandwidth_cap_removed", "Daily bandwidth cap removed"),
+        },
+        data: None,
+    })
+}
+
+// ============================================================================
+// PROTOCOL TRACE
+// ============================================================================
+
+/// Start or stop recording recent directory/P2P messages for bug reports
+///
+/// Enabling clears anything recorded earlier.
+#[tauri::command]
+async fn enable_protocol_trace(
+    enabled: bool,
+    capacity: Option<usize>,
+) -> Result<ApiResponse<()>, String> {
+    if enabled {
+        let capacity = capacity.unwrap_or(protocol_trace::DEFAULT_TRACE_CAPACITY);
+        protocol_trace::enable_trace(capacity);
+        Ok(ApiResponse {
+            success: true,
+            message: msg!("trace_started", "Recording the last {capacity} messages", capacity = capacity.max(1)),
+            data: None,
+        })
+    } else {
+        protocol_trace::disable_trace();
+        Ok(ApiResponse {
+            success: true,
+            message: msg!("protocol_trace_stopped", "Protocol trace stopped"),
+            data: None,
+        })
+    }
+}
+
+/// Recorded messages, oldest first (secrets redacted, image data truncated)
+#[tauri::command]
+async fn get_protocol_trace() -> Result<ApiResponse<Vec<TraceEntry>>, String> {
+    let entries = protocol_trace::trace_snapshot();
+    Ok(ApiResponse {
+        success: true,
+        message: if protocol_trace::trace_enabled() {
+            msg!("trace_recorded", "{count} messages recorded", count = entries.len())
+        } else {
+            msg!("trace_recorded_off", "{count} messages recorded (trace is off)", count = entries.len())
+        },
+        data: Some(entries),
+    })
+}
+
+// ============================================================================
+// STATE EVENTS
+// ============================================================================
+
+/// Tauri event carrying every `StateEvent`
+const STATE_EVENT: &str = "state-event";
+
+/// An image as added to one of the frontend's lists
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "collection", content = "image", rename_all = "snake_case")]
+pub enum ImageEntry {
+    Local(LocalImage),
+    Encrypted(LocalImage),
+    Received(ReceivedImage),
+}
+
+/// Incremental state change pushed to the frontend, so it needn't re-fetch whole lists
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StateEvent {
+    ImageAdded(ImageEntry),
+    /// Removed from whichever list holds it
+    ImageRemoved { image_id: String, file_path: String },
+    RequestStatusChanged { request_id: String, status: String },
+    PeerStatusChanged { username: String, status: String },
+    /// The directory server list was reordered or grew after a health check
+    DirectoryServersChanged { servers: Vec<String> },
+    /// An owner revoked or wiped a received image, so any open view of it must close
+    ViewRevoked { from_owner: String, image_id: String, wiped: bool },
+    /// Heartbeats failed and we registered again on our own; says what was brought back in sync
+    Reconnected { attempts: u32, offline_secs: u64, shared_images: usize, pending_updates: Vec<String> },
+    /// A batch encryption moved on; sent as it starts and as each image finishes
+    EncryptionProgress(EncryptionProgress),
+    /// We went offline from outside the window (the tray menu)
+    WentOffline,
+    /// More of our pending requests have waited past the directory's reminder threshold
+    RemindersDue { count: usize },
+    /// Checking a received image with its owner found a revocation or quota change we'd missed
+    GrantReconciled { from_owner: String, file_path: String, views_remaining: u32, revoked: bool },
+    /// A watched peer shared an image, or changed one it shares
+    PeerImageShared { username: String, image: ImageInfoJson },
+    /// A watched peer stopped sharing an image (or hid it from us)
+    PeerImageUnshared { username: String, image_id: String },
+    /// The background scan started by going online read every file and checked the carriers
+    CatalogScanned { files: usize, shared_images: usize },
+}
+
+/// Everything the frontend tracks incrementally, to start from before applying events
+#[derive(Debug, Clone, Serialize)]
+pub struct StateSnapshot {
+    pub username: Option<String>,
+    pub is_online: bool,
+    pub local_images: Vec<LocalImage>,
+    pub received_images: Vec<ReceivedImage>,
+    pub peer_statuses: HashMap<String, String>,
+    pub request_statuses: HashMap<String, String>,
+}
+
+fn emit_state_event(state: &AppState, event: StateEvent) {
+    let Ok(handle) = state.app_handle.lock() else {
+        return;
+    };
+    if let Some(handle) = handle.as_ref() {
+        if let Err(e) = handle.emit(STATE_EVENT, &event) {
+            eprintln!("Failed to emit {:?}: {}", event, e);
+        }
+    }
+}
+
+/// Announce the images in `new` that `old` lacks and those `old` has that `new` lacks (matched by path)
+fn announce_image_changes<T: Clone>(
+    state: &AppState,
+    old: &[T],
+    new: &[T],
+    key: impl Fn(&T) -> (&str, &str),
+    entry: impl Fn(T) -> ImageEntry,
+) {
+    let paths = |list: &[T]| list.iter().map(|img| key(img).1.to_string()).collect::<HashSet<String>>();
+    let (old_paths, new_paths) = (paths(old), paths(new));
+    for img in old.iter().filter(|img| !new_paths.contains(key(img).1)) {
+        let (image_id, file_path) = key(img);
+        emit_state_event(state, StateEvent::ImageRemoved {
+            image_id: image_id.to_string(),
+            file_path: file_path.to_string(),
+        });
+    }
+    for img in new.iter().filter(|img| !old_paths.contains(key(img).1)) {
+        emit_state_event(state, StateEvent::ImageAdded(entry(img.clone())));
+    }
+}
+
+/// Close views of received images as their owners revoke or wipe them
+async fn run_revocation_listener(app: tauri::AppHandle, mut revocations: mpsc::UnboundedReceiver<AccessRevoked>) {
+    while let Some(AccessRevoked { from_owner, image_id, wiped }) = revocations.recv().await {
+        let state = app.state::<AppState>();
+        eprintln!("🚫 {} revoked access to '{}' - closing any open view", from_owner, image_id);
+
+        // The legacy viewer left the decoded image on disk
+        let key = (from_owner.clone(), image_id.clone());
+        if let Some(view_file) = state.legacy_views.lock().ok().and_then(|mut views| views.remove(&key)) {
+            let _ = fs::remove_file(view_file);
+        }
+
+        let is_revoked = |img: &ReceivedImage| img.from_owner == from_owner && img.image_id == image_id;
+        let wiped_images = match state.received_images.lock() {
+            Ok(mut received) if wiped => {
+                let (gone, kept): (Vec<ReceivedImage>, Vec<ReceivedImage>) = received.drain(..).partition(is_revoked);
+                *received = kept;
+                gone
+            }
+            Ok(mut received) => {
+                received.iter_mut().filter(|img| is_revoked(img)).for_each(|img| img.views_remaining = 0);
+                Vec::new()
+            }
+            Err(_) => Vec::new(),
+        };
+
+        emit_state_event(&state, StateEvent::ViewRevoked { from_owner: from_owner.clone(), image_id: image_id.clone(), wiped });
+        for img in wiped_images {
+            emit_state_event(&state, StateEvent::ImageRemoved { image_id: img.image_id, file_path: img.file_path });
+        }
+    }
+}
+
+/// Ask the owners of received images we can still view whether our grants still stand
+///
+/// Run once after going online. Owners who are offline or too old to answer are skipped
+/// until next time.
+async fn verify_received_grants(app: tauri::AppHandle, received_dir: PathBuf) {
+    let state = app.state::<AppState>();
+    let Ok(username) = state.current_user() else {
+        return;
+    };
+    let viewable = scan_received_images(&received_dir, Some(&username), false, ReceivedSort::Owner, false)
+        .into_iter()
+        .filter(|img| img.views_remaining > 0);
+
+    for img in viewable {
+        let owner = match resolve_peer(&state, &img.from_owner).await {
+            Ok(Some(owner)) if owner.status == UserStatus::Online && owner.supports(CAP_GRANT_VERIFY) => owner,
+            Ok(_) => continue,
+            Err(e) => {
+                eprintln!("Could not look up {} to verify {}: {}", img.from_owner, img.file_name, e);
+                continue;
+            }
+        };
+
+        let views_remaining = match reconcile_received_grant(&owner.p2p_address, Path::new(&img.file_path), &username).await {
+            Ok(GrantReconciliation::Revoked) => 0,
+            Ok(GrantReconciliation::Updated { views }) => views,
+            Ok(GrantReconciliation::UpToDate | GrantReconciliation::NotShared) => continue,
+            Err(e) => {
+                eprintln!("Could not verify our grant for {} with {}: {:#}", img.file_name, img.from_owner, e);
+                continue;
+            }
+        };
+        eprintln!("🔄 {} now grants {} view(s) of {}", img.from_owner, views_remaining, img.file_name);
+
+        if let Ok(mut received) = state.received_images.lock() {
+            received.iter_mut()
+                .filter(|entry| entry.file_path == img.file_path)
+                .for_each(|entry| entry.views_remaining = views_remaining);
+        }
+        emit_state_event(&state, StateEvent::GrantReconciled {
+            from_owner: img.from_owner,
+            file_path: img.file_path,
+            views_remaining,
+            revoked: views_remaining == 0,
+        });
+    }
+}
+
+fn local_entry(image: LocalImage) -> ImageEntry {
+    if image.is_encrypted {
+        ImageEntry::Encrypted(image)
+    } else {
+        ImageEntry::Local(image)
+    }
+}
+
+/// Replace the local image list, announcing what changed
+fn store_local_images(state: &AppState, images: Vec<LocalImage>) -> Result<(), String> {
+    let old = std::mem::replace(&mut *state.local_images.lock().map_err(|e| e.to_string())?, images.clone());
+    announce_image_changes(state, &old, &images, |img| (&img.image_id, &img.file_path), local_entry);
+    Ok(())
+}
+
+/// Replace the received image list, announcing what changed
+fn store_received_images(state: &AppState, images: Vec<ReceivedImage>) -> Result<(), String> {
+    let old = std::mem::replace(&mut *state.received_images.lock().map_err(|e| e.to_string())?, images.clone());
+    announce_image_changes(state, &old, &images, |img| (&img.image_id, &img.file_path), ImageEntry::Received);
+    Ok(())
+}
+
+/// Announce a peer's status if it differs from the last one announced
+fn note_peer_status(state: &AppState, username: &str, status: String) {
+    let Ok(mut statuses) = state.peer_statuses.lock() else {
+        return;
+    };
+    if statuses.get(username) != Some(&status) {
+        statuses.insert(username.to_string(), status.clone());
+        drop(statuses);
+        emit_state_event(state, StateEvent::PeerStatusChanged { username: username.to_string(), status });
+    }
+}
+
+/// Announce a request's status if it differs from the last one announced
+fn note_request_status(state: &AppState, request_id: &str, status: String) {
+    let Ok(mut statuses) = state.request_statuses.lock() else {
+        return;
+    };
+    if statuses.get(request_id) != Some(&status) {
+        statuses.insert(request_id.to_string(), status.clone());
+        drop(statuses);
+        emit_state_event(state, StateEvent::RequestStatusChanged { request_id: request_id.to_string(), status });
+    }
+}
+
+/// Current state for the frontend to hydrate from before listening for `state-event`
+#[tauri::command]
+async fn get_state_snapshot(state: State<'_, AppState>) -> Result<ApiResponse<StateSnapshot>, String> {
+    let session = state.session()?;
+    let snapshot = StateSnapshot {
+        username: session.username,
+        is_online: session.phase == SessionPhase::Online,
+        local_images: state.local_images.lock().map_err(|e| e.to_string())?.clone(),
+        received_images: state.received_images.lock().map_err(|e| e.to_string())?.clone(),
+        peer_statuses: state.peer_statuses.lock().map_err(|e| e.to_string())?.clone(),
+        request_statuses: state.request_statuses.lock().map_err(|e| e.to_string())?.clone(),
+    };
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("state_snapshot", "State snapshot"),
+        data: Some(snapshot),
+    })
+}
+
+// ============================================================================
+// BACKGROUND JOBS
+// ============================================================================
+
+/// How often the job worker looks for jobs that are due
+const JOB_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How often the job worker asks the directory for accepted requests still awaiting delivery
+const UNDELIVERED_GRANT_SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Delivery jobs run at once, so several to the same peer can share one bundle
+const CONCURRENT_DELIVERY_JOBS: usize = 8;
+
+/// Queue a side effect to run (and be retried) in the background
+fn enqueue_job(state: &AppState, kind: JobKind) -> Result<(), String> {
+    eprintln!("Queued background job: {}", kind.describe());
+    state.jobs.lock().map_err(|e| e.to_string())?.enqueue(kind);
+    Ok(())
+}
+
+/// Run due jobs until told to stop
+async fn run_job_worker(app: tauri::AppHandle, mut shutdown_rx: mpsc::Receiver<()>) {
+    let mut last_sweep: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(JOB_POLL_INTERVAL) => {}
+            _ = shutdown_rx.recv() => break,
+        }
+
+        let state = app.state::<AppState>();
+        if last_sweep.is_none_or(|at| at.elapsed() >= UNDELIVERED_GRANT_SWEEP_INTERVAL) {
+            last_sweep = Some(Instant::now());
+            requeue_undelivered_grants(&state).await;
+        }
+        let mut deliveries = tokio::task::JoinSet::new();
+        loop {
+            let due = match state.jobs.lock() {
+                Ok(mut jobs) => jobs.next_due(SystemTime::now()),
+                Err(_) => None,
+            };
+            let Some((id, kind)) = due else { break };
+            let is_delivery = matches!(kind, JobKind::DeliverGrant { .. } | JobKind::DeliverPermissionUpdate { .. });
+            if !is_delivery {
+                let result = run_job(&state, kind).await;
+                if let Ok(mut jobs) = state.jobs.lock() {
+                    jobs.finish(id, result);
+                }
+                continue;
+            }
+            if deliveries.len() >= CONCURRENT_DELIVERY_JOBS {
+                deliveries.join_next().await;
+            }
+            let app = app.clone();
+            deliveries.spawn(async move {
+                let state = app.state::<AppState>();
+                let result = run_job(&state, kind).await;
+                if let Ok(mut jobs) = state.jobs.lock() {
+                    jobs.finish(id, result);
+                }
+            });
+        }
+        while deliveries.join_next().await.is_some() {}
+    }
+}
+
+async fn run_job(state: &AppState, kind: JobKind) -> Result<String, String> {
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+    match kind {
+        JobKind::DeliverGrant { target_user, image_id, views, max_dimension, owner: Some(owner), request_id, grant_token } => {
+            // The owner's peer holds the image; it records us as the approver
+            let username = state.current_user()?;
+            let owner_addr = match multicast_directory_message(&dir_servers, DirectoryMessage::QueryUser { username: owner.clone(), requesting_user: None }).await {
+                Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) if user.status == UserStatus::Online => user.p2p_address,
+                Ok(DirectoryMessage::QueryUserResponse { .. }) => return Err(format!("{} is offline", owner)),
+                Ok(_) => return Err("Unexpected response from directory service".to_string()),
+                Err(e) => return Err(format!("Failed to find {}: {}", owner, e)),
+            };
+            let image = request_scaled_image_from_peer(&owner_addr, &target_user, &image_id, views, max_dimension, Some(&username), grant_token.as_deref()).await
+                .map_err(|e| format!("Failed to fetch {}'s image for delivery: {}", owner, e))?;
+            let delivered = deliver_or_store_as(state, &owner, &target_user, &image_id, views, image).await?;
+            finish_grant_delivery(state, &dir_servers, &username, request_id.as_deref()).await;
+            Ok(delivered)
+        }
+        JobKind::DeliverGrant { target_user, image_id, views, max_dimension, owner: None, request_id, grant_token } => {
+            // Fetch the image from our P2P server with the REQUESTING user's name
+            // so the quota gets embedded for them, not the owner
+            let own_addr = state.session()?.p2p_address
+                .ok_or("P2P server is not running")?;
+            let image = request_scaled_image_from_peer(&own_addr, &target_user, &image_id, views, max_dimension, None, grant_token.as_deref()).await
+                .map_err(|e| format!("Failed to fetch image for delivery: {}", e))?;
+            let delivered = deliver_or_store(state, &target_user, &image_id, views, image).await?;
+            let username = state.current_user()?;
+            finish_grant_delivery(state, &dir_servers, &username, request_id.as_deref()).await;
+            Ok(delivered)
+        }
+        JobKind::DeliverPermissionUpdate { target_user, image_id, new_quota } => {
+            let image_path = find_owned_image(state, &image_id).await?
+                .ok_or_else(|| format!("Encrypted image '{}' not found in any share root", image_id))?;
+            let image = fs::read(&image_path).map_err(|e| format!("Failed to read updated image: {}", e))?;
+            deliver_or_store(state, &target_user, &image_id, new_quota, image).await
+        }
+        JobKind::UpdateSharedImages { shared_images } => {
+            let username = state.current_user()?;
+            let update_msg = DirectoryMessage::UpdateSharedImages { username, shared_images };
+            match multicast_directory_message(&dir_servers, update_msg).await {
+                Ok(DirectoryMessage::UpdateResponse { success: true, message }) => Ok(message),
+                Ok(DirectoryMessage::UpdateResponse { message, .. }) => Err(message),
+                Ok(_) => Err("Unexpected response from directory service".to_string()),
+                Err(e) => Err(format!("Failed to update directory service: {}", e)),
+            }
+        }
+        JobKind::ReportAccessDenial { denial } => report_access_denial(&dir_servers, denial).await,
+        JobKind::EncryptImage { image_path, annotations, output_name } => {
+            match encrypt_into_share_root(state, &image_path, annotations, ViewPolicy::default(), &output_name, false).await? {
+                Some(copy) => Ok(format!("Encrypted to {}", copy.path.display())),
+                None => Err("All encryption servers failed".to_string()),
+            }
+        }
+    }
+}
+
+/// Tell the directory an accepted request's image went out, so it stops asking for a retry
+async fn mark_request_delivered(state: &AppState, dir_servers: &[String], username: &str, request_id: &str) -> Result<(), String> {
+    let msg = DirectoryMessage::MarkRequestDelivered {
+        request_id: request_id.to_string(),
+        username: username.to_string(),
+        key: Some(state.registration_key()?),
+    };
+    match multicast_directory_message(dir_servers, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success: true, .. }) => Ok(()),
+        Ok(DirectoryMessage::UpdateResponse { message, .. }) => Err(message),
+        Ok(_) => Err("Unexpected response from directory service".to_string()),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+/// Mark a grant job's request delivered, leaving it to the next sweep if the directory can't be told
+async fn finish_grant_delivery(state: &AppState, dir_servers: &[String], username: &str, request_id: Option<&str>) {
+    if let Some(request_id) = request_id {
+        if let Err(e) = mark_request_delivered(state, dir_servers, username, request_id).await {
+            eprintln!("Failed to mark request {} delivered: {}", request_id, e);
+        }
+    }
+}
+
+/// Queue delivery for accepted requests the directory says never got their image
+///
+/// A request whose job already succeeded (or was replaced by a later quota change) is just
+/// marked delivered again; one whose job is queued or gave up is left alone (failed jobs can
+/// be retried from the job list).
+async fn requeue_undelivered_grants(state: &AppState) {
+    let (Ok(Session { username: Some(username), key, .. }), Ok(dir_servers)) = (
+        state.session(),
+        state.directory_servers.lock().map(|d| d.clone()),
+    ) else {
+        return;
+    };
+    let msg = DirectoryMessage::GetUndeliveredGrants { owner: username.clone(), key };
+    let requests = match multicast_directory_message(&dir_servers, msg).await {
+        Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) => requests,
+        _ => return,
+    };
+
+    for req in requests {
+        let status = match state.jobs.lock() {
+            Ok(jobs) => jobs.delivery_status(&req.request_id),
+            Err(_) => return,
+        };
+        match status {
+            Some(JobStatus::Succeeded) => finish_grant_delivery(state, &dir_servers, &username, Some(&req.request_id)).await,
+            Some(_) => {}
+            None => {
+                let owner = (req.to_user != username).then(|| req.to_user.clone());
+                let _ = enqueue_job(state, JobKind::DeliverGrant {
+                    target_user: req.from_user.clone(),
+                    image_id: req.image_id.clone(),
+                    views: req.views_to_grant(),
+                    max_dimension: req.requested_max_dimension,
+                    owner,
+                    request_id: Some(req.request_id),
+                    grant_token: req.grant_token,
+                });
+            }
+        }
+    }
+}
+
+/// Deliver an image to a user, storing it at the directory if they can't be reached
+async fn deliver_or_store(
+    state: &AppState,
+    target_user: &str,
+    image_id: &str,
+    views: u32,
+    image: Vec<u8>,
+) -> Result<String, String> {
+    let username = state.current_user()?;
+    deliver_or_store_as(state, &username, target_user, image_id, views, image).await
+}
+
+/// `deliver_or_store` for an image `owner` holds, when we approved it as their delegate
+async fn deliver_or_store_as(
+    state: &AppState,
+    owner: &str,
+    target_user: &str,
+    image_id: &str,
+    views: u32,
+    image: Vec<u8>,
+) -> Result<String, String> {
+    let dir_servers = state.directory_servers.lock().map_err(|e| e.to_string())?.clone();
+
+    let deliver = |target: UserEntry| {
+        let deliver_msg = P2PMessage::DeliverImage {
+            from_owner: owner.to_string(),
+            image_id: image_id.to_string(),
+            requested_views: views,
+            encrypted_image: image.clone(),
+            sha256: target.supports(CAP_CHECKSUMS).then(|| sha256_hex(&image)),
+            grant_seq: p2p_protocol::embedded_grant_seq(&image, target_user),
+        };
+        async move {
+            if target.status != UserStatus::Online {
+                bail!("{} is offline", target.username);
+            }
+            // Deliveries to the same peer close together share one connection
+            let response = if target.supports(CAP_DELIVERY_BUNDLES) {
+                deliver_image(&target.p2p_address, deliver_msg).await
+            } else {
+                send_p2p_message(&target.p2p_address, deliver_msg).await
+            };
+            let accepted = matches!(response, Ok(P2PMessage::DeliverImageResponse { success: true, .. }));
+            // A full disk on their side doesn't count against the peer
+            if !matches!(response, Ok(P2PMessage::StorageFull { .. })) {
+                reputation::record_delivery(&target.username, accepted);
+            }
+            if views == 0 && matches!(response, Ok(P2PMessage::DeliverImageResponse { .. })) {
+                reputation::record_revocation(&target.username, accepted);
+            }
+            match response? {
+                P2PMessage::DeliverImageResponse { success: true, message, .. } => Ok(message),
+                P2PMessage::DeliverImageResponse { success: false, message, .. } => bail!("delivery failed: {}", message),
+                P2PMessage::StorageFull { message, .. } => bail!("{} is low on disk space: {}", target.username, message),
+                _ => bail!("unexpected response"),
+            }
+        }
+    };
+
+    let outcome = resolve_and_send(state, target_user, deliver).await;
+    let (delivered, detail) = match outcome {
+        Ok(Some(message)) => (true, message),
+        Ok(None) => (false, format!("{} not found", target_user)),
+        Err(e) => (false, e),
+    };
+    audit_delivery(state, target_user, image_id, views, delivered, detail.clone()).await;
+    if delivered {
+        return Ok(detail);
+    }
+
+    let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
+        from_owner: owner.to_string(),
+        target_user: target_user.to_string(),
+        image_id: image_id.to_string(),
+        new_quota: views,
+        embedded_image: Some(image),
+        wipe: false,
+        ttl_secs: None,
+    };
+    match multicast_directory_message(&dir_servers, pending_msg).await {
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, .. }) => {
+            Ok(format!("Could not deliver ({}), stored for when {} is online", detail, target_user))
+        }
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { message, .. }) => {
+            Err(format!("Could not deliver ({}) or store the update: {}", detail, message))
+        }
+        Ok(_) => Err("Unexpected response from directory service".to_string()),
+        Err(e) => Err(format!("Could not deliver ({}) or reach the directory: {}", detail, e)),
+    }
+}
+
+/// Queued, running and recently finished background jobs, newest first
+#[tauri::command]
+async fn get_background_jobs(state: State<'_, AppState>) -> Result<ApiResponse<Vec<JobSummary>>, String> {
+    let jobs = state.jobs.lock().map_err(|e| e.to_string())?.summaries();
+    Ok(ApiResponse {
+        success: true,
+        message: msg!("background_job_count", "{count} background jobs", count = jobs.len()),
+        data: Some(jobs),
+    })
+}
+
+/// Run a failed job again
+#[tauri::command]
+async fn retry_background_job(state: State<'_, AppState>, job_id: u64) -> Result<ApiResponse<()>, String> {
+    let retried = state.jobs.lock().map_err(|e| e.to_string())?.retry(job_id);
+    Ok(ApiResponse {
+        success: retried,
+        message: if retried {
+            msg!("job_requeued", "Job queued to run again")
+        } else {
+            msg!("job_not_failed", "Job {job_id} has not failed", job_id = job_id)
+        },
+        data: None,
+    })
+}
+
+// ============================================================================
+// MAIN
+// ============================================================================
+
+fn main() {
+    tauri::Builder::default()
+        .manage(AppState::default())
+        .setup(|app| {
+            if let Ok(mut handle) = app.state::<AppState>().app_handle.lock() {
+                *handle = Some(app.handle().clone());
+            }
+
+            // Hear about revocations from our P2P server as they arrive, not at the next refresh
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                let (revocation_tx, revocation_rx) = mpsc::unbounded_channel();
+                handle.state::<AppState>().image_store.write().await.set_revocation_listener(revocation_tx);
+                run_revocation_listener(handle, revocation_rx).await;
+            });
+
+            // Put the healthy directory servers first before anything talks to them
+            let handle = app.handle().clone();
+            tauri::async_runtime::spawn(async move {
+                match refresh_directories(&handle.state::<AppState>()).await {
+                    Ok((report, _)) => println!("Directory servers: {}/{} healthy", report.healthy, report.total),
+                    Err(e) => eprintln!("Directory server refresh failed: {}", e),
+                }
+            });
+
+            setup_tray(app)?;
+            Ok(())
+        })
+        .on_window_event(|window, event| {
+            // In background mode the P2P server, heartbeat and job worker outlive the window
+            if let WindowEvent::CloseRequested { api, .. } = event {
+                let state = window.state::<AppState>();
+                let online = state.session().is_ok_and(|session| session.is_online());
+                let background = state.background_mode.lock().is_ok_and(|background| *background);
+                if online && background {
+                    api.prevent_close();
+                    let _ = window.hide();
+                }
+            }
+        })
         .invoke_handler(tauri::generate_handler![
             set_directory_servers,
             get_directory_servers,
+            refresh_directory_list,
+            get_directory_server_info,
+            set_share_roots,
+            get_share_roots,
             go_online,
             go_offline,
             get_connection_status,
             discover_peers,
+            find_image,
+            search_catalog,
+            get_profile,
+            update_profile,
+            get_delegates,
+            set_delegate,
+            get_anonymous_access,
+            set_anonymous_access,
+            get_presence_history,
+            get_existing_grants,
             request_image,
+            request_by_share_link,
+            request_image_quick,
             get_pending_requests,
+            get_request_throttles,
+            get_reminders,
+            snooze_reminder,
             respond_to_request,
+            respond_to_requests_bulk,
             get_notifications,
+            cancel_request,
             update_permissions,
+            set_image_holder,
+            get_view_policy,
+            set_view_policy,
+            get_permission_history,
+            remote_wipe,
+            export_grant,
+            import_grant,
+            create_pairing_code,
+            cancel_pairing_code,
+            redeem_pairing_code,
             get_local_images,
             get_encrypted_images,
+            set_image_visibility,
+            set_escrow_preview,
+            set_grant_cap,
             get_received_images,
             refresh_images,
+            analyze_carrier,
             encrypt_image,
+            import_library,
             view_image,
+            view_image_bytes,
+            peek_image_permissions,
             send_heartbeat,
             list_peer_images_cmd,
+            watch_peer_catalog,
             get_image_thumbnail,
             check_pending_permission_updates,
+            check_expired_deliveries,
+            follow_peer,
+            get_catalog_changes,
+            get_access_denial_stats,
             delete_image,
+            delete_images,
+            encrypt_images,
+            request_images,
+            verify_received_image_cmd,
+            run_diagnostics,
+            export_audit_log,
+            set_viewer_only,
+            get_viewer_only,
+            set_background_mode,
+            get_tray_status,
+            propose_trade,
+            get_trades,
+            respond_to_trade,
+            deposit_trade,
+            cancel_trade,
+            get_storage_usage,
+            get_integrity_report,
+            set_storage_quota,
+            get_received_name_policy,
+            set_received_name_policy,
+            get_bandwidth_stats,
+            get_encryption_stats,
+            get_p2p_server_stats,
+            set_bandwidth_cap,
+            enable_protocol_trace,
+            get_protocol_trace,
+            get_background_jobs,
+            migrate_image,
+            retry_background_job,
+            get_state_snapshot,
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
@@ -1,5 +1,6 @@
-import React, { useState, useEffect, useCallback } from 'react';
-import { invoke } from '@tauri-apps/api/core';
+import React, { useState, useEffect, useCallback, useRef } from 'react';
+import { invoke, Channel } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { motion, AnimatePresence } from 'framer-motion';
 import {
   Wifi, WifiOff, Users, Image, Bell, Settings, Shield,
@@ -19,17 +20,29 @@ import NotificationsPanel from './components/NotificationsPanel';
 import SettingsPanel from './components/SettingsPanel';
 import ConnectionModal from './components/ConnectionModal';
 import Toast from './components/Toast';
+import { translate } from './i18n';
+
+// Received images fetched per page
+const RECEIVED_PAGE_SIZE = 48;
+
+// A peer's own listing of an image, keeping the preview the directory had for it
+const mergePeerImage = (listed, live) => ({
+  ...live,
+  thumbnail_path: live.thumbnail_path ?? listed?.thumbnail_path ?? null,
+  preview: live.preview ?? listed?.preview ?? null,
+});
 
 function App() {
   // Connection state
   const [isOnline, setIsOnline] = useState(false);
   const [username, setUsername] = useState('');
   const [port, setPort] = useState(8001);
-  const [directoryServers, setDirectoryServers] = useState([
-    '10.7.57.239:9000',
-    '10.7.57.240:9000',
-    '10.7.57.99:9000'
-  ]);
+  const [directoryServers, setDirectoryServers] = useState([]);
+  const [shareRoots, setShareRoots] = useState([]); // [{ name, path }]
+  const [viewerMode, setViewerMode] = useState({ enabled: false, locked: false });
+  const [backgroundMode, setBackgroundMode] = useState(true);
+  const [connectionHealth, setConnectionHealth] = useState(null); // from get_connection_status
+  const [reconnecting, setReconnecting] = useState(false); // heartbeats failed, the backend is re-registering
 
   // UI state
   const [activeTab, setActiveTab] = useState('dashboard');
@@ -40,9 +53,19 @@ function App() {
   const [peers, setPeers] = useState([]);
   const [localImages, setLocalImages] = useState([]);
   const [encryptedImages, setEncryptedImages] = useState([]);
-  const [receivedImages, setReceivedImages] = useState([]);
+  const [denialStats, setDenialStats] = useState({}); // { imageId: { total, by_viewer, last_denied } }
+  const [receivedImages, setReceivedImages] = useState([]); // the current page
+  const [receivedTotal, setReceivedTotal] = useState(0);
+  const [receivedQuery, setReceivedQuery] = useState({ sortBy: 'name', descending: false, offset: 0 });
+  const receivedQueryRef = useRef(receivedQuery); // read by the refresh timer, which outlives renders
   const [pendingRequests, setPendingRequests] = useState([]);
+  const [reminders, setReminders] = useState([]);
+  const [trades, setTrades] = useState([]);
   const [notifications, setNotifications] = useState([]);
+  const [throttledSenders, setThrottledSenders] = useState([]); // requesters refused for flooding us
+  const [followedPeers, setFollowedPeers] = useState([]);
+  const [newPeerImages, setNewPeerImages] = useState({}); // { "peer_imageId": true }
+  const [directoryServerInfo, setDirectoryServerInfo] = useState([]); // which replica each configured server is
 
   // Toast notifications
   const [toasts, setToasts] = useState([]);
@@ -65,17 +88,427 @@ function App() {
     }, 5000);
   }, []);
 
-  // Initialize directory servers
+  // Directory servers come from the backend's bootstrap file (reordered as it health-checks them)
   useEffect(() => {
-    const initServers = async () => {
-      try {
-        await invoke('set_directory_servers', { servers: directoryServers });
-      } catch (error) {
-        console.error('Failed to set directory servers:', error);
+    invoke('get_directory_servers')
+      .then(response => response.data && setDirectoryServers(response.data))
+      .catch(error => console.error('Failed to load directory servers:', error));
+  }, []);
+
+  const handleUpdateServers = async (servers) => {
+    try {
+      const response = await invoke('set_directory_servers', { servers });
+      setDirectoryServers(servers);
+      showToast(translate(response), 'success');
+    } catch (error) {
+      showToast(`Failed to set directory servers: ${error}`, 'error');
+    }
+  };
+
+  // Health-check the directory servers and learn replicas they advertise
+  const handleRefreshDirectories = async () => {
+    try {
+      const response = await invoke('refresh_directory_list');
+      if (response.data) setDirectoryServers(response.data.servers.map(server => server.address));
+      showToast(translate(response), response.success ? 'success' : 'warning');
+      return response.data;
+    } catch (error) {
+      showToast(`Failed to refresh directory servers: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  // Viewer-only mode may be forced on by the environment, so ask the backend
+  useEffect(() => {
+    invoke('get_viewer_only')
+      .then(response => response.data && setViewerMode(response.data))
+      .catch(error => console.error('Failed to read viewer mode:', error));
+    invoke('get_tray_status')
+      .then(response => response.data && setBackgroundMode(response.data.background_mode))
+      .catch(error => console.error('Failed to read tray status:', error));
+  }, []);
+
+  // Hydrate from the backend's state, then apply its incremental updates as they arrive
+  useEffect(() => {
+    invoke('get_state_snapshot')
+      .then(response => {
+        const snapshot = response.data;
+        if (!snapshot?.is_online) return;
+        setIsOnline(true);
+        setUsername(snapshot.username || '');
+        setLocalImages(snapshot.local_images);
+        setReceivedImages(snapshot.received_images.slice(0, RECEIVED_PAGE_SIZE));
+        setReceivedTotal(snapshot.received_images.length);
+      })
+      .catch(error => console.error('Failed to load state snapshot:', error));
+
+    const addImage = (list, image) =>
+      list.some(img => img.file_path === image.file_path) ? list : [...list, image];
+    const setStatus = (list, key, value, status) =>
+      list.map(item => item[key] === value ? { ...item, status } : item);
+    const setSharedImages = (username, update) =>
+      setPeers(list => list.map(peer =>
+        peer.username === username ? { ...peer, shared_images: update(peer.shared_images || []) } : peer
+      ));
+
+    const unlisten = listen('state-event', ({ payload }) => {
+      switch (payload.event) {
+        case 'image_added': {
+          // Received images are paged and sorted by the backend; the fetch that found them shows them
+          const setList = { local: setLocalImages, encrypted: setEncryptedImages }[payload.collection];
+          setList?.(list => addImage(list, payload.image));
+          break;
+        }
+        case 'image_removed': {
+          const remove = list => list.filter(img => img.file_path !== payload.file_path);
+          setLocalImages(remove);
+          setEncryptedImages(remove);
+          setReceivedImages(remove);
+          break;
+        }
+        case 'request_status_changed':
+          setPendingRequests(list => setStatus(list, 'request_id', payload.request_id, payload.status));
+          setNotifications(list => setStatus(list, 'request_id', payload.request_id, payload.status));
+          break;
+        case 'peer_status_changed':
+          setPeers(list => setStatus(list, 'username', payload.username, payload.status));
+          break;
+        case 'directory_servers_changed':
+          setDirectoryServers(payload.servers);
+          break;
+        case 'view_revoked':
+          setReceivedImages(list => list.map(img =>
+            img.from_owner === payload.from_owner && img.image_id === payload.image_id
+              ? { ...img, views_remaining: 0 }
+              : img
+          ));
+          showToast(`${payload.from_owner} ${payload.wiped ? 'deleted your copy of' : 'revoked your access to'} ${payload.image_id}`, 'warning');
+          break;
+        case 'grant_reconciled':
+          setReceivedImages(list => list.map(img =>
+            img.file_path === payload.file_path ? { ...img, views_remaining: payload.views_remaining } : img
+          ));
+          showToast(
+            payload.revoked
+              ? `${payload.from_owner} had revoked your access to an image - updated your copy`
+              : `${payload.from_owner} changed your views of an image to ${payload.views_remaining}`,
+            payload.revoked ? 'warning' : 'info'
+          );
+          break;
+        case 'peer_image_shared':
+          setSharedImages(payload.username, images => {
+            const existing = images.find(img => img.image_id === payload.image.image_id);
+            return existing
+              ? images.map(img => img === existing ? mergePeerImage(existing, payload.image) : img)
+              : [...images, payload.image];
+          });
+          break;
+        case 'peer_image_unshared':
+          setSharedImages(payload.username, images => images.filter(img => img.image_id !== payload.image_id));
+          break;
+        case 'reconnected': {
+          setReconnecting(false);
+          setIsOnline(true);
+          const updates = payload.pending_updates.length;
+          showToast(
+            `Reconnected after ${payload.offline_secs}s: re-shared ${payload.shared_images} images`
+              + (updates ? `, applied ${updates} pending update${updates === 1 ? '' : 's'}` : ''),
+            'success'
+          );
+          payload.pending_updates.forEach(message => showToast(message, 'info'));
+          break;
+        }
+        case 'reminders_due':
+          showToast(`${payload.count} request${payload.count === 1 ? ' has' : 's have'} been waiting on you for a while`, 'warning');
+          invoke('get_reminders')
+            .then(response => response.success && setReminders(response.data || []))
+            .catch(error => console.error('Failed to load reminders:', error));
+          break;
+        case 'catalog_scanned':
+          // The carrier check may have moved damaged carriers aside
+          fetchEncryptedImages();
+          checkIntegrityReport();
+          break;
+        case 'went_offline':
+          setIsOnline(false);
+          setReconnecting(false);
+          setUsername('');
+          setPeers([]);
+          setPendingRequests([]);
+          setNotifications([]);
+          showToast('You went offline from the tray', 'info');
+          break;
+        default:
+          break;
       }
-    };
-    initServers();
-  }, [directoryServers]);
+    });
+    return () => { unlisten.then(stop => stop()); };
+  }, []);
+
+  const handleToggleViewerOnly = async (enabled) => {
+    try {
+      const response = await invoke('set_viewer_only', { enabled });
+      if (response.data) setViewerMode(response.data);
+      showToast(translate(response), response.success ? 'info' : 'warning');
+    } catch (error) {
+      showToast(`Failed to change viewer mode: ${error}`, 'error');
+    }
+  };
+
+  const handleToggleBackgroundMode = async (enabled) => {
+    try {
+      const response = await invoke('set_background_mode', { enabled });
+      if (response.data) setBackgroundMode(response.data.background_mode);
+      showToast(translate(response), 'info');
+    } catch (error) {
+      showToast(`Failed to change background mode: ${error}`, 'error');
+    }
+  };
+
+  // Push share roots to the backend and rescan when they change while online
+  const handleUpdateShareRoots = async (roots) => {
+    try {
+      const response = await invoke('set_share_roots', { roots });
+      if (!response.success) {
+        showToast(translate(response), 'error');
+        return;
+      }
+      setShareRoots(roots);
+      if (isOnline) {
+        await refreshImages();
+      }
+    } catch (error) {
+      showToast(`Failed to set share folders: ${error}`, 'error');
+    }
+  };
+
+  const handleRunDiagnostics = async () => {
+    try {
+      // Once online the backend checks the port we're actually listening on
+      const response = await invoke('run_diagnostics', { port: isOnline ? null : port });
+      if (!response.success) {
+        showToast(translate(response), 'error');
+        return null;
+      }
+      const report = response.data;
+      const allOk = [report.local_ip, report.port_bind, report.reachability,
+        ...report.directory_servers, ...report.encryption_servers].every(c => c.ok);
+      showToast(translate(response), allOk ? 'success' : 'warning');
+      return response.data;
+    } catch (error) {
+      showToast(`Diagnostics failed: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleExportAuditLog = async () => {
+    try {
+      const response = await invoke('export_audit_log');
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.data;
+    } catch (error) {
+      showToast(`Failed to export audit log: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleMigrateImages = async () => {
+    try {
+      const response = await invoke('migrate_image', { imagePath: null });
+      showToast(translate(response), response.success ? 'success' : 'warning');
+    } catch (error) {
+      showToast(`Failed to migrate images: ${error}`, 'error');
+    }
+  };
+
+  const handleGetProfile = async () => {
+    try {
+      const response = await invoke('get_profile');
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Failed to load profile: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleUpdateProfile = async ({ displayName, bio, avatarBytes, clearAvatar }) => {
+    try {
+      const response = await invoke('update_profile', {
+        displayName: displayName || null,
+        bio: bio || null,
+        avatarBytes,
+        clearAvatar,
+      });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Failed to update profile: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleGetDelegates = async () => {
+    try {
+      const response = await invoke('get_delegates');
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Failed to load delegates: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleSetDelegate = async (delegate, imageIds, enabled) => {
+    try {
+      const response = await invoke('set_delegate', { delegate, imageIds, enabled });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to update delegate: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleGetAnonymousAccess = async () => {
+    try {
+      const response = await invoke('get_anonymous_access');
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Failed to load anonymous access: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleSetAnonymousAccess = async (imageIds, allowed) => {
+    try {
+      const response = await invoke('set_anonymous_access', { imageIds, allowed });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to update anonymous access: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  // Tell the user about carriers the startup check moved into damaged/ (or restored from .bak)
+  const checkIntegrityReport = async () => {
+    try {
+      const response = await invoke('get_integrity_report');
+      const report = response.data;
+      if (report && (report.quarantined.length > 0 || report.recovered.length > 0)) {
+        showToast(`${translate(response)}. See damaged/integrity_report.json`, report.quarantined.length > 0 ? 'warning' : 'info');
+      }
+    } catch (error) {
+      console.error('Failed to load integrity report:', error);
+    }
+  };
+
+  const handleGetStorageUsage = async () => {
+    try {
+      const response = await invoke('get_storage_usage');
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Failed to load storage usage: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleSetStorageQuota = async (maxMb) => {
+    try {
+      const response = await invoke('set_storage_quota', { maxMb });
+      showToast(translate(response), response.success ? 'success' : 'warning');
+      if (response.data?.length > 0) {
+        await fetchReceivedImages();
+      }
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to set storage quota: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleGetNamePolicy = async () => {
+    try {
+      const response = await invoke('get_received_name_policy');
+      return response.success ? response.data : null;
+    } catch (error) {
+      console.error('Failed to load naming setting:', error);
+      return null;
+    }
+  };
+
+  const handleSetNamePolicy = async (policy) => {
+    try {
+      const response = await invoke('set_received_name_policy', { policy });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to save naming setting: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleGetBandwidthStats = async () => {
+    try {
+      const response = await invoke('get_bandwidth_stats');
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Failed to load bandwidth stats: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleSetBandwidthCap = async (maxMb) => {
+    try {
+      const response = await invoke('set_bandwidth_cap', { maxMb });
+      showToast(translate(response), response.success ? 'success' : 'warning');
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to set bandwidth cap: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleGetBackgroundJobs = async () => {
+    try {
+      const response = await invoke('get_background_jobs');
+      return response.success ? response.data : [];
+    } catch (error) {
+      showToast(`Failed to load background jobs: ${error}`, 'error');
+      return [];
+    }
+  };
+
+  const handleRetryBackgroundJob = async (jobId) => {
+    try {
+      const response = await invoke('retry_background_job', { jobId });
+      showToast(translate(response), response.success ? 'success' : 'warning');
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to retry job: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleToggleProtocolTrace = async (enabled) => {
+    try {
+      const response = await invoke('enable_protocol_trace', { enabled, capacity: null });
+      showToast(translate(response), response.success ? 'info' : 'error');
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to toggle protocol trace: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleGetProtocolTrace = async () => {
+    try {
+      const response = await invoke('get_protocol_trace');
+      return response.success ? response.data : [];
+    } catch (error) {
+      showToast(`Failed to load protocol trace: ${error}`, 'error');
+      return [];
+    }
+  };
 
   // Heartbeat interval - handles auto-disconnect when servers are down
   useEffect(() => {
@@ -85,18 +518,17 @@ function App() {
       try {
         const response = await invoke('send_heartbeat');
         
-        // Check if we got disconnected due to server failures
+        // Check if we got disconnected due to server failures; the backend keeps trying to reconnect
         if (response.data?.disconnected) {
           setIsOnline(false);
-          setUsername('');
-          setPeers([]);
-          setPendingRequests([]);
-          setNotifications([]);
-          showToast('Disconnected: All directory servers are unreachable', 'error');
+          setReconnecting(true);
+          showToast('Disconnected: All directory servers are unreachable. Reconnecting automatically...', 'error');
         } else if (!response.success && response.data?.failures > 0) {
           // Warn user about connection issues
           showToast(`Connection unstable: ${response.data.failures}/3 failures`, 'warning');
         }
+        const status = await invoke('get_connection_status');
+        if (status.success) setConnectionHealth(status.data);
       } catch (error) {
         console.error('Heartbeat failed:', error);
         // If invoke itself fails, assume disconnected
@@ -109,6 +541,14 @@ function App() {
     return () => clearInterval(heartbeatInterval);
   }, [isOnline, showToast]);
 
+  // Identify the directory replicas whenever the dashboard is shown
+  useEffect(() => {
+    if (activeTab !== 'dashboard') return;
+    invoke('get_directory_server_info')
+      .then(response => setDirectoryServerInfo(response.data || []))
+      .catch(error => console.error('Failed to load directory server info:', error));
+  }, [activeTab, isOnline]);
+
   // Check for pending permission updates periodically
   useEffect(() => {
     if (!isOnline) return;
@@ -128,6 +568,13 @@ function App() {
           // Refresh received images to show updates
           await fetchReceivedImages();
         }
+
+        // Our own queued deliveries the directory gave up on
+        const expired = await invoke('check_expired_deliveries');
+        for (const notice of (expired.success && expired.data) || []) {
+          const what = notice.wipe ? 'Wipe request' : 'Delivery';
+          showToast(`⌛ ${what} of "${notice.image_id}" to ${notice.target_user} expired - re-send it or share it another way`, 'warning');
+        }
       } catch (error) {
         console.error('Failed to check permission updates:', error);
       }
@@ -141,6 +588,41 @@ function App() {
     return () => clearInterval(updateInterval);
   }, [isOnline, showToast]);
 
+  // Poll for catalog changes from followed peers
+  useEffect(() => {
+    if (!isOnline) return;
+
+    const checkCatalogChanges = async () => {
+      try {
+        const response = await invoke('get_catalog_changes');
+        if (!response.success || !response.data) return;
+
+        setFollowedPeers(response.data.following);
+        const changes = response.data.changes;
+        for (const change of changes) {
+          if (change.added.length > 0) {
+            const count = change.added.length;
+            showToast(`🆕 ${change.owner} added ${count} new image${count === 1 ? '' : 's'}`, 'info');
+          }
+        }
+        if (changes.some(change => change.added.length > 0)) {
+          setNewPeerImages(prev => {
+            const next = { ...prev };
+            changes.forEach(change => change.added.forEach(id => { next[`${change.owner}_${id}`] = true; }));
+            return next;
+          });
+          await fetchPeers();
+        }
+      } catch (error) {
+        console.error('Failed to check catalog changes:', error);
+      }
+    };
+
+    checkCatalogChanges();
+    const catalogInterval = setInterval(checkCatalogChanges, 15000);
+    return () => clearInterval(catalogInterval);
+  }, [isOnline, showToast]);
+
   // Auto-refresh data when online
   useEffect(() => {
     if (!isOnline) return;
@@ -149,6 +631,7 @@ function App() {
       await Promise.all([
         fetchPeers(),
         fetchPendingRequests(),
+        fetchTrades(),
         fetchNotifications(),
         fetchReceivedImages()
       ]);
@@ -160,21 +643,26 @@ function App() {
   }, [isOnline]);
 
   // Connection handlers
-  const handleGoOnline = async (user, p2pPort, imagesDir) => {
+  const handleGoOnline = async (user, p2pPort, imagesDir, maxGrantViews, availability = null, anonymous = false, namespace = null) => {
     setLoading(prev => ({ ...prev, connection: true }));
     try {
       const response = await invoke('go_online', {
         username: user,
         port: p2pPort,
-        imagesDir: imagesDir
+        imagesDir: imagesDir,
+        maxGrantViews: maxGrantViews,
+        availability,
+        anonymous,
+        namespace
       });
 
       if (response.success) {
         setIsOnline(true);
+        setReconnecting(false);
         setUsername(user);
         setPort(p2pPort);
         setLocalImages(response.data || []);
-        showToast(`Welcome, ${user}! You are now online.`, 'success');
+        showToast(anonymous ? `Welcome, ${user}! You are online anonymously.` : `Welcome, ${user}! You are now online.`, 'success');
         setShowConnectionModal(false);
         
         // Fetch initial data
@@ -185,8 +673,9 @@ function App() {
           fetchReceivedImages(),
           fetchEncryptedImages()
         ]);
+        // The carrier check runs with the catalog scan in the background; see catalog_scanned
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
       }
     } catch (error) {
       showToast(`Connection failed: ${error}`, 'error');
@@ -198,6 +687,7 @@ function App() {
     try {
       await invoke('go_offline');
       setIsOnline(false);
+      setReconnecting(false);
       setUsername('');
       setPeers([]);
       setPendingRequests([]);
@@ -231,12 +721,45 @@ function App() {
       if (response.success) {
         setPendingRequests(response.data || []);
       }
+      const throttles = await invoke('get_request_throttles');
+      if (throttles.success) {
+        setThrottledSenders(throttles.data || []);
+      }
+      const overdue = await invoke('get_reminders');
+      if (overdue.success) {
+        setReminders(overdue.data || []);
+      }
     } catch (error) {
       console.error('Failed to fetch requests:', error);
     }
     setLoading(prev => ({ ...prev, requests: false }));
   };
 
+  // hours = null dismisses the reminder for good
+  const handleSnoozeReminder = async (requestId, hours) => {
+    try {
+      const response = await invoke('snooze_reminder', { requestId, hours });
+      showToast(translate(response), response.success ? 'info' : 'error');
+      if (response.success) {
+        setReminders(list => list.filter(r => r.request_id !== requestId));
+      }
+    } catch (error) {
+      showToast(`Failed to snooze reminder: ${error}`, 'error');
+    }
+  };
+
+  const fetchTrades = async () => {
+    if (!isOnline) return;
+    try {
+      const response = await invoke('get_trades');
+      if (response.success) {
+        setTrades(response.data || []);
+      }
+    } catch (error) {
+      console.error('Failed to fetch trades:', error);
+    }
+  };
+
   const fetchNotifications = async () => {
     if (!isOnline) return;
     setLoading(prev => ({ ...prev, notifications: true }));
@@ -251,18 +774,39 @@ function App() {
     setLoading(prev => ({ ...prev, notifications: false }));
   };
 
-  const fetchReceivedImages = async () => {
+  const fetchReceivedImages = async (forceRescan = false) => {
+    const query = receivedQueryRef.current;
     try {
-      const response = await invoke('get_received_images');
+      const response = await invoke('get_received_images', {
+        sortBy: query.sortBy,
+        descending: query.descending,
+        offset: query.offset,
+        limit: RECEIVED_PAGE_SIZE,
+        forceRescan
+      });
       console.log('Received images response:', response);
       if (response.success) {
-        setReceivedImages(response.data || []);
+        setReceivedImages(response.data?.images || []);
+        setReceivedTotal(response.data?.total || 0);
       }
     } catch (error) {
       console.error('Failed to fetch received images:', error);
     }
   };
 
+  const handleReceivedQueryChange = async (changes) => {
+    const query = { ...receivedQueryRef.current, ...changes };
+    receivedQueryRef.current = query;
+    setReceivedQuery(query);
+    await fetchReceivedImages();
+  };
+
+  const rescanReceivedImages = async () => {
+    setLoading(prev => ({ ...prev, images: true }));
+    await fetchReceivedImages(true);
+    setLoading(prev => ({ ...prev, images: false }));
+  };
+
   const fetchEncryptedImages = async () => {
     try {
       const response = await invoke('get_encrypted_images');
@@ -273,28 +817,156 @@ function App() {
     } catch (error) {
       console.error('Failed to fetch encrypted images:', error);
     }
+
+    try {
+      const response = await invoke('get_access_denial_stats');
+      if (response.success) {
+        setDenialStats(response.data || {});
+      }
+    } catch (error) {
+      console.error('Failed to fetch denial stats:', error);
+    }
   };
 
   // Request handlers
-  const handleRequestImage = async (peerUsername, imageId, views) => {
+  const handleRequestImage = async (peerUsername, imageId, views, maxDimension = null) => {
     try {
       const response = await invoke('request_image', {
         peerUsername,
         imageId,
-        views: parseInt(views)
+        views: parseInt(views),
+        maxDimension
       });
 
       if (response.success) {
         showToast(`Request sent to ${peerUsername}`, 'success');
         await fetchNotifications();
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
+      }
+    } catch (error) {
+      showToast(`Request failed: ${error}`, 'error');
+    }
+  };
+
+  // link: owner/image_id#token, as given out for a link-only image
+  const handleRequestByLink = async (link, views) => {
+    try {
+      const response = await invoke('request_by_share_link', { link, views: parseInt(views) });
+      showToast(response.success ? 'Request sent via share link' : translate(response), response.success ? 'success' : 'error');
+      if (response.success) {
+        await fetchNotifications();
       }
     } catch (error) {
       showToast(`Request failed: ${error}`, 'error');
     }
   };
 
+  // Batch commands answer per item; the toast sums them up and failures go to the console
+  const reportBatch = (response) => {
+    showToast(translate(response), response.success ? 'success' : 'error');
+    (response.data || [])
+      .filter(result => !result.success)
+      .forEach(result => console.error(`${result.item}: ${result.message}`));
+  };
+
+  // requests: [{ peer_username, image_id, views, max_dimension }]
+  const handleRequestImages = async (requests) => {
+    try {
+      const response = await invoke('request_images', { requests });
+      reportBatch(response);
+      await fetchNotifications();
+      return response.success;
+    } catch (error) {
+      showToast(`Requests failed: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  // Quick-request preset: submit, then follow the request until it's answered and delivered
+  const handleQuickRequest = async (peerUsername, imageId, views) => {
+    const onEvent = new Channel();
+    onEvent.onmessage = async (event) => {
+      switch (event.event) {
+        case 'submitted':
+          showToast(`Asked ${peerUsername} for ${event.views} view${event.views === 1 ? '' : 's'}`, 'info');
+          await fetchNotifications();
+          break;
+        case 'accepted':
+          showToast(`${peerUsername} accepted your request`, 'success');
+          await fetchNotifications();
+          break;
+        case 'rejected':
+          showToast(`${peerUsername} rejected your request`, 'error');
+          await fetchNotifications();
+          break;
+        case 'delivered':
+          showToast(`Image from ${peerUsername} received`, 'success');
+          await fetchReceivedImages();
+          break;
+        case 'timed_out':
+          showToast(`Still waiting on ${peerUsername} - check Notifications later`, 'info');
+          break;
+        case 'failed':
+          showToast(translate(event), 'error');
+          break;
+        default:
+          break;
+      }
+    };
+
+    try {
+      await invoke('request_image_quick', {
+        peerUsername,
+        imageId,
+        views,
+        autoPoll: true,
+        onEvent
+      });
+    } catch (error) {
+      showToast(`Request failed: ${error}`, 'error');
+    }
+  };
+
+  const handleToggleFollow = async (peerUsername) => {
+    const follow = !followedPeers.includes(peerUsername);
+    try {
+      const response = await invoke('follow_peer', { peerUsername, follow });
+      if (response.success) {
+        setFollowedPeers(prev => follow
+          ? [...prev, peerUsername]
+          : prev.filter(p => p !== peerUsername));
+        showToast(translate(response), 'success');
+      } else {
+        showToast(translate(response), 'error');
+      }
+    } catch (error) {
+      showToast(`Follow failed: ${error}`, 'error');
+    }
+  };
+
+  // Follow the expanded peer's catalog live; quietly keeps the directory's list if the peer can't push updates
+  const handleWatchPeerCatalog = async (peerUsername, watch) => {
+    try {
+      const response = await invoke('watch_peer_catalog', { peerUsername, watch });
+      if (response.success && response.data) {
+        setPeers(list => list.map(peer => {
+          if (peer.username !== peerUsername) return peer;
+          const listed = peer.shared_images || [];
+          return {
+            ...peer,
+            shared_images: response.data.map(image =>
+              mergePeerImage(listed.find(img => img.image_id === image.image_id), image)),
+          };
+        }));
+      } else if (!response.success) {
+        console.warn(translate(response));
+      }
+    } catch (error) {
+      console.error('Failed to watch peer catalog:', error);
+    }
+  };
+
   const handleRespondToRequest = async (requestId, accept) => {
     try {
       const response = await invoke('respond_to_request', {
@@ -305,42 +977,242 @@ function App() {
       if (response.success) {
         showToast(accept ? 'Request accepted!' : 'Request rejected', accept ? 'success' : 'info');
         await fetchPendingRequests();
+      } else if (response.data?.kind === 'ImageNoLongerShared') {
+        showToast(translate(response), 'warning');
+        await Promise.all([fetchPendingRequests(), fetchEncryptedImages()]);
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
       }
     } catch (error) {
       showToast(`Response failed: ${error}`, 'error');
     }
   };
 
+  const handleRespondBulk = async (filter, accept) => {
+    try {
+      const response = await invoke('respond_to_requests_bulk', { filter, accept });
+      showToast(translate(response), response.success ? (accept ? 'success' : 'info') : 'error');
+      (response.data || [])
+        .filter(result => !result.success)
+        .forEach(result => console.error(`Request ${result.request_id} from ${result.from_user}: ${result.message}`));
+      await fetchPendingRequests();
+    } catch (error) {
+      showToast(`Bulk response failed: ${error}`, 'error');
+    }
+  };
+
+  const handleProposeTrade = async (peerUsername, offeredImageId, offeredViews, requestedImageId, requestedViews) => {
+    try {
+      const response = await invoke('propose_trade', {
+        peerUsername,
+        offeredImageId,
+        offeredViews,
+        requestedImageId,
+        requestedViews
+      });
+
+      if (response.success) {
+        showToast(translate(response), 'success');
+        await fetchTrades();
+      } else {
+        showToast(translate(response), 'error');
+      }
+    } catch (error) {
+      showToast(`Trade proposal failed: ${error}`, 'error');
+    }
+  };
+
+  // Accept, reject, deposit, and cancel all report back the same way
+  const runTradeAction = async (command, args) => {
+    try {
+      const response = await invoke(command, args);
+      showToast(translate(response), response.success ? 'success' : 'error');
+      await fetchTrades();
+    } catch (error) {
+      showToast(`Trade action failed: ${error}`, 'error');
+    }
+  };
+
+  const handleRespondToTrade = (tradeId, accept) =>
+    runTradeAction('respond_to_trade', { tradeId, accept });
+
+  const handleDepositTrade = (tradeId) =>
+    runTradeAction('deposit_trade', { tradeId });
+
+  const handleCancelTrade = (tradeId) =>
+    runTradeAction('cancel_trade', { tradeId });
+
   const handleUpdatePermissions = async (targetUser, imageId, newQuota) => {
     try {
       const response = await invoke('update_permissions', {
         targetUser,
         imageId,
-        newQuota: parseInt(newQuota)
+        newQuota: parseInt(newQuota),
+        preview: false
       });
 
       if (response.success) {
         showToast(`Permissions updated for ${targetUser}`, 'success');
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
       }
     } catch (error) {
       showToast(`Update failed: ${error}`, 'error');
     }
   };
 
-  const handleEncryptImage = async (imagePath) => {
+  // Quotas the update would leave, without changing or sending anything
+  const handlePreviewPermissions = async (targetUser, imageId, newQuota) => {
+    try {
+      const response = await invoke('update_permissions', {
+        targetUser,
+        imageId,
+        newQuota: parseInt(newQuota),
+        preview: true
+      });
+      if (!response.success) {
+        showToast(translate(response), 'error');
+      }
+      return response.success ? response.data : null;
+    } catch (error) {
+      showToast(`Preview failed: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleSetHolder = async (imageId, holder, enabled) => {
+    try {
+      const response = await invoke('set_image_holder', { imageId, holder, enabled });
+      showToast(translate(response), response.success ? 'success' : 'error');
+    } catch (error) {
+      showToast(`Holder update failed: ${error}`, 'error');
+    }
+  };
+
+  const handleSetViewPolicy = async (imageId, policy) => {
+    try {
+      const response = await invoke('set_view_policy', { imageId, policy });
+      showToast(translate(response), response.success ? 'success' : 'error');
+    } catch (error) {
+      showToast(`Viewer rights update failed: ${error}`, 'error');
+    }
+  };
+
+  const handleSetVisibility = async (imageId, visibility) => {
+    try {
+      const response = await invoke('set_image_visibility', { imageId, visibility });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      if (response.success) {
+        await fetchEncryptedImages();
+      }
+    } catch (error) {
+      showToast(`Visibility update failed: ${error}`, 'error');
+    }
+  };
+
+  const handleSetEscrowPreview = async (imageId, enabled) => {
+    try {
+      const response = await invoke('set_escrow_preview', { imageId, enabled });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      if (response.success) {
+        await fetchEncryptedImages();
+      }
+    } catch (error) {
+      showToast(`Preview update failed: ${error}`, 'error');
+    }
+  };
+
+  const handleSetGrantCap = async (imageId, maxViews) => {
+    try {
+      const response = await invoke('set_grant_cap', { imageId, maxViews });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      if (response.success) {
+        await fetchEncryptedImages();
+      }
+    } catch (error) {
+      showToast(`Grant cap update failed: ${error}`, 'error');
+    }
+  };
+
+  const handleRemoteWipe = async (targetUser, imageId) => {
     try {
-      const response = await invoke('encrypt_image', { imagePath });
+      const response = await invoke('remote_wipe', { targetUser, imageId });
+      showToast(translate(response), response.success ? 'success' : 'error');
+    } catch (error) {
+      showToast(`Wipe failed: ${error}`, 'error');
+    }
+  };
+
+  const handleExportGrant = async (imageId, recipient, views, passphrase, outputDir) => {
+    try {
+      const response = await invoke('export_grant', { imageId, recipient, views, passphrase, outputDir });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.success;
+    } catch (error) {
+      showToast(`Export failed: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleImportGrant = async (packagePath, passphrase) => {
+    try {
+      const response = await invoke('import_grant', { packagePath, passphrase });
+      showToast(translate(response), response.success ? 'success' : 'error');
       if (response.success) {
-        showToast('Image encrypted successfully!', 'success');
+        await fetchReceivedImages();
+      }
+      return response.success;
+    } catch (error) {
+      showToast(`Import failed: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleCreatePairingCode = async (imageId, views) => {
+    try {
+      const response = await invoke('create_pairing_code', { imageId, views });
+      if (!response.success) {
+        showToast(translate(response), 'error');
+      }
+      return response.data || null;
+    } catch (error) {
+      showToast(`Pairing failed: ${error}`, 'error');
+      return null;
+    }
+  };
+
+  const handleCancelPairingCode = async (code) => {
+    try {
+      await invoke('cancel_pairing_code', { code });
+    } catch (error) {
+      console.error('Failed to withdraw pairing code:', error);
+    }
+  };
+
+  const handleRedeemPairing = async (pairing) => {
+    try {
+      const response = await invoke('redeem_pairing_code', { pairing });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      if (response.success) {
+        await fetchReceivedImages();
+      }
+      return response.success;
+    } catch (error) {
+      showToast(`Redeem failed: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleEncryptImage = async (imagePath, annotations = null, policy = null, optimize = false) => {
+    try {
+      const response = await invoke('encrypt_image', { imagePath, annotations, policy, optimize });
+      if (response.success) {
+        showToast(optimize ? translate(response) : 'Image encrypted successfully!', 'success');
         // Auto-refresh images after encryption
         await refreshImages();
         return response.data;
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
       }
     } catch (error) {
       showToast(`Encryption failed: ${error}`, 'error');
@@ -348,6 +1220,29 @@ function App() {
     return null;
   };
 
+  const handleEncryptImages = async (imagePaths, policy = null, optimize = false) => {
+    try {
+      const response = await invoke('encrypt_images', { imagePaths, policy, optimize });
+      reportBatch(response);
+      await refreshImages();
+      return response.success;
+    } catch (error) {
+      showToast(`Encryption failed: ${error}`, 'error');
+      return false;
+    }
+  };
+
+  const handleImportLibrary = async (libraryPath, albums) => {
+    try {
+      const response = await invoke('import_library', { libraryPath, albums });
+      showToast(translate(response), response.success ? 'success' : 'error');
+      return response.success;
+    } catch (error) {
+      showToast(`Import failed: ${error}`, 'error');
+      return false;
+    }
+  };
+
   const refreshImages = async () => {
     setLoading(prev => ({ ...prev, images: true }));
     try {
@@ -356,7 +1251,7 @@ function App() {
         setLocalImages(response.data || []);
         showToast(`Found ${response.data?.length || 0} images`, 'success');
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
       }
       // Also fetch received and encrypted images
       await fetchReceivedImages();
@@ -369,15 +1264,15 @@ function App() {
 
   const handleViewImage = async (imagePath) => {
     try {
-      const response = await invoke('view_image', { imagePath });
+      const response = await invoke('view_image_bytes', { imagePath });
       if (response.success) {
         showToast('Image viewed successfully!', 'success');
         // Refresh received images to update the view count
         await fetchReceivedImages();
-        // Return the path to the viewable image
+        // Return the decoded image as a data URL (never written to disk) plus its annotations
         return response.data;
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
         return null;
       }
     } catch (error) {
@@ -386,11 +1281,26 @@ function App() {
     }
   };
 
+  // Owner, views left and expiry of a received image, read without using up a view
+  const handlePeekImage = async (imagePath) => {
+    try {
+      const response = await invoke('peek_image_permissions', { imagePath });
+      if (response.success) {
+        return response.data;
+      }
+      showToast(translate(response), 'error');
+      return null;
+    } catch (error) {
+      showToast(`Failed to read image permissions: ${error}`, 'error');
+      return null;
+    }
+  };
+
   const handleDeleteImage = async (imagePath, imageType) => {
     try {
       const response = await invoke('delete_image', { filePath: imagePath });
       if (response.success) {
-        showToast(response.message, 'success');
+        showToast(translate(response), 'success');
         // Refresh the appropriate image list based on type
         if (imageType === 'local') {
           await refreshImages();
@@ -400,13 +1310,31 @@ function App() {
           await fetchReceivedImages();
         }
       } else {
-        showToast(response.message, 'error');
+        showToast(translate(response), 'error');
       }
     } catch (error) {
       showToast(`Failed to delete image: ${error}`, 'error');
     }
   };
 
+  const handleDeleteImages = async (imagePaths, imageType) => {
+    try {
+      const response = await invoke('delete_images', { filePaths: imagePaths });
+      reportBatch(response);
+      if (imageType === 'local') {
+        await refreshImages();
+      } else if (imageType === 'encrypted') {
+        await fetchEncryptedImages();
+      } else if (imageType === 'received') {
+        await fetchReceivedImages();
+      }
+      return response.success;
+    } catch (error) {
+      showToast(`Failed to delete images: ${error}`, 'error');
+      return false;
+    }
+  };
+
   // Render panel based on active tab
   const renderPanel = () => {
     switch (activeTab) {
@@ -419,6 +1347,7 @@ function App() {
             imagesCount={localImages.length}
             requestsCount={pendingRequests.length}
             notificationsCount={notifications.length}
+            directoryServers={directoryServerInfo}
             onGoOnline={() => setShowConnectionModal(true)}
             onGoOffline={handleGoOffline}
           />
@@ -430,6 +1359,13 @@ function App() {
             loading={loading.peers}
             onRefresh={fetchPeers}
             onRequestImage={handleRequestImage}
+            onRequestImages={handleRequestImages}
+            onQuickRequest={handleQuickRequest}
+            onRequestByLink={handleRequestByLink}
+            followedPeers={followedPeers}
+            onToggleFollow={handleToggleFollow}
+            onWatchCatalog={handleWatchPeerCatalog}
+            newPeerImages={newPeerImages}
             isOnline={isOnline}
           />
         );
@@ -438,12 +1374,34 @@ function App() {
           <ImagesPanel
             localImages={localImages}
             encryptedImages={encryptedImages}
+            denialStats={denialStats}
             receivedImages={receivedImages}
+            receivedTotal={receivedTotal}
+            receivedQuery={receivedQuery}
+            receivedPageSize={RECEIVED_PAGE_SIZE}
+            onReceivedQueryChange={handleReceivedQueryChange}
+            onRescanReceived={rescanReceivedImages}
             onEncrypt={handleEncryptImage}
+            onEncryptBatch={handleEncryptImages}
+            onImportLibrary={handleImportLibrary}
             onUpdatePermissions={handleUpdatePermissions}
+            onPreviewPermissions={handlePreviewPermissions}
+            onRemoteWipe={handleRemoteWipe}
+            onExportGrant={handleExportGrant}
+            onImportGrant={handleImportGrant}
+            onCreatePairingCode={handleCreatePairingCode}
+            onCancelPairingCode={handleCancelPairingCode}
+            onRedeemPairing={handleRedeemPairing}
+            onSetHolder={handleSetHolder}
+            onSetViewPolicy={handleSetViewPolicy}
+            onSetVisibility={handleSetVisibility}
+            onSetEscrowPreview={handleSetEscrowPreview}
+            onSetGrantCap={handleSetGrantCap}
             onRefresh={refreshImages}
             onViewImage={handleViewImage}
+            onPeekImage={handlePeekImage}
             onDeleteImage={handleDeleteImage}
+            onDeleteBatch={handleDeleteImages}
             loading={loading.images}
             isOnline={isOnline}
           />
@@ -452,9 +1410,20 @@ function App() {
         return (
           <RequestsPanel
             requests={pendingRequests}
+            throttledSenders={throttledSenders}
+            peers={peers}
             loading={loading.requests}
             onRefresh={fetchPendingRequests}
             onRespond={handleRespondToRequest}
+            onRespondBulk={handleRespondBulk}
+            reminders={reminders}
+            onSnoozeReminder={handleSnoozeReminder}
+            trades={trades}
+            onRefreshTrades={fetchTrades}
+            onProposeTrade={handleProposeTrade}
+            onRespondToTrade={handleRespondToTrade}
+            onDepositTrade={handleDepositTrade}
+            onCancelTrade={handleCancelTrade}
             isOnline={isOnline}
           />
         );
@@ -471,7 +1440,34 @@ function App() {
         return (
           <SettingsPanel
             directoryServers={directoryServers}
-            onUpdateServers={setDirectoryServers}
+            onUpdateServers={handleUpdateServers}
+            onRefreshDirectories={handleRefreshDirectories}
+            shareRoots={shareRoots}
+            onUpdateShareRoots={handleUpdateShareRoots}
+            onRunDiagnostics={handleRunDiagnostics}
+            onExportAuditLog={handleExportAuditLog}
+            onGetProfile={handleGetProfile}
+            onUpdateProfile={handleUpdateProfile}
+            onGetDelegates={handleGetDelegates}
+            onSetDelegate={handleSetDelegate}
+            onGetAnonymousAccess={handleGetAnonymousAccess}
+            onSetAnonymousAccess={handleSetAnonymousAccess}
+            isOnline={isOnline}
+            onGetStorageUsage={handleGetStorageUsage}
+            onSetStorageQuota={handleSetStorageQuota}
+            onGetNamePolicy={handleGetNamePolicy}
+            onSetNamePolicy={handleSetNamePolicy}
+            onMigrateImages={handleMigrateImages}
+            onGetBandwidthStats={handleGetBandwidthStats}
+            onSetBandwidthCap={handleSetBandwidthCap}
+            onGetBackgroundJobs={handleGetBackgroundJobs}
+            onRetryBackgroundJob={handleRetryBackgroundJob}
+            onToggleProtocolTrace={handleToggleProtocolTrace}
+            onGetProtocolTrace={handleGetProtocolTrace}
+            viewerMode={viewerMode}
+            onToggleViewerOnly={handleToggleViewerOnly}
+            backgroundMode={backgroundMode}
+            onToggleBackgroundMode={handleToggleBackgroundMode}
           />
         );
       default:
@@ -497,8 +1493,10 @@ function App() {
         {/* Header */}
         <Header
           isOnline={isOnline}
+          reconnecting={reconnecting}
           username={username}
-          onConnectionClick={() => isOnline ? handleGoOffline() : setShowConnectionModal(true)}
+          health={isOnline ? connectionHealth : null}
+          onConnectionClick={() => isOnline || reconnecting ? handleGoOffline() : setShowConnectionModal(true)}
         />
 
         {/* Content area */}
@@ -507,6 +1505,11 @@ function App() {
           <div className="fixed inset-0 cyber-grid pointer-events-none z-0" />
           
           <div className="relative z-10">
+            {viewerMode.enabled && (
+              <div className="mb-4 px-4 py-2 rounded-lg bg-yellow-500/10 border border-yellow-500/20 text-sm text-yellow-400">
+                Viewer-only mode: encrypting, granting, trading, and deleting are disabled in this session
+              </div>
+            )}
             <AnimatePresence mode="wait">
               <motion.div
                 key={activeTab}
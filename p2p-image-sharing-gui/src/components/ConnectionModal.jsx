@@ -2,13 +2,24 @@ import React, { useState, useEffect } from 'react';
 import { motion } from 'framer-motion';
 import { homeDir } from '@tauri-apps/api/path';
 import {
-  Wifi, User, Server, X, Shield, Zap
+  Wifi, User, Server, X, Shield, Zap, Eye, Clock
 } from 'lucide-react';
 
+// Convert a local "HH:MM" to UTC minutes since midnight, as the directory stores it
+const toUtcMinute = (time) => {
+  const [hours, minutes] = time.split(':').map(Number);
+  return ((hours * 60 + minutes + new Date().getTimezoneOffset()) % 1440 + 1440) % 1440;
+};
+
 function ConnectionModal({ onClose, onConnect, loading, directoryServers }) {
   const [username, setUsername] = useState('');
   const [port, setPort] = useState(8001);
   const [imagesDir, setImagesDir] = useState('');
+  const [maxGrantViews, setMaxGrantViews] = useState('');
+  const [availableFrom, setAvailableFrom] = useState('');
+  const [availableUntil, setAvailableUntil] = useState('');
+  const [anonymous, setAnonymous] = useState(false);
+  const [namespace, setNamespace] = useState('');
 
   // Auto-detect home directory on mount
   useEffect(() => {
@@ -30,7 +41,10 @@ function ConnectionModal({ onClose, onConnect, loading, directoryServers }) {
 
   const handleConnect = () => {
     if (username && port && imagesDir) {
-      onConnect(username, port, imagesDir);
+      const availability = availableFrom && availableUntil && availableFrom !== availableUntil
+        ? { start_minute: toUtcMinute(availableFrom), end_minute: toUtcMinute(availableUntil) }
+        : null;
+      onConnect(username, port, imagesDir, maxGrantViews ? parseInt(maxGrantViews) : null, availability, anonymous, namespace.trim() || null);
     }
   };
 
@@ -113,6 +127,89 @@ function ConnectionModal({ onClose, onConnect, loading, directoryServers }) {
             </p>
           </div>
 
+          {/* Max views per grant */}
+          <div>
+            <label className="block text-sm font-medium text-gray-400 mb-2">
+              Max Views Per Grant (optional)
+            </label>
+            <div className="relative">
+              <Eye className="absolute left-4 top-1/2 -translate-y-1/2 w-5 h-5 text-gray-400" />
+              <input
+                type="number"
+                min="1"
+                value={maxGrantViews}
+                onChange={(e) => setMaxGrantViews(e.target.value)}
+                placeholder="No limit"
+                className="w-full pl-12 pr-4 py-3 rounded-xl cyber-input text-white placeholder-gray-500"
+              />
+            </div>
+            <p className="text-xs text-gray-500 mt-2">
+              Requests for more views than this on your images are rejected or clamped
+            </p>
+          </div>
+
+          {/* Usual availability */}
+          <div>
+            <label className="block text-sm font-medium text-gray-400 mb-2">
+              Usually Online (optional)
+            </label>
+            <div className="flex items-center gap-3">
+              <Clock className="w-5 h-5 text-gray-400 flex-shrink-0" />
+              <input
+                type="time"
+                value={availableFrom}
+                onChange={(e) => setAvailableFrom(e.target.value)}
+                className="flex-1 px-4 py-3 rounded-xl cyber-input text-white"
+              />
+              <span className="text-gray-500">to</span>
+              <input
+                type="time"
+                value={availableUntil}
+                onChange={(e) => setAvailableUntil(e.target.value)}
+                className="flex-1 px-4 py-3 rounded-xl cyber-input text-white"
+              />
+            </div>
+            <p className="text-xs text-gray-500 mt-2">
+              Your local time. Peers see it in theirs, and know whether to expect a quick answer
+            </p>
+          </div>
+
+          {/* Directory namespace */}
+          <div>
+            <label className="block text-sm font-medium text-gray-400 mb-2">
+              Namespace (optional)
+            </label>
+            <div className="relative">
+              <Server className="absolute left-4 top-1/2 -translate-y-1/2 w-5 h-5 text-gray-400" />
+              <input
+                type="text"
+                value={namespace}
+                onChange={(e) => setNamespace(e.target.value.toLowerCase())}
+                placeholder="e.g. cs101-section-a"
+                className="w-full pl-12 pr-4 py-3 rounded-xl cyber-input text-white placeholder-gray-500"
+              />
+            </div>
+            <p className="text-xs text-gray-500 mt-2">
+              Only users in the same namespace see you, your images and your requests. Leave empty for the shared default
+            </p>
+          </div>
+
+          {/* Anonymous mode */}
+          <div>
+            <label className="flex items-center gap-2 text-sm text-gray-300 cursor-pointer">
+              <input
+                type="checkbox"
+                checked={anonymous}
+                onChange={(e) => setAnonymous(e.target.checked)}
+                className="accent-purple-500"
+              />
+              Stay anonymous
+            </label>
+            <p className="text-xs text-gray-500 mt-2">
+              The username is used as a pseudonymous handle and all P2P traffic goes through the first directory server, so your IP is never published. Slower, and owners choose which images anonymous users may request
+            </p>
+          </div>
+
           {/* Directory servers info */}
           <div className="p-4 rounded-xl bg-white/5 border border-purple-900/20">
             <div className="flex items-center gap-2 text-sm text-gray-400 mb-2">
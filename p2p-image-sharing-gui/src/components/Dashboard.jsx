@@ -36,6 +36,12 @@ const statCards = [
   }
 ];
 
+// e.g. 2h 05m
+function formatUptime(secs) {
+  const minutes = Math.floor(secs / 60) % 60;
+  return `${Math.floor(secs / 3600)}h ${String(minutes).padStart(2, '0')}m`;
+}
+
 function Dashboard({
   isOnline,
   username,
@@ -43,6 +49,7 @@ function Dashboard({
   imagesCount,
   requestsCount,
   notificationsCount,
+  directoryServers = [],
   onGoOnline,
   onGoOffline
 }) {
@@ -211,18 +218,27 @@ function Dashboard({
             <h3 className="font-semibold text-white">Directory Service</h3>
           </div>
           <div className="space-y-3">
-            <div className="flex items-center justify-between">
-              <span className="text-sm text-gray-400">Servers</span>
-              <span className="text-sm font-medium text-white">3 Replicated</span>
-            </div>
-            <div className="flex items-center justify-between">
-              <span className="text-sm text-gray-400">Consensus</span>
-              <span className="text-sm font-medium text-cyan-400">Raft Protocol</span>
-            </div>
-            <div className="flex items-center justify-between">
-              <span className="text-sm text-gray-400">Persistence</span>
-              <span className="text-sm font-medium text-green-400">Enabled</span>
-            </div>
+            {directoryServers.length === 0 && (
+              <p className="text-sm text-gray-400">No directory servers configured</p>
+            )}
+            {directoryServers.map(({ address, info, error }) => (
+              <div key={address} className="space-y-1">
+                <div className="flex items-center justify-between">
+                  <span className="text-sm text-gray-400 font-mono">{address}</span>
+                  <span className={`text-sm font-medium ${info ? 'text-cyan-400' : 'text-red-400'}`}>
+                    {info ? info.server_id : 'Unreachable'}
+                  </span>
+                </div>
+                {info ? (
+                  <p className="text-xs text-gray-500" title={info.state_file}>
+                    v{info.version} · up {formatUptime(info.uptime_secs)} · {info.users} users ({info.online_users} online){info.namespace && ` in ${info.namespace}`}
+                    · {info.peers.length} peer{info.peers.length === 1 ? '' : 's'}
+                  </p>
+                ) : (
+                  <p className="text-xs text-gray-500 truncate" title={error}>{error}</p>
+                )}
+              </div>
+            ))}
           </div>
         </motion.div>
       </div>
@@ -2,7 +2,11 @@ import React from 'react';
 import { motion } from 'framer-motion';
 import { Wifi, WifiOff, User, Power, Activity } from 'lucide-react';
 
-function Header({ isOnline, username, onConnectionClick }) {
+const secondsAgo = (unixSecs) => Math.max(0, Math.round(Date.now() / 1000 - unixSecs));
+
+function Header({ isOnline, reconnecting = false, username, health, onConnectionClick }) {
+  const unstable = health?.heartbeat_failures > 0;
+
   return (
     <header className="h-16 bg-cyber-darker/80 backdrop-blur-sm border-b border-purple-900/30 flex items-center justify-between px-6">
       {/* Left side - breadcrumb/title area */}
@@ -10,8 +14,8 @@ function Header({ isOnline, username, onConnectionClick }) {
         <div className="flex items-center gap-2 text-sm">
           <Activity className="w-4 h-4 text-purple-400 animate-pulse" />
           <span className="text-gray-400">System Status:</span>
-          <span className={`font-semibold ${isOnline ? 'text-green-400' : 'text-red-400'}`}>
-            {isOnline ? 'Connected' : 'Disconnected'}
+          <span className={`font-semibold ${isOnline ? 'text-green-400' : reconnecting ? 'text-yellow-400' : 'text-red-400'}`}>
+            {isOnline ? 'Connected' : reconnecting ? 'Reconnecting...' : 'Disconnected'}
           </span>
         </div>
       </div>
@@ -19,10 +23,10 @@ function Header({ isOnline, username, onConnectionClick }) {
       {/* Right side - user info and controls */}
       <div className="flex items-center gap-4">
         {/* Network indicator */}
-        <div className="flex items-center gap-3 px-4 py-2 rounded-lg bg-white/5 border border-purple-900/30">
+        <div className="group relative flex items-center gap-3 px-4 py-2 rounded-lg bg-white/5 border border-purple-900/30">
           <div className="flex items-center gap-2">
             <div className={`w-2 h-2 rounded-full ${
-              isOnline ? 'bg-green-500 animate-pulse' : 'bg-red-500'
+              reconnecting ? 'bg-yellow-500 animate-pulse' : !isOnline ? 'bg-red-500' : unstable ? 'bg-yellow-500 animate-pulse' : 'bg-green-500 animate-pulse'
             }`} />
             <span className="text-sm text-gray-400">Network</span>
           </div>
@@ -35,6 +39,52 @@ function Header({ isOnline, username, onConnectionClick }) {
               </div>
             </>
           )}
+
+          {/* Connection health, shown on hover */}
+          {health && (
+            <div className="absolute right-0 top-full mt-2 w-72 p-4 rounded-lg bg-cyber-darker border border-purple-900/30 text-sm space-y-2 hidden group-hover:block z-50">
+              <div className="flex justify-between">
+                <span className="text-gray-400">Last heartbeat</span>
+                <span className="text-white">
+                  {health.last_heartbeat ? `${secondsAgo(health.last_heartbeat)}s ago` : 'none yet'}
+                </span>
+              </div>
+              <div className="flex justify-between">
+                <span className="text-gray-400">Failures in a row</span>
+                <span className={unstable ? 'text-yellow-400' : 'text-white'}>{health.heartbeat_failures}</span>
+              </div>
+              <div className="flex justify-between">
+                <span className="text-gray-400">P2P server</span>
+                <span className="text-white font-mono" title={health.p2p_address ? `Reachable at ${health.p2p_address}` : undefined}>
+                  {health.p2p_bind_address || '-'}
+                </span>
+              </div>
+              <div className="flex justify-between">
+                <span className="text-gray-400">Active transfers</span>
+                <span className="text-white">{health.active_transfers}</span>
+              </div>
+              {health.server_load && (
+                <div className="flex justify-between">
+                  <span className="text-gray-400">Connections</span>
+                  <span
+                    className={health.server_load.queued > 0 ? 'text-yellow-400' : 'text-white'}
+                    title={`${health.server_load.rejected_busy} turned away while busy (peak ${health.server_load.peak_active})`}
+                  >
+                    {health.server_load.active}/{health.server_load.max_concurrent}
+                    {health.server_load.queued > 0 && ` +${health.server_load.queued} queued`}
+                  </span>
+                </div>
+              )}
+              <div>
+                <span className="text-gray-400">Responsive directories</span>
+                {health.responsive_directories.length === 0 ? (
+                  <p className="text-yellow-400">none confirmed</p>
+                ) : health.responsive_directories.map(address => (
+                  <p key={address} className="text-white font-mono">{address}</p>
+                ))}
+              </div>
+            </div>
+          )}
         </div>
 
         {/* Connection button */}
@@ -43,15 +93,15 @@ function Header({ isOnline, username, onConnectionClick }) {
           whileTap={{ scale: 0.98 }}
           onClick={onConnectionClick}
           className={`flex items-center gap-2 px-4 py-2 rounded-lg font-medium transition-all cyber-button ${
-            isOnline
+            isOnline || reconnecting
               ? 'bg-red-600/20 border border-red-500/30 text-red-400 hover:bg-red-600/30'
               : 'bg-gradient-to-r from-purple-600 to-pink-600 text-white glow-purple'
           }`}
         >
-          {isOnline ? (
+          {isOnline || reconnecting ? (
             <>
               <WifiOff className="w-4 h-4" />
-              <span>Disconnect</span>
+              <span>{reconnecting ? 'Stop Reconnecting' : 'Disconnect'}</span>
             </>
           ) : (
             <>
@@ -1,22 +1,119 @@
-import React, { useState } from 'react';
+import React, { useState, useEffect } from 'react';
 import { motion, AnimatePresence } from 'framer-motion';
-import { convertFileSrc } from '@tauri-apps/api/core';
+import { convertFileSrc, invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import {
   Image, Upload, Lock, Unlock, Eye, Edit, Trash2,
-  HardDrive, Download, Search,
-  RefreshCw, Shield, WifiOff, X
+  HardDrive, Download, Search, CheckSquare,
+  RefreshCw, Shield, WifiOff, X, AlertTriangle, Printer, FolderInput,
+  ArrowUp, ArrowDown, ChevronLeft, ChevronRight, Package, QrCode
 } from 'lucide-react';
+import { translate } from '../i18n';
 
-function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt, onUpdatePermissions, onRefresh, onViewImage, onDeleteImage, loading, isOnline }) {
+// What viewers may do with a decoded image unless the owner restricts it
+const DEFAULT_VIEW_POLICY = { allow_save: true, allow_print: true, watermark: false };
+
+const VIEW_POLICY_FIELDS = [
+  { key: 'allow_save', label: 'Viewers may save the image' },
+  { key: 'allow_print', label: 'Viewers may print the image' },
+  { key: 'watermark', label: "Stamp the viewer's name and time on every view" },
+];
+
+const RECEIVED_SORTS = [
+  { value: 'name', label: 'Name' },
+  { value: 'owner', label: 'Owner' },
+  { value: 'date', label: 'Date received' },
+  { value: 'views', label: 'Views left' },
+];
+
+// Quota changes embedded in an image, newest first
+function PermissionHistory({ imageId }) {
+  const [history, setHistory] = useState(null);
+
+  useEffect(() => {
+    setHistory(null);
+    invoke('get_permission_history', { imageId })
+      .then(response => setHistory(response.data || []))
+      .catch(() => setHistory([]));
+  }, [imageId]);
+
+  return (
+    <div className="p-4 rounded-lg bg-white/5 border border-purple-900/20 space-y-2">
+      <p className="text-sm text-gray-400">Permission History</p>
+      {history === null ? (
+        <p className="text-xs text-gray-500">Loading...</p>
+      ) : history.length === 0 ? (
+        <p className="text-xs text-gray-500">No changes recorded in this image</p>
+      ) : (
+        <ul className="space-y-1 max-h-40 overflow-y-auto">
+          {history.map((change, index) => (
+            <li key={index} className="flex items-center justify-between gap-2 text-xs">
+              <span className={change.revocation ? 'text-red-400' : 'text-gray-300'}>
+                {change.changed_by} {change.revocation ? 'revoked' : 'set'} {change.user}: {change.before ?? '-'} → {change.after}
+              </span>
+              <span
+                className="text-gray-500 whitespace-nowrap"
+                title={change.at ? new Date(change.at * 1000).toLocaleString() : undefined}
+              >
+                {change.ago}
+              </span>
+            </li>
+          ))}
+        </ul>
+      )}
+    </div>
+  );
+}
+
+// ImageVisibility variants, as the backend names them
+const VISIBILITY_OPTIONS = [
+  ['Public', 'Public'],
+  ['ContactsOnly', 'Contacts only'],
+  ['LinkOnly', 'Link only'],
+  ['Hidden', 'Hidden'],
+];
+
+// Whole seconds from now until a serialized SystemTime, never negative
+const secondsUntil = (time) => Math.max(0, Math.round(time.secs_since_epoch - Date.now() / 1000));
+
+function ImagesPanel({ localImages, receivedImages, receivedTotal = 0, receivedQuery, receivedPageSize, onReceivedQueryChange, onRescanReceived, encryptedImages, denialStats = {}, onEncrypt, onEncryptBatch, onImportLibrary, onUpdatePermissions, onPreviewPermissions, onRemoteWipe, onExportGrant, onImportGrant, onCreatePairingCode, onCancelPairingCode, onRedeemPairing, onSetHolder, onSetViewPolicy, onSetVisibility, onSetEscrowPreview, onSetGrantCap, onRefresh, onViewImage, onPeekImage, onDeleteImage, onDeleteBatch, loading, isOnline }) {
   const [activeTab, setActiveTab] = useState('local');
   const [searchTerm, setSearchTerm] = useState('');
   const [selectedImage, setSelectedImage] = useState(null);
   const [permissionModal, setPermissionModal] = useState(null);
   const [newQuota, setNewQuota] = useState(5);
   const [targetUser, setTargetUser] = useState('');
+  const [quotaPreview, setQuotaPreview] = useState(null); // dry run of the permission change
   const [viewingImage, setViewingImage] = useState(null);
-  const [viewedImagePath, setViewedImagePath] = useState(null);
+  const [viewedImageData, setViewedImageData] = useState(null);
+  const [viewedAnnotations, setViewedAnnotations] = useState(null);
+  const [encryptModal, setEncryptModal] = useState(null);
+  const [annotations, setAnnotations] = useState({ caption: '', alt_text: '', license: '' });
   const [deleteConfirmModal, setDeleteConfirmModal] = useState(null);
+  const [viewConfirmModal, setViewConfirmModal] = useState(null); // { image, preview } before a view is used up
+  const [carrierAnalysis, setCarrierAnalysis] = useState(null); // { analysis, message } for the encrypt modal
+  const [encryptPolicy, setEncryptPolicy] = useState(DEFAULT_VIEW_POLICY);
+  const [optimizeCarrier, setOptimizeCarrier] = useState(false);
+  const [viewPolicy, setViewPolicy] = useState(null); // current policy of the image in the permission modal
+  const [viewedPolicy, setViewedPolicy] = useState(null);
+  const [importModal, setImportModal] = useState(null); // { path, albums }
+  const [grantFileModal, setGrantFileModal] = useState(null); // export: { imageId, recipient, views, outputDir, passphrase }, import: { path, passphrase }
+  const [pairingModal, setPairingModal] = useState(null); // owner: { code, link, qr_svg, views, image_id, expires_at }, requester: { pairing }
+  const [pairingSecondsLeft, setPairingSecondsLeft] = useState(0);
+  const [selectedPaths, setSelectedPaths] = useState([]); // multi-select on the current tab
+  const [batchBusy, setBatchBusy] = useState(false);
+  const [encryptionProgress, setEncryptionProgress] = useState(null); // of a running batch encryption
+
+  // Check the image will fit the carrier before the user commits to encrypting it
+  useEffect(() => {
+    setCarrierAnalysis(null);
+    if (!encryptModal) return;
+    invoke('analyze_carrier', { imagePath: encryptModal.file_path, annotations: null, carrierPath: null })
+      .then(response => setCarrierAnalysis({ analysis: response.data, message: translate(response) }))
+      .catch(error => setCarrierAnalysis({ analysis: null, message: `Analysis failed: ${error}` }));
+  }, [encryptModal]);
+
+  const carrierTooSmall = carrierAnalysis?.analysis && !carrierAnalysis.analysis.fits;
 
   const filteredLocalImages = localImages.filter(img =>
     img.file_name.toLowerCase().includes(searchTerm.toLowerCase())
@@ -30,14 +127,151 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
     img.file_name.toLowerCase().includes(searchTerm.toLowerCase())
   );
 
+  // Selections don't carry over between tabs
+  useEffect(() => {
+    setSelectedPaths([]);
+  }, [activeTab]);
+
+  const visibleImages = activeTab === 'local'
+    ? filteredLocalImages
+    : activeTab === 'encrypted' ? filteredEncryptedImages : filteredReceivedImages;
+  const selectedImages = visibleImages.filter(img => selectedPaths.includes(img.file_path));
+  const allSelected = visibleImages.length > 0 && selectedImages.length === visibleImages.length;
+  const selectedToEncrypt = selectedImages.filter(img => !img.is_encrypted);
+
+  const toggleSelected = (filePath) => {
+    setSelectedPaths(prev => prev.includes(filePath)
+      ? prev.filter(p => p !== filePath)
+      : [...prev, filePath]);
+  };
+
+  const toggleSelectAll = () => {
+    setSelectedPaths(allSelected ? [] : visibleImages.map(img => img.file_path));
+  };
+
+  const handleEncryptSelected = async () => {
+    setBatchBusy(true);
+    const unlisten = listen('state-event', ({ payload }) => {
+      if (payload.event === 'encryption_progress') setEncryptionProgress(payload);
+    });
+    await onEncryptBatch(selectedToEncrypt.map(img => img.file_path));
+    unlisten.then(stop => stop());
+    setEncryptionProgress(null);
+    setBatchBusy(false);
+    setSelectedPaths([]);
+  };
+
+  const selectionBox = (image) => (
+    <label
+      className="absolute top-2 left-2 p-1 rounded-md bg-black/50 cursor-pointer"
+      onClick={(e) => e.stopPropagation()}
+    >
+      <input
+        type="checkbox"
+        checked={selectedPaths.includes(image.file_path)}
+        onChange={() => toggleSelected(image.file_path)}
+        className="accent-purple-500"
+      />
+    </label>
+  );
+
 
-  const handleEncrypt = async (imagePath) => {
-    const result = await onEncrypt(imagePath);
+  const handleEncrypt = async () => {
+    // Blank fields are sent as null so they aren't embedded
+    const trimmed = Object.fromEntries(
+      Object.entries(annotations).map(([key, value]) => [key, value.trim() || null])
+    );
+    const result = await onEncrypt(encryptModal.file_path, trimmed, encryptPolicy, optimizeCarrier);
+    setEncryptModal(null);
+    setAnnotations({ caption: '', alt_text: '', license: '' });
+    setEncryptPolicy(DEFAULT_VIEW_POLICY);
+    setOptimizeCarrier(false);
     if (result) {
       setSelectedImage(null);
     }
   };
 
+  const handleImportLibrary = async () => {
+    if (importModal?.path.trim() && await onImportLibrary(importModal.path.trim(), importModal.albums)) {
+      setImportModal(null);
+    }
+  };
+
+  const handleExportToFile = () => {
+    if (permissionModal && targetUser) {
+      setGrantFileModal({ imageId: permissionModal.image_id, recipient: targetUser, views: newQuota, outputDir: '', passphrase: '' });
+      setPermissionModal(null);
+    }
+  };
+
+  const handleGrantFile = async () => {
+    const modal = grantFileModal;
+    const done = modal.imageId
+      ? await onExportGrant(modal.imageId, modal.recipient, modal.views, modal.passphrase, modal.outputDir.trim())
+      : await onImportGrant(modal.path.trim(), modal.passphrase);
+    if (done) {
+      setGrantFileModal(null);
+    }
+  };
+
+  const handlePairInPerson = async () => {
+    if (permissionModal && newQuota > 0) {
+      const pairing = await onCreatePairingCode(permissionModal.image_id, newQuota);
+      if (pairing) {
+        setPairingModal(pairing);
+        setPairingSecondsLeft(secondsUntil(pairing.expires_at));
+        setPermissionModal(null);
+      }
+    }
+  };
+
+  const closePairingModal = () => {
+    // A code nobody needs any more shouldn't stay redeemable
+    if (pairingModal?.code && pairingSecondsLeft > 0) {
+      onCancelPairingCode(pairingModal.code);
+    }
+    setPairingModal(null);
+  };
+
+  const handleRedeemPairing = async () => {
+    if (pairingModal?.pairing.trim() && await onRedeemPairing(pairingModal.pairing.trim())) {
+      setPairingModal(null);
+    }
+  };
+
+  // Count an open pairing code down to its expiry
+  useEffect(() => {
+    if (!pairingModal?.code) return;
+    const timer = setInterval(() => setPairingSecondsLeft(secondsUntil(pairingModal.expires_at)), 1000);
+    return () => clearInterval(timer);
+  }, [pairingModal]);
+
+  // Load the image's viewer rights when its permission modal opens
+  useEffect(() => {
+    setViewPolicy(null);
+    if (!permissionModal) return;
+    invoke('get_view_policy', { imageId: permissionModal.image_id })
+      .then(response => setViewPolicy(response.data || DEFAULT_VIEW_POLICY))
+      .catch(() => setViewPolicy(DEFAULT_VIEW_POLICY));
+  }, [permissionModal]);
+
+  const handleSaveViewPolicy = () => {
+    if (permissionModal && viewPolicy) {
+      onSetViewPolicy(permissionModal.image_id, viewPolicy);
+    }
+  };
+
+  // A preview is only valid for the user and quota it was made with
+  useEffect(() => {
+    setQuotaPreview(null);
+  }, [permissionModal, targetUser, newQuota]);
+
+  const handlePreviewPermissions = async () => {
+    if (permissionModal && targetUser) {
+      setQuotaPreview(await onPreviewPermissions(targetUser, permissionModal.image_id, newQuota));
+    }
+  };
+
   const handleUpdatePermissions = () => {
     if (permissionModal && targetUser) {
       onUpdatePermissions(targetUser, permissionModal.image_id, newQuota);
@@ -47,34 +281,115 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
     }
   };
 
+  const handleRemoteWipe = () => {
+    if (permissionModal && targetUser) {
+      onRemoteWipe(targetUser, permissionModal.image_id);
+      setPermissionModal(null);
+      setTargetUser('');
+      setNewQuota(5);
+    }
+  };
+
+  const handleSetHolder = (enabled) => {
+    if (permissionModal && targetUser) {
+      onSetHolder(permissionModal.image_id, targetUser, enabled);
+      setPermissionModal(null);
+      setTargetUser('');
+      setNewQuota(5);
+    }
+  };
+
+  const showCover = (image) => {
+    // No views remaining, show the cover image (the encrypted carrier)
+    setViewingImage({...image, views_remaining: 0});
+    setViewedImageData(null); // Will display the cover/carrier image
+  };
+
   const handleViewImage = async (image) => {
     if (image.views_remaining <= 0) {
-      // No views remaining, show the cover image (the encrypted carrier)
-      setViewingImage({...image, views_remaining: 0});
-      setViewedImagePath(null); // Will display the cover/carrier image
+      showCover(image);
       return;
     }
-    
+
+    // Check the permissions first so the user can decide before a view is used up
+    const preview = await onPeekImage(image.file_path);
+    if (!preview) return;
+    if (preview.denial) {
+      showCover(image);
+    } else if (preview.is_owner) {
+      await openImage(image);
+    } else {
+      setViewConfirmModal({ image, preview });
+    }
+  };
+
+  const handleConfirmView = async () => {
+    const { image } = viewConfirmModal;
+    setViewConfirmModal(null);
+    await openImage(image);
+  };
+
+  const openImage = async (image) => {
     // Attempt to view the image (decrements quota)
-    const viewablePath = await onViewImage(image.file_path);
-    if (viewablePath) {
+    const viewed = await onViewImage(image.file_path);
+    if (viewed) {
       // Successfully viewed - update the views count in the modal
-      setViewingImage({...image, views_remaining: image.views_remaining - 1});
-      setViewedImagePath(viewablePath);
+      setViewingImage({...image, views_remaining: image.views_remaining - 1, provenance: viewed.provenance ?? image.provenance});
+      setViewedImageData(viewed.image);
+      setViewedAnnotations(viewed.annotations);
+      setViewedPolicy(viewed.policy || DEFAULT_VIEW_POLICY);
     } else {
       // Access denied - show the cover image
       setViewingImage({...image, views_remaining: 0});
-      setViewedImagePath(null);
+      setViewedImageData(null);
     }
   };
 
   const closeImageViewer = () => {
     setViewingImage(null);
-    setViewedImagePath(null);
+    setViewedImageData(null);
+    setViewedAnnotations(null);
+    setViewedPolicy(null);
+  };
+
+  // Drop the decoded image the moment its owner revokes it, not at the next refresh
+  useEffect(() => {
+    if (!viewingImage) return;
+    const unlisten = listen('state-event', ({ payload }) => {
+      if (payload.event === 'view_revoked'
+          && payload.from_owner === viewingImage.from_owner
+          && payload.image_id === viewingImage.image_id) {
+        closeImageViewer();
+      }
+    });
+    return () => { unlisten.then(stop => stop()); };
+  }, [viewingImage]);
+
+  const handleSaveViewed = () => {
+    const link = document.createElement('a');
+    link.href = viewedImageData;
+    link.download = `viewed_${viewingImage.file_name}`;
+    link.click();
+  };
+
+  // Print just the image, from a hidden frame so the rest of the app stays off the page
+  const handlePrintViewed = () => {
+    const frame = document.createElement('iframe');
+    frame.style.display = 'none';
+    document.body.appendChild(frame);
+    frame.contentDocument.write(`<img src="${viewedImageData}" style="max-width:100%" onload="window.print()">`);
+    frame.contentDocument.close();
+    setTimeout(() => frame.remove(), 60000);
   };
 
   const handleDeleteConfirm = async () => {
-    if (deleteConfirmModal) {
+    if (deleteConfirmModal?.paths) {
+      setBatchBusy(true);
+      await onDeleteBatch(deleteConfirmModal.paths, deleteConfirmModal.type);
+      setBatchBusy(false);
+      setSelectedPaths([]);
+      setDeleteConfirmModal(null);
+    } else if (deleteConfirmModal) {
       await onDeleteImage(deleteConfirmModal.file_path, deleteConfirmModal.type);
       setDeleteConfirmModal(null);
     }
@@ -151,7 +466,7 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
             <Download className="w-4 h-4" />
             Received Images
             <span className="px-2 py-0.5 text-xs rounded-full bg-cyan-600/20 text-cyan-400">
-              {receivedImages.length}
+              {receivedTotal}
             </span>
           </div>
           {activeTab === 'received' && (
@@ -187,8 +502,128 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
             Refresh
           </motion.button>
         )}
+        {activeTab === 'local' && (
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={() => setImportModal({ path: '', albums: true })}
+            className="flex items-center gap-2 px-4 py-3 rounded-xl bg-purple-600/20 border border-purple-500/30 text-purple-400 hover:bg-purple-600/30 transition-colors"
+          >
+            <FolderInput className="w-4 h-4" />
+            Import Library
+          </motion.button>
+        )}
+        {activeTab === 'received' && (
+          <>
+            <select
+              value={receivedQuery.sortBy}
+              onChange={(e) => onReceivedQueryChange({ sortBy: e.target.value, offset: 0 })}
+              className="px-4 py-3 rounded-xl cyber-input text-white"
+              title="Sort received images"
+            >
+              {RECEIVED_SORTS.map(sort => (
+                <option key={sort.value} value={sort.value}>{sort.label}</option>
+              ))}
+            </select>
+            <button
+              onClick={() => onReceivedQueryChange({ descending: !receivedQuery.descending, offset: 0 })}
+              className="p-3 rounded-xl bg-cyan-600/20 border border-cyan-500/30 text-cyan-400 hover:bg-cyan-600/30 transition-colors"
+              title={receivedQuery.descending ? 'Descending' : 'Ascending'}
+            >
+              {receivedQuery.descending ? <ArrowDown className="w-4 h-4" /> : <ArrowUp className="w-4 h-4" />}
+            </button>
+            <motion.button
+              whileHover={{ scale: 1.02 }}
+              whileTap={{ scale: 0.98 }}
+              onClick={onRescanReceived}
+              disabled={loading}
+              className="flex items-center gap-2 px-4 py-3 rounded-xl bg-cyan-600/20 border border-cyan-500/30 text-cyan-400 hover:bg-cyan-600/30 transition-colors disabled:opacity-50"
+              title="Decode every received image again instead of using the index"
+            >
+              <RefreshCw className={`w-4 h-4 ${loading ? 'animate-spin' : ''}`} />
+              Rescan
+            </motion.button>
+            <motion.button
+              whileHover={{ scale: 1.02 }}
+              whileTap={{ scale: 0.98 }}
+              onClick={() => setGrantFileModal({ path: '', passphrase: '' })}
+              className="flex items-center gap-2 px-4 py-3 rounded-xl bg-purple-600/20 border border-purple-500/30 text-purple-400 hover:bg-purple-600/30 transition-colors"
+              title="Import an image someone gave you on a USB stick or other removable media"
+            >
+              <Package className="w-4 h-4" />
+              Import Grant
+            </motion.button>
+            <motion.button
+              whileHover={{ scale: 1.02 }}
+              whileTap={{ scale: 0.98 }}
+              onClick={() => setPairingModal({ pairing: '' })}
+              disabled={!isOnline}
+              className="flex items-center gap-2 px-4 py-3 rounded-xl bg-purple-600/20 border border-purple-500/30 text-purple-400 hover:bg-purple-600/30 transition-colors disabled:opacity-50"
+              title="Enter the pairing code (or scanned link) an owner is showing you on their device"
+            >
+              <QrCode className="w-4 h-4" />
+              Redeem Code
+            </motion.button>
+          </>
+        )}
       </div>
 
+      {/* Multi-select */}
+      {visibleImages.length > 0 && (
+        <div className="flex items-center gap-3 text-sm">
+          <label className="flex items-center gap-2 text-gray-300 cursor-pointer">
+            <input
+              type="checkbox"
+              checked={allSelected}
+              onChange={toggleSelectAll}
+              className="accent-purple-500"
+            />
+            Select all ({visibleImages.length})
+          </label>
+          {selectedImages.length > 0 && (
+            <>
+              <span className="text-gray-500">{selectedImages.length} selected</span>
+              {activeTab === 'local' && isOnline && selectedToEncrypt.length > 0 && (
+                <button
+                  onClick={handleEncryptSelected}
+                  disabled={batchBusy}
+                  className="flex items-center gap-2 px-3 py-1.5 rounded-lg bg-purple-600/20 border border-purple-500/30 text-purple-400 hover:bg-purple-600/30 transition-colors disabled:opacity-50"
+                >
+                  <Shield className="w-4 h-4" />
+                  Encrypt {selectedToEncrypt.length}
+                </button>
+              )}
+              {encryptionProgress && (
+                <span className="text-gray-400">
+                  {encryptionProgress.completed + encryptionProgress.failed}/{encryptionProgress.total} encrypted
+                  {' '}across {encryptionProgress.servers} server{encryptionProgress.servers === 1 ? '' : 's'}
+                  {encryptionProgress.failed > 0 && <span className="text-red-400"> ({encryptionProgress.failed} failed)</span>}
+                </span>
+              )}
+              <button
+                onClick={() => setDeleteConfirmModal({
+                  file_name: `${selectedImages.length} image${selectedImages.length === 1 ? '' : 's'}`,
+                  paths: selectedImages.map(img => img.file_path),
+                  type: activeTab,
+                })}
+                disabled={batchBusy}
+                className="flex items-center gap-2 px-3 py-1.5 rounded-lg bg-red-600/20 border border-red-500/30 text-red-400 hover:bg-red-600/30 transition-colors disabled:opacity-50"
+              >
+                <Trash2 className="w-4 h-4" />
+                Delete {selectedImages.length}
+              </button>
+              <button
+                onClick={() => setSelectedPaths([])}
+                className="flex items-center gap-1 text-gray-400 hover:text-white transition-colors"
+              >
+                <CheckSquare className="w-4 h-4" />
+                Clear
+              </button>
+            </>
+          )}
+        </div>
+      )}
+
       {/* Content */}
       <AnimatePresence mode="wait">
         {activeTab === 'local' ? (
@@ -210,15 +645,16 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
               <div className="grid grid-cols-1 md:grid-cols-2 lg:grid-cols-3 gap-4">
                 {filteredLocalImages.map((image, index) => (
                   <motion.div
-                    key={image.image_id}
+                    key={image.file_path}
                     initial={{ opacity: 0, y: 20 }}
                     animate={{ opacity: 1, y: 0 }}
                     transition={{ delay: index * 0.05 }}
                     className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm overflow-hidden"
                   >
                     {/* Image preview placeholder */}
-                    <div className="h-40 bg-gradient-to-br from-purple-900/40 to-pink-900/40 flex items-center justify-center">
+                    <div className="relative h-40 bg-gradient-to-br from-purple-900/40 to-pink-900/40 flex items-center justify-center">
                       <Image className="w-16 h-16 text-purple-400/50" />
+                      {selectionBox(image)}
                     </div>
                     
                     <div className="p-4">
@@ -227,7 +663,10 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                           <h3 className="font-medium text-white truncate" title={image.file_name}>
                             {image.file_name}
                           </h3>
-                          <p className="text-sm text-gray-400">{image.file_size_kb} KB</p>
+                          <p className="text-sm text-gray-400">
+                            {image.file_size_kb} KB
+                            {image.origin && <span className="ml-2 text-xs text-purple-300">📁 {image.origin}</span>}
+                          </p>
                         </div>
                         {image.is_encrypted ? (
                           <div className="p-1.5 rounded-lg bg-green-600/20">
@@ -245,7 +684,7 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                           <motion.button
                             whileHover={{ scale: 1.02 }}
                             whileTap={{ scale: 0.98 }}
-                            onClick={() => handleEncrypt(image.file_path)}
+                            onClick={() => setEncryptModal(image)}
                             className="flex-1 flex items-center justify-center gap-2 px-3 py-2 rounded-lg bg-purple-600/20 border border-purple-500/30 text-purple-400 text-sm hover:bg-purple-600/30 transition-colors"
                           >
                             <Shield className="w-4 h-4" />
@@ -303,8 +742,9 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                     className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm overflow-hidden"
                   >
                     {/* Image preview placeholder */}
-                    <div className="h-40 bg-gradient-to-br from-green-900/40 to-emerald-900/40 flex items-center justify-center">
+                    <div className="relative h-40 bg-gradient-to-br from-green-900/40 to-emerald-900/40 flex items-center justify-center">
                       <Shield className="w-16 h-16 text-green-400/50" />
+                      {selectionBox(image)}
                     </div>
 
                     <div className="p-4">
@@ -313,13 +753,80 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                           <h3 className="font-medium text-white truncate" title={image.file_name}>
                             {image.file_name}
                           </h3>
-                          <p className="text-sm text-gray-400">{image.file_size_kb} KB</p>
+                          <p className="text-sm text-gray-400">
+                            {image.file_size_kb} KB
+                            {image.origin && <span className="ml-2 text-xs text-purple-300">📁 {image.origin}</span>}
+                          </p>
+                          {denialStats[image.image_id] && (
+                            <p
+                              className="text-xs text-red-400 mt-1"
+                              title={Object.entries(denialStats[image.image_id].by_viewer)
+                                .map(([viewer, count]) => `${viewer} x${count}`)
+                                .join(', ') + ` (last ${denialStats[image.image_id].last_denied})`}
+                            >
+                              🛡 {denialStats[image.image_id].total} denied
+                            </p>
+                          )}
                         </div>
                         <div className="p-1.5 rounded-lg bg-green-600/20">
                           <Lock className="w-4 h-4 text-green-400" />
                         </div>
                       </div>
 
+                      {/* Who can find and request it */}
+                      <select
+                        value={image.visibility || 'Public'}
+                        onChange={(e) => onSetVisibility(image.image_id, e.target.value)}
+                        className="w-full mt-2 px-2 py-1 rounded-lg cyber-input text-white text-xs"
+                        title="Who can find and request this image"
+                      >
+                        {VISIBILITY_OPTIONS.map(([value, label]) => (
+                          <option key={value} value={value}>{label}</option>
+                        ))}
+                      </select>
+                      {image.share_link && (
+                        <button
+                          onClick={() => navigator.clipboard.writeText(image.share_link)}
+                          className="w-full mt-1 text-xs text-cyan-400 truncate text-left hover:underline"
+                          title={`Copy share link: ${image.share_link}`}
+                        >
+                          🔗 Copy share link
+                        </button>
+                      )}
+                      <label
+                        className="flex items-center gap-2 mt-1 text-xs text-gray-400 cursor-pointer"
+                        title="Leave a blurred preview with the directory, so peers can browse this image while you're offline"
+                      >
+                        <input
+                          type="checkbox"
+                          checked={!!image.escrow_preview}
+                          onChange={(e) => onSetEscrowPreview(image.image_id, e.target.checked)}
+                          className="accent-cyan-500"
+                        />
+                        Browsable while offline
+                      </label>
+                      <label
+                        className="flex items-center gap-2 mt-1 text-xs text-gray-400"
+                        title="Most views one request may be granted (empty for the default)"
+                      >
+                        Max views per grant
+                        <input
+                          type="number"
+                          min="1"
+                          key={`${image.image_id}-${image.max_grant_views ?? ''}`}
+                          defaultValue={image.max_grant_views ?? ''}
+                          placeholder="No cap"
+                          onBlur={(e) => {
+                            const value = e.target.value.trim();
+                            const maxViews = value === '' ? null : parseInt(value, 10);
+                            if (!Number.isNaN(maxViews) && maxViews !== (image.max_grant_views ?? null)) {
+                              onSetGrantCap(image.image_id, maxViews);
+                            }
+                          }}
+                          className="w-16 px-2 py-0.5 rounded-lg cyber-input text-white text-xs"
+                        />
+                      </label>
+
                       <div className="flex items-center gap-2 mt-4">
                         <motion.button
                           whileHover={{ scale: 1.02 }}
@@ -372,8 +879,9 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                     className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm overflow-hidden"
                   >
                     {/* Image preview placeholder */}
-                    <div className="h-40 bg-gradient-to-br from-cyan-900/40 to-blue-900/40 flex items-center justify-center">
+                    <div className="relative h-40 bg-gradient-to-br from-cyan-900/40 to-blue-900/40 flex items-center justify-center">
                       <Image className="w-16 h-16 text-cyan-400/50" />
+                      {selectionBox(image)}
                     </div>
                     
                     <div className="p-4">
@@ -383,6 +891,11 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                             {image.file_name}
                           </h3>
                           <p className="text-sm text-gray-400">From: {image.from_owner}</p>
+                          {image.provenance && (
+                            <p className="text-xs text-gray-500 truncate" title={image.provenance}>
+                              Via: {image.provenance}
+                            </p>
+                          )}
                         </div>
                         <div className="flex items-center gap-1 px-2 py-1 rounded-lg bg-cyan-600/20">
                           <Eye className="w-3 h-3 text-cyan-400" />
@@ -423,6 +936,369 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                 ))}
               </div>
             )}
+
+            {receivedTotal > receivedPageSize && (
+              <div className="flex items-center justify-center gap-4 mt-6 text-sm text-gray-400">
+                <button
+                  onClick={() => onReceivedQueryChange({ offset: Math.max(0, receivedQuery.offset - receivedPageSize) })}
+                  disabled={receivedQuery.offset === 0}
+                  className="p-2 rounded-lg bg-cyan-600/20 text-cyan-400 hover:bg-cyan-600/30 disabled:opacity-30"
+                >
+                  <ChevronLeft className="w-4 h-4" />
+                </button>
+                <span>
+                  {receivedQuery.offset + 1}-{Math.min(receivedQuery.offset + receivedPageSize, receivedTotal)} of {receivedTotal}
+                </span>
+                <button
+                  onClick={() => onReceivedQueryChange({ offset: receivedQuery.offset + receivedPageSize })}
+                  disabled={receivedQuery.offset + receivedPageSize >= receivedTotal}
+                  className="p-2 rounded-lg bg-cyan-600/20 text-cyan-400 hover:bg-cyan-600/30 disabled:opacity-30"
+                >
+                  <ChevronRight className="w-4 h-4" />
+                </button>
+              </div>
+            )}
+          </motion.div>
+        )}
+      </AnimatePresence>
+
+      {/* Encrypt Modal */}
+      <AnimatePresence>
+        {encryptModal && (
+          <motion.div
+            initial={{ opacity: 0 }}
+            animate={{ opacity: 1 }}
+            exit={{ opacity: 0 }}
+            className="fixed inset-0 z-50 flex items-center justify-center modal-backdrop"
+            onClick={() => setEncryptModal(null)}
+          >
+            <motion.div
+              initial={{ scale: 0.9, opacity: 0 }}
+              animate={{ scale: 1, opacity: 1 }}
+              exit={{ scale: 0.9, opacity: 0 }}
+              onClick={(e) => e.stopPropagation()}
+              className="bg-cyber-darker border border-purple-500/30 rounded-2xl p-6 w-full max-w-md glow-purple"
+            >
+              <h3 className="text-xl font-display font-bold text-white mb-4">Encrypt Image</h3>
+
+              <div className="space-y-4">
+                <div className="p-4 rounded-lg bg-white/5 border border-purple-900/20">
+                  <p className="text-sm text-gray-400">Image</p>
+                  <p className="text-white font-medium">{encryptModal.file_name}</p>
+                </div>
+
+                {/* Carrier analysis */}
+                <div className="p-4 rounded-lg bg-white/5 border border-purple-900/20 text-sm">
+                  {!carrierAnalysis ? (
+                    <p className="text-gray-500">Checking carrier capacity...</p>
+                  ) : !carrierAnalysis.analysis ? (
+                    <p className="text-yellow-400">{carrierAnalysis.message}</p>
+                  ) : (
+                    <>
+                      <div className="flex items-center justify-between text-gray-400 mb-2">
+                        <span>Carrier {carrierAnalysis.analysis.width}×{carrierAnalysis.analysis.height} {carrierAnalysis.analysis.format}</span>
+                        <span className={carrierAnalysis.analysis.fits ? 'text-green-400' : 'text-red-400'}>
+                          {(carrierAnalysis.analysis.payload_bytes / 1024).toFixed(1)} / {(carrierAnalysis.analysis.capacity_bytes / 1024).toFixed(1)} KB
+                        </span>
+                      </div>
+                      <div className="h-2 rounded-full bg-white/10 overflow-hidden">
+                        <div
+                          className={`h-full ${carrierAnalysis.analysis.fits ? 'bg-green-500' : 'bg-red-500'}`}
+                          style={{ width: `${Math.min(100, carrierAnalysis.analysis.payload_bytes * 100 / carrierAnalysis.analysis.capacity_bytes)}%` }}
+                        />
+                      </div>
+                      {carrierAnalysis.analysis.warnings.map((warning, index) => (
+                        <p key={index} className="flex items-start gap-2 mt-2 text-yellow-400">
+                          <AlertTriangle className="w-4 h-4 flex-shrink-0 mt-0.5" />
+                          {warning}
+                        </p>
+                      ))}
+                    </>
+                  )}
+                </div>
+
+                {[
+                  { key: 'caption', label: 'Caption', placeholder: 'Shown to recipients and searchable by peers' },
+                  { key: 'alt_text', label: 'Alt Text', placeholder: 'Describe the image' },
+                  { key: 'license', label: 'License', placeholder: 'e.g., CC-BY-4.0' },
+                ].map(field => (
+                  <div key={field.key}>
+                    <label className="block text-sm text-gray-400 mb-2">
+                      {field.label} <span className="text-gray-600">(optional)</span>
+                    </label>
+                    <input
+                      type="text"
+                      value={annotations[field.key]}
+                      onChange={(e) => setAnnotations({ ...annotations, [field.key]: e.target.value })}
+                      placeholder={field.placeholder}
+                      className="w-full px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500"
+                    />
+                  </div>
+                ))}
+
+                <div className="space-y-2">
+                  <p className="text-sm text-gray-400">Viewer Rights</p>
+                  {VIEW_POLICY_FIELDS.map(field => (
+                    <label key={field.key} className="flex items-center gap-2 text-sm text-gray-300">
+                      <input
+                        type="checkbox"
+                        checked={encryptPolicy[field.key]}
+                        onChange={(e) => setEncryptPolicy({ ...encryptPolicy, [field.key]: e.target.checked })}
+                      />
+                      {field.label}
+                    </label>
+                  ))}
+                </div>
+
+                <label className="flex items-center gap-2 text-sm text-gray-300">
+                  <input
+                    type="checkbox"
+                    checked={optimizeCarrier}
+                    onChange={(e) => setOptimizeCarrier(e.target.checked)}
+                  />
+                  Shrink the encrypted file (lossless, slower)
+                </label>
+              </div>
+
+              <div className="flex gap-3 mt-6">
+                <button
+                  onClick={() => setEncryptModal(null)}
+                  className="flex-1 px-4 py-3 rounded-lg border border-purple-500/30 text-gray-400 hover:bg-white/5 transition-colors"
+                >
+                  Cancel
+                </button>
+                <motion.button
+                  whileHover={{ scale: 1.02 }}
+                  whileTap={{ scale: 0.98 }}
+                  onClick={handleEncrypt}
+                  disabled={carrierTooSmall}
+                  className="flex-1 px-4 py-3 rounded-lg text-white font-medium bg-gradient-to-r from-purple-600 to-pink-600 disabled:opacity-50"
+                >
+                  Encrypt
+                </motion.button>
+              </div>
+            </motion.div>
+          </motion.div>
+        )}
+      </AnimatePresence>
+
+      {/* Import Library Modal */}
+      <AnimatePresence>
+        {importModal && (
+          <motion.div
+            initial={{ opacity: 0 }}
+            animate={{ opacity: 1 }}
+            exit={{ opacity: 0 }}
+            className="fixed inset-0 z-50 flex items-center justify-center modal-backdrop"
+            onClick={() => setImportModal(null)}
+          >
+            <motion.div
+              initial={{ scale: 0.9, opacity: 0 }}
+              animate={{ scale: 1, opacity: 1 }}
+              exit={{ scale: 0.9, opacity: 0 }}
+              onClick={(e) => e.stopPropagation()}
+              className="bg-cyber-darker border border-purple-500/30 rounded-2xl p-6 w-full max-w-md glow-purple"
+            >
+              <h3 className="text-xl font-display font-bold text-white mb-4">Import Photo Library</h3>
+
+              <div className="space-y-4">
+                <p className="text-sm text-gray-400">
+                  Every photo in the folder and its subfolders is encrypted in the background and
+                  captioned from its EXIF caption, date and keywords.
+                </p>
+                <div>
+                  <label className="block text-sm text-gray-400 mb-2">Library Folder</label>
+                  <input
+                    type="text"
+                    value={importModal.path}
+                    onChange={(e) => setImportModal({ ...importModal, path: e.target.value })}
+                    placeholder="/home/me/Pictures"
+                    className="w-full px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500"
+                  />
+                </div>
+                <label className="flex items-center gap-2 text-sm text-gray-300">
+                  <input
+                    type="checkbox"
+                    checked={importModal.albums}
+                    onChange={(e) => setImportModal({ ...importModal, albums: e.target.checked })}
+                  />
+                  Group photos into albums named after their folders
+                </label>
+              </div>
+
+              <div className="flex gap-3 mt-6">
+                <button
+                  onClick={() => setImportModal(null)}
+                  className="flex-1 px-4 py-3 rounded-lg border border-purple-500/30 text-gray-400 hover:bg-white/5 transition-colors"
+                >
+                  Cancel
+                </button>
+                <motion.button
+                  whileHover={{ scale: 1.02 }}
+                  whileTap={{ scale: 0.98 }}
+                  onClick={handleImportLibrary}
+                  disabled={!importModal.path.trim()}
+                  className="flex-1 px-4 py-3 rounded-lg text-white font-medium bg-gradient-to-r from-purple-600 to-pink-600 disabled:opacity-50"
+                >
+                  Import
+                </motion.button>
+              </div>
+            </motion.div>
+          </motion.div>
+        )}
+      </AnimatePresence>
+
+      {/* Grant Package Modal */}
+      <AnimatePresence>
+        {grantFileModal && (
+          <motion.div
+            initial={{ opacity: 0 }}
+            animate={{ opacity: 1 }}
+            exit={{ opacity: 0 }}
+            className="fixed inset-0 z-50 flex items-center justify-center modal-backdrop"
+            onClick={() => setGrantFileModal(null)}
+          >
+            <motion.div
+              initial={{ scale: 0.9, opacity: 0 }}
+              animate={{ scale: 1, opacity: 1 }}
+              exit={{ scale: 0.9, opacity: 0 }}
+              onClick={(e) => e.stopPropagation()}
+              className="bg-cyber-darker border border-purple-500/30 rounded-2xl p-6 w-full max-w-md glow-purple"
+            >
+              <h3 className="text-xl font-display font-bold text-white mb-4">
+                {grantFileModal.imageId ? 'Export Grant to File' : 'Import Grant Package'}
+              </h3>
+
+              <div className="space-y-4">
+                <p className="text-sm text-gray-400">
+                  {grantFileModal.imageId
+                    ? `Grants ${grantFileModal.recipient} ${grantFileModal.views} views of ${grantFileModal.imageId} into a package file. Agree on the passphrase in person - they need it to import the package.`
+                    : 'Adds the image in a package someone handed you to your received images, as if they had sent it.'}
+                </p>
+                <div>
+                  <label className="block text-sm text-gray-400 mb-2">
+                    {grantFileModal.imageId ? 'Save To Folder' : 'Package File'}
+                  </label>
+                  <input
+                    type="text"
+                    value={grantFileModal.imageId ? grantFileModal.outputDir : grantFileModal.path}
+                    onChange={(e) => setGrantFileModal({
+                      ...grantFileModal,
+                      [grantFileModal.imageId ? 'outputDir' : 'path']: e.target.value,
+                    })}
+                    placeholder={grantFileModal.imageId ? '/media/usb' : '/media/usb/me_sunset.p2pgrant'}
+                    className="w-full px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500"
+                  />
+                </div>
+                <div>
+                  <label className="block text-sm text-gray-400 mb-2">Passphrase</label>
+                  <input
+                    type="password"
+                    value={grantFileModal.passphrase}
+                    onChange={(e) => setGrantFileModal({ ...grantFileModal, passphrase: e.target.value })}
+                    placeholder="At least 8 characters"
+                    className="w-full px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500"
+                  />
+                </div>
+              </div>
+
+              <div className="flex gap-3 mt-6">
+                <button
+                  onClick={() => setGrantFileModal(null)}
+                  className="flex-1 px-4 py-3 rounded-lg border border-purple-500/30 text-gray-400 hover:bg-white/5 transition-colors"
+                >
+                  Cancel
+                </button>
+                <motion.button
+                  whileHover={{ scale: 1.02 }}
+                  whileTap={{ scale: 0.98 }}
+                  onClick={handleGrantFile}
+                  disabled={!(grantFileModal.imageId ? grantFileModal.outputDir : grantFileModal.path).trim() || grantFileModal.passphrase.length < 8}
+                  className="flex-1 px-4 py-3 rounded-lg text-white font-medium bg-gradient-to-r from-purple-600 to-pink-600 disabled:opacity-50"
+                >
+                  {grantFileModal.imageId ? 'Export' : 'Import'}
+                </motion.button>
+              </div>
+            </motion.div>
+          </motion.div>
+        )}
+      </AnimatePresence>
+
+      {/* Pairing Modal */}
+      <AnimatePresence>
+        {pairingModal && (
+          <motion.div
+            initial={{ opacity: 0 }}
+            animate={{ opacity: 1 }}
+            exit={{ opacity: 0 }}
+            className="fixed inset-0 z-50 flex items-center justify-center modal-backdrop"
+            onClick={closePairingModal}
+          >
+            <motion.div
+              initial={{ scale: 0.9, opacity: 0 }}
+              animate={{ scale: 1, opacity: 1 }}
+              exit={{ scale: 0.9, opacity: 0 }}
+              onClick={(e) => e.stopPropagation()}
+              className="bg-cyber-darker border border-purple-500/30 rounded-2xl p-6 w-full max-w-md glow-purple"
+            >
+              <h3 className="text-xl font-display font-bold text-white mb-4">
+                {pairingModal.code ? 'Pair In Person' : 'Redeem Pairing Code'}
+              </h3>
+
+              {pairingModal.code ? (
+                <div className="space-y-4 text-center">
+                  <p className="text-sm text-gray-400">
+                    {`Whoever enters this code gets ${pairingModal.views} views of ${pairingModal.image_id}. Only show it to the person next to you - it works once.`}
+                  </p>
+                  <div
+                    className="mx-auto w-52 p-2 rounded-lg bg-white"
+                    dangerouslySetInnerHTML={{ __html: pairingModal.qr_svg }}
+                  />
+                  <p className="text-4xl font-display font-bold tracking-[0.3em] text-purple-300">
+                    {pairingModal.code}
+                  </p>
+                  <p className="text-xs text-gray-500 break-all">{pairingModal.link}</p>
+                  <p className={`text-sm ${pairingSecondsLeft > 0 ? 'text-gray-400' : 'text-red-400'}`}>
+                    {pairingSecondsLeft > 0
+                      ? `Expires in ${Math.floor(pairingSecondsLeft / 60)}:${String(pairingSecondsLeft % 60).padStart(2, '0')}`
+                      : 'Expired - close this and make a new code'}
+                  </p>
+                </div>
+              ) : (
+                <div className="space-y-4">
+                  <p className="text-sm text-gray-400">
+                    Enter the link from the owner's QR code, or their address and the code, e.g. 192.168.1.20:8001 042917.
+                  </p>
+                  <input
+                    type="text"
+                    value={pairingModal.pairing}
+                    onChange={(e) => setPairingModal({ pairing: e.target.value })}
+                    placeholder="p2ppair://192.168.1.20:8001/042917"
+                    className="w-full px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500"
+                  />
+                </div>
+              )}
+
+              <div className="flex gap-3 mt-6">
+                <button
+                  onClick={closePairingModal}
+                  className="flex-1 px-4 py-3 rounded-lg border border-purple-500/30 text-gray-400 hover:bg-white/5 transition-colors"
+                >
+                  {pairingModal.code ? 'Done' : 'Cancel'}
+                </button>
+                {!pairingModal.code && (
+                  <motion.button
+                    whileHover={{ scale: 1.02 }}
+                    whileTap={{ scale: 0.98 }}
+                    onClick={handleRedeemPairing}
+                    disabled={!pairingModal.pairing.trim()}
+                    className="flex-1 px-4 py-3 rounded-lg text-white font-medium bg-gradient-to-r from-purple-600 to-pink-600 disabled:opacity-50"
+                  >
+                    Redeem
+                  </motion.button>
+                )}
+              </div>
+            </motion.div>
           </motion.div>
         )}
       </AnimatePresence>
@@ -494,6 +1370,26 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                   )}
                 </div>
 
+                {quotaPreview && (
+                  <div className="p-3 rounded-lg bg-white/5 border border-purple-900/20 text-sm">
+                    <p className="text-gray-400 mb-2">After this change:</p>
+                    {quotaPreview.changes.length === 0 && (
+                      <p className="text-xs text-gray-500 mb-2">No change for {targetUser}</p>
+                    )}
+                    {Object.entries(quotaPreview.quotas).map(([user, views]) => {
+                      const change = quotaPreview.changes.find(c => c.user === user);
+                      return (
+                        <div key={user} className="flex justify-between">
+                          <span className={change ? 'text-white' : 'text-gray-400'}>{user}</span>
+                          <span className={`font-mono ${change ? 'text-purple-300' : 'text-gray-500'}`}>
+                            {change ? `${change.before ?? 'none'} → ` : ''}{views === 0 ? 'revoked' : `${views} views`}
+                          </span>
+                        </div>
+                      );
+                    })}
+                  </div>
+                )}
+
                 {/* Info about multicast */}
                 <div className="p-3 rounded-lg bg-cyan-900/20 border border-cyan-500/20">
                   <p className="text-xs text-cyan-400">
@@ -510,6 +1406,14 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                 >
                   Cancel
                 </button>
+                <button
+                  onClick={handlePreviewPermissions}
+                  disabled={!targetUser}
+                  className="px-4 py-3 rounded-lg border border-purple-500/30 text-purple-300 hover:bg-white/5 transition-colors disabled:opacity-50"
+                  title="Show everyone's quotas after this change without sending anything"
+                >
+                  Preview
+                </button>
                 <motion.button
                   whileHover={{ scale: 1.02 }}
                   whileTap={{ scale: 0.98 }}
@@ -524,6 +1428,80 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                   {newQuota === 0 ? 'Revoke Access' : 'Update & Send'}
                 </motion.button>
               </div>
+              {newQuota === 0 && (
+                <button
+                  onClick={handleRemoteWipe}
+                  disabled={!targetUser}
+                  className="w-full mt-3 px-4 py-2 rounded-lg border border-red-500/30 text-red-400 text-sm hover:bg-red-600/10 transition-colors disabled:opacity-50"
+                  title="Ask their client to securely delete its copy (applied at next login if they're offline)"
+                >
+                  Wipe Their Copy Instead
+                </button>
+              )}
+              <div className="flex gap-3 mt-3">
+                <button
+                  onClick={() => handleSetHolder(true)}
+                  disabled={!targetUser}
+                  className="flex-1 px-4 py-2 rounded-lg border border-blue-500/30 text-blue-300 text-sm hover:bg-blue-600/10 transition-colors disabled:opacity-50"
+                  title="Send them a copy they can keep (e.g. as a backup) but never view"
+                >
+                  Make Holder
+                </button>
+                <button
+                  onClick={() => handleSetHolder(false)}
+                  disabled={!targetUser}
+                  className="flex-1 px-4 py-2 rounded-lg border border-white/10 text-gray-300 text-sm hover:bg-white/5 transition-colors disabled:opacity-50"
+                >
+                  Remove Holder
+                </button>
+              </div>
+              <button
+                onClick={handleExportToFile}
+                disabled={!targetUser || newQuota === 0}
+                className="w-full mt-3 px-4 py-2 rounded-lg border border-purple-500/30 text-purple-300 text-sm hover:bg-white/5 transition-colors disabled:opacity-50"
+                title="Grant the views into a file to hand over on removable media, for peers you can't reach over the network"
+              >
+                Export Grant to File
+              </button>
+              <button
+                onClick={handlePairInPerson}
+                disabled={!isOnline || newQuota === 0}
+                className="w-full mt-3 px-4 py-2 rounded-lg border border-purple-500/30 text-purple-300 text-sm hover:bg-white/5 transition-colors disabled:opacity-50"
+                title="Show a short-lived code for a device next to you - whoever enters it gets the views and the image straight away"
+              >
+                Pair In Person
+              </button>
+
+              <div className="mt-4 p-4 rounded-lg bg-white/5 border border-purple-900/20 space-y-2">
+                <p className="text-sm text-gray-400">Viewer Rights</p>
+                {viewPolicy ? (
+                  <>
+                    {VIEW_POLICY_FIELDS.map(field => (
+                      <label key={field.key} className="flex items-center gap-2 text-sm text-gray-300">
+                        <input
+                          type="checkbox"
+                          checked={viewPolicy[field.key]}
+                          onChange={(e) => setViewPolicy({ ...viewPolicy, [field.key]: e.target.checked })}
+                        />
+                        {field.label}
+                      </label>
+                    ))}
+                    <button
+                      onClick={handleSaveViewPolicy}
+                      className="w-full mt-2 px-4 py-2 rounded-lg border border-purple-500/30 text-purple-300 text-sm hover:bg-white/5 transition-colors"
+                      title="Applies to copies you grant or deliver from now on"
+                    >
+                      Save Viewer Rights
+                    </button>
+                  </>
+                ) : (
+                  <p className="text-xs text-gray-500">Loading...</p>
+                )}
+              </div>
+
+              <div className="mt-4">
+                <PermissionHistory imageId={permissionModal.image_id} />
+              </div>
             </motion.div>
           </motion.div>
         )}
@@ -548,7 +1526,7 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
             >
               <div className="flex items-center justify-between mb-4">
                 <h3 className="text-xl font-display font-bold text-white">
-                  {viewedImagePath ? 'Viewing Image' : 'Access Denied - Cover Image'}
+                  {viewedImageData ? 'Viewing Image' : 'Access Denied - Cover Image'}
                 </h3>
                 <button
                   onClick={closeImageViewer}
@@ -561,11 +1539,13 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
               <div className="space-y-4">
                 {/* Image display */}
                 <div className="relative rounded-lg overflow-hidden bg-black/50 flex items-center justify-center min-h-[300px]">
-                  {viewedImagePath ? (
+                  {viewedImageData ? (
                     <img
-                      src={convertFileSrc(viewedImagePath)}
-                      alt={viewingImage.file_name}
+                      src={viewedImageData}
+                      alt={viewedAnnotations?.alt_text || viewingImage.file_name}
                       className="max-w-full max-h-[60vh] object-contain"
+                      draggable={viewedPolicy?.allow_save !== false}
+                      onContextMenu={(e) => viewedPolicy?.allow_save === false && e.preventDefault()}
                     />
                   ) : (
                     <div className="flex flex-col items-center justify-center p-8 text-center">
@@ -587,6 +1567,27 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                   )}
                 </div>
 
+                {/* Owner's annotations */}
+                {viewedAnnotations?.caption && (
+                  <p className="text-center text-white italic">{viewedAnnotations.caption}</p>
+                )}
+                {(viewedAnnotations?.alt_text || viewedAnnotations?.license) && (
+                  <div className="grid grid-cols-2 gap-4">
+                    {viewedAnnotations.alt_text && (
+                      <div className="p-3 rounded-lg bg-white/5 border border-cyan-900/20">
+                        <p className="text-xs text-gray-400">Alt Text</p>
+                        <p className="text-white text-sm">{viewedAnnotations.alt_text}</p>
+                      </div>
+                    )}
+                    {viewedAnnotations.license && (
+                      <div className="p-3 rounded-lg bg-white/5 border border-cyan-900/20">
+                        <p className="text-xs text-gray-400">License</p>
+                        <p className="text-white text-sm">{viewedAnnotations.license}</p>
+                      </div>
+                    )}
+                  </div>
+                )}
+
                 {/* Image info */}
                 <div className="grid grid-cols-2 gap-4">
                   <div className="p-3 rounded-lg bg-white/5 border border-cyan-900/20">
@@ -607,16 +1608,57 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                     <p className="text-xs text-gray-400">Received</p>
                     <p className="text-white font-medium">{viewingImage.received_at}</p>
                   </div>
+                  {viewingImage.provenance && (
+                    <div className="col-span-2 p-3 rounded-lg bg-white/5 border border-cyan-900/20">
+                      <p className="text-xs text-gray-400">Shared Via</p>
+                      <p className="text-white font-medium">{viewingImage.provenance}</p>
+                    </div>
+                  )}
+                  {viewingImage.sha256 && (
+                    <div className="col-span-2 p-3 rounded-lg bg-white/5 border border-cyan-900/20">
+                      <p className="text-xs text-gray-400">SHA-256 ({(viewingImage.size_bytes / 1024).toFixed(1)} KB received)</p>
+                      <p className="text-white font-mono text-xs break-all">{viewingImage.sha256}</p>
+                    </div>
+                  )}
                 </div>
 
-                {viewedImagePath && (
+                <PermissionHistory imageId={viewingImage.file_path} />
+
+                {viewedImageData && (
                   <p className="text-center text-yellow-400 text-sm">
                     ⚠️ This view has been counted. You have {viewingImage.views_remaining} views remaining.
                   </p>
                 )}
+                {viewedPolicy?.watermark && (
+                  <p className="text-center text-gray-400 text-xs">
+                    The owner has this image watermarked with your name and the time of viewing.
+                  </p>
+                )}
               </div>
 
-              <div className="flex justify-end mt-6">
+              <div className="flex justify-end gap-3 mt-6">
+                {viewedImageData && (
+                  <>
+                    <button
+                      onClick={handleSaveViewed}
+                      disabled={!viewedPolicy?.allow_save}
+                      className="flex items-center gap-2 px-4 py-3 rounded-lg border border-cyan-500/30 text-cyan-300 hover:bg-white/5 transition-colors disabled:opacity-50"
+                      title={viewedPolicy?.allow_save ? 'Save this view as a PNG' : "The owner doesn't allow saving this image"}
+                    >
+                      <Download className="w-4 h-4" />
+                      Save As
+                    </button>
+                    <button
+                      onClick={handlePrintViewed}
+                      disabled={!viewedPolicy?.allow_print}
+                      className="flex items-center gap-2 px-4 py-3 rounded-lg border border-cyan-500/30 text-cyan-300 hover:bg-white/5 transition-colors disabled:opacity-50"
+                      title={viewedPolicy?.allow_print ? 'Print this view' : "The owner doesn't allow printing this image"}
+                    >
+                      <Printer className="w-4 h-4" />
+                      Print
+                    </button>
+                  </>
+                )}
                 <motion.button
                   whileHover={{ scale: 1.02 }}
                   whileTap={{ scale: 0.98 }}
@@ -633,6 +1675,65 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
 
       {/* Delete Confirmation Modal */}
       <AnimatePresence>
+        {viewConfirmModal && (
+          <motion.div
+            initial={{ opacity: 0 }}
+            animate={{ opacity: 1 }}
+            exit={{ opacity: 0 }}
+            className="fixed inset-0 z-50 flex items-center justify-center modal-backdrop"
+            onClick={() => setViewConfirmModal(null)}
+          >
+            <motion.div
+              initial={{ scale: 0.9, opacity: 0 }}
+              animate={{ scale: 1, opacity: 1 }}
+              exit={{ scale: 0.9, opacity: 0 }}
+              onClick={(e) => e.stopPropagation()}
+              className="bg-cyber-darker border border-cyan-500/30 rounded-2xl p-6 w-full max-w-md"
+            >
+              <div className="flex items-center gap-3 mb-4">
+                <div className="p-3 rounded-lg bg-cyan-600/20">
+                  <Eye className="w-6 h-6 text-cyan-400" />
+                </div>
+                <h3 className="text-xl font-display font-bold text-white">Open Image</h3>
+              </div>
+
+              <div className="space-y-4">
+                <p className="text-gray-300">
+                  You have {viewConfirmModal.preview.views_remaining} {viewConfirmModal.preview.views_remaining === 1 ? 'view' : 'views'} left — open now? Opening it uses one up.
+                </p>
+
+                <div className="p-4 rounded-lg bg-white/5 border border-cyan-900/20">
+                  <p className="text-sm text-gray-400">Owner</p>
+                  <p className="text-white font-medium truncate">{viewConfirmModal.preview.owner}</p>
+                  {viewConfirmModal.preview.expires_at && (
+                    <p className="text-xs text-gray-400 mt-2">
+                      Expires {new Date(viewConfirmModal.preview.expires_at.secs_since_epoch * 1000).toLocaleString()}
+                    </p>
+                  )}
+                </div>
+              </div>
+
+              <div className="flex gap-3 mt-6">
+                <button
+                  onClick={() => setViewConfirmModal(null)}
+                  className="flex-1 px-4 py-3 rounded-lg border border-gray-500/30 text-gray-400 hover:bg-white/5 transition-colors"
+                >
+                  Cancel
+                </button>
+                <motion.button
+                  whileHover={{ scale: 1.02 }}
+                  whileTap={{ scale: 0.98 }}
+                  onClick={handleConfirmView}
+                  className="flex-1 flex items-center justify-center gap-2 px-4 py-3 rounded-lg bg-gradient-to-r from-cyan-600 to-blue-600 text-white font-medium"
+                >
+                  <Eye className="w-4 h-4" />
+                  Open
+                </motion.button>
+              </div>
+            </motion.div>
+          </motion.div>
+        )}
+
         {deleteConfirmModal && (
           <motion.div
             initial={{ opacity: 0 }}
@@ -652,23 +1753,25 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                 <div className="p-3 rounded-lg bg-red-600/20">
                   <Trash2 className="w-6 h-6 text-red-400" />
                 </div>
-                <h3 className="text-xl font-display font-bold text-white">Delete Image</h3>
+                <h3 className="text-xl font-display font-bold text-white">
+                  {deleteConfirmModal.paths ? 'Delete Images' : 'Delete Image'}
+                </h3>
               </div>
               
               <div className="space-y-4">
                 <p className="text-gray-300">
-                  Are you sure you want to delete this image? This action cannot be undone.
+                  Are you sure you want to delete {deleteConfirmModal.paths ? 'these images' : 'this image'}? This action cannot be undone.
                 </p>
                 
                 <div className="p-4 rounded-lg bg-white/5 border border-red-900/20">
-                  <p className="text-sm text-gray-400">File</p>
+                  <p className="text-sm text-gray-400">{deleteConfirmModal.paths ? 'Files' : 'File'}</p>
                   <p className="text-white font-medium truncate">{deleteConfirmModal.file_name}</p>
                 </div>
 
                 {deleteConfirmModal.type === 'encrypted' && (
                   <div className="p-3 rounded-lg bg-yellow-900/20 border border-yellow-500/20">
                     <p className="text-xs text-yellow-400">
-                      ⚠️ This is an encrypted image. Deleting it will remove it from your shared images and others will no longer be able to request it.
+                      ⚠️ {deleteConfirmModal.paths ? 'These are encrypted images. Deleting them will remove them from your shared images and others will no longer be able to request them.' : 'This is an encrypted image. Deleting it will remove it from your shared images and others will no longer be able to request it.'}
                     </p>
                   </div>
                 )}
@@ -685,7 +1788,8 @@ function ImagesPanel({ localImages, receivedImages, encryptedImages, onEncrypt,
                   whileHover={{ scale: 1.02 }}
                   whileTap={{ scale: 0.98 }}
                   onClick={handleDeleteConfirm}
-                  className="flex-1 flex items-center justify-center gap-2 px-4 py-3 rounded-lg bg-gradient-to-r from-red-600 to-red-700 text-white font-medium"
+                  disabled={batchBusy}
+                  className="flex-1 flex items-center justify-center gap-2 px-4 py-3 rounded-lg bg-gradient-to-r from-red-600 to-red-700 text-white font-medium disabled:opacity-50"
                 >
                   <Trash2 className="w-4 h-4" />
                   Delete
@@ -149,7 +149,15 @@ function NotificationsPanel({ notifications, loading, onRefresh, isOnline }) {
                             <Eye className="w-4 h-4 text-cyan-400" />
                             <span className="text-cyan-400">{notification.requested_views} views</span>
                           </div>
+                          {notification.approved_by && (
+                            <span className="text-gray-400">
+                              answered by {notification.approved_by} for {notification.to_user}
+                            </span>
+                          )}
                         </div>
+                        {notification.note && (
+                          <p className="mt-2 text-sm text-gray-400">{notification.note}</p>
+                        )}
                       </div>
                     </div>
                   </div>
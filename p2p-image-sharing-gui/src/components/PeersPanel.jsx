@@ -3,16 +3,117 @@ import { motion, AnimatePresence } from 'framer-motion';
 import { invoke } from '@tauri-apps/api/core';
 import {
   Users, RefreshCw, Search, Image, Send, Eye, Clock,
-  ChevronDown, ChevronUp, Globe, Wifi, WifiOff, Loader
+  ChevronDown, ChevronUp, Globe, Wifi, WifiOff, Loader, Star, AlertTriangle
 } from 'lucide-react';
 
-function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
+// Quick-request presets shown under each shared image
+const QUICK_REQUEST_PRESETS = [1, 5];
+
+// Availability windows are published in UTC minutes since midnight; show them in local time
+const formatLocalMinute = (utcMinute) => {
+  const local = ((utcMinute - new Date().getTimezoneOffset()) % 1440 + 1440) % 1440;
+  return `${String(Math.floor(local / 60)).padStart(2, '0')}:${String(local % 60).padStart(2, '0')}`;
+};
+
+const formatAvailability = (window) =>
+  `${formatLocalMinute(window.start_minute)}–${formatLocalMinute(window.end_minute)}`;
+
+// Hint shown when requesting from a peer, based on the directory's outlook
+const describeOutlook = (outlook) => {
+  if (!outlook || outlook === 'OnlineNow') return null;
+  if (outlook === 'UsuallyOnlineNow') return 'Offline, but usually online around now: expect an answer soon';
+  if (outlook.BackIn) {
+    const { minutes } = outlook.BackIn;
+    return `Offline until about ${Math.floor(minutes / 60)}h ${minutes % 60}m from now: your request will wait for them`;
+  }
+  return 'Offline: your request will wait until they come back online';
+};
+
+// Presence sparkline bars in local-time order, from the directory's per-UTC-hour online shares
+const localHourlyOnline = (hourlyOnline) => {
+  const offsetHours = Math.round(new Date().getTimezoneOffset() / 60);
+  return Array.from({ length: 24 }, (_, localHour) => hourlyOnline[((localHour + offsetHours) % 24 + 24) % 24]);
+};
+
+function PeersPanel({
+  peers, loading, onRefresh, onRequestImage, onRequestImages, onQuickRequest, onRequestByLink,
+  followedPeers = [], onToggleFollow, onWatchCatalog, newPeerImages = {}, isOnline
+}) {
   const [searchTerm, setSearchTerm] = useState('');
+  const [imageHolders, setImageHolders] = useState(null); // directory matches for an image ID or hash
+  const [findingImage, setFindingImage] = useState(false);
   const [expandedPeer, setExpandedPeer] = useState(null);
   const [requestModal, setRequestModal] = useState(null);
   const [requestViews, setRequestViews] = useState(5);
+  const [requestMaxDimension, setRequestMaxDimension] = useState('');
   const [thumbnails, setThumbnails] = useState({}); // { "peer_imageId": dataUrl }
   const [loadingThumbnails, setLoadingThumbnails] = useState({}); // { "peer_imageId": true/false }
+  const [presence, setPresence] = useState({}); // { username: { online, summary, hourly_online } }
+  const [selectedImageIds, setSelectedImageIds] = useState([]); // multi-select within the expanded peer
+  const [batchViews, setBatchViews] = useState(5);
+  const [batchBusy, setBatchBusy] = useState(false);
+  const [existingGrant, setExistingGrant] = useState(null); // views we already hold on the image being requested
+
+  // Selections belong to the expanded peer
+  useEffect(() => {
+    setSelectedImageIds([]);
+  }, [expandedPeer]);
+
+  const toggleSelectedImage = (imageId) => {
+    setSelectedImageIds(prev => prev.includes(imageId)
+      ? prev.filter(id => id !== imageId)
+      : [...prev, imageId]);
+  };
+
+  // Each image is asked for with the same views, capped at what its owner allows
+  const handleRequestSelected = async (peer) => {
+    const requests = peer.shared_images
+      .filter(image => selectedImageIds.includes(image.image_id))
+      .map(image => ({
+        peer_username: peer.username,
+        image_id: image.image_id,
+        views: Math.min(batchViews, image.max_grant_views || batchViews),
+        max_dimension: null,
+      }));
+    setBatchBusy(true);
+    if (await onRequestImages(requests)) {
+      setSelectedImageIds([]);
+    }
+    setBatchBusy(false);
+  };
+
+  // Warn before asking again for an image the directory says we already hold views on
+  useEffect(() => {
+    setExistingGrant(null);
+    if (!requestModal) return;
+    invoke('get_existing_grants', { peerUsername: requestModal.peer })
+      .then(result => {
+        const grant = result.success && (result.data || []).find(g => g.image_id === requestModal.imageId);
+        setExistingGrant(grant || null);
+      })
+      .catch(e => console.error('Failed to fetch existing grants:', e));
+  }, [requestModal]);
+
+  // Keep the expanded peer's images current while it's open, if its client pushes changes
+  const expandedPeerLive = peers.some(p =>
+    p.username === expandedPeer && p.status === 'Online' && p.capabilities?.includes('catalog-updates'));
+  useEffect(() => {
+    if (!expandedPeerLive) return;
+    onWatchCatalog(expandedPeer, true);
+    return () => onWatchCatalog(expandedPeer, false);
+  }, [expandedPeer, expandedPeerLive]);
+
+  // Fetch when the expanded peer was last seen and is usually online
+  useEffect(() => {
+    if (!expandedPeer) return;
+    invoke('get_presence_history', { username: expandedPeer })
+      .then(result => {
+        if (result.success && result.data) {
+          setPresence(prev => ({ ...prev, [expandedPeer]: result.data }));
+        }
+      })
+      .catch(e => console.error('Failed to fetch presence history:', e));
+  }, [expandedPeer]);
 
   // Fetch thumbnails when peer is expanded
   useEffect(() => {
@@ -43,15 +144,58 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
     }
   }, [expandedPeer, peers]);
 
-  const filteredPeers = peers.filter(peer =>
-    peer.username.toLowerCase().includes(searchTerm.toLowerCase())
-  );
+  // Match peers by name, or by the names and captions of images they share
+  const filteredPeers = peers.filter(peer => {
+    const term = searchTerm.toLowerCase();
+    return peer.username.toLowerCase().includes(term) ||
+      peer.shared_images?.some(image =>
+        image.image_name.toLowerCase().includes(term) ||
+        image.caption?.toLowerCase().includes(term)
+      );
+  });
+
+  // Ask the directory who shares an image, by ID, owner/ID or content hash prefix,
+  // falling back to a fuzzy search of every catalog's image names and captions.
+  // A share link (owner/image_id#token) goes straight to a request instead.
+  const handleFindImage = async () => {
+    const query = searchTerm.trim();
+    if (!query) return;
+    if (query.includes('#')) {
+      onRequestByLink(query, requestViews);
+      setSearchTerm('');
+      return;
+    }
+    setFindingImage(true);
+    try {
+      const result = await invoke('find_image', { imageIdOrHash: query });
+      const holders = result.success ? result.data || [] : [];
+      if (holders.length > 0) {
+        setImageHolders(holders);
+      } else {
+        const search = await invoke('search_catalog', { query });
+        setImageHolders(search.success ? search.data || [] : []);
+      }
+    } catch (e) {
+      console.error('Failed to find image:', e);
+      setImageHolders([]);
+    } finally {
+      setFindingImage(false);
+    }
+  };
+
+  const maxRequestViews = requestModal?.maxGrantViews || 100;
 
   const handleRequestSubmit = () => {
     if (requestModal) {
-      onRequestImage(requestModal.peer, requestModal.imageId, requestViews);
+      onRequestImage(
+        requestModal.peer,
+        requestModal.imageId,
+        Math.min(requestViews, maxRequestViews),
+        requestMaxDimension ? parseInt(requestMaxDimension) : null
+      );
       setRequestModal(null);
       setRequestViews(5);
+      setRequestMaxDimension('');
     }
   };
 
@@ -98,13 +242,58 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
         <Search className="absolute left-4 top-1/2 -translate-y-1/2 w-5 h-5 text-gray-400" />
         <input
           type="text"
-          placeholder="Search peers..."
+                    placeholder="Search peers, images, or captions..."
           value={searchTerm}
-          onChange={(e) => setSearchTerm(e.target.value)}
-          className="w-full pl-12 pr-4 py-3 rounded-xl cyber-input text-white placeholder-gray-500"
+          onChange={(e) => { setSearchTerm(e.target.value); setImageHolders(null); }}
+          onKeyDown={(e) => e.key === 'Enter' && handleFindImage()}
+          className="w-full pl-12 pr-32 py-3 rounded-xl cyber-input text-white placeholder-gray-500"
         />
+        <button
+          onClick={handleFindImage}
+          disabled={!searchTerm.trim() || findingImage}
+          title="Find who shares this image ID or hash, search every peer's image names and captions, or request a pasted share link"
+          className="absolute right-2 top-1/2 -translate-y-1/2 flex items-center gap-1 px-3 py-1.5 rounded-lg bg-purple-600/20 text-purple-400 text-sm hover:bg-purple-600/30 disabled:opacity-50"
+        >
+          {findingImage ? <Loader className="w-4 h-4 animate-spin" /> : <Image className="w-4 h-4" />}
+          Find image
+        </button>
       </div>
 
+      {/* Directory matches for an image ID or hash */}
+      {imageHolders && (
+        <div className="cyber-card rounded-xl bg-cyber-darker/80 p-4 space-y-2">
+          <p className="text-sm text-gray-400">
+            {imageHolders.length === 0
+              ? 'No peer shares a matching image'
+              : `${imageHolders.length} peer${imageHolders.length === 1 ? '' : 's'} share a matching image`}
+          </p>
+          {imageHolders.map(holder => (
+            <div key={holder.qualified_id} className="flex items-center justify-between gap-3 text-sm">
+              <div className="min-w-0">
+                <p className="text-white truncate">{holder.image.image_name}</p>
+                <p className="text-gray-500 text-xs truncate">
+                  {holder.qualified_id} · {holder.status}
+                  {holder.score !== undefined && ` · ${Math.round(holder.score * 100)}% match`}
+                  {holder.your_grant && ` · you have ${holder.your_grant.views} view${holder.your_grant.views === 1 ? '' : 's'}`}
+                </p>
+              </div>
+              <button
+                onClick={() => setRequestModal({
+                  peer: holder.owner,
+                  imageId: holder.image.image_id,
+                  imageName: holder.image.image_name,
+                  maxGrantViews: holder.image.max_grant_views,
+                })}
+                className="flex items-center gap-1 px-3 py-1 rounded-lg bg-purple-600/20 text-purple-400 hover:bg-purple-600/30"
+              >
+                <Send className="w-3 h-3" />
+                Request
+              </button>
+            </div>
+          ))}
+        </div>
+      )}
+
       {/* Peers list */}
       {loading && peers.length === 0 ? (
         <div className="flex items-center justify-center h-48">
@@ -133,18 +322,52 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                 onClick={() => setExpandedPeer(expandedPeer === peer.username ? null : peer.username)}
               >
                 <div className="flex items-center gap-4">
-                  <div className="w-12 h-12 rounded-full bg-gradient-to-br from-purple-600 to-pink-600 flex items-center justify-center text-white font-bold text-lg">
-                    {peer.username.charAt(0).toUpperCase()}
-                  </div>
+                  {peer.avatar ? (
+                    <img src={peer.avatar} alt={peer.username} className="w-12 h-12 rounded-full object-cover" />
+                  ) : (
+                    <div className="w-12 h-12 rounded-full bg-gradient-to-br from-purple-600 to-pink-600 flex items-center justify-center text-white font-bold text-lg">
+                      {(peer.display_name || peer.username).charAt(0).toUpperCase()}
+                    </div>
+                  )}
                   <div>
-                    <h3 className="font-semibold text-white">{peer.username}</h3>
+                    <h3 className="font-semibold text-white" title={peer.bio || undefined}>
+                      {peer.display_name || peer.username}
+                      {peer.display_name && (
+                        <span className="ml-2 text-sm font-normal text-gray-500">@{peer.username}</span>
+                      )}
+                    </h3>
                     <p className="text-sm text-gray-400 flex items-center gap-2">
                       <Globe className="w-3 h-3" />
-                      {peer.p2p_address}
+                      {peer.anonymous ? 'Anonymous (through a relay)' : peer.p2p_address}
+                      {peer.latency_ms != null && (
+                        <span className="text-xs text-cyan-400">~{peer.latency_ms} ms</span>
+                      )}
+                      {peer.reputation != null && (
+                        <span
+                          className={`text-xs ${peer.low_reputation ? 'text-red-400' : 'text-green-400'}`}
+                          title="Local score from past deliveries, response times and revocations"
+                        >
+                          {peer.low_reputation ? '⚠ ' : ''}Reputation {peer.reputation}/100
+                        </span>
+                      )}
                     </p>
                   </div>
                 </div>
                 <div className="flex items-center gap-4">
+                  <button
+                    onClick={(e) => {
+                      e.stopPropagation();
+                      onToggleFollow(peer.username);
+                    }}
+                    title={followedPeers.includes(peer.username) ? 'Unfollow' : 'Follow for new images'}
+                    className={`p-1 rounded-md transition-colors ${
+                      followedPeers.includes(peer.username)
+                        ? 'text-yellow-400 hover:text-yellow-300'
+                        : 'text-gray-500 hover:text-gray-300'
+                    }`}
+                  >
+                    <Star className="w-4 h-4" fill={followedPeers.includes(peer.username) ? 'currentColor' : 'none'} />
+                  </button>
                   <div className="flex items-center gap-2">
                     <div className={`w-2 h-2 rounded-full ${
                       peer.status === 'Online' ? 'bg-green-500' : 'bg-red-500'
@@ -155,6 +378,12 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                       {peer.status}
                     </span>
                   </div>
+                  {peer.availability && (
+                    <div className="flex items-center gap-1 text-sm text-gray-400" title="Usually online (your local time)">
+                      <Clock className="w-4 h-4" />
+                      {formatAvailability(peer.availability)}
+                    </div>
+                  )}
                   <div className="flex items-center gap-2 text-sm text-gray-400">
                     <Image className="w-4 h-4" />
                     {peer.shared_images?.length || 0} images
@@ -178,12 +407,70 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                     className="border-t border-purple-900/30"
                   >
                     <div className="p-4">
-                      <h4 className="text-sm font-medium text-gray-400 mb-3">Shared Images</h4>
+                      {presence[peer.username] && (
+                        <div className="mb-4 flex items-center gap-3">
+                          <div className="flex items-end gap-px h-6" title="Online through the day, local time (midnight to midnight)">
+                            {localHourlyOnline(presence[peer.username].hourly_online).map((share, hour) => (
+                              <div
+                                key={hour}
+                                className="w-1.5 rounded-sm bg-green-500/70"
+                                style={{ height: `${Math.max(share * 100, 8)}%`, opacity: share > 0 ? 1 : 0.25 }}
+                              />
+                            ))}
+                          </div>
+                          <p className="text-xs text-gray-400 flex items-center gap-1">
+                            <Clock className="w-3 h-3" />
+                            {presence[peer.username].summary}
+                          </p>
+                        </div>
+                      )}
+                      <div className="flex items-center justify-between mb-3">
+                        <h4 className="text-sm font-medium text-gray-400">Shared Images</h4>
+                        {peer.shared_images?.length > 0 && (
+                          <div className="flex items-center gap-3 text-xs">
+                            <label className="flex items-center gap-1 text-gray-400 cursor-pointer">
+                              <input
+                                type="checkbox"
+                                checked={selectedImageIds.length === peer.shared_images.length}
+                                onChange={() => setSelectedImageIds(
+                                  selectedImageIds.length === peer.shared_images.length
+                                    ? []
+                                    : peer.shared_images.map(image => image.image_id)
+                                )}
+                                className="accent-cyan-500"
+                              />
+                              Select all
+                            </label>
+                            {selectedImageIds.length > 0 && (
+                              <>
+                                <input
+                                  type="number"
+                                  min="1"
+                                  value={batchViews}
+                                  onChange={(e) => setBatchViews(Math.max(1, parseInt(e.target.value) || 1))}
+                                  className="w-14 px-2 py-1 rounded-md cyber-input text-white"
+                                  title="Views to ask for on each image"
+                                />
+                                <button
+                                  onClick={() => handleRequestSelected(peer)}
+                                  disabled={batchBusy}
+                                  className="flex items-center gap-1 px-2 py-1 rounded-md bg-cyan-600/20 border border-cyan-500/30 text-cyan-400 hover:bg-cyan-600/30 transition-colors disabled:opacity-50"
+                                >
+                                  <Send className="w-3 h-3" />
+                                  Request {selectedImageIds.length}
+                                </button>
+                              </>
+                            )}
+                          </div>
+                        )}
+                      </div>
                       {peer.shared_images && peer.shared_images.length > 0 ? (
                         <div className="grid grid-cols-1 md:grid-cols-2 lg:grid-cols-3 gap-4">
                           {peer.shared_images.map((image) => {
                             const thumbnailKey = `${peer.username}_${image.image_id}`;
-                            const thumbnail = thumbnails[thumbnailKey];
+                            // Offline owners can only be browsed through previews they left with the directory
+                            const escrowed = peer.status !== 'Online' && image.preview;
+                            const thumbnail = thumbnails[thumbnailKey] || (escrowed ? image.preview : null);
                             const isLoadingThumb = loadingThumbnails[thumbnailKey];
                             
                             return (
@@ -207,10 +494,21 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                                       <span className="text-xs text-gray-500">Preview unavailable</span>
                                     </div>
                                   )}
+                                  <label
+                                    className="absolute top-2 left-2 p-1 rounded-md bg-black/50 cursor-pointer"
+                                    onClick={(e) => e.stopPropagation()}
+                                  >
+                                    <input
+                                      type="checkbox"
+                                      checked={selectedImageIds.includes(image.image_id)}
+                                      onChange={() => toggleSelectedImage(image.image_id)}
+                                      className="accent-cyan-500"
+                                    />
+                                  </label>
                                   {/* Blurred overlay indicator */}
                                   {thumbnail && (
                                     <div className="absolute bottom-2 right-2 px-2 py-1 rounded-md bg-black/60 backdrop-blur-sm">
-                                      <span className="text-xs text-gray-300">Preview</span>
+                                      <span className="text-xs text-gray-300">{escrowed ? 'Offline preview' : 'Preview'}</span>
                                     </div>
                                   )}
                                 </div>
@@ -220,10 +518,32 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                                   <div className="flex-1 min-w-0">
                                     <p className="text-sm font-medium text-white truncate">
                                       {image.image_name}
+                                      {newPeerImages[thumbnailKey] && (
+                                        <span className="ml-2 px-1.5 py-0.5 rounded text-[10px] bg-yellow-500/20 text-yellow-300">NEW</span>
+                                      )}
                                     </p>
+                                    {image.caption && (
+                                      <p className="text-xs text-gray-300 truncate" title={image.caption}>
+                                        {image.caption}
+                                      </p>
+                                    )}
                                     <p className="text-xs text-gray-500 truncate">
                                       ID: {image.image_id.slice(0, 12)}...
                                     </p>
+                                    {image.max_grant_views && (
+                                      <p className="text-xs text-cyan-400 flex items-center gap-1">
+                                        <Eye className="w-3 h-3" />
+                                        Max {image.max_grant_views} views
+                                      </p>
+                                    )}
+                                    {peer.anonymous_images?.includes(image.image_id) && (
+                                      <p className="text-xs text-purple-300">Anonymous requests allowed</p>
+                                    )}
+                                    {escrowed && (
+                                      <p className="text-xs text-yellow-300" title={describeOutlook(peer.outlook) || undefined}>
+                                        {image.file_size_kb != null && `${image.file_size_kb} KB · `}Request will be queued
+                                      </p>
+                                    )}
                                   </div>
                                   <motion.button
                                     whileHover={{ scale: 1.05 }}
@@ -234,14 +554,37 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                                         peer: peer.username, 
                                         imageId: image.image_id, 
                                         imageName: image.image_name,
-                                        thumbnail: thumbnail 
+                                        thumbnail: thumbnail,
+                                        maxGrantViews: image.max_grant_views,
+                                        outlook: peer.outlook
                                       });
+                                      if (image.max_grant_views) {
+                                        setRequestViews(v => Math.min(v, image.max_grant_views));
+                                      }
                                     }}
                                     className="ml-2 p-2 rounded-lg bg-cyan-600/20 text-cyan-400 hover:bg-cyan-600/30 transition-colors flex items-center gap-1"
                                   >
                                     <Send className="w-4 h-4" />
                                   </motion.button>
                                 </div>
+
+                                {/* Quick-request presets */}
+                                <div className="px-3 pb-3 flex gap-2">
+                                  {QUICK_REQUEST_PRESETS
+                                    .filter(views => !image.max_grant_views || views <= image.max_grant_views)
+                                    .map(views => (
+                                      <button
+                                        key={views}
+                                        onClick={(e) => {
+                                          e.stopPropagation();
+                                          onQuickRequest(peer.username, image.image_id, views);
+                                        }}
+                                        className="flex-1 px-2 py-1 rounded-md text-xs bg-purple-600/20 border border-purple-500/30 text-purple-300 hover:bg-purple-600/30 transition-colors"
+                                      >
+                                        Ask for {views} view{views === 1 ? '' : 's'}
+                                      </button>
+                                    ))}
+                                </div>
                               </div>
                             );
                           })}
@@ -295,6 +638,12 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                 <div className="p-4 rounded-lg bg-white/5 border border-purple-900/20">
                   <p className="text-sm text-gray-400">From</p>
                   <p className="text-white font-medium">{requestModal.peer}</p>
+                  {describeOutlook(requestModal.outlook) && (
+                    <p className="text-xs text-yellow-400 mt-1">{describeOutlook(requestModal.outlook)}</p>
+                  )}
+                  {presence[requestModal.peer] && !presence[requestModal.peer].online && (
+                    <p className="text-xs text-gray-400 mt-1">{presence[requestModal.peer].summary}</p>
+                  )}
                 </div>
                 
                 <div className="p-4 rounded-lg bg-white/5 border border-purple-900/20">
@@ -302,25 +651,52 @@ function PeersPanel({ peers, loading, onRefresh, onRequestImage, isOnline }) {
                   <p className="text-white font-medium">{requestModal.imageName}</p>
                 </div>
 
+                {existingGrant && (
+                  <div className="flex items-start gap-2 p-3 rounded-lg bg-yellow-500/10 border border-yellow-500/30 text-sm text-yellow-300">
+                    <AlertTriangle className="w-4 h-4 mt-0.5 flex-shrink-0" />
+                    <span>
+                      You already have {existingGrant.views} view{existingGrant.views === 1 ? '' : 's'} of this image
+                      (granted {existingGrant.granted_ago}). If accepted, this request replaces them.
+                    </span>
+                  </div>
+                )}
+
                 <div>
                   <label className="block text-sm text-gray-400 mb-2">
                     Requested Views
+                    {requestModal.maxGrantViews && (
+                      <span className="ml-2 text-xs text-cyan-400">(owner allows up to {requestModal.maxGrantViews})</span>
+                    )}
                   </label>
                   <div className="flex items-center gap-4">
                     <input
                       type="range"
                       min="1"
-                      max="100"
-                      value={requestViews}
+                      max={maxRequestViews}
+                      value={Math.min(requestViews, maxRequestViews)}
                       onChange={(e) => setRequestViews(parseInt(e.target.value))}
                       className="flex-1 h-2 bg-purple-900/30 rounded-full appearance-none cursor-pointer"
                     />
                     <div className="flex items-center gap-2 px-3 py-2 rounded-lg bg-purple-600/20 border border-purple-500/30">
                       <Eye className="w-4 h-4 text-purple-400" />
-                      <span className="text-white font-mono w-8 text-center">{requestViews}</span>
+                      <span className="text-white font-mono w-8 text-center">{Math.min(requestViews, maxRequestViews)}</span>
                     </div>
                   </div>
                 </div>
+
+                <div>
+                  <label className="block text-sm text-gray-400 mb-2">Resolution</label>
+                  <select
+                    value={requestMaxDimension}
+                    onChange={(e) => setRequestMaxDimension(e.target.value)}
+                    className="w-full px-3 py-2 rounded-lg cyber-input text-white text-sm"
+                  >
+                    <option value="">Full size</option>
+                    <option value="1024">Up to 1024px</option>
+                    <option value="512">Up to 512px</option>
+                    <option value="256">Up to 256px</option>
+                  </select>
+                </div>
               </div>
 
               <div className="flex gap-3 mt-6">
@@ -1,11 +1,23 @@
-import React from 'react';
+import React, { useState } from 'react';
 import { motion } from 'framer-motion';
 import {
   Inbox, RefreshCw, Check, X, Clock, Image, User,
-  Eye, AlertCircle, WifiOff
+  Eye, AlertCircle, WifiOff, Repeat, Upload, Ban, BellOff
 } from 'lucide-react';
 
-function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
+const EMPTY_TRADE_FORM = { peer: '', offer: '', offerViews: 5, want: '', wantViews: 5 };
+
+function RequestsPanel({
+  requests, throttledSenders = [], peers = [], loading, onRefresh, onRespond, onRespondBulk,
+  reminders = [], onSnoozeReminder,
+  trades = [], onRefreshTrades, onProposeTrade, onRespondToTrade, onDepositTrade, onCancelTrade,
+  isOnline
+}) {
+  const [tradeForm, setTradeForm] = useState(EMPTY_TRADE_FORM);
+  const [bulkFrom, setBulkFrom] = useState(''); // limit bulk responses to one requester
+  const requester = (request) => peers.find(p => p.username === request.from_user);
+  const overdue = (request) => reminders.some(r => r.request_id === request.request_id);
+
   if (!isOnline) {
     return (
       <div className="flex flex-col items-center justify-center h-96 text-center">
@@ -22,6 +34,26 @@ function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
   }
 
   const pendingRequests = requests.filter(r => r.status === 'Pending');
+  // Bulk responses only cover our own images, not those we answer for as a delegate
+  const ownRequests = pendingRequests.filter(r => !r.delegated);
+  const openTrades = trades.filter(t => t.status === 'Proposed' || t.status === 'Accepted');
+  const finishedTrades = trades.filter(t => t.status !== 'Proposed' && t.status !== 'Accepted');
+  const canPropose = tradeForm.peer.trim() && tradeForm.offer.trim() && tradeForm.want.trim()
+    && tradeForm.offerViews > 0 && tradeForm.wantViews > 0;
+
+  const handleProposeSubmit = async () => {
+    if (!canPropose) return;
+    await onProposeTrade(
+      tradeForm.peer.trim(),
+      tradeForm.offer.trim(),
+      tradeForm.offerViews,
+      tradeForm.want.trim(),
+      tradeForm.wantViews
+    );
+    setTradeForm(EMPTY_TRADE_FORM);
+  };
+
+  const updateTradeForm = (field, value) => setTradeForm(prev => ({ ...prev, [field]: value }));
 
   return (
     <div className="space-y-6">
@@ -58,6 +90,23 @@ function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
         </div>
       </div>
 
+      {/* Requesters the directory refused for leaving too many requests */}
+      {throttledSenders.map(note => (
+        <div key={note.sender} className="flex items-center gap-3 p-4 rounded-xl bg-red-500/10 border border-red-500/20">
+          <Ban className="w-5 h-5 text-red-400 flex-shrink-0" />
+          <p className="flex-1 text-sm text-gray-300">
+            Requests from <span className="text-white font-medium">{note.sender}</span> were throttled:
+            {' '}{note.rejected} refused for having too many pending (last {note.last_rejected.toLowerCase()}).
+          </p>
+          <button
+            onClick={() => onRespondBulk({ from_user: note.sender }, false)}
+            className="px-3 py-1.5 rounded-lg border border-red-500/30 text-red-400 text-sm hover:bg-red-600/10 transition-colors"
+          >
+            Reject All From {note.sender}
+          </button>
+        </div>
+      ))}
+
       {/* Requests list */}
       {loading && requests.length === 0 ? (
         <div className="flex items-center justify-center h-48">
@@ -84,20 +133,37 @@ function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
               <div className="flex items-start justify-between">
                 <div className="flex items-start gap-4">
                   {/* Avatar */}
-                  <div className="w-12 h-12 rounded-full bg-gradient-to-br from-cyan-600 to-blue-600 flex items-center justify-center text-white font-bold text-lg flex-shrink-0">
-                    {request.from_user.charAt(0).toUpperCase()}
-                  </div>
+                  {requester(request)?.avatar ? (
+                    <img src={requester(request).avatar} alt={request.from_user} className="w-12 h-12 rounded-full object-cover flex-shrink-0" />
+                  ) : (
+                    <div className="w-12 h-12 rounded-full bg-gradient-to-br from-cyan-600 to-blue-600 flex items-center justify-center text-white font-bold text-lg flex-shrink-0">
+                      {(requester(request)?.display_name || request.from_user).charAt(0).toUpperCase()}
+                    </div>
+                  )}
                   
                   <div>
                     <div className="flex items-center gap-2 mb-1">
                       <User className="w-4 h-4 text-gray-400" />
-                      <span className="font-medium text-white">{request.from_user}</span>
+                      <span className="font-medium text-white" title={requester(request)?.bio || undefined}>
+                        {requester(request)?.display_name || request.from_user}
+                      </span>
+                      {requester(request)?.display_name && (
+                        <span className="text-sm text-gray-500">@{request.from_user}</span>
+                      )}
                       <span className="text-gray-500">requests access to</span>
                     </div>
                     
                     <div className="flex items-center gap-2 mb-3">
                       <Image className="w-4 h-4 text-purple-400" />
                       <span className="text-purple-400 font-medium">{request.image_id}</span>
+                      {request.delegated && (
+                        <span
+                          className="px-2 py-0.5 rounded bg-amber-600/20 text-xs text-amber-400"
+                          title={`${request.to_user} made you a delegate - their peer must be online to accept`}
+                        >
+                          for {request.to_user}
+                        </span>
+                      )}
                     </div>
 
                     <div className="flex items-center gap-4 text-sm">
@@ -105,9 +171,18 @@ function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
                         <Eye className="w-4 h-4 text-cyan-400" />
                         <span className="text-cyan-400">{request.requested_views} views</span>
                       </div>
-                      <div className="flex items-center gap-2 text-gray-400">
+                      {request.requested_max_dimension && (
+                        <div className="flex items-center gap-2 px-3 py-1.5 rounded-lg bg-purple-600/20">
+                          <Image className="w-4 h-4 text-purple-400" />
+                          <span className="text-purple-400">≤ {request.requested_max_dimension}px</span>
+                        </div>
+                      )}
+                      <div className={`flex items-center gap-2 ${overdue(request) ? 'text-orange-400' : 'text-gray-400'}`}>
                         <Clock className="w-4 h-4" />
                         <span>{request.timestamp}</span>
+                        {overdue(request) && (
+                          <span className="px-2 py-0.5 rounded bg-orange-600/20 text-xs">Overdue</span>
+                        )}
                       </div>
                     </div>
                   </div>
@@ -115,6 +190,24 @@ function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
 
                 {/* Actions */}
                 <div className="flex items-center gap-2">
+                  {overdue(request) && (
+                    <>
+                      <button
+                        onClick={() => onSnoozeReminder(request.request_id, 24)}
+                        className="px-3 py-2 rounded-lg border border-orange-500/30 text-orange-400 text-sm hover:bg-orange-600/10 transition-colors"
+                        title="Remind me again tomorrow"
+                      >
+                        Snooze 24h
+                      </button>
+                      <button
+                        onClick={() => onSnoozeReminder(request.request_id, null)}
+                        className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+                        title="Stop reminding me about this request"
+                      >
+                        <BellOff className="w-4 h-4" />
+                      </button>
+                    </>
+                  )}
                   <motion.button
                     whileHover={{ scale: 1.05 }}
                     whileTap={{ scale: 0.95 }}
@@ -157,6 +250,202 @@ function RequestsPanel({ requests, loading, onRefresh, onRespond, isOnline }) {
           </div>
         </div>
       )}
+
+      {/* Bulk responses */}
+      {ownRequests.length > 1 && (
+        <div className="flex flex-wrap items-center justify-center gap-3">
+          <select
+            value={bulkFrom}
+            onChange={(e) => setBulkFrom(e.target.value)}
+            className="px-3 py-2 rounded-lg cyber-input text-white text-sm"
+          >
+            <option value="">From anyone</option>
+            {[...new Set(ownRequests.map(r => r.from_user))].map(user => (
+              <option key={user} value={user}>From {user}</option>
+            ))}
+          </select>
+          <button
+            onClick={() => onRespondBulk({ from_user: bulkFrom || null }, true)}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-green-600/20 border border-green-500/30 text-green-400 hover:bg-green-600/30 transition-colors text-sm"
+          >
+            <Check className="w-4 h-4" />
+            Accept All
+          </button>
+          <button
+            onClick={() => onRespondBulk({ from_user: bulkFrom || null }, false)}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-red-600/20 border border-red-500/30 text-red-400 hover:bg-red-600/30 transition-colors text-sm"
+          >
+            <X className="w-4 h-4" />
+            Reject All
+          </button>
+        </div>
+      )}
+
+      {/* Trades */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6 space-y-4">
+        <div className="flex items-center justify-between">
+          <div>
+            <h3 className="text-lg font-semibold text-white flex items-center gap-2">
+              <Repeat className="w-5 h-5 text-cyan-400" />
+              Image Trades
+            </h3>
+            <p className="text-sm text-gray-400 mt-1">
+              Swap views of one of your images for views of a peer's image. Nothing is delivered until both sides are deposited.
+            </p>
+          </div>
+          <motion.button
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            onClick={onRefreshTrades}
+            className="p-2 rounded-lg bg-purple-600/20 border border-purple-500/30 text-purple-400 hover:bg-purple-600/30 transition-colors"
+            title="Refresh trades"
+          >
+            <RefreshCw className="w-4 h-4" />
+          </motion.button>
+        </div>
+
+        {/* Propose form */}
+        <div className="grid grid-cols-1 md:grid-cols-5 gap-3">
+          <input
+            type="text"
+            placeholder="Peer username"
+            value={tradeForm.peer}
+            onChange={(e) => updateTradeForm('peer', e.target.value)}
+            className="px-3 py-2 rounded-lg cyber-input text-white placeholder-gray-500"
+          />
+          <input
+            type="text"
+            placeholder="Your image"
+            value={tradeForm.offer}
+            onChange={(e) => updateTradeForm('offer', e.target.value)}
+            className="px-3 py-2 rounded-lg cyber-input text-white placeholder-gray-500"
+          />
+          <input
+            type="number"
+            min="1"
+            title="Views you give"
+            value={tradeForm.offerViews}
+            onChange={(e) => updateTradeForm('offerViews', parseInt(e.target.value) || 0)}
+            className="px-3 py-2 rounded-lg cyber-input text-white"
+          />
+          <input
+            type="text"
+            placeholder="Their image"
+            value={tradeForm.want}
+            onChange={(e) => updateTradeForm('want', e.target.value)}
+            className="px-3 py-2 rounded-lg cyber-input text-white placeholder-gray-500"
+          />
+          <input
+            type="number"
+            min="1"
+            title="Views you get"
+            value={tradeForm.wantViews}
+            onChange={(e) => updateTradeForm('wantViews', parseInt(e.target.value) || 0)}
+            className="px-3 py-2 rounded-lg cyber-input text-white"
+          />
+        </div>
+        <div className="flex justify-end">
+          <motion.button
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            onClick={handleProposeSubmit}
+            disabled={!canPropose}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-cyan-600/20 border border-cyan-500/30 text-cyan-400 hover:bg-cyan-600/30 transition-colors disabled:opacity-50"
+          >
+            <Repeat className="w-4 h-4" />
+            Propose Trade
+          </motion.button>
+        </div>
+
+        {/* Open and recent trades */}
+        {trades.length === 0 ? (
+          <p className="text-sm text-gray-500 text-center py-4">No trades yet</p>
+        ) : (
+          <div className="space-y-3">
+            {[...openTrades, ...finishedTrades].map(trade => {
+              const isOpen = trade.status === 'Proposed' || trade.status === 'Accepted';
+              // The proposer may escrow early; the counterparty only after accepting
+              const canDeposit = isOpen && !trade.give_deposited && (!trade.incoming || trade.status === 'Accepted');
+              return (
+                <div
+                  key={trade.trade_id}
+                  className="flex items-center justify-between p-4 rounded-lg bg-cyber-dark/50 border border-purple-900/30"
+                >
+                  <div className="space-y-1 text-sm">
+                    <div className="flex items-center gap-2">
+                      <User className="w-4 h-4 text-gray-400" />
+                      <span className="font-medium text-white">{trade.peer}</span>
+                      <span className={`px-2 py-0.5 rounded text-xs ${
+                        isOpen ? 'bg-cyan-600/20 text-cyan-400' :
+                        trade.status === 'Completed' ? 'bg-green-600/20 text-green-400' :
+                        'bg-gray-600/20 text-gray-400'
+                      }`}>
+                        {trade.status}
+                      </span>
+                    </div>
+                    <div className="text-gray-400">
+                      You give <span className="text-purple-400">{trade.give_image_id}</span> × {trade.give_views}
+                      {isOpen && (trade.give_deposited ? ' ✓' : ' (not deposited)')}
+                    </div>
+                    <div className="text-gray-400">
+                      You get <span className="text-purple-400">{trade.get_image_id}</span> × {trade.get_views}
+                      {isOpen && (trade.get_deposited ? ' ✓' : ' (waiting)')}
+                    </div>
+                    <p className="text-xs text-gray-500 font-mono">Trade ID: {trade.trade_id}</p>
+                  </div>
+
+                  <div className="flex items-center gap-2">
+                    {trade.incoming && trade.status === 'Proposed' && (
+                      <>
+                        <motion.button
+                          whileHover={{ scale: 1.05 }}
+                          whileTap={{ scale: 0.95 }}
+                          onClick={() => onRespondToTrade(trade.trade_id, false)}
+                          className="p-2 rounded-lg bg-red-600/20 border border-red-500/30 text-red-400 hover:bg-red-600/30 transition-colors"
+                          title="Reject"
+                        >
+                          <X className="w-4 h-4" />
+                        </motion.button>
+                        <motion.button
+                          whileHover={{ scale: 1.05 }}
+                          whileTap={{ scale: 0.95 }}
+                          onClick={() => onRespondToTrade(trade.trade_id, true)}
+                          className="p-2 rounded-lg bg-green-600/20 border border-green-500/30 text-green-400 hover:bg-green-600/30 transition-colors"
+                          title="Accept"
+                        >
+                          <Check className="w-4 h-4" />
+                        </motion.button>
+                      </>
+                    )}
+                    {canDeposit && (
+                      <motion.button
+                        whileHover={{ scale: 1.05 }}
+                        whileTap={{ scale: 0.95 }}
+                        onClick={() => onDepositTrade(trade.trade_id)}
+                        className="p-2 rounded-lg bg-cyan-600/20 border border-cyan-500/30 text-cyan-400 hover:bg-cyan-600/30 transition-colors"
+                        title="Deposit your image"
+                      >
+                        <Upload className="w-4 h-4" />
+                      </motion.button>
+                    )}
+                    {isOpen && (
+                      <motion.button
+                        whileHover={{ scale: 1.05 }}
+                        whileTap={{ scale: 0.95 }}
+                        onClick={() => onCancelTrade(trade.trade_id)}
+                        className="p-2 rounded-lg bg-gray-600/20 border border-gray-500/30 text-gray-400 hover:bg-gray-600/30 transition-colors"
+                        title="Cancel trade"
+                      >
+                        <Ban className="w-4 h-4" />
+                      </motion.button>
+                    )}
+                  </div>
+                </div>
+              );
+            })}
+          </div>
+        )}
+      </div>
     </div>
   );
 }
@@ -1,14 +1,268 @@
-import React, { useState } from 'react';
+import React, { useState, useEffect } from 'react';
 import { motion } from 'framer-motion';
 import {
   Settings, Server, Plus, Trash2, Save, RefreshCw,
-  Globe, Shield, Database, AlertCircle, Check
+  Globe, Shield, Database, AlertCircle, Check, FolderOpen, Stethoscope, X, Copy, Activity, HardDrive, Lock, FileText,
+  User, Upload, ListChecks, Users, EyeOff, Minimize2
 } from 'lucide-react';
 
-function SettingsPanel({ directoryServers, onUpdateServers }) {
+const NAME_POLICIES = [
+  ['owner_prefixed', 'from_<owner>_<image>'],
+  ['original_name', "Owner's image name"],
+  ['hash_based', 'Hash (hides who shared it)'],
+];
+
+// Phases of an encryption job, as the diagnostics report times them
+const ENCRYPTION_PHASES = ['connect', 'upload', 'processing', 'download', 'total'];
+
+function SettingsPanel({ directoryServers, onUpdateServers, onRefreshDirectories, shareRoots = [], onUpdateShareRoots, onRunDiagnostics,
+  onExportAuditLog, onGetProfile, onUpdateProfile, onGetDelegates, onSetDelegate, onGetAnonymousAccess, onSetAnonymousAccess, isOnline, onGetStorageUsage, onSetStorageQuota, onGetNamePolicy, onSetNamePolicy, onMigrateImages, onGetBandwidthStats, onSetBandwidthCap,
+  onGetBackgroundJobs, onRetryBackgroundJob, onToggleProtocolTrace, onGetProtocolTrace,
+  viewerMode = { enabled: false, locked: false }, onToggleViewerOnly, backgroundMode = true, onToggleBackgroundMode }) {
   const [servers, setServers] = useState(directoryServers);
   const [newServer, setNewServer] = useState('');
   const [saved, setSaved] = useState(false);
+  const [serverHealth, setServerHealth] = useState({}); // { address: KnownDirectory }
+  const [refreshingServers, setRefreshingServers] = useState(false);
+  const [roots, setRoots] = useState(shareRoots);
+  const [newRootName, setNewRootName] = useState('');
+  const [newRootPath, setNewRootPath] = useState('');
+  const [rootsSaved, setRootsSaved] = useState(false);
+  const [report, setReport] = useState(null);
+  const [diagnosing, setDiagnosing] = useState(false);
+
+  const handleRunDiagnostics = async () => {
+    setDiagnosing(true);
+    const result = await onRunDiagnostics();
+    if (result) setReport(result);
+    setDiagnosing(false);
+  };
+
+  const handleCopyReport = () => {
+    navigator.clipboard.writeText(JSON.stringify(report, null, 2));
+  };
+
+  const [profile, setProfile] = useState({ displayName: '', bio: '', avatar: null });
+  const [avatarBytes, setAvatarBytes] = useState(null);
+  const [clearAvatar, setClearAvatar] = useState(false);
+
+  useEffect(() => {
+    if (!isOnline) return;
+    onGetProfile().then((published) => {
+      if (published) {
+        setProfile({
+          displayName: published.display_name || '',
+          bio: published.bio || '',
+          avatar: published.avatar,
+        });
+      }
+    });
+  }, [isOnline]);
+
+  const handlePickAvatar = async (e) => {
+    const file = e.target.files?.[0];
+    if (!file) return;
+    setAvatarBytes(Array.from(new Uint8Array(await file.arrayBuffer())));
+    setClearAvatar(false);
+    setProfile(prev => ({ ...prev, avatar: URL.createObjectURL(file) }));
+  };
+
+  const handleRemoveAvatar = () => {
+    setAvatarBytes(null);
+    setClearAvatar(true);
+    setProfile(prev => ({ ...prev, avatar: null }));
+  };
+
+  const handleSaveProfile = async () => {
+    const saved = await onUpdateProfile({
+      displayName: profile.displayName.trim(),
+      bio: profile.bio.trim(),
+      avatarBytes,
+      clearAvatar,
+    });
+    if (saved) {
+      setAvatarBytes(null);
+      setClearAvatar(false);
+      setProfile(prev => ({ ...prev, avatar: saved.avatar }));
+    }
+  };
+
+  const [delegates, setDelegates] = useState([]);
+  const [newDelegate, setNewDelegate] = useState('');
+  const [newDelegateImages, setNewDelegateImages] = useState(''); // comma-separated, empty for all
+
+  const loadDelegates = async () => {
+    const list = await onGetDelegates();
+    if (list) setDelegates(list);
+  };
+
+  useEffect(() => {
+    if (isOnline) loadDelegates();
+  }, [isOnline]);
+
+  const handleAddDelegate = async () => {
+    const imageIds = newDelegateImages.split(',').map(id => id.trim()).filter(Boolean);
+    if (await onSetDelegate(newDelegate.trim(), imageIds, true)) {
+      setNewDelegate('');
+      setNewDelegateImages('');
+      await loadDelegates();
+    }
+  };
+
+  const handleRemoveDelegate = async (delegate) => {
+    if (await onSetDelegate(delegate, [], false)) await loadDelegates();
+  };
+
+  const [anonymousImages, setAnonymousImages] = useState([]);
+  const [newAnonymousImages, setNewAnonymousImages] = useState(''); // comma-separated
+
+  const loadAnonymousAccess = async () => {
+    const list = await onGetAnonymousAccess();
+    if (list) setAnonymousImages(list);
+  };
+
+  useEffect(() => {
+    if (isOnline) loadAnonymousAccess();
+  }, [isOnline]);
+
+  const handleAllowAnonymous = async () => {
+    const imageIds = newAnonymousImages.split(',').map(id => id.trim()).filter(Boolean);
+    if (await onSetAnonymousAccess(imageIds, true)) {
+      setNewAnonymousImages('');
+      await loadAnonymousAccess();
+    }
+  };
+
+  const handleDisallowAnonymous = async (imageId) => {
+    if (await onSetAnonymousAccess([imageId], false)) await loadAnonymousAccess();
+  };
+
+  const [storageUsage, setStorageUsage] = useState(null);
+  const [quotaMb, setQuotaMb] = useState('');
+
+  const [namePolicy, setNamePolicy] = useState(null);
+
+  const loadStorageUsage = async () => {
+    const usage = await onGetStorageUsage();
+    setStorageUsage(usage);
+    if (usage?.quota_bytes != null) setQuotaMb(String(Math.round(usage.quota_bytes / 1024 / 1024)));
+  };
+
+  useEffect(() => {
+    if (isOnline) {
+      loadStorageUsage();
+      onGetNamePolicy().then(setNamePolicy);
+    }
+  }, [isOnline]);
+
+  const handleNamePolicyChange = async (policy) => {
+    if (await onSetNamePolicy(policy)) setNamePolicy(policy);
+  };
+
+  const handleSaveQuota = async () => {
+    await onSetStorageQuota(quotaMb === '' ? null : parseInt(quotaMb, 10));
+    if (isOnline) await loadStorageUsage();
+  };
+
+  const unviewedBytes = storageUsage
+    ? storageUsage.images.filter(i => i.class === 'Unviewed').reduce((sum, i) => sum + i.size_bytes, 0)
+    : 0;
+  const formatMb = (bytes) => (bytes / 1024 / 1024).toFixed(1);
+
+  const [bandwidth, setBandwidth] = useState(null);
+  const [capMb, setCapMb] = useState('');
+
+  const loadBandwidth = async () => {
+    const stats = await onGetBandwidthStats();
+    setBandwidth(stats);
+    if (stats?.daily_cap_bytes != null) setCapMb(String(Math.round(stats.daily_cap_bytes / 1024 / 1024)));
+  };
+
+  useEffect(() => {
+    if (isOnline) loadBandwidth();
+  }, [isOnline]);
+
+  const handleSaveCap = async () => {
+    await onSetBandwidthCap(capMb === '' ? null : parseInt(capMb, 10));
+    await loadBandwidth();
+  };
+
+  const recentDays = bandwidth ? bandwidth.days.slice(-7) : [];
+  const busiestDay = Math.max(1, ...recentDays.map(d => d.bytes_sent + d.bytes_received));
+
+  const [jobs, setJobs] = useState([]);
+
+  const loadJobs = async () => {
+    setJobs(await onGetBackgroundJobs());
+  };
+
+  useEffect(() => {
+    if (isOnline) loadJobs();
+  }, [isOnline]);
+
+  const handleRetryJob = async (jobId) => {
+    await onRetryBackgroundJob(jobId);
+    await loadJobs();
+  };
+
+  const jobStatusColor = {
+    Pending: 'text-yellow-400',
+    Running: 'text-cyan-400',
+    Succeeded: 'text-green-400',
+    Failed: 'text-red-400',
+  };
+
+  const [tracing, setTracing] = useState(false);
+  const [traceEntries, setTraceEntries] = useState([]);
+
+  const handleToggleTrace = async () => {
+    if (await onToggleProtocolTrace(!tracing)) {
+      setTracing(!tracing);
+      if (!tracing) setTraceEntries([]);
+    }
+  };
+
+  const handleRefreshTrace = async () => {
+    setTraceEntries(await onGetProtocolTrace());
+  };
+
+  const handleCopyTrace = async () => {
+    const entries = await onGetProtocolTrace();
+    setTraceEntries(entries);
+    navigator.clipboard.writeText(JSON.stringify(entries, null, 2));
+  };
+
+  const [auditResult, setAuditResult] = useState(null);
+
+  const handleExportAudit = async () => {
+    setAuditResult(await onExportAuditLog());
+  };
+
+  const reportSections = report ? [
+    { title: 'Network', checks: [report.local_ip, report.port_bind, report.reachability] },
+    { title: 'Directory servers', checks: report.directory_servers },
+    { title: 'Encryption servers', checks: report.encryption_servers },
+  ] : [];
+
+  const encryptionStats = report?.encryption_stats;
+
+  const handleAddRoot = () => {
+    if (newRootName && newRootPath && !roots.some(r => r.name === newRootName)) {
+      setRoots([...roots, { name: newRootName, path: newRootPath }]);
+      setNewRootName('');
+      setNewRootPath('');
+    }
+  };
+
+  const handleRemoveRoot = (index) => {
+    setRoots(roots.filter((_, i) => i !== index));
+  };
+
+  const handleSaveRoots = async () => {
+    await onUpdateShareRoots(roots);
+    setRootsSaved(true);
+    setTimeout(() => setRootsSaved(false), 2000);
+  };
 
   const handleAddServer = () => {
     if (newServer && !servers.includes(newServer)) {
@@ -21,6 +275,20 @@ function SettingsPanel({ directoryServers, onUpdateServers }) {
     setServers(servers.filter((_, i) => i !== index));
   };
 
+  // The backend reorders and extends the list as it health-checks servers
+  useEffect(() => {
+    setServers(directoryServers);
+  }, [directoryServers]);
+
+  const handleRefreshServers = async () => {
+    setRefreshingServers(true);
+    const result = await onRefreshDirectories();
+    if (result) {
+      setServerHealth(Object.fromEntries(result.servers.map(server => [server.address, server])));
+    }
+    setRefreshingServers(false);
+  };
+
   const handleSave = () => {
     onUpdateServers(servers);
     setSaved(true);
@@ -62,6 +330,14 @@ function SettingsPanel({ directoryServers, onUpdateServers }) {
             >
               <Globe className="w-4 h-4 text-cyan-400" />
               <span className="flex-1 font-mono text-sm text-white">{server}</span>
+              {serverHealth[server] && (
+                <span className={`text-xs ${serverHealth[server].failures === 0 && serverHealth[server].last_ok ? 'text-green-400' : 'text-red-400'}`}>
+                  {serverHealth[server].failures === 0 && serverHealth[server].last_ok
+                    ? `${serverHealth[server].latency_ms} ms`
+                    : `${serverHealth[server].failures} failed check(s)`}
+                  {serverHealth[server].learned && ' · learned'}
+                </span>
+              )}
               <button
                 onClick={() => handleRemoveServer(index)}
                 className="p-1.5 rounded-lg text-red-400 hover:bg-red-600/20 transition-colors"
@@ -97,7 +373,18 @@ function SettingsPanel({ directoryServers, onUpdateServers }) {
         </div>
 
         {/* Save button */}
-        <div className="flex justify-end mt-6 pt-6 border-t border-purple-900/30">
+        <div className="flex justify-end gap-3 mt-6 pt-6 border-t border-purple-900/30">
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleRefreshServers}
+            disabled={refreshingServers}
+            title="Health-check the servers, put the healthy ones first, and learn replicas they advertise"
+            className="flex items-center gap-2 px-6 py-3 rounded-lg font-medium bg-white/5 border border-purple-900/30 text-gray-300 hover:bg-white/10 transition-all disabled:opacity-50"
+          >
+            <RefreshCw className={`w-4 h-4 ${refreshingServers ? 'animate-spin' : ''}`} />
+            Check &amp; Discover
+          </motion.button>
           <motion.button
             whileHover={{ scale: 1.02 }}
             whileTap={{ scale: 0.98 }}
@@ -123,6 +410,745 @@ function SettingsPanel({ directoryServers, onUpdateServers }) {
         </div>
       </div>
 
+      {/* Share Folders Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-pink-600/20">
+            <FolderOpen className="w-5 h-5 text-pink-400" />
+          </div>
+          <div>
+            <h3 className="font-semibold text-white">Share Folders</h3>
+            <p className="text-sm text-gray-400">
+              Extra folders shared alongside your images directory (each uses its own encrypted/ subfolder)
+            </p>
+          </div>
+        </div>
+
+        {/* Current share folders */}
+        <div className="space-y-3 mb-4">
+          {roots.map((root, index) => (
+            <motion.div
+              key={root.name}
+              initial={{ opacity: 0, x: -20 }}
+              animate={{ opacity: 1, x: 0 }}
+              transition={{ delay: index * 0.05 }}
+              className="flex items-center gap-3 p-3 rounded-lg bg-white/5 border border-purple-900/20"
+            >
+              <FolderOpen className="w-4 h-4 text-pink-400" />
+              <span className="font-medium text-sm text-white">{root.name}</span>
+              <span className="flex-1 font-mono text-sm text-gray-400 truncate">{root.path}</span>
+              <button
+                onClick={() => handleRemoveRoot(index)}
+                className="p-1.5 rounded-lg text-red-400 hover:bg-red-600/20 transition-colors"
+              >
+                <Trash2 className="w-4 h-4" />
+              </button>
+            </motion.div>
+          ))}
+        </div>
+
+        {/* Add new share folder */}
+        <div className="flex items-center gap-3">
+          <input
+            type="text"
+            value={newRootName}
+            onChange={(e) => setNewRootName(e.target.value)}
+            placeholder="Name (e.g., Work)"
+            className="w-40 px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500 text-sm"
+          />
+          <input
+            type="text"
+            value={newRootPath}
+            onChange={(e) => setNewRootPath(e.target.value)}
+            placeholder="Folder path (e.g., /home/me/Work)"
+            className="flex-1 px-4 py-3 rounded-lg cyber-input text-white placeholder-gray-500 font-mono text-sm"
+            onKeyDown={(e) => e.key === 'Enter' && handleAddRoot()}
+          />
+          <motion.button
+            whileHover={{ scale: 1.05 }}
+            whileTap={{ scale: 0.95 }}
+            onClick={handleAddRoot}
+            disabled={!newRootName || !newRootPath}
+            className="p-3 rounded-lg bg-pink-600/20 border border-pink-500/30 text-pink-400 hover:bg-pink-600/30 transition-colors disabled:opacity-50"
+          >
+            <Plus className="w-5 h-5" />
+          </motion.button>
+        </div>
+
+        {/* Save button */}
+        <div className="flex justify-end mt-6 pt-6 border-t border-purple-900/30">
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleSaveRoots}
+            className={`flex items-center gap-2 px-6 py-3 rounded-lg font-medium transition-all ${
+              rootsSaved
+                ? 'bg-green-600/20 border border-green-500/30 text-green-400'
+                : 'bg-gradient-to-r from-purple-600 to-pink-600 text-white'
+            }`}
+          >
+            {rootsSaved ? (
+              <>
+                <Check className="w-4 h-4" />
+                Saved!
+              </>
+            ) : (
+              <>
+                <Save className="w-4 h-4" />
+                Save Folders
+              </>
+            )}
+          </motion.button>
+        </div>
+      </div>
+
+      {/* Connection Doctor Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-green-600/20">
+            <Stethoscope className="w-5 h-5 text-green-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Connection Doctor</h3>
+            <p className="text-sm text-gray-400">
+              Check your IP, P2P port reachability, directory replicas, and encryption servers
+            </p>
+          </div>
+          {report && (
+            <button
+              onClick={handleCopyReport}
+              className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+              title="Copy report for support"
+            >
+              <Copy className="w-4 h-4" />
+            </button>
+          )}
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleRunDiagnostics}
+            disabled={diagnosing}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-green-600/20 border border-green-500/30 text-green-400 hover:bg-green-600/30 transition-colors disabled:opacity-50"
+          >
+            <RefreshCw className={`w-4 h-4 ${diagnosing ? 'animate-spin' : ''}`} />
+            {diagnosing ? 'Checking...' : 'Run Checks'}
+          </motion.button>
+        </div>
+
+        {reportSections.map(section => (
+          <div key={section.title} className="mb-4 last:mb-0">
+            <p className="text-xs uppercase tracking-wide text-gray-500 mb-2">{section.title}</p>
+            <div className="space-y-2">
+              {section.checks.length === 0 && (
+                <p className="text-sm text-gray-500">Nothing configured</p>
+              )}
+              {section.checks.map((check, index) => (
+                <div
+                  key={`${check.name}-${check.target}-${index}`}
+                  className="flex items-center gap-3 p-3 rounded-lg bg-white/5 border border-purple-900/20"
+                >
+                  {check.ok
+                    ? <Check className="w-4 h-4 text-green-400" />
+                    : <X className="w-4 h-4 text-red-400" />}
+                  <span className="w-36 text-sm text-white">{check.name}</span>
+                  <span className="w-44 font-mono text-sm text-gray-400 truncate">{check.target}</span>
+                  <span className="flex-1 text-sm text-gray-400 truncate">{check.detail}</span>
+                  {check.latency_ms != null && (
+                    <span className="text-xs text-gray-500">{check.latency_ms} ms</span>
+                  )}
+                </div>
+              ))}
+            </div>
+          </div>
+        ))}
+
+        {encryptionStats && (
+          <div className="mb-4 last:mb-0">
+            <p className="text-xs uppercase tracking-wide text-gray-500 mb-2">Encryption jobs</p>
+            {encryptionStats.jobs === encryptionStats.failed ? (
+              <p className="text-sm text-gray-500">
+                {encryptionStats.jobs === 0 ? 'No encryption jobs recorded yet' : `All ${encryptionStats.jobs} recorded jobs failed`}
+              </p>
+            ) : (
+              <div className="p-3 rounded-lg bg-white/5 border border-purple-900/20 text-sm text-gray-400 space-y-1">
+                <p>
+                  {`${encryptionStats.jobs} jobs (${encryptionStats.failed} failed), ${Math.round(encryptionStats.mean_request_bytes / 1024)} KB up and ${Math.round(encryptionStats.mean_response_bytes / 1024)} KB down each`}
+                </p>
+                {ENCRYPTION_PHASES.map(phase => (
+                  <div key={phase} className="flex gap-3 font-mono text-xs">
+                    <span className="w-24 capitalize">{phase}</span>
+                    <span className="w-28">mean {encryptionStats[phase].mean_ms} ms</span>
+                    <span className="w-28">p50 {encryptionStats[phase].p50_ms} ms</span>
+                    <span className="w-28">p95 {encryptionStats[phase].p95_ms} ms</span>
+                  </div>
+                ))}
+                {encryptionStats.servers.map(server => (
+                  <div key={server.server} className="flex gap-3 font-mono text-xs">
+                    <span className="w-44 truncate">{server.server}</span>
+                    <span>{`${server.jobs} jobs, ${server.failed} failed, mean ${server.mean_total_ms} ms`}</span>
+                  </div>
+                ))}
+              </div>
+            )}
+          </div>
+        )}
+      </div>
+
+      {/* Received Storage Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-blue-600/20">
+            <HardDrive className="w-5 h-5 text-blue-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Received Storage</h3>
+            <p className="text-sm text-gray-400">
+              Cap the space received images use. Exhausted, then least recently viewed images are evicted first;
+              un-viewed images are never removed automatically.
+            </p>
+          </div>
+          {isOnline && (
+            <>
+              <button
+                onClick={onMigrateImages}
+                className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+                title="Upgrade images encrypted by older versions"
+              >
+                <Database className="w-4 h-4" />
+              </button>
+              <button
+                onClick={loadStorageUsage}
+                className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+                title="Refresh usage"
+              >
+                <RefreshCw className="w-4 h-4" />
+              </button>
+            </>
+          )}
+        </div>
+
+        <div className="flex gap-3 mb-4">
+          <input
+            type="number"
+            min="1"
+            value={quotaMb}
+            onChange={(e) => setQuotaMb(e.target.value)}
+            placeholder="No limit"
+            className="cyber-input flex-1 px-4 py-3 rounded-lg text-white placeholder-gray-500"
+          />
+          <span className="self-center text-sm text-gray-400">MB</span>
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleSaveQuota}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-blue-600/20 border border-blue-500/30 text-blue-400 hover:bg-blue-600/30 transition-colors"
+          >
+            <Save className="w-4 h-4" />
+            Apply
+          </motion.button>
+        </div>
+
+        {storageUsage ? (
+          <div className="space-y-2 text-sm">
+            {storageUsage.quota_bytes != null && (
+              <div className="h-2 rounded-full bg-white/10 overflow-hidden">
+                <div
+                  className="h-full bg-blue-500"
+                  style={{ width: `${Math.min(100, (storageUsage.used_bytes / storageUsage.quota_bytes) * 100)}%` }}
+                />
+              </div>
+            )}
+            <p className="text-gray-400">
+              {formatMb(storageUsage.used_bytes)} MB used
+              {storageUsage.quota_bytes != null && ` of ${formatMb(storageUsage.quota_bytes)} MB`}
+              {' '}across {storageUsage.images.length} images
+            </p>
+            <p className="text-gray-500">
+              {formatMb(storageUsage.evictable_bytes)} MB can be evicted automatically,
+              {' '}{formatMb(unviewedBytes)} MB is un-viewed
+              {storageUsage.disk_available_bytes != null && `, ${formatMb(storageUsage.disk_available_bytes)} MB free on disk`}
+            </p>
+            {storageUsage.read_only && (
+              <p className="flex items-center gap-2 text-yellow-400">
+                <AlertCircle className="w-4 h-4" />
+                Disk nearly full - deliveries are being refused and queued at the directory until space frees up
+              </p>
+            )}
+          </div>
+        ) : (
+          <p className="text-sm text-gray-500">Go online to see storage usage</p>
+        )}
+
+        {namePolicy && (
+          <div className="flex items-center gap-3 mt-4">
+            <span className="text-sm text-gray-400 flex-1">Name new deliveries</span>
+            <select
+              value={namePolicy}
+              onChange={(e) => handleNamePolicyChange(e.target.value)}
+              className="px-4 py-2 rounded-lg cyber-input text-white text-sm"
+              title="Images you already hold keep their names"
+            >
+              {NAME_POLICIES.map(([value, label]) => (
+                <option key={value} value={value}>{label}</option>
+              ))}
+            </select>
+          </div>
+        )}
+      </div>
+
+      {/* Viewer-Only Mode Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3">
+          <div className="p-2 rounded-lg bg-yellow-600/20">
+            <Lock className="w-5 h-5 text-yellow-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Viewer-Only Mode</h3>
+            <p className="text-sm text-gray-400">
+              {viewerMode.locked
+                ? 'Enforced on this machine: this session can browse and view, but not change your catalog'
+                : 'For shared machines: browse and view received images without encrypting, granting, trading, or deleting'}
+            </p>
+          </div>
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={() => onToggleViewerOnly(!viewerMode.enabled)}
+            disabled={viewerMode.locked}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-yellow-600/20 border border-yellow-500/30 text-yellow-400 hover:bg-yellow-600/30 transition-colors disabled:opacity-50"
+          >
+            {viewerMode.enabled ? 'Turn Off' : 'Turn On'}
+          </motion.button>
+        </div>
+      </div>
+
+      {/* Background Mode Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3">
+          <div className="p-2 rounded-lg bg-indigo-600/20">
+            <Minimize2 className="w-5 h-5 text-indigo-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Keep Sharing in the Tray</h3>
+            <p className="text-sm text-gray-400">
+              {backgroundMode
+                ? 'Closing the window while online keeps your images shared from the system tray. Use Quit in the tray menu to stop.'
+                : 'Closing the window quits the app and takes your shared images offline'}
+            </p>
+          </div>
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={() => onToggleBackgroundMode(!backgroundMode)}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-indigo-600/20 border border-indigo-500/30 text-indigo-400 hover:bg-indigo-600/30 transition-colors"
+          >
+            {backgroundMode ? 'Turn Off' : 'Turn On'}
+          </motion.button>
+        </div>
+      </div>
+
+      {/* Bandwidth Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-teal-600/20">
+            <Activity className="w-5 h-5 text-teal-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Bandwidth</h3>
+            <p className="text-sm text-gray-400">
+              P2P traffic per day. Past the daily cap, image transfers are deferred until 00:00 UTC.
+            </p>
+          </div>
+          {isOnline && (
+            <button
+              onClick={loadBandwidth}
+              className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+              title="Refresh usage"
+            >
+              <RefreshCw className="w-4 h-4" />
+            </button>
+          )}
+        </div>
+
+        <div className="flex gap-3 mb-4">
+          <input
+            type="number"
+            min="1"
+            value={capMb}
+            onChange={(e) => setCapMb(e.target.value)}
+            placeholder="No daily cap"
+            className="cyber-input flex-1 px-4 py-3 rounded-lg text-white placeholder-gray-500"
+          />
+          <span className="self-center text-sm text-gray-400">MB / day</span>
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleSaveCap}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-teal-600/20 border border-teal-500/30 text-teal-400 hover:bg-teal-600/30 transition-colors"
+          >
+            <Save className="w-4 h-4" />
+            Apply
+          </motion.button>
+        </div>
+
+        {recentDays.length === 0 ? (
+          <p className="text-sm text-gray-500">No P2P traffic recorded yet</p>
+        ) : (
+          <div className="space-y-2 text-sm">
+            {recentDays.map((day) => (
+              <div key={day.date} className="flex items-center gap-3">
+                <span className="w-24 text-gray-500 font-mono text-xs">{day.date}</span>
+                <div className="flex-1 h-2 rounded-full bg-white/10 overflow-hidden flex">
+                  <div className="h-full bg-teal-500" style={{ width: `${(day.bytes_sent / busiestDay) * 100}%` }} />
+                  <div className="h-full bg-cyan-700" style={{ width: `${(day.bytes_received / busiestDay) * 100}%` }} />
+                </div>
+                <span className="w-32 text-right text-gray-400">
+                  ↑ {formatMb(day.bytes_sent)} ↓ {formatMb(day.bytes_received)} MB
+                </span>
+              </div>
+            ))}
+            <p className="text-gray-500">
+              {formatMb(bandwidth.today_bytes)} MB today
+              {bandwidth.daily_cap_bytes != null && ` of ${formatMb(bandwidth.daily_cap_bytes)} MB`}
+            </p>
+          </div>
+        )}
+      </div>
+
+      {/* Background Jobs Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-indigo-600/20">
+            <ListChecks className="w-5 h-5 text-indigo-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Background Jobs</h3>
+            <p className="text-sm text-gray-400">
+              Deliveries, directory updates and denial reports, retried with backoff until they succeed
+            </p>
+          </div>
+          {isOnline && (
+            <button
+              onClick={loadJobs}
+              className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+              title="Refresh jobs"
+            >
+              <RefreshCw className="w-4 h-4" />
+            </button>
+          )}
+        </div>
+
+        {jobs.length === 0 ? (
+          <p className="text-sm text-gray-500">No background jobs yet</p>
+        ) : (
+          <div className="space-y-2 max-h-64 overflow-y-auto text-sm">
+            {jobs.map((job) => (
+              <div key={job.id} className="flex items-center gap-3 p-3 rounded-lg bg-white/5">
+                <div className="flex-1 min-w-0">
+                  <p className="text-white truncate">{job.description}</p>
+                  <p className="text-xs text-gray-500 truncate">
+                    <span className={jobStatusColor[job.status]}>{job.status}</span>
+                    {' '}· attempt {job.attempts}/{job.max_attempts}
+                    {job.last_error && ` · ${job.last_error}`}
+                    {job.status === 'Succeeded' && job.outcome && ` · ${job.outcome}`}
+                  </p>
+                </div>
+                {job.status === 'Failed' && (
+                  <motion.button
+                    whileHover={{ scale: 1.02 }}
+                    whileTap={{ scale: 0.98 }}
+                    onClick={() => handleRetryJob(job.id)}
+                    className="flex items-center gap-2 px-3 py-1.5 rounded-lg bg-indigo-600/20 border border-indigo-500/30 text-indigo-400 hover:bg-indigo-600/30 transition-colors"
+                  >
+                    <RefreshCw className="w-4 h-4" />
+                    Retry
+                  </motion.button>
+                )}
+              </div>
+            ))}
+          </div>
+        )}
+      </div>
+
+      {/* Protocol Trace Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-orange-600/20">
+            <Activity className="w-5 h-5 text-orange-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Protocol Trace</h3>
+            <p className="text-sm text-gray-400">
+              Record recent directory and P2P messages to attach to bug reports (tokens are redacted)
+            </p>
+          </div>
+          {tracing && (
+            <>
+              <button
+                onClick={handleRefreshTrace}
+                className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+                title="Refresh"
+              >
+                <RefreshCw className="w-4 h-4" />
+              </button>
+              <button
+                onClick={handleCopyTrace}
+                className="p-2 rounded-lg text-gray-400 hover:bg-white/10 transition-colors"
+                title="Copy trace for bug report"
+              >
+                <Copy className="w-4 h-4" />
+              </button>
+            </>
+          )}
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleToggleTrace}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-orange-600/20 border border-orange-500/30 text-orange-400 hover:bg-orange-600/30 transition-colors"
+          >
+            {tracing ? 'Stop Trace' : 'Start Trace'}
+          </motion.button>
+        </div>
+
+        {traceEntries.length === 0 ? (
+          <p className="text-sm text-gray-500">
+            {tracing ? 'No messages recorded yet' : 'Tracing is off'}
+          </p>
+        ) : (
+          <div className="space-y-1 max-h-64 overflow-y-auto">
+            {traceEntries.slice(-50).reverse().map((entry, index) => (
+              <div
+                key={index}
+                className="flex items-center gap-3 px-3 py-2 rounded-lg bg-white/5 font-mono text-xs"
+              >
+                <span className={entry.direction === 'Sent' ? 'text-cyan-400' : 'text-green-400'}>
+                  {entry.direction === 'Sent' ? '→' : '←'}
+                </span>
+                <span className="w-16 text-gray-500">{entry.channel}</span>
+                <span className="w-40 text-gray-400 truncate">{entry.peer}</span>
+                <span className="flex-1 text-white truncate">{entry.kind}</span>
+                <span className="text-gray-500">{entry.size_bytes} B</span>
+              </div>
+            ))}
+          </div>
+        )}
+      </div>
+
+      {/* Profile Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-pink-600/20">
+            <User className="w-5 h-5 text-pink-400" />
+          </div>
+          <div>
+            <h3 className="font-semibold text-white">Profile</h3>
+            <p className="text-sm text-gray-400">How other peers see you in discovery and requests</p>
+          </div>
+        </div>
+
+        <div className="flex gap-6">
+          <div className="flex flex-col items-center gap-2">
+            {profile.avatar ? (
+              <img src={profile.avatar} alt="Avatar" className="w-16 h-16 rounded-full object-cover" />
+            ) : (
+              <div className="w-16 h-16 rounded-full bg-gradient-to-br from-purple-600 to-pink-600 flex items-center justify-center text-white font-bold text-xl">
+                {(profile.displayName || '?').charAt(0).toUpperCase()}
+              </div>
+            )}
+            <label className={`flex items-center gap-1 text-xs text-cyan-400 ${isOnline ? 'cursor-pointer hover:text-cyan-300' : 'opacity-50'}`}>
+              <Upload className="w-3 h-3" />
+              Change
+              <input type="file" accept="image/*" className="hidden" disabled={!isOnline} onChange={handlePickAvatar} />
+            </label>
+            {profile.avatar && (
+              <button onClick={handleRemoveAvatar} className="text-xs text-gray-500 hover:text-red-400">
+                Remove
+              </button>
+            )}
+          </div>
+
+          <div className="flex-1 space-y-3">
+            <input
+              type="text"
+              value={profile.displayName}
+              maxLength={64}
+              onChange={(e) => setProfile(prev => ({ ...prev, displayName: e.target.value }))}
+              placeholder="Display name"
+              className="cyber-input w-full px-4 py-3 rounded-lg text-white placeholder-gray-500"
+            />
+            <textarea
+              value={profile.bio}
+              maxLength={500}
+              rows={3}
+              onChange={(e) => setProfile(prev => ({ ...prev, bio: e.target.value }))}
+              placeholder="A short bio"
+              className="cyber-input w-full px-4 py-3 rounded-lg text-white placeholder-gray-500 resize-none"
+            />
+            <motion.button
+              whileHover={{ scale: 1.02 }}
+              whileTap={{ scale: 0.98 }}
+              onClick={handleSaveProfile}
+              disabled={!isOnline}
+              className="flex items-center gap-2 px-4 py-2 rounded-lg bg-pink-600/20 border border-pink-500/30 text-pink-400 hover:bg-pink-600/30 transition-colors disabled:opacity-50"
+            >
+              <Save className="w-4 h-4" />
+              Save Profile
+            </motion.button>
+          </div>
+        </div>
+      </div>
+
+      {/* Delegates Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-amber-600/20">
+            <Users className="w-5 h-5 text-amber-400" />
+          </div>
+          <div>
+            <h3 className="font-semibold text-white">Delegates</h3>
+            <p className="text-sm text-gray-400">
+              Users who may approve requests for your images while your peer is online; their approvals are marked in your audit log
+            </p>
+          </div>
+        </div>
+
+        <div className="space-y-2 mb-4">
+          {delegates.length === 0 ? (
+            <p className="text-sm text-gray-500">Only you answer requests for your images</p>
+          ) : delegates.map(d => (
+            <div key={d.delegate} className="flex items-center justify-between p-3 rounded-lg bg-white/5 border border-purple-900/20">
+              <div>
+                <span className="text-white font-medium">{d.delegate}</span>
+                <span className="ml-2 text-sm text-gray-400">
+                  {d.image_ids.length === 0 ? 'all images' : d.image_ids.join(', ')}
+                </span>
+              </div>
+              <button
+                onClick={() => handleRemoveDelegate(d.delegate)}
+                disabled={!isOnline}
+                className="p-2 rounded-lg text-gray-400 hover:text-red-400 hover:bg-red-600/10 transition-colors disabled:opacity-50"
+                title="Remove delegate"
+              >
+                <Trash2 className="w-4 h-4" />
+              </button>
+            </div>
+          ))}
+        </div>
+
+        <div className="flex gap-3">
+          <input
+            type="text"
+            value={newDelegate}
+            onChange={(e) => setNewDelegate(e.target.value)}
+            placeholder="Username"
+            className="cyber-input flex-1 px-4 py-3 rounded-lg text-white placeholder-gray-500"
+          />
+          <input
+            type="text"
+            value={newDelegateImages}
+            onChange={(e) => setNewDelegateImages(e.target.value)}
+            placeholder="Image IDs (blank for all)"
+            className="cyber-input flex-1 px-4 py-3 rounded-lg text-white placeholder-gray-500"
+          />
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleAddDelegate}
+            disabled={!isOnline || !newDelegate.trim()}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-amber-600/20 border border-amber-500/30 text-amber-400 hover:bg-amber-600/30 transition-colors disabled:opacity-50"
+          >
+            <Plus className="w-4 h-4" />
+            Add
+          </motion.button>
+        </div>
+      </div>
+
+      {/* Anonymous Requests Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3 mb-6">
+          <div className="p-2 rounded-lg bg-purple-600/20">
+            <EyeOff className="w-5 h-5 text-purple-400" />
+          </div>
+          <div>
+            <h3 className="font-semibold text-white">Anonymous Requests</h3>
+            <p className="text-sm text-gray-400">
+              Images that users connected anonymously, under a pseudonymous handle, may request
+            </p>
+          </div>
+        </div>
+
+        <div className="space-y-2 mb-4">
+          {anonymousImages.length === 0 ? (
+            <p className="text-sm text-gray-500">Anonymous users can't request any of your images</p>
+          ) : anonymousImages.map(imageId => (
+            <div key={imageId} className="flex items-center justify-between p-3 rounded-lg bg-white/5 border border-purple-900/20">
+              <span className="text-white font-mono text-sm truncate">{imageId}</span>
+              <button
+                onClick={() => handleDisallowAnonymous(imageId)}
+                disabled={!isOnline}
+                className="p-2 rounded-lg text-gray-400 hover:text-red-400 hover:bg-red-600/10 transition-colors disabled:opacity-50"
+                title="Stop anonymous requests"
+              >
+                <Trash2 className="w-4 h-4" />
+              </button>
+            </div>
+          ))}
+        </div>
+
+        <div className="flex gap-3">
+          <input
+            type="text"
+            value={newAnonymousImages}
+            onChange={(e) => setNewAnonymousImages(e.target.value)}
+            placeholder="Image IDs (comma-separated)"
+            className="cyber-input flex-1 px-4 py-3 rounded-lg text-white placeholder-gray-500"
+          />
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleAllowAnonymous}
+            disabled={!isOnline || !newAnonymousImages.trim()}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-purple-600/20 border border-purple-500/30 text-purple-400 hover:bg-purple-600/30 transition-colors disabled:opacity-50"
+          >
+            <Plus className="w-4 h-4" />
+            Allow
+          </motion.button>
+        </div>
+      </div>
+
+      {/* Audit Log Section */}
+      <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
+        <div className="flex items-center gap-3">
+          <div className="p-2 rounded-lg bg-emerald-600/20">
+            <FileText className="w-5 h-5 text-emerald-400" />
+          </div>
+          <div className="flex-1">
+            <h3 className="font-semibold text-white">Audit Log</h3>
+            <p className="text-sm text-gray-400">
+              Every grant and delivery of your images, hash-chained so edits are detected
+            </p>
+          </div>
+          <motion.button
+            whileHover={{ scale: 1.02 }}
+            whileTap={{ scale: 0.98 }}
+            onClick={handleExportAudit}
+            disabled={!isOnline}
+            className="flex items-center gap-2 px-4 py-2 rounded-lg bg-emerald-600/20 border border-emerald-500/30 text-emerald-400 hover:bg-emerald-600/30 transition-colors disabled:opacity-50"
+          >
+            Verify & Export
+          </motion.button>
+        </div>
+
+        {auditResult && (
+          <div className={`mt-4 flex items-center gap-2 text-sm ${auditResult.intact ? 'text-green-400' : 'text-red-400'}`}>
+            {auditResult.intact ? <Check className="w-4 h-4" /> : <AlertCircle className="w-4 h-4" />}
+            {auditResult.intact
+              ? `${auditResult.entries} entries, chain intact`
+              : `Tampered at line ${auditResult.first_bad_line}: ${auditResult.problem}`}
+          </div>
+        )}
+      </div>
+
       {/* Network Info Section */}
       <div className="cyber-card rounded-xl bg-cyber-darker/80 backdrop-blur-sm p-6">
         <div className="flex items-center gap-3 mb-6">
@@ -0,0 +1,41 @@
+// Translating backend messages
+//
+// Every command response carries a stable `code` and `params` next to its English
+// `message`. A catalog maps codes to templates whose `{name}` placeholders are filled
+// from the params; anything the current locale doesn't cover falls back to the English.
+
+// Locale -> { code: template }
+const catalogs = {};
+let currentLocale = (typeof navigator !== 'undefined' && navigator.language) || 'en';
+
+// Add (or extend) the templates for a locale, e.g. registerTranslations('de', { found_peers: '{count} Peers gefunden' })
+export function registerTranslations(locale, catalog) {
+  catalogs[locale] = { ...catalogs[locale], ...catalog };
+}
+
+export function setLocale(locale) {
+  currentLocale = locale;
+}
+
+export function getLocale() {
+  return currentLocale;
+}
+
+// The template for a code in the current locale, trying the bare language after a regional one
+function templateFor(code) {
+  const language = currentLocale.split('-')[0];
+  return catalogs[currentLocale]?.[code] ?? catalogs[language]?.[code];
+}
+
+// Text for a response (or a streamed event) with { code, params, message }
+export function translate(response) {
+  if (!response) {
+    return '';
+  }
+  const template = response.code && templateFor(response.code);
+  if (!template) {
+    return response.message ?? '';
+  }
+  const params = response.params ?? {};
+  return template.replace(/\{(\w+)\}/g, (placeholder, name) => params[name] ?? placeholder);
+}
@@ -0,0 +1,257 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::p2p_protocol::sha256_hex;
+
+// =============================================================================
+// OUTBOUND TRANSFER AUDIT LOG
+// =============================================================================
+
+/// Default file name for an owner's audit log (one JSON entry per line)
+pub const AUDIT_LOG_FILE: &str = "audit_log.jsonl";
+
+/// `prev_hash` of the first entry in a log
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+/// Serializes appends within this process (`flock` covers other processes on Unix)
+static APPEND_LOCK: Mutex<()> = Mutex::new(());
+
+/// What an owner did with one of their images
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum AuditAction {
+    /// Views granted in response to an `ImageRequest`
+    Grant,
+    /// An owner-initiated quota change on our own copy
+    PermissionUpdate,
+    /// Image pushed to a recipient with `DeliverImage`
+    Delivery,
+    /// Image resent to a recipient who lost their copy
+    Redelivery,
+    /// A recipient's copy deleted at our request
+    Wipe,
+}
+
+/// An outbound transfer to be recorded
+#[derive(Debug, Clone)]
+pub struct AuditRecord {
+    pub action: AuditAction,
+    pub recipient: String,
+    pub image_id: String,
+    /// Views granted (None when the action doesn't change views)
+    pub views: Option<u32>,
+    pub success: bool,
+    /// The message returned to (or by) the recipient
+    pub message: String,
+    /// Delegate who approved a grant on the owner's behalf
+    pub approved_by: Option<String>,
+}
+
+/// One line of the audit log, chained to the line before it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp: SystemTime,
+    pub action: AuditAction,
+    pub recipient: String,
+    pub image_id: String,
+    pub views: Option<u32>,
+    pub success: bool,
+    pub message: String,
+    /// Left out when unset so entries written before delegation still hash the same
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub approved_by: Option<String>,
+    /// `hash` of the previous entry
+    pub prev_hash: String,
+    /// SHA-256 over this entry (with `hash` empty)
+    pub hash: String,
+}
+
+impl AuditEntry {
+    fn compute_hash(&self) -> String {
+        let unsealed = AuditEntry { hash: String::new(), ..self.clone() };
+        sha256_hex(serde_json::to_string(&unsealed).unwrap_or_default().as_bytes())
+    }
+}
+
+/// Result of checking an audit log's hash chain
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditVerification {
+    pub entries: usize,
+    /// Every entry parses, is numbered in order, and chains to the one before
+    pub intact: bool,
+    /// Line number (1-based) of the first entry that fails the check
+    pub first_bad_line: Option<usize>,
+    pub problem: Option<String>,
+    /// `hash` of the last entry - compare against an earlier export to spot truncation
+    pub head_hash: String,
+}
+
+/// An audit log written out for review, with its verification
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditExport {
+    pub exported_at: SystemTime,
+    pub verification: AuditVerification,
+    pub entries: Vec<AuditEntry>,
+}
+
+/// Append a record to the log, chaining it to the last entry
+pub fn append_audit_entry(path: &Path, record: AuditRecord) -> Result<AuditEntry> {
+    let _guard = APPEND_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open audit log {}", path.display()))?;
+
+    // Hold an exclusive lock so a CLI command and a running peer can't fork the chain
+    lock_file(&file).context("Failed to lock audit log")?;
+    let result = append_locked(&mut file, path, record);
+    unlock_file(&file);
+    result
+}
+
+fn append_locked(file: &mut std::fs::File, path: &Path, record: AuditRecord) -> Result<AuditEntry> {
+    let mut last = None;
+    for line in BufReader::new(&*file).lines() {
+        let line = line.with_context(|| format!("Failed to read audit log {}", path.display()))?;
+        if !line.trim().is_empty() {
+            last = Some(line);
+        }
+    }
+    // Restarting at the genesis hash would hide whatever damaged the log, so refuse instead
+    let (seq, prev_hash) = match last {
+        Some(line) => {
+            let last: AuditEntry = serde_json::from_str(&line).with_context(|| {
+                format!("Audit log {} ends with an unreadable entry; not appending until it's repaired", path.display())
+            })?;
+            (last.seq + 1, last.hash)
+        }
+        None => (0, GENESIS_HASH.to_string()),
+    };
+
+    let mut entry = AuditEntry {
+        seq,
+        timestamp: SystemTime::now(),
+        action: record.action,
+        recipient: record.recipient,
+        image_id: record.image_id,
+        views: record.views,
+        success: record.success,
+        message: record.message,
+        approved_by: record.approved_by,
+        prev_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry.compute_hash();
+
+    writeln!(file, "{}", serde_json::to_string(&entry)?)
+        .and_then(|_| file.flush())
+        .with_context(|| format!("Failed to write audit log {}", path.display()))?;
+
+    Ok(entry)
+}
+
+#[cfg(unix)]
+fn lock_file(file: &std::fs::File) -> std::io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn unlock_file(file: &std::fs::File) {
+    use std::os::unix::io::AsRawFd;
+    unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+}
+
+/// Without `flock`, only appends from this process are serialized
+#[cfg(not(unix))]
+fn lock_file(_file: &std::fs::File) -> std::io::Result<()> {
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn unlock_file(_file: &std::fs::File) {}
+
+/// Append a record, logging instead of failing the transfer it describes
+pub fn audit(path: Option<&Path>, record: AuditRecord) {
+    if let Some(path) = path {
+        if let Err(e) = append_audit_entry(path, record) {
+            log::error!("Failed to record audit entry: {}", e);
+        }
+    }
+}
+
+/// Read every entry in a log and check its hash chain
+pub fn verify_audit_log(path: &Path) -> Result<(Vec<AuditEntry>, AuditVerification)> {
+    let data = match fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read audit log {}", path.display())),
+    };
+
+    let mut entries: Vec<AuditEntry> = Vec::new();
+    let mut failure = None;
+    for (index, line) in data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+        let entry: AuditEntry = match serde_json::from_str(line) {
+            Ok(entry) => entry,
+            Err(e) => {
+                failure = Some((index + 1, format!("Unreadable entry: {}", e)));
+                break;
+            }
+        };
+
+        let (expected_seq, expected_prev) = match entries.last() {
+            Some(prev) => (prev.seq + 1, prev.hash.as_str()),
+            None => (0, GENESIS_HASH),
+        };
+        let problem = if entry.seq != expected_seq {
+            Some(format!("Expected entry {}, found {}", expected_seq, entry.seq))
+        } else if entry.prev_hash != expected_prev {
+            Some(format!("Entry {} doesn't follow the entry before it", entry.seq))
+        } else if entry.hash != entry.compute_hash() {
+            Some(format!("Entry {} was modified after it was written", entry.seq))
+        } else {
+            None
+        };
+        if let Some(problem) = problem {
+            failure = Some((index + 1, problem));
+            break;
+        }
+        entries.push(entry);
+    }
+
+    let head_hash = entries.last().map_or_else(|| GENESIS_HASH.to_string(), |entry| entry.hash.clone());
+    let (first_bad_line, problem) = failure.unzip();
+    let verification = AuditVerification {
+        entries: entries.len(),
+        intact: first_bad_line.is_none(),
+        first_bad_line,
+        problem,
+        head_hash,
+    };
+    Ok((entries, verification))
+}
+
+/// Verify a log and write it, with the verification, as pretty JSON to `output`
+///
+/// Entries after a broken link are left out of the export.
+pub fn export_audit_log(path: &Path, output: &Path) -> Result<AuditVerification> {
+    let (entries, verification) = verify_audit_log(path)?;
+    let export = AuditExport {
+        exported_at: SystemTime::now(),
+        verification: verification.clone(),
+        entries,
+    };
+    fs::write(output, serde_json::to_string_pretty(&export)?)
+        .with_context(|| format!("Failed to write {}", output.display()))?;
+    Ok(verification)
+}
@@ -0,0 +1,185 @@
+use anyhow::{bail, Result};
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+// =============================================================================
+// BANDWIDTH ACCOUNTING
+// =============================================================================
+
+/// Default file name for persisted daily bandwidth totals
+pub const BANDWIDTH_STATS_FILE: &str = "bandwidth_stats.json";
+
+/// Days of history kept in the stats file
+const RETAINED_DAYS: usize = 90;
+
+/// Bytes exchanged with one peer
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PeerUsage {
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+}
+
+/// Bytes exchanged on one UTC day
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DailyUsage {
+    /// `YYYY-MM-DD` in UTC
+    pub date: String,
+    pub bytes_sent: u64,
+    pub bytes_received: u64,
+    /// Keyed by the peer's IP address
+    pub peers: HashMap<String, PeerUsage>,
+}
+
+impl DailyUsage {
+    pub fn total_bytes(&self) -> u64 {
+        self.bytes_sent + self.bytes_received
+    }
+}
+
+/// Usage history and cap, as shown to the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BandwidthStats {
+    /// Oldest first
+    pub days: Vec<DailyUsage>,
+    pub today_bytes: u64,
+    pub daily_cap_bytes: Option<u64>,
+}
+
+struct Ledger {
+    path: Option<PathBuf>,
+    days: BTreeMap<String, DailyUsage>,
+    daily_cap_bytes: Option<u64>,
+}
+
+static LEDGER: Mutex<Ledger> = Mutex::new(Ledger {
+    path: None,
+    days: BTreeMap::new(),
+    daily_cap_bytes: None,
+});
+
+/// Persist daily totals to `path`, picking up any totals already saved there
+pub fn set_bandwidth_stats_path(path: PathBuf) {
+    let saved: Vec<DailyUsage> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+
+    if let Ok(mut ledger) = LEDGER.lock() {
+        for day in saved {
+            ledger.days.entry(day.date.clone()).or_insert(day);
+        }
+        ledger.path = Some(path);
+    }
+}
+
+/// Set (or clear) the bytes per UTC day after which image transfers are deferred
+pub fn set_daily_cap(cap_bytes: Option<u64>) {
+    if let Ok(mut ledger) = LEDGER.lock() {
+        ledger.daily_cap_bytes = cap_bytes;
+    }
+}
+
+/// Usage history, with today's total against the cap
+pub fn bandwidth_stats() -> BandwidthStats {
+    let today = utc_date(SystemTime::now());
+    let Ok(ledger) = LEDGER.lock() else {
+        return BandwidthStats { days: Vec::new(), today_bytes: 0, daily_cap_bytes: None };
+    };
+    BandwidthStats {
+        days: ledger.days.values().cloned().collect(),
+        today_bytes: ledger.days.get(&today).map_or(0, DailyUsage::total_bytes),
+        daily_cap_bytes: ledger.daily_cap_bytes,
+    }
+}
+
+/// Fail once today's traffic has reached the daily cap (traffic with ourselves is never deferred)
+pub fn check_daily_cap(addr: &str) -> Result<()> {
+    if peer_ip(addr).is_none_or(is_self) {
+        return Ok(());
+    }
+    let stats = bandwidth_stats();
+    if let Some(cap) = stats.daily_cap_bytes.filter(|cap| stats.today_bytes >= *cap) {
+        bail!(
+            "Daily bandwidth cap of {} MB reached ({} MB used today) - image transfers are deferred until 00:00 UTC",
+            cap / 1024 / 1024,
+            stats.today_bytes / 1024 / 1024
+        );
+    }
+    Ok(())
+}
+
+/// Count bytes exchanged with a peer (`addr` is `ip:port` or a bare IP)
+///
+/// Traffic with ourselves (owners fetching their own images) isn't counted.
+pub(crate) fn record_traffic(addr: &str, bytes_sent: u64, bytes_received: u64) {
+    let Some(ip) = peer_ip(addr) else {
+        return;
+    };
+    if is_self(ip) {
+        return;
+    }
+
+    let today = utc_date(SystemTime::now());
+    let Ok(mut ledger) = LEDGER.lock() else {
+        return;
+    };
+    let day = ledger.days.entry(today.clone()).or_insert_with(|| DailyUsage { date: today, ..Default::default() });
+    day.bytes_sent += bytes_sent;
+    day.bytes_received += bytes_received;
+    let peer = day.peers.entry(ip.to_string()).or_default();
+    peer.bytes_sent += bytes_sent;
+    peer.bytes_received += bytes_received;
+
+    while ledger.days.len() > RETAINED_DAYS {
+        ledger.days.pop_first();
+    }
+
+    if let Some(path) = &ledger.path {
+        let days: Vec<&DailyUsage> = ledger.days.values().collect();
+        let result = serde_json::to_string_pretty(&days)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| fs::write(path, json).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            error!("Failed to save bandwidth stats to {}: {}", path.display(), e);
+        }
+    }
+}
+
+fn peer_ip(addr: &str) -> Option<IpAddr> {
+    addr.parse::<std::net::SocketAddr>()
+        .map(|socket| socket.ip())
+        .or_else(|_| addr.parse::<IpAddr>())
+        .ok()
+}
+
+fn is_self(ip: IpAddr) -> bool {
+    static LOCAL_IP: OnceLock<Option<IpAddr>> = OnceLock::new();
+    let local_ip = LOCAL_IP.get_or_init(|| crate::get_local_ip().ok().and_then(|ip| ip.parse().ok()));
+    ip.is_loopback() || ip.is_unspecified() || Some(ip) == *local_ip
+}
+
+/// `YYYY-MM-DD` for a time, in UTC
+pub(crate) fn utc_date(time: SystemTime) -> String {
+    let days = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|since| since.as_secs() / 86_400)
+        .unwrap_or(0) as i64;
+
+    // Civil-from-days (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
@@ -1,33 +1,59 @@
-use anyhow::{bail, Result};
-use bincode;
-use cloud_p2p_project::directory_service::{DirectoryMessage, ImageInfo, send_directory_message};
+use anyhow::{bail, Context, Result};
+use cloud_p2p_project::directory_service::{
+    AvailabilityWindow, DirectoryMessage, ImageInfo, ImageVisibility, PendingPermissionUpdate, PendingRequest, ResponseOutlook, TradeProposal, TradeStatus, UserEntry, DEFAULT_SEARCH_LIMIT, REPLICA_SECRET_ENV, send_directory_message,
+    avatar_thumbnail, negotiated_heartbeat_interval, parse_share_link, qualified_image_id, share_link, unqualify_image_id, user_key, with_token, with_namespace, client_namespace, fetch_pending_permission_updates, USER_KEYS_FILE,
+};
 use cloud_p2p_project::p2p_protocol::{
-    ImageMetadata, PeerImageStore,
-    list_peer_images, start_p2p_server,
+    AccessDenial, CAP_ACCESS_REPORTS, CAP_CHECKSUMS, CAP_IMAGE_PROBE, CAP_PRIORITY_LANES, CAP_REDELIVERY, ACCESS_DENIAL_STATS_FILE, ImageMetadata,
+    PeerImageStore, access_denial_for, content_sha256, embedded_grant_seq, reserve_disk_space, embedded_permissions, image_annotations, search_peer_images, preview_quota_change, ping_peer, probe_peer_image, load_access_denial_stats, local_capabilities, record_access_denials,
+    embedded_image_id, migrate_carrier, new_image_id, set_image_holder, VISIBILITY_FILE, set_view_policy, request_redelivery, send_remote_wipe, send_wipe_ack, wipe_received_image, CAP_REMOTE_WIPE, save_received_image, shared_image_id, send_access_denial, sha256_hex, serve_p2p_via_relay, start_p2p_server,
+    subscribe_catalog, CatalogEvent, CAP_CATALOG_UPDATES, find_received_copies, load_name_policy, received_image_path, save_name_policy, ReceivedNamePolicy,
 };
-use cloud_p2p_project::{lsb, CombinedPayload, ImagePermissions, get_local_ip};
+use cloud_p2p_project::audit_log::{audit, export_audit_log, AuditAction, AuditRecord, AUDIT_LOG_FILE};
+use cloud_p2p_project::bandwidth::{bandwidth_stats, set_bandwidth_stats_path, set_daily_cap, BANDWIDTH_STATS_FILE};
+use cloud_p2p_project::reputation::{record_delivery, record_revocation, reputation, set_reputation_path, LOW_REPUTATION_SCORE, REPUTATION_FILE};
+use cloud_p2p_project::bootstrap::DirectoryBootstrap;
+use cloud_p2p_project::diagnostics::{run_diagnostics, DiagnosticCheck};
+use cloud_p2p_project::photo_import::{scan_library, IMPORTABLE_EXTENSIONS};
+use cloud_p2p_project::encryption_pool::{encrypt_batch, set_encryption_stats_path, EncryptionJob, ENCRYPTION_STATS_FILE};
+use cloud_p2p_project::fsscan::{is_image_file, scan_images};
+use cloud_p2p_project::humantime::time_ago;
+use cloud_p2p_project::relay::{relay_p2p_address, relay_secret, set_outbound_relay, RELAY_SECRETS_FILE};
+use cloud_p2p_project::viewing::{consume_view, ViewDenial, ViewOutcome};
+use cloud_p2p_project::grant_package::{export_grant_package, import_grant_package, GRANT_PACKAGE_EXTENSION};
+use cloud_p2p_project::pairing::{parse_pairing, redeem_pairing};
+use cloud_p2p_project::recovery::{recover_carrier, reembed};
+use cloud_p2p_project::{lsb, provenance_chain, watermark, CombinedPayload, EncryptionMetadata, ImageAnnotations, ImagePermissions, ViewPolicy, PAYLOAD_VERSION, PERMISSION_HISTORY_LIMIT, get_local_ip};
 use clap::{Parser, Subcommand};
 use std::collections::HashMap;
 use std::fs;
 use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::os::unix::io::AsRawFd;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, OnceLock};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
 
 const ENCRYPTED_OUTPUT_IMAGE: &str = "encrypted_lsb_image.png";
 const VIEWABLE_OUTPUT_IMAGE: &str = "viewable_image.png";
 const SERVER_CONFIG_FILE: &str = "servers.conf";
 
-// List of all directory servers for multicast
-const DIRECTORY_SERVERS: &[&str] = &[
-    "10.7.57.239:9000",
-    "10.7.57.240:9000",
-    "10.7.57.99:9000",
-];
+/// How often a running peer retries deliveries owed for requests it accepted
+const UNDELIVERED_GRANT_RETRY_INTERVAL: Duration = Duration::from_secs(60);
+
+/// The key `username` registers with from this directory; answering their requests needs it
+fn registration_key(username: &str) -> Result<String> {
+    user_key(Path::new(USER_KEYS_FILE), username)
+}
+
+// List of all directory servers for multicast, from the bootstrap file (last-known-good first)
+static DIRECTORY_SERVERS: OnceLock<Vec<String>> = OnceLock::new();
+
+fn directory_servers() -> &'static [String] {
+    DIRECTORY_SERVERS.get_or_init(|| DirectoryBootstrap::load_or_default(&DirectoryBootstrap::default_path()).addresses())
+}
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
@@ -47,6 +73,42 @@ enum Commands {
         /// The user who owns this image
         #[arg(short, long)]
         owner: String,
+
+        /// Caption shown to recipients (also searchable in your shared catalog)
+        #[arg(long)]
+        caption: Option<String>,
+
+        /// Alt text describing the image for recipients
+        #[arg(long)]
+        alt_text: Option<String>,
+
+        /// License recipients may use the image under (e.g. CC-BY-4.0)
+        #[arg(long)]
+        license: Option<String>,
+
+        /// Stop recipients viewing the image after this many days
+        #[arg(long)]
+        expires_in_days: Option<u64>,
+
+        /// User who may keep a copy but never view it, e.g. a backup account (repeatable)
+        #[arg(long = "holder")]
+        holders: Vec<String>,
+
+        /// Don't let viewers save the decoded image
+        #[arg(long)]
+        no_save: bool,
+
+        /// Don't let viewers print the decoded image
+        #[arg(long)]
+        no_print: bool,
+
+        /// Stamp the viewer's name and the time across every decoded view
+        #[arg(long)]
+        watermark: bool,
+
+        /// Losslessly recompress the encrypted PNG to shrink it before saving
+        #[arg(long)]
+        optimize: bool,
     },
     
     /// View a protected image (local viewing)
@@ -59,6 +121,90 @@ enum Commands {
         #[arg(short, long)]
         user: String,
     },
+
+    /// Upgrade protected images encrypted by older versions to the current payload format, in place
+    MigrateImage {
+        /// A protected image, or a folder of them
+        #[arg(short, long)]
+        path: PathBuf,
+    },
+
+    /// Let a user keep a copy of one of your protected images without viewing it, or stop them
+    SetHolder {
+        /// Your protected image
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Your username (must be the image's owner)
+        #[arg(short, long)]
+        owner: String,
+
+        /// The user to add or remove as a holder
+        #[arg(long)]
+        holder: String,
+
+        /// Remove the user as a holder instead
+        #[arg(long)]
+        remove: bool,
+    },
+
+    /// Change whether viewers of one of your protected images may save or print it, and whether it's watermarked
+    SetViewPolicy {
+        /// Your protected image
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Your username (must be the image's owner)
+        #[arg(short, long)]
+        owner: String,
+
+        /// Don't let viewers save the decoded image
+        #[arg(long)]
+        no_save: bool,
+
+        /// Don't let viewers print the decoded image
+        #[arg(long)]
+        no_print: bool,
+
+        /// Stamp the viewer's name and the time across every decoded view
+        #[arg(long)]
+        watermark: bool,
+    },
+
+    /// Show the quota changes recorded inside a protected image
+    PermissionHistory {
+        /// A protected image (your own or one you received)
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Only show changes to this user's quota
+        #[arg(short, long)]
+        user: Option<String>,
+    },
+
+    /// Read the grants out of a damaged protected image, and optionally put them into a fresh carrier
+    Recover {
+        /// The damaged protected image
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// A fresh carrier to re-embed the recovered permissions into: the original image
+        /// encrypted again, or any plain image if the whole payload survived
+        #[arg(long)]
+        into: Option<PathBuf>,
+
+        /// Where to write the repaired carrier (defaults to the damaged file's name with `recovered_` in front)
+        #[arg(short, long, requires = "into")]
+        output: Option<PathBuf>,
+
+        /// Image ID to give the repaired carrier, when it didn't survive (see your shared images)
+        #[arg(long, requires = "into")]
+        image_id: Option<String>,
+
+        /// Print the recovery report as JSON
+        #[arg(long)]
+        json: bool,
+    },
     
     /// Start as a P2P peer (register with directory service and listen for requests)
     StartPeer {
@@ -73,6 +219,38 @@ enum Commands {
         /// Directory service address (optional, will multicast if not specified)
         #[arg(short, long)]
         directory: Option<String>,
+
+        /// Maximum views granted per request for each shared image (optional, no cap if omitted)
+        #[arg(long)]
+        max_views: Option<u32>,
+
+        /// Maximum MB received images may occupy; exhausted and viewed images are evicted first
+        #[arg(long)]
+        max_received_mb: Option<u64>,
+
+        /// When you're usually online, as HH:MM-HH:MM in UTC (shown to peers deciding whether to wait)
+        #[arg(long)]
+        available: Option<String>,
+
+        /// Maximum MB of P2P traffic per UTC day; image transfers beyond it are deferred
+        #[arg(long)]
+        daily_cap_mb: Option<u64>,
+
+        /// Stay hidden: register the username as a pseudonymous handle and take all P2P
+        /// traffic through a directory relay, so your IP is never published
+        #[arg(long, default_value_t = false)]
+        anonymous: bool,
+
+        /// Directory server to relay through when anonymous (defaults to --directory, else the first known server)
+        #[arg(long)]
+        relay: Option<String>,
+    },
+
+    /// Show P2P bandwidth used per day and per peer
+    Bandwidth {
+        /// Number of most recent days to show
+        #[arg(short, long, default_value_t = 7)]
+        days: usize,
     },
     
     /// Discover online peers
@@ -86,6 +264,161 @@ enum Commands {
         directory: Option<String>,
     },
     
+    /// Search every peer's shared images by name and caption (typos and partial words are fine)
+    SearchImages {
+        /// Words to search for
+        #[arg(short, long)]
+        query: String,
+
+        /// Maximum results to show
+        #[arg(short, long, default_value_t = DEFAULT_SEARCH_LIMIT)]
+        limit: usize,
+
+        /// Your username, to show the views you already hold on each match
+        #[arg(short, long)]
+        username: Option<String>,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Find which peers share an image, by ID (optionally owner/image_id) or content hash prefix
+    FindImage {
+        /// Image ID, owner/image_id, or at least 8 hex digits of the image's hash
+        #[arg(short, long)]
+        image: String,
+
+        /// Your username, to include images shared only with you as a contact
+        #[arg(short, long)]
+        username: Option<String>,
+        
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+    
+    /// Set the display name, avatar and bio other peers see (unset fields are kept)
+    UpdateProfile {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Display name (empty to clear)
+        #[arg(long)]
+        display_name: Option<String>,
+
+        /// Short bio (empty to clear)
+        #[arg(long)]
+        bio: Option<String>,
+
+        /// Image to use as your avatar (scaled to a thumbnail)
+        #[arg(long, conflicts_with = "clear_avatar")]
+        avatar: Option<PathBuf>,
+
+        /// Remove your avatar
+        #[arg(long, default_value_t = false)]
+        clear_avatar: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+    
+    /// Let another user answer requests for your images (all of them unless --image is given)
+    SetDelegate {
+        /// Your username
+        #[arg(short, long)]
+        owner: String,
+
+        /// User who may approve or reject requests for you
+        #[arg(long)]
+        delegate: String,
+
+        /// Only requests for this image (repeat for several)
+        #[arg(short, long = "image")]
+        images: Vec<String>,
+
+        /// Stop the delegate answering for you
+        #[arg(long, default_value_t = false)]
+        remove: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Choose which of your images anonymous users may request
+    AnonymousAccess {
+        /// Your username
+        #[arg(short, long)]
+        owner: String,
+
+        /// Image to allow (repeat for several)
+        #[arg(short, long = "image", required = true)]
+        images: Vec<String>,
+
+        /// Stop anonymous users requesting these images
+        #[arg(long, default_value_t = false)]
+        deny: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Choose who can find and request one of your images (run in the folder start-peer shares;
+    /// takes effect the next time start-peer runs)
+    SetVisibility {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Image ID or file name
+        #[arg(short, long)]
+        image_id: String,
+
+        /// public, contacts-only (users you follow), link-only (requests need its share link) or hidden
+        #[arg(short, long)]
+        visibility: String,
+    },
+
+    /// Leave a blurred preview of one of your images with the directory, so peers can browse
+    /// it while you're offline (run in the folder start-peer shares; takes effect the next time start-peer runs)
+    EscrowPreview {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Image ID or file name
+        #[arg(short, long)]
+        image_id: String,
+
+        /// Take the preview back instead
+        #[arg(long, default_value_t = false)]
+        off: bool,
+    },
+
+    /// Cap the views granted per request for one of your images, overriding start-peer's --max-views
+    /// (run in the folder start-peer shares; takes effect the next time start-peer runs)
+    SetGrantCap {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Image ID or file name
+        #[arg(short, long)]
+        image_id: String,
+
+        /// Most views one request may be granted
+        #[arg(short, long, required_unless_present = "clear")]
+        max_views: Option<u32>,
+
+        /// Go back to start-peer's --max-views for this image
+        #[arg(long, default_value_t = false, conflicts_with = "max_views")]
+        clear: bool,
+    },
+    
     /// Request an image from a peer
     RequestImage {
         /// Your username
@@ -96,13 +429,17 @@ enum Commands {
         #[arg(short, long)]
         peer: String,
         
-        /// Image ID to request
+        /// Image ID to request (or the share link of a link-only image)
         #[arg(short, long)]
         image_id: String,
         
         /// Number of views requested
         #[arg(short, long)]
         views: u32,
+
+        /// Ask for a copy no larger than this many pixels on its longer side
+        #[arg(long)]
+        max_dimension: Option<u32>,
         
         /// Directory service address (optional, will multicast if not specified)
         #[arg(short, long)]
@@ -119,11 +456,111 @@ enum Commands {
         #[arg(short, long)]
         peer: String,
 
+        /// Only list images whose name or description matches, best match first
+        #[arg(long)]
+        query: Option<String>,
+
+        /// Keep running and print images as the peer shares, changes or unshares them
+        #[arg(long, conflicts_with = "query")]
+        watch: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Ask an owner to resend an image you were granted but lost your copy of (no new approval needed)
+    Redeliver {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Owner of the image
+        #[arg(short, long)]
+        peer: String,
+
+        /// Image ID to re-deliver
+        #[arg(short, long)]
+        image_id: String,
+
         /// Directory service address (optional, will multicast if not specified)
         #[arg(short, long)]
         directory: Option<String>,
     },
 
+    /// Grant views of one of your encrypted images into a signed package file, for carrying over on removable media
+    ExportGrant {
+        /// Your encrypted image
+        #[arg(short, long)]
+        input: PathBuf,
+
+        /// Your username (the image's owner)
+        #[arg(short, long)]
+        owner: String,
+
+        /// Who the package is for
+        #[arg(short, long)]
+        recipient: String,
+
+        /// Views to grant
+        #[arg(short, long)]
+        views: u32,
+
+        /// Passphrase to sign the package with (tell the recipient in person)
+        #[arg(long)]
+        passphrase: String,
+
+        /// Package file to write (defaults to <recipient>_<image>.p2pgrant)
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Import a grant package into your received images, as if it had been delivered over P2P
+    ImportGrant {
+        /// Package file
+        #[arg(short, long)]
+        package: PathBuf,
+
+        /// Your username (the recipient)
+        #[arg(short, long)]
+        username: String,
+
+        /// Passphrase the owner signed the package with
+        #[arg(long)]
+        passphrase: String,
+
+        /// Folder received images are kept in (defaults to the current directory, as for start-peer)
+        #[arg(long)]
+        received_dir: Option<PathBuf>,
+    },
+
+    /// Redeem a pairing code the owner showed you in person, receiving the image right away
+    RedeemPairing {
+        /// The owner's pairing link (p2ppair://address/code), or their address and the code
+        #[arg(required = true, num_args = 1..=2)]
+        pairing: Vec<String>,
+
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Folder received images are kept in (defaults to the current directory, as for start-peer)
+        #[arg(long)]
+        received_dir: Option<PathBuf>,
+    },
+
+    /// Show or set how images delivered to you are named on disk (copies already held keep their names)
+    ReceivedNames {
+        /// owner-prefixed (from_<owner>_<image>), original-name (the owner's image ID) or
+        /// hash-based (reveals nothing about the owner); omit to show the current policy
+        #[arg(short, long)]
+        policy: Option<String>,
+
+        /// Folder received images are kept in (defaults to the current directory, as for start-peer)
+        #[arg(long)]
+        received_dir: Option<PathBuf>,
+    },
+
     /// Check pending image requests (for owners)
     CheckRequests {
         /// Your username
@@ -137,7 +574,7 @@ enum Commands {
 
     /// Respond to a pending request (accept or reject)
     RespondRequest {
-        /// Your username (must be the owner)
+        /// Your username (the owner, or a delegate they chose)
         #[arg(short, long)]
         owner: String,
 
@@ -169,6 +606,55 @@ enum Commands {
         directory: Option<String>,
     },
 
+    /// Cancel a pending request you sent (for requesters)
+    CancelRequest {
+        /// Your username (must be the requester)
+        #[arg(short, long)]
+        username: String,
+
+        /// Request ID to cancel
+        #[arg(short, long)]
+        request_id: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// List requests that have waited on you past the directory's reminder threshold (for owners)
+    Reminders {
+        /// Your username (the owner)
+        #[arg(short, long)]
+        username: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Snooze or dismiss the reminder about a pending request (for owners)
+    SnoozeReminder {
+        /// Your username (the owner)
+        #[arg(short, long)]
+        username: String,
+
+        /// Request ID to stop being reminded about
+        #[arg(short, long)]
+        request_id: String,
+
+        /// Remind again after this many hours
+        #[arg(long, default_value = "24", conflicts_with = "dismiss")]
+        hours: u64,
+
+        /// Never remind about this request again
+        #[arg(long)]
+        dismiss: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
     /// Remotely update permissions on an image you've already shared
     RemoteUpdatePermissions {
         /// Your username (the owner of the image)
@@ -187,49 +673,418 @@ enum Commands {
         #[arg(short, long)]
         new_quota: u32,
 
+        /// Show the resulting quotas from the copy in the current directory without changing or sending anything
+        #[arg(long)]
+        preview: bool,
+
+        /// If they're offline, give up on the queued update after this many days (default: the directory's limit)
+        #[arg(long)]
+        queue_ttl_days: Option<u64>,
+
         /// Directory service address (optional, will multicast if not specified)
         #[arg(short, long)]
         directory: Option<String>,
     },
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    env_logger::init();
-    
-    let cli = Cli::parse();
-    match &cli.command {
-        Commands::Encrypt { ref input, ref owner } => {
-            handle_encrypt(input, owner)?;
-        }
-        Commands::View { ref input, ref user } => {
-            handle_view(input, user)?;
-        }
-        Commands::StartPeer {
-            username,
-            port,
-            directory,
-        } => {
-            handle_start_peer(username, *port, directory.as_deref()).await?;
-        }
-        Commands::DiscoverPeers { username, directory } => {
-            handle_discover_peers(username, directory.as_deref()).await?;
-        }
-        Commands::RequestImage {
-            username,
-            peer,
+    /// Ask a recipient's client to securely delete its copy of your image (queued if they're offline)
+    RemoteWipe {
+        /// Your username (the owner of the image)
+        #[arg(short, long)]
+        owner: String,
+
+        /// The user whose copy should be deleted
+        #[arg(short, long)]
+        target_user: String,
+
+        /// The image ID
+        #[arg(short, long)]
+        image_id: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Interactive console for owners: review requests, grants, and permissions in one session
+    OwnerConsole {
+        /// Your username (the owner)
+        #[arg(short, long)]
+        username: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Check local networking, P2P port reachability, directory replicas, and encryption servers
+    Doctor {
+        /// P2P port you listen on (or plan to)
+        #[arg(short, long, default_value_t = 7001)]
+        port: u16,
+
+        /// Directory service address (optional, checks every known server if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+
+        /// Print the report as JSON (for attaching to support requests)
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+
+    /// Health-check the directory servers in the bootstrap file, learn replicas they advertise, and save the new order
+    RefreshDirectories {
+        /// Bootstrap file (defaults to $P2P_DIRECTORY_BOOTSTRAP or directory_servers.json)
+        #[arg(short, long)]
+        file: Option<PathBuf>,
+    },
+
+    /// Verify the outbound transfer audit log and export it for review
+    ExportAuditLog {
+        /// File to write the export to
+        #[arg(short, long, default_value = "audit_export.json")]
+        output: PathBuf,
+
+        /// Audit log to export
+        #[arg(short, long, default_value = AUDIT_LOG_FILE)]
+        log: PathBuf,
+    },
+
+    /// Offer views on one of your images in exchange for views on one of a peer's images
+    ProposeTrade {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Peer to trade with
+        #[arg(short, long)]
+        peer: String,
+
+        /// Your image ID to offer
+        #[arg(long)]
+        offer: String,
+
+        /// Views the peer gets on your image
+        #[arg(long)]
+        offer_views: u32,
+
+        /// The peer's image ID you want
+        #[arg(long)]
+        want: String,
+
+        /// Views you get on the peer's image
+        #[arg(long)]
+        want_views: u32,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// List trades you are part of
+    CheckTrades {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Accept or reject a trade proposed to you (accepting deposits your image)
+    RespondTrade {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Trade ID to respond to
+        #[arg(short, long)]
+        trade_id: String,
+
+        /// Accept the trade
+        #[arg(long, default_value_t = false)]
+        accept: bool,
+
+        /// Reject the trade
+        #[arg(long, default_value_t = false)]
+        reject: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Withdraw from a trade that hasn't completed
+    CancelTrade {
+        /// Your username
+        #[arg(short, long)]
+        username: String,
+
+        /// Trade ID to cancel
+        #[arg(short, long)]
+        trade_id: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Admin: rename a user (or merge them into an existing account) on every replica
+    RenameUser {
+        /// Current (e.g. mistyped) username
+        #[arg(short, long)]
+        from: String,
+
+        /// Username to rename or merge into
+        #[arg(short, long)]
+        to: String,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Admin: list users archived for staying offline too long, or purge them on every replica
+    ArchivedUsers {
+        /// Delete these archived users for good
+        #[arg(long, num_args = 1..)]
+        purge: Vec<String>,
+
+        /// Delete every archived user for good
+        #[arg(long, conflicts_with = "purge")]
+        purge_all: bool,
+
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+    },
+
+    /// Show which directory replica answers: its ID, version, uptime, state file, peers and counts
+    ServerInfo {
+        /// Directory service address (optional, reports whichever replica answers first)
+        #[arg(short, long)]
+        directory: Option<String>,
+
+        /// Print the info as JSON
+        #[arg(long, default_value_t = false)]
+        json: bool,
+    },
+
+    /// Encrypt every photo in an existing library, captioned from its EXIF caption, date and keywords
+    ImportLibrary {
+        /// Folder holding the photos (searched recursively)
+        #[arg(short, long)]
+        library: PathBuf,
+
+        /// The user who owns the photos
+        #[arg(short, long)]
+        owner: String,
+
+        /// Group photos into albums named after the folders they're in
+        #[arg(long)]
+        albums: bool,
+
+        /// Encryption server address (repeat for several; defaults to servers.conf)
+        #[arg(short, long)]
+        server: Vec<String>,
+
+        /// Folder the encrypted copies go to (the folder you run your peer from)
+        #[arg(long, default_value = ".")]
+        output_dir: PathBuf,
+
+        /// List what would be imported, with captions, without encrypting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+
+    /// Walk a scripted share, view and revoke scenario and print a pass/fail transcript
+    Demo {
+        /// Directory service address (optional, will multicast if not specified)
+        #[arg(short, long)]
+        directory: Option<String>,
+
+        /// Encryption server address (repeat for several; defaults to servers.conf)
+        #[arg(short, long)]
+        server: Vec<String>,
+
+        /// Image to share (a generated sample if omitted)
+        #[arg(short, long)]
+        image: Option<PathBuf>,
+
+        /// Username of the sharing user
+        #[arg(long, default_value = "demo-owner")]
+        owner: String,
+
+        /// Username of the viewing user
+        #[arg(long, default_value = "demo-viewer")]
+        viewer: String,
+
+        /// P2P port of the owner (the viewer uses the next port up)
+        #[arg(short, long, default_value_t = 7700)]
+        port: u16,
+
+        /// Host the demo users register as reachable on
+        #[arg(long, default_value = "127.0.0.1")]
+        advertise_host: String,
+
+        /// Folder for the demo users' images (their subfolders are cleared first)
+        #[arg(long, default_value = "demo-data")]
+        work_dir: PathBuf,
+    },
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::init();
+    set_reputation_path(PathBuf::from(REPUTATION_FILE));
+    
+    let cli = Cli::parse();
+    match &cli.command {
+        Commands::Encrypt {
+            ref input,
+            ref owner,
+            caption,
+            alt_text,
+            license,
+            expires_in_days,
+            holders,
+            no_save,
+            no_print,
+            watermark,
+            optimize,
+        } => {
+            let annotations = ImageAnnotations {
+                caption: caption.clone(),
+                alt_text: alt_text.clone(),
+                license: license.clone(),
+            };
+            let expires_at = expires_in_days.map(|days| SystemTime::now() + Duration::from_secs(days * 86_400));
+            let policy = ViewPolicy { allow_save: !no_save, allow_print: !no_print, watermark: *watermark };
+            handle_encrypt(input, owner, annotations, expires_at, holders.clone(), policy, *optimize)?;
+        }
+        Commands::View { ref input, ref user } => {
+            // Work out a denial before viewing, since a successful view can use up the last view
+            let denial = access_denial_for(input, user);
+            handle_view(input, user).await?;
+            if let Some(denial) = denial {
+                report_access_denial(denial).await;
+            }
+        }
+        Commands::StartPeer {
+            username,
+            port,
+            directory,
+            max_views,
+            max_received_mb,
+            available,
+            daily_cap_mb,
+            anonymous,
+            relay,
+        } => {
+            let availability = available.as_deref().map(AvailabilityWindow::parse).transpose()?;
+            let relay = match (anonymous, relay) {
+                (false, _) => None,
+                (true, Some(relay)) => Some(relay.clone()),
+                (true, None) => match directory.clone().or_else(|| directory_servers().first().cloned()) {
+                    Some(relay) => Some(relay),
+                    None => bail!("No directory server to relay through; pass --relay"),
+                },
+            };
+            handle_start_peer(username, *port, directory.as_deref(), *max_views, *max_received_mb, availability, *daily_cap_mb, relay.as_deref()).await?;
+        }
+        Commands::MigrateImage { path } => {
+            handle_migrate_image(path)?;
+        }
+        Commands::SetHolder { input, owner, holder, remove } => {
+            handle_set_holder(input, owner, holder, !*remove)?;
+        }
+        Commands::SetViewPolicy { input, owner, no_save, no_print, watermark } => {
+            let policy = ViewPolicy { allow_save: !no_save, allow_print: !no_print, watermark: *watermark };
+            handle_set_view_policy(input, owner, policy)?;
+        }
+        Commands::PermissionHistory { input, user } => {
+            handle_permission_history(input, user.as_deref())?;
+        }
+        Commands::Recover { input, into, output, image_id, json } => {
+            handle_recover(input, into.as_deref(), output.as_deref(), image_id.as_deref(), *json)?;
+        }
+        Commands::Bandwidth { days } => {
+            handle_bandwidth(*days);
+        }
+        Commands::DiscoverPeers { username, directory } => {
+            handle_discover_peers(username, directory.as_deref()).await?;
+        }
+        Commands::SearchImages { query, limit, username, directory } => {
+            handle_search_images(query, *limit, username.as_deref(), directory.as_deref()).await?;
+        }
+        Commands::FindImage { image, username, directory } => {
+            handle_find_image(image, username.as_deref(), directory.as_deref()).await?;
+        }
+        Commands::UpdateProfile { username, display_name, bio, avatar, clear_avatar, directory } => {
+            handle_update_profile(
+                username,
+                display_name.clone(),
+                bio.clone(),
+                avatar.as_deref(),
+                *clear_avatar,
+                directory.as_deref(),
+            )
+            .await?;
+        }
+        Commands::SetDelegate { owner, delegate, images, remove, directory } => {
+            handle_set_delegate(owner, delegate, images.clone(), !*remove, directory.as_deref()).await?;
+        }
+        Commands::SetVisibility { username, image_id, visibility } => {
+            handle_set_visibility(username, image_id, ImageVisibility::parse(visibility)?)?;
+        }
+        Commands::EscrowPreview { username, image_id, off } => {
+            handle_escrow_preview(username, image_id, !*off)?;
+        }
+        Commands::SetGrantCap { username, image_id, max_views, .. } => {
+            handle_set_grant_cap(username, image_id, *max_views)?;
+        }
+        Commands::AnonymousAccess { owner, images, deny, directory } => {
+            handle_anonymous_access(owner, images.clone(), !*deny, directory.as_deref()).await?;
+        }
+        Commands::RequestImage {
+            username,
+            peer,
             image_id,
             views,
+            max_dimension,
             directory,
         } => {
-            handle_request_image(username, peer, image_id, *views, directory.as_deref()).await?;
+            handle_request_image(username, peer, image_id, *views, *max_dimension, directory.as_deref()).await?;
         }
         Commands::ListPeerImages {
             username,
             peer,
+            query,
+            watch,
+            directory,
+        } => {
+            handle_list_peer_images(username, peer, query.as_deref(), *watch, directory.as_deref()).await?;
+        }
+        Commands::Redeliver {
+            username,
+            peer,
+            image_id,
             directory,
         } => {
-            handle_list_peer_images(username, peer, directory.as_deref()).await?;
+            handle_redeliver(username, peer, image_id, directory.as_deref()).await?;
+        }
+        Commands::ExportGrant { input, owner, recipient, views, passphrase, output } => {
+            handle_export_grant(input, owner, recipient, *views, passphrase, output.as_deref())?;
+        }
+        Commands::ImportGrant { package, username, passphrase, received_dir } => {
+            handle_import_grant(package, username, passphrase, received_dir.as_deref()).await?;
+        }
+        Commands::RedeemPairing { pairing, username, received_dir } => {
+            handle_redeem_pairing(&pairing.join(" "), username, received_dir.as_deref()).await?;
+        }
+        Commands::ReceivedNames { policy, received_dir } => {
+            let policy = policy.as_deref().map(ReceivedNamePolicy::parse).transpose()?;
+            handle_received_names(policy, received_dir.as_deref())?;
         }
         Commands::CheckRequests { username, directory } => {
             handle_check_requests(username, directory.as_deref()).await?;
@@ -249,19 +1104,109 @@ async fn main() -> Result<()> {
                 bail!("Must specify either --accept or --reject");
             }
 
-            handle_respond_request(owner, request_id, *accept, directory.as_deref()).await?;
+            handle_respond_request(owner, request_id, *accept, None, directory.as_deref()).await?;
         }
         Commands::CheckNotifications { username, directory } => {
             handle_check_notifications(username, directory.as_deref()).await?;
         }
+        Commands::CancelRequest {
+            username,
+            request_id,
+            directory,
+        } => {
+            handle_cancel_request(username, request_id, directory.as_deref()).await?;
+        }
+        Commands::Reminders { username, directory } => {
+            handle_reminders(username, directory.as_deref()).await?;
+        }
+        Commands::SnoozeReminder { username, request_id, hours, dismiss, directory } => {
+            let snooze = (!*dismiss).then(|| Duration::from_secs(hours * 3600));
+            handle_snooze_reminder(username, request_id, snooze, directory.as_deref()).await?;
+        }
         Commands::RemoteUpdatePermissions {
             owner,
             target_user,
             image_id,
             new_quota,
+            preview,
+            queue_ttl_days,
+            directory,
+        } => {
+            if *preview {
+                handle_preview_permissions(owner, target_user, image_id, *new_quota)?;
+            } else {
+                let queue_ttl = queue_ttl_days.map(|days| Duration::from_secs(days * 86_400));
+                handle_remote_update_permissions(owner, target_user, image_id, *new_quota, queue_ttl, directory.as_deref()).await?;
+            }
+        }
+        Commands::RemoteWipe { owner, target_user, image_id, directory } => {
+            handle_remote_wipe(owner, target_user, image_id, directory.as_deref()).await?;
+        }
+        Commands::OwnerConsole { username, directory } => {
+            handle_owner_console(username, directory.as_deref()).await?;
+        }
+        Commands::Doctor { port, directory, json } => {
+            handle_doctor(*port, directory.as_deref(), *json).await?;
+        }
+        Commands::RefreshDirectories { file } => {
+            handle_refresh_directories(file.clone()).await?;
+        }
+        Commands::ExportAuditLog { output, log } => {
+            handle_export_audit_log(log, output)?;
+        }
+        Commands::ProposeTrade {
+            username,
+            peer,
+            offer,
+            offer_views,
+            want,
+            want_views,
+            directory,
+        } => {
+            handle_propose_trade(username, peer, (offer, *offer_views), (want, *want_views), directory.as_deref()).await?;
+        }
+        Commands::CheckTrades { username, directory } => {
+            handle_check_trades(username, directory.as_deref()).await?;
+        }
+        Commands::RespondTrade {
+            username,
+            trade_id,
+            accept,
+            reject,
             directory,
         } => {
-            handle_remote_update_permissions(owner, target_user, image_id, *new_quota, directory.as_deref()).await?;
+            if *accept == *reject {
+                bail!("Must specify exactly one of --accept or --reject");
+            }
+            handle_respond_trade(username, trade_id, *accept, directory.as_deref()).await?;
+        }
+        Commands::CancelTrade { username, trade_id, directory } => {
+            handle_cancel_trade(username, trade_id, directory.as_deref()).await?;
+        }
+        Commands::RenameUser { from, to, directory } => {
+            handle_rename_user(from, to, directory.as_deref()).await?;
+        }
+        Commands::ArchivedUsers { purge, purge_all, directory } => {
+            handle_archived_users(purge, *purge_all, directory.as_deref()).await?;
+        }
+        Commands::ServerInfo { directory, json } => {
+            handle_server_info(directory.as_deref(), *json).await?;
+        }
+        Commands::ImportLibrary { library, owner, albums, server, output_dir, dry_run } => {
+            handle_import_library(library, owner, *albums, server, output_dir, *dry_run).await?;
+        }
+        Commands::Demo { directory, server, image, owner, viewer, port, advertise_host, work_dir } => {
+            let config = DemoConfig {
+                directory: directory.clone(),
+                servers: server.clone(),
+                image: image.clone(),
+                owner: owner.clone(),
+                viewer: viewer.clone(),
+                port: *port,
+                advertise_host: advertise_host.clone(),
+                work_dir: work_dir.clone(),
+            };
+            handle_demo(config).await?;
         }
     }
 
@@ -277,16 +1222,16 @@ async fn main() -> Result<()> {
 async fn multicast_directory_message(
     message: DirectoryMessage,
 ) -> Result<DirectoryMessage> {
-    println!("📡 Multicasting to {} directory servers...", DIRECTORY_SERVERS.len());
+    println!("📡 Multicasting to {} directory servers...", directory_servers().len());
     
     let responses: Arc<Mutex<Vec<Result<DirectoryMessage>>>> = 
         Arc::new(Mutex::new(Vec::new()));
     let mut handles = vec![];
     
-    for &server_addr in DIRECTORY_SERVERS {
+    for server_addr in directory_servers() {
         let msg = message.clone();
         let responses_clone = Arc::clone(&responses);
-        let addr = server_addr.to_string();
+        let addr = server_addr.clone();
         
         let handle = thread::spawn(move || {
             let rt = tokio::runtime::Runtime::new().unwrap();
@@ -315,10 +1260,8 @@ async fn multicast_directory_message(
     
     // Return first successful response
     let responses_lock = responses.lock().unwrap();
-    for response in responses_lock.iter() {
-        if let Ok(msg) = response {
-            return Ok(msg.clone());
-        }
+    if let Some(msg) = responses_lock.iter().flatten().next() {
+        return Ok(msg.clone());
     }
     
     bail!("❌ All directory servers failed to respond")
@@ -338,6 +1281,35 @@ async fn send_directory_or_multicast(
     }
 }
 
+/// Fetch (and clear) our pending permission updates from the directory given, else the first that answers
+///
+/// Not multicast: the images are streamed, and only one server needs to send them.
+async fn fetch_pending_updates(specific_addr: Option<&str>, username: &str) -> Result<Vec<PendingPermissionUpdate>> {
+    let servers = match specific_addr {
+        Some(addr) => vec![addr.to_string()],
+        None => directory_servers().to_vec(),
+    };
+    let mut last_error = None;
+    for server in &servers {
+        match fetch_pending_permission_updates(server, username).await {
+            Ok(updates) => return Ok(updates),
+            Err(e) => {
+                println!("  [{}] ✗ Failed: {}", server, e);
+                last_error = Some(e);
+            }
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No directory servers configured")))
+}
+
+/// Directories our P2P server asks to verify grant tokens: the one given, else all known
+fn grant_directories(specific_addr: Option<&str>) -> Vec<String> {
+    match specific_addr {
+        Some(addr) => vec![addr.to_string()],
+        None => directory_servers().to_vec(),
+    }
+}
+
 // =============================================================================
 // PHASE 1 COMMANDS (ENCRYPTION AND VIEWING)
 // =============================================================================
@@ -391,7 +1363,15 @@ fn load_servers() -> Result<Vec<String>> {
     Ok(servers)
 }
 
-fn handle_encrypt(input_path: &PathBuf, owner: &String) -> Result<()> {
+fn handle_encrypt(
+    input_path: &PathBuf,
+    owner: &String,
+    annotations: ImageAnnotations,
+    expires_at: Option<SystemTime>,
+    holders: Vec<String>,
+    policy: ViewPolicy,
+    optimize: bool,
+) -> Result<()> {
     println!("=== Encryptor Mode (Multicast with Fault Tolerance) ===");
 
     let servers = load_servers()?;
@@ -407,11 +1387,15 @@ fn handle_encrypt(input_path: &PathBuf, owner: &String) -> Result<()> {
     // Other users can be granted access via P2P requests
     let quotas = HashMap::new();
 
-    let permissions = ImagePermissions {
-        owner: owner.clone(),
-        quotas,
-    };
-    let meta_bytes = bincode::serialize(&permissions)?;
+    let mut permissions = ImagePermissions::new(owner.clone(), quotas);
+    permissions.holders = holders;
+    let meta_bytes = bincode::serialize(&EncryptionMetadata {
+        permissions,
+        annotations,
+        image_id: Some(new_image_id(&img_buf)),
+        expires_at,
+        flags: policy.apply_to_flags(0),
+    })?;
 
     println!("\n=== MULTICASTING to all {} servers ===", servers.len());
     
@@ -458,14 +1442,28 @@ fn handle_encrypt(input_path: &PathBuf, owner: &String) -> Result<()> {
             }
         }
 
-        if let Some(encrypted_image) = success_response {
+        if let Some(mut encrypted_image) = success_response {
             println!("\n=== ✓ ENCRYPTION SUCCESSFUL ===");
             println!("Received encrypted image ({} bytes = {:.2} MB)", 
                      encrypted_image.len(),
                      encrypted_image.len() as f64 / 1_048_576.0);
+
+            if optimize {
+                println!("Optimizing carrier...");
+                let optimized = lsb::optimize_carrier(&encrypted_image)?;
+                if optimized.saved_bytes() > 0 {
+                    println!("Optimized carrier: {}", optimized.summary());
+                } else {
+                    println!("Carrier is already as small as it gets; keeping it as-is");
+                }
+                encrypted_image = optimized.bytes;
+            }
             
             fs::write(ENCRYPTED_OUTPUT_IMAGE, &encrypted_image)?;
             println!("Saved encrypted image to '{}'", ENCRYPTED_OUTPUT_IMAGE);
+            if let Some(image_id) = embedded_image_id(&encrypted_image) {
+                println!("Image ID: {}", image_id);
+            }
             
             println!("\n💡 NOTE: If you're running a P2P server (online mode), you need to");
             println!("   restart it for this new image to be shareable with peers.");
@@ -537,6 +1535,38 @@ fn multicast_to_servers(
     responses_lock.clone()
 }
 
+/// Encrypt `image` for `owner` through whichever encryption server answers first, without the progress output
+async fn encrypt_with_any_server(
+    servers: &[String],
+    owner: &str,
+    image: &[u8],
+    annotations: ImageAnnotations,
+    attempts: u32,
+) -> Result<Vec<u8>> {
+    let meta_bytes = bincode::serialize(&EncryptionMetadata {
+        permissions: ImagePermissions::new(owner.to_string(), HashMap::new()),
+        annotations,
+        image_id: Some(new_image_id(image)),
+        expires_at: None,
+        flags: 0,
+    })?;
+
+    for attempt in 1..=attempts {
+        let (servers, meta_bytes, image) = (servers.to_vec(), meta_bytes.clone(), image.to_vec());
+        let responses = tokio::task::spawn_blocking(move || multicast_to_servers(&servers, &meta_bytes, &image)).await?;
+        for (_, response) in responses {
+            if let ServerResponse::Success(encrypted) = response {
+                return Ok(encrypted);
+            }
+        }
+        if attempt < attempts {
+            println!("No server encrypted the image, retrying in 2 seconds...");
+            tokio::time::sleep(Duration::from_secs(2)).await;
+        }
+    }
+    bail!("No encryption server accepted the image after {} attempts", attempts)
+}
+
 fn send_multicast_request(addr: &str, meta_bytes: &[u8], img_buf: &[u8]) -> Result<Vec<u8>> {
     let mut stream = TcpStream::connect_timeout(
         &addr.parse()?, 
@@ -574,82 +1604,106 @@ fn send_multicast_request(addr: &str, meta_bytes: &[u8], img_buf: &[u8]) -> Resu
     Ok(response_buf)
 }
 
-fn handle_view(input_path: &PathBuf, current_user: &String) -> Result<()> {
-    println!("\n=== Viewing Protected Image ===");
-    println!("Viewing user: {}", current_user);
-    println!("Viewing image: {}", input_path.display());
+/// Tell the owner a view was denied, queueing the report in the directory if they're offline
+async fn report_access_denial(denial: AccessDenial) {
+    let owner_query = DirectoryMessage::QueryUser {
+        username: denial.owner.clone(),
+        requesting_user: None,
+    };
 
-    let img_data = fs::read(input_path)?;
-    let carrier_img = image::load_from_memory(&img_data)?;
-
-    let payload = lsb::decode(&carrier_img)?
-        .ok_or_else(|| anyhow::anyhow!("No hidden metadata found!"))?;
-
-    let combined_data: CombinedPayload = bincode::deserialize(&payload)?;
-
-    let mut permissions = combined_data.permissions;
-    let client_image_bytes = combined_data.unified_image;
+    if let Ok(DirectoryMessage::QueryUserResponse { user: Some(owner), .. }) = multicast_directory_message(owner_query).await {
+        use cloud_p2p_project::directory_service::UserStatus;
+        if owner.status == UserStatus::Online && owner.supports(CAP_ACCESS_REPORTS) {
+            match send_access_denial(&owner.p2p_address, denial.clone()).await {
+                Ok(()) => {
+                    println!("📨 Reported denied view to {}", denial.owner);
+                    return;
+                }
+                Err(e) => eprintln!("⚠ Could not reach {} directly: {}", denial.owner, e),
+            }
+        }
+    }
 
-    println!("Decoded metadata before view: {:#?}", permissions);
+    let store_msg = DirectoryMessage::StoreAccessDenial { denial: denial.clone() };
+    match multicast_directory_message(store_msg).await {
+        Ok(DirectoryMessage::StoreAccessDenialResponse { success: true, .. }) => {
+            println!("📨 Denied view queued for {} in the directory", denial.owner);
+        }
+        _ => eprintln!("⚠ Could not report denied view to {}", denial.owner),
+    }
+}
 
-    // Check if current user is the owner
-    let is_owner = current_user == &permissions.owner;
+/// Print the owner's caption, alt text, and license, if any were set
+fn print_annotations(annotations: &ImageAnnotations) {
+    if let Some(caption) = &annotations.caption {
+        println!("📝 Caption: {}", caption);
+    }
+    if let Some(alt_text) = &annotations.alt_text {
+        println!("🔤 Alt text: {}", alt_text);
+    }
+    if let Some(license) = &annotations.license {
+        println!("⚖ License: {}", license);
+    }
+}
 
-    let has_access = if is_owner {
-        // Owner always has unlimited access
-        println!("✓ You are the owner - unlimited access granted!");
-        true
-    } else {
-        // Non-owner users need quota-based access
-        match permissions.quotas.get_mut(current_user) {
-            Some(views_left) if *views_left > 0 => {
-                println!("✓ Access granted. You have {} views left.", *views_left);
-                *views_left -= 1;
-                true
-            }
-            Some(_) => {
-                println!("✗ Access denied. No remaining views!");
-                false
-            }
-            None => {
-                println!("✗ Access denied. You are not authorized to view this image!");
-                false
-            }
-        }
-    };
+async fn handle_view(input_path: &PathBuf, current_user: &String) -> Result<()> {
+    println!("\n=== Viewing Protected Image ===");
+    println!("Viewing user: {}", current_user);
+    println!("Viewing image: {}", input_path.display());
 
-    if has_access {
-        fs::write(VIEWABLE_OUTPUT_IMAGE, &client_image_bytes)?;
-        println!("Saved viewable image to '{}'", VIEWABLE_OUTPUT_IMAGE);
+    let img_data = fs::read(input_path)?;
+    let carrier_img = image::load_from_memory(&img_data)?;
 
-        if !is_owner {
-            println!(
-                "Updated views left: {}",
-                permissions.quotas.get(current_user).unwrap_or(&0)
-            );
-        }
+    let payload = lsb::decode(&carrier_img)?
+        .ok_or_else(|| anyhow::anyhow!("No hidden metadata found!"))?;
 
-        // Only update metadata if non-owner (to save the decremented quota)
-        // Owner doesn't need metadata updates since they have unlimited access
-        if !is_owner {
-            let updated_combined_payload = CombinedPayload {
-                permissions,
-                unified_image: client_image_bytes,
-            };
+    let combined_data = CombinedPayload::from_bytes(&payload)?;
 
-            let updated_payload = bincode::serialize(&updated_combined_payload)?;
-            let updated_carrier = lsb::encode(&carrier_img, &updated_payload)?;
+    println!("Decoded metadata before view: {:#?}", combined_data.permissions);
+    print_annotations(&combined_data.annotations);
+    if !combined_data.provenance.is_empty() {
+        println!("🔗 Shared via: {}", provenance_chain(&combined_data.permissions.owner, &combined_data.provenance));
+    }
 
-            updated_carrier.save(input_path)?;
+    // Viewing here writes the image to a file, which an owner who blocks saving hasn't allowed
+    let policy = ViewPolicy::from_flags(combined_data.flags);
+    if current_user != &combined_data.permissions.owner && !policy.allow_save {
+        bail!("The owner doesn't allow saving this image, so it can only be viewed in the app");
+    }
 
-            println!("Re-embedded updated metadata back into '{}'", input_path.display());
-        } else {
+    // Re-read under the file's lock, so a view running alongside can't spend the same view
+    match consume_view(input_path, current_user).await? {
+        ViewOutcome::Granted { payload, is_owner: true, .. } => {
+            println!("✓ You are the owner - unlimited access granted!");
+            fs::write(VIEWABLE_OUTPUT_IMAGE, &payload.unified_image)?;
+            println!("Saved viewable image to '{}'", VIEWABLE_OUTPUT_IMAGE);
             println!("Owner access - no quota update needed");
         }
-    } else {
-        println!("Access denied - showing default image");
-        carrier_img.save(VIEWABLE_OUTPUT_IMAGE)?;
-        println!("Saved default image to '{}'", VIEWABLE_OUTPUT_IMAGE);
+        ViewOutcome::Granted { payload, views_left, .. } => {
+            let views_left = views_left.unwrap_or(0);
+            println!("✓ Access granted. You have {} views left.", views_left + 1);
+            if policy.watermark {
+                let stamp = watermark::viewer_stamp(current_user);
+                fs::write(VIEWABLE_OUTPUT_IMAGE, watermark::watermark_png(&payload.unified_image, &stamp)?)?;
+                println!("Saved watermarked image to '{}'", VIEWABLE_OUTPUT_IMAGE);
+            } else {
+                fs::write(VIEWABLE_OUTPUT_IMAGE, &payload.unified_image)?;
+                println!("Saved viewable image to '{}'", VIEWABLE_OUTPUT_IMAGE);
+            }
+            println!("Updated views left: {}", views_left);
+            println!("Re-embedded updated metadata back into '{}'", input_path.display());
+        }
+        ViewOutcome::Denied(denial) => {
+            match denial {
+                ViewDenial::HolderOnly => println!("✗ Access denied. You hold this image for safekeeping only."),
+                ViewDenial::Expired => println!("✗ Access denied. This image has expired!"),
+                ViewDenial::NoViewsLeft => println!("✗ Access denied. No remaining views!"),
+                ViewDenial::NotAuthorized => println!("✗ Access denied. You are not authorized to view this image!"),
+            }
+            println!("Access denied - showing default image");
+            carrier_img.save(VIEWABLE_OUTPUT_IMAGE)?;
+            println!("Saved default image to '{}'", VIEWABLE_OUTPUT_IMAGE);
+        }
     }
 
     Ok(())
@@ -659,18 +1713,42 @@ fn handle_view(input_path: &PathBuf, current_user: &String) -> Result<()> {
 // PHASE 2 COMMANDS (P2P AND DIRECTORY SERVICE)
 // =============================================================================
 
+#[allow(clippy::too_many_arguments)]
 async fn handle_start_peer(
     username: &str,
     port: u16,
     directory_addr: Option<&str>,
+    max_views: Option<u32>,
+    max_received_mb: Option<u64>,
+    availability: Option<AvailabilityWindow>,
+    daily_cap_mb: Option<u64>,
+    relay: Option<&str>,
 ) -> Result<()> {
     // Use current directory as images directory
     let images_dir = std::env::current_dir()?;
     
     println!("=== Starting P2P Peer ===");
     println!("Username: {}", username);
-    println!("P2P Port: {}", port);
+    if let Some(namespace) = client_namespace() {
+        println!("Namespace: {}", namespace);
+    }
+    match relay {
+        Some(relay) => println!("Anonymous: reachable only through relay {} (no port opened)", relay),
+        None => println!("P2P Port: {}", port),
+    }
     println!("Images Directory: {}", images_dir.display());
+    if let Some(max) = max_views {
+        println!("Max Views Per Grant: {}", max);
+    }
+    if let Some(max) = max_received_mb {
+        println!("Received Storage Quota: {} MB", max);
+    }
+    if let Some(cap) = daily_cap_mb {
+        println!("Daily Bandwidth Cap: {} MB", cap);
+    }
+    if let Some(window) = availability {
+        println!("Usually Online: {}", window);
+    }
     
     if let Some(addr) = directory_addr {
         println!("Directory Service: {} (specific)", addr);
@@ -680,47 +1758,52 @@ async fn handle_start_peer(
     
     // Scan images directory and build image store
     let image_store = Arc::new(RwLock::new(PeerImageStore::new()));
+    image_store.write().await.set_visibility_path(images_dir.join(VISIBILITY_FILE));
     let mut shared_images = Vec::new();
     
-    if images_dir.exists() && images_dir.is_dir() {
-        for entry in fs::read_dir(&images_dir)? {
-            let entry = entry?;
-            let path = entry.path();
-            
-            if path.is_file() {
-                if let Some(ext) = path.extension() {
-                    if ext == "png" || ext == "jpg" || ext == "jpeg" {
-                        let file_name = path.file_name().unwrap().to_str().unwrap();
-                        let image_id = file_name.to_string();
-                        
-                        let metadata = ImageMetadata {
-                            image_id: image_id.clone(),
-                            image_name: file_name.to_string(),
-                            owner: username.to_string(),
-                            description: Some(format!("Image from {}", username)),
-                            file_size_kb: fs::metadata(&path)?.len() / 1024,
-                        };
-                        
-                        let image_info = ImageInfo {
-                            image_id: image_id.clone(),
-                            image_name: file_name.to_string(),
-                            thumbnail_path: None,
-                        };
-                        
-                        image_store.write().await.add_image(
-                            image_id,
-                            path.clone(),
-                            metadata,
-                        );
-                        
-                        shared_images.push(image_info);
-                    }
-                }
-            }
-        }
+    for file in scan_images(&images_dir) {
+        let image_id = shared_image_id(&file.path);
+        let caption = fs::read(&file.path).ok()
+            .and_then(|data| image_annotations(&data))
+            .and_then(|annotations| annotations.caption);
+        
+        let metadata = ImageMetadata {
+            image_id: image_id.clone(),
+            image_name: file.file_name.clone(),
+            owner: username.to_string(),
+            description: caption.clone().or_else(|| Some(format!("Image from {}", username))),
+            file_size_kb: file.size_kb(),
+            max_grant_views: max_views,
+        };
+        
+        let mut store = image_store.write().await;
+        store.add_image(
+            image_id.clone(),
+            file.path,
+            metadata,
+        );
+        let visibility = store.get_visibility(&image_id);
+        let preview = store.escrowed_preview(&image_id);
+        
+        shared_images.push(ImageInfo {
+            image_id,
+            image_name: file.file_name.clone(),
+            thumbnail_path: None,
+            max_grant_views: max_views,
+            caption,
+            visibility: visibility.visibility,
+            link_token: visibility.link_token,
+            preview,
+        });
     }
     
     println!("Found {} images to share", shared_images.len());
+    image_store.write().await.set_denial_stats_path(images_dir.join(ACCESS_DENIAL_STATS_FILE));
+    image_store.write().await.set_audit_log_path(images_dir.join(AUDIT_LOG_FILE));
+    image_store.write().await.set_directory_servers(grant_directories(directory_addr));
+    set_bandwidth_stats_path(images_dir.join(BANDWIDTH_STATS_FILE));
+    set_daily_cap(daily_cap_mb.map(|mb| mb * 1024 * 1024));
+    image_store.write().await.set_received_quota_bytes(max_received_mb.map(|mb| mb * 1024 * 1024));
 
     // Get local IP address dynamically
     let local_ip = match get_local_ip() {
@@ -732,17 +1815,28 @@ async fn handle_start_peer(
             bail!("Failed to detect local IP address: {}. Please check your network connection.", e);
         }
     };
-    let p2p_address = format!("{}:{}", local_ip, port);
+    let p2p_address = match relay {
+        Some(relay) => {
+            // Deliveries and fetches made by this peer go through the relay too
+            set_outbound_relay(Some(relay.to_string()));
+            relay_p2p_address(relay, username)
+        }
+        None => format!("{}:{}", local_ip, port),
+    };
     let register_msg = DirectoryMessage::Register {
         username: username.to_string(),
         p2p_address: p2p_address.clone(),
         shared_images: shared_images.clone(),
+        capabilities: local_capabilities(),
+        availability,
+        key: Some(registration_key(username)?),
     };
     
-    match send_directory_or_multicast(directory_addr, register_msg).await {
-        Ok(DirectoryMessage::RegisterResponse { success, message }) => {
+    let heartbeat_interval = match send_directory_or_multicast(directory_addr, register_msg).await {
+        Ok(DirectoryMessage::RegisterResponse { success, message, heartbeat_interval_secs, .. }) => {
             if success {
                 println!("✓ Registered with directory service: {}", message);
+                negotiated_heartbeat_interval(heartbeat_interval_secs)
             } else {
                 bail!("Failed to register: {}", message);
             }
@@ -753,7 +1847,7 @@ async fn handle_start_peer(
         _ => {
             bail!("Unexpected response from directory service");
         }
-    }
+    };
 
     // Check for pending requests (someone tried to contact this user while offline)
     println!("\n📬 Checking for pending requests...");
@@ -774,6 +1868,7 @@ async fn handle_start_peer(
             } else {
                 println!("✓ No pending requests");
             }
+            report_request_throttles(directory_addr, username).await;
         }
         Err(e) => {
             eprintln!("⚠ Could not check pending requests: {}", e);
@@ -823,28 +1918,40 @@ async fn handle_start_peer(
     // Apply them locally so permissions are enforced immediately on login
     // -----------------------------------------------------------------
     println!("\n🔁 Checking for pending permission updates...");
-    let pending_updates_msg = DirectoryMessage::GetPendingPermissionUpdates {
-        username: username.to_string(),
-    };
-
-    match send_directory_or_multicast(directory_addr, pending_updates_msg).await {
-        Ok(DirectoryMessage::GetPendingPermissionUpdatesResponse { updates }) => {
+    match fetch_pending_updates(directory_addr, username).await {
+        Ok(updates) => {
             if updates.is_empty() {
                 println!("✓ No pending permission updates");
             } else {
                 println!("🔔 Processing {} pending permission update(s)...", updates.len());
 
                 for upd in updates {
+                    if upd.wipe {
+                        let received_dir = image_store.read().await.get_received_images_dir().cloned();
+                        apply_queued_wipe(directory_addr, username, &upd.from_owner, &upd.image_id, received_dir.as_deref()).await;
+                        continue;
+                    }
                     println!("  • Update from {} for image {} -> {} views",
                              upd.from_owner, upd.image_id, upd.new_quota);
 
                     // Check if we have an embedded image to save directly
                     if let Some(embedded_image) = upd.embedded_image {
-                        // Save the image directly as from_{owner}_{username}.png
-                        let save_path = format!("from_{}_{}.png", upd.from_owner, username);
-                        match std::fs::write(&save_path, &embedded_image) {
-                            Ok(()) => {
-                                println!("    ✅ Saved delivered image as '{}'", save_path);
+                        // Hand it back to the directory rather than half-write it to a full disk
+                        if let Err(shortfall) = reserve_disk_space(Path::new("."), embedded_image.len() as u64) {
+                            eprintln!("    ⚠ {} - queuing it again for later", shortfall);
+                            // Keep the owner's original deadline
+                            let ttl_left = upd.expires_at.and_then(|expires_at| expires_at.duration_since(SystemTime::now()).ok());
+                            store_pending_update_with_image(directory_addr, &upd.from_owner, username, &upd.image_id, upd.new_quota, embedded_image, ttl_left).await;
+                            continue;
+                        }
+
+                        // Save the image directly over our copy, or under the folder's name policy
+                        let received_dir = image_store.read().await.get_received_images_dir().cloned();
+                        let save_path = received_image_path(received_dir.as_deref(), &upd.from_owner, &upd.image_id);
+                        match save_received_image(&save_path, &upd.from_owner, &upd.image_id, &embedded_image) {
+                            Ok(record) => {
+                                println!("    ✅ Saved delivered image as '{}'", save_path.display());
+                                println!("    🔒 SHA-256: {} ({} bytes)", record.sha256, record.size_bytes);
                                 if upd.new_quota == 0 {
                                     println!("    ⚠ Note: Your access has been REVOKED (0 views)");
                                 } else {
@@ -869,11 +1976,12 @@ async fn handle_start_peer(
                                     Ok(img) => {
                                         match lsb::decode(&img) {
                                             Ok(Some(payload)) => {
-                                                match bincode::deserialize::<CombinedPayload>(&payload) {
+                                                match CombinedPayload::from_bytes(&payload) {
                                                     Ok(mut combined) => {
-                                                        combined.permissions.quotas.insert(username.to_string(), upd.new_quota);
+                                                        let owner = combined.permissions.owner.clone();
+                                                        combined.permissions.change_quota(&owner, username, upd.new_quota);
 
-                                                        match bincode::serialize(&combined) {
+                                                        match combined.to_bytes() {
                                                             Ok(new_payload) => match lsb::encode(&img, &new_payload) {
                                                                 Ok(updated_carrier) => {
                                                                     // Atomic save: write to temp file then rename
@@ -937,20 +2045,66 @@ async fn handle_start_peer(
         Err(e) => {
             eprintln!("⚠ Failed to fetch pending permission updates: {}", e);
         }
-        _ => {
-            eprintln!("⚠ Unexpected response when fetching pending permission updates");
+    }
+
+    // Fold in denial reports from recipients who tried to view while we were offline
+    let denials_msg = DirectoryMessage::GetAccessDenials {
+        username: username.to_string(),
+    };
+    match send_directory_or_multicast(directory_addr, denials_msg).await {
+        Ok(DirectoryMessage::GetAccessDenialsResponse { denials }) if !denials.is_empty() => {
+            println!("🛡  {} view attempt(s) were denied while you were offline", denials.len());
+            if let Err(e) = record_access_denials(&images_dir.join(ACCESS_DENIAL_STATS_FILE), &denials) {
+                eprintln!("⚠ Failed to record access denials: {}", e);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("⚠ Failed to fetch access denial reports: {}", e);
+        }
+    }
+
+    // Report queued updates that expired before their recipient came back for them
+    let expired_msg = DirectoryMessage::GetExpiredDeliveries {
+        username: username.to_string(),
+    };
+    match send_directory_or_multicast(directory_addr, expired_msg).await {
+        Ok(DirectoryMessage::GetExpiredDeliveriesResponse { expired }) => {
+            for notice in expired {
+                let what = if notice.wipe { "Wipe request" } else { "Delivery" };
+                println!(
+                    "⌛ {} of '{}' to {} expired undelivered - re-send it or share it another way",
+                    what, notice.image_id, notice.target_user
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(e) => {
+            eprintln!("⚠ Failed to fetch expired deliveries: {}", e);
         }
     }
 
+    // Keep retrying deliveries owed for requests we accepted until they go through
+    let redelivery_username = username.to_string();
+    let redelivery_addr = directory_addr.map(|s| s.to_string());
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(UNDELIVERED_GRANT_RETRY_INTERVAL).await;
+            retry_undelivered_grants(&redelivery_username, redelivery_addr.as_deref()).await;
+        }
+    });
+
     // Start heartbeat task
     let heartbeat_username = username.to_string();
     let heartbeat_addr_opt = directory_addr.map(|s| s.to_string());
     tokio::spawn(async move {
+        let mut last_due = 0;
         loop {
-            tokio::time::sleep(Duration::from_secs(10)).await;
+            tokio::time::sleep(heartbeat_interval).await;
             
             let heartbeat_msg = DirectoryMessage::Heartbeat {
                 username: heartbeat_username.clone(),
+                capabilities: Some(local_capabilities()),
             };
             
             let result = if let Some(ref addr) = heartbeat_addr_opt {
@@ -959,8 +2113,19 @@ async fn handle_start_peer(
                 multicast_directory_message(heartbeat_msg).await
             };
             
-            if let Err(e) = result {
-                eprintln!("Heartbeat failed: {}", e);
+            match result {
+                // Nudge once each time more requests come due, not on every heartbeat
+                Ok(DirectoryMessage::HeartbeatResponse { due_reminders, .. }) => {
+                    if due_reminders > last_due {
+                        println!(
+                            "\n⏰ {} request(s) have been waiting on you for a while. See: cargo run --bin client -- reminders --username {}",
+                            due_reminders, heartbeat_username
+                        );
+                    }
+                    last_due = due_reminders;
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Heartbeat failed: {}", e),
             }
         }
     });
@@ -974,53 +2139,48 @@ async fn handle_start_peer(
             // Scan every 5 seconds for new images
             tokio::time::sleep(Duration::from_secs(5)).await;
             
-            if let Ok(entries) = fs::read_dir(&rescan_dir) {
-                for entry in entries.flatten() {
-                    let path = entry.path();
-                    if path.is_file() {
-                        if let Some(ext) = path.extension() {
-                            if ext == "png" || ext == "jpg" || ext == "jpeg" {
-                                let file_name = path.file_name().unwrap().to_str().unwrap();
-                                let image_id = file_name.to_string();
-                                
-                                // Check if already in store
-                                let already_exists = {
-                                    let store = rescan_store.read().await;
-                                    store.get_image_path(&image_id).is_some()
-                                };
-                                
-                                if !already_exists {
-                                    // New image found - add to store!
-                                    let file_size_kb = fs::metadata(&path)
-                                        .map(|m| m.len() / 1024)
-                                        .unwrap_or(0);
-                                    
-                                    let metadata = ImageMetadata {
-                                        image_id: image_id.clone(),
-                                        image_name: file_name.to_string(),
-                                        owner: rescan_username.clone(),
-                                        description: Some(format!("Image from {}", rescan_username)),
-                                        file_size_kb,
-                                    };
-                                    
-                                    rescan_store.write().await.add_image(
-                                        image_id.clone(),
-                                        path.clone(),
-                                        metadata,
-                                    );
-                                    
-                                    println!("\n📷 [AUTO-DETECT] New image found: '{}'", image_id);
-                                    println!("   ✓ Added to shareable images automatically!");
-                                }
-                            }
-                        }
-                    }
+            for file in scan_images(&rescan_dir) {
+                // Check if already in store
+                let already_exists = {
+                    let store = rescan_store.read().await;
+                    store.contains_file(&file.file_name)
+                };
+                
+                if !already_exists {
+                    // New image found - add to store!
+                    let image_id = shared_image_id(&file.path);
+                    
+                    let metadata = ImageMetadata {
+                        image_id: image_id.clone(),
+                        image_name: file.file_name.clone(),
+                        owner: rescan_username.clone(),
+                        description: Some(format!("Image from {}", rescan_username)),
+                        file_size_kb: file.size_kb(),
+                        max_grant_views: max_views,
+                    };
+                    
+                    rescan_store.write().await.add_image(
+                        image_id.clone(),
+                        file.path,
+                        metadata,
+                    );
+                    
+                    println!("\n📷 [AUTO-DETECT] New image found: '{}' (ID: {})", file.file_name, image_id);
+                    println!("   ✓ Added to shareable images automatically!");
                 }
             }
         }
     });
     
     // Start P2P server
+    if let Some(relay) = relay {
+        let secret = relay_secret(&images_dir.join(RELAY_SECRETS_FILE), username)?;
+        println!("✓ Serving P2P requests as {} through relay {}...", username, relay);
+        println!("📷 Auto-scanning for new images in: {}", images_dir.display());
+        println!("Press Ctrl+C to stop");
+        serve_p2p_via_relay(relay.to_string(), username.to_string(), secret, image_store).await?;
+        return Ok(());
+    }
     println!("✓ Starting P2P server on port {}...", port);
     println!("📷 Auto-scanning for new images in: {}", images_dir.display());
     println!("Press Ctrl+C to stop");
@@ -1048,17 +2208,55 @@ async fn handle_discover_peers(username: &str, directory_addr: Option<&str>) ->
         Ok(DirectoryMessage::QueryPeersResponse { peers }) => {
             println!("\n✓ Found {} online peers:", peers.len());
             
+            // IDs shared by more than one owner are ambiguous unless qualified with the owner
+            let mut id_owners: HashMap<&str, usize> = HashMap::new();
+            for img in peers.iter().flat_map(|peer| &peer.shared_images) {
+                *id_owners.entry(img.image_id.as_str()).or_default() += 1;
+            }
+            
             if peers.is_empty() {
                 println!("  No other peers online");
             } else {
-                for peer in peers {
+                for peer in &peers {
                     println!("\n  Username: {}", peer.username);
-                    println!("  Address:  {}", peer.p2p_address);
+                    if let Some(name) = &peer.profile.display_name {
+                        println!("  Name:     {}", name);
+                    }
+                    if let Some(bio) = &peer.profile.bio {
+                        println!("  Bio:      {}", bio);
+                    }
+                    if peer.is_anonymous() {
+                        println!("  Address:  (anonymous, through a relay)");
+                    } else {
+                        println!("  Address:  {}", peer.p2p_address);
+                    }
                     println!("  Status:   {:?}", peer.status);
+                    if let Some(rep) = reputation(&peer.username) {
+                        let warning = if rep.score < LOW_REPUTATION_SCORE { " ⚠ unreliable" } else { "" };
+                        println!("  Reputation: {}/100{}", rep.score, warning);
+                    }
+                    if peer.capabilities.is_empty() {
+                        println!("  Features: (legacy peer)");
+                    } else {
+                        println!("  Features: {}", peer.capabilities.join(", "));
+                    }
+                    if let Some(window) = peer.availability {
+                        println!("  Usually online: {}", window);
+                    }
                     println!("  Shared Images: {}", peer.shared_images.len());
                     
                     for img in &peer.shared_images {
-                        println!("    - {} (ID: {})", img.image_name, img.image_id);
+                        let anonymous = if peer.allows_anonymous(&img.image_id) { ", anonymous requests allowed" } else { "" };
+                        match img.max_grant_views {
+                            Some(max) => println!("    - {} (ID: {}, max {} views{})", img.image_name, img.image_id, max, anonymous),
+                            None => println!("    - {} (ID: {}{})", img.image_name, img.image_id, anonymous),
+                        }
+                        if let Some(caption) = &img.caption {
+                            println!("      \"{}\"", caption);
+                        }
+                        if id_owners.get(img.image_id.as_str()).is_some_and(|owners| *owners > 1) {
+                            println!("      ⚠ ID also shared by another peer - use {}", qualified_image_id(&peer.username, &img.image_id));
+                        }
                     }
                 }
             }
@@ -1074,80 +2272,68 @@ async fn handle_discover_peers(username: &str, directory_addr: Option<&str>) ->
     }
 }
 
-async fn handle_request_image(
-    username: &str,
-    peer_username: &str,
-    image_id: &str,
-    views: u32,
-    directory_addr: Option<&str>,
-) -> Result<()> {
-    println!("=== Requesting Image from Peer ===");
-    println!("Your username: {}", username);
-    println!("Peer: {}", peer_username);
-    println!("Image ID: {}", image_id);
-    println!("Requested views: {}", views);
+async fn handle_search_images(query: &str, limit: usize, username: Option<&str>, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Searching Shared Images ===");
+    println!("Query: {}", query);
 
-    // First, verify that the requesting user (yourself) is online
-    println!("\nVerifying you are connected to directory service...");
-    let self_query_msg = DirectoryMessage::QueryUser {
-        username: username.to_string(),
+    let msg = DirectoryMessage::SearchCatalog {
+        query: query.to_string(),
+        limit: Some(limit),
+        requesting_user: username.map(str::to_string),
     };
-
-    match send_directory_or_multicast(directory_addr, self_query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry) }) => {
-            // Check if user is actually ONLINE (not just registered)
-            use cloud_p2p_project::directory_service::UserStatus;
-            if user_entry.status == UserStatus::Online {
-                println!("✓ You are online and connected to directory service");
-            } else {
-                bail!(
-                    "❌ You must be online to request images!\n\
-                    \n\
-                    Your account exists but your P2P peer is offline.\n\
-                    You need to start your P2P peer:\n\
-                      cargo run --bin client -- start-peer --username {} --port <PORT>\n\
-                    \n\
-                    This will mark you as online and allow you to request images.",
-                    username
-                );
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::SearchCatalogResponse { results }) => {
+            if results.is_empty() {
+                println!("\n  No shared image matches");
+                return Ok(());
             }
-        }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-            bail!(
-                "❌ You must be online to request images!\n\
-                \n\
-                You need to start your P2P peer first:\n\
-                  cargo run --bin client -- start-peer --username {} --port <PORT>\n\
-                \n\
-                This will register you with the directory service and allow you to request images.",
-                username
-            );
+            println!("\n✓ {} matching images, best first:", results.len());
+            for result in &results {
+                println!("\n  {} ({:.0}% match)", result.image.image_name, result.score * 100.0);
+                println!("  Owner:  {} ({:?})", result.owner, result.status);
+                println!("  ID:     {}", qualified_image_id(&result.owner, &result.image.image_id));
+                if let Some(caption) = &result.image.caption {
+                    println!("  \"{}\"", caption);
+                }
+                if let Some(grant) = &result.your_grant {
+                    println!("  You already have {} view(s), granted {}", grant.views, time_ago(grant.granted_at));
+                }
+            }
+            Ok(())
         }
         Err(e) => {
-            bail!("Error connecting to directory service: {}\n\nMake sure the directory service is running.", e);
+            bail!("Error querying directory service: {}", e);
         }
         _ => {
             bail!("Unexpected response from directory service");
         }
     }
+}
 
-    // Query directory service for peer address
-    println!("\nLooking up peer '{}'...", peer_username);
-    let query_msg = DirectoryMessage::QueryUser {
-        username: peer_username.to_string(),
-    };
+async fn handle_find_image(image: &str, username: Option<&str>, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Finding Image ===");
+    println!("Looking for: {}", image);
     
-    match send_directory_or_multicast(directory_addr, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user) }) => {
-            use cloud_p2p_project::directory_service::UserStatus;
-            if user.status == UserStatus::Online {
-                println!("✓ Owner '{}' is online", peer_username);
-            } else {
-                println!("ℹ Owner '{}' is currently offline", peer_username);
+    let msg = DirectoryMessage::QueryImageHolders {
+        image_id_or_hash: image.to_string(),
+        requesting_user: username.map(str::to_string),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::QueryImageHoldersResponse { holders }) => {
+            if holders.is_empty() {
+                println!("\n  No peer shares a matching image");
+                return Ok(());
             }
-        }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-            println!("ℹ Owner '{}' is not registered yet", peer_username);
+            println!("\n✓ Found {} matching images:", holders.len());
+            for holder in &holders {
+                println!("\n  Owner:  {} ({:?})", holder.owner, holder.status);
+                println!("  Image:  {}", holder.image.image_name);
+                println!("  ID:     {}", qualified_image_id(&holder.owner, &holder.image.image_id));
+                if let Some(caption) = &holder.image.caption {
+                    println!("  \"{}\"", caption);
+                }
+            }
+            Ok(())
         }
         Err(e) => {
             bail!("Error querying directory service: {}", e);
@@ -1155,39 +2341,61 @@ async fn handle_request_image(
         _ => {
             bail!("Unexpected response from directory service");
         }
-    };
+    }
+}
 
-    // Always leave a request for the owner to approve (whether online or offline)
-    println!("\n📝 Submitting request to owner for approval...");
-    let leave_request_msg = DirectoryMessage::LeaveRequest {
-        from_user: username.to_string(),
-        to_user: peer_username.to_string(),
-        image_id: image_id.to_string(),
-        requested_views: views,
+async fn handle_update_profile(
+    username: &str,
+    display_name: Option<String>,
+    bio: Option<String>,
+    avatar: Option<&Path>,
+    clear_avatar: bool,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== Updating Profile ===");
+    println!("Your username: {}", username);
+
+    // Start from the published profile so fields not given are kept
+    let query_msg = DirectoryMessage::QueryUser {
+        username: username.to_string(),
+        requesting_user: None,
+    };
+    let mut profile = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => user.profile,
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!("User {} is not registered - start a peer first", username);
+        }
+        Err(e) => bail!("Error contacting directory service: {}", e),
+        _ => bail!("Unexpected response from directory service"),
     };
 
-    match send_directory_or_multicast(directory_addr, leave_request_msg).await {
-        Ok(DirectoryMessage::LeaveRequestResponse { success: true, request_id, message }) => {
-            println!("✓ Request submitted successfully!");
-            println!("\n📋 Request details:");
-            println!("   Request ID: {}", request_id);
-            println!("   To: {}", peer_username);
-            println!("   Image: {}", image_id);
-            println!("   Requested views: {}", views);
-            println!("\n⏳ Waiting for owner approval...");
-            println!("   The owner must accept your request before you can view the image.");
-            println!("   If the owner is online, they will see your request immediately.");
-            println!("   If offline, they will see it when they come online.");
-            println!("\n💡 Check for owner's response with:");
-            println!("   cargo run --bin client -- check-notifications --username {}", username);
-            println!("\n   Once accepted, the image will be automatically delivered to you!");
+    if display_name.is_some() {
+        profile.display_name = display_name;
+    }
+    if bio.is_some() {
+        profile.bio = bio;
+    }
+    if let Some(path) = avatar {
+        let bytes = fs::read(path)?;
+        profile.avatar_thumbnail = Some(avatar_thumbnail(&bytes)?);
+    } else if clear_avatar {
+        profile.avatar_thumbnail = None;
+    }
+
+    let msg = DirectoryMessage::UpdateProfile {
+        username: username.to_string(),
+        profile,
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
             Ok(())
         }
-        Ok(DirectoryMessage::LeaveRequestResponse { success: false, message, .. }) => {
-            bail!("Failed to leave request: {}", message);
+        Ok(DirectoryMessage::UpdateResponse { success: false, message }) => {
+            bail!("❌ {}", message);
         }
         Err(e) => {
-            bail!("Error leaving request: {}", e);
+            bail!("Error contacting directory service: {}", e);
         }
         _ => {
             bail!("Unexpected response from directory service");
@@ -1195,44 +2403,220 @@ async fn handle_request_image(
     }
 }
 
-async fn handle_list_peer_images(
-    username: &str,
-    peer_username: &str,
+async fn handle_set_delegate(
+    owner: &str,
+    delegate: &str,
+    image_ids: Vec<String>,
+    enabled: bool,
     directory_addr: Option<&str>,
 ) -> Result<()> {
-    println!("=== Listing Peer's Images ===");
-    println!("Your username: {}", username);
-    println!("Peer: {}", peer_username);
+    println!("=== {} Delegate ===", if enabled { "Setting" } else { "Removing" });
+    println!("Owner: {}", owner);
+    println!("Delegate: {}", delegate);
+    if enabled {
+        if image_ids.is_empty() {
+            println!("Images: all");
+        } else {
+            println!("Images: {}", image_ids.join(", "));
+        }
+    }
 
-    // First, verify that the requesting user (yourself) is online
-    println!("\nVerifying you are connected to directory service...");
-    let self_query_msg = DirectoryMessage::QueryUser {
-        username: username.to_string(),
+    let msg = DirectoryMessage::SetDelegate {
+        owner: owner.to_string(),
+        delegate: delegate.to_string(),
+        image_ids,
+        enabled,
+        key: Some(registration_key(owner)?),
     };
-
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
+            if enabled {
+                println!("💡 Your peer must be online for {} to accept requests - it holds the images", delegate);
+            }
+            Ok(())
+        }
+        Ok(DirectoryMessage::UpdateResponse { success: false, message }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+async fn handle_anonymous_access(
+    owner: &str,
+    image_ids: Vec<String>,
+    allowed: bool,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== {} Anonymous Requests ===", if allowed { "Allowing" } else { "Refusing" });
+    println!("Owner: {}", owner);
+    println!("Images: {}", image_ids.join(", "));
+
+    let msg = DirectoryMessage::SetAnonymousAccess {
+        owner: owner.to_string(),
+        image_ids,
+        allowed,
+        key: Some(registration_key(owner)?),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
+            Ok(())
+        }
+        Ok(DirectoryMessage::UpdateResponse { success: false, message }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+/// Set an image's visibility in the current folder's settings, printing its share link if it's link-only
+/// Image store over the images start-peer shares from the current folder, with their visibility settings
+fn shared_folder_store(username: &str) -> Result<(PathBuf, PeerImageStore)> {
+    let images_dir = std::env::current_dir()?;
+    let mut store = PeerImageStore::new();
+    store.set_owner(username.to_string());
+    for file in scan_images(&images_dir) {
+        let metadata = ImageMetadata {
+            image_id: shared_image_id(&file.path),
+            image_name: file.file_name.clone(),
+            owner: username.to_string(),
+            description: None,
+            file_size_kb: file.size_kb(),
+            max_grant_views: None,
+        };
+        store.add_image(metadata.image_id.clone(), file.path, metadata);
+    }
+    store.set_visibility_path(images_dir.join(VISIBILITY_FILE));
+    Ok((images_dir, store))
+}
+
+fn handle_set_visibility(username: &str, image_id: &str, visibility: ImageVisibility) -> Result<()> {
+    let (images_dir, mut store) = shared_folder_store(username)?;
+    let setting = store.set_visibility(image_id, visibility)
+        .with_context(|| format!("{} is not shared from {}", image_id, images_dir.display()))?;
+
+    println!("✓ {} is now {}", image_id, setting.visibility);
+    if let Some(token) = &setting.link_token {
+        let image_id = store.resolve_image_id(image_id).unwrap_or(image_id);
+        println!("
+🔗 Share link: {}", share_link(username, image_id, token));
+        println!("   Anyone with it can request the image with: request-image --image-id <link>");
+    }
+    println!("
+Restart start-peer to publish the change.");
+    Ok(())
+}
+
+fn handle_escrow_preview(username: &str, image_id: &str, escrow: bool) -> Result<()> {
+    let (images_dir, mut store) = shared_folder_store(username)?;
+    store.set_escrow_preview(image_id, escrow)
+        .with_context(|| format!("{} is not shared from {}", image_id, images_dir.display()))?;
+
+    if !escrow {
+        println!("✓ {} will no longer leave a preview with the directory", image_id);
+    } else if let Some(preview) = store.escrowed_preview(image_id) {
+        println!("✓ {} will leave a blurred {} KB preview with the directory", image_id, preview.thumbnail.len().div_ceil(1024));
+        println!("  Peers can browse it while you're offline; their requests are queued until you're back");
+    } else {
+        store.set_escrow_preview(image_id, false)?;
+        bail!("Could not make a preview of {} (see the log)", image_id);
+    }
+    println!("
+Restart start-peer to publish the change.");
+    Ok(())
+}
+
+fn handle_set_grant_cap(username: &str, image_id: &str, max_views: Option<u32>) -> Result<()> {
+    if max_views == Some(0) {
+        bail!("The cap must allow at least one view");
+    }
+    let (images_dir, mut store) = shared_folder_store(username)?;
+    store.set_max_grant_views(image_id, max_views)
+        .with_context(|| format!("{} is not shared from {}", image_id, images_dir.display()))?;
+
+    match max_views {
+        Some(max) => println!("✓ Requests for {} will be granted at most {} views", image_id, max),
+        None => println!("✓ {} goes back to start-peer's --max-views", image_id),
+    }
+    println!("
+Restart start-peer to publish the change.");
+    Ok(())
+}
+
+async fn handle_request_image(
+    username: &str,
+    peer_username: &str,
+    image_id: &str,
+    views: u32,
+    max_dimension: Option<u32>,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    // A share link names the owner itself, with the token a link-only image needs
+    let (image_id, share_token) = if image_id.contains('#') {
+        let (owner, image_id, token) = parse_share_link(image_id)?;
+        if owner != peer_username {
+            bail!("That share link is for {}'s image, not {}'s", owner, peer_username);
+        }
+        (image_id, Some(token))
+    } else {
+        (image_id.to_string(), None)
+    };
+    let image_id = unqualify_image_id(peer_username, &image_id)?;
+    println!("=== Requesting Image from Peer ===");
+    println!("Your username: {}", username);
+    println!("Peer: {}", peer_username);
+    println!("Image ID: {}", image_id);
+    println!("Requested views: {}", views);
+    if let Some(max_dimension) = max_dimension {
+        println!("Requested size: at most {}px", max_dimension);
+    }
+
+    // First, verify that the requesting user (yourself) is online
+    println!("\nVerifying you are connected to directory service...");
+    let self_query_msg = DirectoryMessage::QueryUser {
+        username: username.to_string(),
+        requesting_user: None,
+    };
+
     match send_directory_or_multicast(directory_addr, self_query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry) }) => {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry), .. }) => {
             // Check if user is actually ONLINE (not just registered)
             use cloud_p2p_project::directory_service::UserStatus;
             if user_entry.status == UserStatus::Online {
                 println!("✓ You are online and connected to directory service");
             } else {
                 bail!(
-                    "❌ You must be online to list peer images!\n\
+                    "❌ You must be online to request images!\n\
                     \n\
                     Your account exists but your P2P peer is offline.\n\
                     You need to start your P2P peer:\n\
-                      cargo run --bin client -- start-peer --username {} --port <PORT>",
+                      cargo run --bin client -- start-peer --username {} --port <PORT>\n\
+                    \n\
+                    This will mark you as online and allow you to request images.",
                     username
                 );
             }
         }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
             bail!(
-                "❌ You must be online to list peer images!\n\
+                "❌ You must be online to request images!\n\
                 \n\
                 You need to start your P2P peer first:\n\
-                  cargo run --bin client -- start-peer --username {} --port <PORT>",
+                  cargo run --bin client -- start-peer --username {} --port <PORT>\n\
+                \n\
+                This will register you with the directory service and allow you to request images.",
                 username
             );
         }
@@ -1248,15 +2632,39 @@ async fn handle_list_peer_images(
     println!("\nLooking up peer '{}'...", peer_username);
     let query_msg = DirectoryMessage::QueryUser {
         username: peer_username.to_string(),
+        requesting_user: Some(username.to_string()),
     };
     
-    let peer_addr = match send_directory_or_multicast(directory_addr, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user) }) => {
-            println!("✓ Found peer at: {}", user.p2p_address);
-            user.p2p_address
+    let owner_entry = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), grants }) => {
+            use cloud_p2p_project::directory_service::UserStatus;
+            if user.status == UserStatus::Online {
+                println!("✓ Owner '{}' is online", peer_username);
+            } else {
+                println!("ℹ Owner '{}' is currently offline", peer_username);
+                if let Some(presence) = presence_summary(directory_addr, peer_username).await {
+                    println!("  {}", presence);
+                }
+            }
+            match (user.response_outlook(SystemTime::now()), user.availability) {
+                (ResponseOutlook::UsuallyOnlineNow, Some(window)) => {
+                    println!("  They're usually online around now ({}), so expect an answer soon", window);
+                }
+                (ResponseOutlook::BackIn { minutes }, Some(window)) => {
+                    println!("  They're usually online {} - expect an answer in about {}h {:02}m",
+                             window, minutes / 60, minutes % 60);
+                }
+                _ => {}
+            }
+            if let Some(grant) = grants.iter().find(|g| g.image_id == image_id) {
+                println!("⚠ You already have {} view(s) of {} (granted {}); if accepted, this request replaces them",
+                         grant.views, image_id, time_ago(grant.granted_at));
+            }
+            Some(user)
         }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-            bail!("Peer '{}' not found or offline", peer_username);
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            println!("ℹ Owner '{}' is not registered yet", peer_username);
+            None
         }
         Err(e) => {
             bail!("Error querying directory service: {}", e);
@@ -1265,100 +2673,157 @@ async fn handle_list_peer_images(
             bail!("Unexpected response from directory service");
         }
     };
-    
-    // List images from peer
-    println!("Querying peer for available images...");
-    match list_peer_images(&peer_addr, username).await {
-        Ok(images) => {
-            println!("\n✓ Peer has {} images available:", images.len());
-            
-            if images.is_empty() {
-                println!("  No images shared by this peer");
-            } else {
-                for img in images {
-                    println!("\n  Image ID: {}", img.image_id);
-                    println!("  Name:     {}", img.image_name);
-                    println!("  Owner:    {}", img.owner);
-                    println!("  Size:     {} KB", img.file_size_kb);
-                    
-                    if let Some(desc) = img.description {
-                        println!("  Description: {}", desc);
-                    }
-                }
-            }
-            
-            Ok(())
-        }
-        Err(e) => {
-            bail!("Failed to list images from peer: {}", e);
-        }
+
+    // Skip the download if we already hold the same image and only need more views
+    let delta_only = match &owner_entry {
+        Some(owner) => holds_identical_copy(username, owner, image_id).await,
+        None => false,
+    };
+    if delta_only {
+        println!("♻ You already have this exact image - requesting a permission update only");
     }
-}
 
-/// Helper function to store a pending permission update with embedded image
-async fn store_pending_update_with_image(
-    directory_addr: Option<&str>,
-    owner: &str,
-    target_user: &str,
-    image_id: &str,
-    new_quota: u32,
-    encrypted_image: Vec<u8>,
-) {
-    let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
-        from_owner: owner.to_string(),
-        target_user: target_user.to_string(),
+    // Always leave a request for the owner to approve (whether online or offline)
+    println!("\n📝 Submitting request to owner for approval...");
+    let leave_request_msg = DirectoryMessage::LeaveRequest {
+        from_user: username.to_string(),
+        to_user: peer_username.to_string(),
         image_id: image_id.to_string(),
-        new_quota,
-        embedded_image: Some(encrypted_image),
+        requested_views: views,
+        delta_only,
+        requested_max_dimension: max_dimension,
+        share_token,
     };
 
-    match send_directory_or_multicast(directory_addr, pending_msg).await {
-        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, message, .. }) => {
-            println!("✅ {}", message);
-            println!("   Image will be delivered as from_{}_{}.png when {} comes online", owner, target_user, target_user);
+    match send_directory_or_multicast(directory_addr, leave_request_msg).await {
+        Ok(DirectoryMessage::LeaveRequestResponse { success: true, request_id, .. }) => {
+            println!("✓ Request submitted successfully!");
+            println!("\n📋 Request details:");
+            println!("   Request ID: {}", request_id);
+            println!("   To: {}", peer_username);
+            println!("   Image: {}", image_id);
+            println!("   Requested views: {}", views);
+            println!("\n⏳ Waiting for owner approval...");
+            println!("   The owner must accept your request before you can view the image.");
+            println!("   If the owner is online, they will see your request immediately.");
+            println!("   If offline, they will see it when they come online.");
+            println!("\n💡 Check for owner's response with:");
+            println!("   cargo run --bin client -- check-notifications --username {}", username);
+            println!("\n   Once accepted, the image will be automatically delivered to you!");
+            Ok(())
         }
-        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: false, message, .. }) => {
-            eprintln!("⚠ Failed to store pending update: {}", message);
+        Ok(DirectoryMessage::LeaveRequestResponse { success: false, message, .. }) => {
+            bail!("Failed to leave request: {}", message);
         }
         Err(e) => {
-            eprintln!("⚠ Failed to store pending update: {}", e);
+            bail!("Error leaving request: {}", e);
         }
         _ => {
-            eprintln!("⚠ Unexpected response when storing pending update");
+            bail!("Unexpected response from directory service");
         }
     }
 }
 
-async fn handle_update_permissions(
-    owner: &str,
-    image_id: &str,
+/// When a user was last seen and is typically online, e.g. `last seen 2 hours ago, typically online evenings`
+async fn presence_summary(directory_addr: Option<&str>, username: &str) -> Option<String> {
+    let msg = DirectoryMessage::GetPresenceHistory { username: username.to_string() };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetPresenceHistoryResponse { history: Some(history) }) => {
+            Some(history.summary(SystemTime::now()))
+        }
+        _ => None,
+    }
+}
+
+/// Whether our local copy of an owner's image embeds the same bytes they share
+async fn holds_identical_copy(username: &str, owner: &UserEntry, image_id: &str) -> bool {
+    use cloud_p2p_project::directory_service::UserStatus;
+    if owner.status != UserStatus::Online || !owner.supports(CAP_IMAGE_PROBE) {
+        return false;
+    }
+
+    let Some(local_copy) = find_received_copies(Path::new("."), &owner.username, image_id).into_iter().next() else {
+        return false;
+    };
+    let Some(content_hash) = fs::read(&local_copy).ok().and_then(|data| content_sha256(&data)) else {
+        return false;
+    };
+
+    match probe_peer_image(&owner.p2p_address, username, image_id, &content_hash).await {
+        Ok(identical) => identical,
+        Err(e) => {
+            eprintln!("⚠ Could not compare images with {}: {}", owner.username, e);
+            false
+        }
+    }
+}
+
+async fn handle_list_peer_images(
     username: &str,
-    new_quota: u32,
+    peer_username: &str,
+    query: Option<&str>,
+    watch: bool,
     directory_addr: Option<&str>,
 ) -> Result<()> {
-    println!("=== Updating Permissions ===");
-    println!("Owner: {}", owner);
-    println!("Image ID: {}", image_id);
-    println!("User: {}", username);
-    println!("New quota: {} views", new_quota);
+    println!("=== Listing Peer's Images ===");
+    println!("Your username: {}", username);
+    println!("Peer: {}", peer_username);
 
-    if new_quota == 0 {
-        println!("⚠ This will REVOKE access for user '{}'", username);
+    // First, verify that the requesting user (yourself) is online
+    println!("\nVerifying you are connected to directory service...");
+    let self_query_msg = DirectoryMessage::QueryUser {
+        username: username.to_string(),
+        requesting_user: None,
+    };
+
+    match send_directory_or_multicast(directory_addr, self_query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry), .. }) => {
+            // Check if user is actually ONLINE (not just registered)
+            use cloud_p2p_project::directory_service::UserStatus;
+            if user_entry.status == UserStatus::Online {
+                println!("✓ You are online and connected to directory service");
+            } else {
+                bail!(
+                    "❌ You must be online to list peer images!\n\
+                    \n\
+                    Your account exists but your P2P peer is offline.\n\
+                    You need to start your P2P peer:\n\
+                      cargo run --bin client -- start-peer --username {} --port <PORT>",
+                    username
+                );
+            }
+        }
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!(
+                "❌ You must be online to list peer images!\n\
+                \n\
+                You need to start your P2P peer first:\n\
+                  cargo run --bin client -- start-peer --username {} --port <PORT>",
+                username
+            );
+        }
+        Err(e) => {
+            bail!("Error connecting to directory service: {}\n\nMake sure the directory service is running.", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
     }
 
-    // The owner needs to connect to their OWN P2P server to update the image
-    // Query directory service for own address
+    // Query directory service for peer address
+    println!("\nLooking up peer '{}'...", peer_username);
     let query_msg = DirectoryMessage::QueryUser {
-        username: owner.to_string(),
+        username: peer_username.to_string(),
+        requesting_user: None,
     };
-
-    let own_addr = match send_directory_or_multicast(directory_addr, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user) }) => {
-            println!("✓ Found own P2P server at: {}", user.p2p_address);
-            user.p2p_address
+    
+    let peer = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => {
+            println!("✓ Found peer at: {}", user.p2p_address);
+            user
         }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-            bail!("You must be running your P2P server to update permissions.\nStart with: cargo run --bin client -- start-peer --username {} --port <port>", owner);
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!("Peer '{}' not found or offline", peer_username);
         }
         Err(e) => {
             bail!("Error querying directory service: {}", e);
@@ -1367,137 +2832,493 @@ async fn handle_update_permissions(
             bail!("Unexpected response from directory service");
         }
     };
+    
+    let peer_addr = peer.p2p_address.clone();
+    if watch && !peer.supports(CAP_CATALOG_UPDATES) {
+        bail!("{}'s client doesn't push catalog updates yet - list without --watch", peer_username);
+    }
 
-    // Send update permissions request to own P2P server
-    use cloud_p2p_project::p2p_protocol::{P2PMessage, send_p2p_message, request_image_from_peer};
-
-    let update_msg = P2PMessage::UpdatePermissions {
-        owner: owner.to_string(),
-        image_id: image_id.to_string(),
-        username: username.to_string(),
-        new_quota,
+    // List images from peer
+    println!("Querying peer for available images...");
+    let listed = if watch {
+        subscribe_catalog(&peer_addr, username).await.map(|(images, subscription)| (images, Some(subscription)))
+    } else {
+        search_peer_images(&peer_addr, username, query).await.map(|images| (images, None))
     };
-
-    println!("Sending permission update request...");
-    match send_p2p_message(&own_addr, update_msg).await {
-        Ok(P2PMessage::UpdatePermissionsResponse { success: true, message }) => {
-            println!("✓ {}", message);
-            if new_quota == 0 {
-                println!("✓ User '{}' can no longer view this image", username);
+    match listed {
+        Ok((images, subscription)) => {
+            match query {
+                Some(query) => println!("\n✓ Peer has {} images matching \"{}\":", images.len(), query),
+                None => println!("\n✓ Peer has {} images available:", images.len()),
+            }
+            
+            if images.is_empty() {
+                println!("  No images shared by this peer");
             } else {
-                println!("✓ User '{}' now has {} views", username, new_quota);
+                for img in &images {
+                    print_peer_image(img);
+                }
             }
 
-            // Now check if the target user is online and send them the updated image
-            println!("\n📤 Checking if {} is online to send updated image...", username);
+            if let Some(mut subscription) = subscription {
+                println!("\n👀 Watching for changes (Ctrl+C to stop)...");
+                while let Some(event) = subscription.next_event().await? {
+                    match event {
+                        CatalogEvent::Added { image } => {
+                            println!("\n➕ Shared:");
+                            print_peer_image(&image);
+                        }
+                        CatalogEvent::Updated { image } => {
+                            println!("\n✏️  Changed:");
+                            print_peer_image(&image);
+                        }
+                        CatalogEvent::Removed { image_id } => println!("\n➖ No longer shared: {}", image_id),
+                        CatalogEvent::KeepAlive => {}
+                    }
+                }
+                println!("\n{} ended the subscription (went offline?)", peer_username);
+            }
             
-            let target_query_msg = DirectoryMessage::QueryUser {
-                username: username.to_string(),
-            };
+            Ok(())
+        }
+        Err(e) => {
+            bail!("Failed to list images from peer: {}", e);
+        }
+    }
+}
 
-            match send_directory_or_multicast(directory_addr, target_query_msg).await {
-                Ok(DirectoryMessage::QueryUserResponse { user: Some(target_user) }) => {
-                    use cloud_p2p_project::directory_service::UserStatus;
-                    if target_user.status == UserStatus::Online {
-                        println!("✓ {} is online at {}", username, target_user.p2p_address);
-                        println!("🚀 Fetching updated image to send to {}...", username);
-
-                        // Fetch the updated image from our own P2P server (as owner)
-                        match request_image_from_peer(
-                            &own_addr,
-                            owner,  // Request as owner
-                            image_id,
-                            new_quota,
-                        ).await {
-                            Ok(encrypted_image) => {
-                                println!("✓ Image fetched, now delivering to {}...", username);
-
-                                // Clone the image data in case we need to store it for later
-                                let image_for_fallback = encrypted_image.clone();
-
-                                // Deliver the updated image to the target user
-                                let deliver_msg = P2PMessage::DeliverImage {
-                                    from_owner: owner.to_string(),
-                                    image_id: image_id.to_string(),
-                                    requested_views: new_quota,
-                                    encrypted_image,
-                                };
-
-                                match send_p2p_message(&target_user.p2p_address, deliver_msg).await {
-                                    Ok(P2PMessage::DeliverImageResponse { success: true, message }) => {
-                                        println!("\n✅ Updated image delivered successfully to {}!", username);
-                                        println!("   {}", message);
-                                    }
-                                    Ok(P2PMessage::DeliverImageResponse { success: false, message }) => {
-                                        eprintln!("\n⚠ Failed to deliver updated image: {}", message);
-                                        // Fall back to storing pending update
-                                        println!("📝 Storing update for later delivery...");
-                                        store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback).await;
-                                    }
-                                    Err(e) => {
-                                        eprintln!("\n⚠ Could not deliver updated image to {} (may be offline): {}", username, e);
-                                        // Fall back to storing pending update
-                                        println!("📝 Storing update for later delivery...");
-                                        store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback).await;
-                                    }
-                                    _ => {
-                                        eprintln!("\n⚠ Unexpected response when delivering image");
-                                        // Fall back to storing pending update
-                                        println!("📝 Storing update for later delivery...");
-                                        store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback).await;
-                                    }
-                                }
+fn print_peer_image(img: &ImageMetadata) {
+    println!("\n  Image ID: {}", img.image_id);
+    println!("  Name:     {}", img.image_name);
+    println!("  Owner:    {}", img.owner);
+    println!("  Size:     {} KB", img.file_size_kb);
+
+    if let Some(max) = img.max_grant_views {
+        println!("  Max views per grant: {}", max);
+    }
+
+    if let Some(desc) = &img.description {
+        println!("  Description: {}", desc);
+    }
+}
+
+async fn handle_redeliver(
+    username: &str,
+    peer_username: &str,
+    image_id: &str,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    let image_id = unqualify_image_id(peer_username, image_id)?;
+    println!("=== Requesting Re-delivery ===");
+    println!("Your username: {}", username);
+    println!("Owner: {}", peer_username);
+    println!("Image ID: {}", image_id);
+
+    if let Some(copy) = find_received_copies(Path::new("."), peer_username, image_id).first() {
+        bail!("❌ You still have {} - re-delivery is only for lost copies", copy.display());
+    }
+
+    println!("\nLooking up owner '{}'...", peer_username);
+    let query_msg = DirectoryMessage::QueryUser {
+        username: peer_username.to_string(),
+        requesting_user: None,
+    };
+    let owner = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => user,
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!("❌ Owner '{}' not found", peer_username);
+        }
+        Err(e) => {
+            bail!("Error querying directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    };
+
+    use cloud_p2p_project::directory_service::UserStatus;
+    if owner.status != UserStatus::Online {
+        bail!("❌ Owner '{}' is offline - re-delivery needs them online", peer_username);
+    }
+    if !owner.supports(CAP_REDELIVERY) {
+        bail!("❌ Owner '{}' runs a client without re-delivery - send a new request instead", peer_username);
+    }
+
+    let data = request_redelivery(&owner.p2p_address, username, image_id).await
+        .map_err(|e| anyhow::anyhow!("❌ {}", e))?;
+    let save_path = received_image_path(None, peer_username, image_id);
+    save_received_image(&save_path, peer_username, image_id, &data)?;
+
+    let views = embedded_permissions(&data)
+        .and_then(|permissions| permissions.quotas.get(username).copied())
+        .unwrap_or(0);
+    println!("\n✅ Image re-delivered and saved to: {}", save_path.display());
+    println!("👁  Views: {} (as last granted by the owner)", views);
+    println!("\n💡 View it with:");
+    println!("   cargo run --bin client -- view --input {} --user {}", save_path.display(), username);
+
+    Ok(())
+}
+
+fn handle_export_grant(
+    input: &Path,
+    owner: &str,
+    recipient: &str,
+    views: u32,
+    passphrase: &str,
+    output: Option<&Path>,
+) -> Result<()> {
+    println!("=== Exporting Grant Package ===");
+    let (info, package) = export_grant_package(input, owner, recipient, views, passphrase)?;
+
+    let output = output.map(Path::to_path_buf).unwrap_or_else(|| {
+        let image = info.image_id.trim_end_matches(".png");
+        PathBuf::from(format!("{}_{}.{}", recipient, image, GRANT_PACKAGE_EXTENSION))
+    });
+    fs::write(&output, &package).with_context(|| format!("Failed to write {}", output.display()))?;
+
+    audit(Some(Path::new(AUDIT_LOG_FILE)), AuditRecord {
+        action: AuditAction::Grant,
+        recipient: recipient.to_string(),
+        image_id: info.image_id.clone(),
+        views: Some(views),
+        success: true,
+        message: format!("Exported as grant package {}", output.display()),
+        approved_by: None,
+    });
+
+    println!("✓ Granted {} {} view(s) of '{}' (grant #{})", recipient, views, info.image_id, info.grant_seq);
+    println!("📦 Package written to {} ({} KB)", output.display(), package.len() / 1024);
+    println!("   Tell {} the passphrase in person; they import it with:", recipient);
+    println!("   cargo run --bin client -- import-grant --package {} --username {} --passphrase <passphrase>",
+             output.display(), recipient);
+    Ok(())
+}
+
+async fn handle_import_grant(package: &Path, username: &str, passphrase: &str, received_dir: Option<&Path>) -> Result<()> {
+    println!("=== Importing Grant Package ===");
+    let bytes = fs::read(package).with_context(|| format!("Failed to read {}", package.display()))?;
+
+    let mut store = PeerImageStore::new();
+    if let Some(dir) = received_dir {
+        fs::create_dir_all(dir)?;
+        store.set_received_images_dir(dir.to_path_buf());
+    }
+    let image_store = Arc::new(RwLock::new(store));
+
+    let (info, message) = import_grant_package(&bytes, passphrase, username, &image_store).await?;
+    println!("✓ {} granted you {} view(s) of '{}' (grant #{})", info.from_owner, info.views, info.image_id, info.grant_seq);
+    println!("   {}", message);
+    Ok(())
+}
+
+async fn handle_redeem_pairing(pairing: &str, username: &str, received_dir: Option<&Path>) -> Result<()> {
+    println!("=== Redeeming Pairing Code ===");
+    let (peer_addr, code) = parse_pairing(pairing)?;
+
+    let mut store = PeerImageStore::new();
+    if let Some(dir) = received_dir {
+        fs::create_dir_all(dir)?;
+        store.set_received_images_dir(dir.to_path_buf());
+    }
+    let image_store = Arc::new(RwLock::new(store));
+
+    let redeemed = redeem_pairing(&peer_addr, &code, username, &image_store).await?;
+    println!("✓ {} granted you {} view(s) of '{}'", redeemed.from_owner, redeemed.views, redeemed.image_id);
+    println!("   {}", redeemed.message);
+    Ok(())
+}
+
+fn handle_received_names(policy: Option<ReceivedNamePolicy>, received_dir: Option<&Path>) -> Result<()> {
+    let dir = received_dir.unwrap_or(Path::new("."));
+    let Some(policy) = policy else {
+        println!("Received images in {} are named: {}", dir.display(), load_name_policy(dir));
+        return Ok(());
+    };
+
+    save_name_policy(dir, policy)?;
+    println!("✓ New deliveries to {} will be named: {}", dir.display(), policy);
+    println!("   e.g. {}", policy.file_name("alice", "sunset.png"));
+    println!("   Images you already hold keep their names; updates to them still find them.");
+    Ok(())
+}
+
+/// Record a `DeliverImage` attempt in the audit log in the working directory
+fn audit_delivery(recipient: &str, image_id: &str, views: u32, response: &Result<cloud_p2p_project::p2p_protocol::P2PMessage>) {
+    use cloud_p2p_project::p2p_protocol::P2PMessage;
+    let (success, message) = match response {
+        Ok(P2PMessage::DeliverImageResponse { success, message, .. }) => (*success, message.clone()),
+        Ok(P2PMessage::StorageFull { message, .. }) => (false, message.clone()),
+        Ok(_) => (false, "Unexpected response".to_string()),
+        Err(e) => (false, e.to_string()),
+    };
+    // A full disk on their side doesn't count against the peer
+    if !matches!(response, Ok(P2PMessage::StorageFull { .. })) {
+        record_delivery(recipient, success);
+    }
+    audit(Some(Path::new(AUDIT_LOG_FILE)), AuditRecord {
+        action: AuditAction::Delivery,
+        recipient: recipient.to_string(),
+        image_id: image_id.to_string(),
+        views: Some(views),
+        success,
+        message,
+        approved_by: None,
+    });
+}
+
+/// Print the checksum confirmed by the receiver and whether it matches what we sent
+fn print_delivery_checksum(sent_sha256: &str, received_sha256: Option<&str>, size_bytes: u64) {
+    match received_sha256 {
+        Some(received) if received == sent_sha256 => {
+            println!("   🔒 Verified SHA-256: {} ({} bytes)", received, size_bytes);
+        }
+        Some(received) => {
+            eprintln!("   ⚠ Checksum mismatch! Sent {}, receiver stored {}", sent_sha256, received);
+        }
+        None => {
+            println!("   🔒 Sent SHA-256: {} (receiver did not confirm)", sent_sha256);
+        }
+    }
+}
+
+/// Helper function to store a pending permission update with embedded image
+async fn store_pending_update_with_image(
+    directory_addr: Option<&str>,
+    owner: &str,
+    target_user: &str,
+    image_id: &str,
+    new_quota: u32,
+    encrypted_image: Vec<u8>,
+    ttl: Option<Duration>,
+) -> bool {
+    let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
+        from_owner: owner.to_string(),
+        target_user: target_user.to_string(),
+        image_id: image_id.to_string(),
+        new_quota,
+        embedded_image: Some(encrypted_image),
+        wipe: false,
+        ttl_secs: ttl.map(|ttl| ttl.as_secs()),
+    };
+
+    match send_directory_or_multicast(directory_addr, pending_msg).await {
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, message, .. }) => {
+            println!("✅ {}", message);
+            println!("   Image will be delivered when {} comes online", target_user);
+            true
+        }
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: false, message, .. }) => {
+            eprintln!("⚠ Failed to store pending update: {}", message);
+            false
+        }
+        Err(e) => {
+            eprintln!("⚠ Failed to store pending update: {}", e);
+            false
+        }
+        _ => {
+            eprintln!("⚠ Unexpected response when storing pending update");
+            false
+        }
+    }
+}
+
+async fn handle_update_permissions(
+    owner: &str,
+    image_id: &str,
+    username: &str,
+    new_quota: u32,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== Updating Permissions ===");
+    println!("Owner: {}", owner);
+    println!("Image ID: {}", image_id);
+    println!("User: {}", username);
+    println!("New quota: {} views", new_quota);
+
+    if new_quota == 0 {
+        println!("⚠ This will REVOKE access for user '{}'", username);
+    }
+
+    let own_addr = grant_on_own_copy(owner, image_id, username, new_quota, directory_addr).await?;
+    use cloud_p2p_project::p2p_protocol::{P2PMessage, send_p2p_message, request_image_from_peer};
+
+    // Now check if the target user is online and send them the updated image
+    println!("\n📤 Checking if {} is online to send updated image...", username);
+    
+    let target_query_msg = DirectoryMessage::QueryUser {
+        username: username.to_string(),
+        requesting_user: None,
+    };
+
+    match send_directory_or_multicast(directory_addr, target_query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(target_user), .. }) => {
+            use cloud_p2p_project::directory_service::UserStatus;
+            if target_user.status == UserStatus::Online {
+                println!("✓ {} is online at {}", username, target_user.p2p_address);
+                println!("🚀 Fetching updated image to send to {}...", username);
+
+                // Fetch the updated image from our own P2P server (as owner)
+                match request_image_from_peer(
+                    &own_addr,
+                    owner,  // Request as owner
+                    image_id,
+                    new_quota,
+                ).await {
+                    Ok(encrypted_image) => {
+                        println!("✓ Image fetched, now delivering to {}...", username);
+
+                        // Clone the image data in case we need to store it for later
+                        let image_for_fallback = encrypted_image.clone();
+
+                        // Deliver the updated image to the target user
+                        let sent_sha256 = sha256_hex(&encrypted_image);
+                        let grant_seq = embedded_grant_seq(&encrypted_image, username);
+                        let deliver_msg = P2PMessage::DeliverImage {
+                            from_owner: owner.to_string(),
+                            image_id: image_id.to_string(),
+                            requested_views: new_quota,
+                            encrypted_image,
+                            grant_seq,
+                            sha256: target_user.supports(CAP_CHECKSUMS).then(|| sent_sha256.clone()),
+                        };
+
+                        let response = send_p2p_message(&target_user.p2p_address, deliver_msg).await;
+                        audit_delivery(username, image_id, new_quota, &response);
+                        match response {
+                            Ok(P2PMessage::DeliverImageResponse { success: true, message, sha256, size_bytes }) => {
+                                println!("\n✅ Updated image delivered successfully to {}!", username);
+                                println!("   {}", message);
+                                print_delivery_checksum(&sent_sha256, sha256.as_deref(), size_bytes);
                             }
-                            Err(e) => {
-                                eprintln!("\n⚠ Failed to fetch image for delivery: {}", e);
+                            Ok(P2PMessage::StorageFull { message, .. }) => {
+                                eprintln!("\n⚠ {} is low on disk space: {}", username, message);
+                                println!("📝 Storing update for when they have room...");
+                                store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback, None).await;
                             }
-                        }
-                    } else {
-                        println!("ℹ {} is offline. Storing update with image for delivery when they come online...", username);
-                        
-                        // Fetch the updated image to store for later delivery
-                        match request_image_from_peer(
-                            &own_addr,
-                            owner,  // Request as owner
-                            image_id,
-                            new_quota,
-                        ).await {
-                            Ok(encrypted_image) => {
-                                println!("✓ Image fetched, storing for later delivery...");
-                                store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, encrypted_image).await;
+                            Ok(P2PMessage::DeliverImageResponse { success: false, message, .. }) => {
+                                eprintln!("\n⚠ Failed to deliver updated image: {}", message);
+                                // Fall back to storing pending update
+                                println!("📝 Storing update for later delivery...");
+                                store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback, None).await;
                             }
                             Err(e) => {
-                                eprintln!("⚠ Failed to fetch image for storage: {}", e);
+                                eprintln!("\n⚠ Could not deliver updated image to {} (may be offline): {}", username, e);
+                                // Fall back to storing pending update
+                                println!("📝 Storing update for later delivery...");
+                                store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback, None).await;
+                            }
+                            _ => {
+                                eprintln!("\n⚠ Unexpected response when delivering image");
+                                // Fall back to storing pending update
+                                println!("📝 Storing update for later delivery...");
+                                store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, image_for_fallback, None).await;
                             }
                         }
                     }
+                    Err(e) => {
+                        eprintln!("\n⚠ Failed to fetch image for delivery: {}", e);
+                    }
                 }
-                Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-                    println!("ℹ {} is not registered. Storing update with image for delivery when they register...", username);
-                    
-                    // Fetch the updated image to store for later delivery
-                    match request_image_from_peer(
-                        &own_addr,
-                        owner,  // Request as owner
-                        image_id,
-                        new_quota,
-                    ).await {
-                        Ok(encrypted_image) => {
-                            println!("✓ Image fetched, storing for later delivery...");
-                            store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, encrypted_image).await;
-                        }
-                        Err(e) => {
-                            eprintln!("⚠ Failed to fetch image for storage: {}", e);
-                        }
+            } else {
+                println!("ℹ {} is offline. Storing update with image for delivery when they come online...", username);
+                
+                // Fetch the updated image to store for later delivery
+                match request_image_from_peer(
+                    &own_addr,
+                    owner,  // Request as owner
+                    image_id,
+                    new_quota,
+                ).await {
+                    Ok(encrypted_image) => {
+                        println!("✓ Image fetched, storing for later delivery...");
+                        store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, encrypted_image, None).await;
+                    }
+                    Err(e) => {
+                        eprintln!("⚠ Failed to fetch image for storage: {}", e);
                     }
                 }
+            }
+        }
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            println!("ℹ {} is not registered. Storing update with image for delivery when they register...", username);
+            
+            // Fetch the updated image to store for later delivery
+            match request_image_from_peer(
+                &own_addr,
+                owner,  // Request as owner
+                image_id,
+                new_quota,
+            ).await {
+                Ok(encrypted_image) => {
+                    println!("✓ Image fetched, storing for later delivery...");
+                    store_pending_update_with_image(directory_addr, owner, username, image_id, new_quota, encrypted_image, None).await;
+                }
                 Err(e) => {
-                    eprintln!("⚠ Could not check if {} is online: {}", username, e);
+                    eprintln!("⚠ Failed to fetch image for storage: {}", e);
                 }
-                _ => {}
             }
+        }
+        Err(e) => {
+            eprintln!("⚠ Could not check if {} is online: {}", username, e);
+        }
+        _ => {}
+    }
 
-            Ok(())
+    Ok(())
+}
+
+/// Set a user's quota on the owner's own copy through their P2P server, returning its address
+async fn grant_on_own_copy(
+    owner: &str,
+    image_id: &str,
+    username: &str,
+    new_quota: u32,
+    directory_addr: Option<&str>,
+) -> Result<String> {
+    // The owner needs to connect to their OWN P2P server to update the image
+    // Query directory service for own address
+    let query_msg = DirectoryMessage::QueryUser {
+        username: owner.to_string(),
+        requesting_user: None,
+    };
+
+    let own_addr = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => {
+            println!("✓ Found own P2P server at: {}", user.p2p_address);
+            user.p2p_address
+        }
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!("You must be running your P2P server to update permissions.\nStart with: cargo run --bin client -- start-peer --username {} --port <port>", owner);
+        }
+        Err(e) => {
+            bail!("Error querying directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    };
+
+    // Send update permissions request to own P2P server
+    use cloud_p2p_project::p2p_protocol::{P2PMessage, send_p2p_message};
+
+    let update_msg = P2PMessage::UpdatePermissions {
+        owner: owner.to_string(),
+        image_id: image_id.to_string(),
+        username: username.to_string(),
+        new_quota,
+    };
+
+    println!("Sending permission update request...");
+    match send_p2p_message(&own_addr, update_msg).await {
+        Ok(P2PMessage::UpdatePermissionsResponse { success: true, message }) => {
+            println!("✓ {}", message);
+            if new_quota == 0 {
+                println!("✓ User '{}' can no longer view this image", username);
+            } else {
+                println!("✓ User '{}' now has {} views", username, new_quota);
+            }
+            Ok(own_addr)
         }
         Ok(P2PMessage::UpdatePermissionsResponse { success: false, message }) => {
             bail!("Failed to update permissions: {}", message);
@@ -1522,10 +3343,11 @@ async fn handle_check_requests(
     println!("\n🔍 Verifying you are online...");
     let self_query = DirectoryMessage::QueryUser {
         username: username.to_string(),
+        requesting_user: None,
     };
 
     match send_directory_or_multicast(directory_addr, self_query).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry) }) => {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry), .. }) => {
             use cloud_p2p_project::directory_service::UserStatus;
             if user_entry.status != UserStatus::Online {
                 bail!(
@@ -1540,7 +3362,7 @@ async fn handle_check_requests(
             }
             println!("✓ You are online\n");
         }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
             bail!(
                 "❌ You must be online to check requests!\n\
                 \n\
@@ -1573,24 +3395,20 @@ async fn handle_check_requests(
                     println!("   From: {}", req.from_user);
                     println!("   Image: {}", req.image_id);
                     println!("   Requested views: {}", req.requested_views);
-
-                    if let Ok(duration) = req.timestamp.elapsed() {
-                        let secs = duration.as_secs();
-                        if secs < 60 {
-                            println!("   Time: {} seconds ago", secs);
-                        } else if secs < 3600 {
-                            println!("   Time: {} minutes ago", secs / 60);
-                        } else {
-                            println!("   Time: {} hours ago", secs / 3600);
-                        }
+                    if let Some(max_dimension) = req.requested_max_dimension {
+                        println!("   Requested size: at most {}px", max_dimension);
                     }
 
+                    println!("   Time: {}", time_ago(req.timestamp));
+
                     println!();
                 }
 
                 println!("To respond to a request, use:");
                 println!("  cargo run --bin client -- respond-request --owner {} --request-id <ID> --accept <true/false>", username);
             }
+            report_request_throttles(directory_addr, username).await;
+            report_delegated_requests(directory_addr, username).await;
 
             Ok(())
         }
@@ -1603,25 +3421,105 @@ async fn handle_check_requests(
     }
 }
 
-async fn handle_respond_request(
-    owner: &str,
-    request_id: &str,
-    accept: bool,
-    directory_addr: Option<&str>,
-) -> Result<()> {
-    println!("=== Responding to Request ===");
+/// List requests the user may answer for owners who made them a delegate
+async fn report_delegated_requests(directory_addr: Option<&str>, username: &str) {
+    let msg = DirectoryMessage::GetDelegatedRequests {
+        delegate: username.to_string(),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) if !requests.is_empty() => {
+            println!("\n🤝 {} request(s) you can answer as a delegate:\n", requests.len());
+            for req in &requests {
+                println!("   {} -> {}/{} ({} views)  Request ID: {}",
+                         req.from_user, req.to_user, req.image_id, req.requested_views, req.request_id);
+            }
+            println!("\nAnswer them the same way, with --owner {}", username);
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("⚠ Could not check delegated requests: {}", e),
+    }
+}
+
+/// Owner of a request the user can answer as their delegate (None if it isn't one)
+async fn delegated_request_owner(directory_addr: Option<&str>, delegate: &str, request_id: &str) -> Option<String> {
+    let msg = DirectoryMessage::GetDelegatedRequests {
+        delegate: delegate.to_string(),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) => {
+            requests.into_iter().find(|r| r.request_id == request_id).map(|r| r.to_user)
+        }
+        _ => None,
+    }
+}
+
+/// Tell the owner about requesters the directory refused for leaving too many requests
+async fn report_request_throttles(directory_addr: Option<&str>, username: &str) {
+    let msg = DirectoryMessage::GetRequestThrottles {
+        username: username.to_string(),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetRequestThrottlesResponse { throttled }) => {
+            for note in throttled {
+                let ago = note.last_rejected.elapsed().map_or(0, |d| d.as_secs() / 60);
+                println!(
+                    "🚦 Requests from {} were throttled: {} refused for having too many pending (last {} min ago)",
+                    note.sender, note.rejected, ago
+                );
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("⚠ Could not check throttled requesters: {}", e),
+    }
+}
+
+async fn handle_respond_request(
+    responder: &str,
+    request_id: &str,
+    accept: bool,
+    granted_views: Option<u32>,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== Responding to Request ===");
     println!("Request ID: {}", request_id);
     println!("Action: {}", if accept { "ACCEPT" } else { "REJECT" });
 
+    // A delegate answers for the owner, whose peer still holds the image and grants it
+    let delegated_owner = delegated_request_owner(directory_addr, responder, request_id).await;
+    let approved_by = delegated_owner.is_some().then_some(responder);
+    let owner = delegated_owner.as_deref().unwrap_or(responder);
+    if let Some(delegated_owner) = &delegated_owner {
+        println!("Answering as {}'s delegate", delegated_owner);
+    }
+
     // If accepting, verify the owner is online first
-    if accept {
+    if accept && approved_by.is_some() {
+        println!("\n🔍 Verifying {} is online...", owner);
+        match send_directory_or_multicast(directory_addr, DirectoryMessage::QueryUser { username: owner.to_string(), requesting_user: None }).await {
+            Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry), .. })
+                if user_entry.status == cloud_p2p_project::directory_service::UserStatus::Online =>
+            {
+                println!("✓ {} is online", owner);
+            }
+            Ok(DirectoryMessage::QueryUserResponse { .. }) => {
+                bail!("❌ {} must be online for their image to be granted - try again later or reject", owner);
+            }
+            Err(e) => {
+                bail!("Error checking online status: {}", e);
+            }
+            _ => {
+                bail!("Unexpected response from directory service");
+            }
+        }
+    } else if accept {
         println!("\n🔍 Verifying you are online...");
         let self_query = DirectoryMessage::QueryUser {
             username: owner.to_string(),
+            requesting_user: None,
         };
 
         match send_directory_or_multicast(directory_addr, self_query).await {
-            Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry) }) => {
+            Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry), .. }) => {
                 use cloud_p2p_project::directory_service::UserStatus;
                 if user_entry.status != UserStatus::Online {
                     bail!(
@@ -1636,7 +3534,7 @@ async fn handle_respond_request(
                 }
                 println!("✓ You are online");
             }
-            Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
+            Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
                 bail!(
                     "❌ You must be online to accept requests!\n\
                     \n\
@@ -1656,150 +3554,79 @@ async fn handle_respond_request(
 
     let msg = DirectoryMessage::RespondToRequest {
         request_id: request_id.to_string(),
-        owner: owner.to_string(),
+        owner: responder.to_string(),
         accept,
+        granted_views,
+        note: None,
+        key: Some(registration_key(responder)?),
     };
 
     match send_directory_or_multicast(directory_addr, msg).await {
         Ok(DirectoryMessage::RespondToRequestResponse { success: true, message, request: Some(req) }) => {
             println!("✓ {}", message);
 
+            // A counter-offer grants a different number of views than requested
+            let views = req.views_to_grant();
+
             if accept {
                 // Automatically grant permissions by updating the image
                 println!("\n🔄 Automatically granting permissions...");
                 println!("   User: {}", req.from_user);
                 println!("   Image: {}", req.image_id);
-                println!("   Views: {}", req.requested_views);
+                println!("   Views: {}", views);
+                if let Some(max_dimension) = req.requested_max_dimension {
+                    println!("   Size: downscaled to at most {}px", max_dimension);
+                }
 
-                // Call update_permissions automatically
-                match handle_update_permissions(
+                // Only the owner's copy here - the delivery below sends the requester theirs
+                match grant_on_own_copy(
                     owner,
                     &req.image_id,
                     &req.from_user,
-                    req.requested_views,
+                    views,
                     directory_addr,
                 )
                 .await
                 {
-                    Ok(()) => {
+                    Ok(_) if req.delta_only => {
                         println!("\n✅ Permissions granted successfully!");
 
-                        // Now check if requester is online and deliver the image automatically
-                        println!("\n📤 Checking if {} is online to deliver the image...", req.from_user);
-
-                        let query_msg = DirectoryMessage::QueryUser {
-                            username: req.from_user.clone(),
-                        };
-
-                        // First, fetch the image from our own P2P server (with updated permissions)
-                        use cloud_p2p_project::p2p_protocol::{P2PMessage, send_p2p_message, request_image_from_peer};
-
-                        // Query directory to get our own P2P address
-                        let self_query = DirectoryMessage::QueryUser {
-                            username: owner.to_string(),
-                        };
-
-                        let encrypted_image = match send_directory_or_multicast(directory_addr, self_query).await {
-                            Ok(DirectoryMessage::QueryUserResponse { user: Some(self_user) }) => {
-                                // Fetch the image from our own P2P server WITH THE REQUESTING USER'S NAME
-                                // so the quota gets embedded for them, not the owner
-                                match request_image_from_peer(
-                                    &self_user.p2p_address,
-                                    &req.from_user,  // Request as the requester (Alice), not as owner (Bob)
-                                    &req.image_id,
-                                    req.requested_views,
-                                )
-                                .await
-                                {
-                                    Ok(img) => {
-                                        println!("✓ Image fetched successfully");
-                                        Some(img)
-                                    }
-                                    Err(e) => {
-                                        eprintln!("\n⚠ Failed to fetch image: {}", e);
-                                        None
-                                    }
-                                }
-                            }
-                            _ => {
-                                eprintln!("\n⚠ Could not find own P2P server");
-                                None
+                        // The requester already holds identical bytes, so only send the new quota
+                        println!("\n♻ {} already has this image - sending a permission update instead", req.from_user);
+                        match handle_remote_update_permissions(
+                            owner,
+                            &req.from_user,
+                            &req.image_id,
+                            views,
+                            None,
+                            directory_addr,
+                        )
+                        .await
+                        {
+                            Ok(()) => mark_request_delivered(responder, &req.request_id, directory_addr).await,
+                            Err(e) => {
+                                eprintln!("\n⚠ Could not send permission update: {}", e);
+                                println!("💡 {}'s running peer will retry the delivery", owner);
                             }
-                        };
-
-                        if encrypted_image.is_none() {
-                            println!("💡 {} can manually request the image when ready", req.from_user);
-                            return Ok(());
                         }
+                    }
+                    Ok(_) => {
+                        println!("\n✅ Permissions granted successfully!");
 
-                        let encrypted_image = encrypted_image.unwrap();
-
-                        // Now check if requester is online and try to deliver
-                        match send_directory_or_multicast(directory_addr, query_msg).await {
-                            Ok(DirectoryMessage::QueryUserResponse { user: Some(user) }) => {
-                                use cloud_p2p_project::directory_service::UserStatus;
-                                if user.status == UserStatus::Online {
-                                    println!("✓ {} is online at {}", req.from_user, user.p2p_address);
-                                    println!("🚀 Attempting to deliver image to {}...", req.from_user);
-
-                                    // Clone image for fallback
-                                    let image_for_fallback = encrypted_image.clone();
-
-                                    // Try to deliver the image to the requester
-                                    let deliver_msg = P2PMessage::DeliverImage {
-                                        from_owner: owner.to_string(),
-                                        image_id: req.image_id.clone(),
-                                        requested_views: req.requested_views,
-                                        encrypted_image,
-                                    };
-
-                                    match send_p2p_message(&user.p2p_address, deliver_msg).await {
-                                        Ok(P2PMessage::DeliverImageResponse { success: true, message }) => {
-                                            println!("\n✅ Image delivered successfully to {}!", req.from_user);
-                                            println!("   {}", message);
-                                        }
-                                        Ok(P2PMessage::DeliverImageResponse { success: false, message }) => {
-                                            eprintln!("\n⚠ Failed to deliver image: {}", message);
-                                            println!("📝 Storing image for delivery when {} is fully online...", req.from_user);
-                                            store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, image_for_fallback).await;
-                                        }
-                                        Err(e) => {
-                                            eprintln!("\n⚠ Could not deliver image to {} (connection failed: {})", req.from_user, e);
-                                            println!("📝 Storing image for delivery when {} is fully online...", req.from_user);
-                                            store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, image_for_fallback).await;
-                                        }
-                                        _ => {
-                                            eprintln!("\n⚠ Unexpected response when delivering image");
-                                            println!("📝 Storing image for delivery when {} is fully online...", req.from_user);
-                                            store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, image_for_fallback).await;
-                                        }
-                                    }
-                                } else {
-                                    println!("ℹ {} is offline. Storing image for delivery when they come online...", req.from_user);
-                                    store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, encrypted_image).await;
-                                }
-                            }
-                            Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-                                println!("ℹ {} is not online. Storing image for delivery when they register...", req.from_user);
-                                store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, encrypted_image).await;
-                            }
+                        match deliver_accepted_request(owner, &req, directory_addr).await {
+                            Ok(()) => mark_request_delivered(responder, &req.request_id, directory_addr).await,
                             Err(e) => {
-                                eprintln!("⚠ Could not check if {} is online: {}", req.from_user, e);
-                                println!("📝 Storing image for delivery as fallback...");
-                                store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, encrypted_image).await;
-                            }
-                            _ => {
-                                println!("📝 Storing image for delivery as fallback...");
-                                store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, req.requested_views, encrypted_image).await;
+                                eprintln!("\n⚠ Could not deliver the image: {}", e);
+                                println!("💡 {}'s running peer will retry the delivery", owner);
                             }
                         }
                     }
                     Err(e) => {
                         eprintln!("\n⚠ Warning: Request was accepted but failed to grant permissions:");
                         eprintln!("   {}", e);
-                        eprintln!("\n💡 You can manually grant permissions with:");
+                        eprintln!("\n💡 {}'s running peer will retry the delivery, or grant it yourself with:", owner);
                         eprintln!("   cargo run --bin client -- update-permissions --owner {} --image-id {} --username {} --new-quota {}",
-                                 owner, req.image_id, req.from_user, req.requested_views);
+                                 owner, req.image_id, req.from_user, views);
                     }
                 }
             } else {
@@ -1825,6 +3652,144 @@ async fn handle_respond_request(
     }
 }
 
+/// Fetch an accepted request's image from the owner's peer, which embeds the grant, and deliver it
+///
+/// Falls back to storing it at the directory when the requester can't take it, and only
+/// succeeds once the image was delivered or stored.
+async fn deliver_accepted_request(owner: &str, req: &PendingRequest, directory_addr: Option<&str>) -> Result<()> {
+    use cloud_p2p_project::directory_service::UserStatus;
+    use cloud_p2p_project::p2p_protocol::{P2PMessage, send_p2p_message, request_scaled_image_from_peer};
+
+    let views = req.views_to_grant();
+    let owner_addr = match send_directory_or_multicast(directory_addr, DirectoryMessage::QueryUser { username: owner.to_string(), requesting_user: None }).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(owner_entry), .. }) => owner_entry.p2p_address,
+        Ok(_) => bail!("Could not find {}'s P2P server", owner),
+        Err(e) => bail!("Error querying directory service: {}", e),
+    };
+
+    // Fetch as the requester, so the quota gets embedded for them rather than the owner
+    let encrypted_image = request_scaled_image_from_peer(
+        &owner_addr,
+        &req.from_user,
+        &req.image_id,
+        views,
+        req.requested_max_dimension,
+        req.approved_by.as_deref(),
+        req.grant_token.as_deref(),
+    )
+    .await
+    .context("Failed to fetch image")?;
+    println!("✓ Image fetched successfully");
+
+    println!("\n📤 Checking if {} is online to deliver the image...", req.from_user);
+    let requester = match send_directory_or_multicast(directory_addr, DirectoryMessage::QueryUser { username: req.from_user.clone(), requesting_user: None }).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) if user.status == UserStatus::Online => Some(user),
+        Ok(DirectoryMessage::QueryUserResponse { .. }) => {
+            println!("ℹ {} is offline. Storing image for delivery when they come online...", req.from_user);
+            None
+        }
+        Err(e) => {
+            eprintln!("⚠ Could not check if {} is online: {}", req.from_user, e);
+            None
+        }
+        _ => None,
+    };
+
+    if let Some(user) = requester {
+        println!("✓ {} is online at {}", req.from_user, user.p2p_address);
+        println!("🚀 Attempting to deliver image to {}...", req.from_user);
+
+        let sent_sha256 = sha256_hex(&encrypted_image);
+        let deliver_msg = P2PMessage::DeliverImage {
+            from_owner: owner.to_string(),
+            image_id: req.image_id.clone(),
+            requested_views: views,
+            encrypted_image: encrypted_image.clone(),
+            grant_seq: embedded_grant_seq(&encrypted_image, &req.from_user),
+            sha256: user.supports(CAP_CHECKSUMS).then(|| sent_sha256.clone()),
+        };
+
+        let response = send_p2p_message(&user.p2p_address, deliver_msg).await;
+        audit_delivery(&req.from_user, &req.image_id, views, &response);
+        match response {
+            Ok(P2PMessage::DeliverImageResponse { success: true, message, sha256, size_bytes }) => {
+                println!("\n✅ Image delivered successfully to {}!", req.from_user);
+                println!("   {}", message);
+                print_delivery_checksum(&sent_sha256, sha256.as_deref(), size_bytes);
+                return Ok(());
+            }
+            Ok(P2PMessage::StorageFull { message, .. }) => {
+                eprintln!("\n⚠ {} is low on disk space: {}", req.from_user, message);
+            }
+            Ok(P2PMessage::DeliverImageResponse { success: false, message, .. }) => {
+                eprintln!("\n⚠ Failed to deliver image: {}", message);
+            }
+            Err(e) => {
+                eprintln!("\n⚠ Could not deliver image to {} (connection failed: {})", req.from_user, e);
+            }
+            _ => {
+                eprintln!("\n⚠ Unexpected response when delivering image");
+            }
+        }
+        println!("📝 Storing image for delivery when {} can take it...", req.from_user);
+    }
+
+    if !store_pending_update_with_image(directory_addr, owner, &req.from_user, &req.image_id, views, encrypted_image, None).await {
+        bail!("{} couldn't be reached and the image couldn't be stored for them", req.from_user);
+    }
+    Ok(())
+}
+
+/// Tell the directory an accepted request's image was delivered or stored, so it isn't retried
+async fn mark_request_delivered(username: &str, request_id: &str, directory_addr: Option<&str>) {
+    let key = match registration_key(username) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("⚠ Failed to mark request {} delivered: {}", request_id, e);
+            return;
+        }
+    };
+    let msg = DirectoryMessage::MarkRequestDelivered {
+        request_id: request_id.to_string(),
+        username: username.to_string(),
+        key: Some(key),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success: true, .. }) => {}
+        Ok(DirectoryMessage::UpdateResponse { message, .. }) => eprintln!("⚠ {}", message),
+        Ok(_) => eprintln!("⚠ Unexpected response when marking request {} delivered", request_id),
+        Err(e) => eprintln!("⚠ Could not mark request {} delivered: {}", request_id, e),
+    }
+}
+
+/// Deliver the images owed for requests we accepted whose delivery never went through
+async fn retry_undelivered_grants(owner: &str, directory_addr: Option<&str>) {
+    let key = match registration_key(owner) {
+        Ok(key) => key,
+        Err(e) => {
+            eprintln!("⚠ Failed to fetch undelivered grants: {}", e);
+            return;
+        }
+    };
+    let msg = DirectoryMessage::GetUndeliveredGrants { owner: owner.to_string(), key: Some(key) };
+    let requests = match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetPendingRequestsResponse { requests }) => requests,
+        Ok(_) => return,
+        Err(e) => {
+            eprintln!("⚠ Failed to fetch undelivered grants: {}", e);
+            return;
+        }
+    };
+
+    for req in requests {
+        println!("\n🔁 Retrying delivery of {} to {} (request {})", req.image_id, req.from_user, req.request_id);
+        match deliver_accepted_request(owner, &req, directory_addr).await {
+            Ok(()) => mark_request_delivered(owner, &req.request_id, directory_addr).await,
+            Err(e) => eprintln!("⚠ Delivery of {} to {} failed again: {}", req.image_id, req.from_user, e),
+        }
+    }
+}
+
 async fn handle_check_notifications(
     username: &str,
     directory_addr: Option<&str>,
@@ -1854,18 +3819,15 @@ async fn handle_check_notifications(
                     println!("   Image: {}", notif.image_id);
                     println!("   Requested views: {}", notif.requested_views);
                     println!("   Status: {:?}", notif.status);
-
-                    if let Ok(duration) = notif.timestamp.elapsed() {
-                        let secs = duration.as_secs();
-                        if secs < 60 {
-                            println!("   Time: {} seconds ago", secs);
-                        } else if secs < 3600 {
-                            println!("   Time: {} minutes ago", secs / 60);
-                        } else {
-                            println!("   Time: {} hours ago", secs / 3600);
-                        }
+                    if let Some(delegate) = &notif.approved_by {
+                        println!("   Answered by: {} (for {})", delegate, notif.to_user);
+                    }
+                    if let Some(note) = &notif.response_note {
+                        println!("   Note: {}", note);
                     }
 
+                    println!("   Time: {}", time_ago(notif.timestamp));
+
                     if notif.status == cloud_p2p_project::directory_service::RequestStatus::Accepted {
                         println!("\n   💡 Your request was accepted! You can now request the image:");
                         println!("   cargo run --bin client -- request-image --username {} --peer {} --image-id {} --views {}",
@@ -1887,127 +3849,634 @@ async fn handle_check_notifications(
     }
 }
 
-async fn handle_remote_update_permissions(
-    owner: &str,
-    target_user: &str,
-    image_id: &str,
-    new_quota: u32,
+async fn handle_cancel_request(
+    username: &str,
+    request_id: &str,
     directory_addr: Option<&str>,
 ) -> Result<()> {
-    println!("=== Remote Permission Update ===");
-    println!("Owner: {}", owner);
-    println!("Target user: {}", target_user);
-    println!("Image ID: {}", image_id);
-    println!("New quota: {} views", new_quota);
+    println!("=== Cancelling Request ===");
+    println!("Username: {}", username);
+    println!("Request ID: {}", request_id);
 
-    // First, verify the owner is online and P2P server is actually running
-    println!("\n🔍 Verifying you are online...");
-    let self_query = DirectoryMessage::QueryUser {
-        username: owner.to_string(),
+    let msg = DirectoryMessage::CancelRequest {
+        request_id: request_id.to_string(),
+        from_user: username.to_string(),
+        key: Some(registration_key(username)?),
     };
 
-    let owner_p2p_addr = match send_directory_or_multicast(directory_addr, self_query).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry) }) => {
-            use cloud_p2p_project::directory_service::UserStatus;
-            if user_entry.status != UserStatus::Online {
-                bail!(
-                    "❌ You must be online to send remote permission updates!\n\
-                    \n\
-                    Start your P2P server first:\n\
-                      cargo run --bin client -- start-peer --username {} --port <PORT>",
-                    owner
-                );
-            }
-            user_entry.p2p_address
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::CancelRequestResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
+            Ok(())
         }
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-            bail!("❌ User '{}' not found in directory", owner);
+        Ok(DirectoryMessage::CancelRequestResponse { success: false, message }) => {
+            bail!("❌ {}", message);
         }
         Err(e) => {
-            bail!("Failed to verify online status: {}", e);
+            bail!("Error contacting directory service: {}", e);
         }
         _ => {
             bail!("Unexpected response from directory service");
         }
-    };
+    }
+}
 
-    // Actually verify the P2P server is reachable by sending a list images request
-    use cloud_p2p_project::p2p_protocol::{list_peer_images};
-    use tokio::time::{timeout, Duration};
+async fn handle_reminders(username: &str, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Overdue Requests ===");
+    println!("Owner: {}", username);
 
-    match timeout(Duration::from_secs(2), list_peer_images(&owner_p2p_addr, owner)).await {
-        Ok(Ok(_)) => {
-            println!("✓ Your P2P server is running\n");
-        }
-        Ok(Err(e)) => {
-            bail!(
-                "❌ Your P2P server is not reachable at {}!\n\
-                \n\
-                Error: {}\n\
-                \n\
-                Start your P2P server first:\n\
-                  cargo run --bin client -- start-peer --username {} --port <PORT>",
-                owner_p2p_addr, e, owner
-            );
+    let msg = DirectoryMessage::GetReminders { owner: username.to_string() };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetRemindersResponse { reminders }) => {
+            if reminders.is_empty() {
+                println!("\nNo requests are overdue.");
+                return Ok(());
+            }
+            println!("\n{} request(s) waiting on you:\n", reminders.len());
+            for reminder in &reminders {
+                println!("⏰ {} wants {} view(s) of {}", reminder.from_user, reminder.requested_views, reminder.image_id);
+                println!("   Request ID: {}", reminder.request_id);
+                println!("   Waiting since: {}", time_ago(reminder.requested_at));
+                println!();
+            }
+            println!("Answer with respond-request, or snooze with snooze-reminder --request-id <ID> [--hours N | --dismiss]");
+            Ok(())
         }
-        Err(_) => {
-            bail!(
-                "❌ Connection timeout: Your P2P server is not responding at {}!\n\
-                \n\
-                Start your P2P server first:\n\
-                  cargo run --bin client -- start-peer --username {} --port <PORT>",
-                owner_p2p_addr, owner
-            );
+        Err(e) => bail!("Error contacting directory service: {}", e),
+        _ => bail!("Unexpected response from directory service"),
+    }
+}
+
+async fn handle_snooze_reminder(
+    username: &str,
+    request_id: &str,
+    snooze: Option<Duration>,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    let msg = DirectoryMessage::SnoozeReminder {
+        owner: username.to_string(),
+        request_id: request_id.to_string(),
+        snooze_secs: snooze.map(|snooze| snooze.as_secs()),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::UpdateResponse { success: true, message }) => {
+            println!("✓ {}", message);
+            Ok(())
         }
+        Ok(DirectoryMessage::UpdateResponse { success: false, message }) => bail!("❌ {}", message),
+        Err(e) => bail!("Error contacting directory service: {}", e),
+        _ => bail!("Unexpected response from directory service"),
     }
+}
 
-    // Query the directory service for the target user
-    println!("🔍 Looking up target user '{}'...", target_user);
-    let query_msg = DirectoryMessage::QueryUser {
-        username: target_user.to_string(),
+async fn handle_doctor(port: u16, directory_addr: Option<&str>, json: bool) -> Result<()> {
+    let directory_servers: Vec<String> = match directory_addr {
+        Some(addr) => vec![addr.to_string()],
+        None => directory_servers().to_vec(),
     };
+    let encryption_servers = load_servers().unwrap_or_default();
+    set_encryption_stats_path(PathBuf::from(ENCRYPTION_STATS_FILE));
 
-    let target_user_info = match send_directory_or_multicast(directory_addr, query_msg).await {
-        Ok(DirectoryMessage::QueryUserResponse { user: Some(user) }) => user,
-        Ok(DirectoryMessage::QueryUserResponse { user: None }) => {
-            bail!("❌ User '{}' not found in directory", target_user);
-        }
-        Err(e) => {
-            bail!("Failed to query directory: {}", e);
-        }
-        _ => {
-            bail!("Unexpected response from directory service");
-        }
+    if !json {
+        println!("=== Connection Doctor ===");
+        println!("Checking P2P port {}, {} directory server(s), {} encryption server(s)...",
+                 port, directory_servers.len(), encryption_servers.len());
+    }
+
+    let report = run_diagnostics(port, &directory_servers, &encryption_servers).await;
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    let print_check = |check: &DiagnosticCheck| {
+        let latency = check.latency_ms.map(|ms| format!(" ({} ms)", ms)).unwrap_or_default();
+        println!("  {} {:<18} {:<22} {}{}",
+                 if check.ok { "✓" } else { "✗" }, check.name, check.target, check.detail, latency);
     };
 
-    use cloud_p2p_project::directory_service::UserStatus;
+    println!("\n🌐 Network");
+    print_check(&report.local_ip);
+    print_check(&report.port_bind);
+    print_check(&report.reachability);
 
-    // Check if user is offline or unreachable - if so, queue the update
-    let is_offline = target_user_info.status == UserStatus::Offline;
-    let is_unreachable = if !is_offline {
-        // Try to verify P2P server is actually reachable
-        match timeout(Duration::from_secs(2), list_peer_images(&target_user_info.p2p_address, target_user)).await {
-            Ok(Ok(_)) => false,
-            _ => true,
+    println!("\n📒 Directory servers");
+    report.directory_servers.iter().for_each(print_check);
+
+    println!("\n🔐 Encryption servers");
+    if report.encryption_servers.is_empty() {
+        println!("  ✗ No servers listed in '{}'", SERVER_CONFIG_FILE);
+    }
+    report.encryption_servers.iter().for_each(print_check);
+
+    let stats = &report.encryption_stats;
+    println!("\n⏱  Encryption jobs (recorded in '{}')", ENCRYPTION_STATS_FILE);
+    println!("  {}", stats.summary());
+    if stats.jobs > stats.failed {
+        for (phase, timing) in [
+            ("connect", &stats.connect),
+            ("upload", &stats.upload),
+            ("processing", &stats.processing),
+            ("download", &stats.download),
+            ("total", &stats.total),
+        ] {
+            println!("  {:<12} mean {:>6} ms  p50 {:>6} ms  p95 {:>6} ms  max {:>6} ms",
+                     phase, timing.mean_ms, timing.p50_ms, timing.p95_ms, timing.max_ms);
+        }
+        println!("  {:<12} {} KB up, {} KB down per job", "size", stats.mean_request_bytes / 1024, stats.mean_response_bytes / 1024);
+        for server in &stats.servers {
+            println!("  {:<22} {} job(s), {} failed, mean {} ms ({} ms processing)",
+                     server.server, server.jobs, server.failed, server.mean_total_ms, server.mean_processing_ms);
         }
+    }
+
+    let failed = report.checks().filter(|c| !c.ok).count();
+    if report.all_ok() && !report.encryption_servers.is_empty() {
+        println!("\n✓ All checks passed");
     } else {
-        true
+        println!("\n⚠ {} check(s) failed - rerun with --json and attach the output when asking for help", failed);
+    }
+
+    Ok(())
+}
+
+async fn handle_refresh_directories(file: Option<PathBuf>) -> Result<()> {
+    let path = file.unwrap_or_else(DirectoryBootstrap::default_path);
+    let mut bootstrap = if path.exists() {
+        DirectoryBootstrap::load(&path)?
+    } else {
+        println!("'{}' not found, starting from the built-in servers", path.display());
+        DirectoryBootstrap::default()
     };
 
-    if is_offline || is_unreachable {
-        // User is offline or unreachable - store pending update with the embedded image
-        println!("⚠  Target user '{}' is currently offline or unreachable.", target_user);
-        println!("📝 Fetching image to queue with permission update...");
+    println!("=== Refreshing Directory Servers ===");
+    println!("Checking {} server(s)...", bootstrap.servers.len());
+    let report = bootstrap.refresh().await;
+    bootstrap.save(&path)?;
 
-        // First, fetch the image from our own P2P server with the updated permissions
-        use cloud_p2p_project::p2p_protocol::request_image_from_peer;
-        
-        let embedded_image = match request_image_from_peer(
-            &owner_p2p_addr,
-            owner,  // Request as owner
-            image_id,
-            new_quota,
-        ).await {
+    for server in &bootstrap.servers {
+        let latency = server.latency_ms.map(|ms| format!(" ({} ms)", ms)).unwrap_or_default();
+        let learned = if server.learned { " [learned]" } else { "" };
+        if server.is_healthy() {
+            println!("  ✓ {}{}{}", server.address, latency, learned);
+        } else {
+            println!("  ✗ {} - {} failed check(s){}", server.address, server.failures, learned);
+        }
+    }
+    if !report.learned.is_empty() {
+        println!("\n🆕 Learned {} replica(s): {}", report.learned.len(), report.learned.join(", "));
+    }
+    println!("\n{}/{} healthy, saved to '{}'", report.healthy, report.total, path.display());
+
+    Ok(())
+}
+
+fn handle_bandwidth(days: usize) {
+    set_bandwidth_stats_path(PathBuf::from(BANDWIDTH_STATS_FILE));
+    let stats = bandwidth_stats();
+
+    println!("=== P2P Bandwidth ===");
+    if stats.days.is_empty() {
+        println!("No P2P traffic recorded in '{}'", BANDWIDTH_STATS_FILE);
+        return;
+    }
+
+    let to_mb = |bytes: u64| bytes as f64 / 1_048_576.0;
+    for day in stats.days.iter().rev().take(days) {
+        println!("\n📅 {}  ↑ {:.2} MB  ↓ {:.2} MB", day.date, to_mb(day.bytes_sent), to_mb(day.bytes_received));
+        let mut peers: Vec<_> = day.peers.iter().collect();
+        peers.sort_by_key(|(_, usage)| std::cmp::Reverse(usage.bytes_sent + usage.bytes_received));
+        for (peer, usage) in peers {
+            println!("   {:<40} ↑ {:.2} MB  ↓ {:.2} MB", peer, to_mb(usage.bytes_sent), to_mb(usage.bytes_received));
+        }
+    }
+}
+
+fn handle_migrate_image(path: &Path) -> Result<()> {
+    println!("=== Migrating Protected Images ===");
+
+    let files: Vec<PathBuf> = if path.is_dir() {
+        fs::read_dir(path)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|file| file.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("png")))
+            .collect()
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    let (mut migrated, mut current) = (0, 0);
+    for file in &files {
+        match migrate_carrier(file) {
+            Ok(Some(version)) => {
+                println!("⬆ {} (v{} → v{})", file.display(), version, PAYLOAD_VERSION);
+                migrated += 1;
+            }
+            Ok(None) => current += 1,
+            Err(e) => println!("⚠ {}: {}", file.display(), e),
+        }
+    }
+    println!("
+{} migrated, {} already current, {} skipped", migrated, current, files.len() - migrated - current);
+    Ok(())
+}
+
+fn handle_set_holder(input: &Path, owner: &str, holder: &str, enabled: bool) -> Result<()> {
+    let changed = set_image_holder(input, owner, holder, enabled)?;
+    match (changed, enabled) {
+        (true, true) => println!("✓ {} may now keep {} without viewing it", holder, input.display()),
+        (true, false) => println!("✓ {} is no longer a holder of {}", holder, input.display()),
+        (false, true) => println!("{} is already a holder of {}", holder, input.display()),
+        (false, false) => println!("{} was not a holder of {}", holder, input.display()),
+    }
+    if changed {
+        println!("Their copy picks this up the next time you deliver the image to them.");
+    }
+    Ok(())
+}
+
+fn handle_set_view_policy(input: &Path, owner: &str, policy: ViewPolicy) -> Result<()> {
+    if !set_view_policy(input, owner, policy)? {
+        println!("{} already has this policy", input.display());
+        return Ok(());
+    }
+    println!("✓ Viewer policy for {} updated", input.display());
+    println!("  Save: {}", if policy.allow_save { "allowed" } else { "blocked" });
+    println!("  Print: {}", if policy.allow_print { "allowed" } else { "blocked" });
+    println!("  Watermark: {}", if policy.watermark { "required" } else { "off" });
+    println!("Copies you've already delivered keep their old policy until you deliver the image again.");
+    Ok(())
+}
+
+fn handle_permission_history(input: &Path, user: Option<&str>) -> Result<()> {
+    let data = fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let permissions = embedded_permissions(&data).context("No hidden metadata found in image")?;
+
+    println!("=== Permission History: {} ===", input.display());
+    println!("Owner: {}", permissions.owner);
+    let changes: Vec<_> = permissions.history.iter()
+        .filter(|change| user.is_none_or(|user| change.user == user))
+        .collect();
+    if changes.is_empty() {
+        println!("No quota changes recorded (images encrypted before v5 start with an empty history)");
+        return Ok(());
+    }
+    for change in changes {
+        let before = change.before.map(|views| views.to_string()).unwrap_or_else(|| "-".to_string());
+        println!(
+            "  {:<14} {} {} {}: {} → {}",
+            time_ago(change.at),
+            change.changed_by,
+            if change.is_revocation() { "revoked" } else { "set" },
+            change.user,
+            before,
+            change.after,
+        );
+    }
+    println!("Only the latest {} changes are kept in the image.", PERMISSION_HISTORY_LIMIT);
+    Ok(())
+}
+
+fn handle_recover(input: &Path, into: Option<&Path>, output: Option<&Path>, image_id: Option<&str>, json: bool) -> Result<()> {
+    let data = fs::read(input).with_context(|| format!("Failed to read {}", input.display()))?;
+    let report = recover_carrier(&data)?;
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        println!("=== Recovering: {} ===", input.display());
+        println!("{}", report.summary());
+        if let Some(permissions) = &report.permissions {
+            println!("Owner: {}", permissions.owner);
+            let mut quotas: Vec<_> = permissions.quotas.iter().collect();
+            quotas.sort();
+            for (user, views) in quotas {
+                println!("  {:<20} {} view(s) left (grant #{})", user, views, permissions.grant_seq(user));
+            }
+            for holder in &permissions.holders {
+                println!("  {:<20} holder", holder);
+            }
+        }
+        if let Some(id) = report.payload.as_ref().and_then(|payload| payload.image_id.as_deref()) {
+            println!("Image ID: {}", id);
+        }
+    }
+
+    let Some(into) = into else {
+        return Ok(());
+    };
+    let fresh = fs::read(into).with_context(|| format!("Failed to read {}", into.display()))?;
+    let repaired = reembed(report, &fresh, image_id)?;
+    let output = output.map(Path::to_path_buf).unwrap_or_else(|| {
+        let name = input.file_name().unwrap_or_default().to_string_lossy();
+        input.with_file_name(format!("recovered_{}", name))
+    });
+    fs::write(&output, repaired).with_context(|| format!("Failed to write {}", output.display()))?;
+    if !json {
+        println!("✓ Repaired carrier saved to {}", output.display());
+    }
+    Ok(())
+}
+
+fn handle_export_audit_log(log_path: &Path, output: &Path) -> Result<()> {
+    println!("=== Exporting Audit Log ===");
+    println!("Log: {}", log_path.display());
+
+    let verification = export_audit_log(log_path, output)?;
+    println!("📜 {} entries exported to {}", verification.entries, output.display());
+    println!("🔗 Head hash: {}", verification.head_hash);
+
+    if !verification.intact {
+        bail!(
+            "❌ Audit log has been tampered with at line {}: {} (later entries left out of the export)",
+            verification.first_bad_line.unwrap_or_default(),
+            verification.problem.unwrap_or_default()
+        );
+    }
+    println!("✅ Hash chain intact");
+
+    Ok(())
+}
+
+/// An admin command in our namespace, authorized with the replica secret
+fn admin_message(message: DirectoryMessage) -> Result<DirectoryMessage> {
+    let Some(secret) = std::env::var(REPLICA_SECRET_ENV).ok().filter(|s| !s.is_empty()) else {
+        bail!("❌ Set {} to the directory replica secret to run admin commands", REPLICA_SECRET_ENV);
+    };
+    // Admin commands apply within our namespace, like everything else we send
+    Ok(with_token(with_namespace(message, client_namespace().as_deref()), Some(&secret)))
+}
+
+async fn handle_rename_user(from: &str, to: &str, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Renaming User ===");
+    println!("From: {}", from);
+    println!("To: {}", to);
+
+    let msg = admin_message(DirectoryMessage::RenameUser {
+        from: from.to_string(),
+        to: to.to_string(),
+        replicated: false,
+    })?;
+
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::RenameUserResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
+            Ok(())
+        }
+        Ok(DirectoryMessage::RenameUserResponse { success: false, message }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+async fn handle_archived_users(purge: &[String], purge_all: bool, directory_addr: Option<&str>) -> Result<()> {
+    if purge.is_empty() && !purge_all {
+        let msg = admin_message(DirectoryMessage::ListArchivedUsers {})?;
+        let users = match send_directory_or_multicast(directory_addr, msg).await {
+            Ok(DirectoryMessage::ListArchivedUsersResponse { users }) => users,
+            Ok(DirectoryMessage::AuthError { message }) => bail!("❌ {}", message),
+            Ok(_) => bail!("Unexpected response from directory service (is it running an older version?)"),
+            Err(e) => bail!("Error contacting directory service: {}", e),
+        };
+        println!("=== Archived Users ({}) ===", users.len());
+        for user in users {
+            println!(
+                "  {} - last seen {}, archived {}, {} shared image(s)",
+                user.entry.username,
+                time_ago(user.entry.last_heartbeat),
+                time_ago(user.archived_at),
+                user.entry.shared_images.len()
+            );
+        }
+        return Ok(());
+    }
+
+    println!("=== Purging Archived Users ===");
+    println!("Users: {}", if purge_all { "all".to_string() } else { purge.join(", ") });
+    let msg = admin_message(DirectoryMessage::PurgeArchivedUsers {
+        usernames: purge.to_vec(),
+        replicated: false,
+    })?;
+
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::PurgeArchivedUsersResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
+            Ok(())
+        }
+        Ok(DirectoryMessage::PurgeArchivedUsersResponse { success: false, message })
+        | Ok(DirectoryMessage::AuthError { message }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+async fn handle_server_info(directory_addr: Option<&str>, json: bool) -> Result<()> {
+    let info = match send_directory_or_multicast(directory_addr, DirectoryMessage::GetServerInfo {}).await {
+        Ok(DirectoryMessage::GetServerInfoResponse { info }) => info,
+        Ok(_) => bail!("Unexpected response from directory service (is it running an older version?)"),
+        Err(e) => bail!("Error contacting directory service: {}", e),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&info)?);
+        return Ok(());
+    }
+
+    println!("=== Directory Server Info ===");
+    println!("Server ID:        {}", info.server_id);
+    println!("Version:          {}", info.version);
+    println!("Uptime:           {}h {:02}m", info.uptime_secs / 3600, info.uptime_secs / 60 % 60);
+    println!("State file:       {}", info.state_file.display());
+    println!("Users:            {} ({} online)", info.users, info.online_users);
+    println!("Pending requests: {}", info.pending_requests);
+    println!("Pending updates:  {}", info.pending_updates);
+    println!("Trades:           {}", info.trades);
+    if info.peers.is_empty() {
+        println!("Peers:            none (standalone)");
+    } else {
+        println!("Peers:");
+        for peer in &info.peers {
+            println!("  {}{}", peer.address, if peer.departed { " (shut down)" } else { "" });
+        }
+    }
+
+    Ok(())
+}
+
+/// Print the quotas a permission update would leave, from the carrier we share in the current directory
+fn handle_preview_permissions(owner: &str, target_user: &str, image_id: &str, new_quota: u32) -> Result<()> {
+    println!("=== Permission Update Preview ===");
+    let image_id = unqualify_image_id(owner, image_id)?;
+
+    let carrier_path = fs::read_dir(std::env::current_dir()?)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .find(|path| {
+            path.file_name().is_some_and(|name| name == image_id) || shared_image_id(path) == image_id
+        })
+        .with_context(|| format!("Image {} not found in the current directory", image_id))?;
+
+    let preview = preview_quota_change(&fs::read(&carrier_path)?, target_user, new_quota)?;
+    if preview.owner != owner {
+        bail!("❌ You are not the owner of this image. Owner is: {}", preview.owner);
+    }
+
+    println!("Image: {}", carrier_path.display());
+    if preview.changes.is_empty() {
+        println!("\nNo change: {} already has {} views", target_user, new_quota);
+    }
+    for change in &preview.changes {
+        let before = change.before.map_or("no access".to_string(), |views| format!("{} views", views));
+        let after = change.after.map_or("no access".to_string(), |views| format!("{} views", views));
+        println!("\n  {}: {} -> {}", change.user, before, after);
+    }
+    println!("\nQuotas after the update:");
+    for (user, views) in &preview.quotas {
+        println!("  {}: {} views", user, views);
+    }
+    println!("\n(preview only - nothing was changed or sent)");
+    Ok(())
+}
+
+async fn handle_remote_update_permissions(
+    owner: &str,
+    target_user: &str,
+    image_id: &str,
+    new_quota: u32,
+    queue_ttl: Option<Duration>,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== Remote Permission Update ===");
+    println!("Owner: {}", owner);
+    println!("Target user: {}", target_user);
+    println!("Image ID: {}", image_id);
+    println!("New quota: {} views", new_quota);
+
+    // First, verify the owner is online and P2P server is actually running
+    println!("\n🔍 Verifying you are online...");
+    let self_query = DirectoryMessage::QueryUser {
+        username: owner.to_string(),
+        requesting_user: None,
+    };
+
+    let owner_p2p_addr = match send_directory_or_multicast(directory_addr, self_query).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user_entry), .. }) => {
+            use cloud_p2p_project::directory_service::UserStatus;
+            if user_entry.status != UserStatus::Online {
+                bail!(
+                    "❌ You must be online to send remote permission updates!\n\
+                    \n\
+                    Start your P2P server first:\n\
+                      cargo run --bin client -- start-peer --username {} --port <PORT>",
+                    owner
+                );
+            }
+            user_entry.p2p_address
+        }
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!("❌ User '{}' not found in directory", owner);
+        }
+        Err(e) => {
+            bail!("Failed to verify online status: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    };
+
+    // Actually verify the P2P server is reachable by sending a list images request
+    use cloud_p2p_project::p2p_protocol::{list_peer_images};
+    use tokio::time::{timeout, Duration};
+
+    match timeout(Duration::from_secs(2), list_peer_images(&owner_p2p_addr, owner)).await {
+        Ok(Ok(_)) => {
+            println!("✓ Your P2P server is running\n");
+        }
+        Ok(Err(e)) => {
+            bail!(
+                "❌ Your P2P server is not reachable at {}!\n\
+                \n\
+                Error: {}\n\
+                \n\
+                Start your P2P server first:\n\
+                  cargo run --bin client -- start-peer --username {} --port <PORT>",
+                owner_p2p_addr, e, owner
+            );
+        }
+        Err(_) => {
+            bail!(
+                "❌ Connection timeout: Your P2P server is not responding at {}!\n\
+                \n\
+                Start your P2P server first:\n\
+                  cargo run --bin client -- start-peer --username {} --port <PORT>",
+                owner_p2p_addr, owner
+            );
+        }
+    }
+
+    // Query the directory service for the target user
+    println!("🔍 Looking up target user '{}'...", target_user);
+    let query_msg = DirectoryMessage::QueryUser {
+        username: target_user.to_string(),
+        requesting_user: None,
+    };
+
+    let target_user_info = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) => user,
+        Ok(DirectoryMessage::QueryUserResponse { user: None, .. }) => {
+            bail!("❌ User '{}' not found in directory", target_user);
+        }
+        Err(e) => {
+            bail!("Failed to query directory: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    };
+
+    use cloud_p2p_project::directory_service::UserStatus;
+
+    // Check if user is offline or unreachable - if so, queue the update
+    let is_offline = target_user_info.status == UserStatus::Offline;
+    let is_unreachable = if !is_offline {
+        // Try to verify P2P server is actually reachable (pings skip any queued transfers)
+        let reachable = if target_user_info.supports(CAP_PRIORITY_LANES) {
+            timeout(Duration::from_secs(2), ping_peer(&target_user_info.p2p_address, owner)).await
+                .is_ok_and(|result| result.is_ok())
+        } else {
+            timeout(Duration::from_secs(2), list_peer_images(&target_user_info.p2p_address, target_user)).await
+                .is_ok_and(|result| result.is_ok())
+        };
+        !reachable
+    } else {
+        true
+    };
+
+    if is_offline || is_unreachable {
+        // User is offline or unreachable - store pending update with the embedded image
+        println!("⚠  Target user '{}' is currently offline or unreachable.", target_user);
+        println!("📝 Fetching image to queue with permission update...");
+
+        // First, fetch the image from our own P2P server with the updated permissions
+        use cloud_p2p_project::p2p_protocol::request_image_from_peer;
+        
+        let embedded_image = match request_image_from_peer(
+            &owner_p2p_addr,
+            owner,  // Request as owner
+            image_id,
+            new_quota,
+        ).await {
             Ok(image_data) => {
                 println!("✓ Image fetched successfully");
                 Some(image_data)
@@ -2025,6 +4494,8 @@ async fn handle_remote_update_permissions(
             image_id: image_id.to_string(),
             new_quota,
             embedded_image,
+            wipe: false,
+            ttl_secs: queue_ttl.map(|ttl| ttl.as_secs()),
         };
 
         match send_directory_or_multicast(directory_addr, pending_msg).await {
@@ -2032,7 +4503,7 @@ async fn handle_remote_update_permissions(
                 println!("\n✅ Permission update queued successfully!");
                 println!("   {}", message);
                 println!("\n   When '{}' comes online:", target_user);
-                println!("   • The image will be delivered as from_{}_{}.png", owner, target_user);
+                println!("   • The image will be delivered to their received images");
                 if new_quota == 0 {
                     println!("   • Their access will be revoked (0 views)");
                 } else {
@@ -2068,7 +4539,14 @@ async fn handle_remote_update_permissions(
     };
 
     // NOTE: use the p2p address from the fetched target_user_info (was using undefined `target_p2p_addr`)
-    match send_p2p_message(&target_user_info.p2p_address, update_msg).await {
+    let response = send_p2p_message(&target_user_info.p2p_address, update_msg).await;
+    if let Ok(P2PMessage::RemoteUpdatePermissionsResponse { success, .. }) = &response {
+        record_delivery(target_user, *success);
+        if new_quota == 0 {
+            record_revocation(target_user, *success);
+        }
+    }
+    match response {
         Ok(P2PMessage::RemoteUpdatePermissionsResponse { success: true, message }) => {
             println!("\n✅ Permission update successful!");
             println!("   {}", message);
@@ -2084,6 +4562,15 @@ async fn handle_remote_update_permissions(
         Ok(P2PMessage::RemoteUpdatePermissionsResponse { success: false, message }) => {
             bail!("❌ Permission update failed: {}", message);
         }
+        Ok(P2PMessage::StorageFull { message, .. }) => {
+            eprintln!("\n⚠ '{}' is low on disk space: {}", target_user, message);
+            println!("📝 Fetching image to queue with permission update...");
+            let image = cloud_p2p_project::p2p_protocol::request_image_from_peer(&owner_p2p_addr, owner, image_id, new_quota)
+                .await
+                .context("Could not fetch image to queue")?;
+            store_pending_update_with_image(directory_addr, owner, target_user, image_id, new_quota, image, queue_ttl).await;
+            Ok(())
+        }
         Err(e) => {
             bail!("Failed to send update request: {}", e);
         }
@@ -2091,4 +4578,977 @@ async fn handle_remote_update_permissions(
             bail!("Unexpected response from target user");
         }
     }
-}
\ No newline at end of file
+}
+
+async fn handle_remote_wipe(owner: &str, target_user: &str, image_id: &str, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Remote Wipe ===");
+    println!("Owner: {}", owner);
+    println!("Target user: {}", target_user);
+    println!("Image ID: {}", image_id);
+    let image_id = unqualify_image_id(owner, image_id)?;
+
+    let query_msg = DirectoryMessage::QueryUser {
+        username: target_user.to_string(),
+        requesting_user: None,
+    };
+    let target = match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user, .. }) => user,
+        Ok(_) => bail!("Unexpected response from directory service"),
+        Err(e) => bail!("Failed to query directory service: {}", e),
+    };
+
+    use cloud_p2p_project::directory_service::UserStatus;
+    let reason = match target {
+        Some(user) if user.status == UserStatus::Online && user.supports(CAP_REMOTE_WIPE) => {
+            println!("\n📤 Sending wipe request to {} at {}...", target_user, user.p2p_address);
+            let result = send_remote_wipe(&user.p2p_address, owner, image_id).await;
+            record_revocation(target_user, result.is_ok());
+            match result {
+                Ok(message) => {
+                    audit(Some(Path::new(AUDIT_LOG_FILE)), AuditRecord {
+                        action: AuditAction::Wipe,
+                        recipient: target_user.to_string(),
+                        image_id: image_id.to_string(),
+                        views: None,
+                        success: true,
+                        message: message.clone(),
+                        approved_by: None,
+                    });
+                    println!("\n✅ {} confirmed: {}", target_user, message);
+                    return Ok(());
+                }
+                Err(e) => e.to_string(),
+            }
+        }
+        Some(user) if user.status == UserStatus::Online => format!("{}'s client doesn't support remote wipe yet", target_user),
+        Some(_) => format!("{} is offline", target_user),
+        None => format!("{} not found", target_user),
+    };
+
+    println!("\nℹ Could not wipe now ({}), queueing it for {}'s next login...", reason, target_user);
+    let pending_msg = DirectoryMessage::StorePendingPermissionUpdate {
+        from_owner: owner.to_string(),
+        target_user: target_user.to_string(),
+        image_id: image_id.to_string(),
+        new_quota: 0,
+        embedded_image: None,
+        wipe: true,
+        ttl_secs: None,
+    };
+    match send_directory_or_multicast(directory_addr, pending_msg).await {
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, .. }) => {
+            println!("✅ Wipe queued - you'll get an acknowledgment when it's carried out (if you're online)");
+            Ok(())
+        }
+        Ok(DirectoryMessage::StorePendingPermissionUpdateResponse { message, .. }) => bail!("Failed to queue wipe: {}", message),
+        Ok(_) => bail!("Unexpected response from directory service"),
+        Err(e) => bail!("Failed to communicate with directory service: {}", e),
+    }
+}
+
+/// Carry out a wipe queued while we were offline, and acknowledge it to the owner
+async fn apply_queued_wipe(directory_addr: Option<&str>, username: &str, owner: &str, image_id: &str, received_dir: Option<&Path>) {
+    let (success, message) = match wipe_received_image(received_dir, owner, image_id) {
+        Ok(wiped) => (true, format!("Deleted {} local copies of '{}'", wiped, image_id)),
+        Err(e) => (false, format!("Failed to delete local copy: {}", e)),
+    };
+    println!("  🧹 {} asked for '{}' to be wiped: {}", owner, image_id, message);
+
+    let query_msg = DirectoryMessage::QueryUser {
+        username: owner.to_string(),
+        requesting_user: None,
+    };
+    use cloud_p2p_project::directory_service::UserStatus;
+    match send_directory_or_multicast(directory_addr, query_msg).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) if user.status == UserStatus::Online => {
+            if let Err(e) = send_wipe_ack(&user.p2p_address, username, image_id, success, &message).await {
+                eprintln!("    ⚠ Could not acknowledge the wipe to {}: {}", owner, e);
+            }
+        }
+        _ => println!("    ℹ {} is offline - the wipe could not be acknowledged", owner),
+    }
+}
+
+// =============================================================================
+// IMAGE TRADES
+// =============================================================================
+
+async fn handle_propose_trade(
+    username: &str,
+    peer: &str,
+    offer: (&str, u32),
+    want: (&str, u32),
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== Proposing Trade ===");
+    println!("You give: {} ({} views for {})", offer.0, offer.1, peer);
+    println!("You get:  {}'s {} ({} views)", peer, want.0, want.1);
+
+    let msg = DirectoryMessage::ProposeTrade {
+        from_user: username.to_string(),
+        to_user: peer.to_string(),
+        offered_image_id: offer.0.to_string(),
+        offered_views: offer.1,
+        requested_image_id: want.0.to_string(),
+        requested_views: want.1,
+    };
+
+    let trade_id = match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::ProposeTradeResponse { success: true, message, trade_id }) => {
+            println!("\n✓ {}", message);
+            println!("   Trade ID: {}", trade_id);
+            trade_id
+        }
+        Ok(DirectoryMessage::ProposeTradeResponse { success: false, message, .. }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    };
+
+    // Escrow our side up front so the trade completes as soon as the peer accepts
+    match find_trade(username, &trade_id, directory_addr).await {
+        Ok(trade) => {
+            if let Err(e) = deposit_trade_side(username, &trade, directory_addr).await {
+                eprintln!("\n⚠ Could not deposit your image yet: {}", e);
+                println!("💡 It will be deposited when you run check-trades while online");
+            }
+        }
+        Err(e) => eprintln!("\n⚠ {}", e),
+    }
+    Ok(())
+}
+
+async fn handle_check_trades(username: &str, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Trades for {} ===", username);
+
+    let trades = fetch_trades(username, directory_addr).await?;
+    if trades.is_empty() {
+        println!("✓ No trades");
+        return Ok(());
+    }
+
+    for (idx, trade) in trades.iter().enumerate() {
+        let Some((mine, theirs)) = trade.sides_for(username) else { continue };
+        println!("\n{}. Trade ID: {}", idx + 1, trade.trade_id);
+        println!("   With: {}", theirs.owner);
+        println!("   You give: {} ({} views){}", mine.image_id, mine.views,
+                 if mine.deposit_sha256.is_some() { " - deposited" } else { "" });
+        println!("   You get:  {} ({} views){}", theirs.image_id, theirs.views,
+                 if theirs.deposit_sha256.is_some() { " - deposited" } else { "" });
+        println!("   Status: {:?}", trade.status);
+    }
+
+    // Finish our side of any accepted trades we haven't deposited for yet
+    for trade in &trades {
+        let Some((mine, _)) = trade.sides_for(username) else { continue };
+        let owes_deposit = mine.deposit_sha256.is_none()
+            && (trade.status == TradeStatus::Accepted
+                || (trade.status == TradeStatus::Proposed && trade.proposer.owner == username));
+        if owes_deposit {
+            if let Err(e) = deposit_trade_side(username, trade, directory_addr).await {
+                eprintln!("\n⚠ Could not deposit for trade {}: {}", trade.trade_id, e);
+            }
+        }
+    }
+
+    let pending = trades.iter()
+        .filter(|t| t.status == TradeStatus::Proposed && t.counterparty.owner == username)
+        .count();
+    if pending > 0 {
+        println!("\n💡 To respond to a trade:");
+        println!("   cargo run --bin client -- respond-trade --username {} --trade-id <ID> --accept", username);
+    }
+    Ok(())
+}
+
+async fn handle_respond_trade(
+    username: &str,
+    trade_id: &str,
+    accept: bool,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    println!("=== Responding to Trade ===");
+    println!("Trade ID: {}", trade_id);
+    println!("Action: {}", if accept { "ACCEPT" } else { "REJECT" });
+
+    let msg = DirectoryMessage::RespondToTrade {
+        trade_id: trade_id.to_string(),
+        username: username.to_string(),
+        accept,
+        key: Some(registration_key(username)?),
+    };
+
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::RespondToTradeResponse { success: true, message, trade }) => {
+            println!("\n✓ {}", message);
+            if let Some(trade) = trade.filter(|_| accept) {
+                deposit_trade_side(username, &trade, directory_addr).await?;
+            }
+            Ok(())
+        }
+        Ok(DirectoryMessage::RespondToTradeResponse { success: false, message, .. }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+async fn handle_cancel_trade(username: &str, trade_id: &str, directory_addr: Option<&str>) -> Result<()> {
+    println!("=== Cancelling Trade ===");
+    println!("Trade ID: {}", trade_id);
+
+    let msg = DirectoryMessage::CancelTrade {
+        trade_id: trade_id.to_string(),
+        username: username.to_string(),
+        key: Some(registration_key(username)?),
+    };
+
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::CancelTradeResponse { success: true, message }) => {
+            println!("\n✓ {}", message);
+            Ok(())
+        }
+        Ok(DirectoryMessage::CancelTradeResponse { success: false, message }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+async fn fetch_trades(username: &str, directory_addr: Option<&str>) -> Result<Vec<TradeProposal>> {
+    let msg = DirectoryMessage::GetTrades {
+        username: username.to_string(),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::GetTradesResponse { trades }) => Ok(trades),
+        Err(e) => bail!("Error contacting directory service: {}", e),
+        _ => bail!("Unexpected response from directory service"),
+    }
+}
+
+async fn find_trade(username: &str, trade_id: &str, directory_addr: Option<&str>) -> Result<TradeProposal> {
+    fetch_trades(username, directory_addr).await?
+        .into_iter()
+        .find(|t| t.trade_id == trade_id)
+        .ok_or_else(|| anyhow::anyhow!("Trade {} not found", trade_id))
+}
+
+/// Escrow our side of a trade with the directory
+///
+/// Our own P2P server embeds the other party's views, the same way accepted requests are fulfilled.
+async fn deposit_trade_side(username: &str, trade: &TradeProposal, directory_addr: Option<&str>) -> Result<()> {
+    use cloud_p2p_project::directory_service::UserStatus;
+    use cloud_p2p_project::p2p_protocol::request_scaled_image_from_peer;
+
+    let Some((mine, theirs)) = trade.sides_for(username) else {
+        bail!("You are not part of trade {}", trade.trade_id);
+    };
+
+    let self_query = DirectoryMessage::QueryUser {
+        username: username.to_string(),
+        requesting_user: None,
+    };
+    let self_user = match send_directory_or_multicast(directory_addr, self_query).await {
+        Ok(DirectoryMessage::QueryUserResponse { user: Some(user), .. }) if user.status == UserStatus::Online => user,
+        _ => bail!(
+            "You must be online to deposit trade images.\n\
+            Start your P2P server first:\n  \
+            cargo run --bin client -- start-peer --username {} --port <PORT>",
+            username
+        ),
+    };
+
+    println!("\n📦 Depositing {} ({} views for {})...", mine.image_id, mine.views, theirs.owner);
+    // The accepted trade's ID vouches for the views we embed for them
+    let encrypted_image = request_scaled_image_from_peer(
+        &self_user.p2p_address,
+        &theirs.owner,
+        &mine.image_id,
+        mine.views,
+        None,
+        None,
+        Some(&trade.trade_id),
+    )
+    .await?;
+
+    let msg = DirectoryMessage::DepositTradeImage {
+        trade_id: trade.trade_id.clone(),
+        username: username.to_string(),
+        encrypted_image,
+        key: Some(registration_key(username)?),
+    };
+    match send_directory_or_multicast(directory_addr, msg).await {
+        Ok(DirectoryMessage::DepositTradeImageResponse { success: true, message, completed }) => {
+            println!("✓ {}", message);
+            if completed {
+                println!("🎉 Both images will be delivered when each of you next checks for permission updates");
+            }
+            Ok(())
+        }
+        Ok(DirectoryMessage::DepositTradeImageResponse { success: false, message, .. }) => {
+            bail!("❌ {}", message);
+        }
+        Err(e) => {
+            bail!("Error contacting directory service: {}", e);
+        }
+        _ => {
+            bail!("Unexpected response from directory service");
+        }
+    }
+}
+
+// =============================================================================
+// OWNER CONSOLE
+// =============================================================================
+
+fn print_owner_console_help() {
+    println!("Commands:");
+    println!("  requests                      List pending requests");
+    println!("  accept <#|id>                 Accept a request");
+    println!("  reject <#|id>                 Reject a request");
+    println!("  counter <#|id> <views>        Accept, granting a different number of views");
+    println!("  grants                        Show current grants per image");
+    println!("  set <image> <user> <views>    Update a user's views (local + remote copy)");
+    println!("  revoke <image> <user>         Revoke a user's access");
+    println!("  help                          Show this help");
+    println!("  quit                          Leave the console");
+}
+
+/// Print the quotas embedded in each of the owner's images in the current directory
+fn print_owner_grants(owner: &str) -> Result<()> {
+    let mut found = false;
+    let denial_stats = load_access_denial_stats(Path::new(ACCESS_DENIAL_STATS_FILE));
+
+    for entry in fs::read_dir(std::env::current_dir()?)?.flatten() {
+        let path = entry.path();
+        if !is_image_file(&path) {
+            continue;
+        }
+
+        let Ok(data) = fs::read(&path) else { continue };
+        let Ok(img) = image::load_from_memory(&data) else { continue };
+        let Ok(Some(payload)) = lsb::decode(&img) else { continue };
+        let Ok(combined) = CombinedPayload::from_bytes(&payload) else { continue };
+        if combined.permissions.owner != owner {
+            continue;
+        }
+
+        found = true;
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        println!("🖼  {}", file_name);
+        if combined.permissions.quotas.is_empty() {
+            println!("   (no grants)");
+        }
+        let mut quotas: Vec<_> = combined.permissions.quotas.iter().collect();
+        quotas.sort();
+        for (user, views) in quotas {
+            if *views == 0 {
+                println!("   • {}: revoked", user);
+            } else {
+                println!("   • {}: {} views", user, views);
+            }
+        }
+        if let Some(stats) = denial_stats.get(file_name.as_ref()) {
+            let mut viewers: Vec<_> = stats.by_viewer.iter().collect();
+            viewers.sort();
+            let viewers: Vec<String> = viewers.iter().map(|(user, n)| format!("{} x{}", user, n)).collect();
+            println!("   🛡  {} denied view attempt(s): {}", stats.total, viewers.join(", "));
+        }
+    }
+
+    if !found {
+        println!("✓ No images owned by {} in this directory", owner);
+    }
+    Ok(())
+}
+
+/// Fetch the owner's pending requests
+async fn fetch_pending_requests(
+    owner: &str,
+    directory_addr: Option<&str>,
+) -> Result<Vec<cloud_p2p_project::directory_service::PendingRequest>> {
+    let msg = DirectoryMessage::GetPendingRequests {
+        username: owner.to_string(),
+    };
+
+    match send_directory_or_multicast(directory_addr, msg).await? {
+        DirectoryMessage::GetPendingRequestsResponse { requests } => Ok(requests),
+        _ => bail!("Unexpected response from directory service"),
+    }
+}
+
+async fn handle_owner_console(owner: &str, directory_addr: Option<&str>) -> Result<()> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    println!("=== Owner Console ===");
+    println!("Owner: {}", owner);
+    println!("Run this from the directory your peer shares images from.\n");
+    print_owner_console_help();
+
+    // Requests from the last listing, so they can be referenced by number
+    let mut requests = Vec::new();
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+
+    loop {
+        print!("\nowner> ");
+        std::io::stdout().flush()?;
+
+        let Some(line) = lines.next_line().await? else { break };
+        let args: Vec<&str> = line.split_whitespace().collect();
+
+        // Resolve "#" (from the last listing) or a raw request ID
+        let resolve = |arg: &str, requests: &[cloud_p2p_project::directory_service::PendingRequest]| {
+            match arg.parse::<usize>() {
+                Ok(n) if n >= 1 && n <= requests.len() => requests[n - 1].request_id.clone(),
+                _ => arg.to_string(),
+            }
+        };
+
+        let result = match args.as_slice() {
+            [] => continue,
+            ["quit" | "exit" | "q"] => break,
+            ["help" | "h"] => {
+                print_owner_console_help();
+                Ok(())
+            }
+            ["requests" | "r"] => match fetch_pending_requests(owner, directory_addr).await {
+                Ok(fetched) => {
+                    requests = fetched;
+                    if requests.is_empty() {
+                        println!("✓ No pending requests");
+                    }
+                    for (idx, req) in requests.iter().enumerate() {
+                        println!("{}. {} wants {} view(s) of {} (ID: {})",
+                                 idx + 1, req.from_user, req.requested_views, req.image_id, req.request_id);
+                    }
+                    Ok(())
+                }
+                Err(e) => Err(e),
+            },
+            ["accept", id] => {
+                handle_respond_request(owner, &resolve(id, &requests), true, None, directory_addr).await
+            }
+            ["reject", id] => {
+                handle_respond_request(owner, &resolve(id, &requests), false, None, directory_addr).await
+            }
+            ["counter", id, views] => match views.parse::<u32>() {
+                Ok(views) if views > 0 => {
+                    handle_respond_request(owner, &resolve(id, &requests), true, Some(views), directory_addr).await
+                }
+                _ => Err(anyhow::anyhow!("Views must be a positive number")),
+            },
+            ["grants" | "g"] => print_owner_grants(owner),
+            ["set", image_id, user, views] => match views.parse::<u32>() {
+                Ok(views) => update_grant_everywhere(owner, image_id, user, views, directory_addr).await,
+                Err(_) => Err(anyhow::anyhow!("Views must be a number")),
+            },
+            ["revoke", image_id, user] => {
+                update_grant_everywhere(owner, image_id, user, 0, directory_addr).await
+            }
+            _ => {
+                println!("Unknown command: {}", line.trim());
+                print_owner_console_help();
+                Ok(())
+            }
+        };
+
+        if let Err(e) = result {
+            eprintln!("❌ {}", e);
+        }
+    }
+
+    println!("👋 Leaving owner console");
+    Ok(())
+}
+
+/// Update the owner's copy, then push the change to the user's copy
+async fn update_grant_everywhere(
+    owner: &str,
+    image_id: &str,
+    user: &str,
+    views: u32,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    handle_update_permissions(owner, image_id, user, views, directory_addr).await?;
+    if let Err(e) = handle_remote_update_permissions(owner, user, image_id, views, None, directory_addr).await {
+        eprintln!("⚠ Local grant updated, but the remote copy was not: {}", e);
+    }
+    Ok(())
+}
+
+// =============================================================================
+// PHOTO LIBRARY IMPORT
+// =============================================================================
+
+/// Tries at the encryption servers for each photo before it counts as failed
+const IMPORT_ENCRYPT_ATTEMPTS: u32 = 3;
+
+/// Encrypt every photo in a library into the images directory, captioned from its EXIF data
+///
+/// Photos whose encrypted copy already exists are skipped, so an interrupted import resumes.
+async fn handle_import_library(
+    library: &Path,
+    owner: &str,
+    albums: bool,
+    servers: &[String],
+    output_dir: &Path,
+    dry_run: bool,
+) -> Result<()> {
+    println!("=== Importing Photo Library ===");
+    println!("Library: {}", library.display());
+    println!("Owner: {}", owner);
+    println!("Output: {}", output_dir.display());
+
+    let photos = scan_library(library, albums)?;
+    if photos.is_empty() {
+        println!("No {} files found", IMPORTABLE_EXTENSIONS.join("/"));
+        return Ok(());
+    }
+    println!("Found {} photo(s)\n", photos.len());
+    let servers = match (dry_run, servers.is_empty()) {
+        (true, _) => Vec::new(),
+        (false, true) => load_servers()?,
+        (false, false) => servers.to_vec(),
+    };
+    if !dry_run {
+        fs::create_dir_all(output_dir)?;
+        set_encryption_stats_path(PathBuf::from(ENCRYPTION_STATS_FILE));
+    }
+
+    let (mut imported, mut skipped, mut failed) = (0, 0, 0);
+    let mut pending = Vec::new();
+    for (idx, photo) in photos.iter().enumerate() {
+        let output = output_dir.join(photo.output_name());
+        println!("[{}/{}] {}", idx + 1, photos.len(), photo.path.display());
+        println!("   📝 {}", photo.caption());
+        if output.exists() {
+            println!("   ↷ Already imported as {}", output.display());
+            skipped += 1;
+            continue;
+        }
+        if dry_run {
+            println!("   → {}", output.display());
+            continue;
+        }
+
+        let job = (|| {
+            let image = fs::read(&photo.path)?;
+            // Don't spend encryption server retries on files that aren't images at all
+            image::io::Reader::new(std::io::Cursor::new(&image))
+                .with_guessed_format()?
+                .into_dimensions()
+                .context("Not a readable image")?;
+            let meta_bytes = bincode::serialize(&EncryptionMetadata {
+                permissions: ImagePermissions::new(owner.to_string(), HashMap::new()),
+                annotations: photo.annotations(),
+                image_id: Some(new_image_id(&image)),
+                expires_at: None,
+                flags: 0,
+            })?;
+            Ok::<_, anyhow::Error>(EncryptionJob { meta_bytes, image })
+        })();
+        match job {
+            Ok(job) => pending.push((output, job)),
+            Err(e) => {
+                eprintln!("   ✗ {}", e);
+                failed += 1;
+            }
+        }
+    }
+
+    if !pending.is_empty() {
+        println!("\nEncrypting {} photo(s)...", pending.len());
+        let (outputs, jobs): (Vec<PathBuf>, Vec<EncryptionJob>) = pending.into_iter().unzip();
+        let results = encrypt_batch(&servers, jobs, IMPORT_ENCRYPT_ATTEMPTS, |progress| {
+            println!("   {}", progress.summary());
+        }).await;
+        for (output, result) in outputs.iter().zip(results) {
+            let saved = result.and_then(|carrier| {
+                fs::write(output, &carrier)?;
+                Ok(embedded_image_id(&carrier))
+            });
+            match saved {
+                Ok(image_id) => {
+                    println!("   ✓ {} (ID: {})", output.display(), image_id.as_deref().unwrap_or("none"));
+                    imported += 1;
+                }
+                Err(e) => {
+                    eprintln!("   ✗ {}: {:#}", output.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    if dry_run {
+        println!("\nDry run: {} photo(s) to import, {} already imported", photos.len() - skipped, skipped);
+        return Ok(());
+    }
+    println!("\n✓ Imported {}, skipped {}, failed {}", imported, skipped, failed);
+    if imported > 0 {
+        println!("💡 Restart your P2P peer in {} to share the new images", output_dir.display());
+    }
+    if failed > 0 {
+        bail!("{} photo(s) failed to import; run the command again to retry them", failed);
+    }
+    Ok(())
+}
+
+// =============================================================================
+// SCRIPTED DEMO
+// =============================================================================
+
+/// Views the demo viewer asks for; two are used up before the owner revokes the rest
+const DEMO_REQUESTED_VIEWS: u32 = 3;
+
+/// Tries at the encryption servers before the demo gives up (an election may be running)
+const DEMO_ENCRYPT_ATTEMPTS: u32 = 3;
+
+/// File name of the demo image in the owner's folder
+const DEMO_IMAGE_FILE: &str = "demo_sample.png";
+
+const DEMO_STEPS: [&str; 9] = [
+    "Register the owner and the viewer",
+    "Encrypt the sample image",
+    "Viewer requests the image",
+    "Owner approves the request",
+    "Image is delivered to the viewer",
+    "First view",
+    "Second view",
+    "Owner revokes the viewer's access",
+    "View after revocation is denied",
+];
+
+struct DemoConfig {
+    directory: Option<String>,
+    servers: Vec<String>,
+    image: Option<PathBuf>,
+    owner: String,
+    viewer: String,
+    port: u16,
+    advertise_host: String,
+    work_dir: PathBuf,
+}
+
+/// Outcome of each demo step run so far, in `DEMO_STEPS` order
+#[derive(Default)]
+struct DemoTranscript {
+    results: Vec<std::result::Result<(), String>>,
+}
+
+impl DemoTranscript {
+    fn begin(&self) {
+        let step = self.results.len();
+        println!("\n━━━ Step {}/{}: {} ━━━", step + 1, DEMO_STEPS.len(), DEMO_STEPS[step]);
+    }
+
+    fn record<T>(&mut self, outcome: Result<T>) -> Option<T> {
+        let step = DEMO_STEPS[self.results.len()];
+        match outcome {
+            Ok(value) => {
+                println!("✅ PASS: {}", step);
+                self.results.push(Ok(()));
+                Some(value)
+            }
+            Err(e) => {
+                println!("❌ FAIL: {} - {}", step, e);
+                self.results.push(Err(e.to_string()));
+                None
+            }
+        }
+    }
+
+    /// Print the transcript, failing unless every step ran and passed
+    fn finish(&self) -> Result<()> {
+        println!("\n=== Demo Transcript ===");
+        for (i, step) in DEMO_STEPS.iter().enumerate() {
+            match self.results.get(i) {
+                Some(Ok(())) => println!("  PASS  {}. {}", i + 1, step),
+                Some(Err(e)) => println!("  FAIL  {}. {} - {}", i + 1, step, e),
+                None => println!("  SKIP  {}. {}", i + 1, step),
+            }
+        }
+
+        let passed = self.results.iter().filter(|result| result.is_ok()).count();
+        println!("\n{} of {} steps passed", passed, DEMO_STEPS.len());
+        if passed < DEMO_STEPS.len() {
+            bail!("Demo failed");
+        }
+        println!("✅ Demo passed");
+        Ok(())
+    }
+}
+
+async fn handle_demo(config: DemoConfig) -> Result<()> {
+    let directory = config.directory.as_deref();
+    let (owner, viewer) = (config.owner.as_str(), config.viewer.as_str());
+    if owner == viewer {
+        bail!("The owner and the viewer must be different users");
+    }
+
+    println!("=== Demo: {} shares an image with {} ===", owner, viewer);
+
+    // Settle everything read from the current folder before moving into the work folder
+    let servers = if config.servers.is_empty() { load_servers()? } else { config.servers.clone() };
+    let sample = match &config.image {
+        Some(path) => fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?,
+        None => demo_sample_image()?,
+    };
+    if directory.is_none() {
+        println!("Directory Service: Multicast mode ({} servers)", directory_servers().len());
+    }
+
+    fs::create_dir_all(&config.work_dir)?;
+    let work_dir = fs::canonicalize(&config.work_dir)?;
+    let owner_dir = work_dir.join(owner);
+    let viewer_dir = work_dir.join(viewer);
+    for dir in [&owner_dir, &viewer_dir] {
+        if dir.exists() {
+            fs::remove_dir_all(dir)?;
+        }
+        fs::create_dir_all(dir)?;
+    }
+    // Views write their output and reputation records to the current folder
+    std::env::set_current_dir(&work_dir)?;
+    println!("Work folder: {}", work_dir.display());
+    println!("Encryption servers: {}", servers.join(", "));
+
+    let owner_address = format!("{}:{}", config.advertise_host, config.port);
+    let viewer_address = format!("{}:{}", config.advertise_host, config.port + 1);
+
+    let mut demo = DemoTranscript::default();
+    let outcome = run_demo(&mut demo, &config, &servers, &sample, (&owner_dir, &owner_address), (&viewer_dir, &viewer_address)).await;
+
+    // Don't leave the demo users listed as online once their P2P servers stop
+    for user in [owner, viewer] {
+        let _ = send_directory_or_multicast(directory, DirectoryMessage::Unregister { username: user.to_string() }).await;
+    }
+
+    outcome?;
+    demo.finish()
+}
+
+/// Run the demo steps in order, stopping at the first failure since each step needs the ones before it
+async fn run_demo(
+    demo: &mut DemoTranscript,
+    config: &DemoConfig,
+    servers: &[String],
+    sample: &[u8],
+    (owner_dir, owner_address): (&Path, &str),
+    (viewer_dir, viewer_address): (&Path, &str),
+) -> Result<()> {
+    let directory = config.directory.as_deref();
+    let (owner, viewer) = (config.owner.as_str(), config.viewer.as_str());
+
+    demo.begin();
+    let registered = async {
+        let owner_store = demo_start_peer(owner, owner_dir, config.port, owner_address, directory).await?;
+        demo_start_peer(viewer, viewer_dir, config.port + 1, viewer_address, directory).await?;
+        Ok(owner_store)
+    };
+    let Some(owner_store) = demo.record(registered.await) else { return Ok(()) };
+
+    demo.begin();
+    let encrypted = async {
+        let annotations = ImageAnnotations {
+            caption: Some(format!("Demo image shared by {}", owner)),
+            ..Default::default()
+        };
+        let carrier = encrypt_with_any_server(servers, owner, sample, annotations, DEMO_ENCRYPT_ATTEMPTS).await?;
+        let permissions = embedded_permissions(&carrier).context("The encrypted image has no readable permissions")?;
+        if permissions.owner != owner {
+            bail!("The encrypted image is owned by '{}', not '{}'", permissions.owner, owner);
+        }
+        let image_id = embedded_image_id(&carrier).context("The encrypted image has no image ID")?;
+
+        // Share it the way start-peer does: in the P2P server's store and in the directory's catalog
+        let path = owner_dir.join(DEMO_IMAGE_FILE);
+        fs::write(&path, &carrier)?;
+        let metadata = ImageMetadata {
+            image_id: image_id.clone(),
+            image_name: DEMO_IMAGE_FILE.to_string(),
+            owner: owner.to_string(),
+            description: Some(format!("Demo image from {}", owner)),
+            file_size_kb: carrier.len() as u64 / 1024,
+            max_grant_views: None,
+        };
+        owner_store.write().await.add_image(image_id.clone(), path, metadata);
+        let info = ImageInfo {
+            image_id: image_id.clone(),
+            image_name: DEMO_IMAGE_FILE.to_string(),
+            thumbnail_path: None,
+            max_grant_views: None,
+            caption: None,
+            visibility: ImageVisibility::Public,
+            link_token: None,
+            preview: None,
+        };
+        demo_register(owner, owner_address, vec![info], directory).await?;
+        println!("Image ID: {}", image_id);
+        Ok(image_id)
+    };
+    let Some(image_id) = demo.record(encrypted.await) else { return Ok(()) };
+
+    demo.begin();
+    let requested = handle_request_image(viewer, owner, &image_id, DEMO_REQUESTED_VIEWS, None, directory).await;
+    if demo.record(requested).is_none() {
+        return Ok(());
+    }
+
+    demo.begin();
+    let approved = async {
+        let request = fetch_pending_requests(owner, directory)
+            .await?
+            .into_iter()
+            .find(|request| request.from_user == viewer && request.image_id == image_id)
+            .with_context(|| format!("{}'s request isn't among {}'s pending requests", viewer, owner))?;
+        handle_respond_request(owner, &request.request_id, true, None, directory).await
+    };
+    if demo.record(approved.await).is_none() {
+        return Ok(());
+    }
+
+    demo.begin();
+    let received_path = received_image_path(Some(viewer_dir), owner, &image_id);
+    let delivered = async {
+        if !received_path.exists() {
+            bail!("Nothing was delivered to {}", received_path.display());
+        }
+        let views = demo_views_left(&received_path, viewer)?;
+        if views != DEMO_REQUESTED_VIEWS {
+            bail!("The delivered image grants {} views, not {}", views, DEMO_REQUESTED_VIEWS);
+        }
+        println!("Delivered to {} with {} views", received_path.display(), views);
+        Ok(())
+    };
+    if demo.record(delivered.await).is_none() {
+        return Ok(());
+    }
+
+    for _ in 0..2 {
+        demo.begin();
+        if demo.record(demo_view(&received_path, viewer, sample, true).await).is_none() {
+            return Ok(());
+        }
+    }
+
+    demo.begin();
+    let revoked = async {
+        handle_remote_update_permissions(owner, viewer, &image_id, 0, None, directory).await?;
+        let views = demo_views_left(&received_path, viewer)?;
+        if views != 0 {
+            bail!("The viewer's copy still grants {} views", views);
+        }
+        Ok(())
+    };
+    if demo.record(revoked.await).is_none() {
+        return Ok(());
+    }
+
+    demo.begin();
+    demo.record(demo_view(&received_path, viewer, sample, false).await);
+    Ok(())
+}
+
+/// A small gradient PNG to share when no image is given
+fn demo_sample_image() -> Result<Vec<u8>> {
+    let (width, height) = (96, 64);
+    let gradient = image::RgbImage::from_fn(width, height, |x, y| {
+        image::Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 160])
+    });
+    let mut png = Vec::new();
+    image::DynamicImage::ImageRgb8(gradient).write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Start a demo user's P2P server and register them with no images shared yet
+async fn demo_start_peer(
+    username: &str,
+    dir: &Path,
+    port: u16,
+    p2p_address: &str,
+    directory_addr: Option<&str>,
+) -> Result<Arc<RwLock<PeerImageStore>>> {
+    let image_store = Arc::new(RwLock::new(PeerImageStore::new()));
+    {
+        let mut store = image_store.write().await;
+        store.set_received_images_dir(dir.to_path_buf());
+        store.set_denial_stats_path(dir.join(ACCESS_DENIAL_STATS_FILE));
+        store.set_audit_log_path(dir.join(AUDIT_LOG_FILE));
+        store.set_directory_servers(grant_directories(directory_addr));
+    }
+
+    let server = tokio::spawn(start_p2p_server(port, username.to_string(), image_store.clone()));
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    if server.is_finished() {
+        server.await?.with_context(|| format!("{}'s P2P server could not listen on port {}", username, port))?;
+    }
+    println!("✓ {}'s P2P server is listening on port {}", username, port);
+
+    demo_register(username, p2p_address, Vec::new(), directory_addr).await?;
+    Ok(image_store)
+}
+
+async fn demo_register(
+    username: &str,
+    p2p_address: &str,
+    shared_images: Vec<ImageInfo>,
+    directory_addr: Option<&str>,
+) -> Result<()> {
+    let register_msg = DirectoryMessage::Register {
+        username: username.to_string(),
+        p2p_address: p2p_address.to_string(),
+        shared_images,
+        capabilities: local_capabilities(),
+        availability: None,
+        key: Some(registration_key(username)?),
+    };
+
+    match send_directory_or_multicast(directory_addr, register_msg).await? {
+        DirectoryMessage::RegisterResponse { success: true, .. } => {
+            println!("✓ Registered {} at {}", username, p2p_address);
+            Ok(())
+        }
+        DirectoryMessage::RegisterResponse { message, .. } => bail!("Registering {} failed: {}", username, message),
+        _ => bail!("Unexpected response from directory service"),
+    }
+}
+
+/// Views `viewer` has left on a received image
+fn demo_views_left(path: &Path, viewer: &str) -> Result<u32> {
+    let permissions = embedded_permissions(&fs::read(path)?).context("The received image has no readable permissions")?;
+    Ok(permissions.quotas.get(viewer).copied().unwrap_or(0))
+}
+
+/// View the received image as `viewer`, checking the view was granted (using up one view) or denied
+async fn demo_view(path: &Path, viewer: &str, sample: &[u8], expect_granted: bool) -> Result<()> {
+    let before = demo_views_left(path, viewer)?;
+    handle_view(&path.to_path_buf(), &viewer.to_string()).await?;
+    let after = demo_views_left(path, viewer)?;
+    let shown_original = fs::read(VIEWABLE_OUTPUT_IMAGE)? == sample;
+
+    match (expect_granted, shown_original) {
+        (true, false) => bail!("The view didn't show the original image"),
+        (true, true) if after + 1 != before => bail!("Views left went from {} to {}, not down by one", before, after),
+        (false, true) => bail!("The view was granted with {} views left", before),
+        (false, false) if after != before => bail!("A denied view changed the views left from {} to {}", before, after),
+        (true, true) => println!("Shown the original image, {} views left", after),
+        (false, false) => println!("Shown the carrier instead of the image, {} views left", after),
+    }
+    Ok(())
+}
@@ -0,0 +1,392 @@
+use anyhow::{bail, Result};
+use clap::Parser;
+use cloud_p2p_project::directory_service::{
+    negotiated_heartbeat_interval, send_directory_message, start_directory_service, DirectoryAuth, DirectoryMessage,
+    ImageInfo, ImageVisibility,
+};
+use cloud_p2p_project::p2p_protocol::{
+    image_annotations, local_capabilities, new_image_id, shared_image_id, start_p2p_server, ImageMetadata, PeerImageStore,
+    ACCESS_DENIAL_STATS_FILE,
+};
+use cloud_p2p_project::fsscan::is_image_file;
+use cloud_p2p_project::{lsb, CombinedPayload, EncryptionMetadata, ImageAnnotations, ImagePermissions};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use log::{error, info, warn};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{watch, RwLock};
+
+/// Replica secret used when DIRECTORY_REPLICA_SECRET isn't set
+const DEV_REPLICA_SECRET: &str = "dev-cluster";
+
+/// Number of directory replicas in the local cluster
+const DIRECTORY_REPLICAS: u16 = 3;
+
+/// Run the whole system locally in one process: directory replicas, a mock
+/// encryption server and two P2P peers
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct Cli {
+    /// Folder holding the directory state files and each peer's images
+    #[arg(long, default_value = "dev-cluster")]
+    data_dir: PathBuf,
+
+    /// Port of the first directory server (the others use the next ports up)
+    #[arg(long, default_value_t = 9100)]
+    directory_port: u16,
+
+    /// Port of the mock encryption server
+    #[arg(long, default_value_t = 8180)]
+    encryption_port: u16,
+
+    /// Port of the first peer (the second peer uses the next port up)
+    #[arg(long, default_value_t = 7101)]
+    peer_port: u16,
+
+    /// Names of the two peers; the first one gets a sample encrypted image
+    #[arg(long, num_args = 2, default_values_t = ["alice".to_string(), "bob".to_string()])]
+    peers: Vec<String>,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let cli = Cli::parse();
+    fs::create_dir_all(&cli.data_dir)?;
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    // Directory replicas, each listing the other two as peers. They start one at a time:
+    // a replica doesn't accept connections until its startup sync finishes, so replicas
+    // booting together would wait on each other forever.
+    let mut auth = DirectoryAuth::from_env();
+    auth.replica_secret.get_or_insert_with(|| DEV_REPLICA_SECRET.to_string());
+
+    let directory_addrs: Vec<String> = (0..DIRECTORY_REPLICAS)
+        .map(|i| format!("127.0.0.1:{}", cli.directory_port + i))
+        .collect();
+    let mut directory_tasks = Vec::new();
+
+    for i in 0..DIRECTORY_REPLICAS {
+        let port = cli.directory_port + i;
+        let server_id = format!("dev{}", i + 1);
+        let peer_servers: Vec<String> = directory_addrs.iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i as usize)
+            .map(|(_, addr)| addr.clone())
+            .collect();
+        let state_file = cli.data_dir.join(format!("directory_state_{}.json", server_id));
+        let auth = auth.clone();
+        let shutdown = wait_for_shutdown(shutdown_rx.clone());
+
+        directory_tasks.push(tokio::spawn(async move {
+            if let Err(e) = start_directory_service(port, server_id.clone(), peer_servers, state_file, auth, shutdown).await {
+                error!("Directory server {} failed: {}", server_id, e);
+            }
+        }));
+        wait_until_ready(&directory_addrs[i as usize]).await?;
+    }
+
+    // Mock encryption server
+    let encryption_addr = format!("127.0.0.1:{}", cli.encryption_port);
+    let encryption_listener = TcpListener::bind(("0.0.0.0", cli.encryption_port)).await?;
+    tokio::spawn(run_mock_encryption_server(encryption_listener));
+
+    // P2P peers, each with its own images folder
+    let mut peer_addrs = Vec::new();
+    for (i, username) in cli.peers.iter().enumerate() {
+        let port = cli.peer_port + i as u16;
+        let peer_dir = cli.data_dir.join(username);
+        fs::create_dir_all(peer_dir.join("received"))?;
+        fs::write(peer_dir.join("servers.conf"), format!("{}\n", encryption_addr))?;
+
+        if i == 0 {
+            seed_sample_image(&peer_dir, username, &encryption_addr).await?;
+        }
+
+        start_dev_peer(username, port, &peer_dir, &directory_addrs, shutdown_rx.clone()).await?;
+        peer_addrs.push(format!("127.0.0.1:{}", port));
+    }
+
+    print_cluster_summary(&cli, &directory_addrs, &encryption_addr, &peer_addrs);
+
+    tokio::signal::ctrl_c().await?;
+    info!("Shutting down dev cluster...");
+
+    for username in &cli.peers {
+        let unregister_msg = DirectoryMessage::Unregister {
+            username: username.clone(),
+        };
+        if let Err(e) = send_directory_message(&directory_addrs[0], unregister_msg).await {
+            warn!("Could not unregister {}: {}", username, e);
+        }
+    }
+
+    let _ = shutdown_tx.send(true);
+    for task in directory_tasks {
+        let _ = task.await;
+    }
+
+    info!("Dev cluster stopped. State kept in {}", cli.data_dir.display());
+    Ok(())
+}
+
+/// Poll a directory replica until it answers queries
+async fn wait_until_ready(addr: &str) -> Result<()> {
+    for _ in 0..50 {
+        let probe = DirectoryMessage::QueryPeers {
+            requesting_user: String::new(),
+        };
+        if let Ok(Ok(_)) = tokio::time::timeout(Duration::from_millis(500), send_directory_message(addr, probe)).await {
+            return Ok(());
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    bail!("Directory server at {} did not come up", addr)
+}
+
+/// Resolve once the cluster is asked to stop
+async fn wait_for_shutdown(mut shutdown_rx: watch::Receiver<bool>) {
+    while !*shutdown_rx.borrow() {
+        if shutdown_rx.changed().await.is_err() {
+            return;
+        }
+    }
+}
+
+// =============================================================================
+// MOCK ENCRYPTION SERVER
+// =============================================================================
+
+/// Serve encryption requests with the same wire format as the real servers,
+/// without Raft or load balancing
+async fn run_mock_encryption_server(listener: TcpListener) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, addr)) => {
+                tokio::spawn(async move {
+                    if let Err(e) = handle_encryption_request(stream).await {
+                        error!("Mock encryption request from {} failed: {}", addr, e);
+                    }
+                });
+            }
+            Err(e) => error!("Mock encryption server accept failed: {}", e),
+        }
+    }
+}
+
+async fn handle_encryption_request(mut stream: TcpStream) -> Result<()> {
+    let meta_size = stream.read_u64().await?;
+    let mut meta_buf = vec![0; meta_size as usize];
+    stream.read_exact(&mut meta_buf).await?;
+
+    let img_size = stream.read_u64().await?;
+    let mut img_buf = vec![0; img_size as usize];
+    stream.read_exact(&mut img_buf).await?;
+
+    let encrypted = tokio::task::spawn_blocking(move || mock_encrypt(&meta_buf, &img_buf)).await??;
+
+    stream.write_u64(encrypted.len() as u64).await?;
+    stream.write_all(&encrypted).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+/// Embed the permissions and client image into a generated carrier large enough to hold them
+fn mock_encrypt(meta_buf: &[u8], img_buf: &[u8]) -> Result<Vec<u8>> {
+    let EncryptionMetadata { permissions, annotations, image_id, expires_at, flags } = bincode::deserialize(meta_buf)?;
+    let client_img = image::load_from_memory(img_buf)?;
+    let mut client_img_bytes = Vec::new();
+    client_img.write_to(&mut Cursor::new(&mut client_img_bytes), ImageOutputFormat::Png)?;
+
+    let payload = CombinedPayload {
+        permissions,
+        unified_image: client_img_bytes,
+        annotations,
+        image_id,
+        expires_at,
+        flags,
+        provenance: Vec::new(),
+    }
+    .to_bytes()?;
+
+    // lsb::encode stores one bit per RGBA channel, plus a 4-byte length header
+    let pixels_needed = (payload.len() + 4) * 8 / 4 + 1;
+    let side = ((pixels_needed as f64).sqrt().ceil() as u32).max(256);
+    let carrier = DynamicImage::ImageRgb8(gradient_image(side, side));
+
+    let encoded = lsb::encode(&carrier, &payload)?;
+    let mut out_buf = Vec::new();
+    encoded.write_to(&mut Cursor::new(&mut out_buf), ImageOutputFormat::Png)?;
+    Ok(out_buf)
+}
+
+fn gradient_image(width: u32, height: u32) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        Rgb([(x * 255 / width) as u8, (y * 255 / height) as u8, 160])
+    })
+}
+
+// =============================================================================
+// DEV PEERS
+// =============================================================================
+
+/// Encrypt a generated picture through the mock server so the first peer has something to share
+async fn seed_sample_image(peer_dir: &Path, owner: &str, encryption_addr: &str) -> Result<()> {
+    let output_path = peer_dir.join("encrypted_sample.png");
+    if output_path.exists() {
+        return Ok(());
+    }
+
+    let mut sample_bytes = Vec::new();
+    DynamicImage::ImageRgb8(gradient_image(64, 64))
+        .write_to(&mut Cursor::new(&mut sample_bytes), ImageOutputFormat::Png)?;
+
+    let meta_bytes = bincode::serialize(&EncryptionMetadata {
+        permissions: ImagePermissions::new(owner.to_string(), HashMap::new()),
+        annotations: ImageAnnotations {
+            caption: Some(format!("Sample gradient shared by {}", owner)),
+            alt_text: Some("A purple-green colour gradient".to_string()),
+            license: Some("CC0-1.0".to_string()),
+        },
+        image_id: Some(new_image_id(&sample_bytes)),
+        expires_at: None,
+        flags: 0,
+    })?;
+
+    let mut stream = TcpStream::connect(encryption_addr).await?;
+    stream.write_u64(meta_bytes.len() as u64).await?;
+    stream.write_all(&meta_bytes).await?;
+    stream.write_u64(sample_bytes.len() as u64).await?;
+    stream.write_all(&sample_bytes).await?;
+    stream.flush().await?;
+
+    let encrypted_size = stream.read_u64().await?;
+    let mut encrypted = vec![0; encrypted_size as usize];
+    stream.read_exact(&mut encrypted).await?;
+
+    fs::write(&output_path, &encrypted)?;
+    info!("Seeded {} for {}", output_path.display(), owner);
+    Ok(())
+}
+
+/// Share a peer's images, register it and keep it alive with heartbeats
+async fn start_dev_peer(
+    username: &str,
+    port: u16,
+    peer_dir: &Path,
+    directory_addrs: &[String],
+    mut shutdown_rx: watch::Receiver<bool>,
+) -> Result<()> {
+    let image_store = Arc::new(RwLock::new(PeerImageStore::new()));
+    let mut shared_images = Vec::new();
+
+    for entry in fs::read_dir(peer_dir)? {
+        let path = entry?.path();
+        if !path.is_file() || !is_image_file(&path) {
+            continue;
+        }
+
+        let image_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let image_id = shared_image_id(&path);
+        let caption = fs::read(&path).ok()
+            .and_then(|data| image_annotations(&data))
+            .and_then(|annotations| annotations.caption);
+        let metadata = ImageMetadata {
+            image_id: image_id.clone(),
+            image_name: image_name.clone(),
+            owner: username.to_string(),
+            description: caption.clone().or_else(|| Some(format!("Image from {}", username))),
+            file_size_kb: fs::metadata(&path)?.len() / 1024,
+            max_grant_views: None,
+        };
+        shared_images.push(ImageInfo {
+            image_id: image_id.clone(),
+            image_name,
+            thumbnail_path: None,
+            max_grant_views: None,
+            caption,
+            visibility: ImageVisibility::Public,
+            link_token: None,
+            preview: None,
+        });
+        image_store.write().await.add_image(image_id, path, metadata);
+    }
+
+    {
+        let mut store = image_store.write().await;
+        store.set_received_images_dir(peer_dir.join("received"));
+        store.set_denial_stats_path(peer_dir.join(ACCESS_DENIAL_STATS_FILE));
+    }
+
+    let server_user = username.to_string();
+    tokio::spawn(async move {
+        if let Err(e) = start_p2p_server(port, server_user.clone(), image_store).await {
+            error!("P2P server for {} failed: {}", server_user, e);
+        }
+    });
+
+    let register_msg = DirectoryMessage::Register {
+        username: username.to_string(),
+        p2p_address: format!("127.0.0.1:{}", port),
+        shared_images,
+        capabilities: local_capabilities(),
+        availability: None,
+        key: None,
+    };
+    let heartbeat_interval = match send_directory_message(&directory_addrs[0], register_msg).await? {
+        DirectoryMessage::RegisterResponse { success: true, heartbeat_interval_secs, .. } => {
+            negotiated_heartbeat_interval(heartbeat_interval_secs)
+        }
+        DirectoryMessage::RegisterResponse { message, .. } => bail!("Could not register {}: {}", username, message),
+        _ => bail!("Unexpected response registering {}", username),
+    };
+
+    let heartbeat_user = username.to_string();
+    let heartbeat_servers = directory_addrs.to_vec();
+    tokio::spawn(async move {
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep(heartbeat_interval) => {}
+                _ = shutdown_rx.changed() => break,
+            }
+            for server in &heartbeat_servers {
+                let heartbeat_msg = DirectoryMessage::Heartbeat {
+                    username: heartbeat_user.clone(),
+                    capabilities: Some(local_capabilities()),
+                };
+                if send_directory_message(server, heartbeat_msg).await.is_ok() {
+                    break;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn print_cluster_summary(cli: &Cli, directory_addrs: &[String], encryption_addr: &str, peer_addrs: &[String]) {
+    info!("╔══════════════════════════════════════════════════════════╗");
+    info!("║   Dev Cluster (single process)                           ║");
+    info!("╚══════════════════════════════════════════════════════════╝");
+    info!("Directory servers: {}", directory_addrs.join(", "));
+    info!("Encryption server: {} (mock)", encryption_addr);
+    for (username, addr) in cli.peers.iter().zip(peer_addrs) {
+        info!("Peer {}: {} (images in {})", username, addr, cli.data_dir.join(username).display());
+    }
+    info!("");
+    info!("Try it from another terminal:");
+    info!("  client discover-peers -u {} -d {}", cli.peers[1], directory_addrs[0]);
+    info!("  client list-peer-images -u {} -p {} -d {}", cli.peers[1], cli.peers[0], directory_addrs[0]);
+    info!("  client request-image -u {} -p {} -i encrypted_sample.png -v 3 -d {}",
+          cli.peers[1], cli.peers[0], directory_addrs[0]);
+    info!("  client check-requests -u {} -d {}", cli.peers[0], directory_addrs[0]);
+    info!("Press Ctrl+C to stop.");
+}
@@ -1,8 +1,13 @@
 use anyhow::{bail, Result};
-use cloud_p2p_project::directory_service::start_directory_service;
-use log::info;
+use cloud_p2p_project::directory_service::{
+    start_directory_service, DirectoryAuth, ARCHIVE_AFTER_ENV, BLOB_CAP_ENV, CLIENT_TOKEN_ENV, HEARTBEAT_INTERVAL_ENV, NAMESPACE_ENV,
+    HEARTBEAT_TIMEOUT_ENV, MAX_PENDING_PER_SENDER_ENV, PENDING_UPDATE_TTL_ENV, REMIND_AFTER_ENV, REPLICA_SECRET_ENV,
+    WRITE_QUORUM_ENV,
+};
+use log::{info, warn};
 use std::env;
 use std::path::PathBuf;
+use tokio::signal::unix::{signal, SignalKind};
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -19,6 +24,23 @@ async fn main() -> Result<()> {
         eprintln!("    Server 1: directory_server 9000 dir1 10.40.7.2:9000 10.40.7.3:9000");
         eprintln!("    Server 2: directory_server 9000 dir2 10.40.7.1:9000 10.40.7.3:9000");
         eprintln!("    Server 3: directory_server 9000 dir3 10.40.7.1:9000 10.40.7.2:9000");
+        eprintln!("\nReplication (optional, via environment):");
+        eprintln!("  {}=<n>  servers that must hold a change to commit it, 1 = this one alone (default a majority)", WRITE_QUORUM_ENV);
+        eprintln!("\nAuthentication (optional, via environment):");
+        eprintln!("  {}=<secret>   shared secret required on replica sync", REPLICA_SECRET_ENV);
+        eprintln!("  {}=<token>      token required from clients", CLIENT_TOKEN_ENV);
+        eprintln!("\nNamespaces:");
+        eprintln!("  Clients pick one with {}=<name>; each is kept apart in <state file>.ns-<name>.json", NAMESPACE_ENV);
+        eprintln!("\nStorage (optional, via environment):");
+        eprintln!("  {}=<mb>         cap on queued image storage (default 512)", BLOB_CAP_ENV);
+        eprintln!("  {}=<days>  drop queued updates not collected in time (default 30)", PENDING_UPDATE_TTL_ENV);
+        eprintln!("  {}=<days>  archive users offline this long, 0 never (default 180)", ARCHIVE_AFTER_ENV);
+        eprintln!("\nRequests (optional, via environment):");
+        eprintln!("  {}=<n>  pending requests one user may leave for another (default 5)", MAX_PENDING_PER_SENDER_ENV);
+        eprintln!("  {}=<hours>  remind owners of requests pending this long (default 24)", REMIND_AFTER_ENV);
+        eprintln!("\nHeartbeats (optional, via environment):");
+        eprintln!("  {}=<secs>   how often clients heartbeat (default 10)", HEARTBEAT_INTERVAL_ENV);
+        eprintln!("  {}=<secs>    offline after this long without one (default 30)", HEARTBEAT_TIMEOUT_ENV);
         bail!("Incorrect arguments");
     }
     
@@ -29,6 +51,9 @@ async fn main() -> Result<()> {
     // State file path
     let state_file = PathBuf::from(format!("directory_state_{}.json", server_id));
     
+    // Authentication settings
+    let auth = DirectoryAuth::from_env();
+    
     info!("╔══════════════════════════════════════════════════════════╗");
     info!("║   Directory Service with Replication + Persistence       ║");
     info!("╚══════════════════════════════════════════════════════════╝");
@@ -36,6 +61,9 @@ async fn main() -> Result<()> {
     info!("Server ID: {}", server_id);
     info!("Port: {}", port);
     info!("State file: {}", state_file.display());
+    if auth.replica_secret.is_none() && !peer_servers.is_empty() {
+        info!("⚠ WARNING: {} not set - any host can overwrite replica state", REPLICA_SECRET_ENV);
+    }
     
     if peer_servers.is_empty() {
         info!("Mode: SINGLE SERVER (no replication)");
@@ -51,12 +79,30 @@ async fn main() -> Result<()> {
         info!("✓ This directory service is FULLY FAULT TOLERANT:");
         info!("  • Survives server crashes (disk persistence)");
         info!("  • Survives individual failures (replication)");
+        info!("  • Commits changes only once a majority of servers hold them");
         info!("  • Recovers from total failure (disk + peer sync)");
     }
     info!("");
     
     // Start the directory service
-    start_directory_service(port, server_id, peer_servers, state_file).await?;
+    start_directory_service(port, server_id, peer_servers, state_file, auth, shutdown_signal()).await?;
     
     Ok(())
+}
+
+/// Resolve when the process receives Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            warn!("Could not install SIGTERM handler: {}", e);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+    
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received Ctrl+C"),
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+    }
 }
\ No newline at end of file
@@ -0,0 +1,472 @@
+//! Load generator for the directory and P2P servers
+//!
+//! Simulates N peers that register, heartbeat, leave requests and fetch each
+//! other's images for a while, then reports latency percentiles and error rates.
+//!
+//! Run examples:
+//! # 20 peers for 30 seconds against a local directory
+//! cargo run --release --bin loadgen -- -d 127.0.0.1:9000
+//!
+//! # 100 peers sharing 64 KB and 1 MB images against three replicas
+//! cargo run --release --bin loadgen -- -n 100 --image-kb 64 --image-kb 1024 \
+//!   -d 10.40.7.1:9000 -d 10.40.7.2:9000 -d 10.40.7.3:9000
+
+use anyhow::{bail, Result};
+use clap::Parser;
+use cloud_p2p_project::directory_service::{
+    negotiated_heartbeat_interval, send_directory_message, user_key, DirectoryMessage, ImageInfo, ImageVisibility,
+    USER_KEYS_FILE,
+};
+use cloud_p2p_project::p2p_protocol::{
+    local_capabilities, new_image_id, request_scaled_image_from_peer, start_p2p_server, ImageMetadata, PeerImageStore,
+};
+use cloud_p2p_project::{lsb, CombinedPayload, ImagePermissions};
+use image::{DynamicImage, ImageOutputFormat, Rgb, RgbImage};
+use log::{error, info, warn};
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::future::Future;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::RwLock;
+use tokio::task::JoinSet;
+
+/// Spare payload capacity in generated carriers, for quotas embedded by grants
+const CARRIER_HEADROOM_BYTES: usize = 16 * 1024;
+
+#[derive(Parser)]
+#[command(version, about = "Load generator for the directory and P2P servers", long_about = None)]
+struct Cli {
+    /// Directory server(s) to load; later ones are tried when earlier ones fail
+    #[arg(short, long, required = true)]
+    directory: Vec<String>,
+
+    /// Number of simulated peers
+    #[arg(short = 'n', long, default_value_t = 20)]
+    peers: usize,
+
+    /// How long to generate load for (seconds)
+    #[arg(short = 't', long, default_value_t = 30)]
+    duration_secs: u64,
+
+    /// Size of each peer's shared image in KB (repeat to mix sizes across peers)
+    #[arg(long = "image-kb", default_values_t = [64])]
+    image_kb: Vec<usize>,
+
+    /// Pause between one peer's operations (milliseconds)
+    #[arg(long, default_value_t = 200)]
+    think_ms: u64,
+
+    /// P2P port of the first simulated peer (the others use the next ports up)
+    #[arg(long, default_value_t = 7600)]
+    base_port: u16,
+
+    /// Host the simulated peers register as reachable on
+    #[arg(long, default_value = "127.0.0.1")]
+    advertise_host: String,
+
+    /// Prefix of the simulated peers' usernames
+    #[arg(long, default_value = "loadgen")]
+    prefix: String,
+
+    /// Folder for the simulated peers' images
+    #[arg(long, default_value = "loadgen-data")]
+    data_dir: PathBuf,
+}
+
+/// A simulated peer that is registered and serving its image
+#[derive(Clone)]
+struct SimPeer {
+    username: String,
+    p2p_address: String,
+    image_id: String,
+    /// Key it registered with, so its simulated owner can answer its requests
+    key: String,
+}
+
+// =============================================================================
+// STATISTICS
+// =============================================================================
+
+#[derive(Default)]
+struct OpStats {
+    latencies: Vec<Duration>,
+    errors: usize,
+    bytes: u64,
+}
+
+/// Per-operation results, by operation name
+#[derive(Clone, Default)]
+struct Stats(Arc<Mutex<BTreeMap<&'static str, OpStats>>>);
+
+impl Stats {
+    /// Run one operation, recording its latency (successes only) or failure
+    async fn timed<T>(&self, op: &'static str, future: impl Future<Output = Result<T>>) -> Option<T> {
+        let started = Instant::now();
+        let result = future.await;
+        let elapsed = started.elapsed();
+        let mut stats = self.0.lock().unwrap();
+        let entry = stats.entry(op).or_default();
+        match result {
+            Ok(value) => {
+                entry.latencies.push(elapsed);
+                Some(value)
+            }
+            Err(e) => {
+                entry.errors += 1;
+                if entry.errors <= 3 {
+                    warn!("{} failed: {}", op, e);
+                }
+                None
+            }
+        }
+    }
+
+    fn add_bytes(&self, op: &'static str, bytes: u64) {
+        self.0.lock().unwrap().entry(op).or_default().bytes += bytes;
+    }
+
+    fn print_report(&self, elapsed: Duration) {
+        let stats = self.0.lock().unwrap();
+        println!();
+        println!("═══════════════════════════════════════════════════════════════════════════════");
+        println!("  LOAD TEST RESULTS ({:.1}s)", elapsed.as_secs_f64());
+        println!("═══════════════════════════════════════════════════════════════════════════════");
+        println!(
+            "  {:<12} {:>8} {:>7} {:>7} {:>9} {:>9} {:>9} {:>9} {:>8}",
+            "Operation", "OK", "Errors", "Err %", "p50 ms", "p90 ms", "p99 ms", "max ms", "ops/s"
+        );
+        for (op, op_stats) in stats.iter() {
+            let mut latencies = op_stats.latencies.clone();
+            latencies.sort();
+            let total = latencies.len() + op_stats.errors;
+            let error_rate = 100.0 * op_stats.errors as f64 / total.max(1) as f64;
+            println!(
+                "  {:<12} {:>8} {:>7} {:>6.1}% {:>9} {:>9} {:>9} {:>9} {:>8.1}",
+                op,
+                latencies.len(),
+                op_stats.errors,
+                error_rate,
+                format_ms(percentile(&latencies, 50.0)),
+                format_ms(percentile(&latencies, 90.0)),
+                format_ms(percentile(&latencies, 99.0)),
+                format_ms(latencies.last().copied()),
+                latencies.len() as f64 / elapsed.as_secs_f64(),
+            );
+        }
+        for (op, op_stats) in stats.iter().filter(|(_, s)| s.bytes > 0) {
+            let mb = op_stats.bytes as f64 / (1024.0 * 1024.0);
+            println!("\n  {}: {:.1} MB moved ({:.2} MB/s)", op, mb, mb / elapsed.as_secs_f64());
+        }
+        println!("═══════════════════════════════════════════════════════════════════════════════");
+    }
+}
+
+/// Nearest-rank percentile of sorted samples
+fn percentile(sorted: &[Duration], pct: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    Some(sorted[rank.clamp(1, sorted.len()) - 1])
+}
+
+fn format_ms(latency: Option<Duration>) -> String {
+    latency.map_or_else(|| "-".to_string(), |l| format!("{:.1}", l.as_secs_f64() * 1000.0))
+}
+
+// =============================================================================
+// MAIN
+// =============================================================================
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn")).init();
+
+    let cli = Cli::parse();
+    if cli.peers < 2 {
+        bail!("At least 2 peers are needed so they have someone to request from");
+    }
+    let directories = Arc::new(cli.directory.clone());
+    let stats = Stats::default();
+
+    println!("Starting {} simulated peers against {}...", cli.peers, cli.directory.join(", "));
+    let mut setups = JoinSet::new();
+    for i in 0..cli.peers {
+        let username = format!("{}-{}", cli.prefix, i);
+        let port = cli.base_port + i as u16;
+        let image_kb = cli.image_kb[i % cli.image_kb.len()];
+        let peer_dir = cli.data_dir.join(&username);
+        let p2p_address = format!("{}:{}", cli.advertise_host, port);
+        let (directories, stats) = (directories.clone(), stats.clone());
+        setups.spawn(async move {
+            start_sim_peer(username, port, p2p_address, image_kb, peer_dir, &directories, &stats).await
+        });
+    }
+
+    let mut peers = Vec::new();
+    while let Some(setup) = setups.join_next().await {
+        match setup {
+            Ok(Ok((peer, heartbeat_interval))) => peers.push((peer, heartbeat_interval)),
+            Ok(Err(e)) => error!("Simulated peer failed to start: {}", e),
+            Err(e) => error!("Simulated peer setup panicked: {}", e),
+        }
+    }
+    if peers.len() < 2 {
+        bail!("Only {} of {} peers started - is the directory reachable?", peers.len(), cli.peers);
+    }
+    println!("{} peers registered; generating load for {}s...", peers.len(), cli.duration_secs);
+
+    let roster: Arc<Vec<SimPeer>> = Arc::new(peers.iter().map(|(peer, _)| peer.clone()).collect());
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs(cli.duration_secs);
+    let think = Duration::from_millis(cli.think_ms);
+
+    let mut workers = JoinSet::new();
+    for (peer, heartbeat_interval) in peers {
+        let (heartbeat_directories, heartbeat_stats) = (directories.clone(), stats.clone());
+        let username = peer.username.clone();
+        workers.spawn(async move {
+            heartbeat_loop(&username, heartbeat_interval, deadline, &heartbeat_directories, &heartbeat_stats).await;
+        });
+        let (directories, stats, roster) = (directories.clone(), stats.clone(), roster.clone());
+        workers.spawn(async move {
+            request_loop(&peer, &roster, think, deadline, &directories, &stats).await;
+        });
+    }
+    while workers.join_next().await.is_some() {}
+    let elapsed = started.elapsed();
+
+    for peer in roster.iter() {
+        let unregister_msg = DirectoryMessage::Unregister { username: peer.username.clone() };
+        stats.timed("unregister", send_to_directory(&directories, unregister_msg)).await;
+    }
+
+    stats.print_report(elapsed);
+    Ok(())
+}
+
+// =============================================================================
+// SIMULATED PEERS
+// =============================================================================
+
+/// Share a generated image, start the P2P server and register with the directory
+async fn start_sim_peer(
+    username: String,
+    port: u16,
+    p2p_address: String,
+    image_kb: usize,
+    peer_dir: PathBuf,
+    directories: &[String],
+    stats: &Stats,
+) -> Result<(SimPeer, Duration)> {
+    let owner = username.clone();
+    let dir = peer_dir.clone();
+    let (image_id, image_path, carrier_len) =
+        tokio::task::spawn_blocking(move || write_carrier(&owner, image_kb, &dir)).await??;
+
+    let image_store = Arc::new(RwLock::new(PeerImageStore::new()));
+    {
+        let mut store = image_store.write().await;
+        store.set_owner(username.clone());
+        store.set_received_images_dir(peer_dir.join("received"));
+        store.set_directory_servers(directories.to_vec());
+        store.add_image(image_id.clone(), image_path, ImageMetadata {
+            image_id: image_id.clone(),
+            image_name: image_id.clone(),
+            owner: username.clone(),
+            description: Some(format!("{} KB load test image", image_kb)),
+            file_size_kb: carrier_len / 1024,
+            max_grant_views: None,
+        });
+    }
+
+    let server_user = username.clone();
+    tokio::spawn(async move {
+        if let Err(e) = start_p2p_server(port, server_user.clone(), image_store).await {
+            error!("P2P server for {} failed: {}", server_user, e);
+        }
+    });
+
+    let key = user_key(&peer_dir.join(USER_KEYS_FILE), &username)?;
+    let register_msg = DirectoryMessage::Register {
+        username: username.clone(),
+        p2p_address: p2p_address.clone(),
+        shared_images: vec![ImageInfo {
+            image_id: image_id.clone(),
+            image_name: image_id.clone(),
+            thumbnail_path: None,
+            max_grant_views: None,
+            caption: None,
+            visibility: ImageVisibility::Public,
+            link_token: None,
+            preview: None,
+        }],
+        capabilities: local_capabilities(),
+        availability: None,
+        key: Some(key.clone()),
+    };
+    let response = stats.timed("register", send_to_directory(directories, register_msg)).await;
+    let heartbeat_interval = match response {
+        Some(DirectoryMessage::RegisterResponse { success: true, heartbeat_interval_secs, .. }) => {
+            negotiated_heartbeat_interval(heartbeat_interval_secs)
+        }
+        Some(DirectoryMessage::RegisterResponse { message, .. }) => bail!("Could not register {}: {}", username, message),
+        Some(_) => bail!("Unexpected response registering {}", username),
+        None => bail!("Could not reach a directory to register {}", username),
+    };
+
+    info!("{} serving {} ({} KB carrier) at {}", username, image_id, carrier_len / 1024, p2p_address);
+    Ok((SimPeer { username, p2p_address, image_id, key }, heartbeat_interval))
+}
+
+/// Heartbeat at the negotiated interval until the deadline
+async fn heartbeat_loop(username: &str, interval: Duration, deadline: Instant, directories: &[String], stats: &Stats) {
+    loop {
+        tokio::time::sleep(interval.min(deadline.saturating_duration_since(Instant::now()))).await;
+        if Instant::now() >= deadline {
+            break;
+        }
+        let heartbeat_msg = DirectoryMessage::Heartbeat {
+            username: username.to_string(),
+            capabilities: None,
+        };
+        stats.timed("heartbeat", send_to_directory(directories, heartbeat_msg)).await;
+    }
+}
+
+/// Leave a request for a random peer's image, have that peer's owner accept it, then fetch
+/// the image with the grant token as the owner's delivery would and mark it delivered
+async fn request_loop(
+    peer: &SimPeer,
+    roster: &[SimPeer],
+    think: Duration,
+    deadline: Instant,
+    directories: &[String],
+    stats: &Stats,
+) {
+    while Instant::now() < deadline {
+        let Some(target) = roster.iter().filter(|p| p.username != peer.username).collect::<Vec<_>>()
+            .choose(&mut rand::thread_rng())
+            .map(|p| (*p).clone())
+        else {
+            return;
+        };
+        let views = rand::thread_rng().gen_range(1..=5);
+
+        let request_msg = DirectoryMessage::LeaveRequest {
+            from_user: peer.username.clone(),
+            to_user: target.username.clone(),
+            image_id: target.image_id.clone(),
+            requested_views: views,
+            delta_only: false,
+            requested_max_dimension: None,
+            share_token: None,
+        };
+        let left = stats.timed("request", send_to_directory(directories, request_msg)).await;
+        let Some(DirectoryMessage::LeaveRequestResponse { success: true, request_id, .. }) = left else {
+            tokio::time::sleep(think).await;
+            continue;
+        };
+
+        // The target's owner answers with the key it registered with, like its client would
+        let respond_msg = DirectoryMessage::RespondToRequest {
+            request_id: request_id.clone(),
+            owner: target.username.clone(),
+            accept: true,
+            granted_views: None,
+            note: None,
+            key: Some(target.key.clone()),
+        };
+        let grant_token = match stats.timed("respond", send_to_directory(directories, respond_msg)).await {
+            Some(DirectoryMessage::RespondToRequestResponse { request: Some(request), .. }) => request.grant_token,
+            _ => None,
+        };
+
+        let fetched = stats.timed(
+            "transfer",
+            request_scaled_image_from_peer(
+                &target.p2p_address,
+                &peer.username,
+                &target.image_id,
+                views,
+                None,
+                None,
+                grant_token.as_deref(),
+            ),
+        ).await;
+        if let Some(image) = fetched {
+            stats.add_bytes("transfer", image.len() as u64);
+            // Settle the request so accepted ones don't pile up awaiting delivery
+            let delivered_msg = DirectoryMessage::MarkRequestDelivered {
+                request_id,
+                username: target.username.clone(),
+                key: Some(target.key.clone()),
+            };
+            stats.timed("deliver", send_to_directory(directories, delivered_msg)).await;
+        }
+
+        tokio::time::sleep(think).await;
+    }
+}
+
+/// Send to the first directory that answers
+async fn send_to_directory(directories: &[String], message: DirectoryMessage) -> Result<DirectoryMessage> {
+    let mut last_error = None;
+    for directory in directories {
+        match send_directory_message(directory, message.clone()).await {
+            Ok(response) => return Ok(response),
+            Err(e) => last_error = Some(e),
+        }
+    }
+    Err(last_error.unwrap_or_else(|| anyhow::anyhow!("No directory servers given")))
+}
+
+// =============================================================================
+// GENERATED IMAGES
+// =============================================================================
+
+/// Write a carrier embedding a noise image of about `image_kb`, returning its ID, path and size
+fn write_carrier(owner: &str, image_kb: usize, peer_dir: &Path) -> Result<(String, PathBuf, u64)> {
+    // Noise barely compresses, so the PNG ends up close to its raw size
+    let side = ((image_kb * 1024 / 3) as f64).sqrt().ceil().max(1.0) as u32;
+    let mut rng = rand::thread_rng();
+    let noise = RgbImage::from_fn(side, side, |_, _| Rgb(rng.gen()));
+    let mut image_bytes = Vec::new();
+    DynamicImage::ImageRgb8(noise).write_to(&mut Cursor::new(&mut image_bytes), ImageOutputFormat::Png)?;
+
+    let image_id = new_image_id(&image_bytes);
+    let payload = CombinedPayload {
+        permissions: ImagePermissions::new(owner.to_string(), HashMap::new()),
+        unified_image: image_bytes,
+        annotations: Default::default(),
+        image_id: Some(image_id.clone()),
+        expires_at: None,
+        flags: 0,
+        provenance: Vec::new(),
+    }
+    .to_bytes()?;
+
+    // lsb::encode stores one bit per RGBA channel, plus a 4-byte length header;
+    // leave room for the quota entries grants add to the payload
+    let pixels_needed = (payload.len() + CARRIER_HEADROOM_BYTES + 4) * 8 / 4 + 1;
+    let carrier_side = ((pixels_needed as f64).sqrt().ceil() as u32).max(64);
+    let carrier = DynamicImage::ImageRgb8(RgbImage::from_fn(carrier_side, carrier_side, |x, y| {
+        Rgb([(x * 255 / carrier_side) as u8, (y * 255 / carrier_side) as u8, 160])
+    }));
+    let encoded = lsb::encode(&carrier, &payload)?;
+
+    // Start from a clean folder so earlier runs' images aren't picked up
+    if peer_dir.exists() {
+        fs::remove_dir_all(peer_dir)?;
+    }
+    fs::create_dir_all(peer_dir.join("received"))?;
+    let path = peer_dir.join(&image_id);
+    encoded.save(&path)?;
+    let len = fs::metadata(&path)?.len();
+    Ok((image_id, path, len))
+}
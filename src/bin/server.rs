@@ -702,13 +702,11 @@
 
 
 use anyhow::{bail, Result};
-use bincode;
 use cloud_p2p_project::raft::{RaftConfig, RaftNode};
-use cloud_p2p_project::{lsb, CombinedPayload, ImagePermissions, LoadBalancingMessage, RaftMessage, ServerMetrics};
+use cloud_p2p_project::{lsb, CombinedPayload, EncryptionMetadata, LoadBalancingMessage, RaftMessage, ServerMetrics};
 use image::{ImageOutputFormat, GenericImageView};
 use log::{error, info};
 use std::env;
-use std::fs;
 use std::io::Cursor;
 use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
 use std::sync::Arc;
@@ -764,6 +762,12 @@ pub struct LoadBalancingState {
     pub total_response_time_ms: AtomicU64,
 }
 
+impl Default for LoadBalancingState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LoadBalancingState {
     pub fn new() -> Self {
         Self {
@@ -776,11 +780,9 @@ impl LoadBalancingState {
     /// Get current metrics for this server
     pub fn get_metrics(&self, server_id: String) -> ServerMetrics {
         let total_requests = self.total_requests.load(Ordering::Relaxed);
-        let avg_response = if total_requests > 0 {
-            self.total_response_time_ms.load(Ordering::Relaxed) / total_requests
-        } else {
-            0
-        };
+        let avg_response = self.total_response_time_ms.load(Ordering::Relaxed)
+            .checked_div(total_requests)
+            .unwrap_or(0);
         
         let active_conns = self.active_connections.load(Ordering::Relaxed);
         
@@ -1164,11 +1166,9 @@ async fn handle_client_with_load_balancing(
     // Elections take ~7s each, 3 elections = ~21s max
     // Check every 3s for quicker response once leader is elected
     let max_leader_wait_attempts = 8;  // 8 attempts × 3s = 24s max wait
-    let mut is_leader = false;
     
     for attempt in 1..=max_leader_wait_attempts {
         if raft_node.is_leader().await {
-            is_leader = true;
             break;
         }
         
@@ -1185,7 +1185,7 @@ async fn handle_client_with_load_balancing(
             } else {
                 // There is a leader, but it's not us - reject immediately
                 info!("Rejected client - not leader. Current leader: {:?}", leader_id);
-                let error_msg = format!("NOT_LEADER:{}", leader_id.unwrap());
+                let error_msg = format!("NOT_LEADER:{}", leader_id.unwrap_or_default());
                 let error_bytes = error_msg.as_bytes();
                 stream.write_u64(error_bytes.len() as u64).await?;
                 stream.write_all(error_bytes).await?;
@@ -1414,8 +1414,8 @@ async fn process_encryption_work(meta_buf: &[u8], img_buf: &[u8]) -> Result<Vec<
    
     // Run CPU/IO intensive work on blocking thread pool
     tokio::task::spawn_blocking(move || {
-        // 1. Deserialize the permissions metadata
-        let permissions: ImagePermissions = bincode::deserialize(&meta_buf)?;
+        // 1. Deserialize the permissions metadata (and any annotations sent with it)
+        let EncryptionMetadata { permissions, annotations, image_id, expires_at, flags } = bincode::deserialize(&meta_buf)?;
        
         // 2. Load the CLIENT'S image (this will be embedded)
         let client_img = image::load_from_memory(&img_buf)?;
@@ -1442,10 +1442,15 @@ async fn process_encryption_work(meta_buf: &[u8], img_buf: &[u8]) -> Result<Vec<
         let combined_payload = CombinedPayload {
             permissions,
             unified_image: client_img_bytes,  // ✅ Move happens here
+            annotations,
+            image_id,
+            expires_at,
+            flags,
+            provenance: Vec::new(),
         };
        
         // 6. Serialize the combined payload
-        let final_payload = bincode::serialize(&combined_payload)?;
+        let final_payload = combined_payload.to_bytes()?;
        
         info!("Total payload size to embed: {} bytes ({:.2} KB)",
               final_payload.len(),
@@ -1455,7 +1460,7 @@ async fn process_encryption_work(meta_buf: &[u8], img_buf: &[u8]) -> Result<Vec<
         // CAPACITY CHECK BEFORE EMBEDDING
         // ============================================================
         let (width, height) = default_img.dimensions();
-        let available_capacity = (width * height * 3) as usize; // 3 bits per pixel (RGB)
+        let available_capacity = lsb::capacity_bytes(width, height) * 8; // 3 bits per pixel (RGB)
         let required_capacity = final_payload.len() * 8; // 8 bits per byte
        
         if required_capacity > available_capacity {
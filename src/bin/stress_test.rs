@@ -21,7 +21,6 @@
 
 
 use anyhow::{bail, Result};
-use bincode;
 use cloud_p2p_project::ImagePermissions;
 use image::{ImageFormat, GenericImageView};
 use std::collections::HashMap;
@@ -493,6 +492,8 @@ enum ErrorType {
     Timeout,
     NotLeader,
     NoLeader,
+    // Failed validations are retried rather than recorded as the request's error
+    #[allow(dead_code)]
     InvalidResponse,
     Other,
 }
@@ -510,7 +511,7 @@ fn validate_encrypted_image(data: &[u8]) -> Result<bool> {
     
     // Check PNG signature (first 8 bytes)
     let png_signature: [u8; 8] = [0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A];
-    if &data[0..8] != &png_signature {
+    if data[0..8] != png_signature {
         return Ok(false);
     }
     
@@ -525,7 +526,7 @@ fn validate_encrypted_image(data: &[u8]) -> Result<bool> {
     }
 }
 
-/// Save sample encrypted images for manual inspection
+// Save sample encrypted images for manual inspection
 // fn save_sample_image(data: &[u8], sample_id: usize, thread_id: usize) -> Result<()> {
 //     // Create samples directory if it doesn't exist
 //     let samples_dir = "stress_test_samples";
@@ -561,10 +562,7 @@ fn main() -> Result<()> {
     // Prepare metadata
     let mut quotas = HashMap::new();
     quotas.insert("test_user".to_string(), 5);
-    let permissions = ImagePermissions {
-        owner: "test_owner".to_string(),
-        quotas,
-    };
+    let permissions = ImagePermissions::new("test_owner".to_string(), quotas);
     let meta_bytes = bincode::serialize(&permissions)?;
     
     println!("\n📋 TEST CONFIGURATION");
@@ -699,8 +697,6 @@ fn run_worker(
             // record the FIRST success received for this request attempt.
             // *******************************************************************
             for server_addr in &servers {
-                let mut current_server_success = false;
-
                 match send_encryption_request(
                     server_addr,
                     &meta_bytes,
@@ -709,8 +705,6 @@ fn run_worker(
                     config.rw_timeout,
                 ) {
                     Ok((encrypted_data, leader_id)) => {
-                        current_server_success = true; // This server responded successfully
-                        
                         // ONLY record success metrics/samples if we haven't already recorded one
                         if !success_reported { 
                             match validate_encrypted_image(&encrypted_data) {
@@ -0,0 +1,256 @@
+use crate::directory_service::{send_directory_message, DirectoryMessage};
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+// =============================================================================
+// DIRECTORY DISCOVERY BOOTSTRAP
+// =============================================================================
+
+/// Default file name for the list of known directory servers
+pub const BOOTSTRAP_FILE: &str = "directory_servers.json";
+
+/// Environment variable pointing at a different bootstrap file
+pub const BOOTSTRAP_FILE_ENV: &str = "P2P_DIRECTORY_BOOTSTRAP";
+
+/// Servers used when no bootstrap file exists yet
+pub const DEFAULT_DIRECTORY_SERVERS: &[&str] = &[
+    "10.7.57.239:9000",
+    "10.7.57.240:9000",
+    "10.7.57.99:9000",
+];
+
+/// How long a health check waits on each server
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Learned servers are forgotten after this many failed checks in a row
+const MAX_LEARNED_FAILURES: u32 = 5;
+
+/// One directory server and how it fared in recent health checks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KnownDirectory {
+    pub address: String,
+    /// Unix time of the last successful check
+    #[serde(default)]
+    pub last_ok: Option<u64>,
+    /// Round trip of the last successful check
+    #[serde(default)]
+    pub latency_ms: Option<u64>,
+    /// Checks failed since the last success
+    #[serde(default)]
+    pub failures: u32,
+    /// Advertised by another server rather than configured
+    #[serde(default)]
+    pub learned: bool,
+}
+
+impl KnownDirectory {
+    fn new(address: &str, learned: bool) -> Self {
+        Self { address: address.to_string(), last_ok: None, latency_ms: None, failures: 0, learned }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.failures == 0 && self.last_ok.is_some()
+    }
+}
+
+/// Outcome of a health-checked refresh
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefreshReport {
+    pub healthy: usize,
+    pub total: usize,
+    /// Replicas first heard of in this refresh
+    pub learned: Vec<String>,
+}
+
+/// Directory servers in last-known-good order: healthy servers by latency, then the rest
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectoryBootstrap {
+    pub servers: Vec<KnownDirectory>,
+}
+
+impl Default for DirectoryBootstrap {
+    fn default() -> Self {
+        Self::from_addresses(DEFAULT_DIRECTORY_SERVERS.iter().copied())
+    }
+}
+
+impl DirectoryBootstrap {
+    pub fn from_addresses<'a>(addresses: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut bootstrap = Self { servers: Vec::new() };
+        bootstrap.set_addresses(addresses);
+        bootstrap
+    }
+
+    /// The bootstrap file named by `P2P_DIRECTORY_BOOTSTRAP`, or `directory_servers.json`
+    pub fn default_path() -> PathBuf {
+        std::env::var(BOOTSTRAP_FILE_ENV)
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map_or_else(|| PathBuf::from(BOOTSTRAP_FILE), PathBuf::from)
+    }
+
+    /// Read a bootstrap file: the JSON this module saves, or one `host:port` per line (`#` comments)
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let bootstrap = match serde_json::from_str::<Self>(&content) {
+            Ok(bootstrap) => bootstrap,
+            Err(_) => Self::from_addresses(
+                content.lines().map(|line| line.split('#').next().unwrap_or("").trim()),
+            ),
+        };
+        if bootstrap.servers.is_empty() {
+            bail!("No directory servers listed in {}", path.display());
+        }
+        Ok(bootstrap)
+    }
+
+    /// Load `path`, falling back to the built-in servers if it's missing or unusable
+    pub fn load_or_default(path: &Path) -> Self {
+        if !path.exists() {
+            return Self::default();
+        }
+        Self::load(path).unwrap_or_else(|e| {
+            warn!("{}; using built-in directory servers", e);
+            Self::default()
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Server addresses, best first
+    pub fn addresses(&self) -> Vec<String> {
+        self.servers.iter().map(|server| server.address.clone()).collect()
+    }
+
+    /// Replace the list, keeping the health history of servers that stay on it
+    pub fn set_addresses<'a>(&mut self, addresses: impl IntoIterator<Item = &'a str>) {
+        let mut servers: Vec<KnownDirectory> = Vec::new();
+        for address in addresses.into_iter().filter(|address| !address.is_empty()) {
+            if servers.iter().any(|server| server.address == address) {
+                continue;
+            }
+            let known = self.servers.iter().find(|server| server.address == address);
+            servers.push(known.cloned().unwrap_or_else(|| KnownDirectory::new(address, false)));
+        }
+        self.servers = servers;
+    }
+
+    /// Add servers we haven't seen before, returning the ones added
+    pub fn learn(&mut self, addresses: &[String]) -> Vec<String> {
+        let mut added = Vec::new();
+        for address in addresses {
+            if address.is_empty() || self.servers.iter().any(|server| server.address == *address) {
+                continue;
+            }
+            self.servers.push(KnownDirectory::new(address, true));
+            added.push(address.clone());
+        }
+        added
+    }
+
+    /// Note how a message to a listed server went outside a refresh (None if it didn't answer)
+    ///
+    /// The list isn't reordered, so the servers keep the order the last refresh chose.
+    pub fn record_contact(&mut self, address: &str, latency_ms: Option<u64>) {
+        let Some(server) = self.servers.iter_mut().find(|server| server.address == address) else {
+            return;
+        };
+        match latency_ms {
+            Some(latency_ms) => {
+                server.last_ok = Some(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs()));
+                server.latency_ms = Some(latency_ms);
+                server.failures = 0;
+            }
+            None => server.failures += 1,
+        }
+    }
+
+    /// Servers that answered the last time they were contacted
+    pub fn responsive(&self) -> Vec<String> {
+        self.servers.iter().filter(|server| server.is_healthy()).map(|server| server.address.clone()).collect()
+    }
+
+    /// Health-check every server, learn the replicas they advertise, and reorder the list
+    pub async fn refresh(&mut self) -> RefreshReport {
+        let advertised = self.check(None).await;
+        let learned = self.learn(&advertised);
+        if !learned.is_empty() {
+            info!("Learned {} directory replica(s): {}", learned.len(), learned.join(", "));
+            self.check(Some(&learned)).await;
+        }
+        self.rank();
+
+        RefreshReport {
+            healthy: self.servers.iter().filter(|server| server.is_healthy()).count(),
+            total: self.servers.len(),
+            learned,
+        }
+    }
+
+    /// Check the servers in `only` (or all of them), returning the replicas the healthy ones list
+    async fn check(&mut self, only: Option<&[String]>) -> Vec<String> {
+        let mut checks = JoinSet::new();
+        for server in &self.servers {
+            if only.is_some_and(|only| !only.contains(&server.address)) {
+                continue;
+            }
+            checks.spawn(check_server(server.address.clone()));
+        }
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+        let mut advertised = Vec::new();
+        while let Some(Ok((address, outcome))) = checks.join_next().await {
+            let Some(server) = self.servers.iter_mut().find(|server| server.address == address) else {
+                continue;
+            };
+            match outcome {
+                Some((latency_ms, replicas)) => {
+                    server.last_ok = Some(now);
+                    server.latency_ms = Some(latency_ms);
+                    server.failures = 0;
+                    advertised.extend(replicas);
+                }
+                None => server.failures += 1,
+            }
+        }
+        advertised
+    }
+
+    fn rank(&mut self) {
+        self.servers.retain(|server| !server.learned || server.failures < MAX_LEARNED_FAILURES);
+        self.servers.sort_by_key(|server| {
+            (!server.is_healthy(), server.failures, server.latency_ms.unwrap_or(u64::MAX))
+        });
+    }
+}
+
+/// Probe one server, returning its round trip and the replicas it advertises
+async fn check_server(address: String) -> (String, Option<(u64, Vec<String>)>) {
+    let started = Instant::now();
+    let probe = DirectoryMessage::QueryPeers { requesting_user: String::new() };
+    let alive = matches!(
+        timeout(HEALTH_CHECK_TIMEOUT, send_directory_message(&address, probe)).await,
+        Ok(Ok(DirectoryMessage::QueryPeersResponse { .. }))
+    );
+    if !alive {
+        return (address, None);
+    }
+    let latency_ms = started.elapsed().as_millis() as u64;
+
+    // Servers from before replica discovery don't answer this; they just advertise nothing
+    let replicas = match timeout(HEALTH_CHECK_TIMEOUT, send_directory_message(&address, DirectoryMessage::ListReplicas {})).await {
+        Ok(Ok(DirectoryMessage::ListReplicasResponse { replicas })) => replicas,
+        _ => Vec::new(),
+    };
+    (address, Some((latency_ms, replicas)))
+}
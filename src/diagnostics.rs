@@ -0,0 +1,183 @@
+use crate::directory_service::{send_directory_message, DirectoryMessage};
+use crate::encryption_pool::{encryption_stats, EncryptionStats};
+use crate::get_local_ip;
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, Instant};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+// =============================================================================
+// CONNECTION DIAGNOSTICS
+// =============================================================================
+
+/// How long each individual check may take before it counts as failed
+const CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Outcome of a single diagnostic check
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticCheck {
+    pub name: String,
+    /// Address or resource that was checked
+    pub target: String,
+    pub ok: bool,
+    pub latency_ms: Option<u64>,
+    pub detail: String,
+}
+
+/// Everything `run_diagnostics` found, in the order it was checked
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DiagnosticReport {
+    pub local_ip: DiagnosticCheck,
+    pub port_bind: DiagnosticCheck,
+    pub reachability: DiagnosticCheck,
+    pub directory_servers: Vec<DiagnosticCheck>,
+    pub encryption_servers: Vec<DiagnosticCheck>,
+    /// How recent encryption jobs went, for sizing the encryption cluster
+    pub encryption_stats: EncryptionStats,
+}
+
+impl DiagnosticReport {
+    /// Every check in the report
+    pub fn checks(&self) -> impl Iterator<Item = &DiagnosticCheck> {
+        [&self.local_ip, &self.port_bind, &self.reachability]
+            .into_iter()
+            .chain(self.directory_servers.iter())
+            .chain(self.encryption_servers.iter())
+    }
+
+    /// Whether every check passed
+    pub fn all_ok(&self) -> bool {
+        self.checks().all(|c| c.ok)
+    }
+}
+
+impl DiagnosticCheck {
+    fn new(name: &str, target: &str, ok: bool, latency_ms: Option<u64>, detail: String) -> Self {
+        Self {
+            name: name.to_string(),
+            target: target.to_string(),
+            ok,
+            latency_ms,
+            detail,
+        }
+    }
+}
+
+/// Run a check with the standard timeout, timing how long it took
+async fn timed<T>(check: impl Future<Output = anyhow::Result<T>>) -> (anyhow::Result<T>, u64) {
+    let started = Instant::now();
+    let result = match timeout(CHECK_TIMEOUT, check).await {
+        Ok(result) => result,
+        Err(_) => Err(anyhow::anyhow!("timed out after {}s", CHECK_TIMEOUT.as_secs())),
+    };
+    (result, started.elapsed().as_millis() as u64)
+}
+
+/// Check local networking, the P2P port, every directory replica, and the encryption servers,
+/// and report the timings of recent encryption jobs
+///
+/// Reachability is tested by asking the first responsive directory server to connect
+/// back to `p2p_port` on the address it sees us from.
+pub async fn run_diagnostics(
+    p2p_port: u16,
+    directory_servers: &[String],
+    encryption_servers: &[String],
+) -> DiagnosticReport {
+    let local_ip = match get_local_ip() {
+        Ok(ip) => DiagnosticCheck::new("Local IP", &ip, true, None, "Detected outbound interface".to_string()),
+        Err(e) => DiagnosticCheck::new("Local IP", "-", false, None, format!("Could not detect: {}", e)),
+    };
+
+    // Hold the port while probing so the directory has something to connect to
+    let port_target = format!("0.0.0.0:{}", p2p_port);
+    let listener = TcpListener::bind(&port_target).await;
+    let port_bind = match &listener {
+        Ok(_) => DiagnosticCheck::new("P2P port", &port_target, true, None, "Port is free to bind".to_string()),
+        Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => DiagnosticCheck::new(
+            "P2P port",
+            &port_target,
+            true,
+            None,
+            "Already in use (a peer may be running on it)".to_string(),
+        ),
+        Err(e) => DiagnosticCheck::new("P2P port", &port_target, false, None, format!("Cannot bind: {}", e)),
+    };
+    let accept_task = listener.ok().map(|listener| {
+        tokio::spawn(async move {
+            loop {
+                if listener.accept().await.is_err() {
+                    break;
+                }
+            }
+        })
+    });
+
+    let mut directory_checks = Vec::new();
+    let mut reachability = None;
+    for server in directory_servers {
+        let (result, latency) = timed(send_directory_message(server, DirectoryMessage::QueryPeers {
+            requesting_user: String::new(),
+        }))
+        .await;
+        let ok = matches!(result, Ok(DirectoryMessage::QueryPeersResponse { .. }));
+        let detail = match result {
+            Ok(DirectoryMessage::QueryPeersResponse { peers }) => format!("{} peers online", peers.len()),
+            Ok(_) => "Unexpected response".to_string(),
+            Err(e) => e.to_string(),
+        };
+        directory_checks.push(DiagnosticCheck::new("Directory server", server, ok, ok.then_some(latency), detail));
+
+        if ok && reachability.is_none() {
+            reachability = Some(probe_reachability(server, p2p_port).await);
+        }
+    }
+    let reachability = reachability.unwrap_or_else(|| {
+        DiagnosticCheck::new(
+            "Reachability",
+            "-",
+            false,
+            None,
+            "No directory server available to probe back".to_string(),
+        )
+    });
+
+    if let Some(task) = accept_task {
+        task.abort();
+    }
+
+    let mut encryption_checks = Vec::new();
+    for server in encryption_servers {
+        let (result, latency) = timed(async { Ok(TcpStream::connect(server).await?) }).await;
+        encryption_checks.push(match result {
+            Ok(_) => DiagnosticCheck::new("Encryption server", server, true, Some(latency), "Accepting connections".to_string()),
+            Err(e) => DiagnosticCheck::new("Encryption server", server, false, None, e.to_string()),
+        });
+    }
+
+    DiagnosticReport {
+        local_ip,
+        port_bind,
+        reachability,
+        directory_servers: directory_checks,
+        encryption_servers: encryption_checks,
+        encryption_stats: encryption_stats(),
+    }
+}
+
+/// Ask a directory server to connect back to our P2P port
+async fn probe_reachability(directory_addr: &str, p2p_port: u16) -> DiagnosticCheck {
+    let (result, latency) = timed(send_directory_message(
+        directory_addr,
+        DirectoryMessage::ProbeReachability { port: p2p_port },
+    ))
+    .await;
+
+    match result {
+        Ok(DirectoryMessage::ProbeReachabilityResponse { reachable, observed_address, message }) => {
+            DiagnosticCheck::new("Reachability", &observed_address, reachable, reachable.then_some(latency), message)
+        }
+        Ok(_) => DiagnosticCheck::new("Reachability", directory_addr, false, None, "Unexpected response".to_string()),
+        Err(e) => DiagnosticCheck::new("Reachability", directory_addr, false, None, e.to_string()),
+    }
+}
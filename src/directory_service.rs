@@ -1,13 +1,19 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use crate::humantime;
+use crate::protocol_trace::{record_frame, TraceChannel, TraceDirection};
+use crate::relay::{parse_relay_address, Relay};
+use crate::search::{SearchIndex, DESCRIPTION_WEIGHT, NAME_WEIGHT};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use std::hash::{Hash, Hasher};
 use std::net::SocketAddr;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::{Duration, SystemTime};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::RwLock;
 use tokio::time::sleep;
@@ -21,9 +27,412 @@ use tokio::time::sleep;
 pub struct UserEntry {
     pub username: String,
     pub p2p_address: String,
+    /// Wall-clock time of the last heartbeat, for display only (machines disagree on time)
     pub last_heartbeat: SystemTime,
     pub status: UserStatus,
     pub shared_images: Vec<ImageInfo>,
+    /// P2P protocol features this peer advertises (see `p2p_protocol::local_capabilities`)
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+    /// Version assigned by the directory that last changed this entry; orders replicated updates
+    #[serde(default)]
+    pub sequence: u64,
+    /// When this directory last heard from (or about) the user, on its own monotonic clock
+    #[serde(skip)]
+    pub last_seen: Option<Instant>,
+    /// When the user says they're usually online
+    #[serde(default)]
+    pub availability: Option<AvailabilityWindow>,
+    /// Display name, avatar and bio the user chose to publish
+    #[serde(default)]
+    pub profile: UserProfile,
+    /// Users allowed to answer requests for this user's images
+    #[serde(default)]
+    pub delegates: Vec<Delegation>,
+    /// Images anonymous users may request
+    #[serde(default)]
+    pub anonymous_images: Vec<String>,
+    /// SHA-256 of the key the user last registered with; answering requests as them needs it
+    #[serde(default)]
+    pub key_hash: Option<String>,
+}
+
+impl UserEntry {
+    /// Whether this peer supports a protocol feature
+    ///
+    /// Peers that predate capability advertisement are assumed to support the legacy set.
+    pub fn supports(&self, capability: &str) -> bool {
+        if self.capabilities.is_empty() {
+            return crate::p2p_protocol::LEGACY_CAPABILITIES.contains(&capability);
+        }
+        self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /// Whether this entry is a newer version than `other`
+    ///
+    /// Equal sequences (including entries from directories that predate them) fall back to
+    /// the heartbeat time so every replica settles on the same winner.
+    pub fn supersedes(&self, other: &UserEntry) -> bool {
+        (self.sequence, self.last_heartbeat) > (other.sequence, other.last_heartbeat)
+    }
+
+    /// Whether `key` is the one this user registered with
+    pub fn holds_key(&self, key: Option<&str>) -> bool {
+        match (&self.key_hash, key) {
+            (Some(hash), Some(key)) => constant_time_eq(hash.as_bytes(), user_key_hash(key).as_bytes()),
+            _ => false,
+        }
+    }
+
+    /// Whether `user` may answer requests for `image_id` on this user's behalf
+    pub fn has_delegate(&self, user: &str, image_id: &str) -> bool {
+        self.delegates.iter().any(|d| d.delegate == user && d.covers(image_id))
+    }
+
+    /// Whether this is a hidden peer, reachable only through a relay under a pseudonymous handle
+    pub fn is_anonymous(&self) -> bool {
+        crate::relay::parse_relay_address(&self.p2p_address).is_some()
+    }
+
+    /// Whether anonymous users may request `image_id`
+    pub fn allows_anonymous(&self, image_id: &str) -> bool {
+        self.anonymous_images.iter().any(|id| id == image_id)
+    }
+
+    /// This entry as `viewer` may see it: only the images listed for them, without share link tokens
+    ///
+    /// `contacts` are the users this user follows. The user themselves sees everything.
+    pub fn catalog_for(&self, viewer: Option<&str>, contacts: Option<&HashSet<String>>) -> UserEntry {
+        if viewer == Some(self.username.as_str()) {
+            return self.clone();
+        }
+        let mut entry = self.clone();
+        entry.shared_images.retain(|image| image.listed_for(&self.username, viewer, contacts));
+        for image in &mut entry.shared_images {
+            image.link_token = None;
+        }
+        entry
+    }
+
+    /// Name to show for this user (their display name, if they set one)
+    pub fn display_name(&self) -> &str {
+        self.profile.display_name.as_deref().unwrap_or(&self.username)
+    }
+
+    /// How soon a request left for this user is likely to be answered
+    pub fn response_outlook(&self, now: SystemTime) -> ResponseOutlook {
+        if self.status == UserStatus::Online {
+            return ResponseOutlook::OnlineNow;
+        }
+        match self.availability {
+            Some(window) => match window.minutes_until_open(now) {
+                0 => ResponseOutlook::UsuallyOnlineNow,
+                minutes => ResponseOutlook::BackIn { minutes },
+            },
+            None => ResponseOutlook::Unknown,
+        }
+    }
+}
+
+/// A user archived after staying offline past the retention period
+///
+/// Archived users are left out of every query until they register again, which restores
+/// them, or an admin purges them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedUser {
+    pub entry: UserEntry,
+    pub archived_at: SystemTime,
+}
+
+/// Whether a replicated entry for `username` should be merged, restoring it if it was archived
+///
+/// An entry no newer than the one archived is how the user looked when they went quiet, so
+/// it stays archived; a newer one means they've been back since.
+fn admit_replicated(archived: &mut HashMap<String, ArchivedUser>, username: &str, entry: &UserEntry) -> bool {
+    match archived.get(username) {
+        Some(archived_user) if !entry.supersedes(&archived_user.entry) => false,
+        Some(_) => {
+            archived.remove(username);
+            true
+        }
+        None => true,
+    }
+}
+
+const MINUTES_PER_DAY: u16 = 24 * 60;
+
+/// Daily window in which a user is usually online, in UTC minutes since midnight
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AvailabilityWindow {
+    pub start_minute: u16,
+    /// Exclusive; a window that ends before it starts runs past midnight
+    pub end_minute: u16,
+}
+
+impl AvailabilityWindow {
+    pub fn new(start_minute: u16, end_minute: u16) -> Result<Self> {
+        if start_minute >= MINUTES_PER_DAY || end_minute >= MINUTES_PER_DAY {
+            bail!("Times must be between 00:00 and 23:59");
+        }
+        if start_minute == end_minute {
+            bail!("Availability window can't start and end at the same time");
+        }
+        Ok(Self { start_minute, end_minute })
+    }
+
+    /// Parse a UTC window written as `HH:MM-HH:MM`
+    pub fn parse(text: &str) -> Result<Self> {
+        let Some((start, end)) = text.split_once('-') else {
+            bail!("Expected HH:MM-HH:MM, got '{}'", text);
+        };
+        Self::new(parse_minute(start)?, parse_minute(end)?)
+    }
+
+    pub fn contains(&self, minute: u16) -> bool {
+        if self.start_minute < self.end_minute {
+            (self.start_minute..self.end_minute).contains(&minute)
+        } else {
+            minute >= self.start_minute || minute < self.end_minute
+        }
+    }
+
+    /// Minutes from `now` until the window next opens (0 while it's open)
+    pub fn minutes_until_open(&self, now: SystemTime) -> u32 {
+        let minute = now
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| (d.as_secs() / 60 % MINUTES_PER_DAY as u64) as u16)
+            .unwrap_or(0);
+        if self.contains(minute) {
+            return 0;
+        }
+        ((self.start_minute + MINUTES_PER_DAY - minute) % MINUTES_PER_DAY) as u32
+    }
+}
+
+impl std::fmt::Display for AvailabilityWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:02}:{:02}-{:02}:{:02} UTC",
+            self.start_minute / 60,
+            self.start_minute % 60,
+            self.end_minute / 60,
+            self.end_minute % 60
+        )
+    }
+}
+
+fn parse_minute(text: &str) -> Result<u16> {
+    let Some((hours, minutes)) = text.trim().split_once(':') else {
+        bail!("Expected HH:MM, got '{}'", text.trim());
+    };
+    let (Ok(hours), Ok(minutes)) = (hours.parse::<u16>(), minutes.parse::<u16>()) else {
+        bail!("Expected HH:MM, got '{}'", text.trim());
+    };
+    if hours >= 24 || minutes >= 60 {
+        bail!("Invalid time '{}'", text.trim());
+    }
+    Ok(hours * 60 + minutes)
+}
+
+/// Most status changes kept per user
+const PRESENCE_HISTORY_LIMIT: usize = 64;
+
+/// Only this much recent history counts towards when a user is typically online
+const PRESENCE_WINDOW: Duration = Duration::from_secs(14 * 24 * 60 * 60);
+
+/// History needed before guessing when a user is typically online
+const MIN_PRESENCE_PATTERN: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Share of a part of the day a user must be online in for it to count as typical
+const TYPICAL_ONLINE_SHARE: f64 = 0.5;
+
+/// Parts of the day, by local hour
+const DAY_PERIODS: [(&str, std::ops::Range<usize>); 4] = [
+    ("nights", 0..6),
+    ("mornings", 6..12),
+    ("afternoons", 12..18),
+    ("evenings", 18..24),
+];
+
+/// A user coming online or going offline
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PresenceChange {
+    pub at: SystemTime,
+    pub online: bool,
+}
+
+/// A user's recent online/offline changes, as one directory saw them
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PresenceHistory {
+    pub username: String,
+    pub online: bool,
+    /// Wall-clock time of the user's last heartbeat
+    pub last_seen: SystemTime,
+    /// Oldest first
+    pub changes: Vec<PresenceChange>,
+}
+
+impl PresenceHistory {
+    /// Share of each UTC hour of the day the user spent online over the last two weeks
+    pub fn hourly_online(&self, now: SystemTime) -> [f64; 24] {
+        let (online, observed) = self.hourly_seconds(now);
+        std::array::from_fn(|hour| match observed[hour] {
+            0 => 0.0,
+            total => online[hour] as f64 / total as f64,
+        })
+    }
+
+    /// When the user is typically online in this machine's local time, e.g. `evenings`,
+    /// once there's a day of history to go on
+    pub fn typical_period(&self, now: SystemTime) -> Option<&'static str> {
+        let (utc_online, utc_observed) = self.hourly_seconds(now);
+        let shift = (local_utc_offset_secs(now) as f64 / 3600.0).round() as i64;
+        let local = |hours: [u64; 24]| -> [u64; 24] {
+            std::array::from_fn(|hour| hours[(hour as i64 - shift).rem_euclid(24) as usize])
+        };
+        let (online, observed) = (local(utc_online), local(utc_observed));
+        if observed.iter().sum::<u64>() < MIN_PRESENCE_PATTERN.as_secs() {
+            return None;
+        }
+        let shares: Vec<(&'static str, f64)> = DAY_PERIODS
+            .iter()
+            .map(|(name, hours)| {
+                let total: u64 = observed[hours.clone()].iter().sum();
+                let share = if total == 0 { 0.0 } else { online[hours.clone()].iter().sum::<u64>() as f64 / total as f64 };
+                (*name, share)
+            })
+            .collect();
+        if shares.iter().all(|(_, share)| *share >= TYPICAL_ONLINE_SHARE) {
+            return Some("around the clock");
+        }
+        shares
+            .into_iter()
+            .filter(|(_, share)| *share >= TYPICAL_ONLINE_SHARE)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(name, _)| name)
+    }
+
+    /// e.g. `last seen 2 hours ago, typically online evenings`
+    pub fn summary(&self, now: SystemTime) -> String {
+        let seen = if self.online {
+            "online now".to_string()
+        } else {
+            format!("last seen {}", humantime::ago(now.duration_since(self.last_seen).unwrap_or_default()))
+        };
+        match self.typical_period(now) {
+            Some(period) => format!("{}, typically online {}", seen, period),
+            None => seen,
+        }
+    }
+
+    /// Seconds online and seconds observed in each UTC hour of the day, within the recent window
+    fn hourly_seconds(&self, now: SystemTime) -> ([u64; 24], [u64; 24]) {
+        let unix_secs = |t: SystemTime| t.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let window_start = unix_secs(now).saturating_sub(PRESENCE_WINDOW.as_secs());
+        let (mut online, mut observed) = ([0u64; 24], [0u64; 24]);
+
+        let ends = self.changes.iter().skip(1).map(|c| c.at).chain(std::iter::once(now));
+        for (change, end) in self.changes.iter().zip(ends) {
+            let end = unix_secs(end);
+            let mut at = unix_secs(change.at).max(window_start);
+            while at < end {
+                let hour = (at / 3600 % 24) as usize;
+                let next = ((at / 3600 + 1) * 3600).min(end);
+                observed[hour] += next - at;
+                if change.online {
+                    online[hour] += next - at;
+                }
+                at = next;
+            }
+        }
+        (online, observed)
+    }
+}
+
+/// Longest display name accepted, in characters
+pub const MAX_DISPLAY_NAME_CHARS: usize = 64;
+
+/// Longest bio accepted, in characters
+pub const MAX_BIO_CHARS: usize = 500;
+
+/// Largest avatar thumbnail accepted (it travels with every peer query)
+pub const MAX_AVATAR_BYTES: usize = 16 * 1024;
+
+/// Side length avatars are scaled to
+pub const AVATAR_SIZE: u32 = 64;
+
+/// A user allowed to answer requests for some of an owner's images
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Delegation {
+    pub delegate: String,
+    /// Images they may answer for (every image if empty)
+    #[serde(default)]
+    pub image_ids: Vec<String>,
+}
+
+impl Delegation {
+    pub fn covers(&self, image_id: &str) -> bool {
+        self.image_ids.is_empty() || self.image_ids.iter().any(|id| id == image_id)
+    }
+}
+
+/// Optional profile details shown alongside a username
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserProfile {
+    pub display_name: Option<String>,
+    /// PNG thumbnail, at most `MAX_AVATAR_BYTES`
+    pub avatar_thumbnail: Option<Vec<u8>>,
+    pub bio: Option<String>,
+}
+
+impl UserProfile {
+    /// Check the limits the directory enforces, trimming text and dropping empty fields
+    pub fn validated(self) -> Result<Self> {
+        let trimmed = |text: Option<String>| text.map(|t| t.trim().to_string()).filter(|t| !t.is_empty());
+        let profile = Self {
+            display_name: trimmed(self.display_name),
+            avatar_thumbnail: self.avatar_thumbnail.filter(|bytes| !bytes.is_empty()),
+            bio: trimmed(self.bio),
+        };
+
+        if profile.display_name.as_ref().is_some_and(|name| name.chars().count() > MAX_DISPLAY_NAME_CHARS) {
+            bail!("Display name is longer than {} characters", MAX_DISPLAY_NAME_CHARS);
+        }
+        if profile.bio.as_ref().is_some_and(|bio| bio.chars().count() > MAX_BIO_CHARS) {
+            bail!("Bio is longer than {} characters", MAX_BIO_CHARS);
+        }
+        if let Some(avatar) = &profile.avatar_thumbnail {
+            if avatar.len() > MAX_AVATAR_BYTES {
+                bail!("Avatar is {} KB - at most {} KB is allowed", avatar.len() / 1024, MAX_AVATAR_BYTES / 1024);
+            }
+            if image::load_from_memory(avatar).is_err() {
+                bail!("Avatar is not a readable image");
+            }
+        }
+        Ok(profile)
+    }
+}
+
+/// Scale an image to a square avatar thumbnail (PNG)
+pub fn avatar_thumbnail(image_bytes: &[u8]) -> Result<Vec<u8>> {
+    let img = image::load_from_memory(image_bytes)?;
+    let thumbnail = img.resize_to_fill(AVATAR_SIZE, AVATAR_SIZE, image::imageops::FilterType::Triangle);
+    let mut png = Vec::new();
+    thumbnail.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Whether a request is likely to be answered soon or will wait for the owner to come back
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ResponseOutlook {
+    OnlineNow,
+    /// Offline, but inside their usual availability window
+    UsuallyOnlineNow,
+    /// Offline until their availability window opens
+    BackIn { minutes: u32 },
+    /// Offline with no published availability
+    Unknown,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -32,11 +441,232 @@ pub enum UserStatus {
     Offline,
 }
 
+/// Who can find a shared image in the directory, and who can request it
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ImageVisibility {
+    /// Listed for everyone
+    #[default]
+    Public,
+    /// Listed for, and requestable by, users the owner follows
+    ContactsOnly,
+    /// Never listed; requestable by whoever has its share link
+    LinkOnly,
+    /// Never listed or requestable; grants already made keep working
+    Hidden,
+}
+
+impl ImageVisibility {
+    /// Parse `public`, `contacts-only`, `link-only` or `hidden`
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_ascii_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "public" => Ok(ImageVisibility::Public),
+            "contacts" | "contactsonly" => Ok(ImageVisibility::ContactsOnly),
+            "link" | "linkonly" => Ok(ImageVisibility::LinkOnly),
+            "hidden" => Ok(ImageVisibility::Hidden),
+            _ => bail!("Expected public, contacts-only, link-only or hidden, got '{}'", text.trim()),
+        }
+    }
+}
+
+impl std::fmt::Display for ImageVisibility {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ImageVisibility::Public => "public",
+            ImageVisibility::ContactsOnly => "contacts only",
+            ImageVisibility::LinkOnly => "link only",
+            ImageVisibility::Hidden => "hidden",
+        })
+    }
+}
+
+/// Share link for a link-only image: its qualified ID and the token that unlocks requests
+pub fn share_link(owner: &str, image_id: &str, token: &str) -> String {
+    format!("{}#{}", qualified_image_id(owner, image_id), token)
+}
+
+/// Split a share link into (owner, image ID, token)
+pub fn parse_share_link(link: &str) -> Result<(String, String, String)> {
+    let parts = link.trim().split_once('#')
+        .and_then(|(qualified, token)| Some((qualified.split_once('/')?, token)));
+    match parts {
+        Some(((owner, image_id), token)) if !owner.is_empty() && !image_id.is_empty() && !token.is_empty() => {
+            Ok((owner.to_string(), image_id.to_string(), token.to_string()))
+        }
+        _ => bail!("Malformed share link '{}' (expected <owner>/<image_id>#<token>)", link),
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ImageInfo {
     pub image_id: String,
     pub image_name: String,
     pub thumbnail_path: Option<String>,
+    /// Maximum views the owner will grant per request (None = no cap)
+    #[serde(default)]
+    pub max_grant_views: Option<u32>,
+    /// Caption embedded by the owner, so peers can search catalogs by it
+    #[serde(default)]
+    pub caption: Option<String>,
+    #[serde(default)]
+    pub visibility: ImageVisibility,
+    /// Token a link-only image's share link carries; only ever sent to the directory by its owner
+    #[serde(default)]
+    pub link_token: Option<String>,
+    /// Left by owners who opted in, so the image can be browsed while they're offline
+    #[serde(default)]
+    pub preview: Option<EscrowedPreview>,
+}
+
+/// Largest escrowed preview accepted (it travels with every catalog query)
+pub const MAX_PREVIEW_BYTES: usize = 16 * 1024;
+
+/// Blurred thumbnail and details an owner leaves with the directory for one image
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EscrowedPreview {
+    /// Blurred PNG, at most `MAX_PREVIEW_BYTES`
+    pub thumbnail: Vec<u8>,
+    pub file_size_kb: u64,
+}
+
+/// Drop previews over `MAX_PREVIEW_BYTES` or that aren't images, keeping the rest of the catalog
+fn checked_previews(owner: &str, mut images: Vec<ImageInfo>) -> Vec<ImageInfo> {
+    for image in &mut images {
+        let problem = match &image.preview {
+            Some(preview) if preview.thumbnail.len() > MAX_PREVIEW_BYTES => "too large",
+            Some(preview) if image::load_from_memory(&preview.thumbnail).is_err() => "not a readable image",
+            _ => continue,
+        };
+        warn!("Dropping {}'s preview of {}: {}", owner, image.image_id, problem);
+        image.preview = None;
+    }
+    images
+}
+
+impl ImageInfo {
+    /// Whether `owner`'s image shows up in catalogs and searches for `viewer`
+    pub fn listed_for(&self, owner: &str, viewer: Option<&str>, contacts: Option<&HashSet<String>>) -> bool {
+        match self.visibility {
+            _ if viewer == Some(owner) => true,
+            ImageVisibility::Public => true,
+            ImageVisibility::ContactsOnly => viewer.is_some_and(|viewer| contacts.is_some_and(|c| c.contains(viewer))),
+            ImageVisibility::LinkOnly | ImageVisibility::Hidden => false,
+        }
+    }
+}
+
+/// An image ID `owner` shares that other owners share too
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageIdCollision {
+    pub image_id: String,
+    pub other_owners: Vec<String>,
+}
+
+/// An owner sharing an image that matched a `QueryImageHolders` search
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImageHolder {
+    pub owner: String,
+    pub status: UserStatus,
+    pub image: ImageInfo,
+}
+
+/// A shared image whose name or caption matched a `SearchCatalog` query
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogMatch {
+    pub owner: String,
+    pub status: UserStatus,
+    pub image: ImageInfo,
+    /// How well the image matched, 0-1
+    pub score: f32,
+    /// What the searching user already holds on the image, if they said who they are
+    #[serde(default)]
+    pub your_grant: Option<ExistingGrant>,
+}
+
+/// Catalog search results returned when no limit is given
+pub const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+/// Search index over every shared catalog, keyed by (owner, image ID)
+#[derive(Default)]
+struct CatalogIndex {
+    index: SearchIndex<(String, String)>,
+    /// Owner -> fingerprint of the catalog last indexed for them
+    indexed: HashMap<String, u64>,
+}
+
+impl CatalogIndex {
+    /// Re-index owners whose catalog changed since the last search and drop departed ones
+    fn refresh(&mut self, users: &HashMap<String, UserEntry>) {
+        let departed: Vec<String> = self.indexed.keys().filter(|owner| !users.contains_key(*owner)).cloned().collect();
+        for owner in departed {
+            self.reindex(&owner, &[]);
+        }
+        for user in users.values() {
+            let fingerprint = catalog_fingerprint(&user.shared_images);
+            if self.indexed.get(&user.username) != Some(&fingerprint) {
+                self.reindex(&user.username, &user.shared_images);
+                self.indexed.insert(user.username.clone(), fingerprint);
+            }
+        }
+    }
+
+    fn reindex(&mut self, owner: &str, images: &[ImageInfo]) {
+        let stale: Vec<(String, String)> = self
+            .index
+            .keys()
+            .filter(|(indexed_owner, _)| indexed_owner == owner)
+            .cloned()
+            .collect();
+        for key in stale {
+            self.index.remove(&key);
+        }
+        for image in images {
+            self.index.insert((owner.to_string(), image.image_id.clone()), &[
+                (&image.image_name, NAME_WEIGHT),
+                (image.caption.as_deref().unwrap_or_default(), DESCRIPTION_WEIGHT),
+            ]);
+        }
+        self.indexed.remove(owner);
+    }
+}
+
+/// Hash of the searchable parts of a catalog
+fn catalog_fingerprint(images: &[ImageInfo]) -> u64 {
+    let mut hasher = std::hash::DefaultHasher::new();
+    for image in images {
+        (&image.image_id, &image.image_name, &image.caption).hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+/// Image ID namespaced by its owner (`owner/image_id`), unambiguous across the network
+pub fn qualified_image_id(owner: &str, image_id: &str) -> String {
+    format!("{}/{}", owner, image_id)
+}
+
+/// Strip `owner/` from an image ID, rejecting IDs qualified with a different owner
+///
+/// Unqualified IDs are returned as they are (IDs never contain `/`).
+pub fn unqualify_image_id<'a>(owner: &str, image_id: &'a str) -> Result<&'a str> {
+    match image_id.split_once('/') {
+        Some((qualifier, id)) if qualifier == owner => Ok(id),
+        Some((qualifier, _)) => bail!("Image {} belongs to {}, not {}", image_id, qualifier, owner),
+        None => Ok(image_id),
+    }
+}
+
+/// Key of a queued permission update (one per owner, target and image)
+fn pending_update_id(from_owner: &str, target_user: &str, image_id: &str) -> String {
+    format!("{}:{}:{}", from_owner, target_user, image_id)
+}
+
+/// Commit lock for changes to the requests made to `owner`
+fn requests_lock(owner: &str) -> String {
+    format!("requests:{}", owner)
+}
+
+/// Commit lock for changes to the updates queued for `target_user`
+fn updates_lock(target_user: &str) -> String {
+    format!("updates:{}", target_user)
 }
 
 /// Pending image request notification
@@ -49,6 +679,101 @@ pub struct PendingRequest {
     pub requested_views: u32,
     pub timestamp: SystemTime,
     pub status: RequestStatus,
+    /// Requester already holds identical image bytes and only needs a permission update
+    #[serde(default)]
+    pub delta_only: bool,
+    /// Requester asked for a copy no larger than this on its longer side
+    #[serde(default)]
+    pub requested_max_dimension: Option<u32>,
+    /// Delegate who answered on the owner's behalf (None if the owner did)
+    #[serde(default)]
+    pub approved_by: Option<String>,
+    /// Views granted when accepting, if different from those requested (a counter-offer)
+    #[serde(default)]
+    pub granted_views: Option<u32>,
+    /// Accepted, but the image hasn't been delivered or stored for the requester yet
+    #[serde(default)]
+    pub awaiting_delivery: bool,
+    /// When the request was accepted or rejected, on this directory's clock
+    #[serde(default)]
+    pub responded_at: Option<SystemTime>,
+    /// Why the owner's side answered as it did, shown to the requester
+    #[serde(default)]
+    pub response_note: Option<String>,
+    /// Issued on acceptance; the owner's peer only raises the requester's quota with it,
+    /// and only until the request is marked delivered. Only handed to whoever accepted
+    /// (checked against their registration key) and stripped from the requester's notifications
+    #[serde(default)]
+    pub grant_token: Option<String>,
+    /// Version assigned by the directory that last changed this request; orders replicated changes
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+impl PendingRequest {
+    /// Views the requester gets if the request is accepted
+    pub fn views_to_grant(&self) -> u32 {
+        self.granted_views.unwrap_or(self.requested_views)
+    }
+}
+
+/// Views a user was last granted on another user's image, as the directory heard of it
+///
+/// Recorded when a request is accepted and kept up to date by the owner's queued permission
+/// updates; views used up since aren't known here.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExistingGrant {
+    pub owner: String,
+    pub image_id: String,
+    pub recipient: String,
+    pub views: u32,
+    pub granted_at: SystemTime,
+}
+
+/// Keep the newest grant per (owner, image, recipient) from grants gathered on several replicas
+fn merge_grants(grants: &mut Vec<ExistingGrant>, found: Vec<ExistingGrant>) {
+    for grant in found {
+        match grants.iter_mut().find(|g| g.owner == grant.owner && g.image_id == grant.image_id && g.recipient == grant.recipient) {
+            Some(existing) if existing.granted_at >= grant.granted_at => {}
+            Some(existing) => *existing = grant,
+            None => grants.push(grant),
+        }
+    }
+}
+
+/// A requester whose requests to an owner were refused for having too many outstanding
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThrottledSender {
+    pub sender: String,
+    /// Requests refused since `first_rejected`
+    pub rejected: u32,
+    pub first_rejected: SystemTime,
+    pub last_rejected: SystemTime,
+}
+
+/// Which pending requests a bulk response applies to (unset fields match every request)
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RequestFilter {
+    #[serde(default)]
+    pub from_user: Option<String>,
+    /// Image ID, optionally qualified as `owner/image_id`
+    #[serde(default)]
+    pub image_id: Option<String>,
+    /// Only requests for at most this many views
+    #[serde(default)]
+    pub max_views: Option<u32>,
+}
+
+impl RequestFilter {
+    pub fn matches(&self, request: &PendingRequest) -> bool {
+        let image_id = self
+            .image_id
+            .as_deref()
+            .map(|id| unqualify_image_id(&request.to_user, id).unwrap_or(id));
+        self.from_user.as_ref().is_none_or(|user| *user == request.from_user)
+            && image_id.is_none_or(|id| id == request.image_id)
+            && self.max_views.is_none_or(|max| request.requested_views <= max)
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
@@ -58,6 +783,16 @@ pub enum RequestStatus {
     Rejected,
 }
 
+/// A request that has waited on its owner long enough to nudge them about
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Reminder {
+    pub request_id: String,
+    pub from_user: String,
+    pub image_id: String,
+    pub requested_views: u32,
+    pub requested_at: SystemTime,
+}
+
 /// Pending permission update (for offline users)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PendingPermissionUpdate {
@@ -68,9 +803,149 @@ pub struct PendingPermissionUpdate {
     pub new_quota: u32,
     pub timestamp: SystemTime,
     /// The embedded image data to deliver when the user comes online
+    /// (only populated on the wire - the directory keeps it in the blob store)
     pub embedded_image: Option<Vec<u8>>,
+    /// Hash of the stored image blob, if one is held for this update
+    #[serde(default)]
+    pub blob_sha256: Option<String>,
+    /// Delete the target's copy instead of changing its quota
+    #[serde(default)]
+    pub wipe: bool,
+    /// When the directory gives up on delivering this update
+    #[serde(default)]
+    pub expires_at: Option<SystemTime>,
+    /// Version assigned by the directory that last changed this update; orders replicated changes
+    #[serde(default)]
+    pub sequence: u64,
+}
+
+/// Requests and queued permission updates changed together by one commit
+///
+/// Updates carry their image inline in `embedded_image`; each replica keeps it in its own blob store.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueuedChanges {
+    /// Requests created or changed (the newest `sequence` of each wins)
+    #[serde(default)]
+    pub requests: Vec<PendingRequest>,
+    #[serde(default)]
+    pub removed_requests: Vec<String>,
+    /// Permission updates queued or replaced (the newest `sequence` of each wins)
+    #[serde(default)]
+    pub updates: Vec<PendingPermissionUpdate>,
+    #[serde(default)]
+    pub removed_updates: Vec<String>,
+}
+
+impl QueuedChanges {
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.removed_requests.is_empty() && self.updates.is_empty() && self.removed_updates.is_empty()
+    }
+}
+
+/// A pending update the directory dropped because its target never came back for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpiredDelivery {
+    pub update_id: String,
+    pub from_owner: String,
+    pub target_user: String,
+    pub image_id: String,
+    pub new_quota: u32,
+    pub wipe: bool,
+    /// Whether an image was still queued with the update when it expired
+    pub had_image: bool,
+    pub queued_at: SystemTime,
+    pub expired_at: SystemTime,
+}
+
+/// Where a trade is in its lifecycle
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub enum TradeStatus {
+    /// Waiting for the counterparty to accept
+    Proposed,
+    /// Both parties agreed; waiting for both images to be deposited
+    Accepted,
+    /// Both images queued for delivery
+    Completed,
+    Rejected,
+    Cancelled,
+}
+
+/// One owner's half of a trade
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeSide {
+    pub owner: String,
+    pub image_id: String,
+    /// Views the other party receives on this image
+    pub views: u32,
+    /// Blob hash of the escrowed image, once the owner has deposited it
+    #[serde(default)]
+    pub deposit_sha256: Option<String>,
+}
+
+/// An owner-to-owner image swap held by the directory until both sides are deposited
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TradeProposal {
+    pub trade_id: String,
+    pub proposer: TradeSide,
+    pub counterparty: TradeSide,
+    pub status: TradeStatus,
+    pub timestamp: SystemTime,
+}
+
+impl TradeProposal {
+    /// The side `username` gives, and the side they receive
+    pub fn sides_for(&self, username: &str) -> Option<(&TradeSide, &TradeSide)> {
+        if self.proposer.owner == username {
+            Some((&self.proposer, &self.counterparty))
+        } else if self.counterparty.owner == username {
+            Some((&self.counterparty, &self.proposer))
+        } else {
+            None
+        }
+    }
+
+    /// Whether the trade can still change (not completed, rejected, or cancelled)
+    pub fn is_open(&self) -> bool {
+        matches!(self.status, TradeStatus::Proposed | TradeStatus::Accepted)
+    }
+
+    fn deposits(&self) -> impl Iterator<Item = &String> {
+        [&self.proposer, &self.counterparty]
+            .into_iter()
+            .filter_map(|side| side.deposit_sha256.as_ref())
+    }
+}
+
+/// How long an accepted request's first delivery attempt has before it's offered for retry
+const DELIVERY_GRACE: Duration = Duration::from_secs(2 * 60);
+
+/// How long finished trades are kept so both parties can see the outcome
+const TRADE_HISTORY_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+/// A change to a user's shared catalog (images added or removed)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CatalogChange {
+    pub owner: String,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub timestamp: SystemTime,
 }
 
+/// Number of catalog changes kept in memory
+const MAX_CATALOG_CHANGES: usize = 500;
+
+/// Number of undelivered access denial reports kept for offline owners
+const MAX_ACCESS_DENIALS: usize = 1000;
+
+/// Number of expiry notices kept for owners who haven't collected them
+const MAX_EXPIRED_DELIVERIES: usize = 1000;
+
+/// Image bytes loaded into one inline pending-update fetch (older clients); the rest wait for the next poll
+const MAX_UPDATE_FETCH_BYTES: u64 = 64 * 1024 * 1024;
+
+/// How long an owner is told about a throttled requester after the last refused request
+const THROTTLE_NOTE_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
 /// Directory service messages
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DirectoryMessage {
@@ -78,16 +953,35 @@ pub enum DirectoryMessage {
         username: String,
         p2p_address: String,
         shared_images: Vec<ImageInfo>,
+        #[serde(default)]
+        capabilities: Vec<String>,
+        #[serde(default)]
+        availability: Option<AvailabilityWindow>,
+        /// Client-held secret; whoever registered with it may answer requests as this user
+        #[serde(default)]
+        key: Option<String>,
     },
     RegisterResponse {
         success: bool,
         message: String,
+        /// How often this server expects heartbeats (None from older servers)
+        #[serde(default)]
+        heartbeat_interval_secs: Option<u64>,
+        /// How long without a heartbeat before a peer is marked offline
+        #[serde(default)]
+        heartbeat_timeout_secs: Option<u64>,
     },
     Heartbeat {
         username: String,
+        /// Updated capabilities (None = unchanged)
+        #[serde(default)]
+        capabilities: Option<Vec<String>>,
     },
     HeartbeatResponse {
         success: bool,
+        /// Requests that have waited on this user past the reminder threshold (0 from older servers)
+        #[serde(default)]
+        due_reminders: usize,
     },
     Unregister {
         username: String,
@@ -108,26 +1002,92 @@ pub enum DirectoryMessage {
     QueryAllPeersResponse {
         peers: Vec<UserEntry>,
     },
-    UpdateSharedImages {
-        username: String,
+    /// Find who shares an image, by (optionally `owner/`-qualified) ID or content hash prefix
+    QueryImageHolders {
+        image_id_or_hash: String,
+        /// Who is asking, so images shared with their contacts are included
+        #[serde(default)]
+        requesting_user: Option<String>,
+    },
+    QueryImageHoldersResponse {
+        holders: Vec<ImageHolder>,
+    },
+    /// Search every shared catalog by image name and caption, tolerating typos and partial words
+    SearchCatalog {
+        query: String,
+        #[serde(default)]
+        limit: Option<usize>,
+        /// Attach this user's existing grant to each match
+        #[serde(default)]
+        requesting_user: Option<String>,
+    },
+    /// Best match first, online owners ahead of offline ones on equal scores
+    SearchCatalogResponse {
+        results: Vec<CatalogMatch>,
+    },
+    UpdateSharedImages {
+        username: String,
         shared_images: Vec<ImageInfo>,
     },
+    /// Replace a user's profile (answered with `UpdateResponse`)
+    UpdateProfile {
+        username: String,
+        profile: UserProfile,
+    },
     UpdateResponse {
         success: bool,
         message: String,
     },
     QueryUser {
         username: String,
+        /// Also answer with what this user already holds on `username`'s images
+        #[serde(default)]
+        requesting_user: Option<String>,
     },
     QueryUserResponse {
         user: Option<UserEntry>,
+        /// `requesting_user`'s grants on the user's images (empty if none was given)
+        #[serde(default)]
+        grants: Vec<ExistingGrant>,
+    },
+    /// When a user has recently come online and gone offline
+    GetPresenceHistory {
+        username: String,
+    },
+    GetPresenceHistoryResponse {
+        history: Option<PresenceHistory>,
     },
     SyncState {
         users: HashMap<String, UserEntry>,
+        /// Set when the entries are only proposed: they're held until a `CommitState` for the
+        /// proposal, and applied at once otherwise (older replicas always apply them at once)
+        #[serde(default)]
+        proposal_id: Option<String>,
+        /// Requests and queued updates changed along with the entries (older replicas ignore them)
+        #[serde(default, skip_serializing_if = "QueuedChanges::is_empty")]
+        queued: QueuedChanges,
     },
     SyncStateResponse {
         success: bool,
     },
+    /// Apply (or drop) entries proposed by another replica, once it knows whether a majority holds them
+    CommitState {
+        proposal_id: String,
+        commit: bool,
+    },
+    CommitStateResponse {
+        /// Whether the proposal was still held here
+        success: bool,
+    },
+    /// A peer directory server announcing it is leaving or back online
+    PeerStatus {
+        server_id: String,
+        port: u16,
+        leaving: bool,
+    },
+    PeerStatusResponse {
+        success: bool,
+    },
 
     // Asynchronous request system
     LeaveRequest {
@@ -135,6 +1095,13 @@ pub enum DirectoryMessage {
         to_user: String,
         image_id: String,
         requested_views: u32,
+        #[serde(default)]
+        delta_only: bool,
+        #[serde(default)]
+        requested_max_dimension: Option<u32>,
+        /// Token from the share link, needed to request a link-only image
+        #[serde(default)]
+        share_token: Option<String>,
     },
     LeaveRequestResponse {
         success: bool,
@@ -147,22 +1114,128 @@ pub enum DirectoryMessage {
     GetPendingRequestsResponse {
         requests: Vec<PendingRequest>,
     },
+    /// Requesters recently refused for flooding `username` with requests
+    GetRequestThrottles {
+        username: String,
+    },
+    GetRequestThrottlesResponse {
+        throttled: Vec<ThrottledSender>,
+    },
+    /// Answer a request, as its recipient (`owner`) or one of their delegates
+    ///
+    /// Accepting leaves the request awaiting delivery until `MarkRequestDelivered`.
     RespondToRequest {
         request_id: String,
         owner: String,
         accept: bool,
+        /// Views to grant instead of those requested
+        #[serde(default)]
+        granted_views: Option<u32>,
+        /// Explanation passed on to the requester
+        #[serde(default)]
+        note: Option<String>,
+        /// The key `owner` registered with
+        #[serde(default)]
+        key: Option<String>,
     },
     RespondToRequestResponse {
         success: bool,
         message: String,
         request: Option<PendingRequest>,
     },
+    /// Ask, as the owner's peer, how many views a grant token lets `user` be raised to
+    VerifyGrant {
+        owner: String,
+        user: String,
+        image_id: String,
+        token: String,
+    },
+    /// None if the token doesn't grant `user` anything on the image
+    VerifyGrantResponse {
+        views: Option<u32>,
+    },
+    /// The image for an accepted request was delivered or stored for the requester
+    /// (answered with `UpdateResponse`)
+    MarkRequestDelivered {
+        request_id: String,
+        /// The owner, or the delegate who accepted the request
+        username: String,
+        /// The key `username` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    /// Accepted requests to `owner` whose delivery never completed (answered with
+    /// `GetPendingRequestsResponse`)
+    GetUndeliveredGrants {
+        owner: String,
+        /// The key `owner` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    /// Accept or reject every pending request to `owner` matching `filter` in one go
+    RespondToRequestsBulk {
+        owner: String,
+        #[serde(default)]
+        filter: RequestFilter,
+        accept: bool,
+        /// The key `owner` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    RespondToRequestsBulkResponse {
+        /// The requests responded to, with their new status
+        requests: Vec<PendingRequest>,
+    },
     GetNotifications {
         username: String,
     },
     GetNotificationsResponse {
         notifications: Vec<PendingRequest>,
     },
+    /// Pending requests to `owner` that have waited past the reminder threshold and aren't snoozed
+    GetReminders {
+        owner: String,
+    },
+    GetRemindersResponse {
+        reminders: Vec<Reminder>,
+    },
+    /// Stop reminding `owner` about a request for `snooze_secs`, or for good if unset
+    /// (answered with `UpdateResponse`)
+    SnoozeReminder {
+        owner: String,
+        request_id: String,
+        #[serde(default)]
+        snooze_secs: Option<u64>,
+    },
+    /// Let `delegate` answer requests for `owner`'s images (every image if `image_ids` is empty),
+    /// or stop them (answered with `UpdateResponse`)
+    SetDelegate {
+        owner: String,
+        delegate: String,
+        #[serde(default)]
+        image_ids: Vec<String>,
+        enabled: bool,
+        /// The key `owner` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    /// Pending requests `delegate` may answer on their owners' behalf (answered with
+    /// `GetPendingRequestsResponse`)
+    GetDelegatedRequests {
+        delegate: String,
+    },
+    /// Withdraw a pending request (only the original requester may cancel)
+    CancelRequest {
+        request_id: String,
+        from_user: String,
+        /// The key `from_user` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    CancelRequestResponse {
+        success: bool,
+        message: String,
+    },
     /// Store a pending permission update for an offline user
     StorePendingPermissionUpdate {
         from_owner: String,
@@ -171,6 +1244,12 @@ pub enum DirectoryMessage {
         new_quota: u32,
         /// The embedded image data to deliver when the user comes online
         embedded_image: Option<Vec<u8>>,
+        /// Delete the target's copy when they come online (replaces any queued update for the image)
+        #[serde(default)]
+        wipe: bool,
+        /// Give up on the update after this long (capped at the directory's own limit)
+        #[serde(default)]
+        ttl_secs: Option<u64>,
     },
     StorePendingPermissionUpdateResponse {
         success: bool,
@@ -180,681 +1259,4012 @@ pub enum DirectoryMessage {
     /// Get pending permission updates for a user
     GetPendingPermissionUpdates {
         username: String,
+        /// Stream attached images after the response instead of inlining them
+        /// (see `fetch_pending_permission_updates`)
+        #[serde(default)]
+        stream_images: bool,
     },
     GetPendingPermissionUpdatesResponse {
         updates: Vec<PendingPermissionUpdate>,
     },
-}
 
-// =============================================================================
-// DIRECTORY SERVICE STATE (WITH REPLICATION + PERSISTENCE)
-// =============================================================================
+    // Catalog change tracking
+    /// Follow (or unfollow) a peer's catalog changes
+    FollowPeer {
+        username: String,
+        peer: String,
+        follow: bool,
+    },
+    FollowPeerResponse {
+        success: bool,
+        message: String,
+    },
+    /// Get catalog changes from followed peers since a point in time
+    GetCatalogChanges {
+        username: String,
+        since: Option<SystemTime>,
+    },
+    GetCatalogChangesResponse {
+        changes: Vec<CatalogChange>,
+        /// Peers the user currently follows
+        following: Vec<String>,
+        /// Pass back as `since` on the next poll
+        server_time: SystemTime,
+    },
 
-pub struct DirectoryServiceState {
-    users: RwLock<HashMap<String, UserEntry>>,
-    heartbeat_timeout: Duration,
-    peer_servers: Vec<String>,
-    server_id: String,
+    // Access denial telemetry (fallback when the owner is offline)
+    StoreAccessDenial {
+        denial: crate::p2p_protocol::AccessDenial,
+    },
+    StoreAccessDenialResponse {
+        success: bool,
+        message: String,
+    },
+    /// Get and clear stored denial reports for an owner
+    GetAccessDenials {
+        username: String,
+    },
+    GetAccessDenialsResponse {
+        denials: Vec<crate::p2p_protocol::AccessDenial>,
+    },
+    /// Get and clear notices of an owner's pending updates that expired undelivered
+    GetExpiredDeliveries {
+        username: String,
+    },
+    GetExpiredDeliveriesResponse {
+        expired: Vec<ExpiredDelivery>,
+    },
 
-    /// NEW: Path to persistent state file
-    state_file: PathBuf,
+    /// Ask the directory to connect back to `port` on the address it sees us from
+    ProbeReachability {
+        port: u16,
+    },
+    ProbeReachabilityResponse {
+        reachable: bool,
+        /// The address the directory tried to connect to
+        observed_address: String,
+        message: String,
+    },
+    /// Ask for the other replicas this server replicates with, so clients can learn them
+    ListReplicas {},
+    ListReplicasResponse {
+        replicas: Vec<String>,
+    },
+    /// Ask which replica this is, what it was built from and how much it holds
+    GetServerInfo {},
+    GetServerInfoResponse {
+        info: ServerInfo,
+    },
 
-    /// NEW: Pending requests storage
-    pending_requests: RwLock<HashMap<String, PendingRequest>>,
+    // Image trades (owner-to-owner swaps)
+    /// Offer views on one of our images in exchange for views on one of theirs
+    ProposeTrade {
+        from_user: String,
+        to_user: String,
+        offered_image_id: String,
+        offered_views: u32,
+        requested_image_id: String,
+        requested_views: u32,
+    },
+    ProposeTradeResponse {
+        success: bool,
+        message: String,
+        trade_id: String,
+    },
+    /// Get trades the user is a party to
+    GetTrades {
+        username: String,
+    },
+    GetTradesResponse {
+        trades: Vec<TradeProposal>,
+    },
+    /// Accept or reject a trade proposed to us
+    RespondToTrade {
+        trade_id: String,
+        username: String,
+        accept: bool,
+        /// The key `username` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    RespondToTradeResponse {
+        success: bool,
+        message: String,
+        trade: Option<TradeProposal>,
+    },
+    /// Escrow our side of a trade: our image with the other party's views embedded
+    DepositTradeImage {
+        trade_id: String,
+        username: String,
+        encrypted_image: Vec<u8>,
+        /// The key `username` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    DepositTradeImageResponse {
+        success: bool,
+        message: String,
+        /// Both sides are in and have been queued for delivery
+        completed: bool,
+    },
+    /// Withdraw from a trade that hasn't completed, releasing any escrowed images
+    CancelTrade {
+        trade_id: String,
+        username: String,
+        /// The key `username` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+    CancelTradeResponse {
+        success: bool,
+        message: String,
+    },
 
-    /// NEW: Pending permission updates storage
-    pending_permission_updates: RwLock<HashMap<String, PendingPermissionUpdate>>,
+    // Administration (requires the replica secret)
+    /// Rename a user, or merge them into an existing account, across every replica
+    RenameUser {
+        from: String,
+        to: String,
+        /// Set when forwarded by another replica (not forwarded again)
+        #[serde(default)]
+        replicated: bool,
+    },
+    RenameUserResponse {
+        success: bool,
+        message: String,
+    },
+    /// Users archived after staying offline past the retention period
+    ListArchivedUsers {},
+    ListArchivedUsersResponse {
+        users: Vec<ArchivedUser>,
+    },
+    /// Delete archived users for good (every one of them when `usernames` is empty), across every replica
+    PurgeArchivedUsers {
+        usernames: Vec<String>,
+        /// Set when forwarded by another replica (not forwarded again)
+        #[serde(default)]
+        replicated: bool,
+    },
+    PurgeArchivedUsersResponse {
+        success: bool,
+        message: String,
+    },
+
+    /// Let anonymous (relayed) users request some of `owner`'s images, or stop them
+    /// (answered with `UpdateResponse`)
+    SetAnonymousAccess {
+        owner: String,
+        image_ids: Vec<String>,
+        allowed: bool,
+        /// The key `owner` registered with
+        #[serde(default)]
+        key: Option<String>,
+    },
+
+    /// A hidden peer holding this connection open as `handle`; the relay announces
+    /// circuits on it with `RelayIncoming`
+    RelayListen {
+        handle: String,
+        /// Chosen by whoever first claims the handle; later claims must match it
+        secret: String,
+    },
+    RelayListenResponse {
+        success: bool,
+        message: String,
+    },
+    /// A client is waiting on `circuit_id`; pick it up with `RelayAccept`
+    RelayIncoming {
+        circuit_id: String,
+    },
+    /// A hidden peer's new connection for a circuit, piped to the waiting client once answered
+    RelayAccept {
+        circuit_id: String,
+    },
+    RelayAcceptResponse {
+        success: bool,
+        message: String,
+    },
+    /// Connect through the relay to a hidden peer's `relay://` address or handle, or to a
+    /// registered peer's public address; the connection carries P2P frames once answered
+    RelayConnect {
+        target: String,
+    },
+    RelayConnectResponse {
+        success: bool,
+        message: String,
+    },
+
+    /// Envelope for a request one replica passes to another because it may hold
+    /// data the first lacks (answered locally, never forwarded again)
+    Forwarded {
+        message: Box<DirectoryMessage>,
+    },
+
+    /// Envelope naming the namespace a message belongs to (messages without one belong to
+    /// the default namespace)
+    Namespaced {
+        namespace: String,
+        message: Box<DirectoryMessage>,
+    },
+
+    /// Envelope carrying an auth token alongside another message
+    Authenticated {
+        token: String,
+        message: Box<DirectoryMessage>,
+    },
+    /// Returned when a message fails authentication
+    AuthError {
+        message: String,
+    },
 }
 
-/// Snapshot of directory service state for persistence
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct DirectorySnapshot {
-    users: HashMap<String, UserEntry>,
-    pending_requests: HashMap<String, PendingRequest>,
-    pending_permission_updates: HashMap<String, PendingPermissionUpdate>,
+// =============================================================================
+// AUTHENTICATION
+// =============================================================================
+
+/// Environment variable holding the shared secret between directory replicas
+pub const REPLICA_SECRET_ENV: &str = "DIRECTORY_REPLICA_SECRET";
+
+/// Environment variable holding the token clients present to directory servers
+pub const CLIENT_TOKEN_ENV: &str = "DIRECTORY_CLIENT_TOKEN";
+
+/// Authentication settings for a directory server
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryAuth {
+    /// Shared secret required on inter-replica messages (SyncState)
+    pub replica_secret: Option<String>,
+    /// Token required on user-facing messages (None = open access)
+    pub client_token: Option<String>,
 }
 
-impl DirectoryServiceState {
-    pub fn new(
-        heartbeat_timeout: Duration,
-        server_id: String,
-        peer_servers: Vec<String>,
-        state_file: PathBuf,
-    ) -> Self {
+impl DirectoryAuth {
+    /// Load auth settings from the environment (empty values are ignored)
+    pub fn from_env() -> Self {
+        let read = |key: &str| std::env::var(key).ok().filter(|v| !v.is_empty());
         Self {
-            users: RwLock::new(HashMap::new()),
-            heartbeat_timeout,
-            peer_servers,
-            server_id,
-            state_file,
-            pending_requests: RwLock::new(HashMap::new()),
-            pending_permission_updates: RwLock::new(HashMap::new()),
+            replica_secret: read(REPLICA_SECRET_ENV),
+            client_token: read(CLIENT_TOKEN_ENV),
         }
     }
-    
-    /// NEW: Load state from disk
-    pub async fn load_from_disk(&self) -> Result<()> {
-        if !self.state_file.exists() {
-            info!("[{}] No state file found, starting fresh", self.server_id);
-            return Ok(());
-        }
-        
-        let data = fs::read_to_string(&self.state_file)?;
-        
-        // Try to load the new snapshot format first
-        if let Ok(snapshot) = serde_json::from_str::<DirectorySnapshot>(&data) {
-            let mut users = self.users.write().await;
-            *users = snapshot.users;
-            
-            // Mark all users as offline initially (will come back online with heartbeat)
-            for user in users.values_mut() {
-                user.status = UserStatus::Offline;
+
+    /// Check a message's token against the configured secrets
+    pub fn authorize(&self, message: &DirectoryMessage, token: Option<&str>) -> Result<()> {
+        let matches = |expected: &Option<String>| match (expected, token) {
+            (Some(expected), Some(token)) => constant_time_eq(expected.as_bytes(), token.as_bytes()),
+            _ => false,
+        };
+
+        match message {
+            DirectoryMessage::SyncState { .. }
+            | DirectoryMessage::CommitState { .. }
+            | DirectoryMessage::PeerStatus { .. }
+            | DirectoryMessage::Forwarded { .. } => {
+                if self.replica_secret.is_some() && !matches(&self.replica_secret) {
+                    bail!("Replica messages require a valid replica secret");
+                }
             }
-            
-            let mut pending_requests = self.pending_requests.write().await;
-            *pending_requests = snapshot.pending_requests;
-            
-            let mut pending_updates = self.pending_permission_updates.write().await;
-            *pending_updates = snapshot.pending_permission_updates;
-            
-            info!("[{}] ✓ Loaded snapshot from disk ({} users, {} pending requests, {} pending permission updates)", 
-                  self.server_id, users.len(), pending_requests.len(), pending_updates.len());
-        } else {
-            // Fall back to old format (just users)
-            let loaded_users: HashMap<String, UserEntry> = serde_json::from_str(&data)?;
-            
-            let mut users = self.users.write().await;
-            *users = loaded_users;
-            
-            info!("[{}] ✓ Loaded {} users from disk (legacy format)", self.server_id, users.len());
-            
-            // Mark all users as offline initially (will come back online with heartbeat)
-            for user in users.values_mut() {
-                user.status = UserStatus::Offline;
+            DirectoryMessage::RenameUser { .. }
+            | DirectoryMessage::ListArchivedUsers { .. }
+            | DirectoryMessage::PurgeArchivedUsers { .. } => {
+                // Admin commands are never open, even when replica auth is disabled
+                if self.replica_secret.is_none() {
+                    bail!("Admin commands are disabled ({} not set)", REPLICA_SECRET_ENV);
+                }
+                if !matches(&self.replica_secret) {
+                    bail!("Admin commands require a valid replica secret");
+                }
+            }
+            _ => {
+                // Replicas may also query each other, so the replica secret is accepted here too
+                if self.client_token.is_some()
+                    && !matches(&self.client_token)
+                    && !matches(&self.replica_secret)
+                {
+                    bail!("Missing or invalid client token");
+                }
             }
         }
-        
+
         Ok(())
     }
-    
-    /// NEW: Save state to disk
-    async fn save_to_disk(&self) -> Result<()> {
-        let users = self.users.read().await;
-        let pending_requests = self.pending_requests.read().await;
-        let pending_updates = self.pending_permission_updates.read().await;
-        
-        let snapshot = DirectorySnapshot {
-            users: users.clone(),
-            pending_requests: pending_requests.clone(),
-            pending_permission_updates: pending_updates.clone(),
-        };
-        
-        let data = serde_json::to_string_pretty(&snapshot)?;
-        fs::write(&self.state_file, data)?;
-        
-        info!("[{}] ✓ Saved snapshot to disk ({} users, {} pending requests, {} pending permission updates)", 
-              self.server_id, users.len(), pending_requests.len(), pending_updates.len());
-        Ok(())
+}
+
+/// Compare two byte strings without short-circuiting on the first mismatch
+pub(crate) fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Clients' registration keys, by username (the CLI keeps it in its working directory)
+pub const USER_KEYS_FILE: &str = "user_keys.json";
+
+/// Key proving to the directory that we are `username`, created on first use and kept in `path`
+pub fn user_key(path: &Path, username: &str) -> Result<String> {
+    let mut keys: HashMap<String, String> = match fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).with_context(|| format!("Invalid {}", path.display()))?,
+        Err(_) => HashMap::new(),
+    };
+    if let Some(key) = keys.get(username) {
+        return Ok(key.clone());
     }
-    
-    /// NEW: Request state from peers (for recovery)
-    pub async fn sync_from_peers(&self) -> Result<()> {
-        if self.peer_servers.is_empty() {
-            return Ok(());
-        }
-        
-        info!("[{}] Requesting state from peers for recovery...", self.server_id);
-        
+    let key = uuid::Uuid::new_v4().simple().to_string();
+    keys.insert(username.to_string(), key.clone());
+    fs::write(path, serde_json::to_string_pretty(&keys)?).with_context(|| format!("Failed to save {}", path.display()))?;
+    Ok(key)
+}
+
+/// What the directory keeps of a registration key
+pub fn user_key_hash(key: &str) -> String {
+    crate::p2p_protocol::sha256_hex(key.as_bytes())
+}
+
+/// Wrap a message in an auth envelope if a token is given
+pub fn with_token(message: DirectoryMessage, token: Option<&str>) -> DirectoryMessage {
+    match (token, message) {
+        (_, msg @ DirectoryMessage::Authenticated { .. }) => msg,
+        (Some(token), msg) => DirectoryMessage::Authenticated {
+            token: token.to_string(),
+            message: Box::new(msg),
+        },
+        (None, msg) => msg,
+    }
+}
+
+// =============================================================================
+// NAMESPACES
+// =============================================================================
+//
+// One set of directory servers can serve several groups that must not see each other
+// (e.g. course sections). Each namespace has its own users, requests, grants, catalogs and
+// relay, kept in its own state file. Clients that name no namespace share the default one,
+// which is all older clients and servers know about.
+
+/// Environment variable naming the namespace a client works in (unset = default)
+pub const NAMESPACE_ENV: &str = "DIRECTORY_NAMESPACE";
+
+/// Longest namespace name
+pub const MAX_NAMESPACE_LEN: usize = 64;
+
+/// Check a namespace name: lowercase letters, digits, `-` and `_`, as it's part of a file name
+pub fn validate_namespace(namespace: &str) -> Result<()> {
+    if namespace.is_empty() || namespace.len() > MAX_NAMESPACE_LEN {
+        bail!("Namespace must be 1-{} characters", MAX_NAMESPACE_LEN);
+    }
+    if !namespace.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_') {
+        bail!("Namespace '{}' may only use lowercase letters, digits, '-' and '_'", namespace);
+    }
+    Ok(())
+}
+
+static CLIENT_NAMESPACE: std::sync::Mutex<Option<String>> = std::sync::Mutex::new(None);
+
+/// Send this process's directory messages in `namespace` (None = the default namespace)
+pub fn set_namespace(namespace: Option<String>) -> Result<()> {
+    if let Some(namespace) = &namespace {
+        validate_namespace(namespace)?;
+    }
+    if let Ok(mut current) = CLIENT_NAMESPACE.lock() {
+        *current = namespace;
+    }
+    Ok(())
+}
+
+/// Namespace this process's directory messages are sent in, if any (falls back to `DIRECTORY_NAMESPACE`)
+pub fn client_namespace() -> Option<String> {
+    CLIENT_NAMESPACE
+        .lock()
+        .ok()
+        .and_then(|namespace| namespace.clone())
+        .or_else(|| std::env::var(NAMESPACE_ENV).ok().filter(|namespace| !namespace.is_empty()))
+}
+
+/// Wrap a message in a namespace envelope if a namespace is given
+///
+/// Messages already in an envelope are left alone, so a replica's own envelope wins.
+pub fn with_namespace(message: DirectoryMessage, namespace: Option<&str>) -> DirectoryMessage {
+    match (namespace, message) {
+        (_, msg @ (DirectoryMessage::Namespaced { .. } | DirectoryMessage::Authenticated { .. })) => msg,
+        (Some(namespace), msg) => DirectoryMessage::Namespaced {
+            namespace: namespace.to_string(),
+            message: Box::new(msg),
+        },
+        (None, msg) => msg,
+    }
+}
+
+/// A message as this client sends it: in its namespace, with its token
+pub(crate) fn client_envelope(message: DirectoryMessage) -> Result<DirectoryMessage> {
+    let namespace = client_namespace();
+    if let Some(namespace) = &namespace {
+        validate_namespace(namespace)?;
+    }
+    let client_token = std::env::var(CLIENT_TOKEN_ENV).ok().filter(|t| !t.is_empty());
+    Ok(with_token(with_namespace(message, namespace.as_deref()), client_token.as_deref()))
+}
+
+// =============================================================================
+// HEARTBEAT TIMING
+// =============================================================================
+
+/// Environment variable setting how often clients should send heartbeats, in seconds
+pub const HEARTBEAT_INTERVAL_ENV: &str = "DIRECTORY_HEARTBEAT_INTERVAL_SECS";
+
+/// Environment variable setting how long without a heartbeat marks a peer offline, in seconds
+pub const HEARTBEAT_TIMEOUT_ENV: &str = "DIRECTORY_HEARTBEAT_TIMEOUT_SECS";
+
+/// Heartbeat interval used when the server doesn't announce one
+pub const DEFAULT_HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Default time without a heartbeat before a peer is marked offline
+const DEFAULT_HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Heartbeats that may be missed (or delayed) before a peer is marked offline
+const MIN_MISSED_HEARTBEATS: u32 = 2;
+
+/// How often clients heartbeat and how long the server waits before marking them offline
+#[derive(Debug, Clone, Copy)]
+pub struct HeartbeatConfig {
+    pub interval: Duration,
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatConfig {
+    fn default() -> Self {
+        Self {
+            interval: DEFAULT_HEARTBEAT_INTERVAL,
+            timeout: DEFAULT_HEARTBEAT_TIMEOUT,
+        }
+    }
+}
+
+impl HeartbeatConfig {
+    /// Read the heartbeat timing from the environment, falling back to the defaults
+    ///
+    /// A timeout that wouldn't survive a couple of late heartbeats is raised to do so.
+    pub fn from_env() -> Self {
+        let secs = |var: &str| {
+            std::env::var(var)
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+                .filter(|secs| *secs > 0)
+                .map(Duration::from_secs)
+        };
+        let interval = secs(HEARTBEAT_INTERVAL_ENV).unwrap_or(DEFAULT_HEARTBEAT_INTERVAL);
+        let timeout = secs(HEARTBEAT_TIMEOUT_ENV).unwrap_or(DEFAULT_HEARTBEAT_TIMEOUT);
+
+        let min_timeout = interval * (MIN_MISSED_HEARTBEATS + 1);
+        if timeout < min_timeout {
+            warn!(
+                "Heartbeat timeout of {}s is too short for a {}s interval - using {}s",
+                timeout.as_secs(),
+                interval.as_secs(),
+                min_timeout.as_secs()
+            );
+        }
+        Self {
+            interval,
+            timeout: timeout.max(min_timeout),
+        }
+    }
+}
+
+/// The heartbeat interval a `RegisterResponse` asked for, or the default
+pub fn negotiated_heartbeat_interval(heartbeat_interval_secs: Option<u64>) -> Duration {
+    heartbeat_interval_secs
+        .filter(|secs| *secs > 0)
+        .map_or(DEFAULT_HEARTBEAT_INTERVAL, Duration::from_secs)
+}
+
+// =============================================================================
+// PENDING UPDATE BLOB STORE
+// =============================================================================
+
+/// Environment variable capping total blob storage, in megabytes
+pub const BLOB_CAP_ENV: &str = "DIRECTORY_BLOB_CAP_MB";
+
+/// Default cap on total blob storage (512 MB)
+const DEFAULT_BLOB_CAP_BYTES: u64 = 512 * 1024 * 1024;
+
+/// Read the blob storage cap from the environment, falling back to the default
+pub fn blob_cap_from_env() -> u64 {
+    std::env::var(BLOB_CAP_ENV)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|mb| mb * 1024 * 1024)
+        .unwrap_or(DEFAULT_BLOB_CAP_BYTES)
+}
+
+/// Environment variable capping the pending requests one requester may leave for one owner
+pub const MAX_PENDING_PER_SENDER_ENV: &str = "DIRECTORY_MAX_PENDING_PER_SENDER";
+
+/// Default cap on one requester's pending requests to one owner
+const DEFAULT_MAX_PENDING_PER_SENDER: usize = 5;
+
+/// Read the per-requester pending request cap from the environment, falling back to the default
+pub fn max_pending_per_sender_from_env() -> usize {
+    std::env::var(MAX_PENDING_PER_SENDER_ENV)
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|max| *max > 0)
+        .unwrap_or(DEFAULT_MAX_PENDING_PER_SENDER)
+}
+
+/// Environment variable setting how long pending updates wait for their target, in days
+pub const PENDING_UPDATE_TTL_ENV: &str = "DIRECTORY_PENDING_UPDATE_TTL_DAYS";
+
+/// Default (and maximum) time a pending update waits for its target (30 days)
+const DEFAULT_PENDING_UPDATE_TTL: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// Read the pending update TTL from the environment, falling back to the default
+pub fn pending_update_ttl_from_env() -> Duration {
+    std::env::var(PENDING_UPDATE_TTL_ENV)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|days| *days > 0)
+        .map_or(DEFAULT_PENDING_UPDATE_TTL, |days| Duration::from_secs(days * 24 * 60 * 60))
+}
+
+/// Environment variable setting how long a request waits on its owner before they're reminded, in hours
+pub const REMIND_AFTER_ENV: &str = "DIRECTORY_REMIND_AFTER_HOURS";
+
+/// Default wait before an owner is reminded of a pending request (24 hours)
+const DEFAULT_REMIND_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Read the reminder threshold from the environment, falling back to the default
+pub fn remind_after_from_env() -> Duration {
+    std::env::var(REMIND_AFTER_ENV)
+        .ok()
+        .and_then(|v| v.parse::<u64>().ok())
+        .filter(|hours| *hours > 0)
+        .map_or(DEFAULT_REMIND_AFTER, |hours| Duration::from_secs(hours * 60 * 60))
+}
+
+/// Environment variable setting how long a user may stay offline before they're archived, in days (0 never archives)
+pub const ARCHIVE_AFTER_ENV: &str = "DIRECTORY_ARCHIVE_AFTER_DAYS";
+
+/// Default time a user may stay offline before they're archived (180 days)
+const DEFAULT_ARCHIVE_AFTER: Duration = Duration::from_secs(180 * 24 * 60 * 60);
+
+/// Read the archival threshold from the environment, falling back to the default (None = never archive)
+pub fn archive_after_from_env() -> Option<Duration> {
+    match std::env::var(ARCHIVE_AFTER_ENV).ok().and_then(|v| v.parse::<u64>().ok()) {
+        Some(0) => None,
+        Some(days) => Some(Duration::from_secs(days * 24 * 60 * 60)),
+        None => Some(DEFAULT_ARCHIVE_AFTER),
+    }
+}
+
+/// Environment variable setting how many directory servers, this one included, must hold a change before it's committed
+pub const WRITE_QUORUM_ENV: &str = "DIRECTORY_WRITE_QUORUM";
+
+/// Read the write quorum from the environment (None = a majority of the servers)
+pub fn write_quorum_from_env() -> Option<usize> {
+    std::env::var(WRITE_QUORUM_ENV)
+        .ok()
+        .and_then(|v| v.parse::<usize>().ok())
+        .filter(|quorum| *quorum > 0)
+}
+
+/// How long a replica gets to take a proposed change
+const PROPOSAL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a replica holds a proposed change waiting to hear whether it was committed
+const PROPOSAL_TTL: Duration = Duration::from_secs(30);
+
+/// Tries at telling a replica what became of a proposal it holds
+const RESOLVE_ATTEMPTS: u32 = 4;
+
+/// Wait between those tries (well inside `PROPOSAL_TTL`)
+const RESOLVE_RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// Servers, out of `servers`, that make a majority
+fn majority(servers: usize) -> usize {
+    servers / 2 + 1
+}
+
+/// A TTL in the largest whole unit that fits, e.g. "30 days" or "6 hours"
+fn describe_ttl(ttl: Duration) -> String {
+    let secs = ttl.as_secs();
+    let (count, unit) = match secs {
+        86_400.. => (secs / 86_400, "day"),
+        3_600.. => (secs / 3_600, "hour"),
+        60.. => (secs / 60, "minute"),
+        _ => (secs, "second"),
+    };
+    format!("{} {}{}", count, unit, if count == 1 { "" } else { "s" })
+}
+
+#[derive(Debug, Clone)]
+struct BlobEntry {
+    size: u64,
+    last_used: SystemTime,
+}
+
+/// Content-addressed image blobs on disk, bounded by an LRU size cap
+pub struct BlobStore {
+    dir: PathBuf,
+    cap_bytes: u64,
+    index: HashMap<String, BlobEntry>,
+}
+
+impl BlobStore {
+    pub fn new(dir: PathBuf, cap_bytes: u64) -> Self {
+        Self {
+            dir,
+            cap_bytes,
+            index: HashMap::new(),
+        }
+    }
+
+    fn blob_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.bin", hash))
+    }
+
+    /// Rebuild the index from blobs already on disk (mtime stands in for last use)
+    pub fn load(&mut self) -> Result<()> {
+        self.index.clear();
+        if !self.dir.exists() {
+            return Ok(());
+        }
+
+        for entry in fs::read_dir(&self.dir)?.flatten() {
+            let path = entry.path();
+            let Some(hash) = path.file_stem().and_then(|s| s.to_str()) else { continue };
+            if path.extension().and_then(|e| e.to_str()) != Some("bin") {
+                continue;
+            }
+            let metadata = entry.metadata()?;
+            self.index.insert(hash.to_string(), BlobEntry {
+                size: metadata.len(),
+                last_used: metadata.modified().unwrap_or_else(|_| SystemTime::now()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Store a blob (deduplicated by content) and return its hash
+    pub fn put(&mut self, data: &[u8]) -> Result<String> {
+        let hash = crate::p2p_protocol::sha256_hex(data);
+        if !self.index.contains_key(&hash) {
+            fs::create_dir_all(&self.dir)?;
+            fs::write(self.blob_path(&hash), data)?;
+        }
+        self.index.insert(hash.clone(), BlobEntry {
+            size: data.len() as u64,
+            last_used: SystemTime::now(),
+        });
+        Ok(hash)
+    }
+
+    /// Open a blob for streaming, returning the file and its size
+    pub fn open(&mut self, hash: &str) -> Result<(fs::File, u64)> {
+        let path = self.blob_path(hash);
+        let Some(entry) = self.index.get_mut(hash) else {
+            bail!("Blob {} not found", hash);
+        };
+        entry.last_used = SystemTime::now();
+        Ok((fs::File::open(path)?, entry.size))
+    }
+
+    /// Read a blob back from disk
+    pub fn get(&mut self, hash: &str) -> Result<Vec<u8>> {
+        let Some(entry) = self.index.get_mut(hash) else {
+            bail!("Blob {} not found", hash);
+        };
+        entry.last_used = SystemTime::now();
+        Ok(fs::read(self.blob_path(hash))?)
+    }
+
+    /// Size of a stored blob, without reading it
+    pub fn size(&self, hash: &str) -> Option<u64> {
+        self.index.get(hash).map(|entry| entry.size)
+    }
+
+    pub fn remove(&mut self, hash: &str) {
+        if self.index.remove(hash).is_some() {
+            let _ = fs::remove_file(self.blob_path(hash));
+        }
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.index.values().map(|e| e.size).sum()
+    }
+
+    /// Evict least-recently-used blobs until under the cap, never evicting anything in `keep`
+    pub fn evict_to_cap(&mut self, keep: &[String]) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while self.total_bytes() > self.cap_bytes {
+            let oldest = self.index.iter()
+                .filter(|(hash, _)| !keep.contains(hash))
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(hash, _)| hash.clone());
+            let Some(hash) = oldest else { break };
+            self.remove(&hash);
+            evicted.push(hash);
+        }
+        evicted
+    }
+}
+
+/// A peer replica as this server sees it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReplicaPeer {
+    pub address: String,
+    /// The peer announced it was shutting down, so replication to it is paused
+    pub departed: bool,
+}
+
+/// Identity and size of a directory replica, for telling replicas apart during incidents
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerInfo {
+    pub server_id: String,
+    /// Namespace the counts below are for (None = the default namespace)
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Crate version the server was built from
+    pub version: String,
+    pub uptime_secs: u64,
+    pub state_file: PathBuf,
+    pub peers: Vec<ReplicaPeer>,
+    pub users: usize,
+    pub online_users: usize,
+    pub pending_requests: usize,
+    pub pending_updates: usize,
+    pub trades: usize,
+}
+
+impl ServerInfo {
+    /// e.g. `dir-1 v0.1.0, up 2h 05m, 12 users (4 online)`, with ` in <namespace>` if not the default
+    pub fn summary(&self) -> String {
+        format!(
+            "{} v{}, up {}h {:02}m, {} users ({} online){}",
+            self.server_id,
+            self.version,
+            self.uptime_secs / 3600,
+            self.uptime_secs / 60 % 60,
+            self.users,
+            self.online_users,
+            self.namespace.as_ref().map(|namespace| format!(" in {}", namespace)).unwrap_or_default()
+        )
+    }
+}
+
+// =============================================================================
+// DIRECTORY SERVICE STATE (WITH REPLICATION + PERSISTENCE)
+// =============================================================================
+
+pub struct DirectoryServiceState {
+    users: RwLock<HashMap<String, UserEntry>>,
+    heartbeat_timeout: Duration,
+    /// Heartbeat interval announced to clients when they register
+    heartbeat_interval: Duration,
+    peer_servers: Vec<String>,
+    server_id: String,
+
+    /// NEW: Path to persistent state file
+    state_file: PathBuf,
+
+    /// NEW: Pending requests storage
+    pending_requests: RwLock<HashMap<String, PendingRequest>>,
+
+    /// NEW: Pending permission updates storage
+    pending_permission_updates: RwLock<HashMap<String, PendingPermissionUpdate>>,
+
+    /// Authentication settings for incoming messages
+    auth: DirectoryAuth,
+
+    /// Peers that announced a graceful shutdown (skipped during replication)
+    departed_peers: RwLock<HashSet<String>>,
+
+    /// On-disk storage for images attached to pending permission updates
+    blobs: RwLock<BlobStore>,
+
+    /// Recent catalog changes, oldest first
+    catalog_changes: RwLock<Vec<CatalogChange>>,
+
+    /// Follower -> peers whose catalog changes they want
+    follows: RwLock<HashMap<String, HashSet<String>>>,
+
+    /// Search index over shared catalogs, brought up to date on each search
+    catalog_index: std::sync::Mutex<CatalogIndex>,
+
+    /// Denial reports waiting for their owner to come online
+    access_denials: RwLock<Vec<crate::p2p_protocol::AccessDenial>>,
+
+    /// Longest a pending update waits for its target
+    pending_update_ttl: Duration,
+
+    /// Expiry notices waiting for their owner to collect them
+    expired_deliveries: RwLock<Vec<ExpiredDelivery>>,
+
+    /// Most pending requests one requester may have with one owner
+    max_pending_per_sender: usize,
+
+    /// Owner -> requesters recently refused for having too many requests pending
+    request_throttles: RwLock<HashMap<String, Vec<ThrottledSender>>>,
+
+    /// Image trades by trade ID
+    trades: RwLock<HashMap<String, TradeProposal>>,
+
+    /// Latest grant per (owner, image, recipient) accepted or updated through this replica
+    grants: RwLock<Vec<ExistingGrant>>,
+
+    /// How long a request waits on its owner before they're reminded
+    remind_after: Duration,
+
+    /// Request ID -> when reminders resume (None once dismissed), for requests held here
+    reminder_snoozes: RwLock<HashMap<String, Option<SystemTime>>>,
+
+    /// Recent status changes per user, oldest first (kept by each directory, not replicated)
+    presence: RwLock<HashMap<String, VecDeque<PresenceChange>>>,
+
+    /// Highest user entry sequence seen here or from peers (a Lamport clock)
+    sequence: AtomicU64,
+
+    /// Hidden peers reachable through this server
+    relay: Relay,
+
+    /// When this server started, for reporting uptime
+    started_at: Instant,
+
+    /// Namespace this state belongs to (None = the default namespace)
+    namespace: Option<String>,
+
+    /// How long a user may stay offline before they're archived (None = never)
+    archive_after: Option<Duration>,
+
+    /// Users archived for staying offline too long, kept in their own file beside the snapshot
+    archived: RwLock<HashMap<String, ArchivedUser>>,
+
+    /// Servers, this one included, that must hold a change to a user before it's committed
+    write_quorum: usize,
+
+    /// Username -> lock held while this server commits a change to them, so two changes to
+    /// one user can't start from the same version
+    commit_locks: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+
+    /// Changes other replicas proposed, by proposal ID, waiting to hear whether they were committed
+    proposals: RwLock<HashMap<String, ProposedState>>,
+}
+
+/// Changes another replica proposed, held until it commits or drops them
+struct ProposedState {
+    users: HashMap<String, UserEntry>,
+    queued: QueuedChanges,
+    received_at: Instant,
+}
+
+/// Snapshot of directory service state for persistence
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DirectorySnapshot {
+    users: HashMap<String, UserEntry>,
+    pending_requests: HashMap<String, PendingRequest>,
+    pending_permission_updates: HashMap<String, PendingPermissionUpdate>,
+    #[serde(default)]
+    follows: HashMap<String, HashSet<String>>,
+    #[serde(default)]
+    access_denials: Vec<crate::p2p_protocol::AccessDenial>,
+    #[serde(default)]
+    trades: HashMap<String, TradeProposal>,
+    #[serde(default)]
+    expired_deliveries: Vec<ExpiredDelivery>,
+    #[serde(default)]
+    presence: HashMap<String, VecDeque<PresenceChange>>,
+    #[serde(default)]
+    grants: Vec<ExistingGrant>,
+    #[serde(default)]
+    reminder_snoozes: HashMap<String, Option<SystemTime>>,
+}
+
+impl DirectoryServiceState {
+    pub fn new(
+        heartbeat_timeout: Duration,
+        server_id: String,
+        peer_servers: Vec<String>,
+        state_file: PathBuf,
+    ) -> Self {
+        let write_quorum = majority(peer_servers.len() + 1);
+        Self {
+            users: RwLock::new(HashMap::new()),
+            heartbeat_timeout,
+            heartbeat_interval: DEFAULT_HEARTBEAT_INTERVAL,
+            peer_servers,
+            server_id,
+            pending_requests: RwLock::new(HashMap::new()),
+            pending_permission_updates: RwLock::new(HashMap::new()),
+            auth: DirectoryAuth::default(),
+            departed_peers: RwLock::new(HashSet::new()),
+            blobs: RwLock::new(BlobStore::new(state_file.with_extension("blobs"), DEFAULT_BLOB_CAP_BYTES)),
+            state_file,
+            catalog_changes: RwLock::new(Vec::new()),
+            follows: RwLock::new(HashMap::new()),
+            catalog_index: std::sync::Mutex::new(CatalogIndex::default()),
+            access_denials: RwLock::new(Vec::new()),
+            pending_update_ttl: DEFAULT_PENDING_UPDATE_TTL,
+            expired_deliveries: RwLock::new(Vec::new()),
+            max_pending_per_sender: DEFAULT_MAX_PENDING_PER_SENDER,
+            request_throttles: RwLock::new(HashMap::new()),
+            trades: RwLock::new(HashMap::new()),
+            grants: RwLock::new(Vec::new()),
+            remind_after: DEFAULT_REMIND_AFTER,
+            reminder_snoozes: RwLock::new(HashMap::new()),
+            presence: RwLock::new(HashMap::new()),
+            sequence: AtomicU64::new(0),
+            relay: Relay::default(),
+            started_at: Instant::now(),
+            namespace: None,
+            archive_after: Some(DEFAULT_ARCHIVE_AFTER),
+            archived: RwLock::new(HashMap::new()),
+            write_quorum,
+            commit_locks: std::sync::Mutex::new(HashMap::new()),
+            proposals: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Sequence number for a user entry changed on this server
+    fn next_sequence(&self) -> u64 {
+        self.sequence.fetch_add(1, Ordering::SeqCst) + 1
+    }
+
+    /// Keep our clock ahead of entries written elsewhere
+    fn observe_sequence(&self, sequence: u64) {
+        self.sequence.fetch_max(sequence, Ordering::SeqCst);
+    }
+
+    /// Set the authentication settings for incoming messages
+    pub fn with_auth(mut self, auth: DirectoryAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    /// Set the heartbeat interval announced to clients and the timeout enforced on them
+    pub fn with_heartbeat(mut self, heartbeat: HeartbeatConfig) -> Self {
+        self.heartbeat_interval = heartbeat.interval;
+        self.heartbeat_timeout = heartbeat.timeout;
+        self
+    }
+
+    /// Set the cap on total blob storage for pending update images
+    pub fn with_blob_cap(mut self, cap_bytes: u64) -> Self {
+        self.blobs.get_mut().cap_bytes = cap_bytes;
+        self
+    }
+
+    /// Set how many pending requests one requester may have with one owner
+    pub fn with_max_pending_per_sender(mut self, max: usize) -> Self {
+        self.max_pending_per_sender = max;
+        self
+    }
+
+    /// Set the longest a pending update waits for its target before it's dropped
+    pub fn with_pending_update_ttl(mut self, ttl: Duration) -> Self {
+        self.pending_update_ttl = ttl;
+        self
+    }
+
+    /// Set how long a request waits on its owner before they're reminded of it
+    pub fn with_remind_after(mut self, remind_after: Duration) -> Self {
+        self.remind_after = remind_after;
+        self
+    }
+
+    /// Set how long a user may stay offline before they're archived (None = never)
+    pub fn with_archive_after(mut self, archive_after: Option<Duration>) -> Self {
+        self.archive_after = archive_after;
+        self
+    }
+
+    /// Set how many servers, this one included, must hold a change before it's committed
+    ///
+    /// None means a majority; more than there are servers means all of them.
+    pub fn with_write_quorum(mut self, quorum: Option<usize>) -> Self {
+        let servers = self.peer_servers.len() + 1;
+        self.write_quorum = quorum.map_or(majority(servers), |quorum| quorum.min(servers));
+        self
+    }
+
+    /// Make this the state of `namespace`, whose messages to other replicas name it
+    pub fn in_namespace(mut self, namespace: String) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// A message to another replica: in our namespace, with the replica secret
+    fn peer_message(&self, message: DirectoryMessage) -> DirectoryMessage {
+        with_token(with_namespace(message, self.namespace.as_deref()), self.auth.replica_secret.as_deref())
+    }
+    
+    /// NEW: Load state from disk
+    pub async fn load_from_disk(&self) -> Result<()> {
+        self.load_archive().await?;
+        if !self.state_file.exists() {
+            info!("[{}] No state file found, starting fresh", self.server_id);
+            return Ok(());
+        }
+        
+        let data = fs::read_to_string(&self.state_file)?;
+        
+        // Try to load the new snapshot format first
+        if let Ok(snapshot) = serde_json::from_str::<DirectorySnapshot>(&data) {
+            let mut users = self.users.write().await;
+            *users = snapshot.users;
+            let mut presence = snapshot.presence;
+            
+            // Mark all users as offline initially (will come back online with heartbeat)
+            for user in users.values_mut() {
+                if user.status == UserStatus::Online {
+                    // Their last heartbeat is as close as we can get to when they were lost
+                    push_presence(presence.entry(user.username.clone()).or_default(), false, user.last_heartbeat);
+                }
+                user.status = UserStatus::Offline;
+                self.observe_sequence(user.sequence);
+            }
+            *self.presence.write().await = presence;
+            
+            let mut pending_requests = self.pending_requests.write().await;
+            *pending_requests = snapshot.pending_requests;
+            
+            let mut blobs = self.blobs.write().await;
+            if let Err(e) = blobs.load() {
+                warn!("[{}] Could not index blob store: {}", self.server_id, e);
+            }
+            
+            let mut pending_updates = self.pending_permission_updates.write().await;
+            *pending_updates = snapshot.pending_permission_updates;
+            
+            *self.follows.write().await = snapshot.follows;
+            *self.access_denials.write().await = snapshot.access_denials;
+            *self.expired_deliveries.write().await = snapshot.expired_deliveries;
+            *self.grants.write().await = snapshot.grants;
+            *self.reminder_snoozes.write().await = snapshot.reminder_snoozes;
+            let trades = snapshot.trades;
+            
+            // Move any inline images from older snapshots into the blob store
+            for update in pending_updates.values_mut() {
+                if let Some(data) = update.embedded_image.take() {
+                    match blobs.put(&data) {
+                        Ok(hash) => update.blob_sha256 = Some(hash),
+                        Err(e) => {
+                            warn!("[{}] Could not migrate image for {}: {}", self.server_id, update.update_id, e);
+                            update.embedded_image = Some(data);
+                        }
+                    }
+                }
+            }
+            
+            // Drop blobs left behind by updates and trades that no longer exist
+            let escrowed: HashSet<&String> = trades.values()
+                .filter(|t| t.is_open())
+                .flat_map(|t| t.deposits())
+                .collect();
+            let orphaned: Vec<String> = blobs.index.keys()
+                .filter(|hash| !pending_updates.values().any(|u| u.blob_sha256.as_ref() == Some(*hash)))
+                .filter(|hash| !escrowed.contains(hash))
+                .cloned()
+                .collect();
+            for hash in orphaned {
+                blobs.remove(&hash);
+            }
+            drop(blobs);
+            *self.trades.write().await = trades;
+            
+            info!("[{}] ✓ Loaded snapshot from disk ({} users, {} pending requests, {} pending permission updates)", 
+                  self.server_id, users.len(), pending_requests.len(), pending_updates.len());
+        } else {
+            // Fall back to old format (just users)
+            let loaded_users: HashMap<String, UserEntry> = serde_json::from_str(&data)?;
+            
+            let mut users = self.users.write().await;
+            *users = loaded_users;
+            
+            info!("[{}] ✓ Loaded {} users from disk (legacy format)", self.server_id, users.len());
+            
+            // Mark all users as offline initially (will come back online with heartbeat)
+            for user in users.values_mut() {
+                user.status = UserStatus::Offline;
+                self.observe_sequence(user.sequence);
+            }
+        }
+        
+        Ok(())
+    }
+    
+    /// NEW: Save state to disk
+    async fn save_to_disk(&self) -> Result<()> {
+        let users = self.users.read().await;
+        let pending_requests = self.pending_requests.read().await;
+        let pending_updates = self.pending_permission_updates.read().await;
+        
+        let snapshot = DirectorySnapshot {
+            users: users.clone(),
+            pending_requests: pending_requests.clone(),
+            pending_permission_updates: pending_updates.clone(),
+            follows: self.follows.read().await.clone(),
+            access_denials: self.access_denials.read().await.clone(),
+            trades: self.trades.read().await.clone(),
+            expired_deliveries: self.expired_deliveries.read().await.clone(),
+            presence: self.presence.read().await.clone(),
+            grants: self.grants.read().await.clone(),
+            reminder_snoozes: self.reminder_snoozes.read().await.clone(),
+        };
+        
+        let data = serde_json::to_string_pretty(&snapshot)?;
+        fs::write(&self.state_file, data)?;
+        
+        info!("[{}] ✓ Saved snapshot to disk ({} users, {} pending requests, {} pending permission updates)", 
+              self.server_id, users.len(), pending_requests.len(), pending_updates.len());
+        Ok(())
+    }
+    
+    /// NEW: Request state from peers (for recovery)
+    pub async fn sync_from_peers(&self) -> Result<()> {
+        if self.peer_servers.is_empty() {
+            return Ok(());
+        }
+        
+        info!("[{}] Requesting state from peers for recovery...", self.server_id);
+        
         for peer in &self.peer_servers {
-            match request_state_from_peer(peer).await {
+            match request_state_from_peer(peer, |message| self.peer_message(message)).await {
                 Ok(peer_users) => {
                     let mut users = self.users.write().await;
+                    let mut archived = self.archived.write().await;
                     
                     // Merge peer state
-                    for (username, peer_user) in peer_users {
-                        match users.get(&username) {
-                            Some(local_user) => {
-                                if peer_user.last_heartbeat > local_user.last_heartbeat {
-                                    users.insert(username.clone(), peer_user);
-                                }
-                            }
-                            None => {
-                                users.insert(username.clone(), peer_user);
-                            }
+                    for (username, mut peer_user) in peer_users {
+                        self.observe_sequence(peer_user.sequence);
+                        if !admit_replicated(&mut archived, &username, &peer_user) {
+                            continue;
+                        }
+                        if users.get(&username).is_none_or(|local_user| peer_user.supersedes(local_user)) {
+                            peer_user.last_seen = Some(Instant::now());
+                            users.insert(username.clone(), peer_user);
+                        }
+                    }
+                    
+                    info!("[{}] ✓ Synced state from peer {} ({} users total)", 
+                          self.server_id, peer, users.len());
+                    
+                    drop(archived);
+                    drop(users);
+                    
+                    // Save the recovered state
+                    self.save_archive().await?;
+                    self.save_to_disk().await?;
+                    
+                    return Ok(());
+                }
+                Err(e) => {
+                    warn!("[{}] Could not sync from peer {}: {}", 
+                          self.server_id, peer, e);
+                    continue;
+                }
+            }
+        }
+        
+        warn!("[{}] Could not sync from any peer, using local state", self.server_id);
+        Ok(())
+    }
+    
+    /// Record a peer's leaving/rejoining announcement
+    pub async fn set_peer_status(&self, peer_ip: std::net::IpAddr, port: u16, server_id: &str, leaving: bool) {
+        let peer_addr = format!("{}:{}", peer_ip, port);
+        let Some(peer) = self.peer_servers.iter().find(|p| **p == peer_addr) else {
+            warn!("[{}] Status from unknown peer {} ({})", self.server_id, server_id, peer_addr);
+            return;
+        };
+        
+        let mut departed = self.departed_peers.write().await;
+        if leaving {
+            departed.insert(peer.clone());
+            info!("[{}] Peer {} ({}) is shutting down, pausing replication to it", 
+                  self.server_id, server_id, peer);
+        } else if departed.remove(peer) {
+            info!("[{}] Peer {} ({}) is back online", self.server_id, server_id, peer);
+        }
+    }
+    
+    /// Tell every peer this server is leaving or back online
+    async fn announce_status(&self, port: u16, leaving: bool) {
+        for peer in &self.peer_servers {
+            let message = self.peer_message(DirectoryMessage::PeerStatus {
+                server_id: self.server_id.clone(),
+                port,
+                leaving,
+            });
+            
+            match tokio::time::timeout(Duration::from_secs(2), send_directory_message(peer, message)).await {
+                Ok(Ok(DirectoryMessage::PeerStatusResponse { success: true })) => {}
+                Ok(Ok(_)) => warn!("[{}] Unexpected status response from {}", self.server_id, peer),
+                Ok(Err(e)) => warn!("[{}] Could not notify peer {}: {}", self.server_id, peer, e),
+                Err(_) => warn!("[{}] Timed out notifying peer {}", self.server_id, peer),
+            }
+        }
+    }
+    
+    /// Flush state to disk and notify peers before exiting
+    pub async fn shutdown(&self, port: u16) {
+        info!("[{}] Shutting down directory service...", self.server_id);
+        
+        if let Err(e) = self.save_to_disk().await {
+            error!("[{}] Failed to flush state on shutdown: {}", self.server_id, e);
+        }
+        
+        self.announce_status(port, true).await;
+        
+        info!("[{}] ✓ Shutdown complete", self.server_id);
+    }
+    
+    pub async fn register_user(
+        &self,
+        username: String,
+        p2p_address: String,
+        shared_images: Vec<ImageInfo>,
+        capabilities: Vec<String>,
+        availability: Option<AvailabilityWindow>,
+        key: Option<String>,
+    ) -> Result<()> {
+        let shared_images = checked_previews(&username, shared_images);
+        let archived = self.archived.read().await.get(&username).map(|archived| archived.entry.clone());
+        
+        let (previous, entry) = self.commit_user(&username, |existing| {
+            // Another device may only take the name over once this session has gone offline
+            if existing.is_some_and(|user| {
+                user.status == UserStatus::Online && user.key_hash.is_some() && !user.holds_key(key.as_deref())
+            }) {
+                bail!("{} is already online from another device", username);
+            }
+            let existing = existing.or(archived.as_ref());
+            Ok(UserEntry {
+                username: username.clone(),
+                p2p_address,
+                last_heartbeat: SystemTime::now(),
+                status: UserStatus::Online,
+                shared_images,
+                capabilities,
+                // Stamped when it's committed
+                sequence: 0,
+                last_seen: Some(Instant::now()),
+                availability,
+                // Re-registering doesn't carry the profile, delegates or anonymous access, so keep the ones already set
+                profile: existing.map(|user| user.profile.clone()).unwrap_or_default(),
+                delegates: existing.map(|user| user.delegates.clone()).unwrap_or_default(),
+                anonymous_images: existing.map(|user| user.anonymous_images.clone()).unwrap_or_default(),
+                key_hash: key.as_deref().map(user_key_hash),
+            })
+        }).await?;
+        
+        let restored = self.archived.write().await.remove(&username);
+        if restored.is_some() {
+            info!("[{}] Restored archived user {}", self.server_id, username);
+        }
+        self.record_presence(&username, true, entry.last_heartbeat).await;
+        if let Some(previous) = previous.or(archived) {
+            self.record_catalog_change(&username, &previous.shared_images, &entry.shared_images).await;
+        }
+        info!("[{}] Registered user: {} with {} shared images", 
+              self.server_id, username, entry.shared_images.len());
+        
+        // Persist to disk
+        if restored.is_some() {
+            if let Err(e) = self.save_archive().await {
+                error!("[{}] Failed to save archived users: {}", self.server_id, e);
+            }
+        }
+        let _ = self.save_to_disk().await;
+        
+        Ok(())
+    }
+    
+    pub async fn update_heartbeat(&self, username: &str, capabilities: Option<Vec<String>>) -> Result<()> {
+        let mut users = self.users.write().await;
+        
+        let Some(user) = users.get_mut(username) else {
+            bail!("User {} not found", username)
+        };
+        user.last_heartbeat = SystemTime::now();
+        user.last_seen = Some(Instant::now());
+        if user.status != UserStatus::Online {
+            self.record_presence(username, true, user.last_heartbeat).await;
+        }
+        user.status = UserStatus::Online;
+        
+        // Only replicate when the advertised capabilities actually change
+        let changed = capabilities.filter(|caps| *caps != user.capabilities);
+        drop(users);
+        
+        if let Some(capabilities) = changed {
+            self.commit_user_update(username, |user| {
+                user.capabilities = capabilities;
+                Ok(())
+            }).await?;
+            info!("[{}] Updated capabilities for {}", self.server_id, username);
+            let _ = self.save_to_disk().await;
+        }
+        Ok(())
+    }
+    
+    pub async fn unregister_user(&self, username: &str) -> Result<()> {
+        self.commit_user_update(username, |user| {
+            user.status = UserStatus::Offline;
+            Ok(())
+        }).await?;
+        self.record_presence(username, false, SystemTime::now()).await;
+        info!("[{}] User {} went offline", self.server_id, username);
+        
+        // Clear all notifications for this user (accepted/rejected requests they made)
+        if let Err(e) = self.clear_notifications_for_user(username).await {
+            warn!("[{}] Notifications for {} not cleared: {}", self.server_id, username, e);
+        }
+        
+        // Optionally: Also clear pending requests TO this user that they haven't responded to
+        // This prevents stale requests from accumulating
+        if let Err(e) = self.clear_pending_requests_to_user(username).await {
+            warn!("[{}] Requests to {} not cleared: {}", self.server_id, username, e);
+        }
+        
+        let _ = self.save_to_disk().await;
+        
+        Ok(())
+    }
+    
+    pub async fn get_online_peers(&self, requesting_user: &str) -> Vec<UserEntry> {
+        let follows = self.follows.read().await.clone();
+        let users = self.users.read().await;
+
+        users
+            .values()
+            .filter(|u| {
+                u.username != requesting_user
+                    && u.status == UserStatus::Online
+                    && self.is_user_active(u)
+            })
+            .map(|u| u.catalog_for(Some(requesting_user), follows.get(&u.username)))
+            .collect()
+    }
+
+    /// Get ALL registered peers (both online and offline), excluding the requesting user
+    pub async fn get_all_peers(&self, requesting_user: &str) -> Vec<UserEntry> {
+        let follows = self.follows.read().await.clone();
+        let users = self.users.read().await;
+
+        users
+            .values()
+            .filter(|u| u.username != requesting_user)
+            .map(|u| u.catalog_for(Some(requesting_user), follows.get(&u.username)))
+            .collect()
+    }
+
+    /// A user's entry with only the images listed for `viewer`
+    pub async fn query_user_as(&self, username: &str, viewer: Option<&str>) -> Option<UserEntry> {
+        let contacts = self.follows.read().await.get(username).cloned();
+        let user = self.query_user(username).await?;
+        Some(user.catalog_for(viewer, contacts.as_ref()))
+    }
+
+    /// Refuse a request the image's visibility doesn't allow
+    ///
+    /// Images the owner no longer lists in the directory are left for the owner to judge.
+    async fn check_request_visibility(&self, from_user: &str, owner: &str, image_id: &str, share_token: Option<&str>) -> Result<()> {
+        let is_contact = self.follows.read().await.get(owner).is_some_and(|followed| followed.contains(from_user));
+        let users = self.users.read().await;
+        let Some(image) = users.get(owner).and_then(|u| u.shared_images.iter().find(|i| i.image_id == image_id)) else {
+            return Ok(());
+        };
+        match image.visibility {
+            ImageVisibility::Public => Ok(()),
+            ImageVisibility::ContactsOnly if is_contact => Ok(()),
+            ImageVisibility::ContactsOnly => bail!("{} only takes requests for {} from their contacts", owner, image_id),
+            ImageVisibility::LinkOnly => match (&image.link_token, share_token) {
+                (Some(expected), Some(token)) if constant_time_eq(expected.as_bytes(), token.as_bytes()) => Ok(()),
+                _ => bail!("{} can only be requested through the share link {} gave out", image_id, owner),
+            },
+            ImageVisibility::Hidden => bail!("{} is not taking requests for {}", owner, image_id),
+        }
+    }
+    
+    /// Who this replica is and how much it holds
+    pub async fn server_info(&self) -> ServerInfo {
+        let departed = self.departed_peers.read().await.clone();
+        let users = self.users.read().await;
+        ServerInfo {
+            server_id: self.server_id.clone(),
+            namespace: self.namespace.clone(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            state_file: self.state_file.clone(),
+            peers: self.peer_servers.iter()
+                .map(|address| ReplicaPeer { address: address.clone(), departed: departed.contains(address) })
+                .collect(),
+            users: users.len(),
+            online_users: users.values()
+                .filter(|user| user.status == UserStatus::Online && self.is_user_active(user))
+                .count(),
+            pending_requests: self.pending_requests.read().await.len(),
+            pending_updates: self.pending_permission_updates.read().await.len(),
+            trades: self.trades.read().await.len(),
+        }
+    }
+
+    /// Judged on our own monotonic clock, so skew between machines can't flap the status
+    fn is_user_active(&self, user: &UserEntry) -> bool {
+        user.last_seen
+            .is_some_and(|seen| seen.elapsed() < self.heartbeat_timeout)
+    }
+    
+    pub async fn update_shared_images(
+        &self,
+        username: &str,
+        shared_images: Vec<ImageInfo>,
+    ) -> Result<()> {
+        let shared_images = checked_previews(username, shared_images);
+        let (previous, entry) = self.commit_user_update(username, |user| {
+            user.shared_images = shared_images;
+            Ok(())
+        }).await?;
+        if let Some(previous) = previous {
+            self.record_catalog_change(username, &previous.shared_images, &entry.shared_images).await;
+        }
+        info!("[{}] Updated shared images for user: {}", self.server_id, username);
+        
+        let _ = self.save_to_disk().await;
+        
+        Ok(())
+    }
+    
+    pub async fn update_profile(&self, username: &str, profile: UserProfile) -> Result<()> {
+        let profile = profile.validated()?;
+        self.commit_user_update(username, |user| {
+            user.profile = profile;
+            Ok(())
+        }).await?;
+        info!("[{}] Updated profile for user: {}", self.server_id, username);
+        
+        let _ = self.save_to_disk().await;
+        
+        Ok(())
+    }
+    
+    /// Let `delegate` answer requests for `owner`'s images, or stop them
+    ///
+    /// Empty `image_ids` covers every image; setting a delegate again replaces their images.
+    pub async fn set_delegate(
+        &self,
+        owner: &str,
+        key: Option<&str>,
+        delegate: &str,
+        image_ids: &[String],
+        enabled: bool,
+    ) -> Result<()> {
+        // A delegate can answer requests (and so mint grants), so only the owner may appoint one
+        self.check_user_key(owner, key).await?;
+        if owner == delegate {
+            bail!("Cannot delegate to yourself");
+        }
+        let image_ids = image_ids
+            .iter()
+            .map(|id| unqualify_image_id(owner, id).map(str::to_string))
+            .collect::<Result<Vec<_>>>()?;
+        if enabled && !self.users.read().await.contains_key(delegate) {
+            bail!("User {} not found", delegate);
+        }
+
+        self.commit_user_update(owner, |user| {
+            user.delegates.retain(|d| d.delegate != delegate);
+            if enabled {
+                user.delegates.push(Delegation { delegate: delegate.to_string(), image_ids });
+            }
+            Ok(())
+        }).await?;
+        info!("[{}] {} delegate {} for user: {}", self.server_id,
+              if enabled { "Set" } else { "Removed" }, delegate, owner);
+
+        let _ = self.save_to_disk().await;
+
+        Ok(())
+    }
+
+    /// Let anonymous users request `image_ids` of `owner`'s, or stop them
+    pub async fn set_anonymous_access(&self, owner: &str, key: Option<&str>, image_ids: &[String], allowed: bool) -> Result<()> {
+        self.check_user_key(owner, key).await?;
+        let image_ids = image_ids
+            .iter()
+            .map(|id| unqualify_image_id(owner, id).map(str::to_string))
+            .collect::<Result<Vec<_>>>()?;
+        self.commit_user_update(owner, |user| {
+            user.anonymous_images.retain(|id| !image_ids.contains(id));
+            if allowed {
+                user.anonymous_images.extend(image_ids.iter().cloned());
+            }
+            Ok(())
+        }).await?;
+        info!("[{}] {} anonymous requests for {} image(s) of user: {}", self.server_id,
+              if allowed { "Allowed" } else { "Disallowed" }, image_ids.len(), owner);
+
+        let _ = self.save_to_disk().await;
+
+        Ok(())
+    }
+
+    /// Whether a relay may connect to `address` for a client: only registered, non-hidden peers
+    pub async fn is_public_peer_address(&self, address: &str) -> bool {
+        let users = self.users.read().await;
+        users.values().any(|user| user.p2p_address == address && !user.is_anonymous())
+    }
+
+    /// Fail unless `key` is the one `username` registered with, so only their own device acts as them
+    pub async fn check_user_key(&self, username: &str, key: Option<&str>) -> Result<()> {
+        let users = self.users.read().await;
+        match users.get(username) {
+            Some(user) if user.holds_key(key) => Ok(()),
+            Some(_) if key.is_none() => bail!("Acting as {} needs the key they registered with - update your client", username),
+            Some(_) => bail!("Not registered as {} from this device - start your peer here first", username),
+            None => bail!("User {} not found", username),
+        }
+    }
+
+    pub async fn is_delegate(&self, owner: &str, user: &str, image_id: &str) -> bool {
+        let users = self.users.read().await;
+        users.get(owner).is_some_and(|entry| entry.has_delegate(user, image_id))
+    }
+
+    /// Record which images were added/removed between two versions of a catalog
+    async fn record_catalog_change(&self, owner: &str, old: &[ImageInfo], new: &[ImageInfo]) {
+        let old_ids: HashSet<&str> = old.iter().map(|img| img.image_id.as_str()).collect();
+        let new_ids: HashSet<&str> = new.iter().map(|img| img.image_id.as_str()).collect();
+        
+        let added: Vec<String> = new_ids.difference(&old_ids).map(|id| id.to_string()).collect();
+        let removed: Vec<String> = old_ids.difference(&new_ids).map(|id| id.to_string()).collect();
+        if added.is_empty() && removed.is_empty() {
+            return;
+        }
+        
+        info!("[{}] Catalog change for {}: +{} -{}", self.server_id, owner, added.len(), removed.len());
+        
+        let mut changes = self.catalog_changes.write().await;
+        changes.push(CatalogChange {
+            owner: owner.to_string(),
+            added,
+            removed,
+            timestamp: SystemTime::now(),
+        });
+        if changes.len() > MAX_CATALOG_CHANGES {
+            let excess = changes.len() - MAX_CATALOG_CHANGES;
+            changes.drain(..excess);
+        }
+    }
+    
+    /// Follow or unfollow a peer's catalog
+    pub async fn set_follow(&self, username: &str, peer: &str, follow: bool) -> Result<()> {
+        if username == peer {
+            bail!("Cannot follow yourself");
+        }
+        if follow && self.query_user(peer).await.is_none() {
+            bail!("User {} not found", peer);
+        }
+        
+        let mut follows = self.follows.write().await;
+        let followed = follows.entry(username.to_string()).or_default();
+        if follow {
+            followed.insert(peer.to_string());
+        } else {
+            followed.remove(peer);
+        }
+        if followed.is_empty() {
+            follows.remove(username);
+        }
+        Ok(())
+    }
+    
+    /// Peers a user follows, sorted by name
+    pub async fn get_following(&self, username: &str) -> Vec<String> {
+        let follows = self.follows.read().await;
+        let mut following: Vec<String> = follows
+            .get(username)
+            .map(|f| f.iter().cloned().collect())
+            .unwrap_or_default();
+        following.sort();
+        following
+    }
+    
+    /// Catalog changes from peers a user follows, newer than `since`
+    ///
+    /// Additions are left out while the image isn't listed for the user.
+    pub async fn get_catalog_changes(&self, username: &str, since: Option<SystemTime>) -> Vec<CatalogChange> {
+        // Copied so the users lock is never waited on while holding this one (renames take users first)
+        let follows = self.follows.read().await.clone();
+        let Some(followed) = follows.get(username) else {
+            return Vec::new();
+        };
+        
+        let users = self.users.read().await;
+        let unlisted = |owner: &str, image_id: &String| {
+            users.get(owner).is_some_and(|user| {
+                user.shared_images.iter().any(|image| {
+                    image.image_id == *image_id && !image.listed_for(owner, Some(username), follows.get(owner))
+                })
+            })
+        };
+        let changes = self.catalog_changes.read().await;
+        changes
+            .iter()
+            .filter(|c| followed.contains(&c.owner))
+            .filter(|c| since.is_none_or(|since| c.timestamp > since))
+            .map(|c| CatalogChange {
+                added: c.added.iter().filter(|id| !unlisted(&c.owner, id)).cloned().collect(),
+                ..c.clone()
+            })
+            .filter(|c| !c.added.is_empty() || !c.removed.is_empty())
+            .collect()
+    }
+    
+    pub async fn query_user(&self, username: &str) -> Option<UserEntry> {
+        let users = self.users.read().await;
+        users.get(username).cloned()
+    }
+
+    /// Note a user coming online or going offline (repeats of their current status are ignored)
+    async fn record_presence(&self, username: &str, online: bool, at: SystemTime) {
+        let mut presence = self.presence.write().await;
+        push_presence(presence.entry(username.to_string()).or_default(), online, at);
+    }
+
+    /// A user's recent status changes, or None if they've never registered
+    pub async fn presence_history(&self, username: &str) -> Option<PresenceHistory> {
+        let users = self.users.read().await;
+        let user = users.get(username)?;
+        let changes = self.presence.read().await
+            .get(username)
+            .map(|changes| changes.iter().copied().collect())
+            .unwrap_or_default();
+        Some(PresenceHistory {
+            username: username.to_string(),
+            online: user.status == UserStatus::Online && self.is_user_active(user),
+            last_seen: user.last_heartbeat,
+            changes,
+        })
+    }
+    
+    /// Image IDs `owner` shares that other users share too
+    pub async fn image_id_collisions(&self, owner: &str) -> Vec<ImageIdCollision> {
+        let users = self.users.read().await;
+        let Some(user) = users.get(owner) else {
+            return Vec::new();
+        };
+        
+        user.shared_images
+            .iter()
+            .filter_map(|img| {
+                let mut other_owners: Vec<String> = users
+                    .values()
+                    .filter(|other| other.username != owner)
+                    .filter(|other| other.shared_images.iter().any(|o| o.image_id == img.image_id))
+                    .map(|other| other.username.clone())
+                    .collect();
+                other_owners.sort();
+                (!other_owners.is_empty()).then(|| ImageIdCollision {
+                    image_id: img.image_id.clone(),
+                    other_owners,
+                })
+            })
+            .collect()
+    }
+    
+    /// Every owner sharing an image matching `query`, online owners first
+    ///
+    /// `query` is an image ID, an `owner/image_id`, or a prefix (8+ hex digits) of
+    /// the content hash that image IDs end with.
+    pub async fn image_holders(&self, query: &str, viewer: Option<&str>) -> Vec<ImageHolder> {
+        let query = query.trim();
+        let (owner_filter, id_query) = match query.split_once('/') {
+            Some((owner, id)) => (Some(owner), id),
+            None => (None, query),
+        };
+        let hash_query = id_query.to_ascii_lowercase();
+        let is_hash = hash_query.len() >= 8 && hash_query.chars().all(|c| c.is_ascii_hexdigit());
+
+        let matches = |image: &ImageInfo| {
+            if image.image_id == id_query {
+                return true;
+            }
+            // IDs are `<uuid>-<first 12 hex of sha256>.png`
+            let id_hash = image
+                .image_id
+                .trim_end_matches(".png")
+                .rsplit('-')
+                .next()
+                .unwrap_or_default();
+            is_hash && !id_hash.is_empty() && (id_hash.starts_with(&hash_query) || hash_query.starts_with(id_hash))
+        };
+
+        let follows = self.follows.read().await.clone();
+        let users = self.users.read().await;
+        let mut holders: Vec<ImageHolder> = users
+            .values()
+            .filter(|u| owner_filter.is_none_or(|owner| u.username == owner))
+            .map(|u| u.catalog_for(viewer, follows.get(&u.username)))
+            .flat_map(|u| {
+                let status = if u.status == UserStatus::Online && self.is_user_active(&u) {
+                    UserStatus::Online
+                } else {
+                    UserStatus::Offline
+                };
+                u.shared_images
+                    .into_iter()
+                    .filter(|image| matches(image))
+                    .map(move |image| ImageHolder {
+                        owner: u.username.clone(),
+                        status: status.clone(),
+                        image,
+                    })
+            })
+            .collect();
+        holders.sort_by(|a, b| {
+            (a.status != UserStatus::Online, &a.owner, &a.image.image_id)
+                .cmp(&(b.status != UserStatus::Online, &b.owner, &b.image.image_id))
+        });
+        holders
+    }
+
+    /// Shared images listed for `viewer` matching `query`, best match first, at most `limit` of them
+    pub async fn search_catalog(&self, query: &str, limit: usize, viewer: Option<&str>) -> Vec<CatalogMatch> {
+        let follows = self.follows.read().await.clone();
+        let users = self.users.read().await;
+        let hits = match self.catalog_index.lock() {
+            Ok(mut catalog) => {
+                catalog.refresh(&users);
+                catalog.index.search(query)
+            }
+            Err(_) => return Vec::new(),
+        };
+
+        let mut results: Vec<CatalogMatch> = hits
+            .into_iter()
+            .filter_map(|((owner, image_id), score)| {
+                let user = users.get(&owner)?;
+                let image = user.shared_images.iter().find(|image| image.image_id == image_id)?;
+                if !image.listed_for(&owner, viewer, follows.get(&owner)) {
+                    return None;
+                }
+                let online = user.status == UserStatus::Online && self.is_user_active(user);
+                Some(CatalogMatch {
+                    owner,
+                    status: if online { UserStatus::Online } else { UserStatus::Offline },
+                    image: ImageInfo { link_token: None, ..image.clone() },
+                    score,
+                    your_grant: None,
+                })
+            })
+            .collect();
+        results.sort_by(|a, b| {
+            b.score
+                .total_cmp(&a.score)
+                .then((a.status != UserStatus::Online).cmp(&(b.status != UserStatus::Online)))
+                .then_with(|| (&a.owner, &a.image.image_name).cmp(&(&b.owner, &b.image.image_name)))
+        });
+        results.truncate(limit);
+        results
+    }
+
+    /// Warn about colliding image IDs, returning a note for the owner (empty if none collide)
+    async fn collision_note(&self, owner: &str) -> String {
+        let collisions = self.image_id_collisions(owner).await;
+        if collisions.is_empty() {
+            return String::new();
+        }
+        
+        let listed: Vec<String> = collisions
+            .iter()
+            .map(|c| format!("{} (also {})", c.image_id, c.other_owners.join(", ")))
+            .collect();
+        warn!("[{}] {} shares image IDs used by other owners: {}", self.server_id, owner, listed.join("; "));
+        format!(
+            " - {} image ID(s) are also shared by other peers, so refer to them as {}: {}",
+            collisions.len(),
+            qualified_image_id(owner, "<image_id>"),
+            listed.join("; ")
+        )
+    }
+    
+    pub async fn cleanup_inactive_users(&self) {
+        let mut users = self.users.write().await;
+        
+        let mut to_mark_offline = Vec::new();
+        
+        for (username, user) in users.iter() {
+            if user.status == UserStatus::Online && !self.is_user_active(user) {
+                to_mark_offline.push(username.clone());
+            }
+        }
+        
+        for username in to_mark_offline {
+            if let Some(user) = users.get_mut(&username) {
+                user.status = UserStatus::Offline;
+                self.record_presence(&username, false, user.last_heartbeat).await;
+                info!("[{}] Marked user {} as offline due to timeout", 
+                      self.server_id, username);
+            }
+        }
+        
+        drop(users);
+        
+        let _ = self.save_to_disk().await;
+        self.push_state().await;
+    }
+    
+    /// Push every committed entry to the replicas, so one that missed a commit catches up
+    ///
+    /// Replicas keep whichever version of an entry is newest, so this never undoes a change.
+    async fn push_state(&self) {
+        let users = self.users.read().await.clone();
+        self.push_users(users, QueuedChanges::default()).await;
+    }
+    
+    /// Send entries (and queued changes) to the replicas to merge, without waiting to hear they arrived
+    async fn push_users(&self, users: HashMap<String, UserEntry>, queued: QueuedChanges) {
+        if self.peer_servers.is_empty() {
+            return;
+        }
+        
+        let departed = self.departed_peers.read().await.clone();
+        
+        for peer in self.peer_servers.iter().filter(|p| !departed.contains(*p)) {
+            let peer_addr = peer.clone();
+            let message = self.peer_message(DirectoryMessage::SyncState {
+                users: users.clone(),
+                proposal_id: None,
+                queued: queued.clone(),
+            });
+            
+            tokio::spawn(async move {
+                if let Err(e) = send_state_sync(&peer_addr, message).await {
+                    error!("Failed to replicate to {}: {}", peer_addr, e);
+                }
+            });
+        }
+    }
+    
+    // =========================================================================
+    // QUORUM COMMITS
+    // =========================================================================
+    //
+    // Replicas used to push their user table to each other and merge whatever arrived, so
+    // during a partition both sides kept taking changes and one side's were silently lost
+    // when it healed. A change to a user entry, a request or a queued permission update is
+    // now proposed to every replica and only committed - here and on the replicas holding
+    // it - once `write_quorum` servers hold it. Otherwise it's dropped everywhere and the
+    // client is told, so the minority side of a partition refuses changes instead of
+    // diverging, and a request acknowledged to a client survives losing this server.
+    // Trades are still held by the replica that took them; the deliveries a completed trade
+    // queues are replicated once it completes.
+    
+    /// Commit `username`'s entry as `change` makes it from the current one (None if they have none)
+    ///
+    /// Returns the entry replaced here, if any, and the committed entry. Nothing changes here
+    /// unless the change is committed.
+    async fn commit_user<F>(&self, username: &str, change: F) -> Result<(Option<UserEntry>, UserEntry)>
+    where
+        F: FnOnce(Option<&UserEntry>) -> Result<UserEntry>,
+    {
+        let _committing = self.lock_commit(username).await;
+        let current = self.users.read().await.get(username).cloned();
+        let mut entry = change(current.as_ref())?;
+        entry.sequence = self.next_sequence();
+        self.replicate_with_quorum(HashMap::from([(username.to_string(), entry.clone())]), QueuedChanges::default()).await?;
+        
+        let mut users = self.users.write().await;
+        let previous = users.get(username).cloned();
+        // Another replica may have committed a newer version while this one was out
+        if previous.as_ref().is_none_or(|previous| entry.supersedes(previous)) {
+            users.insert(username.to_string(), entry.clone());
+        }
+        Ok((previous, entry))
+    }
+    
+    /// Wait until no other change to `key` (a username, or `requests_lock`/`updates_lock` of one)
+    /// is being committed from this server
+    async fn lock_commit(&self, key: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = {
+            let mut locks = self.commit_locks.lock().unwrap_or_else(|e| e.into_inner());
+            // Drop locks nobody holds or waits on, so the map doesn't grow with every user
+            locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+            locks.entry(key.to_string()).or_default().clone()
+        };
+        lock.lock_owned().await
+    }
+    
+    /// `commit_user` for a change to an existing user
+    async fn commit_user_update<F>(&self, username: &str, change: F) -> Result<(Option<UserEntry>, UserEntry)>
+    where
+        F: FnOnce(&mut UserEntry) -> Result<()>,
+    {
+        self.commit_user(username, |current| {
+            let mut entry = current.with_context(|| format!("User {} not found", username))?.clone();
+            change(&mut entry)?;
+            Ok(entry)
+        })
+        .await
+    }
+    
+    /// Commit changes to requests and queued updates, then apply them here
+    ///
+    /// The caller holds the commit lock for what it changes (see `lock_commit`) from reading
+    /// the current state until this returns. Returns queued images evicted to make room.
+    async fn commit_queued(&self, mut queued: QueuedChanges) -> Result<Vec<PendingPermissionUpdate>> {
+        for request in &mut queued.requests {
+            request.sequence = self.next_sequence();
+        }
+        for update in &mut queued.updates {
+            update.sequence = self.next_sequence();
+        }
+        self.replicate_with_quorum(HashMap::new(), queued.clone()).await?;
+        self.apply_queued(queued).await
+    }
+    
+    /// Apply committed changes to requests and queued updates, keeping the newest version of each
+    ///
+    /// Returns queued images evicted to make room for the new ones.
+    async fn apply_queued(&self, queued: QueuedChanges) -> Result<Vec<PendingPermissionUpdate>> {
+        if !queued.requests.is_empty() || !queued.removed_requests.is_empty() {
+            let mut requests = self.pending_requests.write().await;
+            for request in queued.requests {
+                self.observe_sequence(request.sequence);
+                if requests.get(&request.request_id).is_none_or(|current| request.sequence > current.sequence) {
+                    requests.insert(request.request_id.clone(), request);
+                }
+            }
+            for request_id in &queued.removed_requests {
+                requests.remove(request_id);
+            }
+        }
+        if queued.updates.is_empty() && queued.removed_updates.is_empty() {
+            return Ok(Vec::new());
+        }
+        
+        let trades = self.trades.read().await;
+        let mut blobs = self.blobs.write().await;
+        let mut updates = self.pending_permission_updates.write().await;
+        let mut keep = escrowed_blobs(&trades);
+        for mut update in queued.updates {
+            self.observe_sequence(update.sequence);
+            if updates.get(&update.update_id).is_some_and(|current| update.sequence <= current.sequence) {
+                continue;
+            }
+            update.blob_sha256 = match update.embedded_image.take() {
+                Some(data) => Some(blobs.put(&data)?),
+                None => None,
+            };
+            keep.extend(update.blob_sha256.clone());
+            if let Some(previous) = updates.insert(update.update_id.clone(), update) {
+                release_blob(&mut blobs, &updates, previous.blob_sha256.as_deref());
+            }
+        }
+        for update_id in &queued.removed_updates {
+            if let Some(removed) = updates.remove(update_id) {
+                release_blob(&mut blobs, &updates, removed.blob_sha256.as_deref());
+            }
+        }
+        
+        // Stay within the storage cap, dropping images from the oldest updates first
+        Ok(self.evict_update_blobs(&mut blobs, &mut updates, &keep))
+    }
+    
+    /// Propose `users` and `queued` to every replica, then commit them wherever they're held if enough are
+    ///
+    /// Fails, dropping the proposal everywhere, when fewer than `write_quorum` servers (this
+    /// one included) took it in time. Older replicas apply a proposal at once.
+    async fn replicate_with_quorum(&self, users: HashMap<String, UserEntry>, queued: QueuedChanges) -> Result<()> {
+        // A quorum of one is this server alone: commit here and let the replicas catch up
+        if self.write_quorum <= 1 {
+            if !users.is_empty() || !queued.is_empty() {
+                self.push_users(users, queued).await;
+            }
+            return Ok(());
+        }
+        
+        let proposal_id = uuid::Uuid::new_v4().to_string();
+        let departed = self.departed_peers.read().await.clone();
+        let (answer, mut answers) = tokio::sync::mpsc::unbounded_channel();
+        let mut asked = 0;
+        
+        for peer in self.peer_servers.iter().filter(|p| !departed.contains(*p)) {
+            let peer = peer.clone();
+            let message = self.peer_message(DirectoryMessage::SyncState {
+                users: users.clone(),
+                proposal_id: Some(proposal_id.clone()),
+                queued: queued.clone(),
+            });
+            let answer = answer.clone();
+            tokio::spawn(async move {
+                let result = tokio::time::timeout(PROPOSAL_TIMEOUT, send_state_sync(&peer, message)).await;
+                let _ = answer.send((peer, result));
+            });
+            asked += 1;
+        }
+        drop(answer);
+        
+        // Decided as soon as enough servers hold it, or too few are left to make up the quorum
+        let mut holding = Vec::new();
+        let mut answered = 0;
+        while holding.len() + 1 < self.write_quorum && holding.len() + 1 + (asked - answered) >= self.write_quorum {
+            let Some((peer, result)) = answers.recv().await else {
+                break;
+            };
+            answered += 1;
+            match result {
+                Ok(Ok(())) => holding.push(peer),
+                Ok(Err(e)) => warn!("[{}] Could not propose a change to {}: {}", self.server_id, peer, e),
+                Err(_) => warn!("[{}] Timed out proposing a change to {}", self.server_id, peer),
+            }
+        }
+        
+        let held = holding.len() + 1;
+        let committed = held >= self.write_quorum;
+        let resolution = self.peer_message(DirectoryMessage::CommitState { proposal_id, commit: committed });
+        for peer in holding {
+            resolve_proposal_on(peer, resolution.clone(), committed);
+        }
+        // Replicas still answering when it was decided need to hear the outcome too
+        if answered < asked {
+            tokio::spawn(async move {
+                while let Some((peer, result)) = answers.recv().await {
+                    if let Ok(Ok(())) = result {
+                        resolve_proposal_on(peer, resolution.clone(), committed);
+                    }
+                }
+            });
+        }
+        
+        if !committed {
+            warn!("[{}] Change not committed: {} of {} servers reachable, {} needed",
+                  self.server_id, held, self.peer_servers.len() + 1, self.write_quorum);
+            bail!(
+                "Not committed: only {} of {} directory servers could be reached ({} needed) - try again shortly",
+                held, self.peer_servers.len() + 1, self.write_quorum
+            );
+        }
+        Ok(())
+    }
+    
+    /// Hold changes another replica proposed until it commits or drops them
+    pub async fn stage_proposal(&self, proposal_id: String, users: HashMap<String, UserEntry>, queued: QueuedChanges) {
+        let mut proposals = self.proposals.write().await;
+        // The proposer may have gone before it could say what became of its proposal
+        proposals.retain(|_, proposal| proposal.received_at.elapsed() < PROPOSAL_TTL);
+        proposals.insert(proposal_id, ProposedState { users, queued, received_at: Instant::now() });
+    }
+    
+    /// Apply or drop a held proposal, returning whether it was still held
+    pub async fn resolve_proposal(&self, proposal_id: &str, commit: bool) -> bool {
+        let Some(proposal) = self.proposals.write().await.remove(proposal_id) else {
+            return false;
+        };
+        if commit {
+            if !proposal.users.is_empty() {
+                self.receive_state_sync(proposal.users).await;
+            }
+            self.receive_queued(proposal.queued).await;
+        }
+        true
+    }
+    
+    /// Apply requests and queued updates another replica committed
+    pub async fn receive_queued(&self, queued: QueuedChanges) {
+        if queued.is_empty() {
+            return;
+        }
+        match self.apply_queued(queued).await {
+            Ok(evicted) => self.notify_evicted_updates(evicted).await,
+            Err(e) => error!("[{}] Failed to apply replicated requests and updates: {}", self.server_id, e),
+        }
+        let _ = self.save_to_disk().await;
+    }
+    
+    pub async fn receive_state_sync(&self, incoming_state: HashMap<String, UserEntry>) {
+        let mut users = self.users.write().await;
+        let mut archived = self.archived.write().await;
+        let archived_before = archived.len();
+        
+        for (username, mut incoming_user) in incoming_state {
+            self.observe_sequence(incoming_user.sequence);
+            if !admit_replicated(&mut archived, &username, &incoming_user) {
+                continue;
+            }
+            // A newer version means the sender heard from the user since we last did
+            incoming_user.last_seen = Some(Instant::now());
+            match users.get(&username) {
+                Some(existing_user) => {
+                    if incoming_user.supersedes(existing_user) {
+                        self.record_catalog_change(&username, &existing_user.shared_images, &incoming_user.shared_images).await;
+                        if incoming_user.status != existing_user.status {
+                            self.record_presence(&username, incoming_user.status == UserStatus::Online, SystemTime::now()).await;
                         }
+                        users.insert(username.clone(), incoming_user);
+                        info!("[{}] Updated user {} from peer sync", 
+                              self.server_id, username);
                     }
-                    
-                    info!("[{}] ✓ Synced state from peer {} ({} users total)", 
-                          self.server_id, peer, users.len());
-                    
-                    drop(users);
-                    
-                    // Save the recovered state
-                    self.save_to_disk().await?;
-                    
-                    return Ok(());
                 }
-                Err(e) => {
-                    warn!("[{}] Could not sync from peer {}: {}", 
-                          self.server_id, peer, e);
-                    continue;
+                None => {
+                    if incoming_user.status == UserStatus::Online {
+                        self.record_presence(&username, true, incoming_user.last_heartbeat).await;
+                    }
+                    users.insert(username.clone(), incoming_user);
+                    info!("[{}] Added new user {} from peer sync", 
+                          self.server_id, username);
+                }
+            }
+        }
+        
+        let restored = archived.len() != archived_before;
+        drop(archived);
+        drop(users);
+        
+        // Persist the merged state
+        if restored {
+            if let Err(e) = self.save_archive().await {
+                error!("[{}] Failed to save archived users: {}", self.server_id, e);
+            }
+        }
+        let _ = self.save_to_disk().await;
+    }
+    
+    pub async fn get_full_state(&self) -> HashMap<String, UserEntry> {
+        let users = self.users.read().await;
+        users.clone()
+    }
+
+    // =============================================================================
+    // ASYNCHRONOUS REQUEST SYSTEM
+    // =============================================================================
+
+    /// Leave a request when target user is offline
+    #[allow(clippy::too_many_arguments)]
+    pub async fn leave_request(
+        &self,
+        from_user: String,
+        to_user: String,
+        image_id: String,
+        requested_views: u32,
+        delta_only: bool,
+        requested_max_dimension: Option<u32>,
+        share_token: Option<&str>,
+    ) -> Result<String> {
+        use uuid::Uuid;
+
+        let image_id = unqualify_image_id(&to_user, &image_id)?.to_string();
+        self.check_request_visibility(&from_user, &to_user, &image_id, share_token).await?;
+
+        // Reject requests that exceed the owner's per-image grant cap
+        if let Some(max_views) = self.get_max_grant_views(&to_user, &image_id).await {
+            if requested_views > max_views {
+                bail!(
+                    "{} allows at most {} views for {} (requested {})",
+                    to_user, max_views, image_id, requested_views
+                );
+            }
+        }
+
+        // Owners choose per image whether anonymous users may ask for it
+        {
+            let users = self.users.read().await;
+            let anonymous = users.get(&from_user).is_some_and(UserEntry::is_anonymous);
+            if anonymous && !users.get(&to_user).is_some_and(|owner| owner.allows_anonymous(&image_id)) {
+                bail!("{} does not accept anonymous requests for {}", to_user, image_id);
+            }
+        }
+        let request_id = Uuid::new_v4().to_string();
+        let request = PendingRequest {
+            request_id: request_id.clone(),
+            from_user,
+            to_user,
+            image_id,
+            requested_views,
+            timestamp: SystemTime::now(),
+            status: RequestStatus::Pending,
+            delta_only,
+            requested_max_dimension,
+            approved_by: None,
+            granted_views: None,
+            awaiting_delivery: false,
+            responded_at: None,
+            response_note: None,
+            grant_token: None,
+            sequence: 0,
+        };
+
+        // Acknowledged only once a quorum holds it, so it outlives this server
+        let _committing = self.lock_commit(&requests_lock(&request.to_user)).await;
+        self.commit_queued(QueuedChanges { requests: vec![request], ..Default::default() }).await?;
+
+        info!("[{}] New request saved: {}", self.server_id, request_id);
+        Ok(request_id)
+    }
+
+    /// Refuse a request if the requester already has the most allowed pending with the owner
+    ///
+    /// Refusals are noted so the owner can see who is being throttled.
+    pub async fn check_request_flood(&self, from_user: &str, to_user: &str, outstanding: usize) -> Result<()> {
+        if outstanding < self.max_pending_per_sender {
+            return Ok(());
+        }
+
+        let now = SystemTime::now();
+        let mut throttles = self.request_throttles.write().await;
+        let notes = throttles.entry(to_user.to_string()).or_default();
+        match notes.iter_mut().find(|note| note.sender == from_user) {
+            Some(note) => {
+                note.rejected += 1;
+                note.last_rejected = now;
+            }
+            None => notes.push(ThrottledSender {
+                sender: from_user.to_string(),
+                rejected: 1,
+                first_rejected: now,
+                last_rejected: now,
+            }),
+        }
+        warn!("[{}] Throttled request from {} to {} ({} already pending)",
+              self.server_id, from_user, to_user, outstanding);
+        bail!(
+            "You already have {} requests waiting on {} (the limit is {}) - wait for them to answer or cancel one first",
+            outstanding, to_user, self.max_pending_per_sender
+        )
+    }
+
+    /// Requesters refused for flooding `owner` within the last day
+    pub async fn request_throttles_for(&self, owner: &str) -> Vec<ThrottledSender> {
+        let mut throttles = self.request_throttles.write().await;
+        let Some(notes) = throttles.get_mut(owner) else {
+            return Vec::new();
+        };
+        notes.retain(|note| note.last_rejected.elapsed().map_or(true, |age| age < THROTTLE_NOTE_TTL));
+        notes.clone()
+    }
+
+    /// Look up the owner's grant cap for one of their shared images
+    pub async fn get_max_grant_views(&self, owner: &str, image_id: &str) -> Option<u32> {
+        let users = self.users.read().await;
+        users
+            .get(owner)?
+            .shared_images
+            .iter()
+            .find(|img| img.image_id == image_id)?
+            .max_grant_views
+    }
+
+    /// Get pending requests for a user (requests TO them)
+    pub async fn get_pending_requests_for_user(&self, username: &str) -> Vec<PendingRequest> {
+        let requests = self.pending_requests.read().await;
+        requests
+            .values()
+            .filter(|r| r.to_user == username && r.status == RequestStatus::Pending)
+            .cloned()
+            .collect()
+    }
+
+    /// Pending requests to `owner` held here that have waited past the reminder threshold, oldest first
+    pub async fn reminders_for(&self, owner: &str) -> Vec<Reminder> {
+        let now = SystemTime::now();
+        let requests = self.pending_requests.read().await;
+        let mut snoozes = self.reminder_snoozes.write().await;
+        // Forget snoozes for requests that were answered, cancelled or dropped
+        snoozes.retain(|id, _| requests.get(id).is_some_and(|r| r.status == RequestStatus::Pending));
+
+        let mut reminders: Vec<Reminder> = requests
+            .values()
+            .filter(|r| r.to_user == owner && r.status == RequestStatus::Pending)
+            .filter(|r| now.duration_since(r.timestamp).unwrap_or_default() >= self.remind_after)
+            .filter(|r| match snoozes.get(&r.request_id) {
+                Some(Some(until)) => *until <= now,
+                Some(None) => false,
+                None => true,
+            })
+            .map(|r| Reminder {
+                request_id: r.request_id.clone(),
+                from_user: r.from_user.clone(),
+                image_id: r.image_id.clone(),
+                requested_views: r.requested_views,
+                requested_at: r.timestamp,
+            })
+            .collect();
+        reminders.sort_by_key(|r| r.requested_at);
+        reminders
+    }
+
+    /// Snooze reminders about one of `owner`'s requests for `snooze`, or dismiss them if unset
+    pub async fn snooze_reminder(&self, owner: &str, request_id: &str, snooze: Option<Duration>) -> Result<String> {
+        let requests = self.pending_requests.read().await;
+        let Some(request) = requests.get(request_id) else {
+            bail!("Request not found");
+        };
+        if request.to_user != owner {
+            bail!("Only the owner can snooze reminders about this request");
+        }
+        if request.status != RequestStatus::Pending {
+            bail!("Request has already been {:?}", request.status);
+        }
+
+        let until = snooze.map(|snooze| SystemTime::now() + snooze);
+        self.reminder_snoozes.write().await.insert(request_id.to_string(), until);
+        Ok(match snooze {
+            Some(snooze) => format!("Reminders about {} snoozed for {}", request_id, describe_ttl(snooze)),
+            None => format!("Reminders about {} dismissed", request_id),
+        })
+    }
+
+    /// Pending requests `delegate` may answer for the owners who delegated to them
+    pub async fn get_delegated_requests(&self, delegate: &str) -> Vec<PendingRequest> {
+        let users = self.users.read().await;
+        let delegations: HashMap<&str, &Delegation> = users
+            .values()
+            .filter_map(|user| {
+                let delegation = user.delegates.iter().find(|d| d.delegate == delegate)?;
+                Some((user.username.as_str(), delegation))
+            })
+            .collect();
+
+        let requests = self.pending_requests.read().await;
+        requests
+            .values()
+            .filter(|r| r.status == RequestStatus::Pending)
+            .filter(|r| delegations.get(r.to_user.as_str()).is_some_and(|d| d.covers(&r.image_id)))
+            .cloned()
+            .collect()
+    }
+
+    /// Respond to a request (accept or reject), as its recipient or one of their delegates
+    pub async fn respond_to_request(
+        &self,
+        request_id: &str,
+        owner: &str,
+        key: Option<&str>,
+        accept: bool,
+        granted_views: Option<u32>,
+        note: Option<String>,
+    ) -> Result<(String, PendingRequest)> {
+        // The response carries the grant token, so only the responder themselves may answer
+        self.check_user_key(owner, key).await?;
+
+        // Check delegation before locking requests, as renames take users first
+        let recipient = self.pending_requests.read().await.get(request_id).map(|r| (r.to_user.clone(), r.image_id.clone()));
+        let (recipient_user, approved_by) = match recipient {
+            Some((to_user, _)) if to_user == owner => (to_user, None),
+            Some((to_user, image_id)) if self.is_delegate(&to_user, owner, &image_id).await => (to_user, Some(owner.to_string())),
+            Some(_) => bail!("Only the recipient or their delegate can respond to this request"),
+            None => bail!("Request not found"),
+        };
+
+        let _committing = self.lock_commit(&requests_lock(&recipient_user)).await;
+        let current = self.pending_requests.read().await.get(request_id).cloned();
+
+        match current {
+            Some(mut request) => {
+                request.approved_by = approved_by;
+                request.granted_views = granted_views.filter(|_| accept);
+                request.awaiting_delivery = accept;
+                request.responded_at = Some(SystemTime::now());
+                request.response_note = note;
+                request.grant_token = accept.then(|| uuid::Uuid::new_v4().to_string());
+
+                // Update status
+                request.status = if accept {
+                    RequestStatus::Accepted
+                } else {
+                    RequestStatus::Rejected
+                };
+                self.commit_queued(QueuedChanges { requests: vec![request.clone()], ..Default::default() }).await?;
+
+                let message = if accept {
+                    format!("Request accepted. User {} can now access the image.", request.from_user)
+                } else {
+                    "Request rejected.".to_string()
+                };
+
+                info!(
+                    "[{}] Request {} {} by {}",
+                    self.server_id,
+                    request_id,
+                    if accept { "accepted" } else { "rejected" },
+                    owner
+                );
+
+                if accept {
+                    self.record_grant(&request.to_user, &request.image_id, &request.from_user, request.views_to_grant()).await;
                 }
+                Ok((message, request))
             }
+            None => bail!("Request not found"),
         }
-        
-        warn!("[{}] Could not sync from any peer, using local state", self.server_id);
-        Ok(())
     }
-    
-    pub async fn register_user(
+
+    /// Respond to every pending request to `owner` matching `filter` in one commit,
+    /// so none are answered twice or slip in half-way
+    pub async fn respond_to_requests_bulk(
         &self,
-        username: String,
-        p2p_address: String,
-        shared_images: Vec<ImageInfo>,
-    ) -> Result<()> {
-        let mut users = self.users.write().await;
-        
-        let entry = UserEntry {
-            username: username.clone(),
-            p2p_address,
-            last_heartbeat: SystemTime::now(),
-            status: UserStatus::Online,
-            shared_images,
+        owner: &str,
+        filter: &RequestFilter,
+        accept: bool,
+    ) -> Result<Vec<PendingRequest>> {
+        let _committing = self.lock_commit(&requests_lock(owner)).await;
+        let status = if accept { RequestStatus::Accepted } else { RequestStatus::Rejected };
+
+        let responded: Vec<PendingRequest> = self
+            .pending_requests
+            .read()
+            .await
+            .values()
+            .filter(|r| r.to_user == owner && r.status == RequestStatus::Pending && filter.matches(r))
+            .map(|r| {
+                let mut r = r.clone();
+                r.status = status.clone();
+                r.awaiting_delivery = accept;
+                r.responded_at = Some(SystemTime::now());
+                r.grant_token = accept.then(|| uuid::Uuid::new_v4().to_string());
+                r
+            })
+            .collect();
+        if !responded.is_empty() {
+            self.commit_queued(QueuedChanges { requests: responded.clone(), ..Default::default() }).await?;
+        }
+
+        info!(
+            "[{}] {} {} request(s) to {} in bulk",
+            self.server_id,
+            if accept { "Accepted" } else { "Rejected" },
+            responded.len(),
+            owner
+        );
+        if accept {
+            for request in &responded {
+                self.record_grant(&request.to_user, &request.image_id, &request.from_user, request.views_to_grant()).await;
+            }
+        }
+        Ok(responded)
+    }
+
+    /// Remember the views `recipient` now holds on `owner`'s image
+    ///
+    /// Revocations are kept as zero-view grants, so they win over older grants other
+    /// replicas still hold when the answers are merged.
+    async fn record_grant(&self, owner: &str, image_id: &str, recipient: &str, views: u32) {
+        let mut grants = self.grants.write().await;
+        grants.retain(|g| !(g.owner == owner && g.image_id == image_id && g.recipient == recipient));
+        grants.push(ExistingGrant {
+            owner: owner.to_string(),
+            image_id: image_id.to_string(),
+            recipient: recipient.to_string(),
+            views,
+            granted_at: SystemTime::now(),
+        });
+    }
+
+    /// Grants `recipient` holds, only on `owner`'s images if given (revoked ones included)
+    pub async fn grants_for(&self, recipient: &str, owner: Option<&str>) -> Vec<ExistingGrant> {
+        self.grants.read().await
+            .iter()
+            .filter(|g| g.recipient == recipient && owner.is_none_or(|owner| g.owner == owner))
+            .cloned()
+            .collect()
+    }
+
+    /// Grants `recipient` holds on `owner`'s images, here and on every other replica
+    async fn grants_everywhere(&self, recipient: &str, owner: &str) -> Vec<ExistingGrant> {
+        let mut grants = self.grants_for(recipient, Some(owner)).await;
+        let query = DirectoryMessage::QueryUser {
+            username: owner.to_string(),
+            requesting_user: Some(recipient.to_string()),
         };
+        for answer in self.ask_peers(query).await {
+            if let DirectoryMessage::QueryUserResponse { grants: found, .. } = answer {
+                merge_grants(&mut grants, found);
+            }
+        }
+        grants
+    }
+
+    /// Note that an accepted request's image reached the requester (or is stored for them)
+    pub async fn mark_request_delivered(&self, request_id: &str, username: &str, key: Option<&str>) -> Result<()> {
+        self.check_user_key(username, key).await?;
+        let Some(owner) = self.pending_requests.read().await.get(request_id).map(|r| r.to_user.clone()) else {
+            bail!("Request not found");
+        };
+        let _committing = self.lock_commit(&requests_lock(&owner)).await;
+        let Some(mut request) = self.pending_requests.read().await.get(request_id).cloned() else {
+            bail!("Request not found");
+        };
+        if request.to_user != username && request.approved_by.as_deref() != Some(username) {
+            bail!("Only the owner or the delegate who accepted can mark this request delivered");
+        }
+        if request.awaiting_delivery {
+            request.awaiting_delivery = false;
+            let from_user = request.from_user.clone();
+            self.commit_queued(QueuedChanges { requests: vec![request], ..Default::default() }).await?;
+            info!("[{}] Request {} delivered to {}", self.server_id, request_id, from_user);
+        }
+        Ok(())
+    }
+
+    /// Views `user` may be raised to on `owner`'s `image_id` under a grant token
+    ///
+    /// Tokens come from accepted requests still awaiting delivery, and from accepted
+    /// trades (the trade ID) until both sides are deposited.
+    pub async fn verify_grant(&self, owner: &str, user: &str, image_id: &str, token: &str) -> Option<u32> {
+        let granted = self.pending_requests.read().await.values()
+            .find(|r| {
+                r.status == RequestStatus::Accepted
+                    && r.awaiting_delivery
+                    && r.to_user == owner
+                    && r.from_user == user
+                    && r.image_id == image_id
+                    && r.grant_token.as_deref().is_some_and(|t| constant_time_eq(t.as_bytes(), token.as_bytes()))
+            })
+            .map(PendingRequest::views_to_grant);
+        if granted.is_some() {
+            return granted;
+        }
+
+        let trades = self.trades.read().await;
+        let trade = trades.get(token).filter(|t| t.status == TradeStatus::Accepted)?;
+        let (given, received) = trade.sides_for(owner)?;
+        (received.owner == user && given.image_id == image_id).then_some(given.views)
+    }
+
+    /// Accepted requests to `owner` still awaiting delivery, once their first attempt has had time to finish
+    pub async fn get_undelivered_grants(&self, owner: &str) -> Vec<PendingRequest> {
+        let requests = self.pending_requests.read().await;
+        let now = SystemTime::now();
+        requests
+            .values()
+            .filter(|r| r.to_user == owner && r.status == RequestStatus::Accepted && r.awaiting_delivery)
+            .filter(|r| {
+                r.responded_at
+                    .and_then(|at| now.duration_since(at).ok())
+                    .is_none_or(|elapsed| elapsed >= DELIVERY_GRACE)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Cancel a pending request on behalf of the requester
+    pub async fn cancel_request(&self, request_id: &str, from_user: &str, key: Option<&str>) -> Result<PendingRequest> {
+        self.check_user_key(from_user, key).await?;
+        let Some(owner) = self.pending_requests.read().await.get(request_id).map(|r| r.to_user.clone()) else {
+            bail!("Request not found");
+        };
+        let _committing = self.lock_commit(&requests_lock(&owner)).await;
+
+        let request = match self.pending_requests.read().await.get(request_id) {
+            Some(request) => request.clone(),
+            None => bail!("Request not found"),
+        };
+
+        // Verify the canceller is the original requester
+        if request.from_user != from_user {
+            bail!("Only the requester can cancel this request");
+        }
+        if request.status != RequestStatus::Pending {
+            bail!("Request has already been {:?}", request.status);
+        }
+
+        self.commit_queued(QueuedChanges { removed_requests: vec![request_id.to_string()], ..Default::default() }).await?;
+        info!(
+            "[{}] Request {} cancelled by {}",
+            self.server_id, request_id, from_user
+        );
+        Ok(request)
+    }
+
+    /// Get notifications for a user (responses to their requests)
+    pub async fn get_notifications_for_user(&self, username: &str) -> Vec<PendingRequest> {
+        let requests = self.pending_requests.read().await;
+        requests
+            .values()
+            .filter(|r| {
+                r.from_user == username
+                    && (r.status == RequestStatus::Accepted || r.status == RequestStatus::Rejected)
+            })
+            .map(|r| PendingRequest { grant_token: None, ..r.clone() })
+            .collect()
+    }
+
+    /// Clear all notifications for a user (called when user goes offline)
+    pub async fn clear_notifications_for_user(&self, username: &str) -> Result<()> {
+        // Collect request IDs to remove (notifications are requests from this user that have been accepted/rejected)
+        let to_remove: Vec<String> = self
+            .pending_requests
+            .read()
+            .await
+            .iter()
+            .filter(|(_, r)| {
+                r.from_user == username
+                    && (r.status == RequestStatus::Accepted || r.status == RequestStatus::Rejected)
+                    // Kept until the owner's delivery goes through
+                    && !r.awaiting_delivery
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
         
-        let image_count = entry.shared_images.len();
-        users.insert(username.clone(), entry.clone());
-        info!("[{}] Registered user: {} with {} shared images", 
-              self.server_id, username, image_count);
-        
-        drop(users);
-        
-        // Persist to disk
-        let _ = self.save_to_disk().await;
+        let count = to_remove.len();
+        if count > 0 {
+            self.commit_queued(QueuedChanges { removed_requests: to_remove, ..Default::default() }).await?;
+            info!("[{}] Cleared {} notifications for user {}", self.server_id, count, username);
+        }
+        Ok(())
+    }
+
+    /// Clear all pending requests TO a user (requests they haven't responded to yet)
+    pub async fn clear_pending_requests_to_user(&self, username: &str) -> Result<()> {
+        let _committing = self.lock_commit(&requests_lock(username)).await;
         
-        // Replicate to peers
-        self.replicate_state().await;
+        // Remove pending requests where this user is the target (to_user)
+        let to_remove: Vec<String> = self
+            .pending_requests
+            .read()
+            .await
+            .iter()
+            .filter(|(_, r)| r.to_user == username && r.status == RequestStatus::Pending)
+            .map(|(id, _)| id.clone())
+            .collect();
         
+        let count = to_remove.len();
+        if count > 0 {
+            self.commit_queued(QueuedChanges { removed_requests: to_remove, ..Default::default() }).await?;
+            info!("[{}] Cleared {} pending requests to user {}", self.server_id, count, username);
+        }
         Ok(())
     }
-    
-    pub async fn update_heartbeat(&self, username: &str) -> Result<()> {
-        let mut users = self.users.write().await;
+
+    /// Store a pending permission update for an offline user
+    ///
+    /// It's dropped after `ttl` (capped at the directory's limit) if the user never collects it.
+    #[allow(clippy::too_many_arguments)]
+    pub async fn store_pending_permission_update(
+        &self,
+        from_owner: &str,
+        target_user: &str,
+        image_id: &str,
+        new_quota: u32,
+        embedded_image: Option<Vec<u8>>,
+        wipe: bool,
+        ttl: Option<Duration>,
+    ) -> Result<(String, Vec<PendingPermissionUpdate>)> {
+        let image_id = unqualify_image_id(from_owner, image_id)?;
+        let update_id = pending_update_id(from_owner, target_user, image_id);
+        let has_image = embedded_image.is_some();
         
-        if let Some(user) = users.get_mut(username) {
-            user.last_heartbeat = SystemTime::now();
-            user.status = UserStatus::Online;
-            Ok(())
+        let now = SystemTime::now();
+        let update = PendingPermissionUpdate {
+            update_id: update_id.clone(),
+            from_owner: from_owner.to_string(),
+            target_user: target_user.to_string(),
+            image_id: image_id.to_string(),
+            new_quota,
+            timestamp: now,
+            // Travels with the commit; each replica moves it into its own blob store
+            embedded_image,
+            blob_sha256: None,
+            wipe,
+            expires_at: Some(now + self.pending_update_ttl(ttl)),
+            sequence: 0,
+        };
+
+        let _committing = self.lock_commit(&updates_lock(target_user)).await;
+        let evicted = self.commit_queued(QueuedChanges { updates: vec![update], ..Default::default() }).await?;
+
+        if wipe {
+            info!("[{}] Stored pending wipe: {} wants {}'s copy of {} deleted", self.server_id, from_owner, target_user, image_id);
         } else {
-            bail!("User {} not found", username)
+            info!(
+                "[{}] Stored pending permission update: {} wants to change {}'s quota for {} to {} views (image attached: {})",
+                self.server_id, from_owner, target_user, image_id, new_quota, has_image
+            );
         }
+        self.record_grant(from_owner, image_id, target_user, if wipe { 0 } else { new_quota }).await;
+
+        Ok((update_id, evicted))
     }
-    
-    pub async fn unregister_user(&self, username: &str) -> Result<()> {
-        let mut users = self.users.write().await;
+
+    /// Evict blobs until under the cap (never `keep`), detaching them from the updates that held them
+    fn evict_update_blobs(
+        &self,
+        blobs: &mut BlobStore,
+        updates: &mut HashMap<String, PendingPermissionUpdate>,
+        keep: &[String],
+    ) -> Vec<PendingPermissionUpdate> {
+        let evicted_hashes = blobs.evict_to_cap(keep);
+        let mut evicted = Vec::new();
+        for update in updates.values_mut() {
+            if update.blob_sha256.as_ref().is_some_and(|h| evicted_hashes.contains(h)) {
+                update.blob_sha256 = None;
+                evicted.push(update.clone());
+            }
+        }
+        if !evicted.is_empty() {
+            warn!("[{}] Evicted {} queued image(s) to stay within {} MB blob cap",
+                  self.server_id, evicted.len(), blobs.cap_bytes / (1024 * 1024));
+        }
+        evicted
+    }
+
+    /// Tell owners (best effort) that the images queued in their updates were evicted
+    pub async fn notify_evicted_updates(&self, evicted: Vec<PendingPermissionUpdate>) {
+        for update in evicted {
+            let Some(owner) = self.query_user(&update.from_owner).await else { continue };
+            if owner.status != UserStatus::Online
+                || !owner.supports(crate::p2p_protocol::CAP_REQUEST_NOTICES)
+            {
+                continue;
+            }
+            tokio::spawn(async move {
+                if let Err(e) = crate::p2p_protocol::notify_pending_image_evicted(
+                    &owner.p2p_address,
+                    &update.update_id,
+                    &update.target_user,
+                    &update.image_id,
+                )
+                .await
+                {
+                    warn!("Could not notify {} of evicted image: {}", owner.username, e);
+                }
+            });
+        }
+    }
+
+    /// Queue a denial report for an owner who is offline
+    pub async fn store_access_denial(&self, denial: crate::p2p_protocol::AccessDenial) {
+        info!("[{}] Stored access denial: {} denied on {}'s {}",
+              self.server_id, denial.viewer, denial.owner, denial.image_id);
         
-        if let Some(user) = users.get_mut(username) {
-            user.status = UserStatus::Offline;
-            info!("[{}] User {} went offline", self.server_id, username);
-            
-            drop(users);
-            
-            // Clear all notifications for this user (accepted/rejected requests they made)
-            self.clear_notifications_for_user(username).await;
-            
-            // Optionally: Also clear pending requests TO this user that they haven't responded to
-            // This prevents stale requests from accumulating
-            self.clear_pending_requests_to_user(username).await;
-            
-            let _ = self.save_to_disk().await;
-            self.replicate_state().await;
-            
-            Ok(())
-        } else {
-            bail!("User {} not found", username)
+        let mut denials = self.access_denials.write().await;
+        denials.push(denial);
+        if denials.len() > MAX_ACCESS_DENIALS {
+            let excess = denials.len() - MAX_ACCESS_DENIALS;
+            denials.drain(..excess);
+        }
+    }
+    
+    /// How long a pending update waits: the TTL asked for, capped at the directory's limit
+    fn pending_update_ttl(&self, requested: Option<Duration>) -> Duration {
+        requested.map_or(self.pending_update_ttl, |ttl| ttl.min(self.pending_update_ttl))
+    }
+
+    /// Drop pending updates past their expiry, queueing a notice for each owner
+    ///
+    /// Updates without their own expiry (stored before it existed, or by trades) get the default TTL.
+    pub async fn expire_pending_updates(&self) -> usize {
+        let now = SystemTime::now();
+        let mut updates = self.pending_permission_updates.write().await;
+        let expired_ids: Vec<String> = updates
+            .values()
+            .filter(|u| u.expires_at.unwrap_or(u.timestamp + self.pending_update_ttl) <= now)
+            .map(|u| u.update_id.clone())
+            .collect();
+        if expired_ids.is_empty() {
+            return 0;
+        }
+
+        let mut blobs = self.blobs.write().await;
+        let mut notices = self.expired_deliveries.write().await;
+        for update_id in &expired_ids {
+            let Some(update) = updates.remove(update_id) else { continue };
+            release_blob(&mut blobs, &updates, update.blob_sha256.as_deref());
+            info!("[{}] Pending update {} from {} to {} expired undelivered",
+                  self.server_id, update.update_id, update.from_owner, update.target_user);
+            notices.push(ExpiredDelivery {
+                update_id: update.update_id,
+                from_owner: update.from_owner,
+                target_user: update.target_user,
+                image_id: update.image_id,
+                new_quota: update.new_quota,
+                wipe: update.wipe,
+                had_image: update.blob_sha256.is_some(),
+                queued_at: update.timestamp,
+                expired_at: now,
+            });
+        }
+        if notices.len() > MAX_EXPIRED_DELIVERIES {
+            let excess = notices.len() - MAX_EXPIRED_DELIVERIES;
+            notices.drain(..excess);
+        }
+        expired_ids.len()
+    }
+
+    /// Get and remove expiry notices for an owner
+    pub async fn take_expired_deliveries(&self, owner: &str) -> Vec<ExpiredDelivery> {
+        let mut notices = self.expired_deliveries.write().await;
+        let (owned, rest) = notices.drain(..).partition(|n| n.from_owner == owner);
+        *notices = rest;
+        owned
+    }
+
+    /// Get and remove stored denial reports for an owner
+    pub async fn take_access_denials(&self, owner: &str) -> Vec<crate::p2p_protocol::AccessDenial> {
+        let mut denials = self.access_denials.write().await;
+        let (owned, rest) = denials.drain(..).partition(|d| d.owner == owner);
+        *denials = rest;
+        owned
+    }
+
+    /// Get and remove pending permission updates for a user, images inline, for older clients
+    ///
+    /// Oldest first, stopping once the attached images would pass `MAX_UPDATE_FETCH_BYTES`
+    /// (always at least one update); the rest stay queued for the next fetch.
+    pub async fn get_and_clear_pending_updates(&self, username: &str) -> Result<Vec<PendingPermissionUpdate>> {
+        let _committing = self.lock_commit(&updates_lock(username)).await;
+        let mut blobs = self.blobs.write().await;
+        let updates = self.pending_permission_updates.read().await;
+        let mut queued: Vec<&PendingPermissionUpdate> = updates
+            .values()
+            .filter(|u| u.target_user == username)
+            .collect();
+        queued.sort_by_key(|u| u.timestamp);
+
+        let mut user_updates = Vec::new();
+        let mut fetch_bytes = 0;
+        for update in queued {
+            let size = update.blob_sha256.as_deref().and_then(|hash| blobs.size(hash)).unwrap_or(0);
+            if !user_updates.is_empty() && fetch_bytes + size > MAX_UPDATE_FETCH_BYTES {
+                break;
+            }
+            fetch_bytes += size;
+            user_updates.push(update.clone());
+        }
+
+        // Read attached images back from the blob store
+        for update in user_updates.iter_mut() {
+            if let Some(hash) = update.blob_sha256.take() {
+                match blobs.get(&hash) {
+                    Ok(data) => update.embedded_image = Some(data),
+                    Err(e) => warn!("[{}] Missing image for {}: {}", self.server_id, update.update_id, e),
+                }
+            }
+        }
+        drop(updates);
+        drop(blobs);
+
+        // Remove the retrieved updates everywhere, dropping their unreferenced blobs
+        if !user_updates.is_empty() {
+            let removed_updates = user_updates.iter().map(|u| u.update_id.clone()).collect();
+            self.commit_queued(QueuedChanges { removed_updates, ..Default::default() }).await?;
+        }
+        Ok(user_updates)
+    }
+
+    /// Send all of `username`'s pending updates, then remove them once every image went out
+    ///
+    /// The response lists the updates oldest first, `blob_sha256` set on those whose image
+    /// follows. Each image is then copied from its blob file as its length (u64) and bytes, in
+    /// list order, so none is ever held in memory whole.
+    pub async fn stream_pending_updates(&self, stream: &mut TcpStream, username: &str) -> Result<()> {
+        let _committing = self.lock_commit(&updates_lock(username)).await;
+        let mut user_updates: Vec<PendingPermissionUpdate> = self
+            .pending_permission_updates
+            .read()
+            .await
+            .values()
+            .filter(|u| u.target_user == username)
+            .cloned()
+            .collect();
+        user_updates.sort_by_key(|u| u.timestamp);
+
+        // Open every blob first: an open file stays readable if the blob is evicted meanwhile
+        let mut images = Vec::new();
+        {
+            let mut blobs = self.blobs.write().await;
+            for update in user_updates.iter_mut() {
+                let Some(hash) = &update.blob_sha256 else { continue };
+                match blobs.open(hash) {
+                    Ok(image) => images.push(image),
+                    Err(e) => {
+                        warn!("[{}] Missing image for {}: {}", self.server_id, update.update_id, e);
+                        update.blob_sha256 = None;
+                    }
+                }
+            }
+        }
+
+        let removed_updates: Vec<String> = user_updates.iter().map(|u| u.update_id.clone()).collect();
+        write_directory_response(stream, &DirectoryMessage::GetPendingPermissionUpdatesResponse { updates: user_updates }).await?;
+        for (file, size) in images {
+            stream.write_u64(size).await?;
+            let sent = tokio::io::copy(&mut tokio::fs::File::from_std(file).take(size), stream).await?;
+            if sent != size {
+                bail!("Blob shrank while it was sent ({} of {} bytes)", sent, size);
+            }
+        }
+        stream.flush().await?;
+
+        // Left queued if the removal can't be committed; the client applies them again harmlessly
+        if !removed_updates.is_empty() {
+            if let Err(e) = self.commit_queued(QueuedChanges { removed_updates, ..Default::default() }).await {
+                warn!("[{}] Pending updates for {} sent but not cleared: {}", self.server_id, username, e);
+            }
+            if let Err(e) = self.save_to_disk().await {
+                error!("Failed to save state after clearing pending updates: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+    // =============================================================================
+    // IMAGE TRADES
+    // =============================================================================
+
+    /// Record a trade offer from `from_user` to `to_user`
+    pub async fn propose_trade(
+        &self,
+        from_user: &str,
+        to_user: &str,
+        offered: (String, u32),
+        requested: (String, u32),
+    ) -> Result<String> {
+        use uuid::Uuid;
+
+        if from_user == to_user {
+            bail!("Cannot trade with yourself");
+        }
+        if offered.1 == 0 || requested.1 == 0 {
+            bail!("Both sides of a trade must grant at least one view");
+        }
+        let offered = (unqualify_image_id(from_user, &offered.0)?.to_string(), offered.1);
+        let requested = (unqualify_image_id(to_user, &requested.0)?.to_string(), requested.1);
+
+        // Both images must be shared, and each side must respect its owner's grant cap
+        {
+            let users = self.users.read().await;
+            for (owner, (image_id, views)) in [(from_user, &offered), (to_user, &requested)] {
+                let Some(image) = users.get(owner)
+                    .and_then(|u| u.shared_images.iter().find(|img| img.image_id == *image_id))
+                else {
+                    bail!("{} does not share {}", owner, image_id);
+                };
+                if let Some(max_views) = image.max_grant_views.filter(|max| views > max) {
+                    bail!("{} allows at most {} views for {} (trade offers {})", owner, max_views, image_id, views);
+                }
+            }
         }
+
+        let trade_id = Uuid::new_v4().to_string();
+        info!("[{}] Trade {} proposed: {} offers {} for {}'s {}",
+              self.server_id, trade_id, from_user, offered.0, to_user, requested.0);
+        let trade = TradeProposal {
+            trade_id: trade_id.clone(),
+            proposer: TradeSide { owner: from_user.to_string(), image_id: offered.0, views: offered.1, deposit_sha256: None },
+            counterparty: TradeSide { owner: to_user.to_string(), image_id: requested.0, views: requested.1, deposit_sha256: None },
+            status: TradeStatus::Proposed,
+            timestamp: SystemTime::now(),
+        };
+
+        let mut trades = self.trades.write().await;
+        trades.retain(|_, t| {
+            t.is_open() || t.timestamp.elapsed().map(|age| age < TRADE_HISTORY_TTL).unwrap_or(true)
+        });
+        trades.insert(trade_id.clone(), trade);
+        Ok(trade_id)
     }
-    
-    pub async fn get_online_peers(&self, requesting_user: &str) -> Vec<UserEntry> {
-        let users = self.users.read().await;
 
-        users
-            .values()
-            .filter(|u| {
-                u.username != requesting_user
-                    && u.status == UserStatus::Online
-                    && self.is_user_active(u)
-            })
+    /// Trades a user is a party to, newest first
+    pub async fn get_trades_for_user(&self, username: &str) -> Vec<TradeProposal> {
+        let trades = self.trades.read().await;
+        let mut mine: Vec<TradeProposal> = trades.values()
+            .filter(|t| t.sides_for(username).is_some())
             .cloned()
-            .collect()
+            .collect();
+        mine.sort_by_key(|trade| std::cmp::Reverse(trade.timestamp));
+        mine
     }
 
-    /// Get ALL registered peers (both online and offline), excluding the requesting user
-    pub async fn get_all_peers(&self, requesting_user: &str) -> Vec<UserEntry> {
-        let users = self.users.read().await;
+    /// Accept or reject a trade on behalf of its counterparty
+    pub async fn respond_to_trade(
+        &self,
+        trade_id: &str,
+        username: &str,
+        key: Option<&str>,
+        accept: bool,
+    ) -> Result<(String, TradeProposal)> {
+        // An accepted trade's ID works as a grant token, so only the counterparty may accept
+        self.check_user_key(username, key).await?;
+        let mut trades = self.trades.write().await;
+        let mut blobs = self.blobs.write().await;
+        let updates = self.pending_permission_updates.read().await;
 
-        users
-            .values()
-            .filter(|u| u.username != requesting_user)
-            .cloned()
-            .collect()
-    }
-    
-    fn is_user_active(&self, user: &UserEntry) -> bool {
-        if let Ok(elapsed) = user.last_heartbeat.elapsed() {
-            elapsed < self.heartbeat_timeout
+        let Some(trade) = trades.get_mut(trade_id) else {
+            bail!("Trade not found");
+        };
+        if trade.counterparty.owner != username {
+            bail!("Only {} can respond to this trade", trade.counterparty.owner);
+        }
+        if trade.status != TradeStatus::Proposed {
+            bail!("Trade has already been {:?}", trade.status);
+        }
+
+        let message = if accept {
+            trade.status = TradeStatus::Accepted;
+            format!("Trade accepted. Deposit {} for {} to complete it.", trade.counterparty.image_id, trade.proposer.owner)
         } else {
-            false
+            trade.status = TradeStatus::Rejected;
+            release_trade_deposits(&mut blobs, &updates, trade);
+            "Trade rejected.".to_string()
+        };
+        trade.timestamp = SystemTime::now();
+
+        info!("[{}] Trade {} {} by {}", self.server_id, trade_id,
+              if accept { "accepted" } else { "rejected" }, username);
+        Ok((message, trade.clone()))
+    }
+
+    /// Withdraw from an open trade, releasing any escrowed images
+    pub async fn cancel_trade(&self, trade_id: &str, username: &str, key: Option<&str>) -> Result<TradeProposal> {
+        self.check_user_key(username, key).await?;
+        let mut trades = self.trades.write().await;
+        let mut blobs = self.blobs.write().await;
+        let updates = self.pending_permission_updates.read().await;
+
+        let Some(trade) = trades.get_mut(trade_id) else {
+            bail!("Trade not found");
+        };
+        if trade.sides_for(username).is_none() {
+            bail!("Only the parties to a trade can cancel it");
         }
+        if !trade.is_open() {
+            bail!("Trade has already been {:?}", trade.status);
+        }
+
+        trade.status = TradeStatus::Cancelled;
+        trade.timestamp = SystemTime::now();
+        release_trade_deposits(&mut blobs, &updates, trade);
+
+        info!("[{}] Trade {} cancelled by {}", self.server_id, trade_id, username);
+        Ok(trade.clone())
     }
-    
-    pub async fn update_shared_images(
+
+    /// Escrow one side of a trade, queueing both deliveries once both sides are in
+    ///
+    /// Returns a status message, whether the trade completed, and any queued images evicted to make room.
+    pub async fn deposit_trade_image(
         &self,
+        trade_id: &str,
         username: &str,
-        shared_images: Vec<ImageInfo>,
-    ) -> Result<()> {
-        let mut users = self.users.write().await;
-        
-        if let Some(user) = users.get_mut(username) {
-            user.shared_images = shared_images;
-            info!("[{}] Updated shared images for user: {}", self.server_id, username);
-            
-            drop(users);
-            
-            let _ = self.save_to_disk().await;
-            self.replicate_state().await;
-            
-            Ok(())
+        key: Option<&str>,
+        encrypted_image: Vec<u8>,
+    ) -> Result<(String, bool, Vec<PendingPermissionUpdate>)> {
+        self.check_user_key(username, key).await?;
+        let (mine, theirs) = {
+            let trades = self.trades.read().await;
+            let Some(trade) = trades.get(trade_id) else {
+                bail!("Trade not found");
+            };
+            let Some((mine, theirs)) = trade.sides_for(username) else {
+                bail!("Only the parties to a trade can deposit images");
+            };
+            (mine.clone(), theirs.clone())
+        };
+
+        // The escrowed carrier must really be ours and grant what the trade promises
+        let data = encrypted_image.clone();
+        let permissions = tokio::task::spawn_blocking(move || crate::p2p_protocol::embedded_permissions(&data))
+            .await?
+            .ok_or_else(|| anyhow::anyhow!("Deposited file is not a protected image"))?;
+        if permissions.owner != username {
+            bail!("Deposited image is owned by {}, not {}", permissions.owner, username);
+        }
+        let granted = permissions.quotas.get(&theirs.owner).copied().unwrap_or(0);
+        if granted < mine.views {
+            bail!("Deposited image grants {} {} views, but the trade promises {}", theirs.owner, granted, mine.views);
+        }
+
+        let mut trades = self.trades.write().await;
+        let mut blobs = self.blobs.write().await;
+        let mut updates = self.pending_permission_updates.write().await;
+
+        let Some(trade) = trades.get_mut(trade_id) else {
+            bail!("Trade not found");
+        };
+        let may_deposit = match trade.status {
+            TradeStatus::Accepted => true,
+            TradeStatus::Proposed => trade.proposer.owner == username,
+            _ => false,
+        };
+        if !may_deposit {
+            bail!("Cannot deposit while the trade is {:?}", trade.status);
+        }
+
+        let hash = blobs.put(&encrypted_image)?;
+        let side = if trade.proposer.owner == username { &mut trade.proposer } else { &mut trade.counterparty };
+        let previous = side.deposit_sha256.replace(hash.clone());
+        if previous.as_ref() != Some(&hash) {
+            release_blob(&mut blobs, &updates, previous.as_deref());
+        }
+        trade.timestamp = SystemTime::now();
+
+        let settled = if trade.status == TradeStatus::Accepted {
+            Some(settle_trade(trade, &mut blobs, &mut updates))
         } else {
-            bail!("User {} not found", username)
+            None
+        };
+
+        // Copies of the queued deliveries, images inline, for the other replicas
+        let mut replicate = Vec::new();
+        if let Some(Ok(update_ids)) = &settled {
+            for update_id in update_ids {
+                let Some(update) = updates.get_mut(update_id) else { continue };
+                update.sequence = self.next_sequence();
+                let mut copy = update.clone();
+                if let Some(hash) = copy.blob_sha256.take() {
+                    copy.embedded_image = Some(blobs.get(&hash)?);
+                }
+                replicate.push(copy);
+            }
         }
+
+        let keep = escrowed_blobs(&trades);
+        let evicted = self.evict_update_blobs(&mut blobs, &mut updates, &keep);
+        drop(updates);
+        drop(blobs);
+        drop(trades);
+
+        let completed = matches!(settled, Some(Ok(_)));
+        let message = match settled {
+            Some(Ok(_)) => {
+                info!("[{}] Trade {} completed - both images queued for delivery", self.server_id, trade_id);
+                let mut message = format!("Trade complete: {}'s {} and {}'s {} are queued for delivery",
+                                          mine.owner, mine.image_id, theirs.owner, theirs.image_id);
+                // The trade is done here either way; the deliveries just aren't safe from losing this server yet
+                if let Err(e) = self.replicate_with_quorum(HashMap::new(), QueuedChanges { updates: replicate, ..Default::default() }).await {
+                    warn!("[{}] Deliveries for trade {} held only here: {}", self.server_id, trade_id, e);
+                    message.push_str(&format!(" (held only on this server for now: {})", e));
+                }
+                message
+            }
+            Some(Err(e)) => {
+                warn!("[{}] Trade {} could not settle: {}", self.server_id, trade_id, e);
+                format!("Deposited {}, but the trade could not complete: {}", mine.image_id, e)
+            }
+            None => format!("Deposited {}; waiting for {} to accept", mine.image_id, theirs.owner),
+        };
+        Ok((message, completed, evicted))
     }
-    
-    pub async fn query_user(&self, username: &str) -> Option<UserEntry> {
-        let users = self.users.read().await;
-        users.get(username).cloned()
+
+    // =============================================================================
+    // ARCHIVING DEAD PEERS
+    // =============================================================================
+
+    /// `directory_state_dir-1.json` -> `directory_state_dir-1.archive.json`
+    fn archive_file(&self) -> PathBuf {
+        self.state_file.with_extension("archive.json")
     }
-    
-    pub async fn cleanup_inactive_users(&self) {
+
+    async fn load_archive(&self) -> Result<()> {
+        let path = self.archive_file();
+        if !path.exists() {
+            return Ok(());
+        }
+        let archived: HashMap<String, ArchivedUser> = serde_json::from_str(&fs::read_to_string(&path)?)
+            .with_context(|| format!("Failed to read archived users from {}", path.display()))?;
+        info!("[{}] ✓ Loaded {} archived users", self.server_id, archived.len());
+        *self.archived.write().await = archived;
+        Ok(())
+    }
+
+    async fn save_archive(&self) -> Result<()> {
+        let data = serde_json::to_string_pretty(&*self.archived.read().await)?;
+        fs::write(self.archive_file(), data)?;
+        Ok(())
+    }
+
+    /// Move users offline for longer than the retention period into the archive
+    ///
+    /// Each replica archives on its own clock; entries replicated from peers that haven't
+    /// archived the user yet are ignored (see `admit_replicated`). Returns how many were archived.
+    pub async fn archive_dead_users(&self) -> usize {
+        let Some(archive_after) = self.archive_after else {
+            return 0;
+        };
+        let now = SystemTime::now();
         let mut users = self.users.write().await;
-        
-        let mut to_mark_offline = Vec::new();
-        
-        for (username, user) in users.iter() {
-            if user.status == UserStatus::Online && !self.is_user_active(user) {
-                to_mark_offline.push(username.clone());
-            }
+        let dead: Vec<String> = users
+            .values()
+            .filter(|user| user.status == UserStatus::Offline)
+            .filter(|user| now.duration_since(user.last_heartbeat).is_ok_and(|offline| offline > archive_after))
+            .map(|user| user.username.clone())
+            .collect();
+        if dead.is_empty() {
+            return 0;
         }
-        
-        for username in to_mark_offline {
-            if let Some(user) = users.get_mut(&username) {
-                user.status = UserStatus::Offline;
-                info!("[{}] Marked user {} as offline due to timeout", 
-                      self.server_id, username);
+
+        let mut archived = self.archived.write().await;
+        for username in &dead {
+            if let Some(entry) = users.remove(username) {
+                archived.insert(username.clone(), ArchivedUser { entry, archived_at: now });
             }
         }
-        
+        drop(archived);
         drop(users);
-        
+
+        info!(
+            "[{}] Archived {} user(s) offline for over {}: {}",
+            self.server_id,
+            dead.len(),
+            describe_ttl(archive_after),
+            dead.join(", ")
+        );
+        if let Err(e) = self.save_archive().await {
+            error!("[{}] Failed to save archived users: {}", self.server_id, e);
+        }
         let _ = self.save_to_disk().await;
-        self.replicate_state().await;
+        dead.len()
     }
-    
-    async fn replicate_state(&self) {
-        if self.peer_servers.is_empty() {
-            return;
+
+    /// Archived users, most recently archived first
+    pub async fn list_archived_users(&self) -> Vec<ArchivedUser> {
+        let mut users: Vec<ArchivedUser> = self.archived.read().await.values().cloned().collect();
+        users.sort_by(|a, b| b.archived_at.cmp(&a.archived_at).then_with(|| a.entry.username.cmp(&b.entry.username)));
+        users
+    }
+
+    /// Delete archived users for good, with their follows and presence history
+    ///
+    /// Purges every archived user when `usernames` is empty. Names that aren't archived
+    /// (including users still active) are refused rather than skipped.
+    pub async fn purge_archived_users(&self, usernames: &[String]) -> Result<String> {
+        let mut archived = self.archived.write().await;
+        let unknown: Vec<&str> = usernames
+            .iter()
+            .filter(|username| !archived.contains_key(*username))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            bail!("Not archived: {}", unknown.join(", "));
         }
-        
-        let users = self.users.read().await;
-        let state_snapshot = users.clone();
-        drop(users);
-        
-        for peer in &self.peer_servers {
-            let peer_addr = peer.clone();
-            let snapshot = state_snapshot.clone();
-            
-            tokio::spawn(async move {
-                if let Err(e) = send_state_sync(&peer_addr, snapshot).await {
-                    error!("Failed to replicate to {}: {}", peer_addr, e);
-                }
-            });
+        let purged: Vec<String> = if usernames.is_empty() {
+            archived.drain().map(|(username, _)| username).collect()
+        } else {
+            usernames.iter().filter(|username| archived.remove(*username).is_some()).cloned().collect()
+        };
+        drop(archived);
+
+        let mut follows = self.follows.write().await;
+        let mut presence = self.presence.write().await;
+        for username in &purged {
+            follows.remove(username);
+            presence.remove(username);
         }
-    }
-    
-    pub async fn receive_state_sync(&self, incoming_state: HashMap<String, UserEntry>) {
-        let mut users = self.users.write().await;
-        
-        for (username, incoming_user) in incoming_state {
-            match users.get(&username) {
-                Some(existing_user) => {
-                    if incoming_user.last_heartbeat > existing_user.last_heartbeat {
-                        users.insert(username.clone(), incoming_user);
-                        info!("[{}] Updated user {} from peer sync", 
-                              self.server_id, username);
-                    }
-                }
-                None => {
-                    users.insert(username.clone(), incoming_user);
-                    info!("[{}] Added new user {} from peer sync", 
-                          self.server_id, username);
-                }
-            }
+        for followed in follows.values_mut() {
+            followed.retain(|username| !purged.contains(username));
         }
-        
-        drop(users);
-        
-        // Persist the merged state
+        drop(presence);
+        drop(follows);
+
+        self.save_archive().await?;
         let _ = self.save_to_disk().await;
-    }
-    
-    pub async fn get_full_state(&self) -> HashMap<String, UserEntry> {
-        let users = self.users.read().await;
-        users.clone()
+        let message = format!("Purged {} archived user(s)", purged.len());
+        info!("[{}] {}", self.server_id, message);
+        Ok(message)
     }
 
     // =============================================================================
-    // ASYNCHRONOUS REQUEST SYSTEM
+    // ADMINISTRATION
     // =============================================================================
 
-    /// Leave a request when target user is offline
-    pub async fn leave_request(
-        &self,
-        from_user: String,
-        to_user: String,
-        image_id: String,
-        requested_views: u32,
-    ) -> Result<String> {
-        use uuid::Uuid;
+    /// Rename `from` to `to`, merging into `to` if it is already registered
+    ///
+    /// Everything is checked before anything changes, so a conflict leaves state untouched.
+    /// A `replicated` rename was committed by the replica that took it, whose commit brings
+    /// the renamed entry here; only the rest is moved.
+    pub async fn rename_user(&self, from: &str, to: &str, replicated: bool) -> Result<String> {
+        if from == to {
+            bail!("Old and new usernames are the same");
+        }
+        if to.trim().is_empty() {
+            bail!("New username cannot be empty");
+        }
 
-        let request_id = Uuid::new_v4().to_string();
-        let request = PendingRequest {
-            request_id: request_id.clone(),
-            from_user,
-            to_user,
-            image_id,
-            requested_views,
-            timestamp: SystemTime::now(),
-            status: RequestStatus::Pending,
+        let Some(old_entry) = self.users.read().await.get(from).cloned() else {
+            bail!("User {} not found", from);
+        };
+
+        // The renamed entry is committed like any other change before anything else moves
+        let renamed = |existing: Option<&UserEntry>| {
+            let mut merged = match existing {
+                Some(existing) => {
+                    let old_online = old_entry.status == UserStatus::Online && self.is_user_active(&old_entry);
+                    let new_online = existing.status == UserStatus::Online && self.is_user_active(existing);
+                    if old_online && new_online {
+                        bail!("Both {} and {} are online - ask one of them to go offline first", from, to);
+                    }
+                    let clashing: Vec<&str> = old_entry
+                        .shared_images
+                        .iter()
+                        .filter(|img| existing.shared_images.iter().any(|e| e.image_id == img.image_id))
+                        .map(|img| img.image_id.as_str())
+                        .collect();
+                    if !clashing.is_empty() {
+                        bail!("{} and {} both share image(s): {}", from, to, clashing.join(", "));
+                    }
+
+                    // Keep the live registration's address and capabilities
+                    let mut merged = if old_online { old_entry.clone() } else { existing.clone() };
+                    merged.shared_images = existing.shared_images.clone();
+                    merged.shared_images.extend(old_entry.shared_images.iter().cloned());
+                    merged
+                }
+                None => old_entry.clone(),
+            };
+            // Delegating to oneself means nothing
+            merged.delegates.retain(|d| d.delegate != to && d.delegate != from);
+            Ok(UserEntry { username: to.to_string(), ..merged })
+        };
+        let merging = if replicated {
+            self.users.read().await.contains_key(to)
+        } else {
+            self.commit_user(to, renamed).await?.0.is_some()
         };
 
-        let mut requests = self.pending_requests.write().await;
-        requests.insert(request_id.clone(), request);
+        let mut users = self.users.write().await;
+        let mut requests = self.pending_requests.write().await;
+        let mut updates = self.pending_permission_updates.write().await;
+        let mut follows = self.follows.write().await;
+        let mut changes = self.catalog_changes.write().await;
+        let mut denials = self.access_denials.write().await;
+        let mut trades = self.trades.write().await;
+        let mut grants = self.grants.write().await;
+        let mut presence = self.presence.write().await;
+
+        // Requests between the two accounts would become requests to oneself
+        let before = requests.len();
+        requests.retain(|_, r| {
+            !((r.from_user == from && r.to_user == to) || (r.from_user == to && r.to_user == from))
+        });
+        let dropped = before - requests.len();
+        let mut moved_requests = 0;
+        for request in requests.values_mut() {
+            if request.from_user == from {
+                request.from_user = to.to_string();
+                moved_requests += 1;
+            }
+            if request.to_user == from {
+                request.to_user = to.to_string();
+                moved_requests += 1;
+            }
+        }
+
+        // Update IDs embed both usernames, so re-key them (newest wins on collision)
+        let affected: Vec<String> = updates
+            .values()
+            .filter(|u| u.from_owner == from || u.target_user == from)
+            .map(|u| u.update_id.clone())
+            .collect();
+        let moved_updates = affected.len();
+        for update_id in affected {
+            let Some(mut update) = updates.remove(&update_id) else { continue };
+            if update.from_owner == from {
+                update.from_owner = to.to_string();
+            }
+            if update.target_user == from {
+                update.target_user = to.to_string();
+            }
+            if update.from_owner == update.target_user {
+                continue;
+            }
+            update.update_id = pending_update_id(&update.from_owner, &update.target_user, &update.image_id);
+            match updates.get(&update.update_id) {
+                Some(existing) if existing.timestamp >= update.timestamp => {}
+                _ => {
+                    updates.insert(update.update_id.clone(), update);
+                }
+            }
+        }
+
+        if let Some(followed) = follows.remove(from) {
+            follows.entry(to.to_string()).or_default().extend(followed);
+        }
+        for (follower, followed) in follows.iter_mut() {
+            if followed.remove(from) && follower != to {
+                followed.insert(to.to_string());
+            }
+        }
+        if let Some(own) = follows.get_mut(to) {
+            own.remove(to);
+        }
+        for user in users.values_mut() {
+            for delegation in user.delegates.iter_mut().filter(|d| d.delegate == from) {
+                delegation.delegate = to.to_string();
+            }
+        }
+        // A merged account keeps its own presence history
+        if let Some(history) = presence.remove(from) {
+            presence.entry(to.to_string()).or_insert(history);
+        }
+
+        for change in changes.iter_mut().filter(|c| c.owner == from) {
+            change.owner = to.to_string();
+        }
+        for denial in denials.iter_mut() {
+            if denial.owner == from {
+                denial.owner = to.to_string();
+            }
+            if denial.viewer == from {
+                denial.viewer = to.to_string();
+            }
+        }
+
+        // Grants between the two accounts would be grants to oneself
+        grants.retain(|g| !((g.owner == from && g.recipient == to) || (g.owner == to && g.recipient == from)));
+        for grant in grants.iter_mut() {
+            if grant.owner == from {
+                grant.owner = to.to_string();
+            }
+            if grant.recipient == from {
+                grant.recipient = to.to_string();
+            }
+        }
+
+        // Open trades between the two accounts would be trades with oneself
+        for trade in trades.values_mut() {
+            for side in [&mut trade.proposer, &mut trade.counterparty] {
+                if side.owner == from {
+                    side.owner = to.to_string();
+                }
+            }
+            if trade.is_open() && trade.proposer.owner == trade.counterparty.owner {
+                trade.status = TradeStatus::Cancelled;
+            }
+        }
+
+        users.remove(from);
+
+        let message = format!(
+            "{} {} into {} ({} request reference(s) and {} pending update(s) moved, {} request(s) between them dropped)",
+            if merging { "Merged" } else { "Renamed" },
+            from, to, moved_requests, moved_updates, dropped
+        );
+        info!("[{}] {}", self.server_id, message);
+        Ok(message)
+    }
+
+    /// Whether this replica holds a request (requests aren't replicated)
+    pub async fn has_request(&self, request_id: &str) -> bool {
+        self.pending_requests.read().await.contains_key(request_id)
+    }
+
+    /// Pass a request to every other replica at once, collecting the answers that arrive in time
+    async fn ask_peers(&self, message: DirectoryMessage) -> Vec<DirectoryMessage> {
+        let departed = self.departed_peers.read().await.clone();
+        let mut asks = tokio::task::JoinSet::new();
 
-        info!("[{}] New request saved: {}", self.server_id, request_id);
-        Ok(request_id)
+        for peer in self.peer_servers.iter().filter(|p| !departed.contains(*p)) {
+            let peer = peer.clone();
+            let forwarded = self.peer_message(DirectoryMessage::Forwarded { message: Box::new(message.clone()) });
+            asks.spawn(async move {
+                let result = tokio::time::timeout(Duration::from_secs(2), send_directory_message(&peer, forwarded)).await;
+                (peer, result)
+            });
+        }
+
+        let mut answers = Vec::new();
+        while let Some(joined) = asks.join_next().await {
+            match joined {
+                Ok((_, Ok(Ok(answer)))) => answers.push(answer),
+                Ok((peer, Ok(Err(e)))) => warn!("[{}] Could not ask {}: {}", self.server_id, peer, e),
+                Ok((peer, Err(_))) => warn!("[{}] Timed out asking {}", self.server_id, peer),
+                Err(e) => warn!("[{}] Peer query failed: {}", self.server_id, e),
+            }
+        }
+        answers
     }
 
-    /// Get pending requests for a user (requests TO them)
-    pub async fn get_pending_requests_for_user(&self, username: &str) -> Vec<PendingRequest> {
-        let requests = self.pending_requests.read().await;
+    /// Our requests plus those other replicas hold, without duplicates
+    async fn merge_peer_requests(
+        &self,
+        mut requests: Vec<PendingRequest>,
+        query: DirectoryMessage,
+    ) -> Vec<PendingRequest> {
+        let mut seen: HashSet<String> = requests.iter().map(|r| r.request_id.clone()).collect();
+        for answer in self.ask_peers(query).await {
+            let found = match answer {
+                DirectoryMessage::GetPendingRequestsResponse { requests } => requests,
+                DirectoryMessage::GetNotificationsResponse { notifications } => notifications,
+                _ => continue,
+            };
+            requests.extend(found.into_iter().filter(|r| seen.insert(r.request_id.clone())));
+        }
         requests
-            .values()
-            .filter(|r| r.to_user == username && r.status == RequestStatus::Pending)
-            .cloned()
-            .collect()
     }
 
-    /// Respond to a request (accept or reject)
-    pub async fn respond_to_request(
-        &self,
-        request_id: &str,
-        owner: &str,
-        accept: bool,
-    ) -> Result<(String, PendingRequest)> {
-        let mut requests = self.pending_requests.write().await;
+    /// Add the due reminders other replicas hold for `owner`, keeping the list oldest first
+    async fn merge_peer_reminders(&self, reminders: &mut Vec<Reminder>, owner: &str) {
+        let mut seen: HashSet<String> = reminders.iter().map(|r| r.request_id.clone()).collect();
+        for answer in self.ask_peers(DirectoryMessage::GetReminders { owner: owner.to_string() }).await {
+            if let DirectoryMessage::GetRemindersResponse { reminders: theirs } = answer {
+                reminders.extend(theirs.into_iter().filter(|r| seen.insert(r.request_id.clone())));
+            }
+        }
+        reminders.sort_by_key(|r| r.requested_at);
+    }
 
-        match requests.get_mut(request_id) {
-            Some(request) => {
-                // Verify the responder is the request recipient
-                if request.to_user != owner {
-                    bail!("Only the recipient can respond to this request");
+    /// Forward an applied admin command (marked `replicated`) to every replica, returning how many took it
+    async fn forward_admin(&self, command: DirectoryMessage) -> usize {
+        let departed = self.departed_peers.read().await.clone();
+        let mut applied = 0;
+
+        for peer in self.peer_servers.iter().filter(|p| !departed.contains(*p)) {
+            let message = self.peer_message(command.clone());
+            match tokio::time::timeout(Duration::from_secs(2), send_directory_message(peer, message)).await {
+                Ok(Ok(DirectoryMessage::RenameUserResponse { success: true, .. }))
+                | Ok(Ok(DirectoryMessage::PurgeArchivedUsersResponse { success: true, .. })) => applied += 1,
+                Ok(Ok(DirectoryMessage::RenameUserResponse { message, .. }))
+                | Ok(Ok(DirectoryMessage::PurgeArchivedUsersResponse { message, .. })) => {
+                    warn!("[{}] Peer {} did not apply the command: {}", self.server_id, peer, message)
                 }
+                Ok(Ok(_)) => warn!("[{}] Unexpected admin response from {}", self.server_id, peer),
+                Ok(Err(e)) => warn!("[{}] Could not forward admin command to {}: {}", self.server_id, peer, e),
+                Err(_) => warn!("[{}] Timed out forwarding admin command to {}", self.server_id, peer),
+            }
+        }
 
-                // Update status
-                request.status = if accept {
-                    RequestStatus::Accepted
-                } else {
-                    RequestStatus::Rejected
-                };
+        applied
+    }
+}
 
-                let message = if accept {
-                    format!("Request accepted. User {} can now access the image.", request.from_user)
-                } else {
-                    format!("Request rejected.")
-                };
+/// This machine's offset from UTC at `at`, in seconds
+#[cfg(unix)]
+fn local_utc_offset_secs(at: SystemTime) -> i64 {
+    let secs = at.duration_since(SystemTime::UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0) as libc::time_t;
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&secs, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
 
-                info!(
-                    "[{}] Request {} {} by {}",
-                    self.server_id,
-                    request_id,
-                    if accept { "accepted" } else { "rejected" },
-                    owner
-                );
+/// No portable way to ask for the local offset off Unix, so times are shown in UTC
+#[cfg(not(unix))]
+fn local_utc_offset_secs(_at: SystemTime) -> i64 {
+    0
+}
+
+/// Append a status change, dropping the oldest past `PRESENCE_HISTORY_LIMIT`
+fn push_presence(changes: &mut VecDeque<PresenceChange>, online: bool, at: SystemTime) {
+    if changes.back().is_some_and(|last| last.online == online) {
+        return;
+    }
+    changes.push_back(PresenceChange { at, online });
+    if changes.len() > PRESENCE_HISTORY_LIMIT {
+        changes.pop_front();
+    }
+}
 
-                // Return a clone of the updated request
-                let request_copy = request.clone();
-                Ok((message, request_copy))
+/// Queue both sides of a fully deposited trade as pending updates, or neither
+///
+/// Checks everything before queueing anything; on failure the trade stays Accepted
+/// and any side whose escrowed image went missing must deposit again.
+fn settle_trade(
+    trade: &mut TradeProposal,
+    blobs: &mut BlobStore,
+    updates: &mut HashMap<String, PendingPermissionUpdate>,
+) -> Result<Vec<String>> {
+    let mut missing = Vec::new();
+    for side in [&mut trade.proposer, &mut trade.counterparty] {
+        match &side.deposit_sha256 {
+            Some(hash) if blobs.index.contains_key(hash) => {}
+            Some(_) => {
+                side.deposit_sha256 = None;
+                missing.push(side.owner.clone());
             }
-            None => bail!("Request not found"),
+            None => missing.push(side.owner.clone()),
         }
     }
-
-    /// Get notifications for a user (responses to their requests)
-    pub async fn get_notifications_for_user(&self, username: &str) -> Vec<PendingRequest> {
-        let requests = self.pending_requests.read().await;
-        requests
-            .values()
-            .filter(|r| {
-                r.from_user == username
-                    && (r.status == RequestStatus::Accepted || r.status == RequestStatus::Rejected)
-            })
-            .cloned()
-            .collect()
+    if !missing.is_empty() {
+        bail!("waiting for {} to deposit", missing.join(" and "));
     }
 
-    /// Clear all notifications for a user (called when user goes offline)
-    pub async fn clear_notifications_for_user(&self, username: &str) {
-        let mut requests = self.pending_requests.write().await;
-        
-        // Collect request IDs to remove (notifications are requests from this user that have been accepted/rejected)
-        let to_remove: Vec<String> = requests
-            .iter()
-            .filter(|(_, r)| {
-                r.from_user == username
-                    && (r.status == RequestStatus::Accepted || r.status == RequestStatus::Rejected)
-            })
-            .map(|(id, _)| id.clone())
-            .collect();
-        
-        let count = to_remove.len();
-        for id in to_remove {
-            requests.remove(&id);
-        }
-        
-        if count > 0 {
-            info!("[{}] Cleared {} notifications for user {}", self.server_id, count, username);
+    let now = SystemTime::now();
+    let queued: Vec<PendingPermissionUpdate> = [(&trade.proposer, &trade.counterparty), (&trade.counterparty, &trade.proposer)]
+        .into_iter()
+        .map(|(giver, receiver)| PendingPermissionUpdate {
+            update_id: pending_update_id(&giver.owner, &receiver.owner, &giver.image_id),
+            from_owner: giver.owner.clone(),
+            target_user: receiver.owner.clone(),
+            image_id: giver.image_id.clone(),
+            new_quota: giver.views,
+            timestamp: now,
+            embedded_image: None,
+            blob_sha256: giver.deposit_sha256.clone(),
+            wipe: false,
+            // Expires on the directory's default TTL
+            expires_at: None,
+            sequence: 0,
+        })
+        .collect();
+    let update_ids = queued.iter().map(|u| u.update_id.clone()).collect();
+
+    // The escrowed blobs now belong to the queued updates
+    let mut replaced = Vec::new();
+    for update in queued {
+        if let Some(previous) = updates.insert(update.update_id.clone(), update) {
+            replaced.push(previous);
         }
     }
+    for previous in replaced {
+        release_blob(blobs, updates, previous.blob_sha256.as_deref());
+    }
 
-    /// Clear all pending requests TO a user (requests they haven't responded to yet)
-    pub async fn clear_pending_requests_to_user(&self, username: &str) {
-        let mut requests = self.pending_requests.write().await;
-        
-        // Remove pending requests where this user is the target (to_user)
-        let to_remove: Vec<String> = requests
-            .iter()
-            .filter(|(_, r)| r.to_user == username && r.status == RequestStatus::Pending)
-            .map(|(id, _)| id.clone())
-            .collect();
-        
-        let count = to_remove.len();
-        for id in to_remove {
-            requests.remove(&id);
-        }
-        
-        if count > 0 {
-            info!("[{}] Cleared {} pending requests to user {}", self.server_id, count, username);
-        }
+    trade.proposer.deposit_sha256 = None;
+    trade.counterparty.deposit_sha256 = None;
+    trade.status = TradeStatus::Completed;
+    Ok(update_ids)
+}
+
+/// Blobs escrowed by trades that are still open
+fn escrowed_blobs(trades: &HashMap<String, TradeProposal>) -> Vec<String> {
+    trades.values()
+        .filter(|t| t.is_open())
+        .flat_map(|t| t.deposits().cloned())
+        .collect()
+}
+
+/// Give back a trade's escrowed images
+fn release_trade_deposits(
+    blobs: &mut BlobStore,
+    updates: &HashMap<String, PendingPermissionUpdate>,
+    trade: &mut TradeProposal,
+) {
+    for side in [&mut trade.proposer, &mut trade.counterparty] {
+        release_blob(blobs, updates, side.deposit_sha256.take().as_deref());
     }
+}
 
-    /// Store a pending permission update for an offline user
-    pub async fn store_pending_permission_update(
-        &self,
-        from_owner: &str,
-        target_user: &str,
-        image_id: &str,
-        new_quota: u32,
-        embedded_image: Option<Vec<u8>>,
-    ) -> String {
-        let update_id = format!("{}:{}:{}", from_owner, target_user, image_id);
-        let has_image = embedded_image.is_some();
-        
-        let update = PendingPermissionUpdate {
-            update_id: update_id.clone(),
-            from_owner: from_owner.to_string(),
-            target_user: target_user.to_string(),
-            image_id: image_id.to_string(),
-            new_quota,
-            timestamp: SystemTime::now(),
-            embedded_image,
-        };
+/// Delete a blob once no pending update references it
+fn release_blob(
+    blobs: &mut BlobStore,
+    updates: &HashMap<String, PendingPermissionUpdate>,
+    hash: Option<&str>,
+) {
+    let Some(hash) = hash else { return };
+    if !updates.values().any(|u| u.blob_sha256.as_deref() == Some(hash)) {
+        blobs.remove(hash);
+    }
+}
 
-        let mut updates = self.pending_permission_updates.write().await;
-        updates.insert(update_id.clone(), update);
+// =============================================================================
+// DIRECTORY SERVICE SERVER
+// =============================================================================
 
-        info!(
-            "[{}] Stored pending permission update: {} wants to change {}'s quota for {} to {} views (image attached: {})",
-            self.server_id, from_owner, target_user, image_id, new_quota, has_image
-        );
+/// Most namespaces one server keeps, so clients can't exhaust it by inventing names
+pub const MAX_NAMESPACES: usize = 256;
 
-        update_id
+/// Everything a directory state is built from, shared by every namespace on a server
+struct StateSettings {
+    server_id: String,
+    peer_servers: Vec<String>,
+    state_file: PathBuf,
+    auth: DirectoryAuth,
+    heartbeat: HeartbeatConfig,
+}
+
+impl StateSettings {
+    fn build(&self, state_file: PathBuf) -> DirectoryServiceState {
+        DirectoryServiceState::new(
+            self.heartbeat.timeout,
+            self.server_id.clone(),
+            self.peer_servers.clone(),
+            state_file,
+        )
+        .with_auth(self.auth.clone())
+        .with_heartbeat(self.heartbeat)
+        .with_blob_cap(blob_cap_from_env())
+        .with_pending_update_ttl(pending_update_ttl_from_env())
+        .with_remind_after(remind_after_from_env())
+        .with_archive_after(archive_after_from_env())
+        .with_max_pending_per_sender(max_pending_per_sender_from_env())
+        .with_write_quorum(write_quorum_from_env())
     }
 
-    /// Get and remove pending permission updates for a user
-    pub async fn get_and_clear_pending_updates(&self, username: &str) -> Vec<PendingPermissionUpdate> {
-        let mut updates = self.pending_permission_updates.write().await;
-        let user_updates: Vec<PendingPermissionUpdate> = updates
-            .values()
-            .filter(|u| u.target_user == username)
-            .cloned()
+    /// `directory_state_dir-1.json` -> `directory_state_dir-1.ns-<namespace>.json`
+    fn state_file_for(&self, namespace: &str) -> PathBuf {
+        let stem = self.state_file.file_stem().unwrap_or_default().to_string_lossy();
+        self.state_file.with_file_name(format!("{}.ns-{}.json", stem, namespace))
+    }
+
+    /// Namespaces this server kept state for before it last stopped
+    fn namespaces_on_disk(&self) -> Vec<String> {
+        let stem = self.state_file.file_stem().unwrap_or_default().to_string_lossy();
+        let prefix = format!("{}.ns-", stem);
+        let dir = self.state_file.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+        let mut namespaces: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let namespace = name.strip_prefix(&prefix)?.strip_suffix(".json")?.to_string();
+                validate_namespace(&namespace).is_ok().then_some(namespace)
+            })
             .collect();
+        namespaces.sort();
+        namespaces
+    }
+}
+
+/// Every namespace's state on this server; named ones are created when first used
+struct Namespaces {
+    settings: StateSettings,
+    default: Arc<DirectoryServiceState>,
+    named: RwLock<HashMap<String, Arc<DirectoryServiceState>>>,
+}
+
+impl Namespaces {
+    /// The state for `namespace` (None = the default namespace), creating it if it's new
+    async fn get(&self, namespace: Option<&str>) -> Result<Arc<DirectoryServiceState>> {
+        let Some(namespace) = namespace else {
+            return Ok(Arc::clone(&self.default));
+        };
+        validate_namespace(namespace)?;
+        if let Some(state) = self.named.read().await.get(namespace) {
+            return Ok(Arc::clone(state));
+        }
 
-        // Remove the retrieved updates
-        for update in &user_updates {
-            updates.remove(&update.update_id);
+        // Loaded outside the lock; if another connection got there first, theirs is kept
+        let state = Arc::new(
+            self.settings
+                .build(self.settings.state_file_for(namespace))
+                .in_namespace(namespace.to_string()),
+        );
+        if let Err(e) = state.load_from_disk().await {
+            warn!("[{}] Could not load namespace {} from disk: {}", self.settings.server_id, namespace, e);
+        }
+        let mut named = self.named.write().await;
+        if let Some(existing) = named.get(namespace) {
+            return Ok(Arc::clone(existing));
         }
+        if named.len() >= MAX_NAMESPACES {
+            bail!("This server already holds {} namespaces", MAX_NAMESPACES);
+        }
+        info!("[{}] Serving namespace {}", self.settings.server_id, namespace);
+        spawn_state_maintenance(&state, self.settings.heartbeat.interval);
+        named.insert(namespace.to_string(), Arc::clone(&state));
+        Ok(state)
+    }
 
-        user_updates
+    async fn all(&self) -> Vec<Arc<DirectoryServiceState>> {
+        let mut states = vec![Arc::clone(&self.default)];
+        states.extend(self.named.read().await.values().cloned());
+        states
     }
 }
 
-// =============================================================================
-// DIRECTORY SERVICE SERVER
-// =============================================================================
+/// Mark silent users offline, expire pending updates and save the state, in the background
+fn spawn_state_maintenance(state: &Arc<DirectoryServiceState>, interval: Duration) {
+    let cleanup_state = Arc::clone(state);
+    tokio::spawn(async move {
+        loop {
+            sleep(interval).await;
+            cleanup_state.cleanup_inactive_users().await;
+            cleanup_state.archive_dead_users().await;
+            if cleanup_state.expire_pending_updates().await > 0 {
+                if let Err(e) = cleanup_state.save_to_disk().await {
+                    error!("Failed to save state after expiring pending updates: {}", e);
+                }
+            }
+        }
+    });
+    
+    let save_state = Arc::clone(state);
+    tokio::spawn(async move {
+        loop {
+            sleep(Duration::from_secs(60)).await;
+            if let Err(e) = save_state.save_to_disk().await {
+                error!("Failed to save state: {}", e);
+            }
+        }
+    });
+}
 
 pub async fn start_directory_service(
     port: u16,
     server_id: String,
     peer_servers: Vec<String>,
     state_file: PathBuf,
+    auth: DirectoryAuth,
+    shutdown: impl std::future::Future<Output = ()>,
 ) -> Result<()> {
     let bind_addr = format!("0.0.0.0:{}", port);
     let listener = TcpListener::bind(&bind_addr).await?;
     
     info!("[{}] Directory service listening on {}", server_id, bind_addr);
     info!("[{}] State file: {}", server_id, state_file.display());
+    info!(
+        "[{}] Replica auth: {}, client auth: {}",
+        server_id,
+        if auth.replica_secret.is_some() { "enabled" } else { "disabled" },
+        if auth.client_token.is_some() { "enabled" } else { "disabled" }
+    );
     
-    let state = Arc::new(DirectoryServiceState::new(
-        Duration::from_secs(30),
-        server_id.clone(),
-        peer_servers.clone(),
-        state_file,
-    ));
+    let heartbeat = HeartbeatConfig::from_env();
+    info!(
+        "[{}] Heartbeat interval: {}s, offline after {}s",
+        server_id,
+        heartbeat.interval.as_secs(),
+        heartbeat.timeout.as_secs()
+    );
+
+    let settings = StateSettings { server_id: server_id.clone(), peer_servers: peer_servers.clone(), state_file, auth, heartbeat };
+    let namespaces = Arc::new(Namespaces {
+        default: Arc::new(settings.build(settings.state_file.clone())),
+        named: RwLock::new(HashMap::new()),
+        settings,
+    });
     
     // Load state from disk
-    if let Err(e) = state.load_from_disk().await {
+    if let Err(e) = namespaces.default.load_from_disk().await {
         warn!("[{}] Could not load state from disk: {}", server_id, e);
     }
+    for namespace in namespaces.settings.namespaces_on_disk() {
+        if let Err(e) = namespaces.get(Some(&namespace)).await {
+            warn!("[{}] Could not load namespace {}: {}", server_id, namespace, e);
+        }
+    }
     
     // Sync from peers if available
     if !peer_servers.is_empty() {
         info!("[{}] Attempting to sync state from peers...", server_id);
-        if let Err(e) = state.sync_from_peers().await {
-            warn!("[{}] Could not sync from peers: {}", server_id, e);
+        for state in namespaces.all().await {
+            if let Err(e) = state.sync_from_peers().await {
+                warn!("[{}] Could not sync from peers: {}", server_id, e);
+            }
         }
     }
     
     info!("[{}] ✓ Directory service ready!", server_id);
     
-    // Spawn cleanup task
-    let cleanup_state = Arc::clone(&state);
-    tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_secs(10)).await;
-            cleanup_state.cleanup_inactive_users().await;
+    // Let peers know we're (back) online so they resume replicating to us
+    if !peer_servers.is_empty() {
+        for announce_state in namespaces.all().await {
+            tokio::spawn(async move {
+                announce_state.announce_status(port, false).await;
+            });
         }
-    });
+    }
     
-    // Spawn periodic save task
-    let save_state = Arc::clone(&state);
-    tokio::spawn(async move {
-        loop {
-            sleep(Duration::from_secs(60)).await;
-            if let Err(e) = save_state.save_to_disk().await {
-                error!("Failed to save state: {}", e);
-            }
-        }
-    });
+    // Named namespaces start their own when created
+    spawn_state_maintenance(&namespaces.default, heartbeat.interval);
     
-    // Accept connections
+    // Accept connections until a shutdown signal arrives
+    tokio::pin!(shutdown);
+    let in_flight = Arc::new(());
     loop {
-        match listener.accept().await {
-            Ok((stream, addr)) => {
-                let state_ref = Arc::clone(&state);
-                tokio::spawn(async move {
-                    if let Err(e) = handle_directory_client(stream, addr, state_ref).await {
-                        error!("Error handling directory client {}: {}", addr, e);
-                    }
-                });
-            }
-            Err(e) => {
-                error!("Error accepting directory connection: {}", e);
-            }
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, addr)) => {
+                    let namespaces_ref = Arc::clone(&namespaces);
+                    let guard = Arc::clone(&in_flight);
+                    tokio::spawn(async move {
+                        if let Err(e) = handle_directory_client(stream, addr, namespaces_ref).await {
+                            error!("Error handling directory client {}: {}", addr, e);
+                        }
+                        drop(guard);
+                    });
+                }
+                Err(e) => {
+                    error!("Error accepting directory connection: {}", e);
+                }
+            },
+            _ = &mut shutdown => break,
         }
     }
+    
+    // Stop accepting, then give in-flight requests a moment to finish
+    drop(listener);
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    while Arc::strong_count(&in_flight) > 1 && tokio::time::Instant::now() < deadline {
+        sleep(Duration::from_millis(50)).await;
+    }
+    
+    for state in namespaces.all().await {
+        state.shutdown(port).await;
+    }
+    Ok(())
 }
 
 async fn handle_directory_client(
     mut stream: TcpStream,
     addr: SocketAddr,
-    state: Arc<DirectoryServiceState>,
+    namespaces: Arc<Namespaces>,
 ) -> Result<()> {
-    let msg_len = stream.read_u32().await?;
-    let mut msg_buf = vec![0u8; msg_len as usize];
-    stream.read_exact(&mut msg_buf).await?;
+    let message = read_directory_frame(&mut stream).await?;
+    
+    // Unwrap the auth and namespace envelopes (if any) and validate before processing
+    let (token, message) = match message {
+        DirectoryMessage::Authenticated { token, message } => (Some(token), *message),
+        other => (None, other),
+    };
+    let (namespace, message) = match message {
+        DirectoryMessage::Namespaced { namespace, message } => (Some(namespace), *message),
+        other => (None, other),
+    };
+    
+    // Every namespace shares the server's secrets, so nothing is created for a rejected client
+    let authorized = namespaces.default.auth.authorize(&message, token.as_deref());
+    let state = match authorized {
+        Ok(()) => namespaces.get(namespace.as_deref()).await,
+        Err(e) => Err(e),
+    };
+    let state = match state {
+        Ok(state) => state,
+        Err(e) => {
+            warn!("[{}] Rejected message from {}: {}", namespaces.settings.server_id, addr, e);
+            let response = DirectoryMessage::AuthError { message: e.to_string() };
+            return write_directory_response(&mut stream, &response).await;
+        }
+    };
     
-    let message: DirectoryMessage = serde_json::from_slice(&msg_buf)?;
+    // Trades live only on the replica that took them, and older replicas keep requests
+    // that way too, so look on the others unless another replica is already doing that
+    let (forwarded, message) = match message {
+        DirectoryMessage::Forwarded { message } => (true, *message),
+        other => (false, other),
+    };
+    let ask_peers = !forwarded && !state.peer_servers.is_empty();
+
+    // Relay messages hand the connection over to the relay, which keeps it open
+    let message = match message {
+        DirectoryMessage::RelayListen { handle, secret } => {
+            return state.relay.listen(stream, handle, secret).await;
+        }
+        DirectoryMessage::RelayAccept { circuit_id } => {
+            return state.relay.accept(stream, &circuit_id).await;
+        }
+        DirectoryMessage::RelayConnect { target } => {
+            if let Some((_, handle)) = parse_relay_address(&target) {
+                return state.relay.connect_hidden(stream, handle).await;
+            }
+            if state.is_public_peer_address(&target).await {
+                return state.relay.connect_public(stream, &target).await;
+            }
+            // A bare handle names a hidden peer on this relay
+            return state.relay.connect_hidden(stream, &target).await;
+        }
+        other => other,
+    };
     
     let response = match message {
         DirectoryMessage::Register {
             username,
             p2p_address,
             shared_images,
+            capabilities,
+            availability,
+            key,
         } => {
-            match state.register_user(username.clone(), p2p_address, shared_images).await {
+            match state.register_user(username.clone(), p2p_address, shared_images, capabilities, availability, key).await {
                 Ok(_) => DirectoryMessage::RegisterResponse {
                     success: true,
-                    message: format!("User {} registered successfully", username),
+                    message: format!(
+                        "User {} registered successfully{}",
+                        username,
+                        state.collision_note(&username).await
+                    ),
+                    heartbeat_interval_secs: Some(state.heartbeat_interval.as_secs()),
+                    heartbeat_timeout_secs: Some(state.heartbeat_timeout.as_secs()),
                 },
                 Err(e) => DirectoryMessage::RegisterResponse {
                     success: false,
                     message: format!("Registration failed: {}", e),
+                    heartbeat_interval_secs: None,
+                    heartbeat_timeout_secs: None,
                 },
             }
         }
-        DirectoryMessage::Heartbeat { username } => {
-            let success = state.update_heartbeat(&username).await.is_ok();
-            DirectoryMessage::HeartbeatResponse { success }
+        DirectoryMessage::Heartbeat { username, capabilities } => {
+            let success = state.update_heartbeat(&username, capabilities).await.is_ok();
+            let mut due_reminders = 0;
+            if success {
+                let mut reminders = state.reminders_for(&username).await;
+                if ask_peers {
+                    state.merge_peer_reminders(&mut reminders, &username).await;
+                }
+                due_reminders = reminders.len();
+            }
+            DirectoryMessage::HeartbeatResponse { success, due_reminders }
         }
         DirectoryMessage::Unregister { username } => {
             let success = state.unregister_user(&username).await.is_ok();
@@ -868,29 +5278,103 @@ async fn handle_directory_client(
             let peers = state.get_all_peers(&requesting_user).await;
             DirectoryMessage::QueryAllPeersResponse { peers }
         }
-        DirectoryMessage::UpdateSharedImages {
-            username,
-            shared_images,
-        } => {
-            match state.update_shared_images(&username, shared_images).await {
+        DirectoryMessage::SearchCatalog { query, limit, requesting_user } => {
+            let mut results = state.search_catalog(&query, limit.unwrap_or(DEFAULT_SEARCH_LIMIT), requesting_user.as_deref()).await;
+            if let Some(requester) = requesting_user {
+                let mut grants = state.grants_for(&requester, None).await;
+                if ask_peers {
+                    // Other replicas run the same search over the same catalogs
+                    let query = DirectoryMessage::SearchCatalog { query, limit, requesting_user: Some(requester) };
+                    for answer in state.ask_peers(query).await {
+                        if let DirectoryMessage::SearchCatalogResponse { results: found } = answer {
+                            merge_grants(&mut grants, found.into_iter().filter_map(|m| m.your_grant).collect());
+                        }
+                    }
+                }
+                if !forwarded {
+                    grants.retain(|g| g.views > 0);
+                }
+                for result in results.iter_mut() {
+                    result.your_grant = grants
+                        .iter()
+                        .find(|g| g.owner == result.owner && g.image_id == result.image.image_id)
+                        .cloned();
+                }
+            }
+            DirectoryMessage::SearchCatalogResponse { results }
+        }
+        DirectoryMessage::QueryImageHolders { image_id_or_hash, requesting_user } => {
+            let holders = state.image_holders(&image_id_or_hash, requesting_user.as_deref()).await;
+            DirectoryMessage::QueryImageHoldersResponse { holders }
+        }
+        DirectoryMessage::UpdateSharedImages {
+            username,
+            shared_images,
+        } => {
+            match state.update_shared_images(&username, shared_images).await {
+                Ok(_) => DirectoryMessage::UpdateResponse {
+                    success: true,
+                    message: format!("Shared images updated{}", state.collision_note(&username).await),
+                },
+                Err(e) => DirectoryMessage::UpdateResponse {
+                    success: false,
+                    message: format!("Update failed: {}", e),
+                },
+            }
+        }
+        DirectoryMessage::UpdateProfile { username, profile } => {
+            match state.update_profile(&username, profile).await {
                 Ok(_) => DirectoryMessage::UpdateResponse {
                     success: true,
-                    message: "Shared images updated".to_string(),
+                    message: "Profile updated".to_string(),
                 },
                 Err(e) => DirectoryMessage::UpdateResponse {
                     success: false,
-                    message: format!("Update failed: {}", e),
+                    message: format!("Profile update failed: {}", e),
                 },
             }
         }
-        DirectoryMessage::QueryUser { username } => {
-            let user = state.query_user(&username).await;
-            DirectoryMessage::QueryUserResponse { user }
+        DirectoryMessage::QueryUser { username, requesting_user } => {
+            let user = state.query_user_as(&username, requesting_user.as_deref()).await;
+            let grants = match requesting_user {
+                Some(requester) => {
+                    let mut grants = if ask_peers {
+                        state.grants_everywhere(&requester, &username).await
+                    } else {
+                        state.grants_for(&requester, Some(&username)).await
+                    };
+                    // Revoked grants only matter to the replica merging answers
+                    if !forwarded {
+                        grants.retain(|g| g.views > 0);
+                    }
+                    grants
+                }
+                None => Vec::new(),
+            };
+            DirectoryMessage::QueryUserResponse { user, grants }
+        }
+        DirectoryMessage::GetPresenceHistory { username } => {
+            let history = state.presence_history(&username).await;
+            DirectoryMessage::GetPresenceHistoryResponse { history }
         }
-        DirectoryMessage::SyncState { users } => {
-            state.receive_state_sync(users).await;
+        DirectoryMessage::SyncState { users, proposal_id, queued } => {
+            match proposal_id {
+                Some(proposal_id) => state.stage_proposal(proposal_id, users, queued).await,
+                None => {
+                    state.receive_state_sync(users).await;
+                    state.receive_queued(queued).await;
+                }
+            }
             DirectoryMessage::SyncStateResponse { success: true }
         }
+        DirectoryMessage::CommitState { proposal_id, commit } => {
+            let success = state.resolve_proposal(&proposal_id, commit).await;
+            DirectoryMessage::CommitStateResponse { success }
+        }
+        DirectoryMessage::PeerStatus { server_id, port, leaving } => {
+            state.set_peer_status(addr.ip(), port, &server_id, leaving).await;
+            DirectoryMessage::PeerStatusResponse { success: true }
+        }
 
         // Asynchronous request handling
         DirectoryMessage::LeaveRequest {
@@ -898,23 +5382,67 @@ async fn handle_directory_client(
             to_user,
             image_id,
             requested_views,
+            delta_only,
+            requested_max_dimension,
+            share_token,
         } => {
-            match state.leave_request(from_user, to_user, image_id, requested_views).await {
-                Ok(request_id) => DirectoryMessage::LeaveRequestResponse {
-                    success: true,
-                    request_id,
-                    message: "Request saved. User will be notified when online.".to_string(),
-                },
-                Err(e) => DirectoryMessage::LeaveRequestResponse {
+            // Count the requester's pending requests to this owner on every replica
+            let mut pending = state.get_pending_requests_for_user(&to_user).await;
+            if ask_peers {
+                let query = DirectoryMessage::GetPendingRequests { username: to_user.clone() };
+                pending = state.merge_peer_requests(pending, query).await;
+            }
+            let outstanding = pending.iter().filter(|r| r.from_user == from_user).count();
+
+            if let Err(e) = state.check_request_flood(&from_user, &to_user, outstanding).await {
+                DirectoryMessage::LeaveRequestResponse {
                     success: false,
                     request_id: String::new(),
-                    message: format!("Failed to save request: {}", e),
-                },
+                    message: e.to_string(),
+                }
+            } else {
+                match state.leave_request(from_user, to_user, image_id, requested_views, delta_only, requested_max_dimension, share_token.as_deref()).await {
+                    Ok(request_id) => DirectoryMessage::LeaveRequestResponse {
+                        success: true,
+                        request_id,
+                        message: "Request saved. User will be notified when online.".to_string(),
+                    },
+                    Err(e) => DirectoryMessage::LeaveRequestResponse {
+                        success: false,
+                        request_id: String::new(),
+                        message: format!("Failed to save request: {}", e),
+                    },
+                }
+            }
+        }
+
+        DirectoryMessage::GetRequestThrottles { username } => {
+            let mut throttled = state.request_throttles_for(&username).await;
+            if ask_peers {
+                let query = DirectoryMessage::GetRequestThrottles { username };
+                for answer in state.ask_peers(query).await {
+                    let DirectoryMessage::GetRequestThrottlesResponse { throttled: theirs } = answer else { continue };
+                    for note in theirs {
+                        match throttled.iter_mut().find(|ours| ours.sender == note.sender) {
+                            Some(ours) => {
+                                ours.rejected += note.rejected;
+                                ours.first_rejected = ours.first_rejected.min(note.first_rejected);
+                                ours.last_rejected = ours.last_rejected.max(note.last_rejected);
+                            }
+                            None => throttled.push(note),
+                        }
+                    }
+                }
             }
+            DirectoryMessage::GetRequestThrottlesResponse { throttled }
         }
 
         DirectoryMessage::GetPendingRequests { username } => {
-            let requests = state.get_pending_requests_for_user(&username).await;
+            let mut requests = state.get_pending_requests_for_user(&username).await;
+            if ask_peers {
+                let query = DirectoryMessage::GetPendingRequests { username };
+                requests = state.merge_peer_requests(requests, query).await;
+            }
             DirectoryMessage::GetPendingRequestsResponse { requests }
         }
 
@@ -922,13 +5450,44 @@ async fn handle_directory_client(
             request_id,
             owner,
             accept,
+            granted_views,
+            note,
+            key,
+        } if ask_peers && !state.has_request(&request_id).await => {
+            let query = DirectoryMessage::RespondToRequest { request_id: request_id.clone(), owner, accept, granted_views, note, key };
+            let answers = state.ask_peers(query).await;
+            answers
+                .iter()
+                .find(|answer| matches!(answer, DirectoryMessage::RespondToRequestResponse { success: true, .. }))
+                .or_else(|| answers.iter().find(|answer| matches!(answer, DirectoryMessage::RespondToRequestResponse { .. })))
+                .cloned()
+                .unwrap_or_else(|| DirectoryMessage::RespondToRequestResponse {
+                    success: false,
+                    message: format!("Failed to respond: request {} not found on any replica", request_id),
+                    request: None,
+                })
+        }
+
+        DirectoryMessage::RespondToRequest {
+            request_id,
+            owner,
+            accept,
+            granted_views,
+            note,
+            key,
         } => {
-            match state.respond_to_request(&request_id, &owner, accept).await {
-                Ok((message, request)) => DirectoryMessage::RespondToRequestResponse {
-                    success: true,
-                    message,
-                    request: Some(request),
-                },
+            match state.respond_to_request(&request_id, &owner, key.as_deref(), accept, granted_views, note).await {
+                Ok((message, request)) => {
+                    // The accepted request is the record that a delivery is owed, so keep it
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after responding to request: {}", e);
+                    }
+                    DirectoryMessage::RespondToRequestResponse {
+                        success: true,
+                        message,
+                        request: Some(request),
+                    }
+                }
                 Err(e) => DirectoryMessage::RespondToRequestResponse {
                     success: false,
                     message: format!("Failed to respond: {}", e),
@@ -937,55 +5496,539 @@ async fn handle_directory_client(
             }
         }
 
+        DirectoryMessage::MarkRequestDelivered { request_id, username, key }
+            if ask_peers && !state.has_request(&request_id).await =>
+        {
+            let query = DirectoryMessage::MarkRequestDelivered { request_id: request_id.clone(), username, key };
+            let answers = state.ask_peers(query).await;
+            answers
+                .iter()
+                .find(|answer| matches!(answer, DirectoryMessage::UpdateResponse { success: true, .. }))
+                .or_else(|| answers.first())
+                .cloned()
+                .unwrap_or_else(|| DirectoryMessage::UpdateResponse {
+                    success: false,
+                    message: format!("Request {} not found on any replica", request_id),
+                })
+        }
+
+        DirectoryMessage::MarkRequestDelivered { request_id, username, key } => {
+            match state.mark_request_delivered(&request_id, &username, key.as_deref()).await {
+                Ok(()) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after marking request delivered: {}", e);
+                    }
+                    DirectoryMessage::UpdateResponse {
+                        success: true,
+                        message: format!("Request {} delivered", request_id),
+                    }
+                }
+                Err(e) => DirectoryMessage::UpdateResponse {
+                    success: false,
+                    message: format!("Failed to mark request delivered: {}", e),
+                },
+            }
+        }
+
+        DirectoryMessage::VerifyGrant { owner, user, image_id, token } => {
+            let mut views = state.verify_grant(&owner, &user, &image_id, &token).await;
+            if views.is_none() && ask_peers {
+                // The request may have been accepted on another replica
+                let query = DirectoryMessage::VerifyGrant { owner, user, image_id, token };
+                views = state.ask_peers(query).await.into_iter().find_map(|answer| match answer {
+                    DirectoryMessage::VerifyGrantResponse { views } => views,
+                    _ => None,
+                });
+            }
+            DirectoryMessage::VerifyGrantResponse { views }
+        }
+
+        DirectoryMessage::GetUndeliveredGrants { owner, key } => {
+            // The grant tokens in these let the caller raise requesters' quotas
+            if let Err(e) = state.check_user_key(&owner, key.as_deref()).await {
+                return write_directory_response(&mut stream, &DirectoryMessage::AuthError { message: e.to_string() }).await;
+            }
+            let mut requests = state.get_undelivered_grants(&owner).await;
+            if ask_peers {
+                let query = DirectoryMessage::GetUndeliveredGrants { owner, key };
+                requests = state.merge_peer_requests(requests, query).await;
+            }
+            DirectoryMessage::GetPendingRequestsResponse { requests }
+        }
+
+        DirectoryMessage::SetAnonymousAccess { owner, image_ids, allowed, key } => {
+            match state.set_anonymous_access(&owner, key.as_deref(), &image_ids, allowed).await {
+                Ok(()) => DirectoryMessage::UpdateResponse {
+                    success: true,
+                    message: format!(
+                        "Anonymous users {} request {} image(s)",
+                        if allowed { "can now" } else { "can no longer" },
+                        image_ids.len()
+                    ),
+                },
+                Err(e) => DirectoryMessage::UpdateResponse {
+                    success: false,
+                    message: format!("Failed to update anonymous access: {}", e),
+                },
+            }
+        }
+
+        DirectoryMessage::SetDelegate { owner, delegate, image_ids, enabled, key } => {
+            match state.set_delegate(&owner, key.as_deref(), &delegate, &image_ids, enabled).await {
+                Ok(()) => DirectoryMessage::UpdateResponse {
+                    success: true,
+                    message: if enabled {
+                        format!("{} can now answer requests for your images", delegate)
+                    } else {
+                        format!("{} can no longer answer requests for your images", delegate)
+                    },
+                },
+                Err(e) => DirectoryMessage::UpdateResponse {
+                    success: false,
+                    message: format!("Failed to update delegate: {}", e),
+                },
+            }
+        }
+
+        DirectoryMessage::GetDelegatedRequests { delegate } => {
+            let mut requests = state.get_delegated_requests(&delegate).await;
+            if ask_peers {
+                let query = DirectoryMessage::GetDelegatedRequests { delegate };
+                requests = state.merge_peer_requests(requests, query).await;
+            }
+            DirectoryMessage::GetPendingRequestsResponse { requests }
+        }
+
+        DirectoryMessage::RespondToRequestsBulk { owner, filter, accept, key } => {
+            if let Err(e) = state.check_user_key(&owner, key.as_deref()).await {
+                return write_directory_response(&mut stream, &DirectoryMessage::AuthError { message: e.to_string() }).await;
+            }
+            let mut requests = state.respond_to_requests_bulk(&owner, &filter, accept).await.unwrap_or_else(|e| {
+                warn!("Bulk response for {} not committed: {}", owner, e);
+                Vec::new()
+            });
+            if !requests.is_empty() {
+                if let Err(e) = state.save_to_disk().await {
+                    error!("Failed to save state after bulk response: {}", e);
+                }
+            }
+            if ask_peers {
+                let query = DirectoryMessage::RespondToRequestsBulk { owner, filter, accept, key };
+                for answer in state.ask_peers(query).await {
+                    if let DirectoryMessage::RespondToRequestsBulkResponse { requests: theirs } = answer {
+                        // Replicas holding the same requests may answer them too
+                        for request in theirs {
+                            if !requests.iter().any(|r| r.request_id == request.request_id) {
+                                requests.push(request);
+                            }
+                        }
+                    }
+                }
+            }
+            DirectoryMessage::RespondToRequestsBulkResponse { requests }
+        }
+
         DirectoryMessage::GetNotifications { username } => {
-            let notifications = state.get_notifications_for_user(&username).await;
+            let mut notifications = state.get_notifications_for_user(&username).await;
+            if ask_peers {
+                let query = DirectoryMessage::GetNotifications { username };
+                notifications = state.merge_peer_requests(notifications, query).await;
+            }
             DirectoryMessage::GetNotificationsResponse { notifications }
         }
 
+        DirectoryMessage::GetReminders { owner } => {
+            let mut reminders = state.reminders_for(&owner).await;
+            if ask_peers {
+                state.merge_peer_reminders(&mut reminders, &owner).await;
+            }
+            DirectoryMessage::GetRemindersResponse { reminders }
+        }
+
+        DirectoryMessage::SnoozeReminder { owner, request_id, snooze_secs }
+            if ask_peers && !state.has_request(&request_id).await =>
+        {
+            let query = DirectoryMessage::SnoozeReminder { owner, request_id: request_id.clone(), snooze_secs };
+            let answers = state.ask_peers(query).await;
+            answers
+                .iter()
+                .find(|answer| matches!(answer, DirectoryMessage::UpdateResponse { success: true, .. }))
+                .or_else(|| answers.iter().find(|answer| matches!(answer, DirectoryMessage::UpdateResponse { .. })))
+                .cloned()
+                .unwrap_or_else(|| DirectoryMessage::UpdateResponse {
+                    success: false,
+                    message: format!("Failed to snooze: request {} not found on any replica", request_id),
+                })
+        }
+
+        DirectoryMessage::SnoozeReminder { owner, request_id, snooze_secs } => {
+            match state.snooze_reminder(&owner, &request_id, snooze_secs.map(Duration::from_secs)).await {
+                Ok(message) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after snoozing a reminder: {}", e);
+                    }
+                    DirectoryMessage::UpdateResponse { success: true, message }
+                }
+                Err(e) => DirectoryMessage::UpdateResponse { success: false, message: e.to_string() },
+            }
+        }
+
+        DirectoryMessage::CancelRequest {
+            request_id,
+            from_user,
+            key,
+        } if ask_peers && !state.has_request(&request_id).await => {
+            let query = DirectoryMessage::CancelRequest { request_id: request_id.clone(), from_user, key };
+            let answers = state.ask_peers(query).await;
+            answers
+                .iter()
+                .find(|answer| matches!(answer, DirectoryMessage::CancelRequestResponse { success: true, .. }))
+                .or_else(|| answers.iter().find(|answer| matches!(answer, DirectoryMessage::CancelRequestResponse { .. })))
+                .cloned()
+                .unwrap_or_else(|| DirectoryMessage::CancelRequestResponse {
+                    success: false,
+                    message: format!("Failed to cancel: request {} not found on any replica", request_id),
+                })
+        }
+
+        DirectoryMessage::CancelRequest {
+            request_id,
+            from_user,
+            key,
+        } => {
+            match state.cancel_request(&request_id, &from_user, key.as_deref()).await {
+                Ok(request) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after cancelling request: {}", e);
+                    }
+
+                    // Let the owner know if they're online (best effort)
+                    if let Some(owner) = state.query_user(&request.to_user).await {
+                        if owner.status == UserStatus::Online
+                            && owner.supports(crate::p2p_protocol::CAP_REQUEST_NOTICES)
+                        {
+                            tokio::spawn(async move {
+                                if let Err(e) = crate::p2p_protocol::notify_request_cancelled(
+                                    &owner.p2p_address,
+                                    &request.request_id,
+                                    &request.from_user,
+                                    &request.image_id,
+                                )
+                                .await
+                                {
+                                    warn!("Could not notify {} of cancellation: {}", owner.username, e);
+                                }
+                            });
+                        }
+                    }
+
+                    DirectoryMessage::CancelRequestResponse {
+                        success: true,
+                        message: format!("Request {} cancelled", request_id),
+                    }
+                }
+                Err(e) => DirectoryMessage::CancelRequestResponse {
+                    success: false,
+                    message: format!("Failed to cancel: {}", e),
+                },
+            }
+        }
+
         DirectoryMessage::StorePendingPermissionUpdate {
             from_owner,
             target_user,
             image_id,
             new_quota,
             embedded_image,
+            wipe,
+            ttl_secs,
         } => {
-            let update_id = state
-                .store_pending_permission_update(&from_owner, &target_user, &image_id, new_quota, embedded_image)
-                .await;
+            let ttl = ttl_secs.map(Duration::from_secs);
+            match state
+                .store_pending_permission_update(&from_owner, &target_user, &image_id, new_quota, embedded_image, wipe, ttl)
+                .await
+            {
+                Ok((update_id, evicted)) => {
+                    state.save_to_disk().await?;
+                    state.notify_evicted_updates(evicted).await;
+
+                    DirectoryMessage::StorePendingPermissionUpdateResponse {
+                        success: true,
+                        message: format!(
+                            "Permission update queued for user '{}'. Will be applied when they come online (expires in {}).",
+                            target_user,
+                            describe_ttl(state.pending_update_ttl(ttl))
+                        ),
+                        update_id,
+                    }
+                }
+                Err(e) => DirectoryMessage::StorePendingPermissionUpdateResponse {
+                    success: false,
+                    message: format!("Failed to store image for pending update: {}", e),
+                    update_id: String::new(),
+                },
+            }
+        }
+
+        DirectoryMessage::FollowPeer { username, peer, follow } => {
+            match state.set_follow(&username, &peer, follow).await {
+                Ok(()) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after follow change: {}", e);
+                    }
+                    DirectoryMessage::FollowPeerResponse {
+                        success: true,
+                        message: if follow {
+                            format!("Now following {}", peer)
+                        } else {
+                            format!("Unfollowed {}", peer)
+                        },
+                    }
+                }
+                Err(e) => DirectoryMessage::FollowPeerResponse {
+                    success: false,
+                    message: format!("Failed to update follow: {}", e),
+                },
+            }
+        }
 
-            state.save_to_disk().await?;
-            state.replicate_state().await;
+        DirectoryMessage::GetCatalogChanges { username, since } => {
+            let server_time = SystemTime::now();
+            let changes = state.get_catalog_changes(&username, since).await;
+            let following = state.get_following(&username).await;
+            DirectoryMessage::GetCatalogChangesResponse { changes, following, server_time }
+        }
 
-            DirectoryMessage::StorePendingPermissionUpdateResponse {
+        DirectoryMessage::StoreAccessDenial { denial } => {
+            state.store_access_denial(denial).await;
+            if let Err(e) = state.save_to_disk().await {
+                error!("Failed to save state after storing access denial: {}", e);
+            }
+            DirectoryMessage::StoreAccessDenialResponse {
                 success: true,
-                message: format!(
-                    "Permission update queued for user '{}'. Will be applied when they come online.",
-                    target_user
-                ),
-                update_id,
+                message: "Denial report queued for the owner".to_string(),
+            }
+        }
+
+        DirectoryMessage::GetExpiredDeliveries { username } => {
+            let expired = state.take_expired_deliveries(&username).await;
+            if !expired.is_empty() {
+                if let Err(e) = state.save_to_disk().await {
+                    error!("Failed to save state after handing out expiry notices: {}", e);
+                }
+            }
+            DirectoryMessage::GetExpiredDeliveriesResponse { expired }
+        }
+
+        DirectoryMessage::GetAccessDenials { username } => {
+            let denials = state.take_access_denials(&username).await;
+            if !denials.is_empty() {
+                if let Err(e) = state.save_to_disk().await {
+                    error!("Failed to save state after clearing access denials: {}", e);
+                }
+            }
+            DirectoryMessage::GetAccessDenialsResponse { denials }
+        }
+
+        DirectoryMessage::ProbeReachability { port } => {
+            // Only ever probe the sender's own address, so this can't be used to scan others
+            let observed_address = SocketAddr::new(addr.ip(), port).to_string();
+            let (reachable, message) =
+                match tokio::time::timeout(Duration::from_secs(2), TcpStream::connect(&observed_address)).await {
+                    Ok(Ok(_)) => (true, "Directory connected back successfully".to_string()),
+                    Ok(Err(e)) => (false, format!("Directory could not connect back: {}", e)),
+                    Err(_) => (false, "Directory timed out connecting back (firewall or NAT?)".to_string()),
+                };
+            DirectoryMessage::ProbeReachabilityResponse { reachable, observed_address, message }
+        }
+
+        DirectoryMessage::ListReplicas {} => {
+            DirectoryMessage::ListReplicasResponse { replicas: state.peer_servers.clone() }
+        }
+
+        DirectoryMessage::GetServerInfo {} => {
+            DirectoryMessage::GetServerInfoResponse { info: state.server_info().await }
+        }
+
+        DirectoryMessage::RenameUser { from, to, replicated } => {
+            match state.rename_user(&from, &to, replicated).await {
+                Ok(mut message) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after renaming {}: {}", from, e);
+                    }
+                    if !replicated && !state.peer_servers.is_empty() {
+                        let applied = state
+                            .forward_admin(DirectoryMessage::RenameUser { from: from.clone(), to: to.clone(), replicated: true })
+                            .await;
+                        message.push_str(&format!(
+                            "; applied on {}/{} peer replica(s)",
+                            applied,
+                            state.peer_servers.len()
+                        ));
+                    }
+                    DirectoryMessage::RenameUserResponse { success: true, message }
+                }
+                Err(e) => DirectoryMessage::RenameUserResponse {
+                    success: false,
+                    message: format!("Rename failed: {}", e),
+                },
+            }
+        }
+
+        DirectoryMessage::ListArchivedUsers {} => {
+            DirectoryMessage::ListArchivedUsersResponse { users: state.list_archived_users().await }
+        }
+
+        DirectoryMessage::PurgeArchivedUsers { usernames, replicated } => {
+            match state.purge_archived_users(&usernames).await {
+                Ok(mut message) => {
+                    if !replicated && !state.peer_servers.is_empty() {
+                        let applied = state
+                            .forward_admin(DirectoryMessage::PurgeArchivedUsers { usernames, replicated: true })
+                            .await;
+                        message.push_str(&format!(
+                            "; applied on {}/{} peer replica(s)",
+                            applied,
+                            state.peer_servers.len()
+                        ));
+                    }
+                    DirectoryMessage::PurgeArchivedUsersResponse { success: true, message }
+                }
+                Err(e) => DirectoryMessage::PurgeArchivedUsersResponse {
+                    success: false,
+                    message: format!("Purge failed: {}", e),
+                },
             }
         }
 
-        DirectoryMessage::GetPendingPermissionUpdates { username } => {
-            let updates = state.get_and_clear_pending_updates(&username).await;
+        DirectoryMessage::GetPendingPermissionUpdates { username, stream_images: true } => {
+            return state.stream_pending_updates(&mut stream, &username).await;
+        }
+
+        DirectoryMessage::GetPendingPermissionUpdates { username, .. } => {
+            // Left queued if the removal can't be committed, so another replica still hands them over
+            let updates = state.get_and_clear_pending_updates(&username).await.unwrap_or_else(|e| {
+                warn!("Pending updates for {} not handed over: {}", username, e);
+                Vec::new()
+            });
             
-            // Persist and replicate the cleared state
+            // Persist the cleared state
             if !updates.is_empty() {
                 if let Err(e) = state.save_to_disk().await {
                     error!("Failed to save state after clearing pending updates: {}", e);
                 }
-                state.replicate_state().await;
             }
             
             DirectoryMessage::GetPendingPermissionUpdatesResponse { updates }
         }
 
+        DirectoryMessage::ProposeTrade {
+            from_user,
+            to_user,
+            offered_image_id,
+            offered_views,
+            requested_image_id,
+            requested_views,
+        } => {
+            match state
+                .propose_trade(&from_user, &to_user, (offered_image_id, offered_views), (requested_image_id, requested_views))
+                .await
+            {
+                Ok(trade_id) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after trade proposal: {}", e);
+                    }
+                    DirectoryMessage::ProposeTradeResponse {
+                        success: true,
+                        message: format!("Trade proposed to {}", to_user),
+                        trade_id,
+                    }
+                }
+                Err(e) => DirectoryMessage::ProposeTradeResponse {
+                    success: false,
+                    message: format!("Failed to propose trade: {}", e),
+                    trade_id: String::new(),
+                },
+            }
+        }
+
+        DirectoryMessage::GetTrades { username } => {
+            let trades = state.get_trades_for_user(&username).await;
+            DirectoryMessage::GetTradesResponse { trades }
+        }
+
+        DirectoryMessage::RespondToTrade { trade_id, username, accept, key } => {
+            match state.respond_to_trade(&trade_id, &username, key.as_deref(), accept).await {
+                Ok((message, trade)) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after trade response: {}", e);
+                    }
+                    DirectoryMessage::RespondToTradeResponse {
+                        success: true,
+                        message,
+                        trade: Some(trade),
+                    }
+                }
+                Err(e) => DirectoryMessage::RespondToTradeResponse {
+                    success: false,
+                    message: format!("Failed to respond: {}", e),
+                    trade: None,
+                },
+            }
+        }
+
+        DirectoryMessage::DepositTradeImage { trade_id, username, encrypted_image, key } => {
+            match state.deposit_trade_image(&trade_id, &username, key.as_deref(), encrypted_image).await {
+                Ok((message, completed, evicted)) => {
+                    state.save_to_disk().await?;
+                    state.notify_evicted_updates(evicted).await;
+                    DirectoryMessage::DepositTradeImageResponse { success: true, message, completed }
+                }
+                Err(e) => DirectoryMessage::DepositTradeImageResponse {
+                    success: false,
+                    message: format!("Failed to deposit image: {}", e),
+                    completed: false,
+                },
+            }
+        }
+
+        DirectoryMessage::CancelTrade { trade_id, username, key } => {
+            match state.cancel_trade(&trade_id, &username, key.as_deref()).await {
+                Ok(_) => {
+                    if let Err(e) = state.save_to_disk().await {
+                        error!("Failed to save state after cancelling trade: {}", e);
+                    }
+                    DirectoryMessage::CancelTradeResponse {
+                        success: true,
+                        message: "Trade cancelled".to_string(),
+                    }
+                }
+                Err(e) => DirectoryMessage::CancelTradeResponse {
+                    success: false,
+                    message: format!("Failed to cancel trade: {}", e),
+                },
+            }
+        }
+
         _ => {
             bail!("Unexpected message type from {}", addr);
         }
     };
     
-    let response_json = serde_json::to_string(&response)?;
+    write_directory_response(&mut stream, &response).await
+}
+
+/// Read one length-prefixed JSON directory message
+pub(crate) async fn read_directory_frame<R: AsyncRead + Unpin>(stream: &mut R) -> Result<DirectoryMessage> {
+    let msg_len = stream.read_u32().await?;
+    let mut msg_buf = vec![0u8; msg_len as usize];
+    stream.read_exact(&mut msg_buf).await?;
+    Ok(serde_json::from_slice(&msg_buf)?)
+}
+
+/// Write a length-prefixed JSON response back to a directory client
+pub(crate) async fn write_directory_response(stream: &mut TcpStream, response: &DirectoryMessage) -> Result<()> {
+    let response_json = serde_json::to_string(response)?;
     let response_bytes = response_json.as_bytes();
     
     stream.write_u32(response_bytes.len() as u32).await?;
@@ -1003,10 +6046,13 @@ pub async fn send_directory_message(
     directory_addr: &str,
     message: DirectoryMessage,
 ) -> Result<DirectoryMessage> {
+    // Send it in our namespace, with the client token from the environment if one is configured
+    let message = client_envelope(message)?;
     let mut stream = TcpStream::connect(directory_addr).await?;
     
     let msg_json = serde_json::to_string(&message)?;
     let msg_bytes = msg_json.as_bytes();
+    record_frame(TraceChannel::Directory, TraceDirection::Sent, directory_addr, msg_bytes);
     
     stream.write_u32(msg_bytes.len() as u32).await?;
     stream.write_all(msg_bytes).await?;
@@ -1015,16 +6061,53 @@ pub async fn send_directory_message(
     let response_len = stream.read_u32().await?;
     let mut response_buf = vec![0u8; response_len as usize];
     stream.read_exact(&mut response_buf).await?;
+    record_frame(TraceChannel::Directory, TraceDirection::Received, directory_addr, &response_buf);
     
     let response: DirectoryMessage = serde_json::from_slice(&response_buf)?;
+    if let DirectoryMessage::AuthError { message } = response {
+        bail!("Authentication failed: {}", message);
+    }
     Ok(response)
 }
 
-async fn send_state_sync(
-    peer_addr: &str,
-    state: HashMap<String, UserEntry>,
-) -> Result<()> {
-    let message = DirectoryMessage::SyncState { users: state };
+/// Fetch and clear `username`'s pending permission updates, their images streamed rather than inlined
+///
+/// Directories from before streaming send the images inline, which is accepted too.
+pub async fn fetch_pending_permission_updates(directory_addr: &str, username: &str) -> Result<Vec<PendingPermissionUpdate>> {
+    let message = client_envelope(DirectoryMessage::GetPendingPermissionUpdates {
+        username: username.to_string(),
+        stream_images: true,
+    })?;
+    let mut stream = TcpStream::connect(directory_addr).await?;
+
+    let msg_json = serde_json::to_string(&message)?;
+    record_frame(TraceChannel::Directory, TraceDirection::Sent, directory_addr, msg_json.as_bytes());
+    stream.write_u32(msg_json.len() as u32).await?;
+    stream.write_all(msg_json.as_bytes()).await?;
+    stream.flush().await?;
+
+    let mut updates = match read_directory_frame(&mut stream).await? {
+        DirectoryMessage::GetPendingPermissionUpdatesResponse { updates } => updates,
+        DirectoryMessage::AuthError { message } => bail!("Authentication failed: {}", message),
+        _ => bail!("Unexpected response from directory service"),
+    };
+    for update in updates.iter_mut() {
+        let Some(hash) = update.blob_sha256.take() else { continue };
+        let size = stream.read_u64().await?;
+        let mut data = Vec::new();
+        (&mut stream).take(size).read_to_end(&mut data).await?;
+        if data.len() as u64 != size {
+            bail!("Image for {} was cut off ({} of {} bytes)", update.update_id, data.len(), size);
+        }
+        if crate::p2p_protocol::sha256_hex(&data) != hash {
+            bail!("Image for {} doesn't match its checksum", update.update_id);
+        }
+        update.embedded_image = Some(data);
+    }
+    Ok(updates)
+}
+
+async fn send_state_sync(peer_addr: &str, message: DirectoryMessage) -> Result<()> {
     let response = send_directory_message(peer_addr, message).await?;
     
     match response {
@@ -1033,13 +6116,43 @@ async fn send_state_sync(
     }
 }
 
+/// Tell a replica holding a proposal whether it was committed, retrying if it can't be reached
+fn resolve_proposal_on(peer: String, message: DirectoryMessage, committed: bool) {
+    tokio::spawn(async move {
+        for attempt in 1..=RESOLVE_ATTEMPTS {
+            match send_state_commit(&peer, message.clone()).await {
+                Ok(true) => return,
+                Ok(false) => {
+                    warn!("A change proposed to {} expired there before it heard the outcome", peer);
+                    return;
+                }
+                Err(e) if attempt == RESOLVE_ATTEMPTS => {
+                    warn!("Could not {} a change on {}: {}", if committed { "commit" } else { "drop" }, peer, e)
+                }
+                Err(_) => sleep(RESOLVE_RETRY_DELAY).await,
+            }
+        }
+    });
+}
+
+/// Send a `CommitState`, returning whether the replica still held the proposal
+async fn send_state_commit(peer_addr: &str, message: DirectoryMessage) -> Result<bool> {
+    match send_directory_message(peer_addr, message).await? {
+        DirectoryMessage::CommitStateResponse { success } => Ok(success),
+        _ => bail!("Unexpected response from peer"),
+    }
+}
+
 /// NEW: Request full state from a peer
-async fn request_state_from_peer(peer_addr: &str) -> Result<HashMap<String, UserEntry>> {
+async fn request_state_from_peer(
+    peer_addr: &str,
+    envelope: impl Fn(DirectoryMessage) -> DirectoryMessage,
+) -> Result<HashMap<String, UserEntry>> {
     // We use QueryPeers with empty user to get all users
     // This is a workaround - in production you'd add a dedicated GetFullState message
-    let message = DirectoryMessage::QueryPeers {
+    let message = envelope(DirectoryMessage::QueryPeers {
         requesting_user: String::new(),
-    };
+    });
     
     let response = send_directory_message(peer_addr, message).await?;
     
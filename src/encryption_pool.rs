@@ -0,0 +1,461 @@
+use anyhow::{anyhow, bail, Result};
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+use tokio::time::timeout;
+
+// =============================================================================
+// BATCH ENCRYPTION ACROSS THE ENCRYPTION SERVERS
+// =============================================================================
+//
+// Only the Raft leader accepts client jobs (followers answer NOT_LEADER), and the
+// leader hands each job to the least loaded server. A batch therefore keeps one job
+// in flight per healthy server, all sent to the leader, so the leader's placement
+// spreads them over the whole cluster instead of running them one after another.
+
+/// How long the health check waits for each server to accept a connection
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How long to wait for a connection to a server before trying the next one
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long one job may take once sent, encryption included
+const JOB_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Wait before a job's next attempt, doubled each time (an election may be running)
+const RETRY_DELAY: Duration = Duration::from_secs(2);
+
+/// One image to encrypt: the bincode `EncryptionMetadata` and the image file's bytes
+#[derive(Debug, Clone)]
+pub struct EncryptionJob {
+    pub meta_bytes: Vec<u8>,
+    pub image: Vec<u8>,
+}
+
+/// How far a batch has got, reported each time a job finishes
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptionProgress {
+    pub total: usize,
+    pub completed: usize,
+    pub failed: usize,
+    /// Attempts beyond each job's first
+    pub retries: usize,
+    /// Servers that accepted connections when the batch started
+    pub servers: usize,
+}
+
+impl EncryptionProgress {
+    /// e.g. `12/50 encrypted (1 failed, 3 retries) across 3 servers`
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{}/{} encrypted", self.completed, self.total);
+        if self.failed > 0 || self.retries > 0 {
+            summary.push_str(&format!(" ({} failed, {} retries)", self.failed, self.retries));
+        }
+        summary.push_str(&format!(" across {} server{}", self.servers, if self.servers == 1 { "" } else { "s" }));
+        summary
+    }
+}
+
+/// Encrypt every job, as many at a time as there are healthy servers, with results in input order
+///
+/// Each job gets up to `attempts` tries at the servers and fails on its own without
+/// holding up the rest. `on_progress` is called once before any job runs and again
+/// as each one finishes.
+pub async fn encrypt_batch(
+    servers: &[String],
+    jobs: Vec<EncryptionJob>,
+    attempts: u32,
+    mut on_progress: impl FnMut(&EncryptionProgress),
+) -> Vec<Result<Vec<u8>>> {
+    let healthy = healthy_servers(servers).await;
+    let mut progress = EncryptionProgress { total: jobs.len(), servers: healthy.len(), ..Default::default() };
+    on_progress(&progress);
+    if healthy.is_empty() {
+        warn!("None of the {} encryption servers is accepting connections", servers.len());
+        progress.failed = jobs.len();
+        on_progress(&progress);
+        return jobs.iter().map(|_| Err(anyhow!("No encryption server is accepting connections"))).collect();
+    }
+    info!("Encrypting {} images with {} of {} servers healthy", jobs.len(), healthy.len(), servers.len());
+
+    let healthy = Arc::new(healthy);
+    let leader = Arc::new(Mutex::new(None));
+    let retries = Arc::new(AtomicUsize::new(0));
+    let limit = Arc::new(Semaphore::new(healthy.len()));
+    let mut tasks = JoinSet::new();
+    for (index, job) in jobs.into_iter().enumerate() {
+        let (healthy, leader, retries, limit) = (healthy.clone(), leader.clone(), retries.clone(), limit.clone());
+        tasks.spawn(async move {
+            let _permit = limit.acquire_owned().await;
+            (index, encrypt_job(&healthy, &leader, &job, attempts, &retries).await)
+        });
+    }
+
+    let mut results: Vec<Option<Result<Vec<u8>>>> = (0..progress.total).map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        let Ok((index, result)) = joined else {
+            continue;
+        };
+        match &result {
+            Ok(_) => progress.completed += 1,
+            Err(_) => progress.failed += 1,
+        }
+        progress.retries = retries.load(Ordering::Relaxed);
+        on_progress(&progress);
+        results[index] = Some(result);
+    }
+    results
+        .into_iter()
+        .map(|result| result.unwrap_or_else(|| Err(anyhow!("Encryption task stopped unexpectedly"))))
+        .collect()
+}
+
+/// Send one job to the leader, finding it again whenever leadership moves
+async fn encrypt_job(
+    servers: &[String],
+    leader: &Mutex<Option<String>>,
+    job: &EncryptionJob,
+    attempts: u32,
+    retries: &AtomicUsize,
+) -> Result<Vec<u8>> {
+    let mut last_error = anyhow!("No encryption servers");
+    for attempt in 1..=attempts {
+        if attempt > 1 {
+            retries.fetch_add(1, Ordering::Relaxed);
+            tokio::time::sleep(RETRY_DELAY * 2u32.pow(attempt - 2)).await;
+        }
+
+        // The last server that took a job first, then everyone else in configured order
+        let known = leader.lock().unwrap().clone();
+        let candidates = known.iter().chain(servers.iter().filter(|server| Some(*server) != known.as_ref()));
+        for server in candidates {
+            match send_encryption_request(server, &job.meta_bytes, &job.image).await {
+                Ok(encrypted) => {
+                    *leader.lock().unwrap() = Some(server.clone());
+                    return Ok(encrypted);
+                }
+                Err(e) => {
+                    let mut leader = leader.lock().unwrap();
+                    if leader.as_ref() == Some(server) {
+                        *leader = None;
+                    }
+                    last_error = e.context(format!("Encryption server {}", server));
+                }
+            }
+        }
+    }
+    Err(last_error.context(format!("No encryption server accepted the image after {} attempts", attempts)))
+}
+
+/// The servers accepting connections, in the order given
+pub async fn healthy_servers(servers: &[String]) -> Vec<String> {
+    let mut checks = JoinSet::new();
+    for (index, server) in servers.iter().enumerate() {
+        let server = server.clone();
+        checks.spawn(async move {
+            let healthy = matches!(timeout(HEALTH_CHECK_TIMEOUT, TcpStream::connect(&server)).await, Ok(Ok(_)));
+            (index, server, healthy)
+        });
+    }
+
+    let mut healthy = Vec::new();
+    while let Some(Ok((index, server, ok))) = checks.join_next().await {
+        if ok {
+            healthy.push((index, server));
+        }
+    }
+    healthy.sort_by_key(|(index, _)| *index);
+    healthy.into_iter().map(|(_, server)| server).collect()
+}
+
+/// Ask one encryption server to embed an image, failing with `NOT_LEADER`/`NO_LEADER` if it won't
+///
+/// Every request is timed and recorded for `encryption_stats`.
+pub async fn send_encryption_request(addr: &str, meta_bytes: &[u8], image: &[u8]) -> Result<Vec<u8>> {
+    // Two u64 length prefixes go ahead of the metadata and the image
+    let mut timing = EncryptionTiming::started(addr, (16 + meta_bytes.len() + image.len()) as u64);
+    let result = timed_encryption_request(addr, meta_bytes, image, &mut timing).await;
+    if let Err(e) = &result {
+        timing.error = Some(e.to_string());
+    }
+    record_encryption_timing(timing);
+    result
+}
+
+/// `send_encryption_request`, filling in `timing` phase by phase as it goes
+async fn timed_encryption_request(addr: &str, meta_bytes: &[u8], image: &[u8], timing: &mut EncryptionTiming) -> Result<Vec<u8>> {
+    let started = Instant::now();
+    let mut stream = timeout(CONNECT_TIMEOUT, TcpStream::connect(addr))
+        .await
+        .map_err(|_| anyhow!("Connection timeout"))??;
+    timing.connect_ms = elapsed_ms(started);
+
+    let exchange = async {
+        let started = Instant::now();
+        stream.write_u64(meta_bytes.len() as u64).await?;
+        stream.write_all(meta_bytes).await?;
+        stream.write_u64(image.len() as u64).await?;
+        stream.write_all(image).await?;
+        stream.flush().await?;
+        timing.upload_ms = elapsed_ms(started);
+
+        let started = Instant::now();
+        let response_size = stream.read_u64().await?;
+        timing.processing_ms = elapsed_ms(started);
+
+        let started = Instant::now();
+        let mut response = vec![0u8; response_size as usize];
+        stream.read_exact(&mut response).await?;
+        timing.download_ms = elapsed_ms(started);
+        timing.response_bytes = 8 + response_size;
+        Ok::<_, anyhow::Error>(response)
+    };
+    let response = timeout(JOB_TIMEOUT, exchange)
+        .await
+        .map_err(|_| anyhow!("No answer within {}s", JOB_TIMEOUT.as_secs()))??;
+
+    if let Ok(msg) = std::str::from_utf8(&response) {
+        if msg.starts_with("NOT_LEADER") || msg.starts_with("NO_LEADER") {
+            bail!("{}", msg);
+        }
+    }
+    Ok(response)
+}
+
+// =============================================================================
+// CAPACITY STATS
+// =============================================================================
+//
+// Each request to an encryption server is timed phase by phase, so a slow batch can be
+// pinned on the network (connect, upload, download) or on the cluster (processing), and
+// the cluster sized from the jobs clients actually send.
+
+/// Default file name for persisted encryption job timings
+pub const ENCRYPTION_STATS_FILE: &str = "encryption_stats.json";
+
+/// Most recent jobs kept in the stats file
+const RETAINED_JOBS: usize = 1000;
+
+/// How one request to an encryption server went
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncryptionTiming {
+    /// Server the job was sent to (as leader, it may have placed the job on another)
+    pub server: String,
+    pub finished_at: SystemTime,
+    pub connect_ms: u64,
+    /// Sending the metadata and the image
+    pub upload_ms: u64,
+    /// From the upload finishing until the server started answering
+    pub processing_ms: u64,
+    /// Reading the carrier back
+    pub download_ms: u64,
+    pub request_bytes: u64,
+    pub response_bytes: u64,
+    /// Why the request failed, if it did
+    pub error: Option<String>,
+}
+
+impl EncryptionTiming {
+    fn started(server: &str, request_bytes: u64) -> Self {
+        Self {
+            server: server.to_string(),
+            finished_at: SystemTime::now(),
+            connect_ms: 0,
+            upload_ms: 0,
+            processing_ms: 0,
+            download_ms: 0,
+            request_bytes,
+            response_bytes: 0,
+            error: None,
+        }
+    }
+
+    pub fn total_ms(&self) -> u64 {
+        self.connect_ms + self.upload_ms + self.processing_ms + self.download_ms
+    }
+}
+
+/// How long one phase took across the recorded jobs
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct PhaseStats {
+    pub mean_ms: u64,
+    pub p50_ms: u64,
+    pub p95_ms: u64,
+    pub max_ms: u64,
+}
+
+impl PhaseStats {
+    fn of(mut durations: Vec<u64>) -> Self {
+        if durations.is_empty() {
+            return Self::default();
+        }
+        durations.sort_unstable();
+        let percentile = |p: usize| durations[(durations.len() - 1) * p / 100];
+        Self {
+            mean_ms: durations.iter().sum::<u64>() / durations.len() as u64,
+            p50_ms: percentile(50),
+            p95_ms: percentile(95),
+            max_ms: durations[durations.len() - 1],
+        }
+    }
+}
+
+/// The recorded jobs sent to one server
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ServerEncryptionStats {
+    pub server: String,
+    pub jobs: usize,
+    pub failed: usize,
+    /// Over the jobs that succeeded
+    pub mean_total_ms: u64,
+    pub mean_processing_ms: u64,
+}
+
+/// Aggregates over the recorded encryption jobs, for sizing the encryption cluster
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct EncryptionStats {
+    pub jobs: usize,
+    pub failed: usize,
+    /// When the oldest recorded job finished
+    pub since: Option<SystemTime>,
+    /// Phase timings of the jobs that succeeded
+    pub connect: PhaseStats,
+    pub upload: PhaseStats,
+    pub processing: PhaseStats,
+    pub download: PhaseStats,
+    pub total: PhaseStats,
+    pub mean_request_bytes: u64,
+    pub mean_response_bytes: u64,
+    /// By server address
+    pub servers: Vec<ServerEncryptionStats>,
+}
+
+impl EncryptionStats {
+    /// e.g. `120 jobs (2 failed): p50 840 ms, p95 2300 ms, 71% of it server processing`
+    pub fn summary(&self) -> String {
+        if self.jobs == 0 {
+            return "No encryption jobs recorded".to_string();
+        }
+        let mut summary = format!("{} jobs", self.jobs);
+        if self.failed > 0 {
+            summary.push_str(&format!(" ({} failed)", self.failed));
+        }
+        if let Some(processing_pct) = (self.processing.mean_ms * 100).checked_div(self.total.mean_ms) {
+            summary.push_str(&format!(
+                ": p50 {} ms, p95 {} ms, {}% of it server processing",
+                self.total.p50_ms, self.total.p95_ms, processing_pct
+            ));
+        }
+        summary
+    }
+}
+
+struct TimingLedger {
+    path: Option<PathBuf>,
+    jobs: VecDeque<EncryptionTiming>,
+}
+
+static TIMINGS: Mutex<TimingLedger> = Mutex::new(TimingLedger { path: None, jobs: VecDeque::new() });
+
+/// Persist job timings to `path`, picking up any already saved there
+pub fn set_encryption_stats_path(path: PathBuf) {
+    let saved: Vec<EncryptionTiming> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+
+    if let Ok(mut ledger) = TIMINGS.lock() {
+        if ledger.path.as_ref() != Some(&path) {
+            ledger.jobs = saved.into();
+            ledger.path = Some(path);
+        }
+    }
+}
+
+fn record_encryption_timing(mut timing: EncryptionTiming) {
+    timing.finished_at = SystemTime::now();
+    debug!(
+        "encryption job server={} ok={} connect_ms={} upload_ms={} processing_ms={} download_ms={} request_bytes={} response_bytes={}",
+        timing.server, timing.error.is_none(), timing.connect_ms, timing.upload_ms, timing.processing_ms,
+        timing.download_ms, timing.request_bytes, timing.response_bytes
+    );
+
+    let Ok(mut ledger) = TIMINGS.lock() else {
+        return;
+    };
+    ledger.jobs.push_back(timing);
+    while ledger.jobs.len() > RETAINED_JOBS {
+        ledger.jobs.pop_front();
+    }
+
+    if let Some(path) = &ledger.path {
+        let result = serde_json::to_string_pretty(&ledger.jobs)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| fs::write(path, json).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            error!("Failed to save encryption stats to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Aggregates over the most recent encryption jobs
+pub fn encryption_stats() -> EncryptionStats {
+    let jobs: Vec<EncryptionTiming> = match TIMINGS.lock() {
+        Ok(ledger) => ledger.jobs.iter().cloned().collect(),
+        Err(_) => return EncryptionStats::default(),
+    };
+    let succeeded: Vec<&EncryptionTiming> = jobs.iter().filter(|job| job.error.is_none()).collect();
+    let phase = |duration: fn(&EncryptionTiming) -> u64| PhaseStats::of(succeeded.iter().map(|job| duration(job)).collect());
+    let mean = |bytes: fn(&EncryptionTiming) -> u64| match succeeded.len() {
+        0 => 0,
+        n => succeeded.iter().map(|job| bytes(job)).sum::<u64>() / n as u64,
+    };
+
+    let mut servers: BTreeMap<&str, (ServerEncryptionStats, Vec<&EncryptionTiming>)> = BTreeMap::new();
+    for job in &jobs {
+        let (stats, ok) = servers.entry(&job.server).or_default();
+        stats.jobs += 1;
+        match job.error {
+            Some(_) => stats.failed += 1,
+            None => ok.push(job),
+        }
+    }
+    let servers = servers
+        .into_iter()
+        .map(|(server, (mut stats, ok))| {
+            stats.server = server.to_string();
+            if !ok.is_empty() {
+                stats.mean_total_ms = ok.iter().map(|job| job.total_ms()).sum::<u64>() / ok.len() as u64;
+                stats.mean_processing_ms = ok.iter().map(|job| job.processing_ms).sum::<u64>() / ok.len() as u64;
+            }
+            stats
+        })
+        .collect();
+
+    EncryptionStats {
+        jobs: jobs.len(),
+        failed: jobs.len() - succeeded.len(),
+        since: jobs.first().map(|job| job.finished_at),
+        connect: phase(|job| job.connect_ms),
+        upload: phase(|job| job.upload_ms),
+        processing: phase(|job| job.processing_ms),
+        download: phase(|job| job.download_ms),
+        total: phase(EncryptionTiming::total_ms),
+        mean_request_bytes: mean(|job| job.request_bytes),
+        mean_response_bytes: mean(|job| job.response_bytes),
+        servers,
+    }
+}
+
+fn elapsed_ms(started: Instant) -> u64 {
+    started.elapsed().as_millis() as u64
+}
@@ -0,0 +1,264 @@
+use log::warn;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinSet;
+
+use crate::lsb;
+use crate::p2p_protocol::shared_image_id;
+
+// =============================================================================
+// IMAGE FOLDER SCANNING
+// =============================================================================
+
+/// File extensions treated as images wherever folders are scanned
+pub const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg"];
+
+/// An image file found by a scan
+#[derive(Debug, Clone)]
+pub struct ImageFile {
+    pub path: PathBuf,
+    pub file_name: String,
+    pub size_bytes: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl ImageFile {
+    pub fn size_kb(&self) -> u64 {
+        self.size_bytes / 1024
+    }
+}
+
+/// Whether `path` has an image extension, in any case
+pub fn is_image_file(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.as_str()))
+}
+
+/// The image files directly inside `dir`, sorted by name (none if it can't be read)
+pub fn scan_images(dir: &Path) -> Vec<ImageFile> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut images: Vec<ImageFile> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && is_image_file(path))
+        .map(|path| {
+            let metadata = fs::metadata(&path).ok();
+            ImageFile {
+                file_name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+                size_bytes: metadata.as_ref().map(|m| m.len()).unwrap_or(0),
+                modified: metadata.and_then(|m| m.modified().ok()),
+                path,
+            }
+        })
+        .collect();
+    images.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    images
+}
+
+/// Whether an image hides a payload, i.e. it's an encrypted carrier
+pub fn is_encrypted_carrier(path: &Path) -> bool {
+    fs::read(path)
+        .ok()
+        .and_then(|data| image::load_from_memory(&data).ok())
+        .is_some_and(|img| matches!(lsb::decode(&img), Ok(Some(_))))
+}
+
+/// An encrypted image shared from a root's `encrypted/` folder
+#[derive(Debug, Clone)]
+pub struct SharedCarrier {
+    /// Label of the root it's shared from
+    pub origin: String,
+    pub image_id: String,
+    pub file: ImageFile,
+}
+
+/// Every image in the `encrypted/` folders of `roots` (label, folder)
+///
+/// When two roots share the same image ID, the first root's copy is kept.
+pub fn scan_shared_carriers(roots: &[(String, PathBuf)]) -> Vec<SharedCarrier> {
+    let mut seen = HashSet::new();
+    let mut carriers = Vec::new();
+    for (origin, root) in roots {
+        for file in scan_images(&root.join("encrypted")) {
+            let image_id = shared_image_id(&file.path);
+            if !seen.insert(image_id.clone()) {
+                warn!("Skipping '{}' in {}: already shared from another root", image_id, origin);
+                continue;
+            }
+            carriers.push(SharedCarrier { origin: origin.clone(), image_id, file });
+        }
+    }
+    carriers
+}
+
+// =============================================================================
+// PARALLEL SCANNING
+// =============================================================================
+//
+// Decoding every carrier in a large folder one after another takes minutes. Files are
+// listed up front, which is quick, then read on the blocking pool a few at a time, and
+// the results are handed back in batches as they complete so callers can act on them
+// before the whole folder is done.
+
+/// Most results handed back in one batch by `scan_parallel`
+pub const SCAN_BATCH_SIZE: usize = 64;
+
+/// How many files `scan_parallel` should read at once on this machine
+pub fn scan_workers() -> usize {
+    std::thread::available_parallelism().map_or(4, |n| n.get())
+}
+
+/// Run `read` over `items`, `workers` at a time, receiving the results in batches as they complete
+///
+/// Results arrive in completion order, not input order. Setting `cancel` stops further
+/// items from being started; those already running are still handed back. The scan also
+/// stops once the receiver is dropped.
+pub fn scan_parallel<T, R, F>(items: Vec<T>, workers: usize, cancel: Arc<AtomicBool>, read: F) -> mpsc::Receiver<Vec<R>>
+where
+    T: Send + 'static,
+    R: Send + 'static,
+    F: Fn(T) -> R + Send + Sync + 'static,
+{
+    let (tx, rx) = mpsc::channel(4);
+    let read = Arc::new(read);
+    tokio::spawn(async move {
+        let mut pending = items.into_iter();
+        let mut running = JoinSet::new();
+        let mut batch = Vec::with_capacity(SCAN_BATCH_SIZE);
+        loop {
+            while running.len() < workers.max(1) && !cancel.load(Ordering::Relaxed) {
+                let Some(item) = pending.next() else {
+                    break;
+                };
+                let read = read.clone();
+                running.spawn_blocking(move || read(item));
+            }
+            let Some(result) = running.join_next().await else {
+                break;
+            };
+            match result {
+                Ok(result) => batch.push(result),
+                Err(e) => warn!("A scan task failed: {}", e),
+            }
+            if batch.len() >= SCAN_BATCH_SIZE && tx.send(std::mem::take(&mut batch)).await.is_err() {
+                return;
+            }
+        }
+        if !batch.is_empty() {
+            let _ = tx.send(batch).await;
+        }
+    });
+    rx
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, RgbImage};
+
+    /// A fresh, empty directory for one test
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fsscan-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn save_png(img: &DynamicImage, path: &Path) {
+        img.save_with_format(path, image::ImageFormat::Png).unwrap();
+    }
+
+    #[test]
+    fn image_extensions_match_in_any_case() {
+        assert!(is_image_file(Path::new("a.png")));
+        assert!(is_image_file(Path::new("a.JPG")));
+        assert!(is_image_file(Path::new("dir/a.JpEg")));
+        assert!(!is_image_file(Path::new("a.gif")));
+        assert!(!is_image_file(Path::new("png")));
+        assert!(!is_image_file(Path::new("a.png.txt")));
+    }
+
+    #[test]
+    fn scan_lists_images_sorted_by_name() {
+        let dir = scratch_dir("sorted");
+        for name in ["b.png", "C.JPG", "a.jpeg", "notes.txt"] {
+            fs::write(dir.join(name), b"data").unwrap();
+        }
+        fs::create_dir(dir.join("nested.png")).unwrap();
+
+        let names: Vec<String> = scan_images(&dir).into_iter().map(|file| file.file_name).collect();
+        assert_eq!(names, ["C.JPG", "a.jpeg", "b.png"]);
+        assert!(scan_images(&dir.join("missing")).is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_images_with_a_payload_are_carriers() {
+        let dir = scratch_dir("carriers");
+        let plain = DynamicImage::ImageRgb8(RgbImage::new(32, 32));
+        save_png(&plain, &dir.join("plain.png"));
+        save_png(&lsb::encode(&plain, b"hidden").unwrap(), &dir.join("carrier.png"));
+        fs::write(dir.join("broken.png"), b"not an image").unwrap();
+
+        assert!(is_encrypted_carrier(&dir.join("carrier.png")));
+        assert!(!is_encrypted_carrier(&dir.join("plain.png")));
+        assert!(!is_encrypted_carrier(&dir.join("broken.png")));
+        assert!(!is_encrypted_carrier(&dir.join("missing.png")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overlapping_roots_share_each_image_once() {
+        let dir = scratch_dir("roots");
+        for (root, names) in [("first", ["a.png", "b.png"]), ("second", ["b.png", "c.png"])] {
+            fs::create_dir_all(dir.join(root).join("encrypted")).unwrap();
+            for name in names {
+                fs::write(dir.join(root).join("encrypted").join(name), root).unwrap();
+            }
+        }
+        let roots = vec![("first".to_string(), dir.join("first")), ("second".to_string(), dir.join("second"))];
+
+        let shared: Vec<(String, String)> = scan_shared_carriers(&roots)
+            .into_iter()
+            .map(|carrier| (carrier.origin, carrier.image_id))
+            .collect();
+        assert_eq!(
+            shared,
+            [
+                ("first".to_string(), "a.png".to_string()),
+                ("first".to_string(), "b.png".to_string()),
+                ("second".to_string(), "c.png".to_string()),
+            ]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn parallel_scan_hands_back_every_result_in_batches() {
+        let items: Vec<usize> = (0..SCAN_BATCH_SIZE * 2 + 5).collect();
+        let mut rx = scan_parallel(items.clone(), 3, Arc::new(AtomicBool::new(false)), |n| n * 2);
+
+        let mut results = Vec::new();
+        while let Some(batch) = rx.recv().await {
+            assert!(!batch.is_empty() && batch.len() <= SCAN_BATCH_SIZE);
+            results.extend(batch);
+        }
+        results.sort_unstable();
+        assert_eq!(results, items.iter().map(|n| n * 2).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn cancelled_scan_starts_nothing_more() {
+        let cancel = Arc::new(AtomicBool::new(true));
+        let mut rx = scan_parallel((0..100).collect::<Vec<u32>>(), 4, cancel, |n| n);
+        assert!(rx.recv().await.is_none());
+    }
+}
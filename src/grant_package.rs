@@ -0,0 +1,220 @@
+use anyhow::{bail, Context, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::RwLock;
+
+use crate::directory_service::constant_time_eq;
+use crate::lsb;
+use crate::p2p_protocol::{embedded_payload, process_p2p_message, sha256_hex, write_atomic, P2PMessage, PeerImageStore};
+use crate::CombinedPayload;
+
+// =============================================================================
+// GRANT PACKAGES (REMOVABLE MEDIA)
+// =============================================================================
+//
+// Peers with no network path between them can still share: the owner grants views as
+// usual, but writes the granted carrier into a package file instead of delivering it,
+// and the recipient imports the file from a USB stick or similar. The package is signed
+// with a passphrase the two agree on in person, so a tampered or swapped file is refused.
+
+/// Extension of grant package files
+pub const GRANT_PACKAGE_EXTENSION: &str = "p2pgrant";
+
+/// Marks a grant package file, ahead of its bincode body
+const PACKAGE_MAGIC: &[u8] = b"P2PGRANT";
+
+/// Bumped when the package layout changes
+const PACKAGE_VERSION: u8 = 1;
+
+/// Shortest passphrase a package may be signed with
+pub const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Everything a package vouches for, as signed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GrantPackageInfo {
+    pub from_owner: String,
+    pub recipient: String,
+    pub image_id: String,
+    pub views: u32,
+    /// Sequence number of the grant embedded in the carrier
+    pub grant_seq: u64,
+    pub created_at: SystemTime,
+    /// SHA-256 of the carrier
+    pub sha256: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct GrantPackage {
+    info: GrantPackageInfo,
+    carrier: Vec<u8>,
+    /// HMAC-SHA256 of `info` and `carrier` under the passphrase
+    signature: [u8; 32],
+}
+
+fn check_passphrase(passphrase: &str) -> Result<()> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        bail!("Passphrase must be at least {} characters", MIN_PASSPHRASE_LEN);
+    }
+    Ok(())
+}
+
+/// HMAC-SHA256 (RFC 2104)
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    const BLOCK_LEN: usize = 64;
+    let mut key_block = [0u8; BLOCK_LEN];
+    if key.len() > BLOCK_LEN {
+        key_block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        key_block[..key.len()].copy_from_slice(key);
+    }
+    let inner = Sha256::new()
+        .chain_update(key_block.map(|byte| byte ^ 0x36))
+        .chain_update(message)
+        .finalize();
+    Sha256::new()
+        .chain_update(key_block.map(|byte| byte ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+fn sign(passphrase: &str, info: &GrantPackageInfo, carrier: &[u8]) -> Result<[u8; 32]> {
+    let mut signed = bincode::serialize(info)?;
+    signed.extend_from_slice(carrier);
+    Ok(hmac_sha256(passphrase.as_bytes(), &signed))
+}
+
+/// Grant `recipient` `views` of the owner's carrier at `carrier_path` and package the granted copy
+///
+/// The grant is recorded in the owner's copy just like one made over P2P, so it can be
+/// revoked or updated later. Returns what the package grants and the file to write.
+pub fn export_grant_package(
+    carrier_path: &Path,
+    owner: &str,
+    recipient: &str,
+    views: u32,
+    passphrase: &str,
+) -> Result<(GrantPackageInfo, Vec<u8>)> {
+    check_passphrase(passphrase)?;
+    if views == 0 {
+        bail!("A package must grant at least one view");
+    }
+    let (image_id, grant_seq, granted) = grant_in_place(carrier_path, owner, recipient, views)?;
+
+    let info = GrantPackageInfo {
+        from_owner: owner.to_string(),
+        recipient: recipient.to_string(),
+        image_id,
+        views,
+        grant_seq,
+        created_at: SystemTime::now(),
+        sha256: sha256_hex(&granted),
+    };
+    let signature = sign(passphrase, &info, &granted)?;
+    let package = GrantPackage { info: info.clone(), carrier: granted, signature };
+
+    let mut bytes = PACKAGE_MAGIC.to_vec();
+    bytes.push(PACKAGE_VERSION);
+    bincode::serialize_into(&mut bytes, &package)?;
+    info!("Exported grant #{} of {} for {} ({} views)", grant_seq, info.image_id, recipient, views);
+    Ok((info, bytes))
+}
+
+/// Grant `recipient` `views` in the owner's carrier at `carrier_path`, returning the image ID,
+/// the grant's sequence number and the granted carrier
+///
+/// For grants handed over outside a P2P request; the caller should hold the carrier's lock.
+pub(crate) fn grant_in_place(carrier_path: &Path, owner: &str, recipient: &str, views: u32) -> Result<(String, u64, Vec<u8>)> {
+    if recipient == owner {
+        bail!("You already own this image");
+    }
+    let data = fs::read(carrier_path).with_context(|| format!("Failed to read {}", carrier_path.display()))?;
+    let carrier_img = image::load_from_memory(&data).context("Not a readable image")?;
+    let mut payload = embedded_payload(&data).context("No readable permissions in the image")?;
+    if payload.permissions.owner != owner {
+        bail!("Only the owner ({}) can grant views of this image", payload.permissions.owner);
+    }
+    if payload.permissions.is_holder(recipient) {
+        bail!("{} holds this image for safekeeping and can't be granted views", recipient);
+    }
+    let image_id = payload.image_id.clone().context("The image has no ID - migrate it first")?;
+
+    let grant_seq = payload.permissions.set_quota(recipient, views);
+    let granted = encode_carrier(&carrier_img, &payload)?;
+    // The owner's copy is the record of every grant, so it must say this one too
+    write_atomic(carrier_path, &granted)?;
+    Ok((image_id, grant_seq, granted))
+}
+
+fn encode_carrier(carrier_img: &image::DynamicImage, payload: &CombinedPayload) -> Result<Vec<u8>> {
+    let updated = lsb::encode(carrier_img, &payload.to_bytes()?)?;
+    let mut png = Vec::new();
+    updated.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
+
+/// Check a package's signature and that it's meant for `username`, returning what it grants
+fn open_package(bytes: &[u8], passphrase: &str, username: &str) -> Result<GrantPackage> {
+    let body = bytes.strip_prefix(PACKAGE_MAGIC).context("Not a grant package")?;
+    match body.first() {
+        Some(&PACKAGE_VERSION) => {}
+        Some(version) => bail!("Grant package version {} isn't supported by this version", version),
+        None => bail!("Grant package is empty"),
+    }
+    let package: GrantPackage = bincode::deserialize(&body[1..]).context("Grant package is damaged")?;
+
+    let expected = sign(passphrase, &package.info, &package.carrier)?;
+    if !constant_time_eq(&expected, &package.signature) {
+        bail!("Signature doesn't match - wrong passphrase, or the package was altered");
+    }
+    if package.info.recipient != username {
+        bail!("This package is for {}, not {}", package.info.recipient, username);
+    }
+    if sha256_hex(&package.carrier) != package.info.sha256 {
+        bail!("Image checksum doesn't match the package");
+    }
+
+    let payload = embedded_payload(&package.carrier).context("No readable permissions in the packaged image")?;
+    if payload.permissions.owner != package.info.from_owner {
+        bail!("Image is owned by {}, not {}", payload.permissions.owner, package.info.from_owner);
+    }
+    if payload.permissions.quotas.get(username) != Some(&package.info.views) {
+        bail!("The image doesn't grant the {} views the package says", package.info.views);
+    }
+    Ok(package)
+}
+
+/// Verify a package and store its image in `received/` as a P2P delivery would
+///
+/// Goes through the same checks as `DeliverImage` - replayed grants are refused, an
+/// image already held has the views merged in - and returns the delivery's message.
+pub async fn import_grant_package(
+    bytes: &[u8],
+    passphrase: &str,
+    username: &str,
+    image_store: &Arc<RwLock<PeerImageStore>>,
+) -> Result<(GrantPackageInfo, String)> {
+    let GrantPackage { info, carrier, .. } = open_package(bytes, passphrase, username)?;
+
+    let delivery = P2PMessage::DeliverImage {
+        from_owner: info.from_owner.clone(),
+        image_id: info.image_id.clone(),
+        requested_views: info.views,
+        encrypted_image: carrier,
+        sha256: Some(info.sha256.clone()),
+        grant_seq: Some(info.grant_seq),
+    };
+    match process_p2p_message(delivery, username.to_string(), image_store.clone()).await? {
+        P2PMessage::DeliverImageResponse { success: true, message, .. } => {
+            info!("Imported grant #{} of {} from {}", info.grant_seq, info.image_id, info.from_owner);
+            Ok((info, message))
+        }
+        P2PMessage::DeliverImageResponse { message, .. } => bail!("Import refused: {}", message),
+        _ => bail!("Unexpected response to the delivery"),
+    }
+}
@@ -0,0 +1,58 @@
+use std::time::{Duration, SystemTime};
+
+// =============================================================================
+// RELATIVE TIMES
+// =============================================================================
+
+/// How long ago `time` was, e.g. `just now`, `5 mins ago`, `1 hour ago`, `3 days ago`
+///
+/// Times in the future (another machine's clock running ahead) count as just now.
+pub fn time_ago(time: SystemTime) -> String {
+    ago(SystemTime::now().duration_since(time).unwrap_or_default())
+}
+
+/// `time_ago` for a duration that has already elapsed
+pub fn ago(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let (count, unit) = match secs {
+        0..=59 => return "just now".to_string(),
+        60..=3_599 => (secs / 60, "min"),
+        3_600..=86_399 => (secs / 3_600, "hour"),
+        _ => (secs / 86_400, "day"),
+    };
+    format!("{} {}{} ago", count, unit, if count == 1 { "" } else { "s" })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn under_a_minute_is_just_now() {
+        assert_eq!(ago(Duration::ZERO), "just now");
+        assert_eq!(ago(Duration::from_secs(59)), "just now");
+    }
+
+    #[test]
+    fn each_unit_starts_singular_then_goes_plural() {
+        assert_eq!(ago(Duration::from_secs(60)), "1 min ago");
+        assert_eq!(ago(Duration::from_secs(119)), "1 min ago");
+        assert_eq!(ago(Duration::from_secs(120)), "2 mins ago");
+        assert_eq!(ago(Duration::from_secs(3_599)), "59 mins ago");
+        assert_eq!(ago(Duration::from_secs(3_600)), "1 hour ago");
+        assert_eq!(ago(Duration::from_secs(7_200)), "2 hours ago");
+        assert_eq!(ago(Duration::from_secs(86_399)), "23 hours ago");
+        assert_eq!(ago(Duration::from_secs(86_400)), "1 day ago");
+        assert_eq!(ago(Duration::from_secs(3 * 86_400)), "3 days ago");
+    }
+
+    #[test]
+    fn future_times_are_just_now() {
+        assert_eq!(time_ago(SystemTime::now() + Duration::from_secs(3_600)), "just now");
+    }
+
+    #[test]
+    fn past_times_count_from_now() {
+        assert_eq!(time_ago(SystemTime::now() - Duration::from_secs(5 * 60 + 10)), "5 mins ago");
+    }
+}
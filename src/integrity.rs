@@ -0,0 +1,212 @@
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fsscan::scan_images;
+use crate::lsb;
+use crate::p2p_protocol::received_record_path;
+use crate::CombinedPayload;
+
+// =============================================================================
+// STARTUP INTEGRITY SCAN
+// =============================================================================
+//
+// A crash or full disk can leave a carrier truncated, and an interrupted atomic write
+// leaves its `.<name>.partial` file behind. Scans used to skip such files without a
+// word, so a broken image simply vanished from the shared list. Checking every carrier
+// once at startup moves the broken ones aside where the user can see them.
+
+/// Folder next to `encrypted/` and `received/` that damaged carriers are moved into
+pub const DAMAGED_DIR: &str = "damaged";
+
+/// Report written into `damaged/` after a scan that found anything
+pub const INTEGRITY_REPORT_FILE: &str = "integrity_report.json";
+
+/// Suffix of a backup copy a damaged carrier can be restored from
+pub const BACKUP_SUFFIX: &str = ".bak";
+
+/// A carrier the scan had to act on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DamagedCarrier {
+    pub path: PathBuf,
+    /// Why the carrier failed the check
+    pub problem: String,
+    /// Where the damaged file was moved (unset if moving it failed too)
+    pub moved_to: Option<PathBuf>,
+}
+
+/// What a startup scan found
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub scanned: usize,
+    pub healthy: usize,
+    /// Damaged carriers replaced by a good `.bak` copy
+    pub recovered: Vec<DamagedCarrier>,
+    /// Damaged carriers moved into `damaged/` with nothing to restore them from
+    pub quarantined: Vec<DamagedCarrier>,
+    /// Leftover `.partial` files from interrupted writes
+    pub partials_removed: usize,
+    pub scanned_at: Option<SystemTime>,
+}
+
+impl IntegrityReport {
+    /// Whether every carrier was fine and nothing needed cleaning up
+    pub fn is_clean(&self) -> bool {
+        self.recovered.is_empty() && self.quarantined.is_empty() && self.partials_removed == 0
+    }
+
+    /// e.g. `40 carriers checked: 38 healthy, 1 restored from backup, 1 quarantined`
+    pub fn summary(&self) -> String {
+        let mut summary = format!("{} carriers checked: {} healthy", self.scanned, self.healthy);
+        if !self.recovered.is_empty() {
+            summary.push_str(&format!(", {} restored from backup", self.recovered.len()));
+        }
+        if !self.quarantined.is_empty() {
+            summary.push_str(&format!(", {} quarantined", self.quarantined.len()));
+        }
+        if self.partials_removed > 0 {
+            summary.push_str(&format!(", {} partial writes removed", self.partials_removed));
+        }
+        summary
+    }
+}
+
+/// Check that `data` is a readable image hiding a payload this build can decode
+pub fn check_carrier(data: &[u8]) -> Result<()> {
+    let img = image::load_from_memory(data).context("Not a readable image")?;
+    let payload = lsb::decode(&img)?.ok_or_else(|| anyhow!("No hidden payload"))?;
+    CombinedPayload::from_bytes(&payload).context("Hidden payload doesn't decode")?;
+    Ok(())
+}
+
+/// Check every carrier in `folders`, moving damaged ones into the `damaged/` folder beside each
+///
+/// A damaged carrier with a `.bak` copy that passes the check is restored from it;
+/// otherwise it's quarantined. Missing folders are skipped.
+pub fn scan_folders(folders: &[PathBuf]) -> IntegrityReport {
+    let mut report = IntegrityReport { scanned_at: Some(SystemTime::now()), ..Default::default() };
+    let mut damaged_dirs = Vec::new();
+
+    for folder in folders.iter().filter(|folder| folder.is_dir()) {
+        let damaged_dir = folder.parent().unwrap_or(folder).join(DAMAGED_DIR);
+        let found = report.recovered.len() + report.quarantined.len();
+        scan_folder(folder, &damaged_dir, &mut report);
+        if report.recovered.len() + report.quarantined.len() > found && !damaged_dirs.contains(&damaged_dir) {
+            damaged_dirs.push(damaged_dir);
+        }
+    }
+
+    for dir in &damaged_dirs {
+        let written = serde_json::to_string_pretty(&report)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(dir.join(INTEGRITY_REPORT_FILE), json)?));
+        if let Err(e) = written {
+            warn!("Failed to write integrity report in {}: {}", dir.display(), e);
+        }
+    }
+    if report.is_clean() {
+        info!("Integrity scan: {}", report.summary());
+    } else {
+        warn!("Integrity scan: {}", report.summary());
+    }
+    report
+}
+
+fn scan_folder(folder: &Path, damaged_dir: &Path, report: &mut IntegrityReport) {
+    report.partials_removed += remove_partials(folder);
+
+    for file in scan_images(folder) {
+        report.scanned += 1;
+        let problem = match fs::read(&file.path).map_err(anyhow::Error::from).and_then(|data| check_carrier(&data)) {
+            Ok(()) => {
+                report.healthy += 1;
+                continue;
+            }
+            Err(e) => format!("{:#}", e),
+        };
+        warn!("{} is damaged: {}", file.path.display(), problem);
+
+        let moved_to = match quarantine(&file.path, damaged_dir) {
+            Ok(moved_to) => Some(moved_to),
+            Err(e) => {
+                warn!("Failed to quarantine {}: {:#}", file.path.display(), e);
+                None
+            }
+        };
+        let damaged = DamagedCarrier { path: file.path.clone(), problem, moved_to };
+
+        // A backup can only go back in place once the damaged file is out of the way
+        let restored = damaged.moved_to.is_some()
+            && match restore_backup(&file.path) {
+                Ok(restored) => restored,
+                Err(e) => {
+                    warn!("Failed to restore {} from its backup: {:#}", file.path.display(), e);
+                    false
+                }
+            };
+        if restored {
+            info!("Restored {} from its backup", file.path.display());
+            report.recovered.push(damaged);
+            continue;
+        }
+
+        // A received image's record goes with it, so the received list doesn't point at nothing
+        let record = received_record_path(&file.path);
+        if let (Some(moved_to), true) = (&damaged.moved_to, record.is_file()) {
+            let _ = fs::rename(&record, received_record_path(moved_to));
+        }
+        report.quarantined.push(damaged);
+    }
+}
+
+/// Copy `<path>.bak` over `path` if there is one and it passes the check, leaving the backup in place
+fn restore_backup(path: &Path) -> Result<bool> {
+    let mut backup = path.as_os_str().to_os_string();
+    backup.push(BACKUP_SUFFIX);
+    let backup = PathBuf::from(backup);
+    if !backup.is_file() {
+        return Ok(false);
+    }
+    let data = fs::read(&backup)?;
+    check_carrier(&data).context("Backup is damaged too")?;
+
+    // Copy under a temporary name first so a crash never leaves a half-restored carrier
+    let file_name = path.file_name().context("Path has no file name")?.to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.partial", file_name));
+    fs::write(&tmp, &data).and_then(|()| fs::rename(&tmp, path))?;
+    Ok(true)
+}
+
+/// Move a damaged file into `damaged_dir`, never overwriting
+fn quarantine(path: &Path, damaged_dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(damaged_dir)?;
+    let file_name = path.file_name().context("Path has no file name")?.to_string_lossy().into_owned();
+    let mut target = damaged_dir.join(&file_name);
+    if target.exists() {
+        let stamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        target = damaged_dir.join(format!("{}-{}", stamp, file_name));
+    }
+    fs::rename(path, &target)?;
+    Ok(target)
+}
+
+/// Delete `.<name>.partial` files left by writes that never finished
+fn remove_partials(folder: &Path) -> usize {
+    let Ok(entries) = fs::read_dir(folder) else {
+        return 0;
+    };
+    let mut removed = 0;
+    for path in entries.flatten().map(|entry| entry.path()) {
+        let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if path.is_file() && name.starts_with('.') && name.ends_with(".partial") {
+            match fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("Failed to remove {}: {}", path.display(), e),
+            }
+        }
+    }
+    removed
+}
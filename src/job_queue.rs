@@ -0,0 +1,372 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::directory_service::ImageInfo;
+use crate::p2p_protocol::AccessDenial;
+use crate::ImageAnnotations;
+
+// =============================================================================
+// BACKGROUND JOB QUEUE
+// =============================================================================
+
+/// Default file name for the persisted job queue
+pub const JOB_QUEUE_FILE: &str = "background_jobs.json";
+
+/// Attempts before a job is marked failed
+pub const DEFAULT_MAX_ATTEMPTS: u32 = 8;
+
+/// Delay before the first retry (doubled after each failed attempt)
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest delay between retries
+const RETRY_MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+
+/// Finished jobs kept for display
+const RETAINED_FINISHED_JOBS: usize = 100;
+
+/// A side effect to carry out in the background
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum JobKind {
+    /// Embed a grant in our copy of an image and deliver it (or queue it at the directory)
+    DeliverGrant {
+        target_user: String,
+        image_id: String,
+        views: u32,
+        /// Send a copy downscaled to at most this many pixels on its longer side
+        #[serde(default)]
+        max_dimension: Option<u32>,
+        /// Owner we approved the request for as their delegate (None for our own images)
+        #[serde(default)]
+        owner: Option<String>,
+        /// Accepted request this delivers, marked delivered at the directory once it's done
+        #[serde(default)]
+        request_id: Option<String>,
+        /// The accepted request's grant token, which the owner's peer needs to raise the quota
+        #[serde(default)]
+        grant_token: Option<String>,
+    },
+    /// Deliver our current copy of an image after changing a user's quota on it
+    DeliverPermissionUpdate {
+        target_user: String,
+        image_id: String,
+        new_quota: u32,
+    },
+    /// Publish our shared image list to the directory
+    UpdateSharedImages {
+        shared_images: Vec<ImageInfo>,
+    },
+    /// Tell an owner a view of their image was denied
+    ReportAccessDenial {
+        denial: AccessDenial,
+    },
+    /// Encrypt a photo imported from a library into our share root
+    EncryptImage {
+        image_path: PathBuf,
+        annotations: ImageAnnotations,
+        /// File name of the encrypted copy
+        output_name: String,
+    },
+}
+
+impl JobKind {
+    pub fn describe(&self) -> String {
+        match self {
+            JobKind::DeliverGrant { target_user, image_id, views, owner: Some(owner), .. } => {
+                format!("Deliver {}'s {} ({} views) to {}", owner, image_id, views, target_user)
+            }
+            JobKind::DeliverGrant { target_user, image_id, views, .. } => {
+                format!("Deliver {} ({} views) to {}", image_id, views, target_user)
+            }
+            JobKind::DeliverPermissionUpdate { target_user, image_id, new_quota } => {
+                format!("Deliver {} with updated quota ({} views) to {}", image_id, new_quota, target_user)
+            }
+            JobKind::UpdateSharedImages { shared_images } => {
+                format!("Publish {} shared images to the directory", shared_images.len())
+            }
+            JobKind::ReportAccessDenial { denial } => {
+                format!("Report denied view of {} to {}", denial.image_id, denial.owner)
+            }
+            JobKind::EncryptImage { image_path, .. } => {
+                format!("Encrypt {}", image_path.display())
+            }
+        }
+    }
+
+    /// Whether running this job makes a still-pending `other` pointless
+    fn supersedes(&self, other: &JobKind) -> bool {
+        match (self, other) {
+            (JobKind::UpdateSharedImages { .. }, JobKind::UpdateSharedImages { .. }) => true,
+            (
+                JobKind::DeliverGrant { target_user, image_id, .. }
+                | JobKind::DeliverPermissionUpdate { target_user, image_id, .. },
+                JobKind::DeliverGrant { target_user: other_user, image_id: other_image, .. }
+                | JobKind::DeliverPermissionUpdate { target_user: other_user, image_id: other_image, .. },
+            ) => target_user == other_user && image_id == other_image,
+            (JobKind::EncryptImage { output_name, .. }, JobKind::EncryptImage { output_name: other_name, .. }) => {
+                output_name == other_name
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u64,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    pub created_at: SystemTime,
+    /// When a pending job may next run
+    pub next_attempt_at: SystemTime,
+    pub last_error: Option<String>,
+    /// What a successful run did
+    pub outcome: Option<String>,
+}
+
+/// A job as shown to the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JobSummary {
+    pub id: u64,
+    pub description: String,
+    pub status: JobStatus,
+    pub attempts: u32,
+    pub max_attempts: u32,
+    pub created_at: SystemTime,
+    /// Set while a retry is scheduled
+    pub next_attempt_at: Option<SystemTime>,
+    pub last_error: Option<String>,
+    pub outcome: Option<String>,
+}
+
+/// Jobs persisted to disk, retried with exponential backoff until they succeed or run out of attempts
+#[derive(Debug, Default)]
+pub struct JobQueue {
+    path: Option<PathBuf>,
+    jobs: Vec<Job>,
+    next_id: u64,
+}
+
+impl JobQueue {
+    /// Persist to `path`, picking up jobs saved there (jobs interrupted mid-run are retried)
+    pub fn set_path(&mut self, path: PathBuf) {
+        let mut jobs: Vec<Job> = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+        for job in jobs.iter_mut().filter(|job| job.status == JobStatus::Running) {
+            job.status = JobStatus::Pending;
+        }
+
+        // Jobs queued before the path was known run after the saved ones
+        let mut next_id = jobs.iter().map(|job| job.id + 1).max().unwrap_or(0);
+        for mut job in self.jobs.drain(..) {
+            job.id = next_id;
+            next_id += 1;
+            jobs.push(job);
+        }
+        self.jobs = jobs;
+        self.next_id = next_id;
+        self.path = Some(path);
+        self.save();
+    }
+
+    /// Queue a job to run as soon as possible, replacing pending jobs it makes redundant
+    ///
+    /// A replaced delivery for an accepted request is kept as succeeded rather than dropped,
+    /// so the undelivered-grant sweep settles the request instead of delivering it again
+    /// (which would undo the quota change that replaced it).
+    pub fn enqueue(&mut self, kind: JobKind) -> u64 {
+        for job in self.jobs.iter_mut().filter(|job| job.status == JobStatus::Pending && kind.supersedes(&job.kind)) {
+            if matches!(job.kind, JobKind::DeliverGrant { request_id: Some(_), .. }) {
+                job.status = JobStatus::Succeeded;
+                job.outcome = Some(format!("Replaced by: {}", kind.describe()));
+            }
+        }
+        self.jobs
+            .retain(|job| !(job.status == JobStatus::Pending && kind.supersedes(&job.kind)));
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let now = SystemTime::now();
+        self.jobs.push(Job {
+            id,
+            kind,
+            status: JobStatus::Pending,
+            attempts: 0,
+            max_attempts: DEFAULT_MAX_ATTEMPTS,
+            created_at: now,
+            next_attempt_at: now,
+            last_error: None,
+            outcome: None,
+        });
+        self.prune();
+        self.save();
+        id
+    }
+
+    /// Claim the oldest job that is due, marking it running
+    pub fn next_due(&mut self, now: SystemTime) -> Option<(u64, JobKind)> {
+        let job = self
+            .jobs
+            .iter_mut()
+            .find(|job| job.status == JobStatus::Pending && job.next_attempt_at <= now)?;
+        job.status = JobStatus::Running;
+        job.attempts += 1;
+        let claimed = (job.id, job.kind.clone());
+        self.save();
+        Some(claimed)
+    }
+
+    /// Record the result of a run, scheduling a retry if it failed with attempts left
+    pub fn finish(&mut self, id: u64, result: std::result::Result<String, String>) {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        match result {
+            Ok(outcome) => {
+                job.status = JobStatus::Succeeded;
+                job.outcome = Some(outcome);
+                job.last_error = None;
+            }
+            Err(e) => {
+                error!("Background job {} ({}) failed: {}", job.id, job.kind.describe(), e);
+                job.last_error = Some(e);
+                if job.attempts >= job.max_attempts {
+                    job.status = JobStatus::Failed;
+                } else {
+                    job.status = JobStatus::Pending;
+                    job.next_attempt_at = SystemTime::now() + retry_delay(job.attempts);
+                }
+            }
+        }
+        self.prune();
+        self.save();
+    }
+
+    /// Run a failed job again with a fresh set of attempts
+    pub fn retry(&mut self, id: u64) -> bool {
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id && job.status == JobStatus::Failed) else {
+            return false;
+        };
+        job.status = JobStatus::Pending;
+        job.attempts = 0;
+        job.next_attempt_at = SystemTime::now();
+        self.save();
+        true
+    }
+
+    /// Status of the latest delivery job for an accepted request, if there is one
+    pub fn delivery_status(&self, request_id: &str) -> Option<JobStatus> {
+        self.jobs
+            .iter()
+            .rev()
+            .find(|job| matches!(&job.kind, JobKind::DeliverGrant { request_id: Some(id), .. } if id == request_id))
+            .map(|job| job.status)
+    }
+
+    /// Every job, newest first
+    pub fn summaries(&self) -> Vec<JobSummary> {
+        self.jobs
+            .iter()
+            .rev()
+            .map(|job| JobSummary {
+                id: job.id,
+                description: job.kind.describe(),
+                status: job.status,
+                attempts: job.attempts,
+                max_attempts: job.max_attempts,
+                created_at: job.created_at,
+                next_attempt_at: (job.status == JobStatus::Pending).then_some(job.next_attempt_at),
+                last_error: job.last_error.clone(),
+                outcome: job.outcome.clone(),
+            })
+            .collect()
+    }
+
+    /// Drop the oldest finished jobs beyond the retention limit
+    fn prune(&mut self) {
+        let finished = |job: &Job| matches!(job.status, JobStatus::Succeeded | JobStatus::Failed);
+        let mut excess = self.jobs.iter().filter(|job| finished(job)).count().saturating_sub(RETAINED_FINISHED_JOBS);
+        self.jobs.retain(|job| {
+            if excess > 0 && finished(job) {
+                excess -= 1;
+                return false;
+            }
+            true
+        });
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let result = serde_json::to_string_pretty(&self.jobs)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| fs::write(path, json).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            error!("Failed to save job queue to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Backoff before retry number `attempts` (1-based)
+pub fn retry_delay(attempts: u32) -> Duration {
+    RETRY_BASE_DELAY
+        .saturating_mul(1 << attempts.saturating_sub(1).min(16))
+        .min(RETRY_MAX_DELAY)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grant(request_id: Option<&str>) -> JobKind {
+        JobKind::DeliverGrant {
+            target_user: "bob".to_string(),
+            image_id: "sunset".to_string(),
+            views: 5,
+            max_dimension: None,
+            owner: None,
+            request_id: request_id.map(str::to_string),
+            grant_token: Some("token".to_string()),
+        }
+    }
+
+    fn revoke() -> JobKind {
+        JobKind::DeliverPermissionUpdate { target_user: "bob".to_string(), image_id: "sunset".to_string(), new_quota: 0 }
+    }
+
+    #[test]
+    fn revoke_settles_a_queued_request_grant() {
+        let mut queue = JobQueue::default();
+        queue.enqueue(grant(Some("req-1")));
+        queue.enqueue(revoke());
+
+        // The sweep marks a succeeded request delivered; with no job it would queue the grant again
+        assert_eq!(queue.delivery_status("req-1"), Some(JobStatus::Succeeded));
+        let (_, due) = queue.next_due(SystemTime::now()).unwrap();
+        assert!(matches!(due, JobKind::DeliverPermissionUpdate { new_quota: 0, .. }));
+        assert!(queue.next_due(SystemTime::now()).is_none());
+    }
+
+    #[test]
+    fn superseded_grants_without_a_request_are_dropped() {
+        let mut queue = JobQueue::default();
+        queue.enqueue(grant(None));
+        queue.enqueue(revoke());
+        assert_eq!(queue.summaries().len(), 1);
+    }
+}
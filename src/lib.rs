@@ -1,14 +1,34 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::HashMap;
 use std::time::SystemTime;
 use std::net::UdpSocket;
-use anyhow::Result;
+use anyhow::{bail, Result};
 
 // This line makes our custom modules available
 pub mod lsb;
 pub mod raft;
 pub mod directory_service;
 pub mod p2p_protocol;
+pub mod diagnostics;
+pub mod protocol_trace;
+pub mod audit_log;
+pub mod bandwidth;
+pub mod job_queue;
+pub mod reputation;
+pub mod search;
+pub mod bootstrap;
+pub mod watermark;
+pub mod photo_import;
+pub mod fsscan;
+pub mod humantime;
+pub mod relay;
+pub mod received_index;
+pub mod encryption_pool;
+pub mod integrity;
+pub mod viewing;
+pub mod grant_package;
+pub mod pairing;
+pub mod recovery;
 
 /// The address the server will listen on.
 pub const ADDR: &str = "10.40.7.1:8080";
@@ -19,6 +39,76 @@ pub const ADDR: &str = "10.40.7.1:8080";
 pub struct ImagePermissions {
     pub owner: String,
     pub quotas: HashMap<String, u32>, // username -> remaining views
+    /// Users who may keep a copy of the carrier (e.g. a backup account) but never view it (payload v3)
+    pub holders: Vec<String>,
+    /// Sequence number of the latest grant to each user, so replayed older grants are refused (payload v4)
+    pub grant_seqs: HashMap<String, u64>,
+    /// The latest quota changes, oldest first, capped at `PERMISSION_HISTORY_LIMIT` (payload v5)
+    pub history: Vec<PermissionChange>,
+}
+
+/// Quota changes kept in an image's history; older ones are dropped
+pub const PERMISSION_HISTORY_LIMIT: usize = 32;
+
+/// One change to a user's quota, as recorded in the image itself
+///
+/// Views used up by viewing aren't recorded, only grants, merges and revocations.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PermissionChange {
+    pub at: SystemTime,
+    pub changed_by: String,
+    pub user: String,
+    /// Quota before the change (None if the user had no entry)
+    pub before: Option<u32>,
+    pub after: u32,
+}
+
+impl PermissionChange {
+    pub fn is_revocation(&self) -> bool {
+        self.after == 0 && self.before.is_some_and(|views| views > 0)
+    }
+}
+
+impl ImagePermissions {
+    pub fn new(owner: String, quotas: HashMap<String, u32>) -> Self {
+        ImagePermissions { owner, quotas, holders: Vec::new(), grant_seqs: HashMap::new(), history: Vec::new() }
+    }
+
+    /// Set a user's quota as a new grant, returning the grant's sequence number
+    pub fn set_quota(&mut self, user: &str, views: u32) -> u64 {
+        let owner = self.owner.clone();
+        self.change_quota(&owner, user, views);
+        let seq = self.grant_seqs.entry(user.to_string()).or_insert(0);
+        *seq += 1;
+        *seq
+    }
+
+    /// Set a user's quota without numbering it as a grant, recording who changed it
+    pub fn change_quota(&mut self, changed_by: &str, user: &str, views: u32) {
+        let before = self.quotas.insert(user.to_string(), views);
+        if before == Some(views) {
+            return;
+        }
+        self.history.push(PermissionChange {
+            at: SystemTime::now(),
+            changed_by: changed_by.to_string(),
+            user: user.to_string(),
+            before,
+            after: views,
+        });
+        let excess = self.history.len().saturating_sub(PERMISSION_HISTORY_LIMIT);
+        self.history.drain(..excess);
+    }
+
+    /// Sequence number of the latest grant to `user` (0 if none was numbered)
+    pub fn grant_seq(&self, user: &str) -> u64 {
+        self.grant_seqs.get(user).copied().unwrap_or(0)
+    }
+
+    /// Whether `user` only holds the image (holding takes precedence over any quota)
+    pub fn is_holder(&self, user: &str) -> bool {
+        user != self.owner && self.holders.iter().any(|holder| holder == user)
+    }
 }
 
 /// This struct holds both the permissions and the raw bytes of the
@@ -27,6 +117,446 @@ pub struct ImagePermissions {
 pub struct CombinedPayload {
     pub permissions: ImagePermissions,
     pub unified_image: Vec<u8>, // Raw bytes of the PNG
+    /// Descriptive details set by the owner (empty in images encrypted before annotations)
+    pub annotations: ImageAnnotations,
+    /// Stable ID assigned at encrypt time (None in images encrypted before IDs)
+    pub image_id: Option<String>,
+    /// After this, only the owner can view the image (payload v2)
+    pub expires_at: Option<SystemTime>,
+    /// Per-image option bits, see `ViewPolicy` (payload v2)
+    pub flags: u32,
+    /// Everyone after the owner who passed this copy on, in order (payload v6)
+    pub provenance: Vec<ProvenanceHop>,
+}
+
+impl CombinedPayload {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| SystemTime::now() >= expires_at)
+    }
+
+    pub fn view_policy(&self) -> ViewPolicy {
+        ViewPolicy::from_flags(self.flags)
+    }
+
+    pub fn set_view_policy(&mut self, policy: ViewPolicy) {
+        self.flags = policy.apply_to_flags(self.flags);
+    }
+
+    /// Note that `sharer` passed this copy on, unless they're the owner or already the last hop
+    pub fn record_hop(&mut self, sharer: &str, role: ProvenanceRole) {
+        if sharer == self.permissions.owner
+            || self.provenance.last().is_some_and(|hop| hop.sharer == sharer && hop.role == role)
+        {
+            return;
+        }
+        self.provenance.push(ProvenanceHop { sharer: sharer.to_string(), role, at: SystemTime::now() });
+    }
+
+    /// The owner followed by each hop, e.g. `alice → bob (re-shared) → carol (delegate)`
+    pub fn provenance_chain(&self) -> String {
+        provenance_chain(&self.permissions.owner, &self.provenance)
+    }
+}
+
+/// How someone other than the owner came to pass an image on
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProvenanceRole {
+    /// Granted views from a copy they had received themselves
+    Reshared,
+    /// Approved the request on the owner's behalf
+    Delegate,
+}
+
+/// One hand an image passed through on its way from its owner
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProvenanceHop {
+    pub sharer: String,
+    pub role: ProvenanceRole,
+    pub at: SystemTime,
+}
+
+impl ProvenanceHop {
+    /// e.g. `bob (re-shared)`
+    pub fn describe(&self) -> String {
+        let role = match self.role {
+            ProvenanceRole::Reshared => "re-shared",
+            ProvenanceRole::Delegate => "delegate",
+        };
+        format!("{} ({})", self.sharer, role)
+    }
+}
+
+/// `owner` followed by each hop, joined with arrows
+pub fn provenance_chain(owner: &str, hops: &[ProvenanceHop]) -> String {
+    std::iter::once(owner.to_string())
+        .chain(hops.iter().map(ProvenanceHop::describe))
+        .collect::<Vec<_>>()
+        .join(" → ")
+}
+
+/// Payload flag: viewers may not save the decoded image
+pub const FLAG_NO_SAVE: u32 = 1 << 0;
+/// Payload flag: viewers may not print the decoded image
+pub const FLAG_NO_PRINT: u32 = 1 << 1;
+/// Payload flag: the decoded image is watermarked with the viewer's name
+pub const FLAG_WATERMARK: u32 = 1 << 2;
+
+/// What the owner lets viewers do with a decoded view
+///
+/// Stored as payload flags that restrict rather than allow, so images encrypted
+/// before the policy existed (flags 0) keep their unrestricted behavior.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewPolicy {
+    pub allow_save: bool,
+    pub allow_print: bool,
+    pub watermark: bool,
+}
+
+impl Default for ViewPolicy {
+    fn default() -> Self {
+        ViewPolicy { allow_save: true, allow_print: true, watermark: false }
+    }
+}
+
+impl ViewPolicy {
+    pub fn from_flags(flags: u32) -> Self {
+        ViewPolicy {
+            allow_save: flags & FLAG_NO_SAVE == 0,
+            allow_print: flags & FLAG_NO_PRINT == 0,
+            watermark: flags & FLAG_WATERMARK != 0,
+        }
+    }
+
+    /// `flags` with this policy's bits set, leaving any other bits alone
+    pub fn apply_to_flags(self, flags: u32) -> u32 {
+        let mut flags = flags & !(FLAG_NO_SAVE | FLAG_NO_PRINT | FLAG_WATERMARK);
+        if !self.allow_save {
+            flags |= FLAG_NO_SAVE;
+        }
+        if !self.allow_print {
+            flags |= FLAG_NO_PRINT;
+        }
+        if self.watermark {
+            flags |= FLAG_WATERMARK;
+        }
+        flags
+    }
+}
+
+/// Descriptive details an owner attaches to an image at encrypt time
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ImageAnnotations {
+    pub caption: Option<String>,
+    pub alt_text: Option<String>,
+    pub license: Option<String>,
+}
+
+impl ImageAnnotations {
+    pub fn is_empty(&self) -> bool {
+        self.caption.is_none() && self.alt_text.is_none() && self.license.is_none()
+    }
+}
+
+// --- EMBEDDED PAYLOAD VERSIONING ---
+
+/// Prefix of versioned payloads. Unversioned (v1) payloads start with the
+/// owner name's u64 length, which can never be this large.
+const PAYLOAD_MAGIC: [u8; 4] = *b"P2PV";
+
+/// Version written by `CombinedPayload::to_bytes`
+pub const PAYLOAD_VERSION: u8 = 6;
+
+/// Permissions layout before holders (payload v1 and v2, and the encryption metadata prefix)
+#[derive(Serialize, Deserialize)]
+struct PermissionsV2 {
+    owner: String,
+    quotas: HashMap<String, u32>,
+}
+
+impl From<PermissionsV2> for ImagePermissions {
+    fn from(v2: PermissionsV2) -> Self {
+        ImagePermissions::new(v2.owner, v2.quotas)
+    }
+}
+
+/// Permissions layout before grant sequence numbers (payload v3)
+#[derive(Deserialize)]
+struct PermissionsV3 {
+    owner: String,
+    quotas: HashMap<String, u32>,
+    holders: Vec<String>,
+}
+
+impl From<PermissionsV3> for ImagePermissions {
+    fn from(v3: PermissionsV3) -> Self {
+        let mut permissions = ImagePermissions::new(v3.owner, v3.quotas);
+        permissions.holders = v3.holders;
+        permissions
+    }
+}
+
+/// Permissions layout before the change history (payload v4)
+#[derive(Deserialize)]
+struct PermissionsV4 {
+    owner: String,
+    quotas: HashMap<String, u32>,
+    holders: Vec<String>,
+    grant_seqs: HashMap<String, u64>,
+}
+
+impl From<PermissionsV4> for ImagePermissions {
+    fn from(v4: PermissionsV4) -> Self {
+        let mut permissions = ImagePermissions::new(v4.owner, v4.quotas);
+        permissions.holders = v4.holders;
+        permissions.grant_seqs = v4.grant_seqs;
+        permissions
+    }
+}
+
+/// Payload layout before versioning (no expiry or flags)
+#[derive(Deserialize)]
+struct CombinedPayloadV1 {
+    permissions: PermissionsV2,
+    unified_image: Vec<u8>,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    annotations: ImageAnnotations,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    image_id: Option<String>,
+}
+
+impl From<CombinedPayloadV1> for CombinedPayload {
+    fn from(v1: CombinedPayloadV1) -> Self {
+        CombinedPayload {
+            permissions: v1.permissions.into(),
+            unified_image: v1.unified_image,
+            annotations: v1.annotations,
+            image_id: v1.image_id,
+            expires_at: None,
+            flags: 0,
+            provenance: Vec::new(),
+        }
+    }
+}
+
+/// Payload v2 layout (no holders)
+#[derive(Deserialize)]
+struct CombinedPayloadV2 {
+    permissions: PermissionsV2,
+    unified_image: Vec<u8>,
+    annotations: ImageAnnotations,
+    image_id: Option<String>,
+    expires_at: Option<SystemTime>,
+    flags: u32,
+}
+
+impl From<CombinedPayloadV2> for CombinedPayload {
+    fn from(v2: CombinedPayloadV2) -> Self {
+        CombinedPayload {
+            permissions: v2.permissions.into(),
+            unified_image: v2.unified_image,
+            annotations: v2.annotations,
+            image_id: v2.image_id,
+            expires_at: v2.expires_at,
+            flags: v2.flags,
+            provenance: Vec::new(),
+        }
+    }
+}
+
+/// Payload v3 layout (no grant sequence numbers)
+#[derive(Deserialize)]
+struct CombinedPayloadV3 {
+    permissions: PermissionsV3,
+    unified_image: Vec<u8>,
+    annotations: ImageAnnotations,
+    image_id: Option<String>,
+    expires_at: Option<SystemTime>,
+    flags: u32,
+}
+
+impl From<CombinedPayloadV3> for CombinedPayload {
+    fn from(v3: CombinedPayloadV3) -> Self {
+        CombinedPayload {
+            permissions: v3.permissions.into(),
+            unified_image: v3.unified_image,
+            annotations: v3.annotations,
+            image_id: v3.image_id,
+            expires_at: v3.expires_at,
+            flags: v3.flags,
+            provenance: Vec::new(),
+        }
+    }
+}
+
+/// Payload v4 layout (no permission history)
+#[derive(Deserialize)]
+struct CombinedPayloadV4 {
+    permissions: PermissionsV4,
+    unified_image: Vec<u8>,
+    annotations: ImageAnnotations,
+    image_id: Option<String>,
+    expires_at: Option<SystemTime>,
+    flags: u32,
+}
+
+impl From<CombinedPayloadV4> for CombinedPayload {
+    fn from(v4: CombinedPayloadV4) -> Self {
+        CombinedPayload {
+            permissions: v4.permissions.into(),
+            unified_image: v4.unified_image,
+            annotations: v4.annotations,
+            image_id: v4.image_id,
+            expires_at: v4.expires_at,
+            flags: v4.flags,
+            provenance: Vec::new(),
+        }
+    }
+}
+
+/// Payload v5 layout (no provenance)
+#[derive(Deserialize)]
+struct CombinedPayloadV5 {
+    permissions: ImagePermissions,
+    unified_image: Vec<u8>,
+    annotations: ImageAnnotations,
+    image_id: Option<String>,
+    expires_at: Option<SystemTime>,
+    flags: u32,
+}
+
+impl From<CombinedPayloadV5> for CombinedPayload {
+    fn from(v5: CombinedPayloadV5) -> Self {
+        CombinedPayload {
+            permissions: v5.permissions,
+            unified_image: v5.unified_image,
+            annotations: v5.annotations,
+            image_id: v5.image_id,
+            expires_at: v5.expires_at,
+            flags: v5.flags,
+            provenance: Vec::new(),
+        }
+    }
+}
+
+impl CombinedPayload {
+    /// Encode for embedding, tagged with the current payload version
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = PAYLOAD_MAGIC.to_vec();
+        bytes.push(PAYLOAD_VERSION);
+        bincode::serialize_into(&mut bytes, self)?;
+        Ok(bytes)
+    }
+
+    /// Decode an embedded payload of any known version, migrating it to the current layout
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        match payload_version(bytes) {
+            1 => Ok(bincode::deserialize::<CombinedPayloadV1>(bytes)?.into()),
+            2 => Ok(bincode::deserialize::<CombinedPayloadV2>(&bytes[PAYLOAD_MAGIC.len() + 1..])?.into()),
+            3 => Ok(bincode::deserialize::<CombinedPayloadV3>(&bytes[PAYLOAD_MAGIC.len() + 1..])?.into()),
+            4 => Ok(bincode::deserialize::<CombinedPayloadV4>(&bytes[PAYLOAD_MAGIC.len() + 1..])?.into()),
+            5 => Ok(bincode::deserialize::<CombinedPayloadV5>(&bytes[PAYLOAD_MAGIC.len() + 1..])?.into()),
+            PAYLOAD_VERSION => Ok(bincode::deserialize(&bytes[PAYLOAD_MAGIC.len() + 1..])?),
+            version => bail!(
+                "Image payload version {} is newer than this client supports ({}) - please upgrade",
+                version,
+                PAYLOAD_VERSION
+            ),
+        }
+    }
+}
+
+impl ImagePermissions {
+    /// Decode just the permissions at the start of an embedded payload of any known version
+    ///
+    /// Everything after them may be missing or damaged, as in a carrier that was cut short.
+    pub fn from_payload_prefix(bytes: &[u8]) -> Result<Self> {
+        let body = bytes.get(PAYLOAD_MAGIC.len() + 1..).unwrap_or_default();
+        match payload_version(bytes) {
+            1 => Ok(bincode::deserialize::<PermissionsV2>(bytes)?.into()),
+            2 => Ok(bincode::deserialize::<PermissionsV2>(body)?.into()),
+            3 => Ok(bincode::deserialize::<PermissionsV3>(body)?.into()),
+            4 => Ok(bincode::deserialize::<PermissionsV4>(body)?.into()),
+            5 | PAYLOAD_VERSION => Ok(bincode::deserialize(body)?),
+            version => bail!(
+                "Image payload version {} is newer than this client supports ({}) - please upgrade",
+                version,
+                PAYLOAD_VERSION
+            ),
+        }
+    }
+}
+
+/// Version of an embedded payload (1 for payloads written before versioning)
+pub fn payload_version(bytes: &[u8]) -> u8 {
+    match bytes.strip_prefix(&PAYLOAD_MAGIC) {
+        Some(rest) => rest.first().copied().unwrap_or(0),
+        None => 1,
+    }
+}
+
+/// Metadata a client sends alongside an image to be encrypted
+///
+/// Starts with the bare `ImagePermissions` encoding from before holders, so
+/// older servers (which only read the permissions) still accept it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(from = "EncryptionMetadataWire", into = "EncryptionMetadataWire")]
+pub struct EncryptionMetadata {
+    pub permissions: ImagePermissions,
+    pub annotations: ImageAnnotations,
+    pub image_id: Option<String>,
+    /// When the image stops being viewable by anyone but the owner
+    pub expires_at: Option<SystemTime>,
+    /// Payload flags to embed, e.g. the `ViewPolicy`
+    pub flags: u32,
+}
+
+/// `EncryptionMetadata` as sent, with holders and flags trailing so the prefix keeps its old layout
+#[derive(Serialize, Deserialize)]
+struct EncryptionMetadataWire {
+    permissions: PermissionsV2,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    annotations: ImageAnnotations,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    image_id: Option<String>,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    expires_at: Option<SystemTime>,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    holders: Vec<String>,
+    #[serde(default, deserialize_with = "lenient_trailing")]
+    flags: u32,
+}
+
+impl From<EncryptionMetadataWire> for EncryptionMetadata {
+    fn from(wire: EncryptionMetadataWire) -> Self {
+        let mut permissions = ImagePermissions::from(wire.permissions);
+        permissions.holders = wire.holders;
+        EncryptionMetadata {
+            permissions,
+            annotations: wire.annotations,
+            image_id: wire.image_id,
+            expires_at: wire.expires_at,
+            flags: wire.flags,
+        }
+    }
+}
+
+impl From<EncryptionMetadata> for EncryptionMetadataWire {
+    fn from(metadata: EncryptionMetadata) -> Self {
+        let ImagePermissions { owner, quotas, holders, .. } = metadata.permissions;
+        EncryptionMetadataWire {
+            permissions: PermissionsV2 { owner, quotas },
+            annotations: metadata.annotations,
+            image_id: metadata.image_id,
+            expires_at: metadata.expires_at,
+            holders,
+            flags: metadata.flags,
+        }
+    }
+}
+
+/// Bincode can't skip fields, so treat trailing fields that aren't there as empty
+fn lenient_trailing<'de, D: Deserializer<'de>, T: Deserialize<'de> + Default>(deserializer: D) -> Result<T, D::Error> {
+    Ok(T::deserialize(deserializer).unwrap_or_default())
 }
 
 // --- RAFT MESSAGE TYPES ---
@@ -1,8 +1,12 @@
 //! Manual implementation of Least Significant Bit (LSB) steganography.
 
+use crate::{CombinedPayload, EncryptionMetadata};
 use anyhow::{bail, Result};
 // use image::{DynamicImage, GenericImageView, Rgba};
-use image::{DynamicImage, GenericImageView};
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{DynamicImage, GenericImageView, ImageEncoder, ImageFormat, ImageOutputFormat};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
 
 /// Encodes a payload of bytes into the least significant bits of an image's pixels.
 pub fn encode(img: &DynamicImage, payload: &[u8]) -> Result<DynamicImage> {
@@ -76,3 +80,227 @@ pub fn decode(img: &DynamicImage) -> Result<Option<Vec<u8>>> {
 
     Ok(Some(payload))
 }
+
+/// Decode as much of a payload as `pixels` (raw RGBA bytes, possibly cut short) still hold
+///
+/// Returns the length the carrier declares and the payload bytes read, which fall short of
+/// it when pixels are missing; None when not even the length survived or it can't be right
+/// for a carrier of `total_pixel_bytes`.
+pub fn decode_partial(pixels: &[u8], total_pixel_bytes: usize) -> Option<(usize, Vec<u8>)> {
+    if pixels.len() < 32 {
+        return None;
+    }
+    let mut bits = pixels.iter().map(|byte| byte & 1);
+
+    let mut len_bits = 0u32;
+    for _ in 0..32 {
+        len_bits = (len_bits << 1) | bits.next().unwrap_or(0) as u32;
+    }
+    let declared_len = len_bits as usize;
+    if declared_len > total_pixel_bytes.saturating_sub(32) / 8 {
+        return None;
+    }
+
+    let available = ((pixels.len() - 32) / 8).min(declared_len);
+    let mut payload = Vec::with_capacity(available);
+    for _ in 0..available {
+        let mut byte = 0u8;
+        for _ in 0..8 {
+            byte = (byte << 1) | bits.next().unwrap_or(0);
+        }
+        payload.push(byte);
+    }
+    Some((declared_len, payload))
+}
+
+// =============================================================================
+// CARRIER ANALYSIS
+// =============================================================================
+
+/// Carriers narrower or shorter than this are rejected as too small to be useful
+pub const MIN_CARRIER_DIMENSION: u32 = 64;
+
+/// Fill ratio above which a carrier is flagged as nearly full
+const TIGHT_FIT_RATIO: f64 = 0.9;
+
+/// Payload bytes a carrier can hold, using the encryption servers' budget of 3 bits per pixel
+pub fn capacity_bytes(width: u32, height: u32) -> usize {
+    (width as usize * height as usize * 3) / 8
+}
+
+/// Whether a carrier can hold a payload reliably, checked before committing to encryption
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CarrierAnalysis {
+    pub width: u32,
+    pub height: u32,
+    /// Format detected from the file contents, e.g. `Png`
+    pub format: String,
+    /// Whether the format keeps every pixel bit exactly (required for LSB data to survive)
+    pub lossless: bool,
+    pub capacity_bytes: usize,
+    pub payload_bytes: usize,
+    /// The payload fits and the carrier is usable as-is
+    pub fits: bool,
+    pub warnings: Vec<String>,
+}
+
+/// Check a carrier image against the size of the payload it would need to hold
+pub fn analyze_carrier(carrier_bytes: &[u8], payload_bytes: usize) -> Result<CarrierAnalysis> {
+    let format = image::guess_format(carrier_bytes)?;
+    let (width, height) = image::io::Reader::with_format(Cursor::new(carrier_bytes), format)
+        .into_dimensions()?;
+
+    let lossless = matches!(
+        format,
+        ImageFormat::Png | ImageFormat::Bmp | ImageFormat::Tiff | ImageFormat::Tga | ImageFormat::Pnm
+    );
+    let capacity = capacity_bytes(width, height);
+    let mut warnings = Vec::new();
+
+    if !lossless {
+        warnings.push(format!(
+            "{:?} is a lossy format: saving a carrier as {:?} destroys the hidden data, so keep it as PNG",
+            format, format
+        ));
+    }
+    if width < MIN_CARRIER_DIMENSION || height < MIN_CARRIER_DIMENSION {
+        warnings.push(format!(
+            "Carrier is only {}x{} (minimum {}x{})",
+            width, height, MIN_CARRIER_DIMENSION, MIN_CARRIER_DIMENSION
+        ));
+    }
+    if payload_bytes > capacity {
+        let min_dimension = ((payload_bytes * 8 / 3) as f64).sqrt().ceil() as u32;
+        warnings.push(format!(
+            "Payload needs {:.1} KB but the carrier holds {:.1} KB (needs about {}x{} pixels)",
+            payload_bytes as f64 / 1024.0,
+            capacity as f64 / 1024.0,
+            min_dimension,
+            min_dimension
+        ));
+    } else if payload_bytes as f64 > capacity as f64 * TIGHT_FIT_RATIO {
+        warnings.push(format!(
+            "Payload uses {:.0}% of the carrier's capacity; later permission changes may not fit",
+            payload_bytes as f64 * 100.0 / capacity as f64
+        ));
+    }
+
+    let fits = payload_bytes <= capacity
+        && width >= MIN_CARRIER_DIMENSION
+        && height >= MIN_CARRIER_DIMENSION;
+
+    Ok(CarrierAnalysis {
+        width,
+        height,
+        format: format!("{:?}", format),
+        lossless,
+        capacity_bytes: capacity,
+        payload_bytes,
+        fits,
+        warnings,
+    })
+}
+
+/// Exact size of the payload an encryption server would embed for this image and metadata
+pub fn payload_size(image_bytes: &[u8], metadata: EncryptionMetadata) -> Result<usize> {
+    let image = image::load_from_memory(image_bytes)?;
+    let mut png_bytes = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png_bytes), ImageOutputFormat::Png)?;
+
+    let payload = CombinedPayload {
+        permissions: metadata.permissions,
+        unified_image: png_bytes,
+        annotations: metadata.annotations,
+        image_id: metadata.image_id,
+        expires_at: metadata.expires_at,
+        flags: metadata.flags,
+        provenance: Vec::new(),
+    };
+    Ok(payload.to_bytes()?.len())
+}
+
+// =============================================================================
+// CARRIER OPTIMIZATION
+// =============================================================================
+
+/// Filter strategies tried when recompressing a carrier
+const OPTIMIZE_FILTERS: [FilterType; 6] = [
+    FilterType::NoFilter,
+    FilterType::Sub,
+    FilterType::Up,
+    FilterType::Avg,
+    FilterType::Paeth,
+    FilterType::Adaptive,
+];
+
+/// A carrier PNG after recompression
+#[derive(Debug, Clone)]
+pub struct OptimizedCarrier {
+    pub bytes: Vec<u8>,
+    pub original_bytes: usize,
+}
+
+impl OptimizedCarrier {
+    pub fn saved_bytes(&self) -> usize {
+        self.original_bytes.saturating_sub(self.bytes.len())
+    }
+
+    /// e.g. `1024.0 KB -> 768.0 KB (25.0% smaller)`
+    pub fn summary(&self) -> String {
+        let percent = if self.original_bytes == 0 {
+            0.0
+        } else {
+            self.saved_bytes() as f64 * 100.0 / self.original_bytes as f64
+        };
+        format!(
+            "{:.1} KB -> {:.1} KB ({:.1}% smaller)",
+            self.original_bytes as f64 / 1024.0,
+            self.bytes.len() as f64 / 1024.0,
+            percent
+        )
+    }
+}
+
+/// Losslessly recompress a carrier PNG, keeping the original unless a verified smaller copy comes out
+///
+/// The color type is only narrowed when no pixel changes (opaque RGBA to RGB, gray to
+/// luma), each filter strategy is tried at maximum deflate compression, and the smallest
+/// result is kept only if it decodes to exactly the original RGBA pixels, which is what
+/// `decode` reads the payload from.
+pub fn optimize_carrier(png_bytes: &[u8]) -> Result<OptimizedCarrier> {
+    let original = image::load_from_memory_with_format(png_bytes, ImageFormat::Png)?;
+    let pixels = original.to_rgba8();
+    let (width, height) = pixels.dimensions();
+
+    let opaque = pixels.pixels().all(|p| p[3] == 255);
+    let gray = pixels.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+    let narrowed = match (gray, opaque) {
+        (true, true) => DynamicImage::ImageRgba8(pixels.clone()).into_luma8().into(),
+        (true, false) => DynamicImage::ImageRgba8(pixels.clone()).into_luma_alpha8().into(),
+        (false, true) => DynamicImage::ImageRgba8(pixels.clone()).into_rgb8().into(),
+        (false, false) => DynamicImage::ImageRgba8(pixels.clone()),
+    };
+
+    let mut best: Option<Vec<u8>> = None;
+    for filter in OPTIMIZE_FILTERS {
+        let mut candidate = Vec::new();
+        PngEncoder::new_with_quality(&mut candidate, CompressionType::Best, filter).write_image(
+            narrowed.as_bytes(),
+            width,
+            height,
+            narrowed.color(),
+        )?;
+        if best.as_ref().is_none_or(|best| candidate.len() < best.len()) {
+            best = Some(candidate);
+        }
+    }
+
+    let verified = best.filter(|best| {
+        best.len() < png_bytes.len()
+            && image::load_from_memory_with_format(best, ImageFormat::Png)
+                .is_ok_and(|roundtrip| roundtrip.to_rgba8().as_raw() == pixels.as_raw())
+    });
+    let bytes = verified.unwrap_or_else(|| png_bytes.to_vec());
+
+    Ok(OptimizedCarrier { bytes, original_bytes: png_bytes.len() })
+}
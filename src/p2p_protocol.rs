@@ -1,12 +1,18 @@
 use anyhow::{bail, Context, Result};
-use bincode;
-use log::{error, info};
+use log::{error, info, warn};
+use crate::audit_log::{audit, AuditAction, AuditRecord};
+use crate::bandwidth::{check_daily_cap, record_traffic};
+use crate::directory_service::{EscrowedPreview, ImageVisibility, MAX_PREVIEW_BYTES};
+use crate::protocol_trace::{record_frame, TraceChannel, TraceDirection};
+use crate::relay::{open_relay_stream, outbound_relay, parse_relay_address};
+use crate::search::{SearchIndex, DESCRIPTION_WEIGHT, NAME_WEIGHT};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
 
 // =============================================================================
 // P2P MESSAGE PROTOCOL
@@ -20,6 +26,16 @@ pub enum P2PMessage {
         requesting_user: String,
         image_id: String,
         requested_views: u32,
+        /// Ask for a copy no larger than this on its longer side
+        #[serde(default)]
+        requested_max_dimension: Option<u32>,
+        /// Delegate who approved the request on the owner's behalf, recorded in the audit log
+        #[serde(default)]
+        approved_by: Option<String>,
+        /// Token the directory issued when the request was accepted, needed to raise
+        /// a non-owner's quota (an accepted trade's ID also serves)
+        #[serde(default)]
+        grant_token: Option<String>,
     },
     
     /// Response with the encrypted image or rejection
@@ -32,6 +48,9 @@ pub enum P2PMessage {
     /// Query available images from a peer
     ListImages {
         requesting_user: String,
+        /// Only images whose name or description fuzzily matches, best first
+        #[serde(default)]
+        query: Option<String>,
     },
     
     /// Response with list of available images
@@ -59,12 +78,47 @@ pub enum P2PMessage {
         image_id: String,
         requested_views: u32,
         encrypted_image: Vec<u8>, // The actual image data with embedded permissions
+        /// SHA-256 of encrypted_image as computed by the sender
+        #[serde(default)]
+        sha256: Option<String>,
+        /// Sequence number of the grant embedded for the recipient (must match the carrier)
+        #[serde(default)]
+        grant_seq: Option<u64>,
     },
 
     /// Response to image delivery
     DeliverImageResponse {
         success: bool,
         message: String,
+        /// SHA-256 of the bytes the receiver stored
+        #[serde(default)]
+        sha256: Option<String>,
+        /// Size in bytes of the stored image
+        #[serde(default)]
+        size_bytes: u64,
+    },
+
+    /// Refusal of a delivery or update because the receiver is short of disk space;
+    /// the sender should queue it at the directory instead
+    StorageFull {
+        message: String,
+        available_bytes: u64,
+        needed_bytes: u64,
+    },
+
+    /// Several deliveries from one owner in a single request, stored one after another
+    DeliverBundle {
+        from_owner: String,
+        items: Vec<BundledImage>,
+    },
+
+    /// Outcome of one item of a `DeliverBundle`, sent as soon as it's stored
+    ///
+    /// A bundle gets one ack per item, in order, instead of a single response.
+    DeliverBundleAck {
+        index: usize,
+        /// The `DeliverImageResponse` (or `StorageFull`) a lone `DeliverImage` would have got
+        response: Box<P2PMessage>,
     },
 
     /// Remote permission update: Owner asks requester to update their local copy's permissions
@@ -85,82 +139,1865 @@ pub enum P2PMessage {
     ThumbnailRequest {
         requesting_user: String,
         image_id: String,
+        /// Smaller, harder-compressed preview for requesters on slow links
+        #[serde(default)]
+        quality: ThumbnailQuality,
+    },
+
+    /// Response with low-resolution thumbnail
+    ThumbnailResponse {
+        success: bool,
+        message: String,
+        thumbnail: Option<Vec<u8>>, // Low-res blurred preview as PNG bytes
+    },
+
+    /// Notify an owner that a requester withdrew a pending request
+    RequestCancelled {
+        request_id: String,
+        from_user: String,
+        image_id: String,
+    },
+
+    /// Response to request cancellation notice
+    RequestCancelledResponse {
+        success: bool,
+    },
+
+    /// Notify an owner that a directory dropped a queued image to stay within its storage cap
+    PendingImageEvicted {
+        update_id: String,
+        target_user: String,
+        image_id: String,
+    },
+
+    /// Response to eviction notice
+    PendingImageEvictedResponse {
+        success: bool,
+    },
+
+    /// Tell an owner that a recipient's view attempt was denied
+    AccessDeniedReport {
+        denial: AccessDenial,
+    },
+
+    /// Response to access denial report
+    AccessDeniedReportResponse {
+        success: bool,
+    },
+
+    /// Ask an owner whether our local copy embeds the same image they're sharing
+    HaveImage {
+        requesting_user: String,
+        image_id: String,
+        /// `content_sha256` of our local copy
+        content_hash: String,
+    },
+
+    /// Response to an image probe
+    HaveImageResponse {
+        /// The owner's image embeds identical bytes, so a permission update is enough
+        identical: bool,
+    },
+
+    /// Liveness check, answered on the control lane even while transfers are in flight
+    Ping {
+        from_user: String,
+    },
+
+    /// Response to a liveness check
+    Pong {
+        username: String,
+    },
+
+    /// Ask an owner to resend an image we were already granted (e.g. after losing our copy)
+    ///
+    /// Answered with `ImageResponse`, without another approval, while the owner's
+    /// embedded quotas still give us views.
+    RedeliverRequest {
+        requesting_user: String,
+        image_id: String,
+    },
+
+    /// Ask a recipient to securely delete their copy of an owner's image
+    RemoteWipe {
+        from_owner: String,
+        image_id: String,
+    },
+
+    /// Response to a wipe request
+    RemoteWipeResponse {
+        success: bool,
+        message: String,
+    },
+
+    /// Tell an owner that a wipe queued while we were offline has been carried out
+    RemoteWipeAck {
+        from_user: String,
+        image_id: String,
+        success: bool,
+        message: String,
+    },
+
+    /// Response to a wipe acknowledgment
+    RemoteWipeAckResponse {
+        success: bool,
+    },
+
+    /// Ask an owner what their copy of an image grants `for_user`, to catch revocations
+    /// or top-ups that never reached our copy
+    VerifyGrant {
+        image_id: String,
+        for_user: String,
+    },
+
+    /// The owner's side of a grant
+    VerifyGrantResponse {
+        /// Whether the owner still shares the image
+        found: bool,
+        /// Views the owner's copy grants the user (None if it never granted any)
+        quota: Option<u32>,
+        /// Sequence number of the owner's latest grant to the user
+        grant_seq: u64,
+    },
+
+    /// Follow changes to a peer's catalog instead of listing it again and again
+    ///
+    /// Answered with a `CatalogSnapshot`, then a `CatalogUpdate` frame per change for as
+    /// long as the subscriber keeps the connection open.
+    SubscribeCatalog {
+        requesting_user: String,
+    },
+
+    /// The images listed to the subscriber when it subscribed
+    CatalogSnapshot {
+        images: Vec<ImageMetadata>,
+    },
+
+    /// One change to a subscribed catalog, pushed by the owner
+    CatalogUpdate {
+        event: CatalogEvent,
+    },
+
+    /// Redeem a pairing code the owner showed us in person, for the views it offers
+    RedeemPairing {
+        code: String,
+        requesting_user: String,
     },
 
-    /// Response with low-resolution thumbnail
-    ThumbnailResponse {
-        success: bool,
-        message: String,
-        thumbnail: Option<Vec<u8>>, // Low-res blurred preview as PNG bytes
-    },
+    /// The owner's answer to a pairing code, with the granted carrier if it was accepted
+    RedeemPairingResponse {
+        success: bool,
+        message: String,
+        #[serde(default)]
+        grant: Option<PairedGrant>,
+    },
+
+    /// Sent instead of a response when the server has no room for another connection
+    Busy {
+        message: String,
+        /// How long to wait before trying again
+        retry_after_secs: u64,
+    },
+}
+
+/// One image of a `DeliverBundle`: a `DeliverImage` without the owner, which the bundle names once
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BundledImage {
+    pub image_id: String,
+    pub requested_views: u32,
+    pub encrypted_image: Vec<u8>,
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub grant_seq: Option<u64>,
+}
+
+/// A grant made for a pairing code, handed over in the `RedeemPairingResponse`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairedGrant {
+    pub from_owner: String,
+    pub image_id: String,
+    pub views: u32,
+    pub grant_seq: u64,
+    pub sha256: String,
+    pub encrypted_image: Vec<u8>,
+}
+
+impl BundledImage {
+    /// The `DeliverImage` this item stands for
+    pub fn into_delivery(self, from_owner: &str) -> P2PMessage {
+        P2PMessage::DeliverImage {
+            from_owner: from_owner.to_string(),
+            image_id: self.image_id,
+            requested_views: self.requested_views,
+            encrypted_image: self.encrypted_image,
+            sha256: self.sha256,
+            grant_seq: self.grant_seq,
+        }
+    }
+}
+
+/// A change pushed to catalog subscribers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CatalogEvent {
+    /// An image was shared, or became visible to the subscriber
+    Added { image: ImageMetadata },
+    /// A listed image's name, description or grant cap changed
+    Updated { image: ImageMetadata },
+    /// An image was unshared, or hidden from the subscriber
+    Removed { image_id: String },
+    /// Nothing changed for a while; lets the subscriber tell a quiet owner from a dead connection
+    KeepAlive,
+}
+
+/// Size of a thumbnail preview, picked by the requester from its link speed to the owner
+///
+/// Owners from before quality hints ignore it and always send `Medium`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ThumbnailQuality {
+    /// 64x64, compressed as hard as PNG allows
+    Tiny,
+    /// 150x150 at the default compression
+    #[default]
+    Medium,
+}
+
+impl ThumbnailQuality {
+    /// Side length of the thumbnail's bounding box
+    pub fn dimension(self) -> u32 {
+        match self {
+            ThumbnailQuality::Tiny => 64,
+            ThumbnailQuality::Medium => 150,
+        }
+    }
+
+    fn compression(self) -> image::codecs::png::CompressionType {
+        match self {
+            ThumbnailQuality::Tiny => image::codecs::png::CompressionType::Best,
+            ThumbnailQuality::Medium => image::codecs::png::CompressionType::Default,
+        }
+    }
+
+    /// Tiny previews for peers we've measured below `SLOW_LINK_BYTES_PER_SEC`
+    pub fn for_peer(peer_addr: &str) -> Self {
+        match peer_throughput(peer_addr) {
+            Some(rate) if rate < SLOW_LINK_BYTES_PER_SEC => ThumbnailQuality::Tiny,
+            _ => ThumbnailQuality::Medium,
+        }
+    }
+}
+
+/// Metadata about an available image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageMetadata {
+    pub image_id: String,
+    pub image_name: String,
+    pub owner: String,
+    pub description: Option<String>,
+    pub file_size_kb: u64,
+    /// Maximum views the owner will grant per request (None = no cap)
+    #[serde(default)]
+    pub max_grant_views: Option<u32>,
+}
+
+// =============================================================================
+// PEER CAPABILITIES
+// =============================================================================
+
+/// Serves blurred thumbnail previews (`ThumbnailRequest`)
+pub const CAP_THUMBNAILS: &str = "thumbnails";
+/// Sends and verifies SHA-256 checksums on `DeliverImage`
+pub const CAP_CHECKSUMS: &str = "checksums";
+/// Accepts `RequestCancelled` / `PendingImageEvicted` notices
+pub const CAP_REQUEST_NOTICES: &str = "request-notices";
+/// Accepts `AccessDeniedReport` telemetry
+pub const CAP_ACCESS_REPORTS: &str = "access-reports";
+/// Answers `HaveImage` probes
+pub const CAP_IMAGE_PROBE: &str = "image-probe";
+/// Answers `Ping`, and handles control messages ahead of queued image transfers
+pub const CAP_PRIORITY_LANES: &str = "priority-lanes";
+/// Answers `RedeliverRequest` for images a peer was already granted
+pub const CAP_REDELIVERY: &str = "redelivery";
+/// Deletes received copies on `RemoteWipe`
+pub const CAP_REMOTE_WIPE: &str = "remote-wipe";
+/// Accepts `DeliverBundle`, acking each image as it's stored
+pub const CAP_DELIVERY_BUNDLES: &str = "delivery-bundles";
+/// Answers `VerifyGrant` from its own copies of shared images
+pub const CAP_GRANT_VERIFY: &str = "grant-verify";
+/// Pushes catalog changes to `SubscribeCatalog` subscribers
+pub const CAP_CATALOG_UPDATES: &str = "catalog-updates";
+
+/// Features assumed for peers that registered without advertising capabilities
+pub const LEGACY_CAPABILITIES: &[&str] = &[CAP_THUMBNAILS];
+
+/// Capabilities this build of the P2P protocol supports
+pub fn local_capabilities() -> Vec<String> {
+    [
+        CAP_THUMBNAILS,
+        CAP_CHECKSUMS,
+        CAP_REQUEST_NOTICES,
+        CAP_ACCESS_REPORTS,
+        CAP_IMAGE_PROBE,
+        CAP_PRIORITY_LANES,
+        CAP_REDELIVERY,
+        CAP_REMOTE_WIPE,
+        CAP_DELIVERY_BUNDLES,
+        CAP_GRANT_VERIFY,
+        CAP_CATALOG_UPDATES,
+    ]
+        .iter()
+        .map(|c| c.to_string())
+        .collect()
+}
+
+// =============================================================================
+// IMAGE IDS
+// =============================================================================
+
+/// New image ID: a random UUID plus the first 12 hex digits of the original image's SHA-256
+///
+/// Ends in `.png` because recipients may name their copy after it (see `ReceivedNamePolicy`).
+pub fn new_image_id(image_bytes: &[u8]) -> String {
+    format!("{}-{}.png", uuid::Uuid::new_v4(), &sha256_hex(image_bytes)[..12])
+}
+
+/// Image ID embedded in a carrier, if it can be decoded and has one
+pub fn embedded_image_id(carrier_bytes: &[u8]) -> Option<String> {
+    let img = image::load_from_memory(carrier_bytes).ok()?;
+    let payload = crate::lsb::decode(&img).ok()??;
+    let combined = crate::CombinedPayload::from_bytes(&payload).ok()?;
+    combined.image_id
+}
+
+/// ID to share a file under: its embedded ID, or the file name for images encrypted before IDs
+pub fn shared_image_id(path: &std::path::Path) -> String {
+    fs::read(path)
+        .ok()
+        .and_then(|data| embedded_image_id(&data))
+        .unwrap_or_else(|| path.file_name().unwrap_or_default().to_string_lossy().into_owned())
+}
+
+// =============================================================================
+// P2P REQUEST HANDLER
+// =============================================================================
+
+/// File in the images directory recording who each shared image is visible to, and its grant cap
+pub const VISIBILITY_FILE: &str = "image_visibility.json";
+
+/// A shared image's visibility, with the token its share link carries while it's link-only
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct VisibilitySetting {
+    pub visibility: ImageVisibility,
+    #[serde(default)]
+    pub link_token: Option<String>,
+    /// Leave a blurred preview with the directory, so the image can be browsed while we're offline
+    #[serde(default)]
+    pub escrow_preview: bool,
+    /// Cap on views granted per request for this image, overriding the one it was shared with
+    #[serde(default)]
+    pub max_grant_views: Option<u32>,
+}
+
+/// Information about images that this peer owns
+pub struct PeerImageStore {
+    /// User whose images these are, so `owner/image_id` requests resolve
+    owner: Option<String>,
+    /// Map of image_id -> (file_path, metadata)
+    images: HashMap<String, (PathBuf, ImageMetadata)>,
+    /// Map of file name -> image_id, so peers still using file names as IDs are served
+    file_ids: HashMap<String, String>,
+    /// Directory where received images should be saved
+    received_images_dir: Option<PathBuf>,
+    /// File where access denial stats for our images are aggregated
+    denial_stats_path: Option<PathBuf>,
+    /// Maximum bytes received images may occupy (None for unlimited)
+    received_quota_bytes: Option<u64>,
+    /// File where outbound grants are recorded (None to not record them)
+    audit_log_path: Option<PathBuf>,
+    /// When each (user, image) pair was last re-delivered
+    redeliveries: HashMap<(String, String), std::time::Instant>,
+    /// Last carrier served for each image, so repeat grants skip the LSB rewrite
+    carrier_cache: HashMap<String, CachedCarrier>,
+    /// Image names and descriptions, for `ListImages` searches
+    search_index: SearchIndex<String>,
+    /// Told when an owner revokes or wipes one of our received images
+    revocation_listener: Option<tokio::sync::mpsc::UnboundedSender<AccessRevoked>>,
+    /// Directory servers asked to verify grant tokens
+    directory_servers: Vec<String>,
+    /// Visibility of images that aren't public, by image ID
+    visibility: HashMap<String, VisibilitySetting>,
+    /// File the visibility settings are kept in (None to keep them in memory only)
+    visibility_path: Option<PathBuf>,
+    /// Grant cap each image was shared with, restored when its own cap is cleared
+    default_grant_caps: HashMap<String, Option<u32>>,
+    /// Told the ID of every shared image added, changed or removed, for catalog subscribers
+    catalog_changes: tokio::sync::broadcast::Sender<String>,
+    /// Open pairing codes for our images
+    pairing_offers: crate::pairing::PairingOffers,
+}
+
+/// An owner revoked (or wiped) one of our received images while we were online
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccessRevoked {
+    pub from_owner: String,
+    pub image_id: String,
+    /// Our copies were deleted rather than set to 0 views
+    pub wiped: bool,
+}
+
+/// A carrier as last granted, with the permissions embedded in it
+struct CachedCarrier {
+    /// File modification time and size the bytes were read or written at
+    modified: std::time::SystemTime,
+    len: u64,
+    owner: String,
+    holders: Vec<String>,
+    png_bytes: Vec<u8>,
+}
+
+/// Minimum time between re-deliveries of the same image to the same user
+///
+/// A re-delivered copy carries the views the owner last granted (views spent on the
+/// lost copy can't be known), so this keeps it from becoming a way to reset quotas.
+pub const REDELIVERY_COOLDOWN: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Catalog changes a slow subscriber may fall behind by before it's sent a full resync
+const CATALOG_CHANGE_BACKLOG: usize = 256;
+
+impl Default for PeerImageStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PeerImageStore {
+    pub fn new() -> Self {
+        Self {
+            owner: None,
+            images: HashMap::new(),
+            file_ids: HashMap::new(),
+            received_images_dir: None,
+            denial_stats_path: None,
+            received_quota_bytes: None,
+            audit_log_path: None,
+            redeliveries: HashMap::new(),
+            carrier_cache: HashMap::new(),
+            search_index: SearchIndex::default(),
+            revocation_listener: None,
+            directory_servers: Vec::new(),
+            visibility: HashMap::new(),
+            visibility_path: None,
+            default_grant_caps: HashMap::new(),
+            catalog_changes: tokio::sync::broadcast::channel(CATALOG_CHANGE_BACKLOG).0,
+            pairing_offers: crate::pairing::PairingOffers::default(),
+        }
+    }
+    
+    /// Set the user whose images these are
+    pub fn set_owner(&mut self, owner: String) {
+        self.owner = Some(owner);
+    }
+    
+    /// Set the directory where received images should be saved
+    pub fn set_received_images_dir(&mut self, dir: PathBuf) {
+        self.received_images_dir = Some(dir);
+    }
+    
+    /// Get the directory where received images should be saved
+    pub fn get_received_images_dir(&self) -> Option<&PathBuf> {
+        self.received_images_dir.as_ref()
+    }
+    
+    /// Set the file where access denial stats for our images are kept
+    pub fn set_denial_stats_path(&mut self, path: PathBuf) {
+        self.denial_stats_path = Some(path);
+    }
+    
+    /// Get the file where access denial stats for our images are kept
+    pub fn get_denial_stats_path(&self) -> Option<&PathBuf> {
+        self.denial_stats_path.as_ref()
+    }
+    
+    /// The pairing codes open for our images
+    pub fn pairing_offers(&mut self) -> &mut crate::pairing::PairingOffers {
+        &mut self.pairing_offers
+    }
+    
+    /// Set (or clear) the maximum bytes received images may occupy
+    pub fn set_received_quota_bytes(&mut self, quota: Option<u64>) {
+        self.received_quota_bytes = quota;
+    }
+    
+    /// Get the maximum bytes received images may occupy
+    pub fn get_received_quota_bytes(&self) -> Option<u64> {
+        self.received_quota_bytes
+    }
+    
+    /// Set the file where outbound grants are recorded
+    pub fn set_audit_log_path(&mut self, path: PathBuf) {
+        self.audit_log_path = Some(path);
+    }
+    
+    /// Get the file where outbound grants are recorded
+    pub fn get_audit_log_path(&self) -> Option<&PathBuf> {
+        self.audit_log_path.as_ref()
+    }
+
+    /// Set the directory servers asked to verify grant tokens
+    pub fn set_directory_servers(&mut self, servers: Vec<String>) {
+        self.directory_servers = servers;
+    }
+
+    /// Get the directory servers asked to verify grant tokens
+    pub fn get_directory_servers(&self) -> &[String] {
+        &self.directory_servers
+    }
+
+    /// Keep visibility settings in `path`, loading any saved there
+    pub fn set_visibility_path(&mut self, path: PathBuf) {
+        self.visibility = fs::read_to_string(&path)
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+        self.visibility_path = Some(path);
+        for (image_id, (_, metadata)) in self.images.iter_mut() {
+            if let Some(cap) = self.visibility.get(image_id).and_then(|s| s.max_grant_views) {
+                metadata.max_grant_views = Some(cap);
+            }
+        }
+    }
+
+    /// Who an image is visible to (public unless set otherwise)
+    pub fn get_visibility(&self, image_id: &str) -> VisibilitySetting {
+        let image_id = self.resolve_image_id(image_id).unwrap_or(image_id);
+        self.visibility.get(image_id).cloned().unwrap_or_default()
+    }
+
+    /// Change who an image is visible to, returning the new setting
+    ///
+    /// Making an image link-only issues a fresh share link token, so a link given out
+    /// before it was last made visible some other way stops working.
+    pub fn set_visibility(&mut self, image_id: &str, visibility: ImageVisibility) -> Result<VisibilitySetting> {
+        let image_id = self.resolve_image_id(image_id).context("No such shared image")?.to_string();
+        let current = self.visibility.get(&image_id).cloned().unwrap_or_default();
+        let link_token = match visibility {
+            ImageVisibility::LinkOnly if current.visibility == visibility => current.link_token,
+            ImageVisibility::LinkOnly => Some(uuid::Uuid::new_v4().simple().to_string()),
+            _ => None,
+        };
+        self.save_visibility(image_id, VisibilitySetting { visibility, link_token, ..current })
+    }
+
+    /// Start or stop leaving a blurred preview of an image with the directory
+    pub fn set_escrow_preview(&mut self, image_id: &str, escrow: bool) -> Result<VisibilitySetting> {
+        let image_id = self.resolve_image_id(image_id).context("No such shared image")?.to_string();
+        let current = self.visibility.get(&image_id).cloned().unwrap_or_default();
+        self.save_visibility(image_id, VisibilitySetting { escrow_preview: escrow, ..current })
+    }
+
+    fn save_visibility(&mut self, image_id: String, setting: VisibilitySetting) -> Result<VisibilitySetting> {
+        self.catalog_changed(&image_id);
+        if setting == VisibilitySetting::default() {
+            self.visibility.remove(&image_id);
+        } else {
+            self.visibility.insert(image_id, setting.clone());
+        }
+        if let Some(path) = &self.visibility_path {
+            fs::write(path, serde_json::to_string_pretty(&self.visibility)?)?;
+        }
+        Ok(setting)
+    }
+
+    /// Blurred preview to leave with the directory for an image, if we opted in for it
+    pub fn escrowed_preview(&self, image_id: &str) -> Option<EscrowedPreview> {
+        if !self.get_visibility(image_id).escrow_preview {
+            return None;
+        }
+        let (path, metadata) = self.images.get(self.resolve_image_id(image_id)?)?;
+        let thumbnail = fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| blurred_thumbnail(&data, ThumbnailQuality::Tiny));
+        match thumbnail {
+            Ok(thumbnail) if thumbnail.len() <= MAX_PREVIEW_BYTES => {
+                Some(EscrowedPreview { thumbnail, file_size_kb: metadata.file_size_kb })
+            }
+            Ok(thumbnail) => {
+                warn!("Preview of {} is {} KB, too large to leave with the directory", image_id, thumbnail.len() / 1024);
+                None
+            }
+            Err(e) => {
+                warn!("Failed to make a preview of {}: {:#}", image_id, e);
+                None
+            }
+        }
+    }
+    
+    /// Follow the catalog: receives the ID of every shared image added, changed or removed
+    /// (or whose visibility changed) from now on
+    pub fn watch_catalog(&self) -> tokio::sync::broadcast::Receiver<String> {
+        self.catalog_changes.subscribe()
+    }
+
+    fn catalog_changed(&self, image_id: &str) {
+        // Fails only when nobody is subscribed
+        let _ = self.catalog_changes.send(image_id.to_string());
+    }
+
+    /// Send revocations of our received images to `listener`, e.g. to close an open viewer
+    pub fn set_revocation_listener(&mut self, listener: tokio::sync::mpsc::UnboundedSender<AccessRevoked>) {
+        self.revocation_listener = Some(listener);
+    }
+
+    fn notify_revoked(&self, revoked: AccessRevoked) {
+        if let Some(listener) = &self.revocation_listener {
+            let _ = listener.send(revoked);
+        }
+    }
+    
+    /// Record a re-delivery, or return how long until the next one is allowed
+    pub fn claim_redelivery(&mut self, user: &str, image_id: &str) -> std::result::Result<(), std::time::Duration> {
+        let image_id = self.resolve_image_id(image_id).unwrap_or(image_id);
+        let key = (user.to_string(), image_id.to_string());
+        if let Some(elapsed) = self.redeliveries.get(&key).map(|at| at.elapsed()) {
+            if elapsed < REDELIVERY_COOLDOWN {
+                return Err(REDELIVERY_COOLDOWN - elapsed);
+            }
+        }
+        self.redeliveries.insert(key, std::time::Instant::now());
+        Ok(())
+    }
+    
+    /// Carrier bytes for a request that wouldn't change the image on disk
+    ///
+    /// Only hits when the file is untouched since it was cached and the user is the
+    /// owner or a holder (every grant to anyone else is numbered, so rewrites the carrier).
+    fn cached_grant(&self, image_id: &str, file: &fs::Metadata, user: &str) -> Option<Vec<u8>> {
+        let cached = self.carrier_cache.get(self.resolve_image_id(image_id)?)?;
+        if file.modified().ok()? != cached.modified || file.len() != cached.len {
+            return None;
+        }
+        let unchanged = user == cached.owner || cached.holders.iter().any(|holder| holder == user);
+        unchanged.then(|| cached.png_bytes.clone())
+    }
+
+    fn cache_carrier(&mut self, image_id: &str, path: &Path, permissions: &crate::ImagePermissions, png_bytes: &[u8]) {
+        let Some(image_id) = self.resolve_image_id(image_id).map(str::to_string) else {
+            return;
+        };
+        let Ok(file) = fs::metadata(path) else {
+            return;
+        };
+        let Ok(modified) = file.modified() else {
+            return;
+        };
+        self.carrier_cache.insert(image_id, CachedCarrier {
+            modified,
+            len: file.len(),
+            owner: permissions.owner.clone(),
+            holders: permissions.holders.clone(),
+            png_bytes: png_bytes.to_vec(),
+        });
+    }
+
+    /// Drop the cached carrier for an image (call on any permission change)
+    pub fn invalidate_carrier(&mut self, image_id: &str) {
+        if let Some(image_id) = self.resolve_image_id(image_id).map(str::to_string) {
+            self.carrier_cache.remove(&image_id);
+        }
+    }
+    
+    /// Add an image to the store
+    pub fn add_image(
+        &mut self,
+        image_id: String,
+        file_path: PathBuf,
+        mut metadata: ImageMetadata,
+    ) {
+        self.default_grant_caps.insert(image_id.clone(), metadata.max_grant_views);
+        if let Some(cap) = self.visibility.get(&image_id).and_then(|s| s.max_grant_views) {
+            metadata.max_grant_views = Some(cap);
+        }
+        // A renamed file keeps its embedded ID, so drop the name it was shared under before
+        if let Some(old_name) = self.images.get(&image_id).and_then(|(path, _)| path.file_name()) {
+            self.file_ids.remove(&*old_name.to_string_lossy());
+        }
+        if let Some(file_name) = file_path.file_name() {
+            self.file_ids.insert(file_name.to_string_lossy().into_owned(), image_id.clone());
+        }
+        self.carrier_cache.remove(&image_id);
+        self.search_index.insert(image_id.clone(), &[
+            (&metadata.image_name, NAME_WEIGHT),
+            (metadata.description.as_deref().unwrap_or_default(), DESCRIPTION_WEIGHT),
+        ]);
+        self.catalog_changed(&image_id);
+        self.images.insert(image_id, (file_path, metadata));
+    }
+    
+    /// Resolve an image ID, accepting `owner/image_id` and the file name it was shared under before IDs
+    pub fn resolve_image_id<'a>(&'a self, image_id: &'a str) -> Option<&'a str> {
+        let image_id = match (&self.owner, image_id.split_once('/')) {
+            (Some(owner), Some((qualifier, id))) if qualifier == owner => id,
+            _ => image_id,
+        };
+        if self.images.contains_key(image_id) {
+            return Some(image_id);
+        }
+        self.file_ids.get(image_id).map(String::as_str)
+    }
+    
+    /// Whether a file is already being shared
+    pub fn contains_file(&self, file_name: &str) -> bool {
+        self.file_ids.contains_key(file_name)
+    }
+    
+    /// Get image file path
+    pub fn get_image_path(&self, image_id: &str) -> Option<&PathBuf> {
+        let image_id = self.resolve_image_id(image_id)?;
+        self.images.get(image_id).map(|(path, _)| path)
+    }
+    
+    /// Get the owner's grant cap for an image
+    pub fn get_max_grant_views(&self, image_id: &str) -> Option<u32> {
+        let image_id = self.resolve_image_id(image_id)?;
+        self.images
+            .get(image_id)
+            .and_then(|(_, metadata)| metadata.max_grant_views)
+    }
+    
+    /// Cap the views granted per request for one image, saving it with its visibility
+    ///
+    /// Clearing it goes back to the cap the image was shared with. Returns the cap now in force.
+    pub fn set_max_grant_views(&mut self, image_id: &str, max_views: Option<u32>) -> Result<Option<u32>> {
+        let image_id = self.resolve_image_id(image_id).context("No such shared image")?.to_string();
+        let cap = max_views.or(self.default_grant_caps.get(&image_id).copied().flatten());
+        if let Some((_, metadata)) = self.images.get_mut(&image_id) {
+            metadata.max_grant_views = cap;
+        }
+        let current = self.visibility.get(&image_id).cloned().unwrap_or_default();
+        self.save_visibility(image_id, VisibilitySetting { max_grant_views: max_views, ..current })?;
+        Ok(cap)
+    }
+    
+    /// Get an image's metadata
+    pub fn get_metadata(&self, image_id: &str) -> Option<&ImageMetadata> {
+        let image_id = self.resolve_image_id(image_id)?;
+        self.images.get(image_id).map(|(_, metadata)| metadata)
+    }
+
+    /// Get all image metadata
+    pub fn get_all_metadata(&self) -> Vec<ImageMetadata> {
+        self.images
+            .values()
+            .map(|(_, metadata)| metadata.clone())
+            .collect()
+    }
+    
+    /// Metadata of images whose name or description matches `query`, best match first
+    pub fn search(&self, query: &str) -> Vec<ImageMetadata> {
+        self.search_index
+            .search(query)
+            .into_iter()
+            .filter_map(|(image_id, _)| self.images.get(&image_id).map(|(_, metadata)| metadata.clone()))
+            .collect()
+    }
+    
+    /// Remove an image from the store
+    pub fn remove_image(&mut self, image_id: &str) {
+        let Some(image_id) = self.resolve_image_id(image_id).map(str::to_string) else {
+            return;
+        };
+        self.carrier_cache.remove(&image_id);
+        self.search_index.remove(&image_id);
+        self.default_grant_caps.remove(&image_id);
+        if let Some((path, _)) = self.images.remove(&image_id) {
+            if let Some(file_name) = path.file_name() {
+                self.file_ids.remove(&*file_name.to_string_lossy());
+            }
+        }
+        self.catalog_changed(&image_id);
+    }
+}
+
+// =============================================================================
+// RECEIVED IMAGE INTEGRITY
+// =============================================================================
+
+/// Integrity record kept next to each received image
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedImageRecord {
+    pub from_owner: String,
+    pub image_id: String,
+    /// SHA-256 of the exact bytes received
+    pub sha256: String,
+    pub size_bytes: u64,
+    /// SHA-256 of the embedded image (stable across quota updates), if decodable
+    pub content_sha256: Option<String>,
+    pub received_at: std::time::SystemTime,
+    /// When the viewer last opened the image (None if never viewed)
+    #[serde(default)]
+    pub last_viewed_at: Option<std::time::SystemTime>,
+}
+
+/// Result of re-verifying a received image against its record
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedImageVerification {
+    pub record: ReceivedImageRecord,
+    /// Current SHA-256 of the file on disk
+    pub current_sha256: String,
+    /// File is byte-for-byte what was received
+    pub file_unchanged: bool,
+    /// Embedded image still matches what was received (quota changes allowed)
+    pub content_matches: bool,
+}
+
+/// Hex-encoded SHA-256 of some bytes
+pub fn sha256_hex(data: &[u8]) -> String {
+    use sha2::{Digest, Sha256};
+    Sha256::digest(data)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// SHA-256 of the embedded image inside a carrier, if it can be decoded
+pub fn content_sha256(carrier_bytes: &[u8]) -> Option<String> {
+    let img = image::load_from_memory(carrier_bytes).ok()?;
+    let payload = crate::lsb::decode(&img).ok()??;
+    let combined = crate::CombinedPayload::from_bytes(&payload).ok()?;
+    Some(sha256_hex(&combined.unified_image))
+}
+
+/// Annotations embedded in a carrier, if it can be decoded
+pub fn image_annotations(carrier_bytes: &[u8]) -> Option<crate::ImageAnnotations> {
+    let img = image::load_from_memory(carrier_bytes).ok()?;
+    let payload = crate::lsb::decode(&img).ok()??;
+    let combined = crate::CombinedPayload::from_bytes(&payload).ok()?;
+    Some(combined.annotations)
+}
+
+/// What the owner lets viewers do with a carrier's image, if the carrier can be decoded
+pub fn embedded_view_policy(carrier_bytes: &[u8]) -> Option<crate::ViewPolicy> {
+    let img = image::load_from_memory(carrier_bytes).ok()?;
+    let payload = crate::lsb::decode(&img).ok()??;
+    let combined = crate::CombinedPayload::from_bytes(&payload).ok()?;
+    Some(combined.view_policy())
+}
+
+/// Re-embed a carrier's payload in the current layout, in place
+///
+/// Returns the version it was upgraded from, or None if it was already current.
+pub fn migrate_carrier(path: &Path) -> Result<Option<u8>> {
+    let data = fs::read(path)?;
+    let img = image::load_from_memory(&data)?;
+    let payload = crate::lsb::decode(&img)?.context("No hidden metadata found")?;
+    let version = crate::payload_version(&payload);
+    if version == crate::PAYLOAD_VERSION {
+        return Ok(None);
+    }
+
+    let combined = crate::CombinedPayload::from_bytes(&payload)?;
+    let migrated = crate::lsb::encode(&img, &combined.to_bytes()?)?;
+    migrated.save(path)?;
+    Ok(Some(version))
+}
+
+/// Path of the integrity record for a received image
+pub fn received_record_path(image_path: &std::path::Path) -> PathBuf {
+    let mut name = image_path.as_os_str().to_os_string();
+    name.push(".meta.json");
+    PathBuf::from(name)
+}
+
+/// Save a received image along with its integrity record
+pub fn save_received_image(
+    save_path: &std::path::Path,
+    from_owner: &str,
+    image_id: &str,
+    data: &[u8],
+) -> Result<ReceivedImageRecord> {
+    write_atomic(save_path, data)?;
+
+    let record = ReceivedImageRecord {
+        from_owner: from_owner.to_string(),
+        image_id: image_id.to_string(),
+        sha256: sha256_hex(data),
+        size_bytes: data.len() as u64,
+        content_sha256: content_sha256(data),
+        received_at: std::time::SystemTime::now(),
+        last_viewed_at: None,
+    };
+    fs::write(received_record_path(save_path), serde_json::to_string_pretty(&record)?)?;
+    if let Err(e) = crate::received_index::ReceivedIndex::record_delivery(save_path) {
+        warn!("Failed to index received image {}: {}", save_path.display(), e);
+    }
+
+    Ok(record)
+}
+
+/// What happened when a delivered image was stored
+#[derive(Debug, Clone)]
+pub enum DeliveryOutcome {
+    /// Saved as a new copy
+    Saved { path: PathBuf, record: ReceivedImageRecord },
+    /// Same image already held - views merged into the existing file
+    Merged { path: PathBuf, total_views: u32 },
+    /// A different image already used the name - saved alongside with a suffix
+    KeptBoth { path: PathBuf, existing: PathBuf, record: ReceivedImageRecord },
+}
+
+/// Permissions embedded in a carrier, if it can be decoded
+pub fn embedded_permissions(carrier_bytes: &[u8]) -> Option<crate::ImagePermissions> {
+    Some(embedded_payload(carrier_bytes)?.permissions)
+}
+
+/// The whole payload embedded in a carrier, if it can be decoded
+pub fn embedded_payload(carrier_bytes: &[u8]) -> Option<crate::CombinedPayload> {
+    let img = image::load_from_memory(carrier_bytes).ok()?;
+    let payload = crate::lsb::decode(&img).ok()??;
+    crate::CombinedPayload::from_bytes(&payload).ok()
+}
+
+/// Sequence number of the grant embedded for `user`, if the carrier can be decoded
+pub fn embedded_grant_seq(carrier_bytes: &[u8], user: &str) -> Option<u64> {
+    Some(embedded_permissions(carrier_bytes)?.grant_seq(user))
+}
+
+/// File in the received images folder recording the latest grant applied for each image
+pub const GRANT_LEDGER_FILE: &str = "grant_ledger.json";
+
+/// How a delivered grant compares with the latest one applied for its image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GrantFreshness {
+    /// Newer than anything applied (or unnumbered, from an owner on an older version)
+    New,
+    /// The same grant again, e.g. a retried delivery
+    AlreadyApplied,
+    /// Older than a grant already applied - a replay
+    Stale { applied: u64 },
+}
+
+fn load_grant_ledger(dir: &Path) -> HashMap<String, u64> {
+    fs::read_to_string(dir.join(GRANT_LEDGER_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Compare grant `seq` of `owner`'s image with the latest applied in `dir`
+pub fn grant_freshness(dir: &Path, owner: &str, image_id: &str, seq: u64) -> GrantFreshness {
+    if seq == 0 {
+        return GrantFreshness::New;
+    }
+    let key = crate::directory_service::qualified_image_id(owner, image_id);
+    match load_grant_ledger(dir).get(&key).copied() {
+        Some(applied) if seq < applied => GrantFreshness::Stale { applied },
+        Some(applied) if seq == applied => GrantFreshness::AlreadyApplied,
+        _ => GrantFreshness::New,
+    }
+}
+
+/// Remember that grant `seq` of `owner`'s image was applied
+pub fn record_applied_grant(dir: &Path, owner: &str, image_id: &str, seq: u64) -> Result<()> {
+    let mut ledger = load_grant_ledger(dir);
+    let applied = ledger.entry(crate::directory_service::qualified_image_id(owner, image_id)).or_insert(0);
+    *applied = (*applied).max(seq);
+    fs::write(dir.join(GRANT_LEDGER_FILE), serde_json::to_string_pretty(&ledger)?)?;
+    Ok(())
+}
+
+/// What checking a received image's grant with its owner changed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GrantReconciliation {
+    /// Our copy already matches the owner's
+    UpToDate,
+    /// The owner revoked our views and the revocation never reached us; our copy now has none
+    Revoked,
+    /// The owner changed our quota since the grant our copy holds; our copy now has `views`
+    Updated { views: u32 },
+    /// The owner no longer shares the image (or can't read their copy); nothing was changed
+    NotShared,
+}
+
+/// One user's quota before and after a permission change (None = no entry)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaChange {
+    pub user: String,
+    pub before: Option<u32>,
+    pub after: Option<u32>,
+}
+
+/// The quota map a permission change would leave in a carrier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QuotaPreview {
+    pub owner: String,
+    /// Every user's quota after the change
+    pub quotas: std::collections::BTreeMap<String, u32>,
+    /// Only the users whose quota would change
+    pub changes: Vec<QuotaChange>,
+}
+
+/// Work out what setting `target_user`'s quota to `new_quota` would do, without writing anything
+pub fn preview_quota_change(carrier_bytes: &[u8], target_user: &str, new_quota: u32) -> Result<QuotaPreview> {
+    let permissions = embedded_permissions(carrier_bytes)
+        .context("No readable permissions embedded in image")?;
+    let before = permissions.quotas.get(target_user).copied();
+    let mut quotas: std::collections::BTreeMap<String, u32> = permissions.quotas.into_iter().collect();
+    quotas.insert(target_user.to_string(), new_quota);
+
+    let changes = if before == Some(new_quota) {
+        Vec::new()
+    } else {
+        vec![QuotaChange { user: target_user.to_string(), before, after: Some(new_quota) }]
+    };
+    Ok(QuotaPreview { owner: permissions.owner, quotas, changes })
+}
+
+/// Add or remove `user` as a holder of one of `owner`'s carriers, in place
+///
+/// Returns whether the holder list changed.
+pub fn set_image_holder(path: &Path, owner: &str, user: &str, holder: bool) -> Result<bool> {
+    let data = fs::read(path)?;
+    let img = image::load_from_memory(&data)?;
+    let payload = crate::lsb::decode(&img)?.context("No hidden metadata found")?;
+    let mut combined = crate::CombinedPayload::from_bytes(&payload)?;
+    if combined.permissions.owner != owner {
+        bail!("You are not the owner of this image. Owner is: {}", combined.permissions.owner);
+    }
+    if user == owner {
+        bail!("The owner can't be a holder of their own image");
+    }
+
+    let holders = &mut combined.permissions.holders;
+    if holder == holders.iter().any(|h| h == user) {
+        return Ok(false);
+    }
+    if holder {
+        holders.push(user.to_string());
+    } else {
+        holders.retain(|h| h != user);
+    }
+    crate::lsb::encode(&img, &combined.to_bytes()?)?.save(path)?;
+    Ok(true)
+}
+
+/// Change what viewers may do with one of `owner`'s carriers, in place
+///
+/// Returns whether the policy changed.
+pub fn set_view_policy(path: &Path, owner: &str, policy: crate::ViewPolicy) -> Result<bool> {
+    let data = fs::read(path)?;
+    let img = image::load_from_memory(&data)?;
+    let payload = crate::lsb::decode(&img)?.context("No hidden metadata found")?;
+    let mut combined = crate::CombinedPayload::from_bytes(&payload)?;
+    if combined.permissions.owner != owner {
+        bail!("You are not the owner of this image. Owner is: {}", combined.permissions.owner);
+    }
+
+    if combined.view_policy() == policy {
+        return Ok(false);
+    }
+    combined.set_view_policy(policy);
+    crate::lsb::encode(&img, &combined.to_bytes()?)?.save(path)?;
+    Ok(true)
+}
+
+/// Views the embedded permissions grant a user, if the carrier can be decoded
+fn embedded_quota(carrier_bytes: &[u8], user: &str) -> Option<u32> {
+    embedded_permissions(carrier_bytes)?.quotas.get(user).copied()
+}
+
+/// First free `name_N.ext` path next to an existing file
+fn next_free_path(path: &std::path::Path) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    (2..)
+        .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .expect("unbounded suffix search")
+}
+
+/// Store a delivered image without clobbering an earlier grant for the same name
+pub fn store_delivered_image(
+    save_path: &std::path::Path,
+    from_owner: &str,
+    image_id: &str,
+    data: &[u8],
+    viewer: &str,
+) -> Result<DeliveryOutcome> {
+    if !save_path.exists() {
+        let record = save_received_image(save_path, from_owner, image_id, data)?;
+        return Ok(DeliveryOutcome::Saved { path: save_path.to_path_buf(), record });
+    }
+
+    let existing_data = fs::read(save_path)?;
+    let same_image = match (content_sha256(&existing_data), content_sha256(data)) {
+        (Some(existing), Some(incoming)) => existing == incoming,
+        _ => sha256_hex(&existing_data) == sha256_hex(data),
+    };
+
+    if same_image {
+        let remaining = embedded_quota(&existing_data, viewer).unwrap_or(0);
+        let granted = embedded_quota(data, viewer).unwrap_or(0);
+        let total_views = remaining.saturating_add(granted);
+        update_local_image_permissions(&save_path.to_path_buf(), viewer, total_views)?;
+        return Ok(DeliveryOutcome::Merged { path: save_path.to_path_buf(), total_views });
+    }
+
+    let path = next_free_path(save_path);
+    let record = save_received_image(&path, from_owner, image_id, data)?;
+    Ok(DeliveryOutcome::KeptBoth { path, existing: save_path.to_path_buf(), record })
+}
+
+/// Load the integrity record for a received image, if one exists
+pub fn load_received_record(image_path: &std::path::Path) -> Option<ReceivedImageRecord> {
+    let data = fs::read_to_string(received_record_path(image_path)).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+/// Re-verify a received image against its stored integrity record
+pub fn verify_received_image(image_path: &std::path::Path) -> Result<ReceivedImageVerification> {
+    let record = load_received_record(image_path)
+        .with_context(|| format!("No integrity record for {}", image_path.display()))?;
+    let data = fs::read(image_path)?;
+
+    let current_sha256 = sha256_hex(&data);
+    let file_unchanged = current_sha256 == record.sha256;
+    let content_matches = match &record.content_sha256 {
+        Some(expected) => content_sha256(&data).as_ref() == Some(expected),
+        None => file_unchanged,
+    };
+
+    Ok(ReceivedImageVerification {
+        record,
+        current_sha256,
+        file_unchanged,
+        content_matches,
+    })
+}
+
+/// Note in a received image's integrity record that it has just been viewed
+pub fn mark_received_viewed(image_path: &std::path::Path) -> Result<()> {
+    let Some(mut record) = load_received_record(image_path) else {
+        return Ok(());
+    };
+    record.last_viewed_at = Some(std::time::SystemTime::now());
+    fs::write(received_record_path(image_path), serde_json::to_string_pretty(&record)?)?;
+    Ok(())
+}
+
+/// Securely delete every copy of an owner's image we received, with its integrity record
+///
+/// Matches any copy whose record names the image, whatever name it was saved under.
+/// Returns how many copies were deleted.
+pub fn wipe_received_image(received_dir: Option<&Path>, from_owner: &str, image_id: &str) -> Result<usize> {
+    let dir = received_dir.unwrap_or(Path::new("."));
+    // Report a missing folder rather than wiping nothing
+    fs::read_dir(dir)?;
+
+    let mut wiped = 0;
+    for path in find_received_copies(dir, from_owner, image_id) {
+        secure_delete(&path)?;
+        let record_path = received_record_path(&path);
+        if record_path.exists() {
+            fs::remove_file(record_path)?;
+        }
+        wiped += 1;
+    }
+    Ok(wiped)
+}
+
+/// Overwrite a file with zeros before removing it
+fn secure_delete(path: &Path) -> Result<()> {
+    use std::io::Write;
+    let len = fs::metadata(path)?.len() as usize;
+    let mut file = fs::OpenOptions::new().write(true).open(path)?;
+    file.write_all(&vec![0u8; len])?;
+    file.sync_all()?;
+    drop(file);
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+// =============================================================================
+// RECEIVED FILE NAMES
+// =============================================================================
+
+/// File in the received images folder holding the name policy for new deliveries
+pub const RECEIVED_NAME_POLICY_FILE: &str = "received_names.json";
+
+/// How delivered images are named on disk
+///
+/// Whatever the name, the integrity record next to each copy says whose image it is, so
+/// lookups go through the record rather than the name.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReceivedNamePolicy {
+    /// `from_{owner}_{image_id}`
+    #[default]
+    OwnerPrefixed,
+    /// The owner's image ID alone
+    OriginalName,
+    /// A hash of the owner and image ID, so the folder says nothing about who shared what
+    HashBased,
+}
+
+impl ReceivedNamePolicy {
+    /// Parse `owner-prefixed`, `original-name` or `hash-based`
+    pub fn parse(text: &str) -> Result<Self> {
+        match text.trim().to_ascii_lowercase().replace(['-', '_', ' '], "").as_str() {
+            "owner" | "ownerprefixed" => Ok(ReceivedNamePolicy::OwnerPrefixed),
+            "original" | "originalname" => Ok(ReceivedNamePolicy::OriginalName),
+            "hash" | "hashbased" => Ok(ReceivedNamePolicy::HashBased),
+            _ => bail!("Expected owner-prefixed, original-name or hash-based, got '{}'", text.trim()),
+        }
+    }
+
+    /// File name a new delivery of `owner`'s image gets
+    pub fn file_name(self, owner: &str, image_id: &str) -> String {
+        let hashed = || format!("{}.png", &sha256_hex(crate::directory_service::qualified_image_id(owner, image_id).as_bytes())[..16]);
+        match self {
+            ReceivedNamePolicy::OwnerPrefixed => safe_file_name(&format!("from_{}_{}", owner, image_id)).unwrap_or_else(hashed),
+            ReceivedNamePolicy::OriginalName => safe_file_name(image_id)
+                .map(|name| if crate::fsscan::is_image_file(Path::new(&name)) { name } else { format!("{}.png", name) })
+                .unwrap_or_else(hashed),
+            ReceivedNamePolicy::HashBased => hashed(),
+        }
+    }
+}
+
+impl std::fmt::Display for ReceivedNamePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ReceivedNamePolicy::OwnerPrefixed => "owner-prefixed",
+            ReceivedNamePolicy::OriginalName => "original name",
+            ReceivedNamePolicy::HashBased => "hash-based",
+        })
+    }
+}
+
+/// A peer-supplied name made safe to create in the received folder (None if nothing usable is left)
+fn safe_file_name(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') || c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim_start_matches('.').trim();
+    (!name.is_empty() && !name.ends_with(".meta.json")).then(|| name.to_string())
+}
+
+/// Name policy for new deliveries into `dir` (owner-prefixed if never set)
+pub fn load_name_policy(dir: &Path) -> ReceivedNamePolicy {
+    fs::read_to_string(dir.join(RECEIVED_NAME_POLICY_FILE))
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Set the name policy for new deliveries into `dir`; copies already held keep their names
+pub fn save_name_policy(dir: &Path, policy: ReceivedNamePolicy) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join(RECEIVED_NAME_POLICY_FILE), serde_json::to_string_pretty(&policy)?)?;
+    Ok(())
+}
+
+/// Every copy of `owner`'s image held in `dir`, in name order
+///
+/// Copies are found by their integrity record; a copy saved before records existed is
+/// found by its `from_{owner}_{image_id}` name.
+pub fn find_received_copies(dir: &Path, owner: &str, image_id: &str) -> Vec<PathBuf> {
+    let legacy_name = format!("from_{}_{}", owner, image_id);
+    let mut copies: Vec<PathBuf> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() && !path.to_string_lossy().ends_with(".meta.json"))
+        .filter(|path| match load_received_record(path) {
+            Some(record) => record.from_owner == owner && record.image_id == image_id,
+            None => path.file_name().is_some_and(|name| *name == *legacy_name),
+        })
+        .collect();
+    copies.sort();
+    copies
+}
+
+/// Where a delivery of `owner`'s image goes in `received_dir` (the current directory if None)
+///
+/// The copy already held, if any, so updates and merges land on it; otherwise a new name
+/// under the folder's policy, suffixed if another image already has it.
+pub fn received_image_path(received_dir: Option<&Path>, owner: &str, image_id: &str) -> PathBuf {
+    let dir = received_dir.unwrap_or(Path::new("."));
+    if let Some(existing) = find_received_copies(dir, owner, image_id).into_iter().next() {
+        return existing;
+    }
+    let path = dir.join(load_name_policy(dir).file_name(owner, image_id));
+    if path.exists() { next_free_path(&path) } else { path }
+}
+
+// =============================================================================
+// RECEIVED STORAGE QUOTA
+// =============================================================================
+
+/// Whether a received image may be evicted to make room, and in what order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum EvictionClass {
+    /// No views left for us - evicted first
+    Exhausted,
+    /// Viewed at least once - evicted oldest-viewed first
+    Viewed,
+    /// Never viewed - only removed when the user deletes it
+    Unviewed,
+}
+
+/// A received image counted towards the storage quota
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedFileUsage {
+    pub path: PathBuf,
+    pub from_owner: Option<String>,
+    /// Size of the image plus its integrity record
+    pub size_bytes: u64,
+    pub class: EvictionClass,
+    pub last_viewed_at: Option<std::time::SystemTime>,
+}
+
+/// How much space received images take against the quota
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StorageUsage {
+    pub used_bytes: u64,
+    pub quota_bytes: Option<u64>,
+    /// Bytes that could be reclaimed automatically (exhausted and viewed images)
+    pub evictable_bytes: u64,
+    pub images: Vec<ReceivedFileUsage>,
+    /// Free space on the disk holding the received images (None if unknown)
+    #[serde(default)]
+    pub disk_available_bytes: Option<u64>,
+    /// Deliveries are being refused until disk space frees up
+    #[serde(default)]
+    pub read_only: bool,
+}
+
+// =============================================================================
+// LOW DISK SPACE SAFE MODE
+// =============================================================================
+
+/// Environment variable setting the free space (in MB) kept in reserve when storing deliveries
+pub const MIN_FREE_DISK_ENV: &str = "P2P_MIN_FREE_DISK_MB";
+
+/// Default free space kept in reserve
+const DEFAULT_MIN_FREE_DISK_MB: u64 = 64;
+
+/// Set while the disk is too full to accept deliveries
+static STORAGE_READ_ONLY: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
+/// Not enough free disk space to store something
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskShortfall {
+    pub available_bytes: u64,
+    pub needed_bytes: u64,
+}
+
+impl std::fmt::Display for DiskShortfall {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Low disk space: {} MB free, {} MB needed (incoming image plus reserve)",
+            self.available_bytes / (1024 * 1024),
+            self.needed_bytes.div_ceil(1024 * 1024)
+        )
+    }
+}
+
+impl DiskShortfall {
+    pub fn into_message(self) -> P2PMessage {
+        P2PMessage::StorageFull {
+            message: self.to_string(),
+            available_bytes: self.available_bytes,
+            needed_bytes: self.needed_bytes,
+        }
+    }
+}
+
+fn min_free_disk_bytes() -> u64 {
+    std::env::var(MIN_FREE_DISK_ENV)
+        .ok()
+        .and_then(|mb| mb.parse::<u64>().ok())
+        .unwrap_or(DEFAULT_MIN_FREE_DISK_MB)
+        * 1024
+        * 1024
+}
+
+/// Bytes available to us on the filesystem holding `path`, if it can be determined
+#[cfg(unix)]
+pub fn available_disk_space(path: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = if path.as_os_str().is_empty() { Path::new(".") } else { path };
+    let c_path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stats: libc::statvfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stats) } != 0 {
+        return None;
+    }
+    Some(stats.f_bavail as u64 * stats.f_frsize as u64)
+}
+
+/// Free space is unknown off Unix, so disk checks let writes through
+#[cfg(not(unix))]
+pub fn available_disk_space(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// Whether the local store is read-only because the disk filled up
+pub fn storage_read_only() -> bool {
+    STORAGE_READ_ONLY.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Check `incoming_bytes` can be written under `dir` while keeping the reserve free
+///
+/// A shortfall puts the store in read-only mode; it leaves that mode only once
+/// twice the reserve is free, so it doesn't flap at the threshold.
+pub fn reserve_disk_space(dir: &Path, incoming_bytes: u64) -> std::result::Result<(), DiskShortfall> {
+    let Some(available_bytes) = available_disk_space(dir) else {
+        return Ok(());
+    };
+    let reserve = min_free_disk_bytes();
+    let read_only = storage_read_only();
+    let needed_bytes = incoming_bytes + if read_only { 2 * reserve } else { reserve };
+
+    if available_bytes < needed_bytes {
+        if !read_only {
+            warn!("Disk nearly full ({} MB free) - refusing deliveries until space frees up",
+                  available_bytes / (1024 * 1024));
+            STORAGE_READ_ONLY.store(true, std::sync::atomic::Ordering::Relaxed);
+        }
+        return Err(DiskShortfall { available_bytes, needed_bytes });
+    }
+    if read_only {
+        info!("Disk space recovered ({} MB free) - accepting deliveries again", available_bytes / (1024 * 1024));
+        STORAGE_READ_ONLY.store(false, std::sync::atomic::Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Write `data` to a hidden file beside `path` and rename it into place,
+/// so a failed write (e.g. disk full) never leaves a truncated file behind
+pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let file_name = path.file_name().context("Path has no file name")?.to_string_lossy();
+    let tmp = path.with_file_name(format!(".{}.partial", file_name));
+    if let Err(e) = fs::write(&tmp, data).and_then(|()| fs::rename(&tmp, path)) {
+        let _ = fs::remove_file(&tmp);
+        return Err(e).with_context(|| format!("Failed to write {}", path.display()));
+    }
+    Ok(())
+}
+
+fn file_size(path: &std::path::Path) -> u64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0)
+}
+
+/// Measure the received images directory from `viewer`'s point of view
+pub fn received_storage_usage(dir: &std::path::Path, viewer: &str, quota_bytes: Option<u64>) -> StorageUsage {
+    let mut used_bytes = 0;
+    let mut images = Vec::new();
+
+    for entry in fs::read_dir(dir).into_iter().flatten().flatten() {
+        let path = entry.path();
+        if !path.is_file() || path.to_string_lossy().ends_with(".meta.json") {
+            continue;
+        }
+
+        // The directory may also hold our own images (the CLI saves into the working directory)
+        let record = load_received_record(&path);
+        let from_peer = path.file_name().is_some_and(|name| name.to_string_lossy().starts_with("from_"));
+        if record.is_none() && !from_peer {
+            continue;
+        }
+
+        let size_bytes = file_size(&path) + file_size(&received_record_path(&path));
+        used_bytes += size_bytes;
+        let last_viewed_at = record.as_ref().and_then(|r| r.last_viewed_at);
+        let quota = fs::read(&path).ok().and_then(|data| embedded_quota(&data, viewer));
+        let class = match (quota, last_viewed_at) {
+            (Some(0), _) => EvictionClass::Exhausted,
+            (_, Some(_)) => EvictionClass::Viewed,
+            (_, None) => EvictionClass::Unviewed,
+        };
+
+        images.push(ReceivedFileUsage {
+            size_bytes,
+            from_owner: record.map(|r| r.from_owner),
+            path,
+            class,
+            last_viewed_at,
+        });
+    }
+
+    // Eviction order: exhausted, then least recently viewed, then un-viewed
+    images.sort_by_key(|image| (image.class, image.last_viewed_at));
+    let evictable_bytes = images.iter()
+        .filter(|image| image.class != EvictionClass::Unviewed)
+        .map(|image| image.size_bytes)
+        .sum();
+
+    StorageUsage {
+        used_bytes,
+        quota_bytes,
+        evictable_bytes,
+        images,
+        disk_available_bytes: available_disk_space(dir),
+        read_only: storage_read_only(),
+    }
+}
+
+/// Evict exhausted and least recently viewed images until `incoming_bytes` more fit under the quota
+///
+/// Un-viewed images and `keep` (the file about to be replaced, if any) are never evicted;
+/// fails without deleting anything if that isn't enough.
+pub fn make_room_for_received(
+    dir: &std::path::Path,
+    viewer: &str,
+    quota_bytes: u64,
+    incoming_bytes: u64,
+    keep: &std::path::Path,
+) -> Result<Vec<PathBuf>> {
+    let usage = received_storage_usage(dir, viewer, Some(quota_bytes));
+    let needed = (usage.used_bytes + incoming_bytes).saturating_sub(quota_bytes);
+    if needed == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut freed = 0;
+    let victims: Vec<&ReceivedFileUsage> = usage.images.iter()
+        .filter(|image| image.class != EvictionClass::Unviewed && image.path != keep)
+        .take_while(|image| {
+            let take = freed < needed;
+            freed += image.size_bytes;
+            take
+        })
+        .collect();
+
+    let freed: u64 = victims.iter().map(|image| image.size_bytes).sum();
+    if freed < needed {
+        bail!(
+            "Received storage quota exceeded ({} of {} KB used); delete un-viewed images to make room",
+            usage.used_bytes / 1024,
+            quota_bytes / 1024
+        );
+    }
+
+    let mut evicted = Vec::new();
+    for image in victims {
+        fs::remove_file(&image.path)?;
+        let _ = fs::remove_file(received_record_path(&image.path));
+        info!("Evicted {} ({:?}) to stay under the received storage quota", image.path.display(), image.class);
+        evicted.push(image.path.clone());
+    }
+    Ok(evicted)
+}
+
+// =============================================================================
+// ACCESS DENIAL TELEMETRY
+// =============================================================================
+
+/// Default file name for an owner's aggregated denial stats
+pub const ACCESS_DENIAL_STATS_FILE: &str = "access_denials.json";
+
+/// Why a view attempt was denied
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum DenialReason {
+    /// The viewer had a grant but no views left (exhausted or revoked)
+    NoViewsLeft,
+    /// The viewer was never granted access
+    NotAuthorized,
+    /// The image's expiry date has passed
+    Expired,
+    /// The viewer only holds the image for safekeeping
+    HolderOnly,
 }
 
-/// Metadata about an available image
+/// A single denied view attempt, reported by the viewer to the owner
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ImageMetadata {
-    pub image_id: String,
-    pub image_name: String,
+pub struct AccessDenial {
     pub owner: String,
-    pub description: Option<String>,
-    pub file_size_kb: u64,
+    pub image_id: String,
+    pub viewer: String,
+    pub reason: DenialReason,
+    pub timestamp: std::time::SystemTime,
+}
+
+/// Denial counts for one of the owner's images
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImageDenialStats {
+    pub total: u32,
+    pub by_viewer: HashMap<String, u32>,
+    pub last_denied_at: Option<std::time::SystemTime>,
+}
+
+/// Work out whether `viewer` would be denied, before the view is attempted
+///
+/// Returns None for owners, viewers with views left, and files that aren't protected images.
+pub fn access_denial_for(image_path: &std::path::Path, viewer: &str) -> Option<AccessDenial> {
+    let data = fs::read(image_path).ok()?;
+    let img = image::load_from_memory(&data).ok()?;
+    let payload = crate::lsb::decode(&img).ok()??;
+    let combined = crate::CombinedPayload::from_bytes(&payload).ok()?;
+    let expired = combined.is_expired();
+    let permissions = combined.permissions;
+
+    if permissions.owner == viewer {
+        return None;
+    }
+    let reason = match permissions.quotas.get(viewer) {
+        _ if permissions.is_holder(viewer) => DenialReason::HolderOnly,
+        Some(_) if expired => DenialReason::Expired,
+        Some(views) if *views > 0 => return None,
+        Some(_) => DenialReason::NoViewsLeft,
+        None => DenialReason::NotAuthorized,
+    };
+
+    // Prefer the owner's image ID from the integrity record, else strip the from_{owner}_ prefix
+    let image_id = load_received_record(image_path)
+        .map(|record| record.image_id)
+        .unwrap_or_else(|| {
+            let name = image_path.file_name().unwrap_or_default().to_string_lossy();
+            let prefix = format!("from_{}_", permissions.owner);
+            name.strip_prefix(&prefix).unwrap_or(&name).to_string()
+        });
+
+    Some(AccessDenial {
+        owner: permissions.owner,
+        image_id,
+        viewer: viewer.to_string(),
+        reason,
+        timestamp: std::time::SystemTime::now(),
+    })
+}
+
+/// Load an owner's aggregated denial stats (image_id -> stats)
+pub fn load_access_denial_stats(path: &std::path::Path) -> HashMap<String, ImageDenialStats> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default()
+}
+
+/// Fold denials into an owner's stats file
+pub fn record_access_denials(path: &std::path::Path, denials: &[AccessDenial]) -> Result<()> {
+    let mut stats = load_access_denial_stats(path);
+    for denial in denials {
+        let entry = stats.entry(denial.image_id.clone()).or_default();
+        entry.total += 1;
+        *entry.by_viewer.entry(denial.viewer.clone()).or_default() += 1;
+        if entry.last_denied_at.is_none_or(|last| denial.timestamp > last) {
+            entry.last_denied_at = Some(denial.timestamp);
+        }
+    }
+    fs::write(path, serde_json::to_string_pretty(&stats)?)?;
+    Ok(())
 }
 
 // =============================================================================
-// P2P REQUEST HANDLER
+// DELIVERY LANES
 // =============================================================================
 
-/// Information about images that this peer owns
-pub struct PeerImageStore {
-    /// Map of image_id -> (file_path, metadata)
-    images: HashMap<String, (PathBuf, ImageMetadata)>,
-    /// Directory where received images should be saved
-    received_images_dir: Option<PathBuf>,
+/// Image transfers processed at once; further transfers queue behind these
+const MAX_BULK_TRANSFERS: usize = 2;
+
+/// Frames at least this large can only be image transfers
+const LARGE_FRAME_BYTES: usize = 1024 * 1024;
+
+/// How long the server keeps a connection open waiting for the next request
+const SERVER_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Which lane an incoming message is processed on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Lane {
+    /// Small messages (permission updates, pings, notices), handled as soon as they arrive
+    Control,
+    /// Image transfers and decodes, limited to `MAX_BULK_TRANSFERS` at a time
+    Bulk,
 }
 
-impl PeerImageStore {
-    pub fn new() -> Self {
-        Self {
-            images: HashMap::new(),
-            received_images_dir: None,
+impl Lane {
+    fn of(message: &P2PMessage) -> Self {
+        match message {
+            P2PMessage::ImageRequest { .. }
+            | P2PMessage::DeliverImage { .. }
+            | P2PMessage::DeliverBundle { .. }
+            | P2PMessage::ThumbnailRequest { .. }
+            | P2PMessage::HaveImage { .. }
+            | P2PMessage::RedeliverRequest { .. }
+            | P2PMessage::VerifyGrant { .. }
+            | P2PMessage::RedeemPairing { .. } => Lane::Bulk,
+            _ => Lane::Control,
         }
     }
-    
-    /// Set the directory where received images should be saved
-    pub fn set_received_images_dir(&mut self, dir: PathBuf) {
-        self.received_images_dir = Some(dir);
+}
+
+/// Whether a message moves image data, and so is deferred once the daily bandwidth cap is hit
+fn is_transfer(message: &P2PMessage) -> bool {
+    matches!(
+        message,
+        P2PMessage::ImageRequest { .. }
+            | P2PMessage::DeliverImage { .. }
+            | P2PMessage::DeliverBundle { .. }
+            | P2PMessage::ThumbnailRequest { .. }
+            | P2PMessage::RedeliverRequest { .. }
+            | P2PMessage::RedeemPairing { .. }
+    )
+}
+
+/// Transfers being sent or served by this process right now
+static ACTIVE_TRANSFERS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Counts a transfer as active until dropped
+struct ActiveTransfer;
+
+impl ActiveTransfer {
+    fn start() -> Self {
+        ACTIVE_TRANSFERS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        ActiveTransfer
     }
-    
-    /// Get the directory where received images should be saved
-    pub fn get_received_images_dir(&self) -> Option<&PathBuf> {
-        self.received_images_dir.as_ref()
+}
+
+impl Drop for ActiveTransfer {
+    fn drop(&mut self) {
+        ACTIVE_TRANSFERS.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
     }
-    
-    /// Add an image to the store
-    pub fn add_image(
-        &mut self,
-        image_id: String,
-        file_path: PathBuf,
-        metadata: ImageMetadata,
-    ) {
-        self.images.insert(image_id, (file_path, metadata));
+}
+
+/// Image transfers we're sending or serving right now (an owner fetching from their own
+/// server counts on both sides)
+pub fn active_transfers() -> usize {
+    ACTIVE_TRANSFERS.load(std::sync::atomic::Ordering::Relaxed)
+}
+
+/// Connections served at once; further connections wait in the accept queue
+const MAX_CONCURRENT_CONNECTIONS: usize = 64;
+
+/// Connections allowed to wait for a free slot; beyond this they're turned away at once
+const MAX_QUEUED_CONNECTIONS: usize = 128;
+
+/// How long a queued connection waits for a free slot before it's turned away
+const ACCEPT_QUEUE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// What a turned-away client is told to wait before trying again
+const BUSY_RETRY_AFTER: std::time::Duration = std::time::Duration::from_secs(10);
+
+/// How long turning a connection away may take, reading its request included
+const BUSY_ANSWER_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Largest request read off a turned-away connection before answering it
+const BUSY_DRAIN_LIMIT: usize = 64 * 1024;
+
+/// Load on the P2P server since the process started
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct ServerLoadStats {
+    /// Connections being served right now
+    pub active: usize,
+    /// Connections waiting for a free slot right now
+    pub queued: usize,
+    /// Most connections served at once
+    pub peak_active: usize,
+    pub accepted: u64,
+    /// Turned away with `Busy`, queue full or timed out waiting
+    pub rejected_busy: u64,
+    /// Of those rejected, how many waited out `ACCEPT_QUEUE_TIMEOUT` first
+    pub queue_timeouts: u64,
+    pub max_concurrent: usize,
+    pub max_queued: usize,
+}
+
+static ACTIVE_CONNECTIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+static QUEUED_CONNECTIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+static PEAK_CONNECTIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+static ACCEPTED_CONNECTIONS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static REJECTED_CONNECTIONS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+static QUEUE_TIMEOUTS: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Counts a connection as served until dropped, holding its slot
+struct ActiveConnection {
+    _slot: tokio::sync::OwnedSemaphorePermit,
+}
+
+impl ActiveConnection {
+    fn start(slot: tokio::sync::OwnedSemaphorePermit) -> Self {
+        let active = ACTIVE_CONNECTIONS.fetch_add(1, std::sync::atomic::Ordering::Relaxed) + 1;
+        PEAK_CONNECTIONS.fetch_max(active, std::sync::atomic::Ordering::Relaxed);
+        ActiveConnection { _slot: slot }
     }
-    
-    /// Get image file path
-    pub fn get_image_path(&self, image_id: &str) -> Option<&PathBuf> {
-        self.images.get(image_id).map(|(path, _)| path)
+}
+
+impl Drop for ActiveConnection {
+    fn drop(&mut self) {
+        ACTIVE_CONNECTIONS.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
     }
-    
-    /// Get all image metadata
-    pub fn get_all_metadata(&self) -> Vec<ImageMetadata> {
-        self.images
-            .values()
-            .map(|(_, metadata)| metadata.clone())
-            .collect()
+}
+
+/// Connection counters for the P2P server in this process
+pub fn p2p_server_stats() -> ServerLoadStats {
+    use std::sync::atomic::Ordering::Relaxed;
+    ServerLoadStats {
+        active: ACTIVE_CONNECTIONS.load(Relaxed),
+        queued: QUEUED_CONNECTIONS.load(Relaxed),
+        peak_active: PEAK_CONNECTIONS.load(Relaxed),
+        accepted: ACCEPTED_CONNECTIONS.load(Relaxed),
+        rejected_busy: REJECTED_CONNECTIONS.load(Relaxed),
+        queue_timeouts: QUEUE_TIMEOUTS.load(Relaxed),
+        max_concurrent: MAX_CONCURRENT_CONNECTIONS,
+        max_queued: MAX_QUEUED_CONNECTIONS,
     }
-    
-    /// Remove an image from the store
-    pub fn remove_image(&mut self, image_id: &str) {
-        self.images.remove(image_id);
+}
+
+/// Answer a connection we have no room for with `Busy` and close it
+///
+/// The request is read first (unless it's large): closing a socket with unread data
+/// resets the connection, and the client would never see why.
+async fn reject_busy(mut stream: TcpStream, addr: std::net::SocketAddr, reason: &str) {
+    REJECTED_CONNECTIONS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    warn!("Turned away P2P connection from {}: {}", addr, reason);
+    let busy = P2PMessage::Busy {
+        message: format!("Peer is busy ({})", reason),
+        retry_after_secs: BUSY_RETRY_AFTER.as_secs(),
+    };
+    let answer = async {
+        let len = stream.read_u32().await? as usize;
+        if len <= BUSY_DRAIN_LIMIT {
+            let mut request = vec![0u8; len];
+            stream.read_exact(&mut request).await?;
+        }
+        let body = serde_json::to_vec(&busy)?;
+        stream.write_u32(body.len() as u32).await?;
+        stream.write_all(&body).await?;
+        stream.flush().await?;
+        Ok::<_, anyhow::Error>(())
+    };
+    let _ = tokio::time::timeout(BUSY_ANSWER_TIMEOUT, answer).await;
+}
+
+/// Refusal sent instead of serving a transfer while the daily bandwidth cap is hit
+fn deferred_response(message: &P2PMessage, reason: String) -> Option<P2PMessage> {
+    match message {
+        P2PMessage::ImageRequest { .. } | P2PMessage::RedeliverRequest { .. } => Some(P2PMessage::ImageResponse {
+            success: false,
+            message: reason,
+            encrypted_image: None,
+        }),
+        P2PMessage::DeliverImage { .. } => Some(P2PMessage::DeliverImageResponse {
+            success: false,
+            message: reason,
+            sha256: None,
+            size_bytes: 0,
+        }),
+        P2PMessage::ThumbnailRequest { .. } => Some(P2PMessage::ThumbnailResponse {
+            success: false,
+            message: reason,
+            thumbnail: None,
+        }),
+        P2PMessage::RedeemPairing { .. } => Some(P2PMessage::RedeemPairingResponse {
+            success: false,
+            message: reason,
+            grant: None,
+        }),
+        _ => None,
     }
 }
 
@@ -168,26 +2005,64 @@ impl PeerImageStore {
 // P2P SERVER
 // =============================================================================
 
+/// Address the P2P server listens on for a port (every interface)
+pub fn p2p_bind_address(port: u16) -> String {
+    format!("0.0.0.0:{}", port)
+}
+
 /// Start a P2P server to handle incoming requests from other peers
 pub async fn start_p2p_server(
     port: u16,
     username: String,
     image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
 ) -> Result<()> {
-    let bind_addr = format!("0.0.0.0:{}", port);
+    let bind_addr = p2p_bind_address(port);
     let listener = TcpListener::bind(&bind_addr).await?;
     info!("P2P server for user '{}' listening on {}", username, bind_addr);
     
+    let bulk_lane = std::sync::Arc::new(Semaphore::new(MAX_BULK_TRANSFERS));
+    let slots = std::sync::Arc::new(Semaphore::new(MAX_CONCURRENT_CONNECTIONS));
+    image_store.write().await.set_owner(username.clone());
+    
     loop {
         match listener.accept().await {
             Ok((stream, addr)) => {
+                use std::sync::atomic::Ordering::Relaxed;
+                ACCEPTED_CONNECTIONS.fetch_add(1, Relaxed);
                 info!("Received P2P connection from {}", addr);
                 println!("[INFO] Received P2P connection from {}", addr);
+
+                // Take a free slot, else wait in the queue for one, else turn the connection away
+                let slot = slots.clone().try_acquire_owned().ok();
+                if slot.is_none() && QUEUED_CONNECTIONS.fetch_add(1, Relaxed) >= MAX_QUEUED_CONNECTIONS {
+                    QUEUED_CONNECTIONS.fetch_sub(1, Relaxed);
+                    tokio::spawn(reject_busy(stream, addr, "too many connections"));
+                    continue;
+                }
                 let username_clone = username.clone();
                 let store_clone = image_store.clone();
+                let lane_clone = bulk_lane.clone();
+                let slots = slots.clone();
 
                 tokio::spawn(async move {
-                    if let Err(e) = handle_p2p_request(stream, username_clone, store_clone).await {
+                    let slot = match slot {
+                        Some(slot) => slot,
+                        None => {
+                            let waited = tokio::time::timeout(ACCEPT_QUEUE_TIMEOUT, slots.acquire_owned()).await;
+                            QUEUED_CONNECTIONS.fetch_sub(1, Relaxed);
+                            match waited {
+                                Ok(Ok(slot)) => slot,
+                                Ok(Err(_)) => return,
+                                Err(_) => {
+                                    QUEUE_TIMEOUTS.fetch_add(1, Relaxed);
+                                    reject_busy(stream, addr, "no free connection slot").await;
+                                    return;
+                                }
+                            }
+                        }
+                    };
+                    let _active = ActiveConnection::start(slot);
+                    if let Err(e) = handle_p2p_connection(stream, username_clone, store_clone, lane_clone).await {
                         error!("Error handling P2P request from {}: {}", addr, e);
                     }
                 });
@@ -199,40 +2074,377 @@ pub async fn start_p2p_server(
     }
 }
 
-/// Handle a single P2P request
-async fn handle_p2p_request(
+/// Serve P2P requests as a hidden peer: through `relay` under `handle`, without listening on a port
+pub async fn serve_p2p_via_relay(
+    relay: String,
+    handle: String,
+    secret: String,
+    image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> Result<()> {
+    let bulk_lane = std::sync::Arc::new(Semaphore::new(MAX_BULK_TRANSFERS));
+    image_store.write().await.set_owner(handle.clone());
+    info!("P2P server for '{}' serving through relay {}", handle, relay);
+
+    let owner = handle.clone();
+    crate::relay::serve_hidden(relay, handle, secret, move |stream| {
+        let owner = owner.clone();
+        let store = image_store.clone();
+        let lane = bulk_lane.clone();
+        async move {
+            if let Err(e) = handle_p2p_connection(stream, owner, store, lane).await {
+                error!("Error handling relayed P2P request: {}", e);
+            }
+        }
+    })
+    .await
+}
+
+/// Answer requests on a connection one after another until the client closes it or goes idle
+async fn handle_p2p_connection(
     mut stream: TcpStream,
     owner_username: String,
     image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+    bulk_lane: std::sync::Arc<Semaphore>,
+) -> Result<()> {
+    loop {
+        let msg_len = match tokio::time::timeout(SERVER_IDLE_TIMEOUT, stream.read_u32()).await {
+            Ok(Ok(len)) => len as usize,
+            // Closed between requests (older clients close after every response)
+            Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Ok(Err(e)) => return Err(e.into()),
+            Err(_) => return Ok(()),
+        };
+        handle_p2p_request(&mut stream, msg_len, owner_username.clone(), image_store.clone(), bulk_lane.clone()).await?;
+    }
+}
+
+/// Handle a single P2P request whose length prefix has been read
+async fn handle_p2p_request(
+    stream: &mut TcpStream,
+    msg_len: usize,
+    owner_username: String,
+    image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+    bulk_lane: std::sync::Arc<Semaphore>,
 ) -> Result<()> {
+    // Large frames are transfers, so queue them before buffering the whole image
+    let large_frame = msg_len >= LARGE_FRAME_BYTES;
+    let early_permit = if large_frame {
+        Some(bulk_lane.clone().acquire_owned().await?)
+    } else {
+        None
+    };
+    
     // Read message
-    let msg_len = stream.read_u32().await?;
-    let mut msg_buf = vec![0u8; msg_len as usize];
+    let mut msg_buf = vec![0u8; msg_len];
     stream.read_exact(&mut msg_buf).await?;
     
-    let message: P2PMessage = serde_json::from_slice(&msg_buf)?;
+    let remote = stream.peer_addr().map(|a| a.to_string()).unwrap_or_default();
+    record_frame(TraceChannel::P2P, TraceDirection::Received, &remote, &msg_buf);
+    
+    let message: P2PMessage = if large_frame {
+        tokio::task::spawn_blocking(move || serde_json::from_slice(&msg_buf)).await??
+    } else {
+        serde_json::from_slice(&msg_buf)?
+    };
+
+    if let P2PMessage::SubscribeCatalog { requesting_user } = message {
+        return serve_catalog_subscription(stream, &remote, msg_len, requesting_user, owner_username, image_store).await;
+    }
+
+    if let P2PMessage::DeliverBundle { from_owner, items } = message {
+        let _permit = match early_permit {
+            Some(permit) => permit,
+            None => bulk_lane.acquire_owned().await?,
+        };
+        return serve_bundle(stream, &remote, msg_len, from_owner, items, owner_username, image_store).await;
+    }
+    
+    let deferred = match is_transfer(&message).then(|| check_daily_cap(&remote)) {
+        Some(Err(e)) => deferred_response(&message, e.to_string()),
+        _ => None,
+    };
+    
+    let response_bytes = match deferred {
+        Some(response) => {
+            warn!("Deferred transfer from {}: daily bandwidth cap reached", remote);
+            serde_json::to_vec(&response)?
+        }
+        None => {
+            let _permit = match (early_permit, Lane::of(&message)) {
+                (Some(permit), _) => Some(permit),
+                (None, Lane::Bulk) => Some(bulk_lane.acquire_owned().await?),
+                (None, Lane::Control) => None,
+            };
+            let _transfer = is_transfer(&message).then(ActiveTransfer::start);
+            
+            // Handlers do CPU-heavy image work, so keep them off the async workers
+            // where they would stall control messages on other connections
+            let runtime = tokio::runtime::Handle::current();
+            tokio::task::spawn_blocking(move || -> Result<Vec<u8>> {
+                let response = runtime.block_on(process_p2p_message(message, owner_username, image_store))?;
+                Ok(serde_json::to_vec(&response)?)
+            })
+            .await??
+        }
+    };
+    record_frame(TraceChannel::P2P, TraceDirection::Sent, &remote, &response_bytes);
+    
+    // Send response
+    stream.write_u32(response_bytes.len() as u32).await?;
+    stream.write_all(&response_bytes).await?;
+    stream.flush().await?;
+    record_traffic(&remote, 4 + response_bytes.len() as u64, 4 + msg_len as u64);
     
-    // Process message
+    Ok(())
+}
+
+/// Store the images of a `DeliverBundle` one at a time, acking each before starting the next
+async fn serve_bundle(
+    stream: &mut TcpStream,
+    remote: &str,
+    msg_len: usize,
+    from_owner: String,
+    items: Vec<BundledImage>,
+    owner_username: String,
+    image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> Result<()> {
+    info!("Receiving a bundle of {} deliveries from {}", items.len(), from_owner);
+    let deferred = check_daily_cap(remote).err().map(|e| e.to_string());
+    if deferred.is_some() {
+        warn!("Deferred bundle from {}: daily bandwidth cap reached", remote);
+    }
+    let _transfer = ActiveTransfer::start();
+
+    let mut sent_bytes = 0;
+    for (index, item) in items.into_iter().enumerate() {
+        let delivery = item.into_delivery(&from_owner);
+        let response = match deferred.clone().and_then(|reason| deferred_response(&delivery, reason)) {
+            Some(response) => response,
+            None => {
+                let (owner_username, image_store) = (owner_username.clone(), image_store.clone());
+                let runtime = tokio::runtime::Handle::current();
+                tokio::task::spawn_blocking(move || runtime.block_on(process_p2p_message(delivery, owner_username, image_store)))
+                    .await??
+            }
+        };
+        let ack = serde_json::to_vec(&P2PMessage::DeliverBundleAck { index, response: Box::new(response) })?;
+        record_frame(TraceChannel::P2P, TraceDirection::Sent, remote, &ack);
+        stream.write_u32(ack.len() as u32).await?;
+        stream.write_all(&ack).await?;
+        stream.flush().await?;
+        sent_bytes += 4 + ack.len() as u64;
+    }
+    record_traffic(remote, sent_bytes, 4 + msg_len as u64);
+    Ok(())
+}
+
+/// Whether `ListImages` and catalog subscriptions show an image to `requesting_user`
+///
+/// Peers can't tell who our contacts are, so only public images are listed to others.
+fn listed_to(store: &PeerImageStore, owner_username: &str, requesting_user: &str, image_id: &str) -> bool {
+    requesting_user == owner_username || store.get_visibility(image_id).visibility == ImageVisibility::Public
+}
+
+/// Most catalog subscriptions served at once, so they can't take every connection slot
+const MAX_CATALOG_SUBSCRIPTIONS: usize = 16;
+
+/// How often a quiet catalog subscription is sent a `KeepAlive`
+pub const CATALOG_KEEPALIVE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+static CATALOG_SUBSCRIPTIONS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Counts a catalog subscription as open until dropped
+struct OpenSubscription;
+
+impl OpenSubscription {
+    fn start() -> Option<Self> {
+        use std::sync::atomic::Ordering::Relaxed;
+        if CATALOG_SUBSCRIPTIONS.fetch_add(1, Relaxed) >= MAX_CATALOG_SUBSCRIPTIONS {
+            CATALOG_SUBSCRIPTIONS.fetch_sub(1, Relaxed);
+            return None;
+        }
+        Some(OpenSubscription)
+    }
+}
+
+impl Drop for OpenSubscription {
+    fn drop(&mut self) {
+        CATALOG_SUBSCRIPTIONS.fetch_sub(1, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// The change to push for `image_id`, given what the subscriber has been shown so far
+/// (which is brought up to date)
+fn catalog_event(
+    store: &PeerImageStore,
+    owner_username: &str,
+    requesting_user: &str,
+    shown: &mut HashMap<String, ImageMetadata>,
+    image_id: &str,
+) -> Option<CatalogEvent> {
+    let current = store
+        .get_metadata(image_id)
+        .filter(|_| listed_to(store, owner_username, requesting_user, image_id))
+        .cloned();
+    match (current, shown.get(image_id)) {
+        (Some(image), None) => {
+            shown.insert(image_id.to_string(), image.clone());
+            Some(CatalogEvent::Added { image })
+        }
+        (Some(image), Some(previous)) if *previous != image => {
+            shown.insert(image_id.to_string(), image.clone());
+            Some(CatalogEvent::Updated { image })
+        }
+        (None, Some(_)) => {
+            shown.remove(image_id);
+            Some(CatalogEvent::Removed { image_id: image_id.to_string() })
+        }
+        _ => None,
+    }
+}
+
+/// Write one frame of a long-lived exchange, returning the bytes sent
+async fn write_frame(stream: &mut TcpStream, remote: &str, message: &P2PMessage) -> Result<u64> {
+    let body = serde_json::to_vec(message)?;
+    record_frame(TraceChannel::P2P, TraceDirection::Sent, remote, &body);
+    stream.write_u32(body.len() as u32).await?;
+    stream.write_all(&body).await?;
+    stream.flush().await?;
+    Ok(4 + body.len() as u64)
+}
+
+/// Answer a `SubscribeCatalog` with our catalog as the subscriber may see it, then push
+/// each change until the subscriber hangs up
+async fn serve_catalog_subscription(
+    stream: &mut TcpStream,
+    remote: &str,
+    msg_len: usize,
+    requesting_user: String,
+    owner_username: String,
+    image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> Result<()> {
+    let Some(_subscription) = OpenSubscription::start() else {
+        warn!("Turned away catalog subscription from {}: too many subscribers", requesting_user);
+        let busy = P2PMessage::Busy {
+            message: "Peer is busy (too many catalog subscribers)".to_string(),
+            retry_after_secs: BUSY_RETRY_AFTER.as_secs(),
+        };
+        let sent = write_frame(stream, remote, &busy).await?;
+        record_traffic(remote, sent, 4 + msg_len as u64);
+        return Ok(());
+    };
+    info!("{} subscribed to our catalog", requesting_user);
+
+    // Watch before taking the snapshot, so no change falls between the two
+    let (mut changes, mut shown) = {
+        let store = image_store.read().await;
+        let changes = store.watch_catalog();
+        let shown: HashMap<String, ImageMetadata> = store
+            .get_all_metadata()
+            .into_iter()
+            .filter(|image| listed_to(&store, &owner_username, &requesting_user, &image.image_id))
+            .map(|image| (image.image_id.clone(), image))
+            .collect();
+        (changes, shown)
+    };
+    let snapshot = P2PMessage::CatalogSnapshot { images: shown.values().cloned().collect() };
+    let mut sent_bytes = write_frame(stream, remote, &snapshot).await?;
+
+    let mut keepalive = tokio::time::interval(CATALOG_KEEPALIVE_INTERVAL);
+    keepalive.reset();
+    let mut probe = [0u8; 1];
+    let result = loop {
+        let events = tokio::select! {
+            change = changes.recv() => {
+                let store = image_store.read().await;
+                match change {
+                    Ok(image_id) => catalog_event(&store, &owner_username, &requesting_user, &mut shown, &image_id)
+                        .into_iter()
+                        .collect(),
+                    // Fell behind: compare every image instead of replaying what was missed
+                    Err(tokio::sync::broadcast::error::RecvError::Lagged(missed)) => {
+                        warn!("Catalog subscriber {} missed {} changes - resyncing", requesting_user, missed);
+                        let mut image_ids: Vec<String> = store.get_all_metadata().into_iter().map(|image| image.image_id).collect();
+                        image_ids.extend(shown.keys().cloned());
+                        image_ids.sort();
+                        image_ids.dedup();
+                        image_ids
+                            .iter()
+                            .filter_map(|image_id| catalog_event(&store, &owner_username, &requesting_user, &mut shown, image_id))
+                            .collect()
+                    }
+                    Err(tokio::sync::broadcast::error::RecvError::Closed) => break Ok(()),
+                }
+            }
+            _ = keepalive.tick() => vec![CatalogEvent::KeepAlive],
+            // Subscribers send nothing more, so a read only returns when they hang up
+            read = stream.read(&mut probe) => match read {
+                Ok(0) => break Ok(()),
+                Ok(_) => break Err(anyhow::anyhow!("Unexpected data on a catalog subscription")),
+                Err(e) => break Err(e.into()),
+            },
+        };
+        if !events.is_empty() {
+            keepalive.reset();
+        }
+        for event in events {
+            sent_bytes += write_frame(stream, remote, &P2PMessage::CatalogUpdate { event }).await?;
+        }
+    };
+    info!("{} unsubscribed from our catalog", requesting_user);
+    record_traffic(remote, sent_bytes, 4 + msg_len as u64);
+    result
+}
+
+/// Work out the response to one P2P message
+pub(crate) async fn process_p2p_message(
+    message: P2PMessage,
+    owner_username: String,
+    image_store: std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> Result<P2PMessage> {
     let response = match message {
         P2PMessage::ImageRequest {
             requesting_user,
             image_id,
             requested_views,
+            requested_max_dimension,
+            approved_by,
+            grant_token,
         } => {
+            let approval = approved_by.as_ref().map(|delegate| format!(", approved by {}", delegate)).unwrap_or_default();
             info!(
-                "Image request from {} for {} ({} views)",
-                requesting_user, image_id, requested_views
+                "Image request from {} for {} ({} views{})",
+                requesting_user, image_id, requested_views, approval
             );
             println!(
-                "[INFO] Image request from {} for {} ({} views)",
-                requesting_user, image_id, requested_views
+                "[INFO] Image request from {} for {} ({} views{})",
+                requesting_user, image_id, requested_views, approval
             );
 
+            // Clamp grants that exceed the owner's per-image cap
+            let (max_grant_views, audit_log) = {
+                let store = image_store.read().await;
+                (store.get_max_grant_views(&image_id), store.get_audit_log_path().cloned())
+            };
+            let requested_views = match max_grant_views {
+                Some(max_views) if requested_views > max_views => {
+                    warn!(
+                        "Clamping {} views for {} on {} to cap of {}",
+                        requested_views, requesting_user, image_id, max_views
+                    );
+                    max_views
+                }
+                _ => requested_views,
+            };
+
             let response = handle_image_request(
                 &owner_username,
                 &requesting_user,
                 &image_id,
                 requested_views,
+                requested_max_dimension,
+                grant_token.as_deref(),
+                approved_by.as_deref(),
                 &image_store,
             )
             .await;
@@ -250,10 +2462,25 @@ async fn handle_p2p_request(
                 _ => {}
             }
 
+            // Owners fetch their own images to deliver them, which isn't a grant
+            if let P2PMessage::ImageResponse { success, message, .. } = &response {
+                if requesting_user != owner_username {
+                    audit(audit_log.as_deref(), AuditRecord {
+                        action: AuditAction::Grant,
+                        recipient: requesting_user.clone(),
+                        image_id: image_id.clone(),
+                        views: Some(requested_views),
+                        success: *success,
+                        message: message.clone(),
+                        approved_by: approved_by.clone(),
+                    });
+                }
+            }
+
             response
         }
         
-        P2PMessage::ListImages { requesting_user } => {
+        P2PMessage::ListImages { requesting_user, query } => {
             // Only log if it's not a self-request (connectivity check)
             if requesting_user != owner_username {
                 info!("List images request from {}", requesting_user);
@@ -261,7 +2488,11 @@ async fn handle_p2p_request(
             }
 
             let store = image_store.read().await;
-            let images = store.get_all_metadata();
+            let mut images = match query.as_deref().map(str::trim).filter(|q| !q.is_empty()) {
+                Some(query) => store.search(query),
+                None => store.get_all_metadata(),
+            };
+            images.retain(|image| listed_to(&store, &owner_username, &requesting_user, &image.image_id));
 
             if requesting_user != owner_username {
                 println!("[INFO] Sending {} images to {}", images.len(), requesting_user);
@@ -295,6 +2526,18 @@ async fn handle_p2p_request(
             } else {
                 let response = handle_update_permissions(&image_id, &username, new_quota, &image_store).await;
 
+                if let P2PMessage::UpdatePermissionsResponse { success, message } = &response {
+                    audit(image_store.read().await.get_audit_log_path().map(PathBuf::as_path), AuditRecord {
+                        action: AuditAction::PermissionUpdate,
+                        recipient: username.clone(),
+                        image_id: image_id.clone(),
+                        views: Some(new_quota),
+                        success: *success,
+                        message: message.clone(),
+                        approved_by: None,
+                    });
+                }
+
                 // Log the result
                 match &response {
                     P2PMessage::UpdatePermissionsResponse { success: true, .. } => {
@@ -322,6 +2565,8 @@ async fn handle_p2p_request(
             image_id,
             requested_views,
             encrypted_image,
+            sha256,
+            grant_seq,
         } => {
             info!(
                 "Receiving image delivery from {} for image {} ({} views)",
@@ -336,39 +2581,135 @@ async fn handle_p2p_request(
             println!("👁  Views granted: {}", requested_views);
             println!("========================================\n");
 
-            // Generate filename: from_{owner}_{image_id}
-            let file_name = format!("from_{}_{}", from_owner, image_id);
-            
-            // Determine save path - use received_images_dir if set, otherwise current directory
-            let save_path = {
+            // Our copy if we hold one, else a new name under the folder's policy - use
+            // received_images_dir if set, otherwise current directory
+            let (save_path, quota) = {
                 let store = image_store.read().await;
-                match store.get_received_images_dir() {
-                    Some(dir) => dir.join(&file_name),
-                    None => PathBuf::from(&file_name),
-                }
+                let save_path = received_image_path(store.get_received_images_dir().map(PathBuf::as_path), &from_owner, &image_id);
+                (save_path, store.get_received_quota_bytes())
             };
+            let save_dir = save_path.parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(std::path::Path::new("."))
+                .to_path_buf();
+
+            // Held from the replay check until the grant is recorded, so two deliveries of one
+            // grant can't both pass it; a merge also rewrites the quota of a copy being viewed
+            let _carrier_lock = crate::viewing::lock_carrier(&save_path).await;
+
+            // The grant number embedded for us is authoritative; the message's copy must agree
+            let embedded_seq = embedded_grant_seq(&encrypted_image, &owner_username).unwrap_or(0);
+            let freshness = grant_freshness(&save_dir, &from_owner, &image_id, embedded_seq);
+
+            // Verify the transfer against the sender's checksum before saving
+            let received_sha256 = sha256_hex(&encrypted_image);
+            if let Some(expected) = sha256.as_ref().filter(|expected| **expected != received_sha256) {
+                error!("Checksum mismatch for {}: expected {}, got {}", image_id, expected, received_sha256);
+                println!("❌ Checksum mismatch - image corrupted in transfer");
+
+                P2PMessage::DeliverImageResponse {
+                    success: false,
+                    message: format!("Checksum mismatch: expected {}, got {}", expected, received_sha256),
+                    sha256: Some(received_sha256),
+                    size_bytes: encrypted_image.len() as u64,
+                }
+            } else if grant_seq.is_some_and(|seq| seq != embedded_seq) {
+                warn!("Rejected delivery of {}: grant #{} in message, #{} in image", image_id, grant_seq.unwrap_or(0), embedded_seq);
+                println!("❌ Grant number doesn't match the image - refusing delivery");
+
+                P2PMessage::DeliverImageResponse {
+                    success: false,
+                    message: format!(
+                        "Grant #{} in the message doesn't match grant #{} in the image",
+                        grant_seq.unwrap_or(0), embedded_seq
+                    ),
+                    sha256: Some(received_sha256),
+                    size_bytes: encrypted_image.len() as u64,
+                }
+            } else if let GrantFreshness::Stale { applied } = freshness {
+                warn!("Rejected replayed grant #{} of {} (#{} already applied)", embedded_seq, image_id, applied);
+                println!("❌ Replayed grant #{} refused - grant #{} was already applied", embedded_seq, applied);
+
+                P2PMessage::DeliverImageResponse {
+                    success: false,
+                    message: format!("Grant #{} is older than grant #{} already applied", embedded_seq, applied),
+                    sha256: Some(received_sha256),
+                    size_bytes: encrypted_image.len() as u64,
+                }
+            } else if freshness == GrantFreshness::AlreadyApplied && save_path.exists() {
+                println!("🔁 Grant #{} was already applied - nothing changed", embedded_seq);
+
+                P2PMessage::DeliverImageResponse {
+                    success: true,
+                    message: format!("Grant #{} of '{}' was already applied", embedded_seq, image_id),
+                    sha256: Some(received_sha256),
+                    size_bytes: encrypted_image.len() as u64,
+                }
+            } else if let Err(shortfall) = reserve_disk_space(&save_dir, encrypted_image.len() as u64) {
+                warn!("Refused delivery of {}: {}", image_id, shortfall);
+                println!("❌ {} - ask {} to retry later", shortfall, from_owner);
+                shortfall.into_message()
+            } else if let Some(Err(e)) = quota.map(|quota| {
+                make_room_for_received(&save_dir, &owner_username, quota, encrypted_image.len() as u64, &save_path)
+            }) {
+                warn!("Rejected delivery of {}: {}", image_id, e);
+                println!("❌ {}", e);
 
-            match fs::write(&save_path, &encrypted_image) {
-                Ok(_) => {
-                    let file_size = encrypted_image.len() / 1024;
-                    println!("✅ Image saved to: {}", save_path.display());
-                    println!("📊 Size: {} KB", file_size);
-                    println!("\n💡 You can now view the image with:");
-                    println!("   cargo run --bin client -- view --input {} --user {}",
-                             save_path.display(), owner_username);
+                P2PMessage::DeliverImageResponse {
+                    success: false,
+                    message: e.to_string(),
+                    sha256: Some(received_sha256),
+                    size_bytes: encrypted_image.len() as u64,
+                }
+            } else {
+                match store_delivered_image(&save_path, &from_owner, &image_id, &encrypted_image, &owner_username) {
+                    Ok(outcome) => {
+                        if embedded_seq > 0 {
+                            if let Err(e) = record_applied_grant(&save_dir, &from_owner, &image_id, embedded_seq) {
+                                warn!("Failed to record grant #{} of {}: {}", embedded_seq, image_id, e);
+                            }
+                        }
+                        let (path, message) = match &outcome {
+                            DeliveryOutcome::Saved { path, .. } => {
+                                println!("✅ Image saved to: {}", path.display());
+                                (path, format!("Image '{}' delivered and saved to {}", image_id, path.display()))
+                            }
+                            DeliveryOutcome::Merged { path, total_views } => {
+                                println!("🔗 Same image already received - merged views into {}", path.display());
+                                println!("👁  Total views now: {}", total_views);
+                                (path, format!("Image '{}' already held; views merged into {} ({} total)",
+                                               image_id, path.display(), total_views))
+                            }
+                            DeliveryOutcome::KeptBoth { path, existing, .. } => {
+                                println!("⚠ A different image already exists at {}", existing.display());
+                                println!("✅ Image saved to: {}", path.display());
+                                (path, format!("Image '{}' differs from {}; saved as {}",
+                                               image_id, existing.display(), path.display()))
+                            }
+                        };
+                        println!("📊 Size: {} KB", encrypted_image.len() / 1024);
+                        println!("🔒 SHA-256: {}", received_sha256);
+                        println!("\n💡 You can now view the image with:");
+                        println!("   cargo run --bin client -- view --input {} --user {}",
+                                 path.display(), owner_username);
 
-                    P2PMessage::DeliverImageResponse {
-                        success: true,
-                        message: format!("Image '{}' delivered and saved to {}", image_id, save_path.display()),
+                        P2PMessage::DeliverImageResponse {
+                            success: true,
+                            message,
+                            sha256: Some(received_sha256),
+                            size_bytes: encrypted_image.len() as u64,
+                        }
                     }
-                }
-                Err(e) => {
-                    error!("Failed to save delivered image: {}", e);
-                    println!("❌ Failed to save image: {}", e);
+                    Err(e) => {
+                        error!("Failed to save delivered image: {}", e);
+                        println!("❌ Failed to save image: {}", e);
 
-                    P2PMessage::DeliverImageResponse {
-                        success: false,
-                        message: format!("Failed to save image: {}", e),
+                        P2PMessage::DeliverImageResponse {
+                            success: false,
+                            message: format!("Failed to save image: {}", e),
+                            sha256: None,
+                            size_bytes: 0,
+                        }
                     }
                 }
             }
@@ -401,14 +2742,10 @@ async fn handle_p2p_request(
                     message: format!("Permission update is for user '{}', not '{}'", for_user, owner_username),
                 }
             } else {
-                // Find the local image file: from_{owner}_{image_id} in received_images_dir or current directory
-                let file_name = format!("from_{}_{}", from_owner, image_id);
+                // Find our copy of the image in received_images_dir or current directory
                 let local_image_path = {
                     let store = image_store.read().await;
-                    match store.get_received_images_dir() {
-                        Some(dir) => dir.join(&file_name),
-                        None => PathBuf::from(&file_name),
-                    }
+                    received_image_path(store.get_received_images_dir().map(PathBuf::as_path), &from_owner, &image_id)
                 };
 
                 if !local_image_path.exists() {
@@ -417,16 +2754,31 @@ async fn handle_p2p_request(
                         success: false,
                         message: format!("Image not found locally: {}", local_image_path.display()),
                     }
+                } else if let Err(shortfall) = reserve_disk_space(
+                    local_image_path.parent().unwrap_or(Path::new(".")),
+                    file_size(&local_image_path),
+                ) {
+                    warn!("Refused permission update for {}: {}", image_id, shortfall);
+                    println!("❌ {} - ask {} to retry later", shortfall, from_owner);
+                    shortfall.into_message()
                 } else {
                     println!("🔍 Found local image: {}", local_image_path.display());
                     println!("🔧 Updating embedded permissions...");
 
                     // Re-encrypt the image with new permissions
-                    match update_local_image_permissions(&local_image_path, &for_user, new_quota) {
+                    let carrier_lock = crate::viewing::lock_carrier(&local_image_path).await;
+                    let updated = update_local_image_permissions(&local_image_path, &for_user, new_quota);
+                    drop(carrier_lock);
+                    match updated {
                         Ok(()) => {
                             if new_quota == 0 {
                                 println!("\n✅ Permission revoked!");
                                 println!("   You can no longer view this image.");
+                                image_store.read().await.notify_revoked(AccessRevoked {
+                                    from_owner: from_owner.clone(),
+                                    image_id: image_id.clone(),
+                                    wiped: false,
+                                });
                                 P2PMessage::RemoteUpdatePermissionsResponse {
                                     success: true,
                                     message: format!("Permissions revoked. Image '{}' access removed.", image_id),
@@ -456,11 +2808,166 @@ async fn handle_p2p_request(
         P2PMessage::ThumbnailRequest {
             requesting_user,
             image_id,
+            quality,
         } => {
-            info!("Thumbnail request from {} for {}", requesting_user, image_id);
+            info!("Thumbnail request from {} for {} ({:?})", requesting_user, image_id, quality);
             println!("[INFO] Thumbnail request from {} for {}", requesting_user, image_id);
 
-            handle_thumbnail_request(&image_id, &image_store).await
+            handle_thumbnail_request(&image_id, quality, &image_store).await
+        }
+
+        P2PMessage::RequestCancelled {
+            request_id,
+            from_user,
+            image_id,
+        } => {
+            info!("{} cancelled request {} for {}", from_user, request_id, image_id);
+            println!("\n🚫 {} cancelled their request for '{}' (ID: {})", from_user, image_id, request_id);
+
+            P2PMessage::RequestCancelledResponse { success: true }
+        }
+
+        P2PMessage::PendingImageEvicted {
+            update_id,
+            target_user,
+            image_id,
+        } => {
+            warn!("Directory evicted queued image {} for {} (update {})", image_id, target_user, update_id);
+            println!("\n🗑  The directory dropped your queued copy of '{}' for {} (storage full)", image_id, target_user);
+            println!("   The quota update still applies - resend the image when {} is online.", target_user);
+
+            P2PMessage::PendingImageEvictedResponse { success: true }
+        }
+
+        P2PMessage::AccessDeniedReport { denial } => {
+            if denial.owner != owner_username {
+                warn!("Ignoring denial report for {} (we are {})", denial.owner, owner_username);
+                P2PMessage::AccessDeniedReportResponse { success: false }
+            } else {
+                info!("{} was denied access to {} ({:?})", denial.viewer, denial.image_id, denial.reason);
+                println!("\n🛡  {} was denied a view of '{}' ({:?})", denial.viewer, denial.image_id, denial.reason);
+
+                let stats_path = image_store.read().await.get_denial_stats_path().cloned();
+                let success = match stats_path {
+                    Some(path) => match record_access_denials(&path, std::slice::from_ref(&denial)) {
+                        Ok(()) => true,
+                        Err(e) => {
+                            error!("Failed to record access denial: {}", e);
+                            false
+                        }
+                    },
+                    None => true,
+                };
+                P2PMessage::AccessDeniedReportResponse { success }
+            }
+        }
+
+        P2PMessage::HaveImage {
+            requesting_user,
+            image_id,
+            content_hash,
+        } => {
+            let image_path = image_store.read().await.get_image_path(&image_id).cloned();
+            let identical = image_path
+                .and_then(|path| fs::read(path).ok())
+                .and_then(|data| content_sha256(&data))
+                .is_some_and(|hash| hash == content_hash);
+
+            info!(
+                "Image probe from {} for {}: {}",
+                requesting_user, image_id, if identical { "identical" } else { "different" }
+            );
+            P2PMessage::HaveImageResponse { identical }
+        }
+
+        P2PMessage::Ping { from_user } => {
+            info!("Ping from {}", from_user);
+            P2PMessage::Pong { username: owner_username }
+        }
+
+        P2PMessage::RedeliverRequest { requesting_user, image_id } => {
+            info!("Re-delivery request from {} for {}", requesting_user, image_id);
+            println!("[INFO] Re-delivery request from {} for {}", requesting_user, image_id);
+
+            let response = handle_redeliver_request(&requesting_user, &image_id, &image_store).await;
+            match &response {
+                P2PMessage::ImageResponse { success: true, .. } => {
+                    println!("[INFO] ✓ Re-delivered {} to {}", image_id, requesting_user);
+                }
+                P2PMessage::ImageResponse { message, .. } => {
+                    info!("✗ Refused re-delivery to {}: {}", requesting_user, message);
+                }
+                _ => {}
+            }
+
+            if let P2PMessage::ImageResponse { success, message, .. } = &response {
+                audit(image_store.read().await.get_audit_log_path().map(PathBuf::as_path), AuditRecord {
+                    action: AuditAction::Redelivery,
+                    recipient: requesting_user.clone(),
+                    image_id: image_id.clone(),
+                    views: None,
+                    success: *success,
+                    message: message.clone(),
+                    approved_by: None,
+                });
+            }
+            response
+        }
+
+        P2PMessage::RemoteWipe { from_owner, image_id } => {
+            info!("Remote wipe from {} for {}", from_owner, image_id);
+            println!("\n🧹 {} asked us to delete our copy of '{}'", from_owner, image_id);
+
+            let received_dir = image_store.read().await.get_received_images_dir().cloned();
+            match wipe_received_image(received_dir.as_deref(), &from_owner, &image_id) {
+                Ok(wiped) => {
+                    println!("   ✓ Securely deleted {} cop{}", wiped, if wiped == 1 { "y" } else { "ies" });
+                    if wiped > 0 {
+                        image_store.read().await.notify_revoked(AccessRevoked {
+                            from_owner: from_owner.clone(),
+                            image_id: image_id.clone(),
+                            wiped: true,
+                        });
+                    }
+                    P2PMessage::RemoteWipeResponse {
+                        success: true,
+                        message: format!("Deleted {} local cop{} of '{}'", wiped, if wiped == 1 { "y" } else { "ies" }, image_id),
+                    }
+                }
+                Err(e) => {
+                    error!("Failed to wipe {}: {}", image_id, e);
+                    P2PMessage::RemoteWipeResponse {
+                        success: false,
+                        message: format!("Failed to delete local copy: {}", e),
+                    }
+                }
+            }
+        }
+
+        P2PMessage::RemoteWipeAck { from_user, image_id, success, message } => {
+            info!("{} acknowledged wipe of {} (success: {})", from_user, image_id, success);
+            println!("\n🧹 {} carried out the queued wipe of '{}': {}", from_user, image_id, message);
+
+            audit(image_store.read().await.get_audit_log_path().map(PathBuf::as_path), AuditRecord {
+                action: AuditAction::Wipe,
+                recipient: from_user,
+                image_id,
+                views: None,
+                success,
+                message,
+                approved_by: None,
+            });
+            P2PMessage::RemoteWipeAckResponse { success: true }
+        }
+
+        P2PMessage::VerifyGrant { image_id, for_user } => {
+            info!("{} asked to verify their grant for {}", for_user, image_id);
+            handle_verify_grant(&for_user, &image_id, &image_store).await
+        }
+
+        P2PMessage::RedeemPairing { code, requesting_user } => {
+            info!("{} redeemed a pairing code", requesting_user);
+            crate::pairing::handle_redeem_pairing(&owner_username, &code, &requesting_user, &image_store).await
         }
 
         _ => {
@@ -468,23 +2975,89 @@ async fn handle_p2p_request(
         }
     };
     
-    // Send response
-    let response_json = serde_json::to_string(&response)?;
-    let response_bytes = response_json.as_bytes();
-    
-    stream.write_u32(response_bytes.len() as u32).await?;
-    stream.write_all(response_bytes).await?;
-    stream.flush().await?;
-    
-    Ok(())
+    Ok(response)
+}
+
+/// Resend our copy of an image to a user it already grants views to
+async fn handle_redeliver_request(
+    requesting_user: &str,
+    image_id: &str,
+    image_store: &std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> P2PMessage {
+    let refuse = |message: String| P2PMessage::ImageResponse {
+        success: false,
+        message,
+        encrypted_image: None,
+    };
+
+    let Some(image_path) = image_store.read().await.get_image_path(image_id).cloned() else {
+        return refuse(format!("Image {} not found", image_id));
+    };
+    let encrypted_data = match fs::read(&image_path) {
+        Ok(data) => data,
+        Err(e) => return refuse(format!("Failed to read image: {}", e)),
+    };
+
+    // The owner's copy is the record of what each user was granted
+    match embedded_quota(&encrypted_data, requesting_user) {
+        Some(views) if views > 0 => {}
+        Some(_) => return refuse("No views remaining on this image - send a new request".to_string()),
+        None => return refuse(format!("You haven't been granted {} - send a request", image_id)),
+    }
+
+    if let Err(wait) = image_store.write().await.claim_redelivery(requesting_user, image_id) {
+        return refuse(format!(
+            "Already re-delivered recently - try again in {}h {:02}m",
+            wait.as_secs() / 3600,
+            wait.as_secs() / 60 % 60
+        ));
+    }
+
+    P2PMessage::ImageResponse {
+        success: true,
+        message: format!("Re-delivered {}", image_id),
+        encrypted_image: Some(encrypted_data),
+    }
+}
+
+/// What our copy of an image grants a user, read from its embedded permissions
+async fn handle_verify_grant(
+    for_user: &str,
+    image_id: &str,
+    image_store: &std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> P2PMessage {
+    let not_found = P2PMessage::VerifyGrantResponse { found: false, quota: None, grant_seq: 0 };
+    let Some(image_path) = image_store.read().await.get_image_path(image_id).cloned() else {
+        return not_found;
+    };
+    let permissions = match fs::read(&image_path).ok().and_then(|data| embedded_permissions(&data)) {
+        Some(permissions) => permissions,
+        None => {
+            warn!("Can't read the permissions of {} to verify a grant", image_path.display());
+            return not_found;
+        }
+    };
+
+    P2PMessage::VerifyGrantResponse {
+        found: true,
+        quota: permissions.quotas.get(for_user).copied(),
+        grant_seq: permissions.grant_seq(for_user),
+    }
 }
 
 /// Handle an image request - grant access by modifying the encrypted image
+///
+/// Raising a non-owner's quota needs a grant token the directory vouches for. `local_user`
+/// is whoever runs this server, recorded in the copy sent if they aren't the image's owner.
+#[allow(clippy::too_many_arguments)]
 async fn handle_image_request(
-    _owner: &str,
+    local_user: &str,
     requesting_user: &str,
     image_id: &str,
     requested_views: u32,
+    max_dimension: Option<u32>,
+    grant_token: Option<&str>,
+    approved_by: Option<&str>,
     image_store: &std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
 ) -> P2PMessage {
     // Get the image path
@@ -501,7 +3074,23 @@ async fn handle_image_request(
             }
         }
     };
+
+    // The owner's and holders' copies leave the carrier as it is - serve the cached bytes
+    if let Ok(file) = fs::metadata(&image_path) {
+        let cached = image_store.read().await.cached_grant(image_id, &file, requesting_user);
+        if let Some(png_bytes) = cached {
+            info!("Serving unchanged copy of {} to {} from cache", image_id, requesting_user);
+            return P2PMessage::ImageResponse {
+                success: true,
+                message: format!("Unchanged copy of {} for {}", image_id, requesting_user),
+                encrypted_image: Some(png_bytes),
+            };
+        }
+    }
     
+    // Held until the new quota is written, so concurrent grants can't drop each other's change
+    let _carrier_lock = crate::viewing::lock_carrier(&image_path).await;
+
     // Read the encrypted image
     let encrypted_data = match fs::read(&image_path) {
         Ok(data) => data,
@@ -549,7 +3138,7 @@ async fn handle_image_request(
     };
     
     // Deserialize the combined payload
-    let mut combined_data: CombinedPayload = match bincode::deserialize(&payload) {
+    let mut combined_data = match CombinedPayload::from_bytes(&payload) {
         Ok(data) => data,
         Err(e) => {
             return P2PMessage::ImageResponse {
@@ -561,19 +3150,69 @@ async fn handle_image_request(
     };
 
     // Check if requesting user is the owner - owners don't consume quota
-    let is_owner = requesting_user == &combined_data.permissions.owner;
+    let is_owner = *requesting_user == combined_data.permissions.owner;
+    // Holders get the carrier as it is, never a quota. Anyone else gets a new numbered
+    // grant, even of the quota they already have, so receivers can tell it from a replay.
+    let unchanged = is_owner || combined_data.permissions.is_holder(requesting_user);
+
+    if unchanged {
+        // Nothing to rewrite - the carrier on disk already says this
+        let mut store = image_store.write().await;
+        store.cache_carrier(image_id, &image_path, &combined_data.permissions, &encrypted_data);
+        let message = if combined_data.permissions.is_holder(requesting_user) {
+            format!("Holder copy granted to {} (no views)", requesting_user)
+        } else {
+            format!("Access granted: {} views for user {}", requested_views, requesting_user)
+        };
+        return P2PMessage::ImageResponse {
+            success: true,
+            message,
+            encrypted_image: Some(encrypted_data),
+        };
+    }
 
     if !is_owner {
         // Only enforce and decrement quota for non-owners
         let existing_quota = combined_data.permissions.quotas.get(requesting_user).copied();
 
+        // A crafted request could otherwise name any quota, so raises need an accepted request
+        let current_quota = existing_quota.unwrap_or(0);
+        let requested_views = if existing_quota != Some(0) && requested_views > current_quota {
+            let directories = image_store.read().await.get_directory_servers().to_vec();
+            let owner = combined_data.permissions.owner.clone();
+            let authorized = match grant_token {
+                Some(token) => verify_grant(&directories, &owner, requesting_user, image_id, token).await,
+                None => None,
+            };
+            match authorized {
+                Some(views) if views < requested_views => {
+                    warn!("Clamping {} views for {} on {} to the {} accepted", requested_views, requesting_user, image_id, views);
+                    views.max(current_quota)
+                }
+                Some(_) => requested_views,
+                None => {
+                    warn!("Refused to raise {}'s quota on {} without an accepted request", requesting_user, image_id);
+                    return P2PMessage::ImageResponse {
+                        success: false,
+                        message: format!(
+                            "Raising your quota to {} views needs a request the owner accepted - leave a request first",
+                            requested_views
+                        ),
+                        encrypted_image: None,
+                    };
+                }
+            }
+        } else {
+            requested_views
+        };
+
         match existing_quota {
             Some(0) => {
                 // User was explicitly revoked (quota = 0)
                 info!("Denied {} - access was revoked by owner", requesting_user);
                 return P2PMessage::ImageResponse {
                     success: false,
-                    message: format!("Access denied. Owner has revoked your permissions."),
+                    message: "Access denied. Owner has revoked your permissions.".to_string(),
                     encrypted_image: None,
                 };
             }
@@ -583,36 +3222,27 @@ async fn handle_image_request(
                 println!("[DEBUG] Existing user {} has quota: {}, setting to: {}", requesting_user, current_quota, requested_views);
                 
                 // Set the quota to exactly what was requested - this is granting access
-                combined_data
-                    .permissions
-                    .quotas
-                    .insert(requesting_user.to_string(), requested_views);
+                let seq = combined_data.permissions.set_quota(requesting_user, requested_views);
 
-                info!("Set {} views for {} (was: {})", requested_views, requesting_user, current_quota);
+                info!("Set {} views for {} (was: {}, grant #{})", requested_views, requesting_user, current_quota, seq);
                 println!("[DEBUG] After update, quota for '{}': {}", requesting_user, requested_views);
             }
             None => {
                 // New user - grant requested access
-                combined_data
-                    .permissions
-                    .quotas
-                    .insert(requesting_user.to_string(), requested_views);
+                let seq = combined_data.permissions.set_quota(requesting_user, requested_views);
 
-                info!("Granted {} views to {} for image {}", requested_views, requesting_user, image_id);
+                info!("Granted {} views to {} for image {} (grant #{})", requested_views, requesting_user, image_id, seq);
                 println!("[DEBUG] New user quota - inserted {} views for '{}' in quotas", requested_views, requesting_user);
                 println!("[DEBUG] Updated quotas after insert: {:?}", combined_data.permissions.quotas);
             }
         }
-    } else {
-        // Owner has unlimited access - don't modify quotas
-        info!("Owner {} accessing their own image - unlimited access", requesting_user);
     }
 
     // DEBUG: Log the final quotas before re-encoding
     println!("[DEBUG] Final quotas before re-encoding: {:?}", combined_data.permissions.quotas);
 
     // Re-serialize and re-encode
-    let updated_payload = match bincode::serialize(&combined_data) {
+    let updated_payload = match combined_data.to_bytes() {
         Ok(data) => data,
         Err(e) => {
             return P2PMessage::ImageResponse {
@@ -634,15 +3264,6 @@ async fn handle_image_request(
         }
     };
 
-    // Persist the updated carrier back to disk so changes (decrements/revocations) are authoritative
-    if let Err(e) = updated_carrier.save(&image_path) {
-        return P2PMessage::ImageResponse {
-            success: false,
-            message: format!("Failed to save updated image after permission change: {}", e),
-            encrypted_image: None,
-        };
-    }
-
     // Convert to PNG bytes
     use image::ImageOutputFormat;
     use std::io::Cursor;
@@ -657,16 +3278,139 @@ async fn handle_image_request(
         };
     }
 
+    // Persist the updated carrier back to disk so changes (decrements/revocations) are authoritative
+    if let Err(e) = write_atomic(&image_path, &out_buf) {
+        return P2PMessage::ImageResponse {
+            success: false,
+            message: format!("Failed to save updated image after permission change: {:#}", e),
+            encrypted_image: None,
+        };
+    }
+
+    image_store.write().await.cache_carrier(image_id, &image_path, &combined_data.permissions, &out_buf);
+
+    // The copy sent says who it came through; the one on disk stays the owner's record
+    match grant_with_provenance(&carrier_img, &mut combined_data, local_user, approved_by) {
+        Ok(Some(png_bytes)) => out_buf = png_bytes,
+        Ok(None) => {}
+        Err(e) => {
+            return P2PMessage::ImageResponse {
+                success: false,
+                message: format!("Failed to record provenance: {}", e),
+                encrypted_image: None,
+            };
+        }
+    }
+
+    let mut message = format!("Access granted: {} views for user {}", requested_views, requesting_user);
+
+    // A smaller copy only leaves with the smaller image in it; the one on disk stays full size
+    if let Some(max_dimension) = max_dimension {
+        match downscaled_grant(&carrier_img, combined_data, max_dimension) {
+            Ok(Some(png_bytes)) => {
+                info!("Downscaled {} to {}px for {}", image_id, max_dimension, requesting_user);
+                message.push_str(&format!(" (downscaled to {}px)", max_dimension));
+                out_buf = png_bytes;
+            }
+            Ok(None) => {}
+            Err(e) => {
+                return P2PMessage::ImageResponse {
+                    success: false,
+                    message: format!("Failed to downscale image: {}", e),
+                    encrypted_image: None,
+                };
+            }
+        }
+    }
+
     P2PMessage::ImageResponse {
         success: true,
-        message: format!(
-            "Access granted: {} views for user {}",
-            requested_views, requesting_user
-        ),
+        message,
         encrypted_image: Some(out_buf),
     }
 }
 
+/// Re-embed a grant with the hops it took from the owner, or None if it comes straight from them
+fn grant_with_provenance(
+    carrier: &image::DynamicImage,
+    combined: &mut crate::CombinedPayload,
+    sharer: &str,
+    approved_by: Option<&str>,
+) -> Result<Option<Vec<u8>>> {
+    use crate::{lsb, ProvenanceRole};
+    use image::ImageOutputFormat;
+    use std::io::Cursor;
+
+    let hops = combined.provenance.len();
+    if let Some(delegate) = approved_by {
+        combined.record_hop(delegate, ProvenanceRole::Delegate);
+    }
+    combined.record_hop(sharer, ProvenanceRole::Reshared);
+    if combined.provenance.len() == hops {
+        return Ok(None);
+    }
+
+    let encoded = lsb::encode(carrier, &combined.to_bytes()?)?;
+    let mut png = Vec::new();
+    encoded.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(Some(png))
+}
+
+/// Smallest copy an owner will make for a size-limited request
+pub const MIN_REQUESTED_DIMENSION: u32 = 32;
+
+/// Spare room left in a shrunk carrier for later permission updates
+const SHRUNK_CARRIER_HEADROOM: usize = 4 * 1024;
+
+/// PNG of `image_bytes` scaled to fit `max_dimension` on its longer side, or None if it already fits
+pub fn downscale_image(image_bytes: &[u8], max_dimension: u32) -> Result<Option<Vec<u8>>> {
+    use image::{imageops::FilterType, ImageOutputFormat};
+    use std::io::Cursor;
+
+    let image = image::load_from_memory(image_bytes)?;
+    let max_dimension = max_dimension.max(MIN_REQUESTED_DIMENSION);
+    if image.width().max(image.height()) <= max_dimension {
+        return Ok(None);
+    }
+    let mut png = Vec::new();
+    image
+        .resize(max_dimension, max_dimension, FilterType::Lanczos3)
+        .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(Some(png))
+}
+
+/// Re-embed a grant with its image downscaled, in a carrier shrunk to what the smaller payload needs
+fn downscaled_grant(
+    carrier: &image::DynamicImage,
+    mut combined: crate::CombinedPayload,
+    max_dimension: u32,
+) -> Result<Option<Vec<u8>>> {
+    use crate::lsb;
+    use image::{imageops::FilterType, ImageOutputFormat};
+    use std::io::Cursor;
+
+    let Some(small) = downscale_image(&combined.unified_image, max_dimension)? else {
+        return Ok(None);
+    };
+    combined.unified_image = small;
+    let payload = combined.to_bytes()?;
+
+    // Same proportions, no more pixels than the payload needs
+    let needed = (payload.len() + SHRUNK_CARRIER_HEADROOM) as f64;
+    let scale = (needed / lsb::capacity_bytes(carrier.width(), carrier.height()) as f64).sqrt();
+    let shrunk = if scale < 1.0 {
+        let width = ((carrier.width() as f64 * scale).ceil() as u32).max(lsb::MIN_CARRIER_DIMENSION);
+        let height = ((carrier.height() as f64 * scale).ceil() as u32).max(lsb::MIN_CARRIER_DIMENSION);
+        carrier.resize_exact(width, height, FilterType::Triangle)
+    } else {
+        carrier.clone()
+    };
+
+    let mut png = Vec::new();
+    lsb::encode(&shrunk, &payload)?.write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(Some(png))
+}
+
 /// Handle updating permissions for an existing user
 async fn handle_update_permissions(
     image_id: &str,
@@ -688,7 +3432,8 @@ async fn handle_update_permissions(
         }
     };
     
-    // Read, decode, update, encode, write back
+    // Read, decode, update, encode, write back - all under the carrier's lock
+    let _carrier_lock = crate::viewing::lock_carrier(&image_path).await;
     let encrypted_data = match fs::read(&image_path) {
         Ok(data) => data,
         Err(e) => {
@@ -722,7 +3467,7 @@ async fn handle_update_permissions(
         }
     };
     
-    let mut combined_data: CombinedPayload = match bincode::deserialize(&payload) {
+    let mut combined_data = match CombinedPayload::from_bytes(&payload) {
         Ok(data) => data,
         Err(e) => {
             return P2PMessage::UpdatePermissionsResponse {
@@ -733,13 +3478,11 @@ async fn handle_update_permissions(
     };
     
     // Update the quota
-    combined_data
-        .permissions
-        .quotas
-        .insert(username.to_string(), new_quota);
+    let owner = combined_data.permissions.owner.clone();
+    combined_data.permissions.change_quota(&owner, username, new_quota);
     
     // Re-encode and save
-    let updated_payload = match bincode::serialize(&combined_data) {
+    let updated_payload = match combined_data.to_bytes() {
         Ok(data) => data,
         Err(e) => {
             return P2PMessage::UpdatePermissionsResponse {
@@ -760,133 +3503,128 @@ async fn handle_update_permissions(
     };
     
     // Save back to the same file
-    if let Err(e) = updated_carrier.save(&image_path) {
+    let mut png = Vec::new();
+    if let Err(e) = updated_carrier.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png) {
         return P2PMessage::UpdatePermissionsResponse {
             success: false,
-            message: format!("Failed to save updated image: {}", e),
+            message: format!("Failed to encode: {}", e),
         };
     }
-    
-    P2PMessage::UpdatePermissionsResponse {
-        success: true,
-        message: format!("Updated {} to {} views", username, new_quota),
-    }
-}
-
-/// Handle a thumbnail request - return a low-resolution blurred preview
-async fn handle_thumbnail_request(
-    image_id: &str,
-    image_store: &std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
-) -> P2PMessage {
-    use crate::lsb;
-    use crate::CombinedPayload;
-    use image::imageops;
-    use std::io::Cursor;
-
-    // Get the image path
-    let image_path = {
-        let store = image_store.read().await;
-        match store.get_image_path(image_id) {
-            Some(path) => path.clone(),
-            None => {
-                return P2PMessage::ThumbnailResponse {
-                    success: false,
-                    message: format!("Image {} not found", image_id),
-                    thumbnail: None,
-                };
-            }
-        }
-    };
-
-    // Read the encrypted image
-    let encrypted_data = match fs::read(&image_path) {
-        Ok(data) => data,
-        Err(e) => {
-            return P2PMessage::ThumbnailResponse {
-                success: false,
-                message: format!("Failed to read image: {}", e),
-                thumbnail: None,
-            };
-        }
-    };
-
-    // Load the image
-    let carrier_img = match image::load_from_memory(&encrypted_data) {
-        Ok(img) => img,
-        Err(e) => {
-            return P2PMessage::ThumbnailResponse {
-                success: false,
-                message: format!("Failed to load image: {}", e),
-                thumbnail: None,
-            };
-        }
-    };
-
-    // Decode embedded payload to get the actual image
-    let payload = match lsb::decode(&carrier_img) {
-        Ok(Some(data)) => data,
-        Ok(None) => {
-            return P2PMessage::ThumbnailResponse {
-                success: false,
-                message: "No embedded data found".to_string(),
-                thumbnail: None,
-            };
-        }
-        Err(e) => {
-            return P2PMessage::ThumbnailResponse {
-                success: false,
-                message: format!("Failed to decode: {}", e),
-                thumbnail: None,
-            };
+    image_store.write().await.invalidate_carrier(image_id);
+    if let Err(e) = write_atomic(&image_path, &png) {
+        return P2PMessage::UpdatePermissionsResponse {
+            success: false,
+            message: format!("Failed to save updated image: {:#}", e),
+        };
+    }
+    
+    P2PMessage::UpdatePermissionsResponse {
+        success: true,
+        message: format!("Updated {} to {} views", username, new_quota),
+    }
+}
+
+/// Handle a thumbnail request - return a low-resolution blurred preview
+async fn handle_thumbnail_request(
+    image_id: &str,
+    quality: ThumbnailQuality,
+    image_store: &std::sync::Arc<tokio::sync::RwLock<PeerImageStore>>,
+) -> P2PMessage {
+    // Get the image path
+    let image_path = {
+        let store = image_store.read().await;
+        match store.get_image_path(image_id) {
+            Some(path) => path.clone(),
+            None => {
+                return P2PMessage::ThumbnailResponse {
+                    success: false,
+                    message: format!("Image {} not found", image_id),
+                    thumbnail: None,
+                };
+            }
         }
     };
 
-    let combined_data: CombinedPayload = match bincode::deserialize(&payload) {
+    // Read the encrypted image
+    let encrypted_data = match fs::read(&image_path) {
         Ok(data) => data,
         Err(e) => {
             return P2PMessage::ThumbnailResponse {
                 success: false,
-                message: format!("Failed to deserialize: {}", e),
+                message: format!("Failed to read image: {}", e),
                 thumbnail: None,
             };
         }
     };
 
-    // Load the unified image from the payload
-    let actual_img = match image::load_from_memory(&combined_data.unified_image) {
-        Ok(img) => img,
+    let thumb_buf = match blurred_thumbnail(&encrypted_data, quality) {
+        Ok(thumbnail) => thumbnail,
         Err(e) => {
             return P2PMessage::ThumbnailResponse {
                 success: false,
-                message: format!("Failed to load embedded image: {}", e),
+                message: format!("{:#}", e),
                 thumbnail: None,
             };
         }
     };
 
-    // Create a low-resolution thumbnail (150x150) with blur
-    let thumbnail = actual_img.resize(150, 150, imageops::FilterType::Lanczos3);
-    // Apply heavy blur to make it a preview only (sigma=8.0)
-    let blurred = imageops::blur(&thumbnail, 8.0);
-
-    // Convert to PNG bytes
-    let mut thumb_buf = Cursor::new(Vec::new());
-    if let Err(e) = blurred.write_to(&mut thumb_buf, image::ImageFormat::Png) {
-        return P2PMessage::ThumbnailResponse {
-            success: false,
-            message: format!("Failed to encode thumbnail: {}", e),
-            thumbnail: None,
-        };
-    }
-
-    info!("Generated thumbnail for {} ({}x{} blurred)", image_id, 150, 150);
+    info!("Generated thumbnail for {} ({:?} blurred, {} bytes)", image_id, quality, thumb_buf.len());
     println!("[INFO] Generated thumbnail for {}", image_id);
 
     P2PMessage::ThumbnailResponse {
         success: true,
         message: "Thumbnail generated".to_string(),
-        thumbnail: Some(thumb_buf.into_inner()),
+        thumbnail: Some(thumb_buf),
+    }
+}
+
+/// Low-resolution, heavily blurred PNG of the image hidden in a carrier
+pub fn blurred_thumbnail(carrier_bytes: &[u8], quality: ThumbnailQuality) -> Result<Vec<u8>> {
+    use crate::lsb;
+    use crate::CombinedPayload;
+    use image::codecs::png::{FilterType, PngEncoder};
+    use image::{imageops, ImageEncoder};
+
+    let carrier_img = image::load_from_memory(carrier_bytes).context("Failed to load image")?;
+    // Decode embedded payload to get the actual image
+    let payload = lsb::decode(&carrier_img)
+        .context("Failed to decode")?
+        .context("No embedded data found")?;
+    let combined_data = CombinedPayload::from_bytes(&payload).context("Failed to deserialize")?;
+    let actual_img = image::load_from_memory(&combined_data.unified_image).context("Failed to load embedded image")?;
+
+    // Create a low-resolution thumbnail with blur
+    let size = quality.dimension();
+    let thumbnail = actual_img.resize(size, size, imageops::FilterType::Lanczos3);
+    // Apply heavy blur to make it a preview only (sigma=8.0 at full size)
+    let blurred = imageops::blur(&thumbnail, 8.0 * size as f32 / 150.0);
+
+    let mut thumb_buf = Vec::new();
+    let encoder = PngEncoder::new_with_quality(&mut thumb_buf, quality.compression(), FilterType::Adaptive);
+    encoder
+        .write_image(blurred.as_raw(), blurred.width(), blurred.height(), image::ColorType::Rgba8)
+        .context("Failed to encode thumbnail")?;
+    Ok(thumb_buf)
+}
+
+/// Views the directory says a grant token lets `user` be raised to on `owner`'s image
+///
+/// The first directory that answers decides (it asks its replicas itself).
+async fn verify_grant(directories: &[String], owner: &str, user: &str, image_id: &str, token: &str) -> Option<u32> {
+    let message = crate::directory_service::DirectoryMessage::VerifyGrant {
+        owner: owner.to_string(),
+        user: user.to_string(),
+        image_id: image_id.to_string(),
+        token: token.to_string(),
+    };
+    for directory in directories {
+        match crate::directory_service::send_directory_message(directory, message.clone()).await {
+            Ok(crate::directory_service::DirectoryMessage::VerifyGrantResponse { views }) => return views,
+            Ok(_) => warn!("Unexpected answer from {} verifying a grant", directory),
+            Err(e) => warn!("Could not verify grant with {}: {}", directory, e),
+        }
     }
+    None
 }
 
 /// Update permissions in a local image file (used for remote permission updates)
@@ -911,16 +3649,17 @@ fn update_local_image_permissions(
         .ok_or_else(|| anyhow::anyhow!("No embedded data found in image"))?;
 
     // Deserialize the combined payload
-    let mut combined_data: CombinedPayload = bincode::deserialize(&payload)
+    let mut combined_data = CombinedPayload::from_bytes(&payload)
         .context("Failed to deserialize payload")?;
 
-    // Update the quota for the specified user
-    combined_data.permissions.quotas.insert(user.to_string(), new_quota);
+    // Update the quota for the specified user (the owner's change, applied to our copy)
+    let owner = combined_data.permissions.owner.clone();
+    combined_data.permissions.change_quota(&owner, user, new_quota);
 
     info!("Updated local permissions for user {} to {} views", user, new_quota);
 
     // Re-serialize the updated payload
-    let updated_payload = bincode::serialize(&combined_data)
+    let updated_payload = combined_data.to_bytes()
         .context("Failed to serialize updated payload")?;
 
     // Re-encode into the carrier image
@@ -928,7 +3667,10 @@ fn update_local_image_permissions(
         .context("Failed to encode updated image")?;
 
     // Save the updated image back to disk
-    updated_carrier.save(image_path)
+    let mut png = Vec::new();
+    updated_carrier.write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .context("Failed to encode updated image as PNG")?;
+    write_atomic(image_path, &png)
         .with_context(|| format!("Failed to save updated image to {}", image_path.display()))?;
 
     info!("Successfully saved updated image to {}", image_path.display());
@@ -940,38 +3682,374 @@ fn update_local_image_permissions(
 // P2P CLIENT HELPERS
 // =============================================================================
 
+/// Idle connections are dropped after this long (well inside the server's idle timeout)
+const POOL_IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Idle connections kept per peer
+const POOL_MAX_IDLE_PER_PEER: usize = 4;
+
+/// Open connections not in use, by peer address, with when each was last used
+type IdleConnections = HashMap<String, Vec<(TcpStream, std::time::Instant)>>;
+
+static CONNECTION_POOL: std::sync::Mutex<Option<IdleConnections>> = std::sync::Mutex::new(None);
+
+/// A kept-alive connection to `peer_addr`, if one is idle and fresh
+fn checkout_connection(peer_addr: &str) -> Option<TcpStream> {
+    let mut pool = CONNECTION_POOL.lock().ok()?;
+    let idle = pool.get_or_insert_with(HashMap::new).get_mut(peer_addr)?;
+    while let Some((stream, last_used)) = idle.pop() {
+        if last_used.elapsed() < POOL_IDLE_TIMEOUT {
+            return Some(stream);
+        }
+    }
+    None
+}
+
+/// Keep a connection that just completed a request for the next one to `peer_addr`
+fn return_connection(peer_addr: &str, stream: TcpStream) {
+    let Ok(mut pool) = CONNECTION_POOL.lock() else {
+        return;
+    };
+    let idle = pool.get_or_insert_with(HashMap::new).entry(peer_addr.to_string()).or_default();
+    idle.retain(|(_, last_used)| last_used.elapsed() < POOL_IDLE_TIMEOUT);
+    if idle.len() < POOL_MAX_IDLE_PER_PEER {
+        idle.push((stream, std::time::Instant::now()));
+    }
+}
+
+/// Below this measured throughput, a peer gets `ThumbnailQuality::Tiny` previews
+pub const SLOW_LINK_BYTES_PER_SEC: u64 = 64 * 1024;
+
+/// Exchanges smaller than this are dominated by latency and say little about link speed
+const MIN_THROUGHPUT_SAMPLE_BYTES: usize = 16 * 1024;
+
+/// Weight of the newest sample in a peer's running throughput estimate
+const THROUGHPUT_SMOOTHING: f64 = 0.3;
+
+/// Smoothed bytes per second observed to each peer address
+static PEER_THROUGHPUT: std::sync::Mutex<Option<HashMap<String, f64>>> = std::sync::Mutex::new(None);
+
+/// Fold one exchange with `peer_addr` into its throughput estimate
+fn record_throughput(peer_addr: &str, bytes: usize, elapsed: std::time::Duration) {
+    if bytes < MIN_THROUGHPUT_SAMPLE_BYTES || elapsed.is_zero() {
+        return;
+    }
+    let sample = bytes as f64 / elapsed.as_secs_f64();
+    if let Ok(mut rates) = PEER_THROUGHPUT.lock() {
+        rates
+            .get_or_insert_with(HashMap::new)
+            .entry(peer_addr.to_string())
+            .and_modify(|rate| *rate += THROUGHPUT_SMOOTHING * (sample - *rate))
+            .or_insert(sample);
+    }
+}
+
+/// Observed throughput to `peer_addr` in bytes per second (None until a large enough exchange)
+pub fn peer_throughput(peer_addr: &str) -> Option<u64> {
+    let rates = PEER_THROUGHPUT.lock().ok()?;
+    rates.as_ref()?.get(peer_addr).map(|rate| *rate as u64)
+}
+
+/// Write one request frame and read the response frame
+async fn exchange_frames(stream: &mut TcpStream, msg_bytes: &[u8]) -> std::io::Result<Vec<u8>> {
+    stream.write_u32(msg_bytes.len() as u32).await?;
+    stream.write_all(msg_bytes).await?;
+    stream.flush().await?;
+    
+    let response_len = stream.read_u32().await?;
+    let mut response_buf = vec![0u8; response_len as usize];
+    stream.read_exact(&mut response_buf).await?;
+    Ok(response_buf)
+}
+
+/// Connect to a peer: hidden peers through their relay, others directly unless we use a relay ourselves
+async fn connect_peer(peer_addr: &str) -> Result<TcpStream> {
+    if let Some((relay, handle)) = parse_relay_address(peer_addr) {
+        return open_relay_stream(relay, handle).await;
+    }
+    match outbound_relay() {
+        Some(relay) => open_relay_stream(&relay, peer_addr).await,
+        None => Ok(TcpStream::connect(peer_addr).await?),
+    }
+}
+
+/// A peer's catalog as it changes, opened with `subscribe_catalog`
+///
+/// Holds its own connection to the peer; dropping it unsubscribes.
+pub struct CatalogSubscription {
+    peer_addr: String,
+    stream: TcpStream,
+}
+
+impl CatalogSubscription {
+    /// The next change to the catalog, or None once the peer ends the subscription
+    ///
+    /// Fails if the peer goes quiet for longer than its keepalives allow.
+    pub async fn next_event(&mut self) -> Result<Option<CatalogEvent>> {
+        loop {
+            let frame = async {
+                let len = self.stream.read_u32().await?;
+                let mut frame = vec![0u8; len as usize];
+                self.stream.read_exact(&mut frame).await?;
+                Ok::<_, std::io::Error>(frame)
+            };
+            let frame = match tokio::time::timeout(CATALOG_KEEPALIVE_INTERVAL * 3, frame).await {
+                Ok(Ok(frame)) => frame,
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_) => bail!("{} stopped sending catalog updates", self.peer_addr),
+            };
+            record_frame(TraceChannel::P2P, TraceDirection::Received, &self.peer_addr, &frame);
+            record_traffic(&self.peer_addr, 0, 4 + frame.len() as u64);
+
+            match serde_json::from_slice(&frame)? {
+                P2PMessage::CatalogUpdate { event: CatalogEvent::KeepAlive } => continue,
+                P2PMessage::CatalogUpdate { event } => return Ok(Some(event)),
+                _ => bail!("Unexpected message on a catalog subscription"),
+            }
+        }
+    }
+}
+
+/// Subscribe to a peer's catalog (peers advertising `CAP_CATALOG_UPDATES` only)
+///
+/// Returns the images the peer lists to us now, and the subscription its changes arrive on.
+pub async fn subscribe_catalog(peer_addr: &str, requesting_user: &str) -> Result<(Vec<ImageMetadata>, CatalogSubscription)> {
+    let message = serde_json::to_vec(&P2PMessage::SubscribeCatalog { requesting_user: requesting_user.to_string() })?;
+    record_frame(TraceChannel::P2P, TraceDirection::Sent, peer_addr, &message);
+
+    // Never pooled: the connection belongs to the subscription for as long as it lasts
+    let mut stream = connect_peer(peer_addr).await?;
+    let response = exchange_frames(&mut stream, &message).await?;
+    record_frame(TraceChannel::P2P, TraceDirection::Received, peer_addr, &response);
+    record_traffic(peer_addr, 4 + message.len() as u64, 4 + response.len() as u64);
+
+    match serde_json::from_slice(&response)? {
+        P2PMessage::CatalogSnapshot { images } => {
+            Ok((images, CatalogSubscription { peer_addr: peer_addr.to_string(), stream }))
+        }
+        P2PMessage::Busy { message, retry_after_secs } => bail!("{} - try again in {}s", message, retry_after_secs),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
 /// Send a P2P message and receive response
+///
+/// Connections are kept alive and reused for later messages to the same peer.
 pub async fn send_p2p_message(peer_addr: &str, message: P2PMessage) -> Result<P2PMessage> {
-    let mut stream = TcpStream::connect(peer_addr).await?;
+    if is_transfer(&message) {
+        check_daily_cap(peer_addr)?;
+    }
+    let _transfer = is_transfer(&message).then(ActiveTransfer::start);
     
     // Send message
     let msg_json = serde_json::to_string(&message)?;
     let msg_bytes = msg_json.as_bytes();
+    record_frame(TraceChannel::P2P, TraceDirection::Sent, peer_addr, msg_bytes);
     
-    stream.write_u32(msg_bytes.len() as u32).await?;
-    stream.write_all(msg_bytes).await?;
-    stream.flush().await?;
+    // A pooled connection may have been closed by the peer (older peers close after
+    // every response), in which case the request never reached it and is sent again
+    let started = std::time::Instant::now();
+    let pooled = match checkout_connection(peer_addr) {
+        Some(mut stream) => exchange_frames(&mut stream, msg_bytes).await.ok().map(|buf| (stream, buf)),
+        None => None,
+    };
+    let (stream, response_buf) = match pooled {
+        Some(exchanged) => exchanged,
+        None => {
+            let mut stream = connect_peer(peer_addr).await?;
+            let response_buf = exchange_frames(&mut stream, msg_bytes).await?;
+            (stream, response_buf)
+        }
+    };
+    record_throughput(peer_addr, msg_bytes.len() + response_buf.len(), started.elapsed());
     
-    // Read response
-    let response_len = stream.read_u32().await?;
-    let mut response_buf = vec![0u8; response_len as usize];
-    stream.read_exact(&mut response_buf).await?;
+    record_frame(TraceChannel::P2P, TraceDirection::Received, peer_addr, &response_buf);
+    record_traffic(peer_addr, 4 + msg_bytes.len() as u64, 4 + response_buf.len() as u64);
     
     let response: P2PMessage = serde_json::from_slice(&response_buf)?;
+    // A busy peer closes the connection after turning us away, so it isn't pooled
+    if let P2PMessage::Busy { message, retry_after_secs } = response {
+        bail!("{} - try again in {}s", message, retry_after_secs);
+    }
+    return_connection(peer_addr, stream);
     Ok(response)
 }
 
+/// How long a delivery waits for others to the same peer to share its connection
+const BUNDLE_WINDOW: std::time::Duration = std::time::Duration::from_millis(250);
+
+/// Most deliveries sent in one `DeliverBundle`
+const MAX_BUNDLE_ITEMS: usize = 16;
+
+/// A delivery waiting out the bundle window, and who is waiting for its response
+struct PendingDelivery {
+    item: BundledImage,
+    reply: tokio::sync::oneshot::Sender<Result<P2PMessage>>,
+}
+
+/// Deliveries waiting to be sent, by peer address and owner, each group tagged with the
+/// window that will send it
+type PendingDeliveries = HashMap<(String, String), (u64, Vec<PendingDelivery>)>;
+
+static PENDING_DELIVERIES: std::sync::Mutex<Option<PendingDeliveries>> = std::sync::Mutex::new(None);
+static NEXT_BUNDLE_WINDOW: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Send a `DeliverImage`, bundled with any other deliveries to the same peer made meanwhile
+///
+/// Deliveries from one owner to one peer within `BUNDLE_WINDOW` of the first go out as
+/// a single `DeliverBundle` over one connection, and each caller still gets the
+/// `DeliverImageResponse` (or `StorageFull`) for its own image. A delivery with no
+/// company is sent on its own, and peers that don't understand bundles get each image
+/// separately. Other messages are passed to `send_p2p_message` unchanged.
+pub async fn deliver_image(peer_addr: &str, message: P2PMessage) -> Result<P2PMessage> {
+    let P2PMessage::DeliverImage { from_owner, image_id, requested_views, encrypted_image, sha256, grant_seq } = message else {
+        return send_p2p_message(peer_addr, message).await;
+    };
+    let item = BundledImage { image_id, requested_views, encrypted_image, sha256, grant_seq };
+    let (reply, response) = tokio::sync::oneshot::channel();
+    let key = (peer_addr.to_string(), from_owner);
+
+    let (window, opened, full) = {
+        let mut pending = PENDING_DELIVERIES.lock().map_err(|_| anyhow::anyhow!("Delivery queue is unavailable"))?;
+        let (window, deliveries) = pending.get_or_insert_with(HashMap::new).entry(key.clone()).or_insert_with(|| {
+            (NEXT_BUNDLE_WINDOW.fetch_add(1, std::sync::atomic::Ordering::Relaxed), Vec::new())
+        });
+        deliveries.push(PendingDelivery { item, reply });
+        (*window, deliveries.len() == 1, deliveries.len() >= MAX_BUNDLE_ITEMS)
+    };
+    if full {
+        tokio::spawn(flush_deliveries(key, window));
+    } else if opened {
+        tokio::spawn(async move {
+            tokio::time::sleep(BUNDLE_WINDOW).await;
+            flush_deliveries(key, window).await;
+        });
+    }
+
+    response.await.map_err(|_| anyhow::anyhow!("Delivery to {} was dropped", peer_addr))?
+}
+
+/// Send the deliveries gathered in `window`, unless a full bundle already took them
+async fn flush_deliveries(key: (String, String), window: u64) {
+    let deliveries = match PENDING_DELIVERIES.lock() {
+        Ok(mut pending) => {
+            let pending = pending.get_or_insert_with(HashMap::new);
+            match pending.get(&key) {
+                Some((current, _)) if *current == window => pending.remove(&key).map(|(_, deliveries)| deliveries),
+                _ => None,
+            }
+        }
+        Err(_) => None,
+    };
+    let Some(deliveries) = deliveries else {
+        return;
+    };
+    let (peer_addr, from_owner) = key;
+
+    let acks = if deliveries.len() > 1 {
+        let items = deliveries.iter().map(|delivery| delivery.item.clone()).collect();
+        send_bundle(&peer_addr, &from_owner, items).await.unwrap_or_else(|e| {
+            warn!("Bundle of {} deliveries to {} failed, sending them one by one: {}", deliveries.len(), peer_addr, e);
+            Vec::new()
+        })
+    } else {
+        Vec::new()
+    };
+    let mut acks = acks.into_iter();
+    for delivery in deliveries {
+        let response = match acks.next().flatten() {
+            Some(ack) => Ok(ack),
+            None => send_p2p_message(&peer_addr, delivery.item.into_delivery(&from_owner)).await,
+        };
+        let _ = delivery.reply.send(response);
+    }
+}
+
+/// Send a `DeliverBundle` over a fresh connection and collect its acks
+///
+/// Stops reading at the first error; items it has no ack for are `None`, and an
+/// old peer that can't parse the bundle closes the connection before acking any.
+async fn send_bundle(peer_addr: &str, from_owner: &str, items: Vec<BundledImage>) -> Result<Vec<Option<P2PMessage>>> {
+    check_daily_cap(peer_addr)?;
+    let _transfer = ActiveTransfer::start();
+    let count = items.len();
+    let msg_bytes = serde_json::to_vec(&P2PMessage::DeliverBundle { from_owner: from_owner.to_string(), items })?;
+    record_frame(TraceChannel::P2P, TraceDirection::Sent, peer_addr, &msg_bytes);
+
+    let started = std::time::Instant::now();
+    let mut stream = connect_peer(peer_addr).await?;
+    stream.write_u32(msg_bytes.len() as u32).await?;
+    stream.write_all(&msg_bytes).await?;
+    stream.flush().await?;
+
+    let mut acks: Vec<Option<P2PMessage>> = (0..count).map(|_| None).collect();
+    let mut received_bytes = 0;
+    for _ in 0..count {
+        let frame = async {
+            let len = stream.read_u32().await?;
+            let mut frame = vec![0u8; len as usize];
+            stream.read_exact(&mut frame).await?;
+            Ok::<_, std::io::Error>(frame)
+        };
+        let frame = match frame.await {
+            Ok(frame) => frame,
+            Err(e) => {
+                warn!("Bundle to {} ended after {} of {} acks: {}", peer_addr, acks.iter().flatten().count(), count, e);
+                break;
+            }
+        };
+        received_bytes += 4 + frame.len();
+        record_frame(TraceChannel::P2P, TraceDirection::Received, peer_addr, &frame);
+        match serde_json::from_slice(&frame) {
+            Ok(P2PMessage::DeliverBundleAck { index, response }) if index < count => acks[index] = Some(*response),
+            Ok(P2PMessage::Busy { message, retry_after_secs }) => bail!("{} - try again in {}s", message, retry_after_secs),
+            _ => {
+                warn!("Unexpected answer to a delivery bundle from {}", peer_addr);
+                break;
+            }
+        }
+    }
+    record_throughput(peer_addr, msg_bytes.len() + received_bytes, started.elapsed());
+    record_traffic(peer_addr, 4 + msg_bytes.len() as u64, received_bytes as u64);
+
+    if acks.iter().all(Option::is_some) {
+        return_connection(peer_addr, stream);
+    }
+    Ok(acks)
+}
+
 /// Request an image from a peer
 pub async fn request_image_from_peer(
     peer_addr: &str,
     requesting_user: &str,
     image_id: &str,
     requested_views: u32,
+) -> Result<Vec<u8>> {
+    request_scaled_image_from_peer(peer_addr, requesting_user, image_id, requested_views, None, None, None).await
+}
+
+/// Request an image from a peer, downscaled to fit `max_dimension` if given
+///
+/// Owners from before size-limited requests ignore the limit and send the full image.
+/// A delegate fetching a grant for the owner passes their name as `approved_by`, and
+/// anyone raising a quota passes the accepted request's `grant_token`.
+pub async fn request_scaled_image_from_peer(
+    peer_addr: &str,
+    requesting_user: &str,
+    image_id: &str,
+    requested_views: u32,
+    max_dimension: Option<u32>,
+    approved_by: Option<&str>,
+    grant_token: Option<&str>,
 ) -> Result<Vec<u8>> {
     let message = P2PMessage::ImageRequest {
         requesting_user: requesting_user.to_string(),
         image_id: image_id.to_string(),
         requested_views,
+        requested_max_dimension: max_dimension,
+        approved_by: approved_by.map(str::to_string),
+        grant_token: grant_token.map(str::to_string),
     };
     
     let response = send_p2p_message(peer_addr, message).await?;
@@ -991,10 +4069,80 @@ pub async fn request_image_from_peer(
     }
 }
 
+/// Ask an owner to resend an image we were already granted
+pub async fn request_redelivery(peer_addr: &str, requesting_user: &str, image_id: &str) -> Result<Vec<u8>> {
+    let message = P2PMessage::RedeliverRequest {
+        requesting_user: requesting_user.to_string(),
+        image_id: image_id.to_string(),
+    };
+
+    match send_p2p_message(peer_addr, message).await? {
+        P2PMessage::ImageResponse {
+            success: true,
+            encrypted_image: Some(data),
+            ..
+        } => Ok(data),
+        P2PMessage::ImageResponse { message, .. } => bail!("Re-delivery refused: {}", message),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Check `user`'s grant on a received image with its owner, bringing our copy in line
+///
+/// A revocation is applied whenever the owner's copy grants no views. Any other difference
+/// is applied only when the owner's grant is newer than the one we hold; quota changes
+/// from owners on versions without grant numbers can't be told apart, so they're left alone.
+pub async fn reconcile_received_grant(peer_addr: &str, image_path: &Path, user: &str) -> Result<GrantReconciliation> {
+    let record = load_received_record(image_path)
+        .with_context(|| format!("No delivery record for {}", image_path.display()))?;
+    let message = P2PMessage::VerifyGrant {
+        image_id: record.image_id.clone(),
+        for_user: user.to_string(),
+    };
+    let (owner_views, owner_seq) = match send_p2p_message(peer_addr, message).await? {
+        P2PMessage::VerifyGrantResponse { found: false, .. } => return Ok(GrantReconciliation::NotShared),
+        P2PMessage::VerifyGrantResponse { quota, grant_seq, .. } => (quota.unwrap_or(0), grant_seq),
+        _ => bail!("Unexpected response type"),
+    };
+
+    // Read our copy only now, under its lock, so a view during the round trip isn't undone
+    let _carrier_lock = crate::viewing::lock_carrier(image_path).await;
+    let carrier = fs::read(image_path)
+        .with_context(|| format!("Failed to read {}", image_path.display()))?;
+    let permissions = embedded_permissions(&carrier).context("Failed to decode the image's permissions")?;
+    let local_views = permissions.quotas.get(user).copied().unwrap_or(0);
+    let dir = image_path.parent().unwrap_or(Path::new("."));
+    let key = crate::directory_service::qualified_image_id(&record.from_owner, &record.image_id);
+    let applied_seq = load_grant_ledger(dir).get(&key).copied().unwrap_or(0).max(permissions.grant_seq(user));
+
+    let outcome = if owner_views == 0 && local_views > 0 {
+        GrantReconciliation::Revoked
+    } else if owner_seq > applied_seq && owner_views != local_views {
+        GrantReconciliation::Updated { views: owner_views }
+    } else {
+        return Ok(GrantReconciliation::UpToDate);
+    };
+
+    update_local_image_permissions(&image_path.to_path_buf(), user, owner_views)?;
+    if owner_seq > 0 {
+        record_applied_grant(dir, &record.from_owner, &record.image_id, owner_seq)?;
+    }
+    info!("Reconciled {} with {}: {:?}", record.image_id, record.from_owner, outcome);
+    Ok(outcome)
+}
+
 /// List available images from a peer
 pub async fn list_peer_images(peer_addr: &str, requesting_user: &str) -> Result<Vec<ImageMetadata>> {
+    search_peer_images(peer_addr, requesting_user, None).await
+}
+
+/// List a peer's images matching `query` (best first), or all of them without one
+///
+/// Peers from before catalog search ignore the query and list everything.
+pub async fn search_peer_images(peer_addr: &str, requesting_user: &str, query: Option<&str>) -> Result<Vec<ImageMetadata>> {
     let message = P2PMessage::ListImages {
         requesting_user: requesting_user.to_string(),
+        query: query.map(str::to_string),
     };
     
     let response = send_p2p_message(peer_addr, message).await?;
@@ -1006,6 +4154,8 @@ pub async fn list_peer_images(peer_addr: &str, requesting_user: &str) -> Result<
 }
 
 /// Request a low-resolution thumbnail preview from a peer
+///
+/// Peers we've measured on a slow link are asked for a tiny preview instead.
 pub async fn request_thumbnail_from_peer(
     peer_addr: &str,
     requesting_user: &str,
@@ -1014,6 +4164,7 @@ pub async fn request_thumbnail_from_peer(
     let message = P2PMessage::ThumbnailRequest {
         requesting_user: requesting_user.to_string(),
         image_id: image_id.to_string(),
+        quality: ThumbnailQuality::for_peer(peer_addr),
     };
     
     let response = send_p2p_message(peer_addr, message).await?;
@@ -1031,4 +4182,122 @@ pub async fn request_thumbnail_from_peer(
         } => bail!("Thumbnail request failed: {}", message),
         _ => bail!("Unexpected response type"),
     }
-}
\ No newline at end of file
+}
+
+/// Tell an owner that a pending request to them was cancelled
+pub async fn notify_request_cancelled(
+    peer_addr: &str,
+    request_id: &str,
+    from_user: &str,
+    image_id: &str,
+) -> Result<()> {
+    let message = P2PMessage::RequestCancelled {
+        request_id: request_id.to_string(),
+        from_user: from_user.to_string(),
+        image_id: image_id.to_string(),
+    };
+    
+    let response = send_p2p_message(peer_addr, message).await?;
+    
+    match response {
+        P2PMessage::RequestCancelledResponse { success: true } => Ok(()),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Tell an owner that the image queued in one of their pending updates was evicted
+pub async fn notify_pending_image_evicted(
+    peer_addr: &str,
+    update_id: &str,
+    target_user: &str,
+    image_id: &str,
+) -> Result<()> {
+    let message = P2PMessage::PendingImageEvicted {
+        update_id: update_id.to_string(),
+        target_user: target_user.to_string(),
+        image_id: image_id.to_string(),
+    };
+    
+    let response = send_p2p_message(peer_addr, message).await?;
+    
+    match response {
+        P2PMessage::PendingImageEvictedResponse { success: true } => Ok(()),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Report a denied view attempt directly to the owner's P2P server
+pub async fn send_access_denial(peer_addr: &str, denial: AccessDenial) -> Result<()> {
+    let message = P2PMessage::AccessDeniedReport { denial };
+    
+    let response = send_p2p_message(peer_addr, message).await?;
+    
+    match response {
+        P2PMessage::AccessDeniedReportResponse { success: true } => Ok(()),
+        P2PMessage::AccessDeniedReportResponse { success: false } => bail!("Owner rejected the report"),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Ask a recipient to securely delete their copy of one of our images
+pub async fn send_remote_wipe(peer_addr: &str, from_owner: &str, image_id: &str) -> Result<String> {
+    let message = P2PMessage::RemoteWipe {
+        from_owner: from_owner.to_string(),
+        image_id: image_id.to_string(),
+    };
+
+    match send_p2p_message(peer_addr, message).await? {
+        P2PMessage::RemoteWipeResponse { success: true, message } => Ok(message),
+        P2PMessage::RemoteWipeResponse { success: false, message } => bail!("Wipe failed: {}", message),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Tell an owner the outcome of a wipe they queued while we were offline
+pub async fn send_wipe_ack(peer_addr: &str, from_user: &str, image_id: &str, success: bool, message: &str) -> Result<()> {
+    let message = P2PMessage::RemoteWipeAck {
+        from_user: from_user.to_string(),
+        image_id: image_id.to_string(),
+        success,
+        message: message.to_string(),
+    };
+
+    match send_p2p_message(peer_addr, message).await? {
+        P2PMessage::RemoteWipeAckResponse { success: true } => Ok(()),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Ask an owner whether a local copy with `content_hash` matches the image they share
+pub async fn probe_peer_image(
+    peer_addr: &str,
+    requesting_user: &str,
+    image_id: &str,
+    content_hash: &str,
+) -> Result<bool> {
+    let message = P2PMessage::HaveImage {
+        requesting_user: requesting_user.to_string(),
+        image_id: image_id.to_string(),
+        content_hash: content_hash.to_string(),
+    };
+    
+    let response = send_p2p_message(peer_addr, message).await?;
+    
+    match response {
+        P2PMessage::HaveImageResponse { identical } => Ok(identical),
+        _ => bail!("Unexpected response type"),
+    }
+}
+
+/// Check that a peer's P2P server is responsive, returning the round trip
+pub async fn ping_peer(peer_addr: &str, from_user: &str) -> Result<std::time::Duration> {
+    let started = std::time::Instant::now();
+    let message = P2PMessage::Ping {
+        from_user: from_user.to_string(),
+    };
+
+    match send_p2p_message(peer_addr, message).await? {
+        P2PMessage::Pong { .. } => Ok(started.elapsed()),
+        _ => bail!("Unexpected response type"),
+    }
+}
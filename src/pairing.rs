@@ -0,0 +1,227 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::sync::RwLock;
+
+use crate::audit_log::{audit, AuditAction, AuditRecord};
+use crate::grant_package::grant_in_place;
+use crate::p2p_protocol::{process_p2p_message, send_p2p_message, sha256_hex, P2PMessage, PairedGrant, PeerImageStore};
+use crate::viewing::lock_carrier;
+
+// =============================================================================
+// LOCAL PAIRING
+// =============================================================================
+//
+// Two devices on the same desk can skip the directory's request and approval cycle: the
+// owner offers views of one image under a short-lived code, the requester types it in (or
+// scans the QR code carrying it) and the owner's peer grants the views and hands the
+// carrier back in the same exchange. Each code works once, and a handful of wrong guesses
+// withdraws every open code.
+
+/// Digits in a pairing code
+pub const PAIRING_CODE_DIGITS: u32 = 6;
+
+/// How long a pairing code can be redeemed for
+pub const PAIRING_CODE_TTL: Duration = Duration::from_secs(2 * 60);
+
+/// Wrong codes tried before every open code is withdrawn
+const MAX_FAILED_REDEMPTIONS: u32 = 5;
+
+/// Prefix of pairing links, e.g. `p2ppair://192.168.1.20:8001/042917`
+pub const PAIRING_LINK_PREFIX: &str = "p2ppair://";
+
+/// Views of one image offered under a pairing code
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PairingOffer {
+    pub code: String,
+    pub image_id: String,
+    pub views: u32,
+    pub expires_at: SystemTime,
+}
+
+impl PairingOffer {
+    /// Link for the QR code: where to redeem the code, and the code
+    pub fn link(&self, p2p_address: &str) -> String {
+        format!("{}{}/{}", PAIRING_LINK_PREFIX, p2p_address, self.code)
+    }
+}
+
+/// The owner's open pairing codes
+#[derive(Debug, Default)]
+pub struct PairingOffers {
+    offers: HashMap<String, PairingOffer>,
+    /// Wrong codes tried since the last one that worked
+    failed: u32,
+}
+
+impl PairingOffers {
+    /// Offer `views` of `image_id` under a new code
+    pub fn offer(&mut self, image_id: &str, views: u32) -> PairingOffer {
+        let now = SystemTime::now();
+        self.offers.retain(|_, offer| offer.expires_at > now);
+        let mut rng = rand::thread_rng();
+        let code = loop {
+            let code = format!(
+                "{:0width$}",
+                rng.gen_range(0..10u32.pow(PAIRING_CODE_DIGITS)),
+                width = PAIRING_CODE_DIGITS as usize
+            );
+            if !self.offers.contains_key(&code) {
+                break code;
+            }
+        };
+        let offer = PairingOffer { code: code.clone(), image_id: image_id.to_string(), views, expires_at: now + PAIRING_CODE_TTL };
+        self.offers.insert(code, offer.clone());
+        offer
+    }
+
+    /// Withdraw a code before it's used, returning whether it was open
+    pub fn withdraw(&mut self, code: &str) -> bool {
+        self.offers.remove(code).is_some()
+    }
+
+    /// Take the offer under `code`, which can't be redeemed again
+    fn redeem(&mut self, code: &str) -> Result<PairingOffer> {
+        let now = SystemTime::now();
+        self.offers.retain(|_, offer| offer.expires_at > now);
+        if let Some(offer) = self.offers.remove(code.trim()) {
+            self.failed = 0;
+            return Ok(offer);
+        }
+
+        self.failed += 1;
+        if self.failed >= MAX_FAILED_REDEMPTIONS {
+            warn!("Withdrew {} pairing code(s) after {} wrong guesses", self.offers.len(), self.failed);
+            self.offers.clear();
+            self.failed = 0;
+            bail!("Too many wrong codes - ask the owner for a new one");
+        }
+        bail!("Unknown or expired pairing code")
+    }
+}
+
+/// Where to redeem a pairing and its code, from a `p2ppair://address/code` link or `address code`
+pub fn parse_pairing(text: &str) -> Result<(String, String)> {
+    let text = text.trim();
+    let (address, code) = match text.strip_prefix(PAIRING_LINK_PREFIX) {
+        Some(link) => link.rsplit_once('/').context("Pairing link has no code")?,
+        None => text.split_once(char::is_whitespace).context("Enter the owner's address and the code")?,
+    };
+    let (address, code) = (address.trim(), code.trim());
+    if address.is_empty() {
+        bail!("Pairing is missing the owner's address");
+    }
+    if code.len() != PAIRING_CODE_DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        bail!("A pairing code is {} digits", PAIRING_CODE_DIGITS);
+    }
+    Ok((address.to_string(), code.to_string()))
+}
+
+/// Owner side of `RedeemPairing`: grant the views offered under `code` and hand over the carrier
+pub(crate) async fn handle_redeem_pairing(
+    owner: &str,
+    code: &str,
+    requesting_user: &str,
+    image_store: &Arc<RwLock<PeerImageStore>>,
+) -> P2PMessage {
+    let refused = |message: String| P2PMessage::RedeemPairingResponse { success: false, message, grant: None };
+
+    let (offer, carrier_path, audit_log): (PairingOffer, PathBuf, Option<PathBuf>) = {
+        let mut store = image_store.write().await;
+        let offer = match store.pairing_offers().redeem(code) {
+            Ok(offer) => offer,
+            Err(e) => return refused(e.to_string()),
+        };
+        let Some(path) = store.get_image_path(&offer.image_id).cloned() else {
+            return refused(format!("'{}' is no longer shared", offer.image_id));
+        };
+        (offer, path, store.get_audit_log_path().cloned())
+    };
+
+    let granted = {
+        // The grant is written into the owner's copy, which may be being viewed
+        let _carrier_lock = lock_carrier(&carrier_path).await;
+        grant_in_place(&carrier_path, owner, requesting_user, offer.views)
+    };
+    image_store.write().await.invalidate_carrier(&offer.image_id);
+
+    let (response, message) = match granted {
+        Ok((image_id, grant_seq, carrier)) => {
+            let message = format!("Paired: {} views of '{}' for {}", offer.views, image_id, requesting_user);
+            info!("{} (grant #{})", message, grant_seq);
+            let grant = PairedGrant {
+                from_owner: owner.to_string(),
+                image_id,
+                views: offer.views,
+                grant_seq,
+                sha256: sha256_hex(&carrier),
+                encrypted_image: carrier,
+            };
+            (P2PMessage::RedeemPairingResponse { success: true, message: message.clone(), grant: Some(grant) }, message)
+        }
+        Err(e) => {
+            let message = format!("Could not grant the paired views: {}", e);
+            (refused(message.clone()), message)
+        }
+    };
+    audit(audit_log.as_deref(), AuditRecord {
+        action: AuditAction::Grant,
+        recipient: requesting_user.to_string(),
+        image_id: offer.image_id,
+        views: Some(offer.views),
+        success: matches!(response, P2PMessage::RedeemPairingResponse { success: true, .. }),
+        message,
+        approved_by: None,
+    });
+    response
+}
+
+/// What redeeming a pairing code brought in
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RedeemedPairing {
+    pub from_owner: String,
+    pub image_id: String,
+    pub views: u32,
+    /// How the delivery was stored
+    pub message: String,
+}
+
+/// Redeem `code` with the owner's peer at `peer_addr` and store the image in `received/`
+///
+/// The carrier goes through the same checks as a `DeliverImage` - replayed grants are
+/// refused and an image already held has the views merged in.
+pub async fn redeem_pairing(
+    peer_addr: &str,
+    code: &str,
+    username: &str,
+    image_store: &Arc<RwLock<PeerImageStore>>,
+) -> Result<RedeemedPairing> {
+    let request = P2PMessage::RedeemPairing { code: code.to_string(), requesting_user: username.to_string() };
+    let grant = match send_p2p_message(peer_addr, request).await? {
+        P2PMessage::RedeemPairingResponse { success: true, grant: Some(grant), .. } => grant,
+        P2PMessage::RedeemPairingResponse { message, .. } => bail!("{}", message),
+        _ => bail!("Unexpected response to the pairing code (is the owner running an older version?)"),
+    };
+
+    let delivery = P2PMessage::DeliverImage {
+        from_owner: grant.from_owner.clone(),
+        image_id: grant.image_id.clone(),
+        requested_views: grant.views,
+        encrypted_image: grant.encrypted_image,
+        sha256: Some(grant.sha256),
+        grant_seq: Some(grant.grant_seq),
+    };
+    match process_p2p_message(delivery, username.to_string(), image_store.clone()).await? {
+        P2PMessage::DeliverImageResponse { success: true, message, .. } => {
+            info!("Paired with {} for {} ({} views)", grant.from_owner, grant.image_id, grant.views);
+            Ok(RedeemedPairing { from_owner: grant.from_owner, image_id: grant.image_id, views: grant.views, message })
+        }
+        P2PMessage::DeliverImageResponse { message, .. } => bail!("Delivery refused: {}", message),
+        _ => bail!("Unexpected response to the delivery"),
+    }
+}
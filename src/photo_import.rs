@@ -0,0 +1,281 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::fsscan::{is_image_file, IMAGE_EXTENSIONS};
+use crate::ImageAnnotations;
+
+// =============================================================================
+// PHOTO LIBRARY IMPORT
+// =============================================================================
+
+/// File extensions picked up from a library
+pub const IMPORTABLE_EXTENSIONS: &[&str] = IMAGE_EXTENSIONS;
+
+/// Folders inside a library that hold our own output, never originals
+const SKIPPED_FOLDERS: &[&str] = &["encrypted", "received"];
+
+/// EXIF tags read from IFD0 and the Exif sub-IFD
+const TAG_IMAGE_DESCRIPTION: u16 = 0x010E;
+const TAG_DATE_TIME: u16 = 0x0132;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_XP_TITLE: u16 = 0x9C9B;
+const TAG_XP_COMMENT: u16 = 0x9C9C;
+const TAG_XP_KEYWORDS: u16 = 0x9C9E;
+
+/// What a photo's EXIF block says about it
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PhotoExif {
+    pub caption: Option<String>,
+    /// Capture date as `YYYY-MM-DD`
+    pub taken: Option<String>,
+    pub keywords: Vec<String>,
+}
+
+/// A photo found in a library, with what we could learn about it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LibraryPhoto {
+    pub path: PathBuf,
+    /// Folder it sits in relative to the library root (None at the root)
+    pub folder: Option<String>,
+    /// Album it's grouped into, if grouping by folder
+    pub album: Option<String>,
+    pub exif: PhotoExif,
+}
+
+impl LibraryPhoto {
+    /// Caption to encrypt with: the EXIF caption (or file name), then album, date and keywords
+    ///
+    /// Shared catalogs describe an image by its caption, so all of it is searchable.
+    pub fn caption(&self) -> String {
+        let title = self.exif.caption.clone().unwrap_or_else(|| {
+            self.path.file_stem().unwrap_or_default().to_string_lossy().into_owned()
+        });
+        let details: Vec<&str> = [self.album.as_deref(), self.exif.taken.as_deref()].into_iter().flatten().collect();
+        let mut caption = if details.is_empty() { title } else { format!("{} ({})", title, details.join(", ")) };
+        for keyword in &self.exif.keywords {
+            caption.push_str(&format!(" #{}", keyword.replace(' ', "-")));
+        }
+        caption
+    }
+
+    pub fn annotations(&self) -> ImageAnnotations {
+        ImageAnnotations { caption: Some(self.caption()), ..Default::default() }
+    }
+
+    /// File name for the encrypted copy, prefixed with its folder so same-named photos don't collide
+    pub fn output_name(&self) -> String {
+        let file_name = self.path.file_name().unwrap_or_default().to_string_lossy();
+        match &self.folder {
+            Some(folder) => format!("encrypted_{}_{}", slug(folder), file_name),
+            None => format!("encrypted_{}", file_name),
+        }
+    }
+}
+
+/// Every importable photo under `root`, in path order
+///
+/// Hidden entries and our own `encrypted`/`received` folders are skipped. With
+/// `albums`, each photo is grouped into an album named after its folder.
+pub fn scan_library(root: &Path, albums: bool) -> Result<Vec<LibraryPhoto>> {
+    let mut photos = Vec::new();
+    let mut folders = vec![root.to_path_buf()];
+    while let Some(folder) = folders.pop() {
+        let entries = fs::read_dir(&folder).with_context(|| format!("Failed to read {}", folder.display()))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                if !SKIPPED_FOLDERS.contains(&name.as_str()) {
+                    folders.push(path);
+                }
+            } else if is_image_file(&path) {
+                let relative = folder.strip_prefix(root).unwrap_or(Path::new(""));
+                let folder = (!relative.as_os_str().is_empty()).then(|| {
+                    relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join(" / ")
+                });
+                let exif = fs::read(&path).map(|data| read_exif(&data)).unwrap_or_default();
+                photos.push(LibraryPhoto { album: folder.clone().filter(|_| albums), folder, path, exif });
+            }
+        }
+    }
+    photos.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(photos)
+}
+
+/// Lowercase letters and digits, with runs of anything else collapsed to `-`
+fn slug(text: &str) -> String {
+    let mut slug = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() {
+            slug.push(c.to_ascii_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+// =============================================================================
+// EXIF READING
+// =============================================================================
+
+/// Caption, capture date and keywords from a JPEG or PNG's EXIF block (empty if it has none)
+pub fn read_exif(data: &[u8]) -> PhotoExif {
+    let Some(tiff) = find_tiff(data).and_then(Tiff::new) else {
+        return PhotoExif::default();
+    };
+    let ifd0 = tiff.ifd(tiff.first_ifd);
+    let exif_ifd = ifd0.get(&TAG_EXIF_IFD)
+        .and_then(|value| tiff.u32(value, 0))
+        .map(|offset| tiff.ifd(offset as usize))
+        .unwrap_or_default();
+
+    let caption = ifd0.get(&TAG_IMAGE_DESCRIPTION).and_then(|value| ascii(value))
+        .or_else(|| ifd0.get(&TAG_XP_TITLE).and_then(|value| utf16(value)))
+        .or_else(|| ifd0.get(&TAG_XP_COMMENT).and_then(|value| utf16(value)));
+    let taken = exif_ifd.get(&TAG_DATE_TIME_ORIGINAL)
+        .or_else(|| ifd0.get(&TAG_DATE_TIME))
+        .and_then(|value| ascii(value))
+        .and_then(|date_time| exif_date(&date_time));
+    let keywords = ifd0.get(&TAG_XP_KEYWORDS)
+        .and_then(|value| utf16(value))
+        .map(|keywords| {
+            keywords.split([';', ',']).map(str::trim).filter(|k| !k.is_empty()).map(str::to_string).collect()
+        })
+        .unwrap_or_default();
+
+    PhotoExif { caption, taken, keywords }
+}
+
+/// The TIFF structure holding EXIF tags, from a JPEG APP1 segment or a PNG eXIf chunk
+fn find_tiff(data: &[u8]) -> Option<&[u8]> {
+    if data.starts_with(&[0xFF, 0xD8]) {
+        let mut at = 2;
+        while at + 4 <= data.len() && data[at] == 0xFF {
+            let marker = data[at + 1];
+            // Image data follows start-of-scan; nothing after it is metadata
+            if marker == 0xDA {
+                return None;
+            }
+            let length = u16::from_be_bytes([data[at + 2], data[at + 3]]) as usize;
+            let segment = data.get(at + 4..at + 2 + length)?;
+            if marker == 0xE1 && segment.starts_with(b"Exif\0\0") {
+                return Some(&segment[6..]);
+            }
+            at += 2 + length;
+        }
+        None
+    } else if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        let mut at = 8;
+        while at + 8 <= data.len() {
+            let length = u32::from_be_bytes(data[at..at + 4].try_into().ok()?) as usize;
+            let chunk = data.get(at + 8..at + 8 + length)?;
+            match &data[at + 4..at + 8] {
+                b"eXIf" => return Some(chunk),
+                b"IDAT" | b"IEND" => return None,
+                _ => at += 12 + length,
+            }
+        }
+        None
+    } else {
+        None
+    }
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+    first_ifd: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        let mut tiff = Self { data, little_endian, first_ifd: 0 };
+        tiff.first_ifd = tiff.u32(data, 4)? as usize;
+        Some(tiff)
+    }
+
+    fn u16(&self, bytes: &[u8], at: usize) -> Option<u16> {
+        let bytes = bytes.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) })
+    }
+
+    fn u32(&self, bytes: &[u8], at: usize) -> Option<u32> {
+        let bytes = bytes.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(bytes) } else { u32::from_be_bytes(bytes) })
+    }
+
+    /// Raw value bytes of every entry in the IFD at `offset` (empty if it's unreadable)
+    fn ifd(&self, offset: usize) -> HashMap<u16, &'a [u8]> {
+        let mut entries = HashMap::new();
+        let count = self.u16(self.data, offset).unwrap_or(0) as usize;
+        for i in 0..count {
+            let entry = offset + 2 + i * 12;
+            let (Some(tag), Some(kind), Some(items)) =
+                (self.u16(self.data, entry), self.u16(self.data, entry + 2), self.u32(self.data, entry + 4))
+            else {
+                break;
+            };
+            let item_size = match kind {
+                1 | 2 | 6 | 7 => 1,
+                3 | 8 => 2,
+                4 | 9 | 11 => 4,
+                5 | 10 | 12 => 8,
+                _ => continue,
+            };
+            let size = items as usize * item_size;
+            // Values of up to four bytes sit in the entry itself, larger ones at an offset
+            let start = if size <= 4 {
+                entry + 8
+            } else {
+                match self.u32(self.data, entry + 8) {
+                    Some(start) => start as usize,
+                    None => continue,
+                }
+            };
+            if let Some(value) = self.data.get(start..start + size) {
+                entries.insert(tag, value);
+            }
+        }
+        entries
+    }
+}
+
+/// A NUL-terminated ASCII value, or None if it's blank
+fn ascii(value: &[u8]) -> Option<String> {
+    let end = value.iter().position(|&b| b == 0).unwrap_or(value.len());
+    let text = String::from_utf8_lossy(&value[..end]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// A Windows `XP*` value: UTF-16LE whatever the file's byte order, NUL-terminated
+fn utf16(value: &[u8]) -> Option<String> {
+    let units: Vec<u16> = value.chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .take_while(|&unit| unit != 0)
+        .collect();
+    let text = String::from_utf16_lossy(&units).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// `YYYY-MM-DD` from an EXIF `YYYY:MM:DD HH:MM:SS` timestamp (cameras write zeros when unset)
+fn exif_date(date_time: &str) -> Option<String> {
+    let date = date_time.get(..10)?.replace(':', "-");
+    let parts: Vec<&str> = date.split('-').collect();
+    let valid = parts.len() == 3
+        && parts.iter().all(|part| part.chars().all(|c| c.is_ascii_digit()))
+        && parts[0] != "0000";
+    valid.then_some(date)
+}
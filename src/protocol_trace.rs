@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+// =============================================================================
+// PROTOCOL TRACE
+// =============================================================================
+
+/// Entries kept when tracing is enabled without an explicit capacity
+pub const DEFAULT_TRACE_CAPACITY: usize = 200;
+
+/// Frames larger than this are recorded by type and size only
+const MAX_CAPTURED_FRAME_BYTES: usize = 64 * 1024;
+
+/// Byte arrays longer than this are replaced with their length
+const MAX_ARRAY_ITEMS: usize = 32;
+
+/// Strings longer than this are cut short
+const MAX_STRING_CHARS: usize = 256;
+
+/// Field names whose values are never recorded
+const REDACTED_FIELDS: &[&str] = &["token", "secret"];
+
+/// Which protocol a traced message belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceChannel {
+    Directory,
+    P2P,
+}
+
+/// Whether we sent or received a traced message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TraceDirection {
+    Sent,
+    Received,
+}
+
+/// One recorded message, sanitized for sharing in bug reports
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub timestamp: SystemTime,
+    pub channel: TraceChannel,
+    pub direction: TraceDirection,
+    /// Address of the other end of the connection
+    pub peer: String,
+    /// Message variant, e.g. `QueryPeers`
+    pub kind: String,
+    pub size_bytes: usize,
+    /// Message contents with secrets redacted and blobs truncated (None for large frames)
+    pub body: Option<Value>,
+}
+
+struct TraceBuffer {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+static TRACE_ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACE: Mutex<TraceBuffer> = Mutex::new(TraceBuffer {
+    capacity: DEFAULT_TRACE_CAPACITY,
+    entries: VecDeque::new(),
+});
+
+/// Start recording the last `capacity` messages, discarding anything recorded before
+pub fn enable_trace(capacity: usize) {
+    if let Ok(mut trace) = TRACE.lock() {
+        trace.capacity = capacity.max(1);
+        trace.entries.clear();
+    }
+    TRACE_ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Stop recording (entries recorded so far are kept until the next `enable_trace`)
+pub fn disable_trace() {
+    TRACE_ENABLED.store(false, Ordering::Relaxed);
+}
+
+pub fn trace_enabled() -> bool {
+    TRACE_ENABLED.load(Ordering::Relaxed)
+}
+
+/// Recorded messages, oldest first
+pub fn trace_snapshot() -> Vec<TraceEntry> {
+    TRACE
+        .lock()
+        .map(|trace| trace.entries.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Record a JSON frame as it goes over the wire (a no-op unless tracing is enabled)
+pub(crate) fn record_frame(channel: TraceChannel, direction: TraceDirection, peer: &str, frame: &[u8]) {
+    if !trace_enabled() {
+        return;
+    }
+
+    let (kind, body) = if frame.len() > MAX_CAPTURED_FRAME_BYTES {
+        (frame_kind(frame), None)
+    } else {
+        match serde_json::from_slice::<Value>(frame) {
+            Ok(value) => (value_kind(&value), Some(sanitize(value, None))),
+            Err(_) => ("<unparseable>".to_string(), None),
+        }
+    };
+
+    let entry = TraceEntry {
+        timestamp: SystemTime::now(),
+        channel,
+        direction,
+        peer: peer.to_string(),
+        kind,
+        size_bytes: frame.len(),
+        body,
+    };
+
+    if let Ok(mut trace) = TRACE.lock() {
+        if trace.entries.len() >= trace.capacity {
+            trace.entries.pop_front();
+        }
+        trace.entries.push_back(entry);
+    }
+}
+
+/// Variant name of an externally tagged enum value
+fn value_kind(value: &Value) -> String {
+    match value {
+        Value::Object(map) => map.keys().next().cloned().unwrap_or_default(),
+        Value::String(name) => name.clone(),
+        _ => "<unknown>".to_string(),
+    }
+}
+
+/// Variant name read from the start of a frame, without parsing the rest
+fn frame_kind(frame: &[u8]) -> String {
+    let head = &frame[..frame.len().min(128)];
+    String::from_utf8_lossy(head)
+        .trim_start_matches(['{', '"'])
+        .split('"')
+        .next()
+        .unwrap_or("<unknown>")
+        .to_string()
+}
+
+/// Redact secrets and shrink blobs so entries stay small and safe to share
+fn sanitize(value: Value, field: Option<&str>) -> Value {
+    if field.is_some_and(|name| REDACTED_FIELDS.contains(&name)) {
+        return Value::String("<redacted>".to_string());
+    }
+
+    match value {
+        Value::Array(items) if items.len() > MAX_ARRAY_ITEMS && items.iter().all(Value::is_number) => {
+            Value::String(format!("<{} bytes>", items.len()))
+        }
+        Value::Array(items) => Value::Array(items.into_iter().map(|item| sanitize(item, None)).collect()),
+        Value::Object(map) => Value::Object(
+            map.into_iter()
+                .map(|(key, item)| {
+                    let item = sanitize(item, Some(&key));
+                    (key, item)
+                })
+                .collect(),
+        ),
+        Value::String(text) if text.chars().count() > MAX_STRING_CHARS => {
+            let cut: String = text.chars().take(MAX_STRING_CHARS).collect();
+            Value::String(format!("{}… ({} chars)", cut, text.chars().count()))
+        }
+        other => other,
+    }
+}
@@ -332,6 +332,12 @@ pub struct RaftState {
     pub consecutive_failed_elections: u32, // NEW: Track failed election attempts
 }
 
+impl Default for RaftState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RaftState {
     pub fn new() -> Self {
         Self {
@@ -420,6 +426,7 @@ impl RaftNode {
         // Request votes from all peers
         let mut vote_count = 1; // We already voted for ourselves
         let mut failed_peers = 0; // NEW: Track failed peer connections
+        #[allow(clippy::manual_div_ceil)]
         let majority = (self.config.peers.len() + 1) / 2 + 1;
 
         for peer_addr in &self.config.peers {
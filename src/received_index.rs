@@ -0,0 +1,141 @@
+use anyhow::Result;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::fsscan::{self, ImageFile};
+use crate::p2p_protocol::{embedded_payload, load_received_record};
+use crate::ProvenanceHop;
+
+// =============================================================================
+// RECEIVED IMAGE INDEX
+// =============================================================================
+
+/// File in the received images folder caching what each image carries
+pub const RECEIVED_INDEX_FILE: &str = "received_index.json";
+
+/// What a received image carries, cached so listing doesn't decode every carrier
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedImage {
+    pub file_name: String,
+    /// Owner named in the embedded permissions (None if they can't be decoded)
+    pub from_owner: Option<String>,
+    /// Views each user has left, as embedded
+    pub quotas: HashMap<String, u32>,
+    /// Who passed it on after the owner, as embedded
+    #[serde(default)]
+    pub provenance: Vec<ProvenanceHop>,
+    /// When it was delivered (file modification time if there's no integrity record)
+    pub received_at: Option<SystemTime>,
+    pub sha256: Option<String>,
+    pub size_bytes: u64,
+    /// Size and modification time the entry was read at, to spot changed files
+    file_len: u64,
+    file_modified: Option<SystemTime>,
+}
+
+impl IndexedImage {
+    /// Views `user` has left on this image
+    pub fn views_remaining(&self, user: &str) -> u32 {
+        self.quotas.get(user).copied().unwrap_or(0)
+    }
+
+    fn read(file: &ImageFile) -> Self {
+        let payload = fs::read(&file.path).ok().and_then(|data| embedded_payload(&data));
+        let record = load_received_record(&file.path);
+        IndexedImage {
+            file_name: file.file_name.clone(),
+            from_owner: payload.as_ref().map(|p| p.permissions.owner.clone()),
+            quotas: payload.as_ref().map(|p| p.permissions.quotas.clone()).unwrap_or_default(),
+            provenance: payload.map(|p| p.provenance).unwrap_or_default(),
+            received_at: record.as_ref().map(|r| r.received_at).or(file.modified),
+            sha256: record.as_ref().map(|r| r.sha256.clone()),
+            size_bytes: record.map(|r| r.size_bytes).unwrap_or(file.size_bytes),
+            file_len: file.size_bytes,
+            file_modified: file.modified,
+        }
+    }
+
+    fn is_current(&self, file: &ImageFile) -> bool {
+        self.file_len == file.size_bytes && self.file_modified == file.modified
+    }
+}
+
+/// The cached entries of a received images folder, by file name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReceivedIndex {
+    entries: BTreeMap<String, IndexedImage>,
+}
+
+impl ReceivedIndex {
+    fn load(dir: &Path) -> Self {
+        fs::read_to_string(dir.join(RECEIVED_INDEX_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default()
+    }
+
+    fn save(&self, dir: &Path) -> Result<()> {
+        fs::write(dir.join(RECEIVED_INDEX_FILE), serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Bring the index of `dir` up to date and return it
+    ///
+    /// Only new or changed files are decoded; `force_rescan` decodes every file again.
+    pub fn refresh(dir: &Path, force_rescan: bool) -> Self {
+        let mut index = if force_rescan { Self::default() } else { Self::load(dir) };
+        let mut entries = BTreeMap::new();
+        let mut changed = force_rescan;
+
+        for file in fsscan::scan_images(dir) {
+            let entry = match index.entries.remove(&file.file_name) {
+                Some(entry) if entry.is_current(&file) => entry,
+                _ => {
+                    changed = true;
+                    IndexedImage::read(&file)
+                }
+            };
+            entries.insert(file.file_name, entry);
+        }
+        changed |= !index.entries.is_empty();
+        index.entries = entries;
+
+        if changed {
+            if let Err(e) = index.save(dir) {
+                warn!("Failed to save the received image index in {}: {}", dir.display(), e);
+            }
+        }
+        index
+    }
+
+    /// Cache the entry for an image just saved to `path`
+    pub fn record_delivery(path: &Path) -> Result<()> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let metadata = fs::metadata(path)?;
+        let file = ImageFile {
+            path: path.to_path_buf(),
+            file_name: path.file_name().unwrap_or_default().to_string_lossy().into_owned(),
+            size_bytes: metadata.len(),
+            modified: metadata.modified().ok(),
+        };
+        let mut index = Self::load(dir);
+        index.entries.insert(file.file_name.clone(), IndexedImage::read(&file));
+        index.save(dir)
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &IndexedImage> {
+        self.entries.values()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
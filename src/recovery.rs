@@ -0,0 +1,157 @@
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+use crate::lsb;
+use crate::p2p_protocol::embedded_payload;
+use crate::{payload_version, CombinedPayload, ImagePermissions};
+
+// =============================================================================
+// DAMAGED CARRIER RECOVERY
+// =============================================================================
+//
+// The payload is written from the first pixel on, permissions first, so a carrier whose
+// PNG was cut short or damaged part way through usually still holds every grant even when
+// the hidden image after them is gone. Rows are read one at a time until the first one
+// that fails to decode, and whatever they hold is read as far as it goes.
+
+/// What could be read back out of a damaged carrier
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecoveryReport {
+    pub width: u32,
+    pub height: u32,
+    /// Rows that decoded before the damage
+    pub rows_read: u32,
+    /// Payload length the carrier declares (None if not even that survived)
+    pub declared_len: Option<usize>,
+    /// Payload bytes read back
+    pub recovered_len: usize,
+    pub payload_version: Option<u8>,
+    pub permissions: Option<ImagePermissions>,
+    /// The whole payload, hidden image included, when it survived
+    pub payload: Option<CombinedPayload>,
+}
+
+impl RecoveryReport {
+    /// e.g. `412 of 512 rows readable, 18230 of 18230 payload bytes - everything recovered`
+    pub fn summary(&self) -> String {
+        let found = match (&self.payload, &self.permissions) {
+            (Some(_), _) => "everything recovered",
+            (None, Some(_)) => "permissions recovered, the hidden image is lost",
+            (None, None) => "no permissions recovered",
+        };
+        let bytes = match self.declared_len {
+            Some(declared) => format!("{} of {} payload bytes", self.recovered_len, declared),
+            None => "no payload length".to_string(),
+        };
+        format!("{} of {} rows readable, {} - {}", self.rows_read, self.height, bytes, found)
+    }
+}
+
+/// Read every row of a PNG up to the first one that doesn't decode, as RGBA bytes
+///
+/// Returns the image's dimensions, the rows read and their pixels. Checksums are ignored,
+/// since a flipped bit shouldn't cost the rows around it.
+fn read_rows_lenient(png_bytes: &[u8]) -> Result<(u32, u32, u32, Vec<u8>)> {
+    let mut options = png::DecodeOptions::default();
+    options.set_ignore_checksums(true);
+    let mut decoder = png::Decoder::new_with_options(Cursor::new(png_bytes), options);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().context("PNG header is damaged - nothing can be recovered")?;
+
+    let info = reader.info();
+    let (width, height) = (info.width, info.height);
+    if info.interlaced {
+        bail!("Interlaced PNGs can't be read past damage (carriers are never interlaced)");
+    }
+    let (color, _) = reader.output_color_type();
+
+    let mut pixels = Vec::with_capacity(width as usize * height as usize * 4);
+    let mut rows_read = 0;
+    while let Ok(Some(row)) = reader.next_row() {
+        match color {
+            png::ColorType::Rgba => pixels.extend_from_slice(row.data()),
+            png::ColorType::Rgb => row.data().chunks_exact(3).for_each(|px| pixels.extend_from_slice(&[px[0], px[1], px[2], 255])),
+            png::ColorType::GrayscaleAlpha => row.data().chunks_exact(2).for_each(|px| pixels.extend_from_slice(&[px[0], px[0], px[0], px[1]])),
+            png::ColorType::Grayscale => row.data().iter().for_each(|&g| pixels.extend_from_slice(&[g, g, g, 255])),
+            png::ColorType::Indexed => bail!("Palette PNG wasn't expanded"),
+        }
+        rows_read += 1;
+    }
+    Ok((width, height, rows_read, pixels))
+}
+
+/// Read as much of a damaged carrier's payload as survives
+pub fn recover_carrier(png_bytes: &[u8]) -> Result<RecoveryReport> {
+    let (width, height, rows_read, pixels) = read_rows_lenient(png_bytes)?;
+    let mut report = RecoveryReport {
+        width,
+        height,
+        rows_read,
+        declared_len: None,
+        recovered_len: 0,
+        payload_version: None,
+        permissions: None,
+        payload: None,
+    };
+
+    let total_pixel_bytes = width as usize * height as usize * 4;
+    let Some((declared_len, payload)) = lsb::decode_partial(&pixels, total_pixel_bytes) else {
+        return Ok(report);
+    };
+    report.declared_len = Some(declared_len);
+    report.recovered_len = payload.len();
+    if payload.is_empty() {
+        return Ok(report);
+    }
+    report.payload_version = Some(payload_version(&payload));
+    report.permissions = ImagePermissions::from_payload_prefix(&payload).ok();
+    if payload.len() == declared_len {
+        report.payload = CombinedPayload::from_bytes(&payload).ok();
+    }
+    Ok(report)
+}
+
+/// Embed what was recovered into a fresh carrier from the owner, returning the new carrier
+///
+/// A fresh carrier that already holds a payload (the owner encrypted the original image
+/// again) keeps its hidden image and takes the recovered permissions; `image_id`, or the
+/// recovered one, replaces the ID it was given so copies already out there still match.
+/// A plain image can only take a payload that survived whole.
+pub fn reembed(report: RecoveryReport, fresh_carrier: &[u8], image_id: Option<&str>) -> Result<Vec<u8>> {
+    let Some(permissions) = report.permissions else {
+        bail!("No permissions were recovered, so there's nothing to re-embed");
+    };
+    let carrier_img = image::load_from_memory(fresh_carrier).context("The fresh carrier isn't a readable image")?;
+
+    let payload = match (embedded_payload(fresh_carrier), report.payload) {
+        (Some(mut fresh), recovered) => {
+            if fresh.permissions.owner != permissions.owner {
+                bail!(
+                    "The fresh carrier belongs to {}, but the recovered permissions to {}",
+                    fresh.permissions.owner,
+                    permissions.owner
+                );
+            }
+            fresh.permissions = permissions;
+            if let Some(id) = image_id.map(str::to_string).or_else(|| recovered.and_then(|p| p.image_id)) {
+                fresh.image_id = Some(id);
+            }
+            fresh
+        }
+        (None, Some(mut payload)) => {
+            if let Some(id) = image_id {
+                payload.image_id = Some(id.to_string());
+            }
+            payload
+        }
+        (None, None) => bail!(
+            "Only the permissions survived - encrypt the original image again and use that as the fresh carrier"
+        ),
+    };
+
+    let updated = lsb::encode(&carrier_img, &payload.to_bytes()?)?;
+    let mut png = Vec::new();
+    updated.write_to(&mut Cursor::new(&mut png), image::ImageOutputFormat::Png)?;
+    Ok(png)
+}
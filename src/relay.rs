@@ -0,0 +1,326 @@
+use anyhow::{bail, Context, Result};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use uuid::Uuid;
+
+use crate::directory_service::{
+    client_envelope, read_directory_frame, write_directory_response, DirectoryMessage,
+};
+
+// =============================================================================
+// ANONYMOUS PEERS BEHIND A DIRECTORY RELAY
+// =============================================================================
+//
+// A hidden peer keeps a control connection open to one directory server and
+// registers `relay://<directory>/<handle>` instead of its own address. Peers
+// reaching it ask that directory to connect them; the directory announces a
+// circuit on the control connection, the hidden peer dials back to pick it up,
+// and the directory pipes the two connections together.
+
+/// Prefix of P2P addresses served through a relay
+pub const RELAY_SCHEME: &str = "relay://";
+
+/// Route this process's outgoing P2P connections through the relay at this directory address
+pub const RELAY_ENV: &str = "P2P_RELAY";
+
+/// Hidden peers' relay secrets, by handle, kept in the images directory
+pub const RELAY_SECRETS_FILE: &str = "relay_secrets.json";
+
+/// How long a client waits for the hidden peer to pick up a circuit
+const RELAY_PICKUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How often a hidden peer pings its control connection so idle NATs keep it open
+const RELAY_KEEPALIVE_INTERVAL: Duration = Duration::from_secs(30);
+
+const RELAY_RETRY_INITIAL_DELAY: Duration = Duration::from_secs(5);
+const RELAY_RETRY_MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// The P2P address a hidden peer registers: its relay and handle, never its own IP
+pub fn relay_p2p_address(relay: &str, handle: &str) -> String {
+    format!("{}{}/{}", RELAY_SCHEME, relay, handle)
+}
+
+/// The (relay, handle) of a relayed P2P address
+pub fn parse_relay_address(p2p_address: &str) -> Option<(&str, &str)> {
+    p2p_address
+        .strip_prefix(RELAY_SCHEME)?
+        .split_once('/')
+        .filter(|(relay, handle)| !relay.is_empty() && !handle.is_empty())
+}
+
+/// Secret proving we own `handle` at the relay, created on first use and kept in `path`
+pub fn relay_secret(path: &Path, handle: &str) -> Result<String> {
+    let mut secrets: HashMap<String, String> = match std::fs::read_to_string(path) {
+        Ok(json) => serde_json::from_str(&json).with_context(|| format!("Invalid {}", path.display()))?,
+        Err(_) => HashMap::new(),
+    };
+    if let Some(secret) = secrets.get(handle) {
+        return Ok(secret.clone());
+    }
+    let secret = Uuid::new_v4().simple().to_string();
+    secrets.insert(handle.to_string(), secret.clone());
+    std::fs::write(path, serde_json::to_string_pretty(&secrets)?)
+        .with_context(|| format!("Failed to save {}", path.display()))?;
+    Ok(secret)
+}
+
+static OUTBOUND_RELAY: Mutex<Option<String>> = Mutex::new(None);
+
+/// Route outgoing P2P connections through `relay` (None = connect directly)
+pub fn set_outbound_relay(relay: Option<String>) {
+    if let Ok(mut outbound) = OUTBOUND_RELAY.lock() {
+        *outbound = relay;
+    }
+}
+
+/// Relay outgoing P2P connections go through, if any (falls back to `P2P_RELAY`)
+pub fn outbound_relay() -> Option<String> {
+    OUTBOUND_RELAY
+        .lock()
+        .ok()
+        .and_then(|relay| relay.clone())
+        .or_else(|| std::env::var(RELAY_ENV).ok().filter(|relay| !relay.is_empty()))
+}
+
+// =============================================================================
+// RELAY (DIRECTORY SIDE)
+// =============================================================================
+
+/// Hidden peers connected to this directory and circuits waiting for them (in memory only)
+#[derive(Default)]
+pub struct Relay {
+    listeners: Mutex<HashMap<String, RelayListener>>,
+    /// Circuit ID -> client waiting for the hidden peer to pick up
+    circuits: Mutex<HashMap<String, oneshot::Sender<TcpStream>>>,
+}
+
+struct RelayListener {
+    /// Whoever first claimed the handle; only they may reconnect as it
+    secret: String,
+    /// Where to announce new circuits (None while the peer is away)
+    incoming: Option<mpsc::UnboundedSender<String>>,
+}
+
+impl Relay {
+    /// Hold a hidden peer's control connection, announcing circuits until it drops
+    pub async fn listen(&self, mut stream: TcpStream, handle: String, secret: String) -> Result<()> {
+        let (incoming, mut circuits) = mpsc::unbounded_channel();
+        let claimed = {
+            let mut listeners = self.listeners.lock().map_err(|_| anyhow::anyhow!("Relay lock poisoned"))?;
+            match listeners.get_mut(&handle) {
+                Some(listener) if !crate::directory_service::constant_time_eq(listener.secret.as_bytes(), secret.as_bytes()) => false,
+                Some(listener) => {
+                    listener.incoming = Some(incoming.clone());
+                    true
+                }
+                None => {
+                    listeners.insert(handle.clone(), RelayListener { secret, incoming: Some(incoming.clone()) });
+                    true
+                }
+            }
+        };
+        let response = DirectoryMessage::RelayListenResponse {
+            success: claimed,
+            message: if claimed {
+                format!("Relaying connections for {}", handle)
+            } else {
+                format!("Handle {} is held by another peer on this relay", handle)
+            },
+        };
+        write_directory_response(&mut stream, &response).await?;
+        if !claimed {
+            return Ok(());
+        }
+        info!("Relay: {} connected", handle);
+
+        // The peer only ever sends keepalive bytes, so any read ending means it's gone
+        let mut keepalive = [0u8; 1];
+        let result = loop {
+            tokio::select! {
+                Some(circuit_id) = circuits.recv() => {
+                    let announce = DirectoryMessage::RelayIncoming { circuit_id };
+                    if let Err(e) = write_directory_response(&mut stream, &announce).await {
+                        break Err(e);
+                    }
+                }
+                read = stream.read(&mut keepalive) => match read {
+                    Ok(0) | Err(_) => break Ok(()),
+                    Ok(_) => {}
+                },
+            }
+        };
+
+        if let Ok(mut listeners) = self.listeners.lock() {
+            if let Some(listener) = listeners.get_mut(&handle) {
+                if listener.incoming.as_ref().is_some_and(|current| current.same_channel(&incoming)) {
+                    listener.incoming = None;
+                }
+            }
+        }
+        info!("Relay: {} disconnected", handle);
+        result
+    }
+
+    /// Connect a client to the hidden peer behind `handle`
+    pub async fn connect_hidden(&self, mut client: TcpStream, handle: &str) -> Result<()> {
+        let incoming = self.listeners.lock().ok()
+            .and_then(|listeners| listeners.get(handle).and_then(|listener| listener.incoming.clone()));
+        let Some(incoming) = incoming else {
+            return refuse_connect(&mut client, format!("{} is not connected to this relay", handle)).await;
+        };
+
+        let circuit_id = Uuid::new_v4().to_string();
+        let (picked_up, pickup) = oneshot::channel();
+        if let Ok(mut circuits) = self.circuits.lock() {
+            circuits.insert(circuit_id.clone(), picked_up);
+        }
+        let peer = if incoming.send(circuit_id.clone()).is_ok() {
+            tokio::time::timeout(RELAY_PICKUP_TIMEOUT, pickup).await.ok().and_then(Result::ok)
+        } else {
+            None
+        };
+        if let Ok(mut circuits) = self.circuits.lock() {
+            circuits.remove(&circuit_id);
+        }
+
+        match peer {
+            Some(peer) => splice(client, peer).await,
+            None => refuse_connect(&mut client, format!("{} did not pick up", handle)).await,
+        }
+    }
+
+    /// Connect a client to a peer that registered a public address
+    pub async fn connect_public(&self, mut client: TcpStream, address: &str) -> Result<()> {
+        match tokio::time::timeout(RELAY_PICKUP_TIMEOUT, TcpStream::connect(address)).await {
+            Ok(Ok(peer)) => splice(client, peer).await,
+            Ok(Err(e)) => refuse_connect(&mut client, format!("Could not reach {}: {}", address, e)).await,
+            Err(_) => refuse_connect(&mut client, format!("Timed out reaching {}", address)).await,
+        }
+    }
+
+    /// Hand a hidden peer's pick-up connection to the client waiting on `circuit_id`
+    pub async fn accept(&self, mut stream: TcpStream, circuit_id: &str) -> Result<()> {
+        let waiting = self.circuits.lock().ok().and_then(|mut circuits| circuits.remove(circuit_id));
+        let response = DirectoryMessage::RelayAcceptResponse {
+            success: waiting.is_some(),
+            message: if waiting.is_some() { "Connected".to_string() } else { "Unknown or expired circuit".to_string() },
+        };
+        write_directory_response(&mut stream, &response).await?;
+        if let Some(waiting) = waiting {
+            let _ = waiting.send(stream);
+        }
+        Ok(())
+    }
+}
+
+async fn refuse_connect(client: &mut TcpStream, message: String) -> Result<()> {
+    write_directory_response(client, &DirectoryMessage::RelayConnectResponse { success: false, message }).await
+}
+
+/// Tell the client it's connected, then pipe bytes both ways until either side closes
+async fn splice(mut client: TcpStream, mut peer: TcpStream) -> Result<()> {
+    let connected = DirectoryMessage::RelayConnectResponse { success: true, message: "Connected".to_string() };
+    write_directory_response(&mut client, &connected).await?;
+    let _ = tokio::io::copy_bidirectional(&mut client, &mut peer).await;
+    Ok(())
+}
+
+// =============================================================================
+// RELAY CLIENTS
+// =============================================================================
+
+/// Open a connection to a relay and send it one message, returning the connection and its answer
+async fn relay_handshake(relay: &str, message: DirectoryMessage) -> Result<(TcpStream, DirectoryMessage)> {
+    let mut stream = TcpStream::connect(relay).await
+        .with_context(|| format!("Could not reach relay {}", relay))?;
+    // Hidden peers are only reachable from their own namespace
+    write_directory_response(&mut stream, &client_envelope(message)?).await?;
+    match read_directory_frame(&mut stream).await? {
+        DirectoryMessage::AuthError { message } => bail!("Relay authentication failed: {}", message),
+        response => Ok((stream, response)),
+    }
+}
+
+/// Open a P2P connection through `relay` to a hidden peer's handle or a registered peer's address
+pub async fn open_relay_stream(relay: &str, target: &str) -> Result<TcpStream> {
+    let connect = DirectoryMessage::RelayConnect { target: target.to_string() };
+    match relay_handshake(relay, connect).await? {
+        (stream, DirectoryMessage::RelayConnectResponse { success: true, .. }) => Ok(stream),
+        (_, DirectoryMessage::RelayConnectResponse { message, .. }) => bail!("Relay {} refused: {}", relay, message),
+        _ => bail!("Unexpected response from relay {}", relay),
+    }
+}
+
+/// Keep `handle` reachable through `relay`, passing each relayed connection to `serve`
+///
+/// Reconnects with backoff whenever the relay drops; fails only if another peer holds the handle.
+pub async fn serve_hidden<F, Fut>(relay: String, handle: String, secret: String, serve: F) -> Result<()>
+where
+    F: Fn(TcpStream) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut delay = RELAY_RETRY_INITIAL_DELAY;
+    loop {
+        let listen = DirectoryMessage::RelayListen { handle: handle.clone(), secret: secret.clone() };
+        match relay_handshake(&relay, listen).await {
+            Ok((stream, DirectoryMessage::RelayListenResponse { success: true, .. })) => {
+                info!("Reachable as {} through relay {}", handle, relay);
+                delay = RELAY_RETRY_INITIAL_DELAY;
+                if let Err(e) = hold_control_connection(stream, &relay, serve.clone()).await {
+                    warn!("Lost relay {}: {}", relay, e);
+                }
+            }
+            Ok((_, DirectoryMessage::RelayListenResponse { message, .. })) => bail!("{}", message),
+            Ok(_) => warn!("Unexpected response from relay {}", relay),
+            Err(e) => warn!("{}", e),
+        }
+        tokio::time::sleep(delay).await;
+        delay = (delay * 2).min(RELAY_RETRY_MAX_DELAY);
+    }
+}
+
+/// Pick up every circuit the relay announces until the control connection drops
+async fn hold_control_connection<F, Fut>(stream: TcpStream, relay: &str, serve: F) -> Result<()>
+where
+    F: Fn(TcpStream) -> Fut + Clone + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let (mut reader, mut writer) = stream.into_split();
+    let keepalive = tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(RELAY_KEEPALIVE_INTERVAL).await;
+            if writer.write_u8(0).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let result = loop {
+        match read_directory_frame(&mut reader).await {
+            Ok(DirectoryMessage::RelayIncoming { circuit_id }) => {
+                let relay = relay.to_string();
+                let serve = serve.clone();
+                tokio::spawn(async move {
+                    let accept = DirectoryMessage::RelayAccept { circuit_id };
+                    match relay_handshake(&relay, accept).await {
+                        Ok((stream, DirectoryMessage::RelayAcceptResponse { success: true, .. })) => serve(stream).await,
+                        Ok((_, DirectoryMessage::RelayAcceptResponse { message, .. })) => warn!("Relay circuit dropped: {}", message),
+                        Ok(_) => warn!("Unexpected response from relay {}", relay),
+                        Err(e) => warn!("Could not pick up relay circuit: {}", e),
+                    }
+                });
+            }
+            Ok(_) => warn!("Ignoring unexpected message from relay {}", relay),
+            Err(e) => break Err(e),
+        }
+    };
+    keepalive.abort();
+    result
+}
@@ -0,0 +1,164 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+// =============================================================================
+// PEER REPUTATION
+// =============================================================================
+
+/// Default file name for persisted peer reputation
+pub const REPUTATION_FILE: &str = "peer_reputation.json";
+
+/// Scores below this are flagged as unreliable
+pub const LOW_REPUTATION_SCORE: u8 = 40;
+
+/// Weight of the newest latency sample in the running average
+const LATENCY_SMOOTHING: f64 = 0.3;
+
+/// Round trips at or under this count as fully responsive
+const FAST_LATENCY_MS: f64 = 200.0;
+
+/// Round trips at or over this count as unresponsive
+const SLOW_LATENCY_MS: f64 = 5000.0;
+
+/// What we've observed of one peer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PeerRecord {
+    pub deliveries_succeeded: u32,
+    pub deliveries_failed: u32,
+    /// Smoothed round trip of our P2P calls to the peer
+    pub avg_latency_ms: Option<f64>,
+    /// Revocations the peer applied to its copy
+    pub revocations_honored: u32,
+    /// Revocations the peer received but refused
+    pub revocations_refused: u32,
+    pub updated_at: Option<SystemTime>,
+}
+
+impl PeerRecord {
+    /// 0-100, from delivery success (50%), revocation compliance (30%) and latency (20%)
+    ///
+    /// Each part starts neutral and moves toward what's been observed, so a
+    /// single failure doesn't sink a new peer.
+    pub fn score(&self) -> u8 {
+        let ratio = |good: u32, bad: u32| (good as f64 + 1.0) / ((good + bad) as f64 + 2.0);
+        let reliability = ratio(self.deliveries_succeeded, self.deliveries_failed);
+        let compliance = ratio(self.revocations_honored, self.revocations_refused);
+        let responsiveness = self.avg_latency_ms.map_or(0.5, |ms| {
+            1.0 - ((ms - FAST_LATENCY_MS) / (SLOW_LATENCY_MS - FAST_LATENCY_MS)).clamp(0.0, 1.0)
+        });
+        (100.0 * (0.5 * reliability + 0.3 * compliance + 0.2 * responsiveness)).round() as u8
+    }
+}
+
+/// A peer's score with the observations behind it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PeerReputation {
+    pub username: String,
+    pub score: u8,
+    pub record: PeerRecord,
+}
+
+struct Ledger {
+    path: Option<PathBuf>,
+    peers: Option<HashMap<String, PeerRecord>>,
+}
+
+static LEDGER: Mutex<Ledger> = Mutex::new(Ledger { path: None, peers: None });
+
+/// Persist reputation to `path`, picking up any records already saved there
+pub fn set_reputation_path(path: PathBuf) {
+    let saved: HashMap<String, PeerRecord> = fs::read_to_string(&path)
+        .ok()
+        .and_then(|data| serde_json::from_str(&data).ok())
+        .unwrap_or_default();
+
+    if let Ok(mut ledger) = LEDGER.lock() {
+        let peers = ledger.peers.get_or_insert_with(HashMap::new);
+        for (username, record) in saved {
+            peers.entry(username).or_insert(record);
+        }
+        ledger.path = Some(path);
+    }
+}
+
+/// Note whether an image or permission update reached a peer
+pub fn record_delivery(peer: &str, success: bool) {
+    update(peer, |record| {
+        if success {
+            record.deliveries_succeeded += 1;
+        } else {
+            record.deliveries_failed += 1;
+        }
+    });
+}
+
+/// Fold a round trip into the peer's average latency
+pub fn record_latency(peer: &str, latency: Duration) {
+    let ms = latency.as_secs_f64() * 1000.0;
+    update(peer, |record| {
+        record.avg_latency_ms = Some(match record.avg_latency_ms {
+            Some(avg) => avg + LATENCY_SMOOTHING * (ms - avg),
+            None => ms,
+        });
+    });
+}
+
+/// Note whether a peer applied a revocation we sent it
+pub fn record_revocation(peer: &str, honored: bool) {
+    update(peer, |record| {
+        if honored {
+            record.revocations_honored += 1;
+        } else {
+            record.revocations_refused += 1;
+        }
+    });
+}
+
+/// A peer's reputation, or None if we've never dealt with them
+pub fn reputation(peer: &str) -> Option<PeerReputation> {
+    let ledger = LEDGER.lock().ok()?;
+    let record = ledger.peers.as_ref()?.get(peer)?.clone();
+    Some(PeerReputation { username: peer.to_string(), score: record.score(), record })
+}
+
+/// Every peer we've dealt with, lowest score first
+pub fn all_reputations() -> Vec<PeerReputation> {
+    let Ok(ledger) = LEDGER.lock() else {
+        return Vec::new();
+    };
+    let mut reputations: Vec<PeerReputation> = ledger
+        .peers
+        .iter()
+        .flatten()
+        .map(|(username, record)| PeerReputation {
+            username: username.clone(),
+            score: record.score(),
+            record: record.clone(),
+        })
+        .collect();
+    reputations.sort_by_key(|reputation| reputation.score);
+    reputations
+}
+
+fn update(peer: &str, change: impl FnOnce(&mut PeerRecord)) {
+    let Ok(mut ledger) = LEDGER.lock() else {
+        return;
+    };
+    let record = ledger.peers.get_or_insert_with(HashMap::new).entry(peer.to_string()).or_default();
+    change(record);
+    record.updated_at = Some(SystemTime::now());
+
+    if let (Some(path), Some(peers)) = (&ledger.path, &ledger.peers) {
+        let result = serde_json::to_string_pretty(peers)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| fs::write(path, json).map_err(anyhow::Error::from));
+        if let Err(e) = result {
+            error!("Failed to save peer reputation to {}: {}", path.display(), e);
+        }
+    }
+}
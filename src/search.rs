@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// =============================================================================
+// FUZZY TEXT SEARCH
+// =============================================================================
+
+/// Weight of words in an image's name
+pub const NAME_WEIGHT: f32 = 1.0;
+
+/// Weight of words in captions and descriptions
+pub const DESCRIPTION_WEIGHT: f32 = 0.7;
+
+/// Shortest query word matched as a prefix of an indexed word
+const MIN_PREFIX_LEN: usize = 2;
+
+/// Shortest query word matched inside an indexed word
+const MIN_INFIX_LEN: usize = 3;
+
+/// Inverted index from words to the documents they appear in
+///
+/// Query words match indexed words exactly, as a prefix, inside them, or within
+/// a small edit distance, so searches survive typos and partial words.
+#[derive(Debug, Clone)]
+pub struct SearchIndex<K> {
+    /// Word -> documents containing it, with the weight of the best field it's in
+    postings: HashMap<String, HashMap<K, f32>>,
+    /// Document -> its words, so it can be removed
+    documents: HashMap<K, Vec<String>>,
+}
+
+impl<K> Default for SearchIndex<K> {
+    fn default() -> Self {
+        Self { postings: HashMap::new(), documents: HashMap::new() }
+    }
+}
+
+impl<K: Clone + Eq + Hash> SearchIndex<K> {
+    /// Index a document's text fields, each with its weight, replacing any earlier version
+    pub fn insert(&mut self, key: K, fields: &[(&str, f32)]) {
+        self.remove(&key);
+        let mut words: HashMap<String, f32> = HashMap::new();
+        for (text, weight) in fields {
+            for word in tokenize(text) {
+                let best = words.entry(word).or_insert(0.0);
+                *best = best.max(*weight);
+            }
+        }
+        for (word, weight) in &words {
+            self.postings.entry(word.clone()).or_default().insert(key.clone(), *weight);
+        }
+        self.documents.insert(key, words.into_keys().collect());
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        let Some(words) = self.documents.remove(key) else {
+            return;
+        };
+        for word in words {
+            if let Some(docs) = self.postings.get_mut(&word) {
+                docs.remove(key);
+                if docs.is_empty() {
+                    self.postings.remove(&word);
+                }
+            }
+        }
+    }
+
+    /// Every indexed document
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.documents.keys()
+    }
+
+    /// Documents matching `query`, best first, scored 0-1
+    ///
+    /// Each query word contributes its best match in the document; words that
+    /// match nothing count as zero, so documents matching more words rank higher.
+    pub fn search(&self, query: &str) -> Vec<(K, f32)> {
+        let query_words = tokenize(query);
+        if query_words.is_empty() {
+            return Vec::new();
+        }
+
+        let mut totals: HashMap<&K, f32> = HashMap::new();
+        for query_word in &query_words {
+            let mut best: HashMap<&K, f32> = HashMap::new();
+            for (word, docs) in &self.postings {
+                let similarity = word_similarity(query_word, word);
+                if similarity == 0.0 {
+                    continue;
+                }
+                for (key, weight) in docs {
+                    let score = best.entry(key).or_insert(0.0);
+                    *score = score.max(similarity * weight);
+                }
+            }
+            for (key, score) in best {
+                *totals.entry(key).or_insert(0.0) += score;
+            }
+        }
+
+        let mut hits: Vec<(K, f32)> = totals
+            .into_iter()
+            .map(|(key, total)| (key.clone(), total / query_words.len() as f32))
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits
+    }
+}
+
+/// Lowercase alphanumeric words, splitting on everything else (including `_`, `-` and `.`)
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// How well a query word matches an indexed word, 0 for no match
+fn word_similarity(query: &str, word: &str) -> f32 {
+    if query == word {
+        return 1.0;
+    }
+    let query_len = query.chars().count();
+    if query_len >= MIN_PREFIX_LEN && word.starts_with(query) {
+        return 0.9;
+    }
+    if query_len >= MIN_INFIX_LEN && word.contains(query) {
+        return 0.7;
+    }
+
+    // One typo allowed in words of 4+ letters, two in words of 8+
+    let allowed = match query_len {
+        0..=3 => return 0.0,
+        4..=7 => 1,
+        _ => 2,
+    };
+    match edit_distance(query, word, allowed) {
+        Some(1) => 0.6,
+        Some(_) => 0.4,
+        None => 0.0,
+    }
+}
+
+/// Levenshtein distance between two words, or None if it exceeds `max`
+fn edit_distance(a: &str, b: &str, max: usize) -> Option<usize> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    if a.len().abs_diff(b.len()) > max {
+        return None;
+    }
+
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.iter().enumerate() {
+        let mut current = vec![i + 1; b.len() + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != cb);
+            current[j + 1] = substitution.min(previous[j + 1] + 1).min(current[j] + 1);
+        }
+        if current.iter().min().is_some_and(|&row_min| row_min > max) {
+            return None;
+        }
+        previous = current;
+    }
+    Some(previous[b.len()]).filter(|&distance| distance <= max)
+}
@@ -0,0 +1,148 @@
+use anyhow::{anyhow, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tokio::sync::OwnedMutexGuard;
+
+use crate::lsb;
+use crate::p2p_protocol::{embedded_payload, mark_received_viewed, write_atomic};
+use crate::CombinedPayload;
+
+// =============================================================================
+// CONSUMING VIEWS
+// =============================================================================
+//
+// A view reads the viewer's quota out of the carrier, decrements it and writes the carrier
+// back. Two views of the same file at once used to read the same quota, so both were shown
+// but only one view was used up (or the two writes interleaved and corrupted the file).
+// Every in-place rewrite of a carrier's payload now holds that file's lock.
+
+/// Carrier path -> its lock, shared by every view and permission update in this process
+static CARRIER_LOCKS: Mutex<Option<HashMap<PathBuf, Arc<tokio::sync::Mutex<()>>>>> = Mutex::new(None);
+
+/// Wait for exclusive use of a carrier file, held until the guard is dropped
+pub async fn lock_carrier(path: &Path) -> OwnedMutexGuard<()> {
+    // The same file may be reached through different paths
+    let key = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let lock = {
+        let mut locks = CARRIER_LOCKS.lock().unwrap_or_else(|e| e.into_inner());
+        let locks = locks.get_or_insert_with(HashMap::new);
+        // Drop locks nobody holds or waits on, so the map doesn't grow with every file viewed
+        locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        locks.entry(key).or_default().clone()
+    };
+    lock.lock_owned().await
+}
+
+/// Why a view was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ViewDenial {
+    /// Past the image's expiry, when only the owner may view it
+    Expired,
+    /// The viewer keeps the carrier for safekeeping only
+    HolderOnly,
+    /// The viewer was granted views but has used them all
+    NoViewsLeft,
+    /// The viewer was never granted any views
+    NotAuthorized,
+}
+
+/// What happened when a user tried to view a carrier
+#[derive(Debug)]
+pub enum ViewOutcome {
+    /// The view was allowed; for non-owners the decremented quota is already saved
+    Granted {
+        payload: Box<CombinedPayload>,
+        is_owner: bool,
+        /// Views the viewer has left after this one (None for the owner)
+        views_left: Option<u32>,
+    },
+    Denied(ViewDenial),
+}
+
+/// What viewing a carrier would do, read without using up a view
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ViewPreview {
+    pub owner: String,
+    pub is_owner: bool,
+    /// Views the viewer has before opening it (None for the owner)
+    pub views_remaining: Option<u32>,
+    pub expires_at: Option<SystemTime>,
+    /// Why opening it would be refused, if it would
+    pub denial: Option<ViewDenial>,
+}
+
+/// Why `viewer` may not view a carrier with this payload, if they may not
+fn view_denial(payload: &CombinedPayload, viewer: &str) -> Option<ViewDenial> {
+    if viewer == payload.permissions.owner {
+        return None;
+    }
+    if payload.permissions.is_holder(viewer) {
+        return Some(ViewDenial::HolderOnly);
+    }
+    if payload.is_expired() {
+        return Some(ViewDenial::Expired);
+    }
+    match payload.permissions.quotas.get(viewer) {
+        Some(views) if *views > 0 => None,
+        Some(_) => Some(ViewDenial::NoViewsLeft),
+        None => Some(ViewDenial::NotAuthorized),
+    }
+}
+
+/// Check what viewing the carrier at `path` as `viewer` would do, without using up a view
+///
+/// For asking the viewer to confirm before `consume_view`; another view may still use up
+/// the last one in between, which `consume_view` then refuses.
+pub fn peek_view(path: &Path, viewer: &str) -> Result<ViewPreview> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let payload = embedded_payload(&data).context("No readable permissions in the image")?;
+    let is_owner = viewer == payload.permissions.owner;
+    Ok(ViewPreview {
+        denial: view_denial(&payload, viewer),
+        views_remaining: (!is_owner).then(|| payload.permissions.quotas.get(viewer).copied().unwrap_or(0)),
+        expires_at: payload.expires_at,
+        owner: payload.permissions.owner,
+        is_owner,
+    })
+}
+
+/// View the carrier at `path` as `viewer`, using up one of their views unless they own it
+pub async fn consume_view(path: &Path, viewer: &str) -> Result<ViewOutcome> {
+    let _guard = lock_carrier(path).await;
+    let (path, viewer) = (path.to_path_buf(), viewer.to_string());
+    tokio::task::spawn_blocking(move || consume_view_locked(&path, &viewer)).await?
+}
+
+/// `consume_view` for a caller already holding the carrier's lock
+fn consume_view_locked(path: &Path, viewer: &str) -> Result<ViewOutcome> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let carrier = image::load_from_memory(&data).context("Not a readable image")?;
+    let payload = lsb::decode(&carrier)?.ok_or_else(|| anyhow!("No hidden metadata found"))?;
+    let mut payload = CombinedPayload::from_bytes(&payload)?;
+
+    if viewer == payload.permissions.owner {
+        return Ok(ViewOutcome::Granted { payload: Box::new(payload), is_owner: true, views_left: None });
+    }
+    if let Some(denial) = view_denial(&payload, viewer) {
+        return Ok(ViewOutcome::Denied(denial));
+    }
+    let Some(views) = payload.permissions.quotas.get_mut(viewer) else {
+        return Ok(ViewOutcome::Denied(ViewDenial::NotAuthorized));
+    };
+    *views -= 1;
+    let views_left = *views;
+
+    let updated = lsb::encode(&carrier, &payload.to_bytes()?)?;
+    let mut png = Vec::new();
+    updated
+        .write_to(&mut std::io::Cursor::new(&mut png), image::ImageOutputFormat::Png)
+        .context("Failed to encode the updated carrier")?;
+    write_atomic(path, &png)?;
+    mark_received_viewed(path)?;
+
+    Ok(ViewOutcome::Granted { payload: Box::new(payload), is_owner: false, views_left: Some(views_left) })
+}
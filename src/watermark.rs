@@ -0,0 +1,150 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageOutputFormat, Rgba, RgbaImage};
+use std::io::Cursor;
+use std::time::SystemTime;
+
+// =============================================================================
+// VIEWER WATERMARKS
+// =============================================================================
+
+/// Glyph cell size in font pixels (3x5 glyph plus a column and row of spacing)
+const CELL_WIDTH: u32 = 4;
+const CELL_HEIGHT: u32 = 6;
+
+/// Opacity of the watermark text and its shadow
+const TEXT_ALPHA: f32 = 0.45;
+const SHADOW_ALPHA: f32 = 0.3;
+
+/// 3x5 bitmap glyphs, three bits per row from the top, leftmost pixel in the high bit
+fn glyph(c: char) -> u16 {
+    match c.to_ascii_uppercase() {
+        'A' => 0b010_101_111_101_101,
+        'B' => 0b110_101_110_101_110,
+        'C' => 0b011_100_100_100_011,
+        'D' => 0b110_101_101_101_110,
+        'E' => 0b111_100_110_100_111,
+        'F' => 0b111_100_110_100_100,
+        'G' => 0b011_100_101_101_011,
+        'H' => 0b101_101_111_101_101,
+        'I' => 0b111_010_010_010_111,
+        'J' => 0b001_001_001_101_010,
+        'K' => 0b101_101_110_101_101,
+        'L' => 0b100_100_100_100_111,
+        'M' => 0b101_111_111_101_101,
+        'N' => 0b110_101_101_101_101,
+        'O' => 0b010_101_101_101_010,
+        'P' => 0b110_101_110_100_100,
+        'Q' => 0b010_101_101_110_011,
+        'R' => 0b110_101_110_101_101,
+        'S' => 0b011_100_010_001_110,
+        'T' => 0b111_010_010_010_010,
+        'U' => 0b101_101_101_101_111,
+        'V' => 0b101_101_101_101_010,
+        'W' => 0b101_101_111_111_101,
+        'X' => 0b101_101_010_101_101,
+        'Y' => 0b101_101_010_010_010,
+        'Z' => 0b111_001_010_100_111,
+        '0' => 0b111_101_101_101_111,
+        '1' => 0b010_110_010_010_111,
+        '2' => 0b110_001_010_100_111,
+        '3' => 0b110_001_010_001_110,
+        '4' => 0b101_101_111_001_001,
+        '5' => 0b111_100_110_001_110,
+        '6' => 0b011_100_111_101_111,
+        '7' => 0b111_001_010_010_010,
+        '8' => 0b111_101_111_101_111,
+        '9' => 0b111_101_111_001_110,
+        '-' => 0b000_000_111_000_000,
+        '_' => 0b000_000_000_000_111,
+        '.' => 0b000_000_000_000_010,
+        ':' => 0b000_010_000_010_000,
+        '/' => 0b001_001_010_100_100,
+        '@' => 0b010_101_111_100_011,
+        ' ' => 0,
+        _ => 0b110_001_010_000_010, // '?'
+    }
+}
+
+fn glyph_pixel(glyph: u16, x: u32, y: u32) -> bool {
+    x < 3 && y < 5 && glyph & (1 << (14 - (y * 3 + x))) != 0
+}
+
+/// Blend `color` over the pixel at (x, y) if it's inside the image
+fn blend(image: &mut RgbaImage, x: i64, y: i64, color: [u8; 3], alpha: f32) {
+    if x < 0 || y < 0 || x >= image.width() as i64 || y >= image.height() as i64 {
+        return;
+    }
+    let Rgba([r, g, b, a]) = *image.get_pixel(x as u32, y as u32);
+    let mix = |channel: u8, over: u8| (channel as f32 * (1.0 - alpha) + over as f32 * alpha).round() as u8;
+    image.put_pixel(x as u32, y as u32, Rgba([mix(r, color[0]), mix(g, color[1]), mix(b, color[2]), a]));
+}
+
+/// Tile `text` across the image in staggered rows, scaled so each copy spans about a third of its width
+pub fn watermark_image(image: &DynamicImage, text: &str) -> DynamicImage {
+    let mut canvas = image.to_rgba8();
+    let glyphs: Vec<u16> = text.chars().map(glyph).collect();
+    if glyphs.is_empty() {
+        return DynamicImage::ImageRgba8(canvas);
+    }
+
+    let text_units = glyphs.len() as u32 * CELL_WIDTH;
+    let scale = (canvas.width() / (3 * text_units)).max(1);
+    let stamp_width = (text_units * scale) as i64;
+    let row_height = (CELL_HEIGHT * scale * 4) as i64;
+    let shadow = scale.div_ceil(3) as i64;
+
+    // Every font pixel's top-left corner in image coordinates
+    let mut dots = Vec::new();
+    let mut row = 0;
+    let mut top = row_height / 3;
+    while top < canvas.height() as i64 {
+        // Offset alternate rows by half a stamp so the text can't be cropped out in one strip
+        let mut left = if row % 2 == 0 { 0 } else { -stamp_width / 2 };
+        while left < canvas.width() as i64 {
+            for (i, &glyph) in glyphs.iter().enumerate() {
+                for y in 0..5 {
+                    for x in (0..3).filter(|&x| glyph_pixel(glyph, x, y)) {
+                        dots.push((left + ((i as u32 * CELL_WIDTH + x) * scale) as i64, top + (y * scale) as i64));
+                    }
+                }
+            }
+            left += stamp_width * 3 / 2;
+        }
+        top += row_height;
+        row += 1;
+    }
+
+    // Shadows first, so they never darken the text itself
+    for (offset, color, alpha) in [(shadow, [0, 0, 0], SHADOW_ALPHA), (0, [255, 255, 255], TEXT_ALPHA)] {
+        for &(px, py) in &dots {
+            for dy in 0..scale as i64 {
+                for dx in 0..scale as i64 {
+                    blend(&mut canvas, px + dx + offset, py + dy + offset, color, alpha);
+                }
+            }
+        }
+    }
+
+    DynamicImage::ImageRgba8(canvas)
+}
+
+/// Watermark text naming the viewer and the current UTC time, e.g. `alice 2026-10-15 14:03 UTC`
+pub fn viewer_stamp(viewer: &str) -> String {
+    let now = SystemTime::now();
+    let minute_of_day = now.duration_since(SystemTime::UNIX_EPOCH).map_or(0, |d| d.as_secs() / 60 % 1440);
+    format!(
+        "{} {} {:02}:{:02} UTC",
+        viewer,
+        crate::bandwidth::utc_date(now),
+        minute_of_day / 60,
+        minute_of_day % 60
+    )
+}
+
+/// Watermark a PNG (or any format the image crate reads), returning PNG bytes
+pub fn watermark_png(image_bytes: &[u8], text: &str) -> Result<Vec<u8>> {
+    let image = image::load_from_memory(image_bytes)?;
+    let mut png = Vec::new();
+    watermark_image(&image, text).write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)?;
+    Ok(png)
+}
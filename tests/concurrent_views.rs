@@ -0,0 +1,157 @@
+//! Views of the same received image running at once must each use up a view
+//!
+//! Every view reads the viewer's quota, decrements it and writes the carrier back. Without
+//! the per-file lock two views read the same quota and the second write undoes the first,
+//! so more views are shown than were granted (or the carrier is left half-written).
+//! Grants and permission updates on the owner's copy rewrite it the same way.
+
+use cloud_p2p_project::p2p_protocol::{
+    embedded_permissions, send_p2p_message, start_p2p_server, ImageMetadata, P2PMessage, PeerImageStore,
+};
+use cloud_p2p_project::viewing::{consume_view, ViewDenial, ViewOutcome};
+use cloud_p2p_project::{lsb, CombinedPayload, ImageAnnotations, ImagePermissions};
+use image::{DynamicImage, ImageOutputFormat, RgbaImage};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const GRANTED_VIEWS: u32 = 10;
+const CONCURRENT_VIEWS: usize = 32;
+
+/// A carrier owned by alice granting bob `views`, written into a fresh temporary folder
+fn write_carrier(views: u32) -> PathBuf {
+    write_carrier_for(HashMap::from([("bob".to_string(), views)]))
+}
+
+fn write_carrier_for(quotas: HashMap<String, u32>) -> PathBuf {
+    let payload = CombinedPayload {
+        permissions: ImagePermissions::new("alice".to_string(), quotas),
+        unified_image: vec![7; 64],
+        annotations: ImageAnnotations::default(),
+        image_id: Some("sunset".to_string()),
+        expires_at: None,
+        flags: 0,
+        provenance: Vec::new(),
+    };
+    let carrier = lsb::encode(&DynamicImage::ImageRgba8(RgbaImage::new(128, 128)), &payload.to_bytes().unwrap()).unwrap();
+    let mut png = Vec::new();
+    carrier.write_to(&mut std::io::Cursor::new(&mut png), ImageOutputFormat::Png).unwrap();
+
+    let dir = std::env::temp_dir().join(format!("concurrent-views-{}", uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("from_alice_sunset");
+    fs::write(&path, png).unwrap();
+    path
+}
+
+fn views_left(path: &Path) -> u32 {
+    let permissions = embedded_permissions(&fs::read(path).unwrap()).expect("carrier still decodes");
+    permissions.quotas.get("bob").copied().unwrap_or(0)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_views_each_use_up_a_view() {
+    let path = write_carrier(GRANTED_VIEWS);
+
+    let views: Vec<_> = (0..CONCURRENT_VIEWS)
+        .map(|_| {
+            let path = path.clone();
+            tokio::spawn(async move { consume_view(&path, "bob").await })
+        })
+        .collect();
+
+    let (mut granted, mut denied) = (0, 0);
+    for view in views {
+        match view.await.unwrap().unwrap() {
+            ViewOutcome::Granted { is_owner: false, .. } => granted += 1,
+            ViewOutcome::Denied(ViewDenial::NoViewsLeft) => denied += 1,
+            other => panic!("Unexpected outcome {:?}", other),
+        }
+    }
+
+    assert_eq!(granted, GRANTED_VIEWS as usize);
+    assert_eq!(denied, CONCURRENT_VIEWS - GRANTED_VIEWS as usize);
+    assert_eq!(views_left(&path), 0);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_owner_views_leave_quotas_alone() {
+    let path = write_carrier(GRANTED_VIEWS);
+
+    let views: Vec<_> = (0..CONCURRENT_VIEWS)
+        .map(|_| {
+            let path = path.clone();
+            tokio::spawn(async move { consume_view(&path, "alice").await })
+        })
+        .collect();
+    for view in views {
+        assert!(matches!(view.await.unwrap().unwrap(), ViewOutcome::Granted { is_owner: true, .. }));
+    }
+
+    assert_eq!(views_left(&path), GRANTED_VIEWS);
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn concurrent_grants_and_updates_all_land() {
+    // Lowering a quota needs no grant token, so every change here goes straight to the carrier
+    let granted: Vec<String> = (0..CONCURRENT_VIEWS / 2).map(|i| format!("granted-{}", i)).collect();
+    let updated: Vec<String> = (0..CONCURRENT_VIEWS / 2).map(|i| format!("updated-{}", i)).collect();
+    let path = write_carrier_for(granted.iter().chain(&updated).map(|user| (user.clone(), GRANTED_VIEWS)).collect());
+
+    let mut store = PeerImageStore::new();
+    store.set_owner("alice".to_string());
+    let metadata = ImageMetadata {
+        image_id: "sunset".to_string(),
+        image_name: "sunset.png".to_string(),
+        owner: "alice".to_string(),
+        description: None,
+        file_size_kb: 1,
+        max_grant_views: None,
+    };
+    store.add_image("sunset".to_string(), path.clone(), metadata);
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    tokio::spawn(start_p2p_server(port, "alice".to_string(), std::sync::Arc::new(tokio::sync::RwLock::new(store))));
+    let addr = format!("127.0.0.1:{}", port);
+    while tokio::net::TcpStream::connect(&addr).await.is_err() {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
+    let grants = granted.iter().map(|user| P2PMessage::ImageRequest {
+        requesting_user: user.clone(),
+        image_id: "sunset".to_string(),
+        requested_views: 2,
+        requested_max_dimension: None,
+        approved_by: None,
+        grant_token: None,
+    });
+    let updates = updated.iter().map(|user| P2PMessage::UpdatePermissions {
+        owner: "alice".to_string(),
+        image_id: "sunset".to_string(),
+        username: user.clone(),
+        new_quota: 1,
+    });
+    let changes: Vec<_> = grants
+        .chain(updates)
+        .map(|message| {
+            let addr = addr.clone();
+            tokio::spawn(async move { send_p2p_message(&addr, message).await })
+        })
+        .collect();
+    for change in changes {
+        match change.await.unwrap().unwrap() {
+            P2PMessage::ImageResponse { success: true, .. } | P2PMessage::UpdatePermissionsResponse { success: true, .. } => {}
+            other => panic!("Change refused: {:?}", other),
+        }
+    }
+
+    let permissions = embedded_permissions(&fs::read(&path).unwrap()).expect("carrier still decodes");
+    for user in &granted {
+        assert_eq!(permissions.quotas.get(user), Some(&2), "grant to {} was lost", user);
+    }
+    for user in &updated {
+        assert_eq!(permissions.quotas.get(user), Some(&1), "update for {} was lost", user);
+    }
+    fs::remove_dir_all(path.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,47 @@
+//! A cap set on one shared image is kept with its visibility and outlives the store
+
+use cloud_p2p_project::p2p_protocol::{ImageMetadata, PeerImageStore, VISIBILITY_FILE};
+use std::path::Path;
+
+fn metadata(image_id: &str, max_grant_views: Option<u32>) -> ImageMetadata {
+    ImageMetadata {
+        image_id: image_id.to_string(),
+        image_name: format!("{}.png", image_id),
+        owner: "alice".to_string(),
+        description: None,
+        file_size_kb: 1,
+        max_grant_views,
+    }
+}
+
+/// A store sharing `sunset` and `harbor` with `default_cap`, as start-peer builds it
+fn open_store(dir: &Path, default_cap: Option<u32>) -> PeerImageStore {
+    let mut store = PeerImageStore::new();
+    store.set_owner("alice".to_string());
+    for image_id in ["sunset", "harbor"] {
+        store.add_image(image_id.to_string(), dir.join(format!("{}.png", image_id)), metadata(image_id, default_cap));
+    }
+    store.set_visibility_path(dir.join(VISIBILITY_FILE));
+    store
+}
+
+#[test]
+fn per_image_cap_persists_and_clears_to_the_default() {
+    let dir = std::env::temp_dir().join(format!("grant-caps-{}", uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut store = open_store(&dir, Some(10));
+    assert_eq!(store.set_max_grant_views("alice/sunset", Some(3)).unwrap(), Some(3));
+    assert!(store.set_max_grant_views("missing", Some(3)).is_err());
+
+    let mut store = open_store(&dir, Some(10));
+    assert_eq!(store.get_max_grant_views("sunset"), Some(3));
+    assert_eq!(store.get_metadata("sunset").unwrap().max_grant_views, Some(3));
+    assert_eq!(store.get_max_grant_views("harbor"), Some(10));
+
+    assert_eq!(store.set_max_grant_views("sunset", None).unwrap(), Some(10));
+    let store = open_store(&dir, None);
+    assert_eq!(store.get_max_grant_views("sunset"), None);
+
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,192 @@
+//! Messages sent as an owner or a party to a trade must carry the key that user registered with
+//!
+//! Without the check anyone could appoint themselves a delegate, accept a trade (whose ID
+//! then works as a grant token) or settle someone else's requests just by naming them.
+
+use cloud_p2p_project::directory_service::{
+    send_directory_message, start_directory_service, DirectoryAuth, DirectoryMessage, ImageInfo, ImageVisibility,
+};
+use std::time::Duration;
+
+const ALICE_KEY: &str = "alice-key";
+const BOB_KEY: &str = "bob-key";
+
+fn shared(image_id: &str) -> ImageInfo {
+    ImageInfo {
+        image_id: image_id.to_string(),
+        image_name: format!("{}.png", image_id),
+        thumbnail_path: None,
+        max_grant_views: None,
+        caption: None,
+        visibility: ImageVisibility::default(),
+        link_token: None,
+        preview: None,
+    }
+}
+
+/// Start a directory on a free port with no replicas and return its address
+async fn start_directory() -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let state_file = std::env::temp_dir().join(format!("owner-keys-{}.json", uuid::Uuid::new_v4()));
+    tokio::spawn(start_directory_service(
+        port,
+        "dir-test".to_string(),
+        Vec::new(),
+        state_file,
+        DirectoryAuth::default(),
+        std::future::pending(),
+    ));
+    let addr = format!("127.0.0.1:{}", port);
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+            return addr;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Directory never came up on {}", addr);
+}
+
+async fn register(addr: &str, username: &str, image_id: &str, key: &str) {
+    let msg = DirectoryMessage::Register {
+        username: username.to_string(),
+        p2p_address: "127.0.0.1:1".to_string(),
+        shared_images: vec![shared(image_id)],
+        capabilities: Vec::new(),
+        availability: None,
+        key: Some(key.to_string()),
+    };
+    let response = send_directory_message(addr, msg).await.unwrap();
+    assert!(matches!(response, DirectoryMessage::RegisterResponse { success: true, .. }), "{:?}", response);
+}
+
+/// Success flag and message of any of the responses these messages get
+fn outcome(response: DirectoryMessage) -> (bool, String) {
+    match response {
+        DirectoryMessage::UpdateResponse { success, message }
+        | DirectoryMessage::CancelRequestResponse { success, message }
+        | DirectoryMessage::CancelTradeResponse { success, message }
+        | DirectoryMessage::RespondToTradeResponse { success, message, .. }
+        | DirectoryMessage::DepositTradeImageResponse { success, message, .. }
+        | DirectoryMessage::RespondToRequestResponse { success, message, .. } => (success, message),
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
+
+/// Send `build(key)` with no key and with someone else's key, expecting both refused for the key,
+/// then with the right key, returning that outcome
+async fn check_keyed(addr: &str, right_key: &str, build: impl Fn(Option<String>) -> DirectoryMessage) -> (bool, String) {
+    for key in [None, Some("not-the-key".to_string())] {
+        let shown = format!("{:?}", key);
+        let (success, message) = outcome(send_directory_message(addr, build(key)).await.unwrap());
+        assert!(!success, "accepted with key {}: {}", shown, message);
+        assert!(message.contains("registered"), "refused for another reason with key {}: {}", shown, message);
+    }
+    outcome(send_directory_message(addr, build(Some(right_key.to_string()))).await.unwrap())
+}
+
+async fn leave_request(addr: &str) -> String {
+    let msg = DirectoryMessage::LeaveRequest {
+        from_user: "bob".to_string(),
+        to_user: "alice".to_string(),
+        image_id: "sunset".to_string(),
+        requested_views: 3,
+        delta_only: false,
+        requested_max_dimension: None,
+        share_token: None,
+    };
+    match send_directory_message(addr, msg).await.unwrap() {
+        DirectoryMessage::LeaveRequestResponse { success: true, request_id, .. } => request_id,
+        other => panic!("Request refused: {:?}", other),
+    }
+}
+
+async fn propose_trade(addr: &str) -> String {
+    let msg = DirectoryMessage::ProposeTrade {
+        from_user: "bob".to_string(),
+        to_user: "alice".to_string(),
+        offered_image_id: "harbor".to_string(),
+        offered_views: 2,
+        requested_image_id: "sunset".to_string(),
+        requested_views: 2,
+    };
+    match send_directory_message(addr, msg).await.unwrap() {
+        DirectoryMessage::ProposeTradeResponse { success: true, trade_id, .. } => trade_id,
+        other => panic!("Trade refused: {:?}", other),
+    }
+}
+
+#[tokio::test]
+async fn owner_and_party_messages_need_the_registered_key() {
+    let addr = start_directory().await;
+    register(&addr, "alice", "sunset", ALICE_KEY).await;
+    register(&addr, "bob", "harbor", BOB_KEY).await;
+
+    let set_delegate = |key| DirectoryMessage::SetDelegate {
+        owner: "alice".to_string(),
+        delegate: "bob".to_string(),
+        image_ids: Vec::new(),
+        enabled: true,
+        key,
+    };
+    assert!(check_keyed(&addr, ALICE_KEY, set_delegate).await.0);
+
+    let set_anonymous = |key| DirectoryMessage::SetAnonymousAccess {
+        owner: "alice".to_string(),
+        image_ids: vec!["sunset".to_string()],
+        allowed: true,
+        key,
+    };
+    assert!(check_keyed(&addr, ALICE_KEY, set_anonymous).await.0);
+
+    let request_id = leave_request(&addr).await;
+    let cancel_request = |key| DirectoryMessage::CancelRequest {
+        request_id: request_id.clone(),
+        from_user: "bob".to_string(),
+        key,
+    };
+    assert!(check_keyed(&addr, BOB_KEY, cancel_request).await.0);
+
+    // Accept a fresh request so there is a delivery to mark
+    let request_id = leave_request(&addr).await;
+    let accept = DirectoryMessage::RespondToRequest {
+        request_id: request_id.clone(),
+        owner: "alice".to_string(),
+        accept: true,
+        granted_views: None,
+        note: None,
+        key: Some(ALICE_KEY.to_string()),
+    };
+    assert!(outcome(send_directory_message(&addr, accept).await.unwrap()).0);
+    let mark_delivered = |key| DirectoryMessage::MarkRequestDelivered {
+        request_id: request_id.clone(),
+        username: "alice".to_string(),
+        key,
+    };
+    assert!(check_keyed(&addr, ALICE_KEY, mark_delivered).await.0);
+
+    let trade_id = propose_trade(&addr).await;
+    let respond_trade = |key| DirectoryMessage::RespondToTrade {
+        trade_id: trade_id.clone(),
+        username: "alice".to_string(),
+        accept: true,
+        key,
+    };
+    assert!(check_keyed(&addr, ALICE_KEY, respond_trade).await.0);
+
+    // With the right key the deposit gets as far as checking the image itself
+    let deposit = |key| DirectoryMessage::DepositTradeImage {
+        trade_id: trade_id.clone(),
+        username: "alice".to_string(),
+        encrypted_image: vec![0; 16],
+        key,
+    };
+    let (success, message) = check_keyed(&addr, ALICE_KEY, deposit).await;
+    assert!(!success && message.contains("not a protected image"), "{}", message);
+
+    let cancel_trade = |key| DirectoryMessage::CancelTrade {
+        trade_id: trade_id.clone(),
+        username: "bob".to_string(),
+        key,
+    };
+    assert!(check_keyed(&addr, BOB_KEY, cancel_trade).await.0);
+}
@@ -0,0 +1,66 @@
+//! Images queued with permission updates are streamed from the directory's blob store on fetch
+//!
+//! They used to be read whole and inlined into the JSON response, so one fetch could hold
+//! every queued image in memory several times over.
+
+use cloud_p2p_project::directory_service::{
+    fetch_pending_permission_updates, send_directory_message, start_directory_service, DirectoryAuth, DirectoryMessage,
+};
+use std::time::Duration;
+
+/// Start a directory on a free port with no replicas and return its address
+async fn start_directory() -> String {
+    let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    let state_file = std::env::temp_dir().join(format!("pending-update-images-{}.json", uuid::Uuid::new_v4()));
+    tokio::spawn(start_directory_service(
+        port,
+        "dir-test".to_string(),
+        Vec::new(),
+        state_file,
+        DirectoryAuth::default(),
+        std::future::pending(),
+    ));
+    let addr = format!("127.0.0.1:{}", port);
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+            return addr;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Directory never came up on {}", addr);
+}
+
+async fn queue(addr: &str, image_id: &str, new_quota: u32, embedded_image: Option<Vec<u8>>) {
+    let msg = DirectoryMessage::StorePendingPermissionUpdate {
+        from_owner: "alice".to_string(),
+        target_user: "bob".to_string(),
+        image_id: image_id.to_string(),
+        new_quota,
+        embedded_image,
+        wipe: false,
+        ttl_secs: None,
+    };
+    let response = send_directory_message(addr, msg).await.unwrap();
+    assert!(matches!(response, DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, .. }), "{:?}", response);
+}
+
+#[tokio::test]
+async fn queued_images_stream_in_order_and_are_cleared_after() {
+    let addr = start_directory().await;
+    let large: Vec<u8> = (0..3 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    queue(&addr, "sunset", 4, Some(large.clone())).await;
+    queue(&addr, "harbor", 2, None).await;
+    queue(&addr, "forest", 1, Some(vec![137, 80, 78, 71])).await;
+
+    let mut updates = fetch_pending_permission_updates(&addr, "bob").await.unwrap();
+    updates.sort_by(|a, b| a.image_id.cmp(&b.image_id));
+    let images: Vec<(&str, Option<usize>)> = updates
+        .iter()
+        .map(|u| (u.image_id.as_str(), u.embedded_image.as_ref().map(Vec::len)))
+        .collect();
+    assert_eq!(images, [("forest", Some(4)), ("harbor", None), ("sunset", Some(large.len()))]);
+    assert_eq!(updates[2].embedded_image.as_deref(), Some(&large[..]));
+    assert!(updates.iter().all(|u| u.blob_sha256.is_none()));
+
+    assert!(fetch_pending_permission_updates(&addr, "bob").await.unwrap().is_empty());
+}
@@ -0,0 +1,129 @@
+//! Requests and queued permission updates are committed to a quorum of replicas before the client hears back
+//!
+//! They used to stay on the replica that took them, so losing that server lost every request
+//! it had acknowledged.
+
+use cloud_p2p_project::directory_service::{
+    send_directory_message, start_directory_service, DirectoryAuth, DirectoryMessage, ImageInfo, ImageVisibility,
+};
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+fn shared(image_id: &str) -> ImageInfo {
+    ImageInfo {
+        image_id: image_id.to_string(),
+        image_name: format!("{}.png", image_id),
+        thumbnail_path: None,
+        max_grant_views: None,
+        caption: None,
+        visibility: ImageVisibility::default(),
+        link_token: None,
+        preview: None,
+    }
+}
+
+fn free_port() -> u16 {
+    std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port()
+}
+
+/// Start a directory replicating to `peers`, returning its address and a handle that stops it
+async fn start_directory(port: u16, server_id: &str, peers: Vec<String>) -> (String, oneshot::Sender<()>) {
+    let state_file = std::env::temp_dir().join(format!("replicated-requests-{}.json", uuid::Uuid::new_v4()));
+    let (stop, stopped) = oneshot::channel::<()>();
+    tokio::spawn(start_directory_service(
+        port,
+        server_id.to_string(),
+        peers,
+        state_file,
+        DirectoryAuth::default(),
+        async move {
+            let _ = stopped.await;
+        },
+    ));
+    let addr = format!("127.0.0.1:{}", port);
+    for _ in 0..50 {
+        if tokio::net::TcpStream::connect(&addr).await.is_ok() {
+            return (addr, stop);
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Directory never came up on {}", addr);
+}
+
+async fn register(addr: &str, username: &str, image_id: &str) {
+    let msg = DirectoryMessage::Register {
+        username: username.to_string(),
+        p2p_address: "127.0.0.1:1".to_string(),
+        shared_images: vec![shared(image_id)],
+        capabilities: Vec::new(),
+        availability: None,
+        key: Some(format!("{}-key", username)),
+    };
+    let response = send_directory_message(addr, msg).await.unwrap();
+    assert!(matches!(response, DirectoryMessage::RegisterResponse { success: true, .. }), "{:?}", response);
+}
+
+fn leave_request() -> DirectoryMessage {
+    DirectoryMessage::LeaveRequest {
+        from_user: "bob".to_string(),
+        to_user: "alice".to_string(),
+        image_id: "sunset".to_string(),
+        requested_views: 3,
+        delta_only: false,
+        requested_max_dimension: None,
+        share_token: None,
+    }
+}
+
+/// Ask `addr` for what it holds itself, without it asking its replicas
+async fn ask_alone(addr: &str, message: DirectoryMessage) -> DirectoryMessage {
+    send_directory_message(addr, DirectoryMessage::Forwarded { message: Box::new(message) }).await.unwrap()
+}
+
+#[tokio::test]
+async fn follower_holds_requests_and_updates_once_acknowledged() {
+    let (port_a, port_b) = (free_port(), free_port());
+    let (a, _stop_a) = start_directory(port_a, "dir-a", vec![format!("127.0.0.1:{}", port_b)]).await;
+    let (b, stop_b) = start_directory(port_b, "dir-b", vec![format!("127.0.0.1:{}", port_a)]).await;
+    register(&a, "alice", "sunset").await;
+    register(&a, "bob", "harbor").await;
+
+    let request_id = match send_directory_message(&a, leave_request()).await.unwrap() {
+        DirectoryMessage::LeaveRequestResponse { success: true, request_id, .. } => request_id,
+        other => panic!("Request refused: {:?}", other),
+    };
+    match ask_alone(&b, DirectoryMessage::GetPendingRequests { username: "alice".to_string() }).await {
+        DirectoryMessage::GetPendingRequestsResponse { requests } => {
+            assert!(requests.iter().any(|r| r.request_id == request_id), "follower is missing {}: {:?}", request_id, requests);
+        }
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    let store = DirectoryMessage::StorePendingPermissionUpdate {
+        from_owner: "alice".to_string(),
+        target_user: "bob".to_string(),
+        image_id: "sunset".to_string(),
+        new_quota: 4,
+        embedded_image: Some(vec![137, 80, 78, 71]),
+        wipe: false,
+        ttl_secs: None,
+    };
+    let response = send_directory_message(&a, store).await.unwrap();
+    assert!(matches!(response, DirectoryMessage::StorePendingPermissionUpdateResponse { success: true, .. }), "{:?}", response);
+    match ask_alone(&b, DirectoryMessage::GetPendingPermissionUpdates { username: "bob".to_string(), stream_images: false }).await {
+        DirectoryMessage::GetPendingPermissionUpdatesResponse { updates } => {
+            assert_eq!(updates.len(), 1, "{:?}", updates);
+            assert_eq!(updates[0].new_quota, 4);
+            assert_eq!(updates[0].embedded_image.as_deref(), Some(&[137, 80, 78, 71][..]));
+        }
+        other => panic!("Unexpected response {:?}", other),
+    }
+
+    // With its only replica gone, a server of two can't reach a quorum and says so
+    let _ = stop_b.send(());
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    match send_directory_message(&a, leave_request()).await.unwrap() {
+        DirectoryMessage::LeaveRequestResponse { success, message, .. } => assert!(!success, "acknowledged without a quorum: {}", message),
+        other => panic!("Unexpected response {:?}", other),
+    }
+}
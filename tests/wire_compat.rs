@@ -0,0 +1,122 @@
+//! Wire compatibility checks against recorded golden messages
+//!
+//! `tests/golden/{p2p,directory}` hold one sample of every message variant as this build
+//! sends it; `tests/golden/legacy` holds messages as older peers sent them, before fields
+//! with serde defaults were added. A failure here means deployed peers would stop
+//! understanding us (or we them). If the change is deliberate, add a new defaulted field
+//! instead of renaming one, and record the new shape alongside the old.
+
+use cloud_p2p_project::directory_service::DirectoryMessage;
+use cloud_p2p_project::p2p_protocol::P2PMessage;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+fn golden_dir(kind: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(kind)
+}
+
+/// Every golden sample in `kind`, as (file stem, parsed JSON)
+fn golden_messages(kind: &str) -> Vec<(String, Value)> {
+    let dir = golden_dir(kind);
+    let mut messages: Vec<(String, Value)> = fs::read_dir(&dir)
+        .unwrap_or_else(|e| panic!("Failed to read {}: {}", dir.display(), e))
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            let data = fs::read_to_string(&path).unwrap();
+            let value = serde_json::from_str(&data)
+                .unwrap_or_else(|e| panic!("{} is not valid JSON: {}", path.display(), e));
+            (name, value)
+        })
+        .collect();
+    messages.sort_by(|a, b| a.0.cmp(&b.0));
+    messages
+}
+
+/// Variant names serde knows for `T`, read from its unknown-variant error
+fn variant_names<T: DeserializeOwned>() -> Vec<String> {
+    let error = serde_json::from_str::<T>("\"__no_such_variant__\"")
+        .err()
+        .expect("sentinel variant decoded");
+    let message = error.to_string();
+    let message = message.rsplit_once(" at line").map_or(message.as_str(), |(message, _)| message);
+    let expected = message
+        .split_once("expected one of ")
+        .unwrap_or_else(|| panic!("Unexpected serde error: {}", message))
+        .1;
+    expected
+        .split(", ")
+        .map(|name| name.trim_matches(|c: char| c == '`' || c.is_whitespace()).to_string())
+        .collect()
+}
+
+/// Each sample is tagged with its file's variant, decodes, and re-encodes to the same JSON
+fn assert_round_trips<T: Serialize + DeserializeOwned>(kind: &str) {
+    for (name, golden) in golden_messages(kind) {
+        let tag = golden.as_object().and_then(|object| object.keys().next()).cloned();
+        assert_eq!(tag.as_deref(), Some(name.as_str()), "{}/{}.json is tagged with another variant", kind, name);
+
+        let message: T = serde_json::from_value(golden.clone())
+            .unwrap_or_else(|e| panic!("{}/{}.json no longer decodes: {}", kind, name, e));
+        let encoded = serde_json::to_value(&message).unwrap();
+        assert_eq!(encoded, golden, "{}/{}.json re-encodes differently", kind, name);
+    }
+}
+
+/// Every variant of `T` has a golden sample, and every sample names a variant
+fn assert_every_variant_recorded<T: DeserializeOwned>(kind: &str) {
+    let variants = variant_names::<T>();
+    let recorded: Vec<String> = golden_messages(kind).into_iter().map(|(name, _)| name).collect();
+
+    let missing: Vec<&String> = variants.iter().filter(|v| !recorded.contains(v)).collect();
+    assert!(missing.is_empty(), "No golden sample in tests/golden/{} for {:?}", kind, missing);
+    let unknown: Vec<&String> = recorded.iter().filter(|r| !variants.contains(r)).collect();
+    assert!(unknown.is_empty(), "Golden samples in tests/golden/{} for removed variants {:?}", kind, unknown);
+}
+
+/// Messages from older peers still decode, and come out in the current shape
+fn assert_legacy_decodes<T: Serialize + DeserializeOwned>(kind: &str) {
+    let legacy = golden_messages(&format!("legacy/{}", kind));
+    assert!(!legacy.is_empty(), "No legacy samples for {}", kind);
+    for (name, old) in legacy {
+        let message: T = serde_json::from_value(old)
+            .unwrap_or_else(|e| panic!("legacy/{}/{}.json no longer decodes: {}", kind, name, e));
+        let upgraded = serde_json::to_value(&message).unwrap();
+        serde_json::from_value::<T>(upgraded)
+            .unwrap_or_else(|e| panic!("legacy/{}/{}.json doesn't survive re-encoding: {}", kind, name, e));
+    }
+}
+
+#[test]
+fn p2p_messages_round_trip() {
+    assert_round_trips::<P2PMessage>("p2p");
+}
+
+#[test]
+fn directory_messages_round_trip() {
+    assert_round_trips::<DirectoryMessage>("directory");
+}
+
+#[test]
+fn every_p2p_variant_recorded() {
+    assert_every_variant_recorded::<P2PMessage>("p2p");
+}
+
+#[test]
+fn every_directory_variant_recorded() {
+    assert_every_variant_recorded::<DirectoryMessage>("directory");
+}
+
+#[test]
+fn legacy_p2p_messages_decode() {
+    assert_legacy_decodes::<P2PMessage>("p2p");
+}
+
+#[test]
+fn legacy_directory_messages_decode() {
+    assert_legacy_decodes::<DirectoryMessage>("directory");
+}